@@ -0,0 +1,46 @@
+# Labels shown on the displays, in German
+
+status-free = FREI
+status-busy = BELEGT
+status-unknown = UNBEKANNT
+status-free-until = FREI bis { $time }
+status-busy-until = BELEGT bis { $time }
+status-free-for = FREI für { $minutes } Min.
+status-starting-soon = BEGINNT um { $time }
+
+banner-ends-in = Endet in { $minutes } Min.
+banner-ends-in-next = Endet in { $minutes } Min. - danach: { $next } { $time }
+banner-dnd = BITTE NICHT STÖREN
+
+agenda-today = Heute
+agenda-tomorrow = Morgen
+agenda-empty = Keine anstehenden Termine
+
+weekday-mon = Montag
+weekday-tue = Dienstag
+weekday-wed = Mittwoch
+weekday-thu = Donnerstag
+weekday-fri = Freitag
+weekday-sat = Samstag
+weekday-sun = Sonntag
+
+header-floor = Etage { $floor }
+header-seats =
+    { $count ->
+        [one] { $count } Platz
+       *[other] { $count } Plätze
+    }
+
+header-degraded = ERSATZKALENDER
+
+issue-reported = Problem gemeldet: { $summary }
+
+battery-replace = Batterie bald ersetzen
+
+maintenance = Server wird gewartet
+
+overview-other-rooms = Weitere Räume
+overview-empty = Keine Räume
+
+error-calendar = Kalender nicht verfügbar ({ $code })
+error-unassigned = Keinem Raum zugewiesen ({ $code })
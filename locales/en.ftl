@@ -0,0 +1,50 @@
+# Labels shown on the displays, in English
+#
+# This pack is the fallback for messages missing in other packs.
+
+status-free = FREE
+status-busy = BUSY
+status-unknown = UNKNOWN
+status-free-until = FREE until { $time }
+status-busy-until = BUSY until { $time }
+status-free-for = FREE for { $minutes } min
+status-starting-soon = STARTING SOON at { $time }
+
+banner-ends-in = Ends in { $minutes } min
+banner-ends-in-next = Ends in { $minutes } min - next: { $next } { $time }
+banner-dnd = DO NOT DISTURB
+
+agenda-today = Today
+agenda-tomorrow = Tomorrow
+agenda-empty = No upcoming events
+
+weekday-mon = Monday
+weekday-tue = Tuesday
+weekday-wed = Wednesday
+weekday-thu = Thursday
+weekday-fri = Friday
+weekday-sat = Saturday
+weekday-sun = Sunday
+
+header-floor = Floor { $floor }
+header-seats =
+    { $count ->
+        [one] { $count } seat
+       *[other] { $count } seats
+    }
+
+# Badge of a room whose calendar fails, while its fallback calendar is shown
+header-degraded = BACKUP CALENDAR
+
+issue-reported = Issue reported: { $summary }
+
+battery-replace = Replace battery soon
+
+maintenance = Server under maintenance
+
+overview-other-rooms = Other rooms
+overview-empty = No rooms
+
+# Errors shown on the display, with a code for support, e.g. CAL-01
+error-calendar = Calendar unavailable ({ $code })
+error-unassigned = Not assigned to a room ({ $code })
@@ -0,0 +1,46 @@
+# Labels shown on the displays, in Spanish
+
+status-free = LIBRE
+status-busy = OCUPADA
+status-unknown = DESCONOCIDO
+status-free-until = LIBRE hasta las { $time }
+status-busy-until = OCUPADA hasta las { $time }
+status-free-for = LIBRE durante { $minutes } min
+status-starting-soon = EMPIEZA a las { $time }
+
+banner-ends-in = Termina en { $minutes } min
+banner-ends-in-next = Termina en { $minutes } min - siguiente: { $next } { $time }
+banner-dnd = NO MOLESTAR
+
+agenda-today = Hoy
+agenda-tomorrow = Mañana
+agenda-empty = No hay reuniones próximas
+
+weekday-mon = Lunes
+weekday-tue = Martes
+weekday-wed = Miércoles
+weekday-thu = Jueves
+weekday-fri = Viernes
+weekday-sat = Sábado
+weekday-sun = Domingo
+
+header-floor = Planta { $floor }
+header-seats =
+    { $count ->
+        [one] { $count } plaza
+       *[other] { $count } plazas
+    }
+
+header-degraded = CALENDARIO DE RESPALDO
+
+issue-reported = Incidencia notificada: { $summary }
+
+battery-replace = Cambiar la batería pronto
+
+maintenance = Servidor en mantenimiento
+
+overview-other-rooms = Otras salas
+overview-empty = No hay salas
+
+error-calendar = Calendario no disponible ({ $code })
+error-unassigned = No asignado a ninguna sala ({ $code })
@@ -0,0 +1,46 @@
+# Labels shown on the displays, in French
+
+status-free = LIBRE
+status-busy = OCCUPÉ
+status-unknown = INCONNU
+status-free-until = LIBRE jusqu'à { $time }
+status-busy-until = OCCUPÉ jusqu'à { $time }
+status-free-for = LIBRE pendant { $minutes } min
+status-starting-soon = COMMENCE à { $time }
+
+banner-ends-in = Fin dans { $minutes } min
+banner-ends-in-next = Fin dans { $minutes } min - ensuite : { $next } { $time }
+banner-dnd = NE PAS DÉRANGER
+
+agenda-today = Aujourd'hui
+agenda-tomorrow = Demain
+agenda-empty = Aucune réunion à venir
+
+weekday-mon = Lundi
+weekday-tue = Mardi
+weekday-wed = Mercredi
+weekday-thu = Jeudi
+weekday-fri = Vendredi
+weekday-sat = Samedi
+weekday-sun = Dimanche
+
+header-floor = Étage { $floor }
+header-seats =
+    { $count ->
+        [one] { $count } place
+       *[other] { $count } places
+    }
+
+header-degraded = CALENDRIER DE SECOURS
+
+issue-reported = Problème signalé : { $summary }
+
+battery-replace = Remplacer la batterie bientôt
+
+maintenance = Serveur en maintenance
+
+overview-other-rooms = Autres salles
+overview-empty = Aucune salle
+
+error-calendar = Calendrier indisponible ({ $code })
+error-unassigned = Non attribué à une salle ({ $code })
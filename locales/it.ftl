@@ -0,0 +1,46 @@
+# Labels shown on the displays, in Italian
+
+status-free = LIBERA
+status-busy = OCCUPATA
+status-unknown = SCONOSCIUTO
+status-free-until = LIBERA fino alle { $time }
+status-busy-until = OCCUPATA fino alle { $time }
+status-free-for = LIBERA per { $minutes } min
+status-starting-soon = INIZIA alle { $time }
+
+banner-ends-in = Termina tra { $minutes } min
+banner-ends-in-next = Termina tra { $minutes } min - poi: { $next } { $time }
+banner-dnd = NON DISTURBARE
+
+agenda-today = Oggi
+agenda-tomorrow = Domani
+agenda-empty = Nessuna riunione in programma
+
+weekday-mon = Lunedì
+weekday-tue = Martedì
+weekday-wed = Mercoledì
+weekday-thu = Giovedì
+weekday-fri = Venerdì
+weekday-sat = Sabato
+weekday-sun = Domenica
+
+header-floor = Piano { $floor }
+header-seats =
+    { $count ->
+        [one] { $count } posto
+       *[other] { $count } posti
+    }
+
+header-degraded = CALENDARIO DI RISERVA
+
+issue-reported = Problema segnalato: { $summary }
+
+battery-replace = Sostituire presto la batteria
+
+maintenance = Server in manutenzione
+
+overview-other-rooms = Altre sale
+overview-empty = Nessuna sala
+
+error-calendar = Calendario non disponibile ({ $code })
+error-unassigned = Non assegnato a una sala ({ $code })
@@ -0,0 +1,44 @@
+# Labels shown on the displays, in Japanese
+#
+# The default font has no Japanese glyphs, set FONT_PATH to a font that has.
+
+status-free = 空室
+status-busy = 使用中
+status-unknown = 不明
+status-free-until = { $time }まで空室
+status-busy-until = { $time }まで使用中
+status-free-for = あと{ $minutes }分空室
+status-starting-soon = { $time }に開始
+
+banner-ends-in = あと{ $minutes }分で終了
+banner-ends-in-next = あと{ $minutes }分で終了 - 次: { $next } { $time }
+banner-dnd = 取り込み中
+
+agenda-today = 今日
+agenda-tomorrow = 明日
+agenda-empty = 予定はありません
+
+weekday-mon = 月曜日
+weekday-tue = 火曜日
+weekday-wed = 水曜日
+weekday-thu = 木曜日
+weekday-fri = 金曜日
+weekday-sat = 土曜日
+weekday-sun = 日曜日
+
+header-floor = { $floor }階
+header-seats = { $count }席
+
+header-degraded = 予備カレンダー
+
+issue-reported = 問題の報告: { $summary }
+
+battery-replace = まもなく電池交換が必要です
+
+maintenance = サーバーはメンテナンス中です
+
+overview-other-rooms = その他の会議室
+overview-empty = 会議室がありません
+
+error-calendar = カレンダーを取得できません ({ $code })
+error-unassigned = 会議室に割り当てられていません ({ $code })
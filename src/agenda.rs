@@ -0,0 +1,340 @@
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime, Weekday};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::calendar::{CalendarEvent, EventWindow};
+use crate::labels::LabelPack;
+use crate::week::Week;
+
+/// Business hours of a room
+///
+/// Outside of today's meetings, the agenda shows the meetings of the next
+/// business day that start within business hours.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BusinessHours {
+    /// Start of business hours, e.g. `"08:00"`
+    #[serde(with = "hh_mm")]
+    pub start: NaiveTime,
+    /// End of business hours, e.g. `"18:00"`
+    #[serde(with = "hh_mm")]
+    pub end: NaiveTime,
+    /// Business days, e.g. `["Mon", "Tue", "Wed", "Thu", "Fri"]`
+    ///
+    /// Defaults to the days outside the room's weekend.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days: Option<Vec<Weekday>>,
+}
+
+impl Default for BusinessHours {
+    fn default() -> Self {
+        Self {
+            start: NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+            end: NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+            days: None,
+        }
+    }
+}
+
+impl BusinessHours {
+    /// Returns true if the day is a business day
+    pub fn is_business_day(&self, day: Weekday, week: &Week) -> bool {
+        match &self.days {
+            Some(days) => days.contains(&day),
+            None => !week.is_weekend(day),
+        }
+    }
+
+    /// Returns true if the event starts on a business day within business hours
+    fn contains(&self, event: &CalendarEvent, week: &Week) -> bool {
+        let start = event.start_time;
+        self.is_business_day(start.weekday(), week)
+            && start.time() >= self.start
+            && start.time() < self.end
+    }
+}
+
+/// (De)serialization of times as `HH:MM`
+mod hh_mm {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(time: &NaiveTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&time.format("%H:%M").to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        NaiveTime::parse_from_str(&s, "%H:%M").map_err(serde::de::Error::custom)
+    }
+}
+
+/// Day the agenda shows meetings of
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AgendaDay {
+    Today,
+    Tomorrow,
+    /// A later day, e.g. Monday when looking ahead on a Friday evening
+    Later(NaiveDate),
+}
+
+impl AgendaDay {
+    /// The given date, as seen from today
+    pub fn of(date: NaiveDate, today: NaiveDate) -> Self {
+        if date <= today {
+            AgendaDay::Today
+        } else if today.succ_opt() == Some(date) {
+            AgendaDay::Tomorrow
+        } else {
+            AgendaDay::Later(date)
+        }
+    }
+
+    /// Heading of the agenda section
+    pub fn heading(&self, labels: &LabelPack) -> String {
+        match self {
+            AgendaDay::Today => labels.text("agenda-today"),
+            AgendaDay::Tomorrow => labels.text("agenda-tomorrow"),
+            AgendaDay::Later(date) => labels.text(&format!(
+                "weekday-{}",
+                date.weekday().to_string().to_lowercase()
+            )),
+        }
+    }
+}
+
+/// Upcoming meetings of a room
+#[derive(Debug, Clone)]
+pub struct Agenda<'a> {
+    pub day: AgendaDay,
+    /// Up to the requested number of meetings, sorted by start time
+    pub events: Vec<&'a CalendarEvent>,
+}
+
+/// How many days to look ahead for the next business day with meetings
+const LOOKAHEAD_DAYS: u64 = 7;
+
+/// Agenda of a room: today's remaining meetings, or once the last one of the
+/// day has ended, the first meetings of the next business day
+pub fn agenda<'a>(
+    events: &'a [CalendarEvent],
+    now: DateTime<Local>,
+    hours: &BusinessHours,
+    week: &Week,
+    limit: usize,
+) -> Agenda<'a> {
+    let today = now.date_naive();
+    let remaining_today: Vec<&CalendarEvent> = events
+        .iter()
+        .filter(|e| e.end_time > now && e.start_time.date_naive() == today)
+        .take(limit)
+        .collect();
+    if !remaining_today.is_empty() {
+        return Agenda {
+            day: AgendaDay::Today,
+            events: remaining_today,
+        };
+    }
+
+    let next_day = today
+        .iter_days()
+        .skip(1)
+        .take(LOOKAHEAD_DAYS as usize)
+        .filter(|day| hours.is_business_day(day.weekday(), week))
+        .find_map(|day| {
+            let day_events: Vec<&CalendarEvent> = events
+                .iter()
+                .filter(|e| e.start_time.date_naive() == day && hours.contains(e, week))
+                .take(limit)
+                .collect();
+            (!day_events.is_empty()).then_some((day, day_events))
+        });
+
+    match next_day {
+        Some((day, events)) => Agenda {
+            day: AgendaDay::of(day, today),
+            events,
+        },
+        None => Agenda {
+            day: AgendaDay::Today,
+            events: Vec::new(),
+        },
+    }
+}
+
+/// Agendas of the next days: up to `limit` meetings that have not ended yet
+/// and start within `days` days (including today), grouped by day
+///
+/// Unlike [`agenda`], this is not limited to one day, for rooms with only a
+/// few meetings a week. Days without meetings are left out; a meeting in
+/// progress counts as today's.
+pub fn upcoming_days(
+    events: &[CalendarEvent],
+    now: DateTime<Local>,
+    days: u32,
+    limit: usize,
+) -> Vec<Agenda<'_>> {
+    let window = EventWindow::days(now, days);
+    let today = now.date_naive();
+    let mut agendas: Vec<Agenda> = Vec::new();
+    for event in events.iter().filter(|e| window.contains(e)).take(limit) {
+        let day = AgendaDay::of(event.start_time.date_naive(), today);
+        match agendas.last_mut() {
+            Some(agenda) if agenda.day == day => agenda.events.push(event),
+            _ => agendas.push(Agenda {
+                day,
+                events: vec![event],
+            }),
+        }
+    }
+    agendas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::Labels;
+    use chrono::TimeZone;
+
+    fn event(name: &str, day: u32, start: (u32, u32), end: (u32, u32)) -> CalendarEvent {
+        CalendarEvent::new(
+            name.to_string(),
+            Local
+                .with_ymd_and_hms(2024, 3, day, start.0, start.1, 0)
+                .unwrap(),
+            Local
+                .with_ymd_and_hms(2024, 3, day, end.0, end.1, 0)
+                .unwrap(),
+            None,
+            None,
+        )
+    }
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, 3, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_agenda_rolls_over_to_tomorrow() {
+        // 2024-03-04 is a Monday
+        let events = vec![
+            event("Standup", 4, (9, 0), (9, 15)),
+            event("Retro", 4, (16, 0), (17, 0)),
+            event("Early bird", 5, (6, 0), (7, 0)),
+            event("Planning", 5, (9, 0), (10, 0)),
+            event("Review", 5, (11, 0), (12, 0)),
+        ];
+        let hours = BusinessHours::default();
+
+        let agenda_at = |now| agenda(&events, now, &hours, &Week::default(), 3);
+        let names = |a: &Agenda| a.events.iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+
+        let a = agenda_at(at(4, 10, 0));
+        assert_eq!(a.day, AgendaDay::Today);
+        assert_eq!(names(&a), ["Retro"]);
+
+        // After the last meeting, tomorrow's meetings within business hours
+        let a = agenda_at(at(4, 17, 30));
+        assert_eq!(a.day, AgendaDay::Tomorrow);
+        assert_eq!(a.day.heading(&Labels::embedded().pack("en")), "Tomorrow");
+        assert_eq!(names(&a), ["Planning", "Review"]);
+    }
+
+    #[test]
+    fn test_agenda_skips_weekend() {
+        // 2024-03-08 is a Friday, 2024-03-11 a Monday
+        let events = vec![
+            event("Saturday hackathon", 9, (10, 0), (16, 0)),
+            event("Monday standup", 11, (9, 0), (9, 15)),
+        ];
+        let week = Week::default();
+        let a = agenda(&events, at(8, 19, 0), &BusinessHours::default(), &week, 3);
+        assert_eq!(
+            a.day,
+            AgendaDay::Later(NaiveDate::from_ymd_opt(2024, 3, 11).unwrap())
+        );
+        let labels = Labels::embedded();
+        assert_eq!(a.day.heading(&labels.pack("en")), "Monday");
+        assert_eq!(a.day.heading(&labels.pack("fr")), "Lundi");
+        assert_eq!(a.events[0].name, "Monday standup");
+
+        assert!(
+            agenda(&[], at(8, 19, 0), &BusinessHours::default(), &week, 3)
+                .events
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_agenda_follows_week() {
+        // A site working Sunday to Thursday: on Thursday evening, the next
+        // business day is Sunday. 2024-03-07 is a Thursday.
+        let events = vec![
+            event("Friday prayer", 8, (12, 0), (13, 0)),
+            event("Sunday kickoff", 10, (9, 0), (10, 0)),
+        ];
+        let week = Week {
+            first_day: Weekday::Sun,
+            weekend: vec![Weekday::Fri, Weekday::Sat],
+        };
+        let a = agenda(&events, at(7, 19, 0), &BusinessHours::default(), &week, 3);
+        assert_eq!(a.events[0].name, "Sunday kickoff");
+
+        // Explicit business days take precedence over the weekend
+        let hours = BusinessHours {
+            days: Some(vec![Weekday::Fri]),
+            ..BusinessHours::default()
+        };
+        let a = agenda(&events, at(7, 19, 0), &hours, &week, 3);
+        assert_eq!(a.day, AgendaDay::Tomorrow);
+        assert_eq!(a.events[0].name, "Friday prayer");
+    }
+
+    #[test]
+    fn test_upcoming_days() {
+        // 2024-03-04 is a Monday
+        let events = vec![
+            event("Standup", 4, (9, 0), (9, 15)),
+            event("Retro", 4, (16, 0), (17, 0)),
+            event("Planning", 6, (9, 0), (10, 0)),
+            event("Review", 6, (11, 0), (12, 0)),
+            event("Offsite", 7, (9, 0), (17, 0)),
+        ];
+        fn summary(agendas: Vec<Agenda<'_>>) -> Vec<(AgendaDay, Vec<&str>)> {
+            agendas
+                .into_iter()
+                .map(|a| (a.day, a.events.iter().map(|e| e.name.as_str()).collect()))
+                .collect()
+        }
+        let wednesday = AgendaDay::Later(NaiveDate::from_ymd_opt(2024, 3, 6).unwrap());
+
+        // Meetings in progress are today's, days without meetings are left out
+        assert_eq!(
+            summary(upcoming_days(&events, at(4, 16, 30), 3, 10)),
+            [
+                (AgendaDay::Today, vec!["Retro"]),
+                (wednesday, vec!["Planning", "Review"]),
+            ]
+        );
+        // Limited by number of meetings
+        assert_eq!(
+            summary(upcoming_days(&events, at(4, 8, 0), 3, 2)),
+            [(AgendaDay::Today, vec!["Standup", "Retro"])]
+        );
+        // Tomorrow is labeled as such
+        let agendas = upcoming_days(&events, at(5, 8, 0), 3, 10);
+        assert_eq!(agendas[0].day, AgendaDay::Tomorrow);
+        assert_eq!(agendas.len(), 2);
+        assert!(upcoming_days(&events, at(8, 8, 0), 3, 10).is_empty());
+    }
+
+    #[test]
+    fn test_parse_business_hours() {
+        let hours: BusinessHours =
+            toml::from_str("start = \"07:30\"\nend = \"17:00\"\ndays = [\"Mon\", \"Sat\"]")
+                .unwrap();
+        assert_eq!(hours.start, NaiveTime::from_hms_opt(7, 30, 0).unwrap());
+        assert_eq!(hours.days, Some(vec![Weekday::Mon, Weekday::Sat]));
+        assert!(toml::from_str::<BusinessHours>("start = \"7\"").is_err());
+    }
+}
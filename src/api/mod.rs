@@ -0,0 +1,5 @@
+//! Public API of the server, for clients
+//!
+//! Nothing in here depends on the server itself, see [`types`].
+
+pub mod types;
@@ -0,0 +1,712 @@
+//! Request and response bodies of the HTTP API
+//!
+//! These types are the wire contract of the server: the device API, the admin
+//! API and the do-not-disturb endpoints serialize exactly these structures.
+//! They only depend on serde and plain domain types, so that clients such as
+//! the simulator, the CLI or external Rust tools can use them without the
+//! server. Fields are only ever added, with a serde default, never renamed or
+//! removed within an API version.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::device_id::DeviceIdKind;
+use crate::experiments::Experiment;
+use crate::health::{DeviceHealth, DeviceStatus, Remediation};
+use crate::render::layout::Layout;
+
+/// Success response structure
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SetupResponse {
+    /// Status code, should be 200
+    pub status: u16,
+    /// API key for the device
+    pub api_key: String,
+    /// Friendly ID for the device
+    pub friendly_id: String,
+    /// Image to show on the setup screen (BMP, 800x480px)
+    pub image_url: String,
+}
+
+/// Setup response structure of the BYOS-compatible API (v1)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ByosSetupResponse {
+    pub status: u16,
+    pub api_key: String,
+    pub friendly_id: String,
+    pub image_url: String,
+    pub message: String,
+}
+
+impl From<SetupResponse> for ByosSetupResponse {
+    fn from(response: SetupResponse) -> Self {
+        Self {
+            message: format!("Device {} registered", response.friendly_id),
+            status: response.status,
+            api_key: response.api_key,
+            friendly_id: response.friendly_id,
+            image_url: response.image_url,
+        }
+    }
+}
+
+/// Display response structure
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisplayResponse {
+    /// Name of the image, derived from its content, e.g.
+    /// `room-a-9f3c2a1b04d7e6c5.bmp`
+    ///
+    /// It starts with the room ID (or `broadcast-<id>`, `maintenance-<time>`,
+    /// `unassigned`), followed by a hash of the image data and the extension
+    /// of the format. Firmware that compares it with the filename of the image
+    /// it shows skips downloading unchanged images.
+    pub filename: String,
+    pub image_url: String,
+    pub image_url_timeout: u32,
+    pub refresh_rate: u32,
+    /// Base64-encoded Ed25519 signature of the image data, if signing is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_signature: Option<String>,
+    /// Unix timestamp until which the device may sleep, outside business hours
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sleep_until: Option<i64>,
+}
+
+/// Display response structure of the BYOS-compatible API (v1)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ByosDisplayResponse {
+    /// Status code, 0 on success
+    pub status: u16,
+    /// Content-derived name of the image, see [`DisplayResponse::filename`]
+    pub filename: String,
+    pub image_url: String,
+    pub image_url_timeout: u32,
+    pub refresh_rate: u32,
+    pub reset_firmware: bool,
+    pub update_firmware: bool,
+    pub firmware_url: Option<String>,
+    pub special_function: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_signature: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sleep_until: Option<i64>,
+}
+
+impl From<DisplayResponse> for ByosDisplayResponse {
+    fn from(response: DisplayResponse) -> Self {
+        Self {
+            status: 0,
+            filename: response.filename,
+            image_url: response.image_url,
+            image_url_timeout: response.image_url_timeout,
+            refresh_rate: response.refresh_rate,
+            reset_firmware: false,
+            update_firmware: false,
+            firmware_url: None,
+            special_function: "none".to_string(),
+            image_signature: response.image_signature,
+            sleep_until: response.sleep_until,
+        }
+    }
+}
+
+/// Device structure
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Device {
+    pub id: String,
+    pub registered_at: i64,
+}
+
+/// Target group in the Prometheus HTTP service discovery format
+///
+/// See <https://prometheus.io/docs/prometheus/latest/http_sd/>
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrometheusTargetGroup {
+    pub targets: Vec<String>,
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Device as returned by the admin API
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub id: String,
+    /// Form of the ID, `None` for devices registered with an ID of neither form
+    #[serde(default)]
+    pub id_type: Option<DeviceIdKind>,
+    /// Unix timestamp when the device was registered
+    pub registered_at: i64,
+    /// Room the device is assigned to, if any
+    pub room_id: Option<String>,
+    /// Device model as last reported by the device
+    pub model: Option<String>,
+    /// Firmware version as last reported by the device
+    #[serde(default)]
+    pub firmware_version: Option<String>,
+    /// Format of the last image served to the device
+    pub last_payload_format: Option<String>,
+    /// Size of the last image served to the device, in bytes
+    pub last_payload_bytes: Option<i64>,
+    /// Health score from 0 (broken) to 100, from the logs of the last day and
+    /// the last check-in
+    pub health_score: u8,
+    /// Problems reported in the logs of the last day and the last check-in
+    pub health: DeviceHealth,
+    /// Image delivery mode set for the device, if it differs from the configured one
+    pub image_delivery: Option<String>,
+    /// Image format set for the device, if it is not negotiated
+    #[serde(default)]
+    pub image_format: Option<String>,
+    /// Device that replaced this one, see the device adoption endpoint
+    #[serde(default)]
+    pub replaced_by: Option<String>,
+    /// Name given by an admin
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Unix timestamp of the last display request of the device
+    #[serde(default)]
+    pub last_seen_at: Option<i64>,
+    /// Battery voltage last reported by the device
+    #[serde(default)]
+    pub battery_voltage: Option<f64>,
+    /// WiFi signal strength last reported by the device, in dBm
+    #[serde(default)]
+    pub rssi: Option<i64>,
+    /// Whether the device requested an image recently
+    pub status: DeviceStatus,
+    /// Set if the device fails to draw the images it fetches
+    #[serde(default)]
+    pub display_alert: Option<DisplayAlert>,
+}
+
+/// Alert of the display watchdog about a device that fetches images but
+/// fails to draw them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisplayAlert {
+    /// Unix timestamp since when the device is flagged
+    pub since: i64,
+    /// Suggested fix
+    pub remediation: Remediation,
+    /// Instructions for the admin, e.g. `Set the image format of the device to bmp`
+    pub description: String,
+}
+
+/// Severity of a device log entry, most severe first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl LogLevel {
+    /// Name of the level, as stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(format!("Unknown log level: {}", s)),
+        }
+    }
+}
+
+/// Device log entry in the admin API
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceLog {
+    pub device_id: String,
+    pub message: String,
+    pub received_at: i64,
+    /// False if the request did not carry the device's key, i.e. the device ID may be spoofed
+    pub authenticated: bool,
+    /// Unix timestamp when the device logged the message, if it said so
+    #[serde(default)]
+    pub logged_at: Option<i64>,
+    /// Severity of the message, if the device said so
+    #[serde(default)]
+    pub level: Option<LogLevel>,
+}
+
+/// Request body of the device name endpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceNameRequest {
+    /// New name of the device, or null to clear it
+    pub name: Option<String>,
+}
+
+/// Request body of the device image delivery endpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageDeliveryRequest {
+    /// `inline`, `hosted` or `chunked`, or null to use the configured mode
+    pub image_delivery: Option<String>,
+}
+
+/// Request body of the device image format endpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageFormatRequest {
+    /// `bmp` or `png`, or null to negotiate the format
+    pub image_format: Option<String>,
+}
+
+/// Request body of the device adoption endpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdoptDeviceRequest {
+    /// ID of the device being replaced
+    pub replaces: String,
+}
+
+/// Response of the device adoption endpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceAdoption {
+    /// ID of the replacement device
+    pub device_id: String,
+    /// ID of the retired device
+    pub replaced: String,
+    /// Room the replacement device is now shown in, if any
+    pub room_id: Option<String>,
+}
+
+/// Broadcast to create
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BroadcastRequest {
+    /// Message shown full-screen on every device
+    pub message: String,
+    /// Minutes until the broadcast expires automatically
+    #[serde(default = "default_broadcast_duration")]
+    pub duration_minutes: i64,
+}
+
+fn default_broadcast_duration() -> i64 {
+    60
+}
+
+/// Created broadcast
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BroadcastResponse {
+    pub id: i64,
+    pub message: String,
+    /// Unix timestamp when the broadcast expires
+    pub expires_at: i64,
+    pub triggered_by: String,
+}
+
+/// Maintenance to start
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceRequest {
+    /// Message shown on every device below the maintenance notice, e.g. when
+    /// the displays will be back
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// Maintenance in progress
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceResponse {
+    pub message: Option<String>,
+    /// Unix timestamp when the maintenance started
+    pub started_at: i64,
+    pub started_by: String,
+}
+
+/// Scheduled job of the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobInfo {
+    /// Unique name, e.g. `calendar_refresh`
+    pub name: String,
+    /// Interval or cron expression, e.g. `@every 5m` or `5 0 * * *`
+    pub schedule: String,
+    /// Whether the job runs on one instance of a deployment at a time
+    pub exclusive: bool,
+    pub paused: bool,
+    /// Who paused the job, if it is paused
+    pub paused_by: Option<String>,
+    /// Whether the job is running on the instance answering the request
+    pub running: bool,
+    /// Unix timestamp of the next scheduled run on the instance answering
+    /// the request
+    pub next_run: Option<i64>,
+    /// Most recent run on any instance
+    pub last_run: Option<JobRunInfo>,
+}
+
+/// Run of a scheduled job
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobRunInfo {
+    pub instance_id: String,
+    /// Whether an admin started the run, rather than the schedule
+    pub manual: bool,
+    /// Unix timestamp when the run started
+    pub started_at: i64,
+    /// Unix timestamp when the run finished
+    pub finished_at: i64,
+    pub succeeded: bool,
+    /// Summary of the run, or the error if it failed
+    pub message: Option<String>,
+}
+
+/// Layout experiment to start
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExperimentRequest {
+    /// Unique name, e.g. `focus-at-doors`
+    pub name: String,
+    /// Layout shown on the devices of the variant
+    pub layout: Layout,
+    /// Share of the devices in the variant, in percent
+    pub percent: u8,
+    /// Room to limit the experiment to, all rooms if left out
+    #[serde(default)]
+    pub room_id: Option<String>,
+}
+
+/// Layout experiment in the admin API
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExperimentInfo {
+    #[serde(flatten)]
+    pub experiment: Experiment,
+    /// Registered devices showing the layout of their room
+    pub control_devices: usize,
+    /// Registered devices showing the layout of the experiment
+    pub variant_devices: usize,
+}
+
+/// Open issue reported for a room
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IssueReport {
+    pub id: i64,
+    pub room_id: String,
+    pub category: String,
+    pub description: String,
+    /// Unix timestamp when the issue was reported
+    pub created_at: i64,
+}
+
+/// Claim code creation request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClaimCodeRequest {
+    /// Room the claiming device is bound to
+    pub room_id: String,
+    /// Hours until the code expires
+    #[serde(default = "default_claim_code_expiry_hours")]
+    pub expires_in_hours: i64,
+}
+
+fn default_claim_code_expiry_hours() -> i64 {
+    72
+}
+
+/// Claim code as returned by the admin API
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClaimCode {
+    pub code: String,
+    pub room_id: String,
+    /// Device the code is pre-provisioned for, if any
+    pub device_id: Option<String>,
+    /// Unix timestamp when the code expires, if it does
+    pub expires_at: Option<i64>,
+}
+
+/// Batch of devices to provision
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProvisioningRequest {
+    pub devices: Vec<ProvisioningEntry>,
+}
+
+/// Device to provision
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProvisioningEntry {
+    /// MAC address or UUID of the device, see [`DeviceId`](crate::device_id::DeviceId)
+    pub mac: String,
+    /// Room the device is installed in
+    pub room_id: String,
+    /// Free text label, e.g. the mounting position
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Device model, e.g. `og`
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Firmware release channel
+    #[serde(default = "default_firmware_channel")]
+    pub firmware_channel: String,
+}
+
+fn default_firmware_channel() -> String {
+    "stable".to_string()
+}
+
+/// Provisioned device as returned by the admin API
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProvisionedDevice {
+    pub device_id: String,
+    pub room_id: String,
+    pub label: Option<String>,
+    pub model: Option<String>,
+    pub firmware_channel: String,
+    /// Claim code bound to the device, used automatically on setup
+    pub claim_code: String,
+    /// API key returned to the device on setup
+    pub api_key: String,
+    /// Whether the device has completed setup
+    pub claimed: bool,
+    /// Whether the device was newly provisioned by this request
+    #[serde(default)]
+    pub created: bool,
+}
+
+/// Device counts of the fleet summary
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceSummary {
+    pub total: usize,
+    pub online: usize,
+    pub offline: usize,
+    /// Devices that never requested an image, including provisioned devices not set up yet
+    pub pending: usize,
+    /// Devices whose last reported battery voltage is low, whatever their status
+    pub low_battery: usize,
+}
+
+/// Room counts of the fleet summary
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoomSummary {
+    pub total: usize,
+    pub free: usize,
+    pub busy: usize,
+    /// Rooms whose calendar failed to refresh
+    pub error: usize,
+    /// Rooms without a calendar, or whose calendar was not fetched yet
+    pub unknown: usize,
+    /// Rooms whose calendar failed to refresh, shown from their fallback
+    /// calendar; also counted as free or busy
+    #[serde(default)]
+    pub degraded: usize,
+}
+
+/// Calendar source counts of the fleet summary
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CalendarSummary {
+    pub total: usize,
+    pub ok: usize,
+    pub failing: usize,
+    pub unknown: usize,
+}
+
+/// Response of the fleet summary endpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FleetSummary {
+    pub devices: DeviceSummary,
+    pub rooms: RoomSummary,
+    pub calendars: CalendarSummary,
+}
+
+/// Do-not-disturb mark of a meeting
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DndResponse {
+    pub room_id: String,
+    /// Name of the marked meeting
+    pub meeting: String,
+    /// Unix timestamp when the meeting ends, and with it the mark
+    pub expires_at: i64,
+    pub set_by: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use serde::de::DeserializeOwned;
+    use serde_json::json;
+
+    use super::*;
+
+    /// Assert that a value serializes to the given JSON and back
+    fn assert_round_trip<T>(value: &T, expected: serde_json::Value)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + Debug,
+    {
+        assert_eq!(serde_json::to_value(value).unwrap(), expected);
+        assert_eq!(&serde_json::from_value::<T>(expected).unwrap(), value);
+    }
+
+    #[test]
+    fn test_device_api_round_trip() {
+        let setup = SetupResponse {
+            status: 200,
+            api_key: "key".to_string(),
+            friendly_id: "ABC123".to_string(),
+            image_url: "https://example.com/setup.bmp".to_string(),
+        };
+        assert_round_trip(
+            &setup,
+            json!({
+                "status": 200,
+                "api_key": "key",
+                "friendly_id": "ABC123",
+                "image_url": "https://example.com/setup.bmp"
+            }),
+        );
+        assert_eq!(
+            ByosSetupResponse::from(setup).message,
+            "Device ABC123 registered"
+        );
+
+        // Optional fields are left out rather than sent as null
+        let display = DisplayResponse {
+            filename: "room-a-9f3c2a1b04d7e6c5.bmp".to_string(),
+            image_url: "data:image/bmp;base64,".to_string(),
+            image_url_timeout: 0,
+            refresh_rate: 300,
+            image_signature: None,
+            sleep_until: None,
+        };
+        assert_round_trip(
+            &display,
+            json!({
+                "filename": "room-a-9f3c2a1b04d7e6c5.bmp",
+                "image_url": "data:image/bmp;base64,",
+                "image_url_timeout": 0,
+                "refresh_rate": 300
+            }),
+        );
+        let byos = ByosDisplayResponse::from(DisplayResponse {
+            sleep_until: Some(1700000000),
+            ..display
+        });
+        assert_round_trip(
+            &byos,
+            json!({
+                "status": 0,
+                "filename": "room-a-9f3c2a1b04d7e6c5.bmp",
+                "image_url": "data:image/bmp;base64,",
+                "image_url_timeout": 0,
+                "refresh_rate": 300,
+                "reset_firmware": false,
+                "update_firmware": false,
+                "firmware_url": null,
+                "special_function": "none",
+                "sleep_until": 1700000000
+            }),
+        );
+    }
+
+    #[test]
+    fn test_admin_api_round_trip() {
+        let device = DeviceInfo {
+            id: "AA:BB:CC:DD:EE:FF".to_string(),
+            id_type: Some(DeviceIdKind::Mac),
+            registered_at: 1700000000,
+            room_id: Some("room-a".to_string()),
+            model: Some("og".to_string()),
+            firmware_version: None,
+            last_payload_format: Some("bmp".to_string()),
+            last_payload_bytes: Some(48062),
+            health_score: 100,
+            health: DeviceHealth::default(),
+            image_delivery: None,
+            image_format: None,
+            replaced_by: None,
+            name: None,
+            last_seen_at: None,
+            battery_voltage: None,
+            rssi: None,
+            status: DeviceStatus::Pending,
+            display_alert: None,
+        };
+        let json = serde_json::to_value(&device).unwrap();
+        assert_eq!(json["status"], "pending");
+        assert_eq!(json["id_type"], "mac");
+        assert_eq!(json["health"]["reboots"], 0);
+        assert_eq!(serde_json::from_value::<DeviceInfo>(json).unwrap(), device);
+
+        // Responses of servers from before a field was added still parse
+        let mut old = serde_json::to_value(&device).unwrap();
+        for field in [
+            "firmware_version",
+            "image_format",
+            "replaced_by",
+            "name",
+            "battery_voltage",
+            "rssi",
+            "display_alert",
+        ] {
+            old.as_object_mut().unwrap().remove(field);
+        }
+        assert_eq!(serde_json::from_value::<DeviceInfo>(old).unwrap(), device);
+
+        assert_round_trip(
+            &FleetSummary {
+                devices: DeviceSummary {
+                    total: 2,
+                    online: 1,
+                    pending: 1,
+                    ..DeviceSummary::default()
+                },
+                rooms: RoomSummary::default(),
+                calendars: CalendarSummary::default(),
+            },
+            json!({
+                "devices": {"total": 2, "online": 1, "offline": 0, "pending": 1, "low_battery": 0},
+                "rooms": {"total": 0, "free": 0, "busy": 0, "error": 0, "unknown": 0, "degraded": 0},
+                "calendars": {"total": 0, "ok": 0, "failing": 0, "unknown": 0}
+            }),
+        );
+
+        // Request defaults apply to fields left out by clients
+        let broadcast: BroadcastRequest =
+            serde_json::from_value(json!({"message": "Fire drill"})).unwrap();
+        assert_eq!(broadcast.duration_minutes, 60);
+        let claim: ClaimCodeRequest = serde_json::from_value(json!({"room_id": "room-a"})).unwrap();
+        assert_eq!(claim.expires_in_hours, 72);
+    }
+
+    #[test]
+    fn test_issue_and_dnd_round_trip() {
+        let issue = IssueReport {
+            id: 1,
+            room_id: "room-a".to_string(),
+            category: "projector".to_string(),
+            description: "broken".to_string(),
+            created_at: 1700000000,
+        };
+        assert_round_trip(
+            &issue,
+            json!({
+                "id": 1,
+                "room_id": "room-a",
+                "category": "projector",
+                "description": "broken",
+                "created_at": 1700000000
+            }),
+        );
+
+        let dnd = DndResponse {
+            room_id: "room-a".to_string(),
+            meeting: "Board meeting".to_string(),
+            expires_at: 1700003600,
+            set_by: "facilities".to_string(),
+        };
+        assert_round_trip(
+            &dnd,
+            json!({
+                "room_id": "room-a",
+                "meeting": "Board meeting",
+                "expires_at": 1700003600,
+                "set_by": "facilities"
+            }),
+        );
+    }
+}
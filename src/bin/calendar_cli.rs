@@ -69,10 +69,7 @@ async fn main() -> Result<()> {
 fn print_event(event: &CalendarEvent) {
     println!("Title: {}", event.name);
     println!("Time: {}", event.format_time_range());
-    println!(
-        "Date: {}",
-        event.start_time.format("%A, %B %d, %Y").to_string()
-    );
+    println!("Date: {}", event.start_time.format("%A, %B %d, %Y"));
     println!("Duration: {} minutes", event.duration_minutes);
 
     if event.is_current() {
@@ -1,15 +1,67 @@
 use std::{
+    borrow::Cow,
+    collections::HashMap,
     fs::File,
+    hash::{DefaultHasher, Hash, Hasher},
     io::{Cursor, Read},
     path::Path,
+    sync::{Arc, Mutex},
 };
 
 use anyhow::{Context, Result};
-use image::{ImageBuffer, Luma, codecs::bmp::BmpEncoder};
-use imageproc::drawing::draw_text_mut;
+use image::{GrayImage, ImageBuffer, Luma, codecs::bmp::BmpEncoder, imageops::FilterType};
+use imageproc::{
+    drawing::{draw_filled_rect_mut, draw_text_mut},
+    rect::Rect,
+};
 use rusttype::{Font, Scale};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug_span, warn};
+use unicode_bidi::{BidiInfo, Direction, get_base_direction};
+
+use crate::error_code::ErrorCode;
+use crate::render::layout::{Band, CurrentMeeting, Layout, StatusBar};
+
+/// Errors of image generation, other than lock and watermark failures
+#[derive(Debug, Error)]
+pub enum BmpError {
+    #[error("Failed to read font file at {path}")]
+    FontMissing {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to parse font data of {0}")]
+    InvalidFont(String),
+
+    #[error("Failed to encode BMP image")]
+    Encode(#[from] image::ImageError),
+
+    #[error("Failed to encode PNG image")]
+    EncodePng(#[from] png::EncodingError),
+
+    #[error("External renderer failed: {0}")]
+    Remote(String),
+}
 
+impl BmpError {
+    /// Short code of the error, shown in API error bodies
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            BmpError::FontMissing { .. } => ErrorCode::FontMissing,
+            BmpError::InvalidFont(_) => ErrorCode::FontInvalid,
+            BmpError::Encode(_) | BmpError::EncodePng(_) | BmpError::Remote(_) => {
+                ErrorCode::RenderFailed
+            }
+        }
+    }
+}
 /// Configuration for image generation
+///
+/// Serializable, so that rendering can be offloaded to an external service.
+#[derive(Serialize, Deserialize)]
 pub struct ImageConfig {
     /// Width of the image
     pub width: u32,
@@ -23,6 +75,303 @@ pub struct ImageConfig {
     pub text: String,
     /// Border padding around the text
     pub border_padding: i32,
+    /// Optional small notice shown as an inverted badge in the bottom left corner
+    pub footer: Option<String>,
+    /// Optional icon shown in the badge before the notice
+    pub footer_icon: Option<Icon>,
+    /// Optional attention message shown as an inverted banner across the top
+    pub banner: Option<String>,
+    /// Optional room status shown in large text as an inverted banner below
+    /// the attention banner, e.g. `FREE for 45 min`
+    pub status_indicator: Option<String>,
+    /// Optional header with a title on the left and details on the right
+    pub header: Option<Header>,
+    /// Optional small text shown in the bottom right corner
+    pub footer_text: Option<String>,
+    /// Optional agenda shown instead of the centered text
+    pub agenda: Option<AgendaSection>,
+    /// Agendas of the following days, shown below the agenda
+    pub further_days: Vec<AgendaSection>,
+    /// Arrangement of the agenda and the blocks below the banner
+    pub layout: Layout,
+    /// Meeting in progress, shown above the agenda by layouts with a block for it
+    pub current: Option<CurrentMeeting>,
+    /// Clock and battery level, shown centered in the footer
+    pub status_bar: Option<StatusBar>,
+    /// Background elements drawn beneath the content
+    pub background: Background,
+    /// Bits per pixel of the encoded BMP
+    pub color_depth: ColorDepth,
+    /// How shades of gray are reduced to black and white
+    pub dither: Dither,
+    /// File format of the encoded image
+    pub format: ImageFormat,
+}
+
+/// File format of the encoded image
+///
+/// Newer firmware also accepts PNG, which is compressed and therefore a
+/// fraction of the size of a BMP, especially as a base64 data URL.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFormat {
+    #[default]
+    Bmp,
+    Png,
+}
+
+impl ImageFormat {
+    /// Name of the format, as used in the configuration and as file extension
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Png => "png",
+        }
+    }
+
+    /// Media type of the format
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ImageFormat::Bmp => "image/bmp",
+            ImageFormat::Png => "image/png",
+        }
+    }
+
+    /// Format of an image file, by its extension
+    pub fn of_file_name(name: &str) -> Option<ImageFormat> {
+        let (_, extension) = name.rsplit_once('.')?;
+        extension.parse().ok()
+    }
+
+    /// Pick the image format for a device
+    ///
+    /// A format set by an admin always wins. Otherwise PNG is used if the
+    /// device accepts it (`Accept: image/png`), BMP if it only accepts BMP,
+    /// and the configured default if it names neither. Media ranges with
+    /// `q=0` count as not accepted.
+    pub fn negotiate(
+        default: ImageFormat,
+        device_setting: Option<ImageFormat>,
+        accept: Option<&str>,
+    ) -> ImageFormat {
+        if let Some(format) = device_setting {
+            return format;
+        }
+        let accepted: Vec<ImageFormat> = accept
+            .into_iter()
+            .flat_map(|ranges| ranges.split(','))
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let media_type = parts.next()?.trim();
+                let rejected = parts.any(|param| {
+                    param
+                        .trim()
+                        .strip_prefix("q=")
+                        .and_then(|q| q.trim().parse::<f32>().ok())
+                        .is_some_and(|q| q == 0.0)
+                });
+                [ImageFormat::Png, ImageFormat::Bmp]
+                    .into_iter()
+                    .find(|format| media_type.eq_ignore_ascii_case(format.content_type()))
+                    .filter(|_| !rejected)
+            })
+            .collect();
+        if accepted.contains(&ImageFormat::Png) {
+            ImageFormat::Png
+        } else if accepted.contains(&ImageFormat::Bmp) {
+            ImageFormat::Bmp
+        } else {
+            default
+        }
+    }
+}
+
+impl std::str::FromStr for ImageFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bmp" => Ok(ImageFormat::Bmp),
+            "png" => Ok(ImageFormat::Png),
+            other => Err(anyhow::anyhow!(
+                "Unknown IMAGE_FORMAT: {} (expected bmp or png)",
+                other
+            )),
+        }
+    }
+}
+
+/// Reduction of shades of gray to black and white
+///
+/// Without dithering, every pixel darker than mid-gray becomes black, so
+/// shaded regions, e.g. of logos, turn into solid blocks on the display.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dither {
+    /// Hard threshold at mid-gray
+    #[default]
+    None,
+    /// Floyd–Steinberg error diffusion, best for photos and gradients
+    FloydSteinberg,
+    /// Ordered dithering with a 4x4 Bayer matrix, a regular pattern that
+    /// stays stable when the rest of the image changes
+    Ordered,
+}
+
+impl std::str::FromStr for Dither {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Dither::None),
+            "floyd-steinberg" => Ok(Dither::FloydSteinberg),
+            "ordered" => Ok(Dither::Ordered),
+            other => Err(anyhow::anyhow!(
+                "Unknown DITHER: {} (expected none, floyd-steinberg or ordered)",
+                other
+            )),
+        }
+    }
+}
+
+/// Pixel format of the encoded BMP
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorDepth {
+    /// 8 bits per pixel, with anti-aliased text
+    #[default]
+    Grayscale,
+    /// 1 bit per pixel with a black and white palette, as expected by the
+    /// TRMNL firmware, an eighth of the size
+    Monochrome,
+}
+
+/// Small icon drawn next to a text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Icon {
+    /// Battery outline with a single bar left
+    BatteryLow,
+}
+
+/// Background elements, which make screens distinguishable from afar
+///
+/// Both are drawn as sparse black dots, i.e. they appear as a light gray on
+/// the monochrome display and do not impair the legibility of the text.
+#[derive(Debug, Clone, Default, Hash, Serialize, Deserialize)]
+pub struct Background {
+    /// Light diagonal hatch across the whole image, e.g. while a room is busy
+    pub hatch: bool,
+    /// Path to an image, e.g. a company logo, shown as a dithered watermark
+    pub watermark_path: Option<String>,
+}
+
+/// Header line at the top of the image, separated from the content by a rule
+#[derive(Hash, Serialize, Deserialize)]
+pub struct Header {
+    /// Title shown on the left, e.g. the room name
+    pub title: String,
+    /// Details shown on the right, e.g. floor and capacity
+    pub details: Option<String>,
+    /// Small inverted badges shown after the title, e.g. room equipment
+    pub badges: Vec<String>,
+}
+
+/// List of upcoming meetings under a heading, e.g. "Tomorrow"
+#[derive(Hash, Serialize, Deserialize)]
+pub struct AgendaSection {
+    /// Heading of the section
+    pub heading: String,
+    /// One line per meeting
+    pub items: Vec<AgendaItem>,
+    /// Room status shown as an inverted label at the end of the heading line, e.g. `FREE`
+    pub status: Option<String>,
+}
+
+/// Line of the agenda
+#[derive(Hash, Serialize, Deserialize)]
+pub struct AgendaItem {
+    /// Text of the line, e.g. `09:00 Planning`
+    pub text: String,
+    /// Tag shown in a dashed box after the text, e.g. the status of a room
+    pub tag: Option<String>,
+    /// Visual treatment of the line
+    pub style: ItemStyle,
+}
+
+impl AgendaItem {
+    /// A regular item without a tag
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            tag: None,
+            style: ItemStyle::Regular,
+        }
+    }
+}
+
+/// Visual treatment of an agenda line, e.g. for events of a certain category
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemStyle {
+    #[default]
+    Regular,
+    /// Text in bold
+    Bold,
+    /// Line on a hatched background, e.g. for maintenance
+    Hatched,
+}
+
+/// Length of the dashes of tag boxes, and of the gaps between them
+const DASH_LENGTH: i32 = 4;
+
+/// Characters of a text that are rendered, the rest is cut off
+///
+/// Meeting titles come from calendars anyone can invite the room to, so they
+/// can be arbitrarily long. Even at the smallest font size, no more fit on a
+/// display.
+const MAX_TEXT_CHARS: usize = 500;
+
+impl ImageConfig {
+    /// Hash of everything that affects the rendered image
+    ///
+    /// Equal fingerprints mean equal images, as long as the font and
+    /// watermark files do not change. Fingerprints are only comparable within
+    /// the same process.
+    pub fn fingerprint(&self) -> u64 {
+        // Destructured, so that new fields cannot be forgotten here
+        let ImageConfig {
+            width,
+            height,
+            font_path,
+            font_size,
+            text,
+            border_padding,
+            footer,
+            footer_icon,
+            banner,
+            status_indicator,
+            header,
+            footer_text,
+            agenda,
+            further_days,
+            layout,
+            current,
+            status_bar,
+            background,
+            color_depth,
+            dither,
+            format,
+        } = self;
+        let mut hasher = DefaultHasher::new();
+        (width, height, font_path, font_size.to_bits()).hash(&mut hasher);
+        (text, border_padding, footer, banner).hash(&mut hasher);
+        (header, footer_text, agenda, background).hash(&mut hasher);
+        (further_days, layout, current, status_bar).hash(&mut hasher);
+        (status_indicator, footer_icon).hash(&mut hasher);
+        (color_depth, dither, format).hash(&mut hasher);
+        hasher.finish()
+    }
 }
 
 impl Default for ImageConfig {
@@ -34,12 +383,80 @@ impl Default for ImageConfig {
             font_size: 50.0,
             text: "hello world".to_string(),
             border_padding: 20,
+            footer: None,
+            footer_icon: None,
+            banner: None,
+            status_indicator: None,
+            header: None,
+            footer_text: None,
+            agenda: None,
+            further_days: Vec::new(),
+            layout: Layout::default(),
+            current: None,
+            status_bar: None,
+            background: Background::default(),
+            color_depth: ColorDepth::default(),
+            dither: Dither::default(),
+            format: ImageFormat::default(),
+        }
+    }
+}
+
+/// Renderer of display images, caching the parsed fonts
+#[derive(Default)]
+pub struct Renderer {
+    fonts: Mutex<HashMap<String, Arc<Font<'static>>>>,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Generate a monochrome BMP, loading the font only on first use
+    pub fn render(&self, config: &ImageConfig) -> Result<Vec<u8>> {
+        let font = self.font(&config.font_path)?;
+        render_bmp(config, &font)
+    }
+
+    fn font(&self, path: &str) -> Result<Arc<Font<'static>>> {
+        let mut fonts = self
+            .fonts
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to acquire lock on font cache: {}", e))?;
+        if let Some(font) = fonts.get(path) {
+            return Ok(font.clone());
         }
+        let font = Arc::new(load_font(path)?);
+        fonts.insert(path.to_string(), font.clone());
+        Ok(font)
     }
 }
 
+/// Load and parse a TrueType font
+fn load_font(path: &str) -> Result<Font<'static>> {
+    let _span = debug_span!("font_load", path = %path).entered();
+    let mut font_data = Vec::new();
+    File::open(Path::new(path))
+        .and_then(|mut file| file.read_to_end(&mut font_data))
+        .map_err(|source| BmpError::FontMissing {
+            path: path.to_string(),
+            source,
+        })?;
+    Ok(Font::try_from_vec(font_data).ok_or_else(|| BmpError::InvalidFont(path.to_string()))?)
+}
+
 /// Generate a monochrome BMP with text using the given configuration
+///
+/// Each stage (font loading, layout, rasterization, encoding) runs in its own
+/// tracing span, nested in a `render` span.
 pub fn generate_bmp(config: &ImageConfig) -> Result<Vec<u8>> {
+    render_bmp(config, &load_font(&config.font_path)?)
+}
+
+fn render_bmp(config: &ImageConfig, font: &Font) -> Result<Vec<u8>> {
+    let _render = debug_span!("render", width = config.width, height = config.height).entered();
+
     // Create the image buffer
     let mut img = ImageBuffer::<Luma<u8>, Vec<u8>>::new(config.width, config.height);
 
@@ -48,16 +465,15 @@ pub fn generate_bmp(config: &ImageConfig) -> Result<Vec<u8>> {
         *pixel = Luma([255]); // White
     }
 
-    // Load font
-    let font_path = Path::new(&config.font_path);
-    let mut font_data = Vec::new();
-    File::open(font_path)
-        .with_context(|| format!("Failed to open font file at {}", &config.font_path))?
-        .read_to_end(&mut font_data)
-        .context("Failed to read font data")?;
-
-    let font = Font::try_from_bytes(&font_data)
-        .ok_or_else(|| anyhow::anyhow!("Failed to parse font data"))?;
+    if config.background.hatch {
+        draw_hatch(&mut img);
+    }
+    if let Some(path) = &config.background.watermark_path {
+        // A missing logo should not take the display down
+        if let Err(e) = draw_watermark(&mut img, path) {
+            warn!("Failed to draw watermark: {:#}", e);
+        }
+    }
 
     // Configure text scale (font size)
     let scale = Scale {
@@ -65,59 +481,891 @@ pub fn generate_bmp(config: &ImageConfig) -> Result<Vec<u8>> {
         y: config.font_size,
     };
 
-    // Calculate text dimensions to center it
-    let v_metrics = font.v_metrics(scale);
-    let text_width = font
-        .layout(&config.text, scale, rusttype::point(0.0, 0.0))
-        .map(|g| g.position().x + g.unpositioned().h_metrics().advance_width)
-        .last()
-        .unwrap_or(0.0);
+    // The agenda replaces the centered main text
+    let rasterize_span;
+    if config.agenda.is_none() {
+        // Wrap text into lines that fit between the borders
+        let layout_span = debug_span!("layout", lines = tracing::field::Empty).entered();
+        let max_text_width = config.width as f32 - 4.0 * config.border_padding as f32;
+        let lines = wrap_text(font, scale, &config.text, max_text_width);
 
-    // Position text in the center of the image
-    let x = ((config.width as f32 - text_width) / 2.0).floor() as i32;
-    let y = ((config.height as f32 - v_metrics.ascent + v_metrics.descent) / 2.0).floor() as i32;
+        // Calculate text dimensions to center it
+        let v_metrics = font.v_metrics(scale);
+        let text_height = (v_metrics.ascent - v_metrics.descent) as i32;
+        let line_height = text_height + v_metrics.line_gap.ceil() as i32;
+        let block_height = text_height + line_height * (lines.len() as i32 - 1);
+        let line_widths: Vec<f32> = lines
+            .iter()
+            .map(|line| text_width(font, scale, line))
+            .collect();
+        let block_width = line_widths.iter().cloned().fold(0.0, f32::max);
 
-    // Draw text
-    draw_text_mut(
-        &mut img,
-        Luma([0]), // Black text
-        x,
-        y,
-        scale,
-        &font,
-        &config.text,
-    );
+        // Position text block in the center of the image
+        let x = ((config.width as f32 - block_width) / 2.0).floor() as i32;
+        let y = ((config.height as i32 - block_height) as f32 / 2.0).floor() as i32;
+        layout_span.record("lines", lines.len());
+        drop(layout_span);
+        rasterize_span = debug_span!("rasterize").entered();
+
+        // Draw text, each line centered horizontally
+        for (i, (line, width)) in lines.iter().zip(&line_widths).enumerate() {
+            draw_text(
+                &mut img,
+                Luma([0]), // Black text
+                ((config.width as f32 - width) / 2.0).floor() as i32,
+                y + i as i32 * line_height,
+                scale,
+                font,
+                line,
+            );
+        }
+
+        // Draw a border around the text for visibility
+        let border_x = x - config.border_padding;
+        let border_y = y - config.border_padding;
+        let border_width = block_width as i32 + (2 * config.border_padding);
+        let border_height = block_height + (2 * config.border_padding);
 
-    // Draw a border around the text for visibility
-    let text_height = (v_metrics.ascent - v_metrics.descent) as i32;
-
-    let border_x = x - config.border_padding;
-    let border_y = y - config.border_padding;
-    let border_width = text_width as i32 + (2 * config.border_padding);
-    let border_height = text_height + (2 * config.border_padding);
-
-    draw_border(
-        &mut img,
-        border_x,
-        border_y,
-        border_width,
-        border_height,
-        config.width,
-        config.height,
+        draw_border(
+            &mut img,
+            border_x,
+            border_y,
+            border_width,
+            border_height,
+            config.width,
+            config.height,
+        );
+    } else {
+        rasterize_span = debug_span!("rasterize").entered();
+    }
+
+    let header_height = match &config.header {
+        Some(header) => draw_header(&mut img, font, config, header),
+        None => 0,
+    };
+    let banner_bottom = match &config.banner {
+        Some(banner) => draw_banner(&mut img, font, config, banner, header_height, 0.6),
+        None => header_height,
+    };
+    let indicator_bottom = match &config.status_indicator {
+        Some(status) => draw_banner(&mut img, font, config, status, banner_bottom, 0.9),
+        None => banner_bottom,
+    };
+    let bands = config.layout.bands(
+        indicator_bottom,
+        config.height as i32,
+        config.font_size as i32,
     );
+    if let (Some(current), Some(band)) = (&config.current, bands.current) {
+        draw_current(&mut img, font, config, current, band);
+    }
+    if let Some(agenda) = &config.agenda {
+        let mut list = bands.list;
+        list.top = draw_agenda(&mut img, font, config, agenda, list);
+        for day in &config.further_days {
+            list.top = draw_agenda(&mut img, font, config, day, list);
+        }
+    }
+    if let Some(status_bar) = &config.status_bar {
+        draw_status_bar(&mut img, font, config, status_bar);
+    }
+    if let Some(footer) = &config.footer {
+        draw_badge(&mut img, font, config, footer);
+    }
+    if let Some(footer_text) = &config.footer_text {
+        draw_footer_text(&mut img, font, config, footer_text);
+    }
+    drop(rasterize_span);
 
-    // Convert to monochrome BMP
+    let _encode = debug_span!("encode", format = config.format.as_str()).entered();
+    dither(&mut img, config.dither);
+    if config.format == ImageFormat::Png {
+        return encode_png(&img, config.color_depth);
+    }
+    if config.color_depth == ColorDepth::Monochrome {
+        return Ok(encode_monochrome(&img));
+    }
     let mut cursor = Cursor::new(Vec::new());
     let mut encoder = BmpEncoder::new(&mut cursor);
 
     // Encode the image
     encoder
         .encode(&img, config.width, config.height, image::ColorType::L8)
-        .context("Failed to encode BMP image")?;
+        .map_err(BmpError::Encode)?;
 
     Ok(cursor.into_inner())
 }
 
+/// Reduce an image to black and white pixels with the given method
+///
+/// Leaves the image untouched without dithering, the threshold is then
+/// applied by the encoder, if at all.
+fn dither(img: &mut GrayImage, method: Dither) {
+    let _span = debug_span!("dither", method = ?method).entered();
+    match method {
+        Dither::None => {}
+        Dither::Ordered => {
+            for (x, y, pixel) in img.enumerate_pixels_mut() {
+                let black = (pixel.0[0] as u32) < bayer_threshold(x, y);
+                pixel.0[0] = if black { 0 } else { 255 };
+            }
+        }
+        Dither::FloydSteinberg => {
+            let (width, height) = (img.width() as usize, img.height() as usize);
+            // Errors of the current and the next row, with a pixel of margin
+            // on both sides
+            let mut errors = [vec![0i32; width + 2], vec![0i32; width + 2]];
+            for y in 0..height {
+                errors[1].fill(0);
+                for x in 0..width {
+                    let pixel = img.get_pixel_mut(x as u32, y as u32);
+                    let value = (pixel.0[0] as i32 + errors[0][x + 1] / 16).clamp(0, 255);
+                    let output = if value < 128 { 0 } else { 255 };
+                    pixel.0[0] = output as u8;
+                    let error = value - output;
+                    errors[0][x + 2] += error * 7;
+                    errors[1][x] += error * 3;
+                    errors[1][x + 1] += error * 5;
+                    errors[1][x + 2] += error;
+                }
+                errors.swap(0, 1);
+            }
+        }
+    }
+}
+
+/// Size of the file and info headers and the palette of a monochrome BMP
+const MONOCHROME_HEADER_SIZE: u32 = 14 + 40 + 2 * 4;
+
+/// Encode an image as 1 bit per pixel BMP
+///
+/// Pixels darker than mid-gray become black. Palette index 0 is black and
+/// index 1 white, rows are stored bottom-up and padded to 4 bytes.
+fn encode_monochrome(img: &GrayImage) -> Vec<u8> {
+    let (width, height) = img.dimensions();
+    let row_size = width.div_ceil(32) * 4;
+    let image_size = row_size * height;
+    let file_size = MONOCHROME_HEADER_SIZE + image_size;
+
+    let mut bmp = Vec::with_capacity(file_size as usize);
+    // File header
+    bmp.extend_from_slice(b"BM");
+    bmp.extend_from_slice(&file_size.to_le_bytes());
+    bmp.extend_from_slice(&[0; 4]);
+    bmp.extend_from_slice(&MONOCHROME_HEADER_SIZE.to_le_bytes());
+    // Info header (BITMAPINFOHEADER)
+    bmp.extend_from_slice(&40u32.to_le_bytes());
+    bmp.extend_from_slice(&(width as i32).to_le_bytes());
+    bmp.extend_from_slice(&(height as i32).to_le_bytes());
+    bmp.extend_from_slice(&1u16.to_le_bytes()); // Planes
+    bmp.extend_from_slice(&1u16.to_le_bytes()); // Bits per pixel
+    bmp.extend_from_slice(&0u32.to_le_bytes()); // Uncompressed
+    bmp.extend_from_slice(&image_size.to_le_bytes());
+    bmp.extend_from_slice(&2835i32.to_le_bytes()); // 72 DPI
+    bmp.extend_from_slice(&2835i32.to_le_bytes());
+    bmp.extend_from_slice(&2u32.to_le_bytes()); // Colors in the palette
+    bmp.extend_from_slice(&2u32.to_le_bytes());
+    // Palette, as blue, green, red and a reserved byte
+    bmp.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+    bmp.extend_from_slice(&[0xff, 0xff, 0xff, 0x00]);
+
+    for y in (0..height).rev() {
+        let mut row = vec![0u8; row_size as usize];
+        for x in 0..width {
+            if img.get_pixel(x, y).0[0] >= 128 {
+                row[(x / 8) as usize] |= 0x80 >> (x % 8);
+            }
+        }
+        bmp.extend_from_slice(&row);
+    }
+    bmp
+}
+
+/// Encode an image as grayscale PNG
+///
+/// Monochrome images use 1 bit per pixel, with pixels darker than mid-gray
+/// becoming black, like in [`encode_monochrome`].
+fn encode_png(img: &GrayImage, color_depth: ColorDepth) -> Result<Vec<u8>> {
+    let (width, height) = img.dimensions();
+    let (bit_depth, data) = match color_depth {
+        ColorDepth::Grayscale => (png::BitDepth::Eight, Cow::Borrowed(img.as_raw().as_slice())),
+        ColorDepth::Monochrome => {
+            let row_size = width.div_ceil(8) as usize;
+            let mut data = vec![0u8; row_size * height as usize];
+            for (x, y, pixel) in img.enumerate_pixels() {
+                if pixel.0[0] >= 128 {
+                    data[y as usize * row_size + (x / 8) as usize] |= 0x80 >> (x % 8);
+                }
+            }
+            (png::BitDepth::One, Cow::Owned(data))
+        }
+    };
+
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(bit_depth);
+    encoder.set_compression(png::Compression::Best);
+    let mut writer = encoder.write_header().map_err(BmpError::EncodePng)?;
+    writer
+        .write_image_data(&data)
+        .map_err(BmpError::EncodePng)?;
+    writer.finish().map_err(BmpError::EncodePng)?;
+    Ok(png_data)
+}
+
+/// Spacing of the background hatch lines, in pixels
+const HATCH_SPACING: u32 = 16;
+
+/// Draw light diagonal lines across the image
+fn draw_hatch(img: &mut GrayImage) {
+    let (width, height) = img.dimensions();
+    for y in 0..height {
+        for x in (0..width).step_by(2) {
+            if (x + y) % HATCH_SPACING == 0 {
+                img.put_pixel(x, y, Luma([0]));
+            }
+        }
+    }
+}
+
+/// Spacing of the hatch lines of agenda items, in pixels
+const ITEM_HATCH_SPACING: i32 = 6;
+
+/// Draw diagonal lines across a rectangle
+fn draw_hatched_rect(img: &mut GrayImage, rect: Rect) {
+    for y in rect.top().max(0)..=rect.bottom().min(img.height() as i32 - 1) {
+        for x in rect.left().max(0)..=rect.right().min(img.width() as i32 - 1) {
+            if (x + y) % ITEM_HATCH_SPACING == 0 {
+                img.put_pixel(x as u32, y as u32, Luma([0]));
+            }
+        }
+    }
+}
+
+/// 4x4 Bayer matrix for ordered dithering
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Threshold of ordered dithering at a pixel, from 8 to 248
+fn bayer_threshold(x: u32, y: u32) -> u32 {
+    BAYER_4X4[(y % 4) as usize][(x % 4) as usize] as u32 * 16 + 8
+}
+
+/// How dark the watermark is compared to the original image (0-255)
+const WATERMARK_INTENSITY: u32 = 64;
+
+/// Draw the image at `path` centered as a light, dithered watermark
+///
+/// The image is scaled to half the width and height of the display (keeping
+/// its aspect ratio), lightened and ordered-dithered to black and white.
+fn draw_watermark(img: &mut GrayImage, path: &str) -> Result<()> {
+    let watermark = image::open(path)
+        .with_context(|| format!("Failed to open watermark image at {}", path))?
+        .into_luma8();
+    let (width, height) = img.dimensions();
+    let scale = f64::min(
+        (width / 2) as f64 / watermark.width().max(1) as f64,
+        (height / 2) as f64 / watermark.height().max(1) as f64,
+    );
+    let w = ((watermark.width() as f64 * scale) as u32).clamp(1, width);
+    let h = ((watermark.height() as f64 * scale) as u32).clamp(1, height);
+    let watermark = image::imageops::resize(&watermark, w, h, FilterType::Triangle);
+    let x0 = (width - w) / 2;
+    let y0 = (height - h) / 2;
+
+    for (x, y, pixel) in watermark.enumerate_pixels() {
+        let darkness = (255 - pixel[0] as u32) * WATERMARK_INTENSITY / 255;
+        if darkness > bayer_threshold(x, y) {
+            img.put_pixel(x0 + x, y0 + y, Luma([0]));
+        }
+    }
+    Ok(())
+}
+
+fn draw_header(
+    img: &mut ImageBuffer<Luma<u8>, Vec<u8>>,
+    font: &Font,
+    config: &ImageConfig,
+    header: &Header,
+) -> i32 {
+    let scale = Scale::uniform(config.font_size * 0.5);
+    let v_metrics = font.v_metrics(scale);
+    let text_height = (v_metrics.ascent - v_metrics.descent).ceil() as i32;
+    let padding = (config.border_padding / 2).max(1);
+    let header_height = text_height + 2 * padding;
+    if header_height as u32 >= config.height {
+        return 0;
+    }
+
+    // Right-to-left titles start on the right, with the details on the left
+    let rtl = is_rtl(&header.title);
+    let title_width = text_width(font, scale, &header.title).ceil() as i32;
+    let title_x = if rtl {
+        config.width as i32 - padding - title_width
+    } else {
+        padding
+    };
+    draw_text(img, Luma([0]), title_x, padding, scale, font, &header.title);
+    let mut details_width = 0;
+    if let Some(details) = &header.details {
+        let width = text_width(font, scale, details).ceil() as i32;
+        let x = if rtl {
+            padding
+        } else {
+            config.width as i32 - padding - width
+        };
+        // Skip the details rather than overlap the title
+        if title_width + width + 3 * padding < config.width as i32 {
+            draw_text(img, Luma([0]), x, padding, scale, font, details);
+            details_width = width + padding;
+        }
+    }
+
+    // Badges follow the title, as many as fit before the details
+    let badge_scale = Scale::uniform(config.font_size * 0.35);
+    let badge_v_metrics = font.v_metrics(badge_scale);
+    let badge_text_height = (badge_v_metrics.ascent - badge_v_metrics.descent).ceil() as i32;
+    let badge_padding = (padding / 2).max(1);
+    let badge_height = badge_text_height + 2 * badge_padding;
+    let badge_y = (header_height - badge_height) / 2;
+    let mut offset = padding + title_width + padding;
+    for badge in &header.badges {
+        let width = text_width(font, badge_scale, badge).ceil() as i32 + 2 * badge_padding;
+        if offset + width + details_width + padding > config.width as i32 {
+            break;
+        }
+        let x = if rtl {
+            config.width as i32 - offset - width
+        } else {
+            offset
+        };
+        draw_filled_rect_mut(
+            img,
+            Rect::at(x, badge_y).of_size(width as u32, badge_height.max(1) as u32),
+            Luma([0]),
+        );
+        draw_text(
+            img,
+            Luma([255]),
+            x + badge_padding,
+            badge_y + badge_padding,
+            badge_scale,
+            font,
+            badge,
+        );
+        offset += width + padding;
+    }
+    draw_filled_rect_mut(
+        img,
+        Rect::at(0, header_height - 2).of_size(config.width, 2),
+        Luma([0]),
+    );
+    header_height
+}
+
+/// Draw an inverted (white on black) banner with centered text across the top
+///
+/// The banner starts at `top`, i.e. below the header if there is one, and its
+/// text is `size` times the font size. Returns the bottom of the banner.
+fn draw_banner(
+    img: &mut ImageBuffer<Luma<u8>, Vec<u8>>,
+    font: &Font,
+    config: &ImageConfig,
+    text: &str,
+    top: i32,
+    size: f32,
+) -> i32 {
+    let scale = Scale::uniform(config.font_size * size);
+    let v_metrics = font.v_metrics(scale);
+    let text_height = (v_metrics.ascent - v_metrics.descent).ceil() as i32;
+    let banner_height = text_height + config.border_padding.max(0);
+    if banner_height <= 0 || (top + banner_height) as u32 > config.height {
+        return top;
+    }
+
+    draw_filled_rect_mut(
+        img,
+        Rect::at(0, top).of_size(config.width, banner_height as u32),
+        Luma([0]),
+    );
+    let x = ((config.width as f32 - text_width(font, scale, text)) / 2.0).max(0.0) as i32;
+    let y = top + (banner_height - text_height) / 2;
+    draw_text(img, Luma([255]), x, y, scale, font, text);
+    top + banner_height
+}
+
+/// Draw the meeting in progress: the room status as an inverted label, with
+/// the name of the meeting in large text below it
+///
+/// Lines of the name that do not fit into the band are left out.
+fn draw_current(
+    img: &mut ImageBuffer<Luma<u8>, Vec<u8>>,
+    font: &Font,
+    config: &ImageConfig,
+    current: &CurrentMeeting,
+    band: Band,
+) {
+    let status_scale = Scale::uniform(config.font_size * 0.7);
+    let title_scale = Scale::uniform(config.font_size);
+    let line_height = |scale| {
+        let v_metrics = font.v_metrics(scale);
+        (v_metrics.ascent - v_metrics.descent + v_metrics.line_gap).ceil() as i32
+    };
+    let x = config.border_padding.max(0);
+    let padding = (config.border_padding / 2).max(1);
+    // Leave room for the rule separating the block from the agenda
+    let bottom = band.bottom - padding - 2;
+
+    let mut y = band.top + padding;
+    if y + line_height(status_scale) > bottom {
+        return;
+    }
+    let status_width = text_width(font, status_scale, &current.status).ceil() as i32;
+    let status_x = if is_rtl(&current.status) {
+        config.width as i32 - x - status_width - padding
+    } else {
+        x + padding
+    };
+    draw_filled_rect_mut(
+        img,
+        Rect::at(status_x - padding, y).of_size(
+            (status_width + 2 * padding).max(1) as u32,
+            line_height(status_scale).max(1) as u32,
+        ),
+        Luma([0]),
+    );
+    draw_text(
+        img,
+        Luma([255]),
+        status_x,
+        y,
+        status_scale,
+        font,
+        &current.status,
+    );
+    y += line_height(status_scale) + padding;
+
+    if let Some(title) = &current.title {
+        let max_width = config.width as f32 - 2.0 * x as f32;
+        for line in wrap_text(font, title_scale, title, max_width) {
+            if y + line_height(title_scale) > bottom {
+                break;
+            }
+            let line_x = if is_rtl(&line) {
+                config.width as i32 - x - text_width(font, title_scale, &line).ceil() as i32
+            } else {
+                x
+            };
+            draw_text(img, Luma([0]), line_x, y, title_scale, font, &line);
+            y += line_height(title_scale);
+        }
+    }
+
+    draw_filled_rect_mut(
+        img,
+        Rect::at(x, band.bottom - 2).of_size((config.width as i32 - 2 * x).max(1) as u32, 2),
+        Luma([0]),
+    );
+}
+
+/// Draw the agenda as a left-aligned heading with one line per item
+///
+/// The agenda is drawn into the band of the list, i.e. below the header and
+/// banner, and below the current meeting if there is one. Items that do not
+/// fit are left out. Returns the bottom of the agenda.
+fn draw_agenda(
+    img: &mut ImageBuffer<Luma<u8>, Vec<u8>>,
+    font: &Font,
+    config: &ImageConfig,
+    agenda: &AgendaSection,
+    band: Band,
+) -> i32 {
+    let heading_scale = Scale::uniform(config.font_size * 0.8);
+    let item_scale = Scale::uniform(config.font_size * 0.6);
+    let line_height = |scale| {
+        let v_metrics = font.v_metrics(scale);
+        (v_metrics.ascent - v_metrics.descent + v_metrics.line_gap).ceil() as i32
+    };
+    let x = config.border_padding.max(0);
+    let bottom = band.bottom;
+
+    let mut y = band.top + config.border_padding.max(0);
+    if y + line_height(heading_scale) > bottom {
+        return band.bottom;
+    }
+    // Right-to-left lines are aligned to the right, with the status label on the left
+    let heading_width = text_width(font, heading_scale, &agenda.heading).ceil() as i32;
+    let heading_rtl = is_rtl(&agenda.heading);
+    let heading_x = if heading_rtl {
+        config.width as i32 - x - heading_width
+    } else {
+        x
+    };
+    draw_text(
+        img,
+        Luma([0]),
+        heading_x,
+        y,
+        heading_scale,
+        font,
+        &agenda.heading,
+    );
+    if let Some(status) = &agenda.status {
+        let padding = (config.border_padding / 2).max(1);
+        let width = text_width(font, heading_scale, status).ceil() as i32;
+        let label_x = if heading_rtl {
+            x + padding
+        } else {
+            config.width as i32 - x - width - padding
+        };
+        let label_height = line_height(heading_scale);
+        if x + heading_width + width + padding < config.width as i32 - x {
+            draw_filled_rect_mut(
+                img,
+                Rect::at(label_x - padding, y)
+                    .of_size((width + 2 * padding) as u32, label_height.max(1) as u32),
+                Luma([0]),
+            );
+            draw_text(img, Luma([255]), label_x, y, heading_scale, font, status);
+        }
+    }
+    y += line_height(heading_scale) + config.border_padding.max(0) / 2;
+
+    for item in &agenda.items {
+        if y + line_height(item_scale) > bottom {
+            break;
+        }
+        let item_width = text_width(font, item_scale, &item.text).ceil() as i32;
+        let rtl = is_rtl(&item.text);
+        let item_x = if rtl {
+            config.width as i32 - x - item_width
+        } else {
+            x
+        };
+        match item.style {
+            ItemStyle::Regular => {}
+            ItemStyle::Bold => {
+                draw_text(img, Luma([0]), item_x + 1, y, item_scale, font, &item.text)
+            }
+            ItemStyle::Hatched => {
+                let height = line_height(item_scale).max(1) as u32;
+                draw_hatched_rect(
+                    img,
+                    Rect::at(x, y).of_size((config.width as i32 - 2 * x).max(1) as u32, height),
+                );
+                // Keep the text readable
+                let padding = (config.border_padding / 4).max(2);
+                draw_filled_rect_mut(
+                    img,
+                    Rect::at(item_x - padding, y)
+                        .of_size((item_width + 2 * padding) as u32, height),
+                    Luma([255]),
+                );
+            }
+        }
+        draw_text(img, Luma([0]), item_x, y, item_scale, font, &item.text);
+        if let Some(tag) = &item.tag {
+            let tag_scale = Scale::uniform(config.font_size * 0.4);
+            let padding = (config.border_padding / 4).max(2);
+            let tag_width = text_width(font, tag_scale, tag).ceil() as i32 + 2 * padding;
+            let tag_height = line_height(tag_scale) + padding;
+            let tag_x = if rtl {
+                item_x - 3 * padding - tag_width
+            } else {
+                item_x + item_width + 3 * padding
+            };
+            if tag_x >= x && tag_x + tag_width <= config.width as i32 - x {
+                let tag_y = y + (line_height(item_scale) - tag_height) / 2;
+                draw_dashed_rect(
+                    img,
+                    Rect::at(tag_x, tag_y).of_size(tag_width as u32, tag_height.max(1) as u32),
+                );
+                draw_text(
+                    img,
+                    Luma([0]),
+                    tag_x + padding,
+                    tag_y + padding / 2,
+                    tag_scale,
+                    font,
+                    tag,
+                );
+            }
+        }
+        y += line_height(item_scale);
+    }
+    y
+}
+
+/// Draw the outline of a rectangle with a dashed line
+fn draw_dashed_rect(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>, rect: Rect) {
+    let dashed = |i: i32| (i / DASH_LENGTH) % 2 == 0;
+    let mut put = |x: i32, y: i32| {
+        if x >= 0 && y >= 0 && (x as u32) < img.width() && (y as u32) < img.height() {
+            img.put_pixel(x as u32, y as u32, Luma([0]));
+        }
+    };
+    for x in rect.left()..=rect.right() {
+        if dashed(x - rect.left()) {
+            put(x, rect.top());
+            put(x, rect.bottom());
+        }
+    }
+    for y in rect.top()..=rect.bottom() {
+        if dashed(y - rect.top()) {
+            put(rect.left(), y);
+            put(rect.right(), y);
+        }
+    }
+}
+
+/// Draw small text in the bottom right corner
+fn draw_footer_text(
+    img: &mut ImageBuffer<Luma<u8>, Vec<u8>>,
+    font: &Font,
+    config: &ImageConfig,
+    text: &str,
+) {
+    let scale = Scale::uniform(config.font_size * 0.4);
+    let v_metrics = font.v_metrics(scale);
+    let padding = (config.border_padding / 2).max(1);
+    let text_height = (v_metrics.ascent - v_metrics.descent).ceil() as i32;
+    let x = config.width as i32 - 2 * padding - text_width(font, scale, text).ceil() as i32;
+    let y = config.height as i32 - text_height - 2 * padding;
+    if x < 0 || y < 0 {
+        return;
+    }
+    draw_text(img, Luma([0]), x, y, scale, font, text);
+}
+
+/// Draw the status bar centered at the bottom, at the height of the footer text
+fn draw_status_bar(
+    img: &mut ImageBuffer<Luma<u8>, Vec<u8>>,
+    font: &Font,
+    config: &ImageConfig,
+    status_bar: &StatusBar,
+) {
+    let text = status_bar.text();
+    let scale = Scale::uniform(config.font_size * 0.4);
+    let v_metrics = font.v_metrics(scale);
+    let padding = (config.border_padding / 2).max(1);
+    let text_height = (v_metrics.ascent - v_metrics.descent).ceil() as i32;
+    let x = ((config.width as f32 - text_width(font, scale, &text)) / 2.0).floor() as i32;
+    let y = config.height as i32 - text_height - 2 * padding;
+    if x < 0 || y < 0 {
+        return;
+    }
+    draw_text(img, Luma([0]), x, y, scale, font, &text);
+}
+
+/// Draw an inverted (white on black) badge with the given text in the bottom
+/// left corner, preceded by the footer icon if there is one
+fn draw_badge(
+    img: &mut ImageBuffer<Luma<u8>, Vec<u8>>,
+    font: &Font,
+    config: &ImageConfig,
+    text: &str,
+) {
+    let scale = Scale::uniform(config.font_size * 0.4);
+    let v_metrics = font.v_metrics(scale);
+    let padding = (config.border_padding / 2).max(1);
+    let text_height = (v_metrics.ascent - v_metrics.descent).ceil() as i32;
+    // Icons are as wide as the text is high, followed by a padding
+    let icon_width = if config.footer_icon.is_some() {
+        text_height + padding
+    } else {
+        0
+    };
+    let max_width = config.width as i32 - 4 * padding - icon_width;
+    let width = (text_width(font, scale, text).ceil() as i32).min(max_width);
+
+    let badge_width = icon_width + width + 2 * padding;
+    let badge_height = text_height + 2 * padding;
+    let x = padding;
+    let y = config.height as i32 - badge_height - padding;
+    if badge_width <= 0 || badge_height <= 0 || y < 0 {
+        return;
+    }
+
+    draw_filled_rect_mut(
+        img,
+        Rect::at(x, y).of_size(badge_width as u32, badge_height as u32),
+        Luma([0]),
+    );
+    if let Some(icon) = config.footer_icon {
+        draw_icon(
+            img,
+            icon,
+            Rect::at(x + padding, y + padding).of_size(text_height as u32, text_height as u32),
+        );
+    }
+    draw_text(
+        img,
+        Luma([255]),
+        x + padding + icon_width,
+        y + padding,
+        scale,
+        font,
+        text,
+    );
+}
+
+/// Draw an icon in white, centered in the given square
+fn draw_icon(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>, icon: Icon, area: Rect) {
+    match icon {
+        Icon::BatteryLow => {
+            // Lying battery of twice its height, with the terminal on the right
+            let size = area.width() as i32;
+            let height = (size / 2).max(4);
+            let line = (height / 6).max(1);
+            let x = area.left();
+            let y = area.top() + (size - height) / 2;
+            let body = size - line;
+            draw_filled_rect_mut(
+                img,
+                Rect::at(x, y).of_size(body as u32, height as u32),
+                Luma([255]),
+            );
+            draw_filled_rect_mut(
+                img,
+                Rect::at(x + line, y + line)
+                    .of_size((body - 2 * line) as u32, (height - 2 * line) as u32),
+                Luma([0]),
+            );
+            draw_filled_rect_mut(
+                img,
+                Rect::at(x + body, y + height / 4).of_size(line as u32, (height / 2) as u32),
+                Luma([255]),
+            );
+            // The single bar left
+            draw_filled_rect_mut(
+                img,
+                Rect::at(x + 2 * line, y + 2 * line).of_size(
+                    ((body - 4 * line) / 4).max(1) as u32,
+                    (height - 4 * line).max(1) as u32,
+                ),
+                Luma([255]),
+            );
+        }
+    }
+}
+
+/// Draw a line of text with its left edge at `x`, in display order
+fn draw_text(
+    img: &mut ImageBuffer<Luma<u8>, Vec<u8>>,
+    color: Luma<u8>,
+    x: i32,
+    y: i32,
+    scale: Scale,
+    font: &Font,
+    text: &str,
+) {
+    draw_text_mut(img, color, x, y, scale, font, &display_text(text));
+}
+
+/// Text as it is drawn: sanitized, in display order and without invisible characters
+fn display_text(text: &str) -> String {
+    visual_order(&sanitize_text(text))
+        .chars()
+        .filter(|&c| !is_invisible(c))
+        .collect()
+}
+
+/// Text without control characters, cut off after [`MAX_TEXT_CHARS`]
+///
+/// Newlines are kept for [`wrap_text`], tabs become spaces.
+fn sanitize_text(text: &str) -> Cow<'_, str> {
+    let clean = |c: char| c == '\n' || !c.is_control();
+    if text.chars().take(MAX_TEXT_CHARS + 1).count() <= MAX_TEXT_CHARS && text.chars().all(clean) {
+        return Cow::Borrowed(text);
+    }
+    text.chars()
+        .map(|c| if c == '\t' { ' ' } else { c })
+        .filter(|&c| clean(c))
+        .take(MAX_TEXT_CHARS)
+        .collect::<String>()
+        .into()
+}
+
+/// Returns true for characters without a glyph of their own, e.g. zero-width
+/// joiners or bidi marks, which the font would draw as boxes
+///
+/// They are removed only after reordering, as bidi marks affect the order.
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}'
+            | '\u{034F}'
+            | '\u{061C}'
+            | '\u{180E}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{206F}'
+            | '\u{FE00}'..='\u{FE0F}'
+            | '\u{FEFF}'
+            | '\u{E0000}'..='\u{E007F}'
+    )
+}
+
+/// Text in display order, i.e. with right-to-left runs reversed
+///
+/// Glyphs are laid out left to right in string order, so Hebrew or Arabic
+/// text has to be reordered according to the Unicode Bidirectional Algorithm.
+/// The base direction of each paragraph is that of its first strong character.
+/// Arabic letters are not joined; the font has to provide suitable forms.
+fn visual_order(text: &str) -> Cow<'_, str> {
+    let bidi = BidiInfo::new(text, None);
+    if !bidi.has_rtl() {
+        return Cow::Borrowed(text);
+    }
+    bidi.paragraphs
+        .iter()
+        .map(|paragraph| bidi.reorder_line(paragraph, paragraph.range.clone()))
+        .collect::<String>()
+        .into()
+}
+
+/// Returns true if the text starts with a right-to-left script, so that it
+/// should be aligned to the right
+fn is_rtl(text: &str) -> bool {
+    get_base_direction(text) == Direction::Rtl
+}
+
+/// Width of a single line of text in pixels
+fn text_width(font: &Font, scale: Scale, text: &str) -> f32 {
+    font.layout(&display_text(text), scale, rusttype::point(0.0, 0.0))
+        .map(|g| g.position().x + g.unpositioned().h_metrics().advance_width)
+        .last()
+        .unwrap_or(0.0)
+}
+
+/// Split text into lines no wider than `max_width`, breaking at whitespace
+///
+/// Explicit newlines are kept. Words wider than `max_width` get a line of
+/// their own.
+fn wrap_text(font: &Font, scale: Scale, text: &str, max_width: f32) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in sanitize_text(text).lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let candidate = if line.is_empty() {
+                word.to_string()
+            } else {
+                format!("{} {}", line, word)
+            };
+            if !line.is_empty() && text_width(font, scale, &candidate) > max_width {
+                lines.push(std::mem::replace(&mut line, word.to_string()));
+            } else {
+                line = candidate;
+            }
+        }
+        lines.push(line);
+    }
+    if lines.is_empty() {
+        lines.push(String::new());
+    }
+    lines
+}
+
 /// Draw a border around the specified rectangle
 fn draw_border(
     img: &mut ImageBuffer<Luma<u8>, Vec<u8>>,
@@ -156,8 +1404,64 @@ fn draw_border(
     }
 }
 
+/// Small status badge for embedding in web pages, e.g. `Room A | FREE`
+pub struct StatusBadge {
+    /// Text on the gray left part, e.g. the room name
+    pub label: String,
+    /// Text on the colored right part, e.g. `BUSY until 14:00`
+    pub status: String,
+    /// Color of the right part, e.g. `#4c1`
+    pub color: &'static str,
+}
+
+/// Approximate width of a badge character in pixels (11px Verdana)
+const BADGE_CHAR_WIDTH: usize = 7;
+
+/// Horizontal padding of each badge part in pixels
+const BADGE_PADDING: usize = 6;
+
+/// Render a status badge as SVG
+///
+/// Unlike the display images, badges are text-based: the text is not
+/// rasterized, so the width of each part is estimated from its length.
+pub fn status_badge_svg(badge: &StatusBadge) -> String {
+    let part_width = |text: &str| text.chars().count() * BADGE_CHAR_WIDTH + 2 * BADGE_PADDING;
+    let label_width = part_width(&badge.label);
+    let status_width = part_width(&badge.status);
+    let width = label_width + status_width;
+    let label = escape_xml(&badge.label);
+    let status = escape_xml(&badge.status);
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {status}">
+<title>{label}: {status}</title>
+<rect width="{label_width}" height="20" fill="#555"/>
+<rect x="{label_width}" width="{status_width}" height="20" fill="{color}"/>
+<g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11">
+<text x="{label_x}" y="14">{label}</text>
+<text x="{status_x}" y="14">{status}</text>
+</g>
+</svg>
+"##,
+        color = badge.color,
+        label_x = label_width / 2,
+        status_x = label_width + status_width / 2,
+    )
+}
+
+/// Escape text for use in XML content and attribute values
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 #[cfg(test)]
 mod tests {
+    use std::sync::LazyLock;
+
+    use proptest::prelude::*;
+
     use super::*;
 
     #[test]
@@ -181,6 +1485,28 @@ mod tests {
             font_size: 25.0,
             text: "test image".to_string(),
             border_padding: 10,
+            footer: Some("Issue reported: projector broken".to_string()),
+            footer_icon: None,
+            banner: Some("Ends in 5 min - next: Design Review 11:00".to_string()),
+            status_indicator: Some("BUSY until 11:00".to_string()),
+            header: Some(Header {
+                title: "Matterhorn".to_string(),
+                details: Some("Floor 3 | 8 seats".to_string()),
+                badges: vec!["TV".to_string(), "VC".to_string()],
+            }),
+            footer_text: Some("Facilities: ext. 1234".to_string()),
+            agenda: None,
+            further_days: Vec::new(),
+            layout: Layout::Agenda,
+            current: None,
+            status_bar: None,
+            background: Background {
+                hatch: true,
+                watermark_path: None,
+            },
+            color_depth: ColorDepth::Grayscale,
+            dither: Dither::None,
+            format: ImageFormat::Bmp,
         };
 
         let result = generate_bmp(&config);
@@ -192,4 +1518,540 @@ mod tests {
         let bmp_data = result.unwrap();
         assert!(!bmp_data.is_empty(), "Generated BMP data is empty");
     }
+
+    #[test]
+    fn test_header_badges() {
+        let config = |badges: &[&str]| ImageConfig {
+            header: Some(Header {
+                title: "Matterhorn".to_string(),
+                details: Some("Floor 3".to_string()),
+                badges: badges.iter().map(|badge| badge.to_string()).collect(),
+            }),
+            ..ImageConfig::default()
+        };
+        let plain = generate_bmp(&config(&[])).unwrap();
+        let badged = generate_bmp(&config(&["TV", "VC"])).unwrap();
+        assert_ne!(plain, badged);
+    }
+
+    #[test]
+    fn test_generate_monochrome_bmp() {
+        let config = ImageConfig {
+            color_depth: ColorDepth::Monochrome,
+            ..ImageConfig::default()
+        };
+        let bmp = generate_bmp(&config).unwrap();
+        // The size of the images served by the TRMNL cloud
+        assert_eq!(bmp.len(), 48062);
+        assert_eq!(&bmp[28..30], &1u16.to_le_bytes());
+        assert_ne!(config.fingerprint(), ImageConfig::default().fingerprint());
+
+        let mono = image::load_from_memory(&bmp).unwrap().to_luma8();
+        let gray = image::load_from_memory(&generate_bmp(&ImageConfig::default()).unwrap())
+            .unwrap()
+            .to_luma8();
+        assert_eq!(mono.dimensions(), (800, 480));
+        for (mono, gray) in mono.pixels().zip(gray.pixels()) {
+            let expected = if gray.0[0] >= 128 { 255 } else { 0 };
+            assert_eq!(mono.0[0], expected);
+        }
+    }
+
+    #[test]
+    fn test_generate_png() {
+        let config = ImageConfig {
+            color_depth: ColorDepth::Monochrome,
+            format: ImageFormat::Png,
+            ..ImageConfig::default()
+        };
+        let png = generate_bmp(&config).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+        assert!(png.len() < 48062 / 4, "PNG is {} bytes", png.len());
+        assert_ne!(
+            config.fingerprint(),
+            ImageConfig {
+                format: ImageFormat::Bmp,
+                ..config
+            }
+            .fingerprint()
+        );
+
+        // Same pixels as the monochrome BMP
+        let bmp = generate_bmp(&ImageConfig {
+            color_depth: ColorDepth::Monochrome,
+            ..ImageConfig::default()
+        })
+        .unwrap();
+        assert_eq!(
+            image::load_from_memory(&png).unwrap().to_luma8(),
+            image::load_from_memory(&bmp).unwrap().to_luma8()
+        );
+    }
+
+    #[test]
+    fn test_negotiate_image_format() {
+        let negotiate = |device_setting, accept| {
+            ImageFormat::negotiate(ImageFormat::Bmp, device_setting, accept)
+        };
+        assert_eq!(negotiate(None, None), ImageFormat::Bmp);
+        assert_eq!(negotiate(None, Some("*/*")), ImageFormat::Bmp);
+        assert_eq!(
+            negotiate(None, Some("application/json, image/png;q=0.9, image/bmp")),
+            ImageFormat::Png
+        );
+        assert_eq!(
+            negotiate(None, Some("image/png;q=0, image/bmp")),
+            ImageFormat::Bmp
+        );
+        assert_eq!(
+            negotiate(Some(ImageFormat::Bmp), Some("image/png")),
+            ImageFormat::Bmp
+        );
+        assert_eq!(
+            ImageFormat::negotiate(ImageFormat::Png, None, Some("image/bmp")),
+            ImageFormat::Bmp
+        );
+        assert_eq!(
+            ImageFormat::of_file_name("0123abcd.png"),
+            Some(ImageFormat::Png)
+        );
+        assert!("gif".parse::<ImageFormat>().is_err());
+    }
+
+    #[test]
+    fn test_dither_gradient() {
+        let gradient = || GrayImage::from_fn(64, 16, |x, _| Luma([(x * 4) as u8]));
+        let black_share = |img: &GrayImage, from: u32, to: u32| {
+            let black = img
+                .enumerate_pixels()
+                .filter(|(x, _, pixel)| (from..to).contains(x) && pixel.0[0] == 0)
+                .count();
+            black as f64 / ((to - from) * img.height()) as f64
+        };
+
+        let mut thresholded = gradient();
+        dither(&mut thresholded, Dither::None);
+        assert_eq!(thresholded, gradient());
+
+        for method in [Dither::FloydSteinberg, Dither::Ordered] {
+            let mut img = gradient();
+            dither(&mut img, method);
+            assert!(
+                img.pixels()
+                    .all(|pixel| pixel.0[0] == 0 || pixel.0[0] == 255)
+            );
+            // The share of black pixels follows the shade, rather than
+            // jumping from all to none at mid-gray
+            let dark = black_share(&img, 0, 16);
+            let mid = black_share(&img, 24, 40);
+            let light = black_share(&img, 48, 64);
+            assert!(dark > 0.75, "{:?}: {}", method, dark);
+            assert!((0.3..0.7).contains(&mid), "{:?}: {}", method, mid);
+            assert!(light < 0.25, "{:?}: {}", method, light);
+        }
+
+        let ordered = ImageConfig {
+            dither: Dither::Ordered,
+            ..ImageConfig::default()
+        };
+        assert_ne!(ordered.fingerprint(), ImageConfig::default().fingerprint());
+        assert_eq!(
+            "floyd-steinberg".parse::<Dither>().unwrap(),
+            Dither::FloydSteinberg
+        );
+        assert!("random".parse::<Dither>().is_err());
+    }
+
+    #[test]
+    fn test_encode_monochrome_row_padding() {
+        // 10 pixels need 2 bytes per row, padded to 4
+        let mut img = GrayImage::from_pixel(10, 2, Luma([255]));
+        img.put_pixel(0, 0, Luma([0]));
+        img.put_pixel(9, 1, Luma([100]));
+        let bmp = encode_monochrome(&img);
+        assert_eq!(bmp.len(), MONOCHROME_HEADER_SIZE as usize + 2 * 4);
+        // Bottom row first
+        let pixels = &bmp[MONOCHROME_HEADER_SIZE as usize..];
+        assert_eq!(pixels, [0xff, 0x80, 0, 0, 0x7f, 0xc0, 0, 0]);
+    }
+
+    #[test]
+    fn test_renderer_caches_fonts() {
+        let renderer = Renderer::new();
+        let config = ImageConfig::default();
+        assert_eq!(
+            renderer.render(&config).unwrap(),
+            generate_bmp(&config).unwrap()
+        );
+        renderer.render(&config).unwrap();
+        assert_eq!(renderer.fonts.lock().unwrap().len(), 1);
+
+        let missing = ImageConfig {
+            font_path: "no-such-font.ttf".to_string(),
+            ..ImageConfig::default()
+        };
+        assert!(renderer.render(&missing).is_err());
+        assert_eq!(renderer.fonts.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_generate_bmp_with_agenda() {
+        let config = ImageConfig {
+            agenda: Some(AgendaSection {
+                heading: "Tomorrow".to_string(),
+                items: vec![
+                    AgendaItem::new("09:00 Planning"),
+                    AgendaItem::new("11:00 Review"),
+                ],
+                status: Some("FREE".to_string()),
+            }),
+            ..ImageConfig::default()
+        };
+        let with_agenda = generate_bmp(&config).unwrap();
+        let without_agenda = generate_bmp(&ImageConfig::default()).unwrap();
+        assert_eq!(with_agenda.len(), without_agenda.len());
+        assert_ne!(with_agenda, without_agenda);
+
+        // Tags are drawn
+        let mut config = config;
+        config.agenda.as_mut().unwrap().items[0].tag = Some("FREE".to_string());
+        let tagged = generate_bmp(&config).unwrap();
+        assert_ne!(tagged, with_agenda);
+
+        // Each style looks different
+        config.agenda.as_mut().unwrap().items[1].style = ItemStyle::Bold;
+        let bold = generate_bmp(&config).unwrap();
+        assert_ne!(bold, tagged);
+        config.agenda.as_mut().unwrap().items[1].style = ItemStyle::Hatched;
+        let hatched = generate_bmp(&config).unwrap();
+        assert_ne!(hatched, tagged);
+        assert_ne!(hatched, bold);
+    }
+
+    #[test]
+    fn test_generate_bmp_with_focus_layout() {
+        let agenda = || AgendaSection {
+            heading: "Today".to_string(),
+            items: vec![AgendaItem::new("11:00 Review")],
+            status: None,
+        };
+        let config = ImageConfig {
+            agenda: Some(agenda()),
+            layout: Layout::Focus,
+            current: Some(CurrentMeeting {
+                status: "BUSY until 11:00".to_string(),
+                title: Some("Quarterly planning with the whole team".to_string()),
+            }),
+            status_bar: Some(StatusBar {
+                clock: "10:15".to_string(),
+                battery_percent: Some(83),
+            }),
+            ..ImageConfig::default()
+        };
+        let focus = generate_bmp(&config).unwrap();
+        let agenda_only = generate_bmp(&ImageConfig {
+            agenda: Some(agenda()),
+            ..ImageConfig::default()
+        })
+        .unwrap();
+        assert_eq!(focus.len(), agenda_only.len());
+        assert_ne!(focus, agenda_only);
+
+        // The agenda layout has no block for the current meeting
+        let ignored = generate_bmp(&ImageConfig {
+            layout: Layout::Agenda,
+            status_bar: None,
+            ..config
+        })
+        .unwrap();
+        assert_eq!(ignored, agenda_only);
+    }
+
+    #[test]
+    fn test_generate_bmp_with_status_indicator() {
+        let agenda = || AgendaSection {
+            heading: "Today".to_string(),
+            items: vec![AgendaItem::new("10:00 Standup")],
+            status: None,
+        };
+        let plain = generate_bmp(&ImageConfig {
+            agenda: Some(agenda()),
+            ..ImageConfig::default()
+        })
+        .unwrap();
+        let config = ImageConfig {
+            agenda: Some(agenda()),
+            status_indicator: Some("FREE for 45 min".to_string()),
+            ..ImageConfig::default()
+        };
+        let indicator = generate_bmp(&config).unwrap();
+        assert_ne!(indicator, plain);
+        assert_ne!(
+            config.fingerprint(),
+            ImageConfig {
+                agenda: Some(agenda()),
+                status_indicator: Some("FREE for 44 min".to_string()),
+                ..ImageConfig::default()
+            }
+            .fingerprint()
+        );
+    }
+
+    #[test]
+    fn test_footer_icon() {
+        let config = ImageConfig {
+            footer: Some("Replace battery soon".to_string()),
+            ..ImageConfig::default()
+        };
+        let plain = generate_bmp(&config).unwrap();
+        let config = ImageConfig {
+            footer_icon: Some(Icon::BatteryLow),
+            ..config
+        };
+        assert_ne!(generate_bmp(&config).unwrap(), plain);
+        assert_ne!(
+            config.fingerprint(),
+            ImageConfig {
+                footer: Some("Replace battery soon".to_string()),
+                ..ImageConfig::default()
+            }
+            .fingerprint()
+        );
+
+        // Outline, terminal and a single bar, in white
+        let mut img = GrayImage::from_pixel(40, 40, Luma([0]));
+        draw_icon(&mut img, Icon::BatteryLow, Rect::at(0, 0).of_size(40, 40));
+        assert_eq!(img.get_pixel(0, 20)[0], 255);
+        assert_eq!(img.get_pixel(8, 20)[0], 255);
+        assert_eq!(img.get_pixel(20, 20)[0], 0);
+        assert_eq!(img.get_pixel(39, 20)[0], 255);
+        assert_eq!(img.get_pixel(20, 5)[0], 0);
+    }
+
+    #[test]
+    fn test_generate_bmp_with_further_days() {
+        let section = |heading: &str, item: &str| AgendaSection {
+            heading: heading.to_string(),
+            items: vec![AgendaItem::new(item)],
+            status: None,
+        };
+        let config = ImageConfig {
+            agenda: Some(section("Today", "16:00 Retro")),
+            layout: Layout::Days,
+            ..ImageConfig::default()
+        };
+        let today_only = generate_bmp(&config).unwrap();
+        let config = ImageConfig {
+            further_days: vec![section("Wednesday", "09:00 Planning")],
+            ..config
+        };
+        assert_ne!(generate_bmp(&config).unwrap(), today_only);
+
+        // Days that do not fit are left out
+        let config = ImageConfig {
+            further_days: (0..10)
+                .map(|_| section("Wednesday", "09:00 Planning"))
+                .collect(),
+            ..config
+        };
+        assert!(generate_bmp(&config).is_ok());
+    }
+
+    #[test]
+    fn test_bidi_text() {
+        assert!(matches!(visual_order("09:00 Planning"), Cow::Borrowed(_)));
+        assert_eq!(visual_order("שלום"), "םולש");
+        // Numbers keep their order within right-to-left text
+        assert_eq!(visual_order("חדר 12"), "12 רדח");
+        assert_eq!(visual_order("Sync עם צוות"), "Sync תווצ םע");
+        assert_eq!(visual_order("09:00 ישיבה"), "הבישי 09:00");
+
+        assert!(is_rtl("09:00 ישיבה"));
+        assert!(is_rtl("اجتماع"));
+        assert!(!is_rtl("Sync עם צוות"));
+        assert!(!is_rtl("09:00"));
+
+        // Right-to-left items are aligned to the right
+        let agenda = |text: &str| ImageConfig {
+            agenda: Some(AgendaSection {
+                heading: "Tomorrow".to_string(),
+                items: vec![AgendaItem::new(text)],
+                status: None,
+            }),
+            ..ImageConfig::default()
+        };
+        let dark_pixels_on_right = |text: &str| {
+            let bmp = generate_bmp(&agenda(text)).unwrap();
+            let img = image::load_from_memory(&bmp).unwrap().to_luma8();
+            img.enumerate_pixels()
+                .filter(|(x, _, pixel)| *x > img.width() / 2 && pixel.0[0] < 128)
+                .count()
+        };
+        assert_eq!(dark_pixels_on_right("09:00 Planning"), 0);
+        assert!(dark_pixels_on_right("09:00 ישיבה") > 0);
+    }
+
+    #[test]
+    fn test_status_badge_svg() {
+        let svg = status_badge_svg(&StatusBadge {
+            label: "R&D <1>".to_string(),
+            status: "FREE".to_string(),
+            color: "#4c1",
+        });
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(">R&amp;D &lt;1&gt;</text>"));
+        assert!(svg.contains(r##"fill="#4c1""##));
+        // 7 + 4 characters of 7px, each part with 2 * 6px padding
+        assert!(svg.contains(r#"width="101""#));
+    }
+
+    #[test]
+    fn test_dashed_rect() {
+        let mut img = GrayImage::from_pixel(40, 20, Luma([255]));
+        draw_dashed_rect(&mut img, Rect::at(0, 0).of_size(16, 8));
+        let dots = img.pixels().filter(|p| p[0] == 0).count();
+        // Half of each side is dashed, the top left corner is shared
+        assert_eq!(dots, 2 * 8 + 2 * 4 - 1);
+        assert_eq!(img.get_pixel(0, 0)[0], 0);
+        assert_eq!(img.get_pixel(5, 0)[0], 255);
+    }
+
+    #[test]
+    fn test_watermark() {
+        let logo_path = std::env::temp_dir().join("trmnl-watermark-test.png");
+        GrayImage::from_fn(40, 20, |x, _| Luma([if x < 20 { 0 } else { 255 }]))
+            .save(&logo_path)
+            .unwrap();
+
+        let mut img = GrayImage::from_pixel(800, 480, Luma([255]));
+        draw_watermark(&mut img, logo_path.to_str().unwrap()).unwrap();
+        let dots = img.pixels().filter(|p| p[0] == 0).count();
+        // The black half of the logo covers 200x200 pixels, a quarter of them dotted
+        assert!((8_000..12_000).contains(&dots), "{}", dots);
+
+        // Missing watermarks are skipped
+        let config = ImageConfig {
+            background: Background {
+                hatch: false,
+                watermark_path: Some("does-not-exist.png".to_string()),
+            },
+            ..ImageConfig::default()
+        };
+        assert!(generate_bmp(&config).is_ok());
+
+        let _ = std::fs::remove_file(logo_path);
+    }
+
+    #[test]
+    fn test_wrap_text() {
+        let mut font_data = Vec::new();
+        File::open("assets/fonts/BlockKie.ttf")
+            .unwrap()
+            .read_to_end(&mut font_data)
+            .unwrap();
+        let font = Font::try_from_bytes(&font_data).unwrap();
+        let scale = Scale::uniform(50.0);
+
+        let lines = wrap_text(&font, scale, "Evacuate - assemble at parking lot B", 400.0);
+        assert!(lines.len() > 1);
+        assert!(
+            lines
+                .iter()
+                .all(|line| text_width(&font, scale, line) <= 400.0)
+        );
+        assert_eq!(lines.join(" "), "Evacuate - assemble at parking lot B");
+
+        assert_eq!(
+            wrap_text(&font, scale, "hello world", 400.0),
+            ["hello world"]
+        );
+        assert_eq!(wrap_text(&font, scale, "", 400.0), [""]);
+    }
+
+    #[test]
+    fn test_sanitize_text() {
+        assert!(matches!(sanitize_text("09:00 Planning"), Cow::Borrowed(_)));
+        assert_eq!(sanitize_text("a\tb\0c\r\nd\u{1b}[31m"), "a bc\nd[31m");
+        assert_eq!(sanitize_text(&"x".repeat(10_000)).len(), MAX_TEXT_CHARS);
+
+        // Invisible characters are dropped after reordering
+        assert_eq!(display_text("👩\u{200D}💻 Sync\u{FE0F}"), "👩💻 Sync");
+        assert_eq!(display_text("\u{202E}abc\u{202C}"), "cba");
+        assert_eq!(display_text("09:00 \u{200F}ישיבה"), "הבישי 09:00");
+    }
+
+    static RENDERER: LazyLock<Renderer> = LazyLock::new(Renderer::new);
+
+    /// Text as it may come from an untrusted calendar
+    fn untrusted_text() -> impl Strategy<Value = String> {
+        prop_oneof![
+            any::<String>(),
+            // Invalid UTF-8 is replaced when the calendar is decoded
+            proptest::collection::vec(any::<u8>(), 0..64)
+                .prop_map(|bytes| String::from_utf8_lossy(&bytes).into_owned()),
+            "[a-zA-Z0-9 \\n\\t\\r\\x00\\x1b\u{200B}-\u{200F}\u{202A}-\u{202E}\u{FEFF}\u{0300}א-תء-ي👩💻]{0,64}",
+            ("[a-zA-Zא-ת ]{1,8}", 1usize..2_000).prop_map(|(text, n)| text.repeat(n)),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_render_untrusted_text(
+            text in untrusted_text(),
+            title in untrusted_text(),
+            banner in proptest::option::of(untrusted_text()),
+            footer in proptest::option::of(untrusted_text()),
+            items in proptest::collection::vec(
+                (untrusted_text(), proptest::option::of(untrusted_text())),
+                0..4,
+            ),
+            agenda in any::<bool>(),
+            focus in any::<bool>(),
+        ) {
+            let config = ImageConfig {
+                width: 200,
+                height: 120,
+                font_size: 12.0,
+                border_padding: 4,
+                text: text.clone(),
+                banner,
+                header: Some(Header {
+                    title,
+                    details: Some(text.clone()),
+                    badges: Vec::new(),
+                }),
+                footer_icon: footer.as_ref().map(|_| Icon::BatteryLow),
+                footer: footer.clone(),
+                footer_text: footer,
+                agenda: agenda.then(|| AgendaSection {
+                    heading: text.clone(),
+                    items: items
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, (text, tag))| AgendaItem {
+                            text,
+                            tag,
+                            style: [ItemStyle::Regular, ItemStyle::Bold, ItemStyle::Hatched][i % 3],
+                        })
+                        .collect(),
+                    status: Some(text.clone()),
+                }),
+                layout: if focus { Layout::Focus } else { Layout::Agenda },
+                current: Some(CurrentMeeting {
+                    status: text.clone(),
+                    title: Some(text.clone()),
+                }),
+                status_bar: Some(StatusBar {
+                    clock: text,
+                    battery_percent: Some(100),
+                }),
+                ..ImageConfig::default()
+            };
+            let bmp = RENDERER.render(&config).unwrap();
+            let img = image::load_from_memory(&bmp).unwrap();
+            prop_assert_eq!((img.width(), img.height()), (200, 120));
+        }
+    }
 }
@@ -0,0 +1,333 @@
+//! Minimal CalDAV client for room calendars, e.g. on Nextcloud or Radicale
+//!
+//! Only the `calendar-query` REPORT (RFC 4791, section 7.8) is supported: the
+//! server returns the events of a time range, with recurring events expanded
+//! to their occurrences. Rooms can thus use private calendars rather than
+//! publicly exported iCalendar links.
+
+use chrono::{DateTime, Utc};
+use reqwest::{Method, StatusCode};
+
+use crate::calendar::{CalendarError, EventWindow};
+
+/// Format of the time range boundaries, always in UTC
+const TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Fetches the events of a time window from a CalDAV calendar collection
+///
+/// Returns the events as iCalendar data of a single calendar, like a
+/// calendar export would be.
+pub async fn fetch_caldav_data(
+    url: &str,
+    username: Option<&str>,
+    password: Option<&str>,
+    window: EventWindow,
+) -> Result<String, CalendarError> {
+    let method = Method::from_bytes(b"REPORT").expect("REPORT is a valid method");
+    let mut request = reqwest::Client::new()
+        .request(method, url)
+        .header("Depth", "1")
+        .header("Content-Type", "application/xml; charset=utf-8")
+        .body(calendar_query(window));
+    if let Some(username) = username {
+        request = request.basic_auth(username, password);
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| CalendarError::FetchError(e.to_string()))?;
+
+    if response.status() != StatusCode::MULTI_STATUS {
+        return Err(CalendarError::FetchError(format!(
+            "HTTP error: {}",
+            response.status()
+        )));
+    }
+
+    let body = response
+        .text()
+        .await
+        .map_err(|e| CalendarError::FetchError(e.to_string()))?;
+    Ok(merge_calendars(&calendar_data_elements(&body)))
+}
+
+/// Body of a REPORT for the events overlapping the window
+fn calendar_query(window: EventWindow) -> String {
+    let time = |t: DateTime<_>| t.with_timezone(&Utc).format(TIME_FORMAT).to_string();
+    let (start, end) = (time(window.start), time(window.end));
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<C:calendar-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:caldav">
+  <D:prop>
+    <C:calendar-data>
+      <C:expand start="{start}" end="{end}"/>
+    </C:calendar-data>
+  </D:prop>
+  <C:filter>
+    <C:comp-filter name="VCALENDAR">
+      <C:comp-filter name="VEVENT">
+        <C:time-range start="{start}" end="{end}"/>
+      </C:comp-filter>
+    </C:comp-filter>
+  </C:filter>
+</C:calendar-query>
+"#
+    )
+}
+
+/// Contents of the `calendar-data` elements of a multistatus response
+///
+/// Elements are matched by local name, whatever namespace prefix the server
+/// uses.
+fn calendar_data_elements(xml: &str) -> Vec<String> {
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[..end];
+        rest = &rest[end + 1..];
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        if name.rsplit(':').next() != Some("calendar-data") || tag.ends_with('/') {
+            continue;
+        }
+        let close = format!("</{}>", name);
+        let Some(content_end) = rest.find(&close) else {
+            break;
+        };
+        elements.push(xml_text(&rest[..content_end]));
+        rest = &rest[content_end + close.len()..];
+    }
+    elements
+}
+
+/// Text content of an element, with entities decoded and CDATA sections
+/// taken as they are
+fn xml_text(content: &str) -> String {
+    let mut text = String::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("<![CDATA[") {
+        text.push_str(&decode_entities(&rest[..start]));
+        rest = &rest[start + "<![CDATA[".len()..];
+        let end = rest.find("]]>").unwrap_or(rest.len());
+        text.push_str(&rest[..end]);
+        rest = rest.get(end + "]]>".len()..).unwrap_or_default();
+    }
+    text.push_str(&decode_entities(rest));
+    text
+}
+
+/// Decode the predefined XML entities and character references
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match c {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            // Not an entity, keep the ampersand
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Merge the calendars of several resources into one
+///
+/// Only the components of the calendars (events and time zones) are kept,
+/// the calendar properties would be repeated otherwise.
+fn merge_calendars(calendars: &[String]) -> String {
+    let mut merged = String::from(
+        "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//trmnl-meeting-room-display//CalDAV//EN\r\n",
+    );
+    for calendar in calendars {
+        let mut depth = 0;
+        for line in calendar.lines() {
+            let is_begin = line.len() >= 6 && line[..6].eq_ignore_ascii_case("BEGIN:");
+            let is_end = line.len() >= 4 && line[..4].eq_ignore_ascii_case("END:");
+            if is_begin {
+                depth += 1;
+            }
+            if depth >= 2 {
+                merged.push_str(line);
+                merged.push_str("\r\n");
+            }
+            if is_end {
+                depth -= 1;
+            }
+        }
+    }
+    merged.push_str("END:VCALENDAR\r\n");
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        body::Bytes,
+        http::{HeaderMap, Method, StatusCode},
+        routing::any,
+    };
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::calendar::parse_calendar_window;
+
+    /// Response of a server with one single and one recurring event
+    const MULTISTATUS: &str = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:" xmlns:cal="urn:ietf:params:xml:ns:caldav">
+  <d:response>
+    <d:href>/calendars/rooms/matterhorn/standup.ics</d:href>
+    <d:propstat><d:prop><cal:calendar-data>BEGIN:VCALENDAR
+VERSION:2.0
+PRODID:-//Nextcloud
+BEGIN:VEVENT
+UID:standup
+DTSTART:20240304T090000Z
+DTEND:20240304T091500Z
+SUMMARY:Standup &amp; planning
+END:VEVENT
+BEGIN:VEVENT
+UID:standup
+RECURRENCE-ID:20240305T090000Z
+DTSTART:20240305T090000Z
+DTEND:20240305T091500Z
+SUMMARY:Standup &amp; planning
+END:VEVENT
+END:VCALENDAR
+</cal:calendar-data></d:prop></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/calendars/rooms/matterhorn/review.ics</d:href>
+    <d:propstat><d:prop><cal:calendar-data><![CDATA[BEGIN:VCALENDAR
+VERSION:2.0
+BEGIN:VEVENT
+UID:review
+DTSTART:20240304T130000Z
+DTEND:20240304T140000Z
+SUMMARY:Design <Review>
+END:VEVENT
+END:VCALENDAR
+]]></cal:calendar-data></d:prop></d:propstat>
+  </d:response>
+</d:multistatus>
+"#;
+
+    fn window() -> EventWindow {
+        EventWindow {
+            start: Utc.with_ymd_and_hms(2024, 3, 4, 0, 0, 0).unwrap().into(),
+            end: Utc.with_ymd_and_hms(2024, 3, 6, 0, 0, 0).unwrap().into(),
+        }
+    }
+
+    #[test]
+    fn test_calendar_data_elements() {
+        let elements = calendar_data_elements(MULTISTATUS);
+        assert_eq!(elements.len(), 2);
+        assert!(elements[0].contains("SUMMARY:Standup & planning"));
+        assert!(elements[1].contains("SUMMARY:Design <Review>"));
+
+        assert_eq!(
+            decode_entities("a &lt;b&gt; &#233;&#x21; & c"),
+            "a <b> é! & c"
+        );
+    }
+
+    #[test]
+    fn test_merge_calendars() {
+        let merged = merge_calendars(&calendar_data_elements(MULTISTATUS));
+        assert_eq!(merged.matches("BEGIN:VCALENDAR").count(), 1);
+        assert!(!merged.contains("Nextcloud"));
+
+        let parsed = parse_calendar_window(&merged, window()).unwrap();
+        let names: Vec<&str> = parsed.events.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "Standup & planning",
+                "Design <Review>",
+                "Standup & planning"
+            ]
+        );
+
+        // Windows without events are an empty calendar, not an error
+        let empty = merge_calendars(&[]);
+        assert!(
+            parse_calendar_window(&empty, window())
+                .unwrap()
+                .events
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_caldav_data() {
+        let router = Router::new().route(
+            "/calendars/rooms/matterhorn/",
+            any(
+                |method: Method, headers: HeaderMap, body: Bytes| async move {
+                    let body = String::from_utf8_lossy(&body);
+                    // "displays:secret"
+                    let authorized = headers.get("authorization").and_then(|v| v.to_str().ok())
+                        == Some("Basic ZGlzcGxheXM6c2VjcmV0");
+                    if method.as_str() != "REPORT" || !authorized {
+                        return (StatusCode::UNAUTHORIZED, String::new());
+                    }
+                    assert_eq!(headers.get("depth").unwrap(), "1");
+                    assert!(body.contains(r#"start="20240304T000000Z" end="20240306T000000Z""#));
+                    (StatusCode::MULTI_STATUS, MULTISTATUS.to_string())
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://{}/calendars/rooms/matterhorn/",
+            listener.local_addr().unwrap()
+        );
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let data = fetch_caldav_data(&url, Some("displays"), Some("secret"), window())
+            .await
+            .unwrap();
+        assert_eq!(
+            parse_calendar_window(&data, window()).unwrap().events.len(),
+            3
+        );
+
+        let error = fetch_caldav_data(&url, Some("displays"), Some("wrong"), window())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, CalendarError::FetchError(_)));
+    }
+}
@@ -1,12 +1,31 @@
-use std::fmt;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Arc, LazyLock, Mutex},
+    time::Instant,
+};
 
 use anyhow::Result;
-use chrono::{DateTime, Local, TimeZone, Utc};
-use icalendar::parser::unfold;
-use log::debug;
+use chrono::{
+    DateTime, Days, Duration, Local, LocalResult, NaiveDateTime, NaiveTime, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use icalendar::parser::{Component, unfold};
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::caldav::fetch_caldav_data;
+use crate::database::Database;
+use crate::description::normalize_description;
+use crate::error_code::ErrorCode;
+use crate::event_changes::{NextEventChange, next_event};
+use crate::google::fetch_google_data;
+use crate::metrics::Metrics;
+use crate::microsoft::{AppCredentials, fetch_microsoft_data};
+use crate::notify::Notifier;
+use crate::rooms::Room;
+
 #[derive(Debug, Error)]
 pub enum CalendarError {
     #[error("Failed to fetch calendar: {0}")]
@@ -19,11 +38,177 @@ pub enum CalendarError {
     NoEventsError,
 }
 
+impl CalendarError {
+    /// Short code of the error, shown on displays
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            CalendarError::FetchError(_) => ErrorCode::CalendarFetch,
+            CalendarError::ParseError(_) => ErrorCode::CalendarParse,
+            CalendarError::NoEventsError => ErrorCode::CalendarEmpty,
+        }
+    }
+}
+
+/// Prefix of the environment variables calendar sources may read secrets from
+///
+/// Room configurations can be changed through the admin API, so a source
+/// naming any variable could send e.g. `ACCESS_TOKEN` to a server of the
+/// admin's choice.
+pub const SECRET_ENV_PREFIX: &str = "TRMNL_CALENDAR_SECRET_";
+
+/// Checks that an environment variable may hold a calendar secret
+fn check_secret_env(name: &str) -> Result<(), String> {
+    if name.starts_with(SECRET_ENV_PREFIX) && name.len() > SECRET_ENV_PREFIX.len() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Environment variable {} is not a calendar secret, its name must start with {}",
+            name, SECRET_ENV_PREFIX
+        ))
+    }
+}
+
+/// Reads a calendar secret, e.g. `the CalDAV password`, from the environment
+fn secret_from_env(name: &str, what: &str) -> Result<String, CalendarError> {
+    check_secret_env(name).map_err(CalendarError::FetchError)?;
+    std::env::var(name).map_err(|_| {
+        CalendarError::FetchError(format!(
+            "Environment variable {} with {} is not set",
+            name, what
+        ))
+    })
+}
+
+/// Protocol used to fetch a room's calendar from its URL
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CalendarSource {
+    /// iCalendar feed, e.g. an exported or published calendar
+    #[default]
+    Ical,
+    /// CalDAV calendar collection, queried for the events of the look-ahead
+    /// window
+    #[serde(rename = "caldav")]
+    CalDav {
+        /// User name for HTTP basic authentication
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        username: Option<String>,
+        /// Name of the environment variable holding the password, so that it
+        /// is neither stored in the database nor part of room exports; must
+        /// start with [`SECRET_ENV_PREFIX`]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password_env: Option<String>,
+    },
+    /// Google calendar, read through the Calendar API with a service account;
+    /// the URL is the calendar ID, e.g. `c_123@resource.calendar.google.com`
+    Google {
+        /// Path to the JSON key of the service account
+        service_account_key: String,
+        /// User the service account acts as with domain-wide delegation, if
+        /// the calendar is not shared with the service account itself
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        delegate: Option<String>,
+    },
+    /// Room mailbox in Microsoft 365, read through Microsoft Graph with the
+    /// client credentials of an app registration; the URL is the e-mail
+    /// address of the mailbox, e.g. `matterhorn@contoso.com`
+    Microsoft {
+        /// Directory (tenant) ID or domain of the app registration
+        tenant_id: String,
+        /// Application (client) ID of the app registration
+        client_id: String,
+        /// Name of the environment variable holding the client secret; must
+        /// start with [`SECRET_ENV_PREFIX`]
+        client_secret_env: String,
+    },
+}
+
+/// Calendar a room falls back to while its own calendar cannot be fetched,
+/// e.g. the published iCalendar feed of a room mailbox read through Microsoft
+/// Graph
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CalendarFallback {
+    /// URL of the fallback calendar, as `calendar_url` of a room
+    pub url: String,
+    /// How the fallback calendar is fetched from its URL
+    #[serde(default)]
+    pub source: CalendarSource,
+}
+
+impl CalendarSource {
+    /// Checks that the source only reads secrets from environment variables
+    /// with [`SECRET_ENV_PREFIX`]
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            CalendarSource::CalDav {
+                password_env: Some(name),
+                ..
+            } => check_secret_env(name),
+            CalendarSource::Microsoft {
+                client_secret_env, ..
+            } => check_secret_env(client_secret_env),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fetches raw iCalendar data of the calendar at the given URL
+    pub async fn fetch(&self, url: &str) -> Result<String, CalendarError> {
+        self.fetch_window(url, EventWindow::lookahead(Local::now()))
+            .await
+    }
+
+    /// Fetches raw iCalendar data of the calendar at the given URL, with at
+    /// least the events overlapping the window
+    ///
+    /// Sources queried for a time range (CalDAV, Google, Microsoft) return the
+    /// events of the window only, iCalendar feeds are returned in full.
+    pub async fn fetch_window(
+        &self,
+        url: &str,
+        window: EventWindow,
+    ) -> Result<String, CalendarError> {
+        match self {
+            CalendarSource::Ical => fetch_calendar_data(url).await,
+            CalendarSource::CalDav {
+                username,
+                password_env,
+            } => {
+                let password = password_env
+                    .as_ref()
+                    .map(|name| secret_from_env(name, "the CalDAV password"))
+                    .transpose()?;
+                fetch_caldav_data(url, username.as_deref(), password.as_deref(), window).await
+            }
+            CalendarSource::Google {
+                service_account_key,
+                delegate,
+            } => fetch_google_data(url, service_account_key, delegate.as_deref(), window).await,
+            CalendarSource::Microsoft {
+                tenant_id,
+                client_id,
+                client_secret_env,
+            } => {
+                let client_secret = secret_from_env(client_secret_env, "the client secret")?;
+                let credentials = AppCredentials {
+                    tenant_id: tenant_id.clone(),
+                    client_id: client_id.clone(),
+                    client_secret,
+                };
+                fetch_microsoft_data(url, &credentials, window).await
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CalendarEvent {
     /// Name/title of the event
     pub name: String,
 
+    /// Unique identifier of the event (`UID`), shared by all instances of a recurring event
+    #[serde(default)]
+    pub uid: Option<String>,
+
     /// Event start time (in local time)
     pub start_time: DateTime<Local>,
 
@@ -38,6 +223,76 @@ pub struct CalendarEvent {
 
     /// Optional description of the event
     pub description: Option<String>,
+
+    /// Categories of the event (`CATEGORIES`), e.g. `Maintenance`
+    #[serde(default)]
+    pub categories: Vec<String>,
+
+    /// Color of the event (`COLOR`, a CSS color name), e.g. `red`
+    #[serde(default)]
+    pub color: Option<String>,
+
+    /// Whether the event is marked private or confidential (`CLASS`), so
+    /// that its title must not be published
+    #[serde(default)]
+    pub private: bool,
+}
+
+/// How strictly duplicate events are detected when calendars are merged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Deduplication {
+    /// Keep all events
+    Off,
+    /// Events with the same UID and start time are duplicates
+    #[default]
+    Uid,
+    /// Additionally, events with the same title (ignoring case and whitespace)
+    /// and the same start and end time are duplicates
+    Fuzzy,
+}
+
+impl Deduplication {
+    /// Returns true if the two events are the same meeting
+    pub fn is_duplicate(&self, a: &CalendarEvent, b: &CalendarEvent) -> bool {
+        let same_uid = a.uid.is_some() && a.uid == b.uid && a.start_time == b.start_time;
+        let same_title_and_time = || {
+            a.start_time == b.start_time
+                && a.end_time == b.end_time
+                && normalize_title(&a.name) == normalize_title(&b.name)
+        };
+        match self {
+            Deduplication::Off => false,
+            Deduplication::Uid => same_uid,
+            Deduplication::Fuzzy => same_uid || same_title_and_time(),
+        }
+    }
+}
+
+fn normalize_title(title: &str) -> String {
+    title
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Remove duplicate events, keeping the first of each
+///
+/// Events from several sources (e.g. a published ICS feed aggregating the
+/// organizers' calendars) would otherwise double-book the room. Keeps the
+/// order of the remaining events.
+pub fn deduplicate(events: Vec<CalendarEvent>, mode: Deduplication) -> Vec<CalendarEvent> {
+    if mode == Deduplication::Off {
+        return events;
+    }
+    let mut unique: Vec<CalendarEvent> = Vec::with_capacity(events.len());
+    for event in events {
+        if !unique.iter().any(|kept| mode.is_duplicate(kept, &event)) {
+            unique.push(event);
+        }
+    }
+    unique
 }
 
 impl fmt::Display for CalendarEvent {
@@ -60,14 +315,45 @@ impl CalendarEvent {
 
         Self {
             name,
+            uid: None,
             start_time,
             end_time,
             duration_minutes,
             location,
             description,
+            categories: Vec::new(),
+            color: None,
+            private: false,
         }
     }
 
+    /// Sets the unique identifier of the event
+    pub fn with_uid(mut self, uid: Option<String>) -> Self {
+        self.uid = uid;
+        self
+    }
+
+    /// Sets the categories and color of the event
+    pub fn with_tags(mut self, categories: Vec<String>, color: Option<String>) -> Self {
+        self.categories = categories;
+        self.color = color;
+        self
+    }
+
+    /// Marks the event as private
+    pub fn with_private(mut self, private: bool) -> Self {
+        self.private = private;
+        self
+    }
+
+    /// Categories and color of the event, used to pick its visual treatment
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.categories
+            .iter()
+            .chain(self.color.as_ref())
+            .map(String::as_str)
+    }
+
     /// Returns a formatted string of the time range (e.g., "09:00 - 10:30")
     pub fn format_time_range(&self) -> String {
         format!(
@@ -91,9 +377,18 @@ impl CalendarEvent {
 
 #[derive(Debug, Clone)]
 pub struct Calendar {
-    /// URL of the ICAL calendar
+    /// URL of the ICAL calendar or CalDAV collection
     url: String,
 
+    /// How the calendar is fetched from the URL
+    source: CalendarSource,
+
+    /// Calendar fetched instead while the calendar cannot be fetched
+    fallback: Option<CalendarFallback>,
+
+    /// Whether the events are those of the fallback calendar
+    degraded: bool,
+
     /// Last time the calendar was fetched
     last_updated: Option<DateTime<Utc>>,
 
@@ -102,6 +397,12 @@ pub struct Calendar {
 
     /// How often to refresh the calendar data (in minutes)
     refresh_interval_minutes: u64,
+
+    /// How duplicate events are detected
+    deduplication: Deduplication,
+
+    /// Where to record parse durations, if anywhere
+    metrics: Option<Arc<Metrics>>,
 }
 
 impl Calendar {
@@ -109,95 +410,235 @@ impl Calendar {
     pub fn new(url: String, refresh_interval_minutes: u64) -> Self {
         Self {
             url,
+            source: CalendarSource::default(),
+            fallback: None,
+            degraded: false,
             last_updated: None,
             events: Vec::new(),
             refresh_interval_minutes,
+            deduplication: Deduplication::default(),
+            metrics: None,
         }
     }
 
-    /// Fetches the calendar data from the URL and updates the events
-    pub async fn update(&mut self) -> Result<(), CalendarError> {
-        // Check if we need to update based on the refresh interval
-        if let Some(last_updated) = self.last_updated {
-            let now = Utc::now();
-            let elapsed = now.signed_duration_since(last_updated);
-
-            if elapsed.num_minutes() < self.refresh_interval_minutes as i64 {
-                debug!(
-                    "Using cached calendar data, last updated {} minutes ago",
-                    elapsed.num_minutes()
-                );
-                return Ok(());
+    /// Sets how the calendar is fetched from the URL
+    pub fn with_source(mut self, source: CalendarSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Sets the calendar fetched while this one cannot be fetched
+    pub fn with_fallback(mut self, fallback: Option<CalendarFallback>) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Whether the events are those of the fallback calendar, because the
+    /// calendar itself failed to be fetched on its last refresh
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// Sets whether the events are those of the fallback calendar
+    fn set_degraded(&mut self, degraded: bool) {
+        if degraded != self.degraded {
+            if degraded {
+                warn!("Calendar {} is degraded, using its fallback", self.url);
+            } else {
+                info!("Calendar {} recovered, leaving its fallback", self.url);
             }
         }
+        self.degraded = degraded;
+    }
 
-        debug!("Fetching calendar data from {}", self.url);
+    /// Fetches the fallback calendar after the calendar failed with `error`
+    ///
+    /// Without a fallback, or if it fails as well, the original error is
+    /// returned.
+    async fn fetch_fallback(&mut self, error: CalendarError) -> Result<String, CalendarError> {
+        let Some(fallback) = &self.fallback else {
+            return Err(error);
+        };
+        warn!(
+            "Failed to fetch calendar {}, trying fallback {}: {}",
+            self.url, fallback.url, error
+        );
+        match fallback.source.fetch(&fallback.url).await {
+            Ok(data) => {
+                self.set_degraded(true);
+                Ok(data)
+            }
+            Err(e) => {
+                warn!("Failed to fetch fallback calendar {}: {}", fallback.url, e);
+                Err(error)
+            }
+        }
+    }
 
-        // Fetch the calendar data
-        let response = reqwest::get(&self.url)
-            .await
-            .map_err(|e| CalendarError::FetchError(e.to_string()))?;
+    /// Sets how duplicate events are detected
+    pub fn with_deduplication(mut self, deduplication: Deduplication) -> Self {
+        self.deduplication = deduplication;
+        self
+    }
+
+    /// Records how long parsing the calendar takes
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
 
-        if !response.status().is_success() {
-            return Err(CalendarError::FetchError(format!(
-                "HTTP error: {}",
-                response.status()
-            )));
+    /// Returns true if the cached events are younger than the refresh interval
+    fn is_fresh(&self) -> bool {
+        self.last_updated.is_some_and(|last_updated| {
+            let elapsed = Utc::now().signed_duration_since(last_updated);
+            elapsed.num_minutes() < self.refresh_interval_minutes as i64
+        })
+    }
+
+    /// Replaces the events with those parsed from the given data
+    ///
+    /// Only events within the [look-ahead window](EventWindow::lookahead) are
+    /// kept. Parsing runs on the blocking thread pool, large feeds take a
+    /// while.
+    async fn apply(
+        &mut self,
+        calendar_data: String,
+        fetched_at: DateTime<Utc>,
+    ) -> Result<(), CalendarError> {
+        let window = EventWindow::lookahead(Local::now());
+        let (parsed, elapsed) = tokio::task::spawn_blocking(move || {
+            let started = Instant::now();
+            let parsed = parse_calendar_window(&calendar_data, window);
+            (parsed, started.elapsed())
+        })
+        .await
+        .map_err(|e| CalendarError::ParseError(format!("Parser task failed: {}", e)))?;
+        let parsed = parsed?;
+        debug!(
+            "Calendar {}: parsed in {} ms, {} events outside the look-ahead window",
+            self.url,
+            elapsed.as_millis(),
+            parsed.outside_window
+        );
+        if let Some(metrics) = &self.metrics {
+            metrics.observe_calendar_parse(elapsed, parsed.events.len(), parsed.outside_window);
+        }
+        for warning in &parsed.warnings {
+            debug!("Calendar {}: {}", self.url, warning);
         }
 
-        let calendar_data = response
-            .text()
-            .await
-            .map_err(|e| CalendarError::FetchError(e.to_string()))?;
+        // Update the calendar
+        let count = parsed.events.len();
+        self.events = deduplicate(parsed.events, self.deduplication);
+        if self.events.len() < count {
+            debug!(
+                "Calendar {}: removed {} duplicate events",
+                self.url,
+                count - self.events.len()
+            );
+        }
+        self.last_updated = Some(fetched_at);
 
-        // Parse the calendar data
-        let unfolded_calendar = unfold(&calendar_data);
-        let parsed_calendar = icalendar::parser::read_calendar(&unfolded_calendar)
-            .map_err(|e| CalendarError::ParseError(e.to_string()))?;
+        debug!("Found {} events in calendar", self.events.len());
 
-        // Extract events
-        let mut events = Vec::new();
+        Ok(())
+    }
 
-        for component in parsed_calendar.components.iter() {
-            // Only process VEVENT components
-            if component.name != "VEVENT" {
-                continue;
-            }
+    /// Fetches the calendar data from the URL and updates the events
+    ///
+    /// Returns true if the data was fetched, false if the cached events were
+    /// still fresh.
+    pub async fn update(&mut self) -> Result<bool, CalendarError> {
+        // Check if we need to update based on the refresh interval
+        if self.is_fresh() {
+            debug!("Using cached calendar data for {}", self.url);
+            return Ok(false);
+        }
 
-            // Extract event properties
-            let mut summary = None;
-            let mut dtstart = None;
-            let mut dtend = None;
-            let mut location = None;
-            let mut description = None;
-
-            for property in &component.properties {
-                match property.name.as_str() {
-                    "SUMMARY" => summary = Some(property.val.to_string()),
-                    "DTSTART" => dtstart = parse_datetime_property(Some(property)),
-                    "DTEND" => dtend = parse_datetime_property(Some(property)),
-                    "LOCATION" => location = Some(property.val.to_string()),
-                    "DESCRIPTION" => description = Some(property.val.to_string()),
-                    _ => {}
-                }
-            }
+        debug!("Fetching calendar data from {}", self.url);
 
-            if let (Some(summary), Some(dtstart), Some(dtend)) = (summary, dtstart, dtend) {
-                let event = CalendarEvent::new(summary, dtstart, dtend, location, description);
-                events.push(event);
+        let calendar_data = match self.source.fetch(&self.url).await {
+            Ok(data) => {
+                self.set_degraded(false);
+                data
             }
+            Err(e) => self.fetch_fallback(e).await?,
+        };
+        self.apply(calendar_data, Utc::now()).await?;
+        Ok(true)
+    }
+
+    /// Like [`Calendar::update`], but shares fetched data via the database
+    ///
+    /// Data fetched by another server instance within the refresh interval is
+    /// reused, so that the calendar server is queried once per interval rather
+    /// than once per instance. Database errors fall back to fetching directly.
+    /// Returns true only if this instance fetched the data.
+    pub async fn update_shared(&mut self, db: &Arc<Database>) -> Result<bool, CalendarError> {
+        if self.is_fresh() {
+            debug!("Using cached calendar data for {}", self.url);
+            return Ok(false);
         }
 
-        // Sort events by start time
-        events.sort_by(|a, b| a.start_time.cmp(&b.start_time));
+        if let Some((data, fetched_at)) = self.shared_data(db, &self.url).await {
+            debug!("Using shared calendar data for {}", self.url);
+            self.set_degraded(false);
+            self.apply(data, fetched_at).await?;
+            return Ok(false);
+        }
 
-        // Update the calendar
-        self.events = events;
-        self.last_updated = Some(Utc::now());
+        debug!("Fetching calendar data from {}", self.url);
 
-        debug!("Found {} events in calendar", self.events.len());
+        let (calendar_data, url) = match self.source.fetch(&self.url).await {
+            Ok(data) => {
+                self.set_degraded(false);
+                (data, self.url.clone())
+            }
+            Err(e) => {
+                // Another instance may have fetched the fallback already
+                let fallback_url = self.fallback.as_ref().map(|fallback| fallback.url.clone());
+                if let Some(url) = fallback_url
+                    && let Some((data, fetched_at)) = self.shared_data(db, &url).await
+                {
+                    debug!("Using shared fallback calendar data for {}", self.url);
+                    self.set_degraded(true);
+                    self.apply(data, fetched_at).await?;
+                    return Ok(false);
+                }
+                let data = self.fetch_fallback(e).await?;
+                let url = self
+                    .fallback
+                    .as_ref()
+                    .map_or(&self.url, |fallback| &fallback.url);
+                (data, url.clone())
+            }
+        };
+        let stored = calendar_data.clone();
+        if let Err(e) = db
+            .run_blocking(move |db| db.store_cached_calendar(&url, &stored))
+            .await
+        {
+            warn!("Failed to write shared calendar cache: {:#}", e);
+        }
+        self.apply(calendar_data, Utc::now()).await?;
+        Ok(true)
+    }
 
-        Ok(())
+    /// Calendar data of the given URL fetched by any instance within the
+    /// refresh interval, with the time it was fetched
+    async fn shared_data(&self, db: &Arc<Database>, url: &str) -> Option<(String, DateTime<Utc>)> {
+        let url = url.to_string();
+        let cached = db
+            .run_blocking(move |db| db.cached_calendar(&url))
+            .await
+            .unwrap_or_else(|e: anyhow::Error| {
+                warn!("Failed to read shared calendar cache: {:#}", e);
+                None
+            })?;
+        let fetched_at = DateTime::from_timestamp(cached.fetched_at, 0)?;
+        (Utc::now().signed_duration_since(fetched_at).num_minutes()
+            < self.refresh_interval_minutes as i64)
+            .then_some((cached.data, fetched_at))
     }
 
     /// Returns the current event (if any)
@@ -221,7 +662,670 @@ impl Calendar {
     }
 }
 
+/// Registry of calendars, one per room, shared between requests
+pub struct CalendarRegistry {
+    /// Calendars keyed by room ID
+    calendars: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Calendar>>>>,
+
+    /// How often to refresh the calendar data (in minutes)
+    refresh_interval_minutes: u64,
+
+    /// Database shared with other server instances for fetched calendar data
+    shared_cache: Option<Arc<Database>>,
+
+    /// Where to report changes of a room's next event, if anywhere
+    change_notifier: Option<Arc<dyn Notifier>>,
+
+    /// Where to record calendar parse durations, if anywhere
+    metrics: Option<Arc<Metrics>>,
+
+    /// Events of each room as of the last successful refresh, keyed by room
+    /// ID, served to requests while the calendar is refreshed
+    snapshots: Mutex<HashMap<String, Vec<CalendarEvent>>>,
+
+    /// Next event of each room as of the last refresh, keyed by room ID
+    next_events: Mutex<HashMap<String, Option<CalendarEvent>>>,
+
+    /// Whether the last refresh of each calendar succeeded, keyed by URL
+    health: Mutex<HashMap<String, CalendarHealth>>,
+
+    /// Rooms whose events are those of their fallback calendar
+    degraded: Mutex<HashSet<String>>,
+
+    /// Failed refreshes in a row of each room's calendar, keyed by room ID
+    refresh_failures: Mutex<HashMap<String, RefreshFailures>>,
+}
+
+/// Streak of failed refreshes of a room's calendar
+#[derive(Debug, Clone, Copy, Default)]
+struct RefreshFailures {
+    count: u32,
+    /// Whether the streak was reported, see
+    /// [`CalendarRegistry::take_failing_calendars`]
+    reported: bool,
+}
+
+/// Health of a calendar source as of its last refresh
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CalendarHealth {
+    /// The calendar was fetched (or taken from the shared cache) and parsed
+    Ok,
+    /// Fetching or parsing the calendar failed
+    Failing,
+    /// The calendar has not been refreshed by this instance yet
+    Unknown,
+}
+
+impl CalendarRegistry {
+    /// Creates a new empty registry
+    pub fn new(refresh_interval_minutes: u64) -> Self {
+        Self {
+            calendars: Mutex::new(HashMap::new()),
+            refresh_interval_minutes,
+            shared_cache: None,
+            change_notifier: None,
+            metrics: None,
+            snapshots: Mutex::new(HashMap::new()),
+            next_events: Mutex::new(HashMap::new()),
+            health: Mutex::new(HashMap::new()),
+            degraded: Mutex::new(HashSet::new()),
+            refresh_failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Shares fetched calendar data with other instances via the database
+    pub fn with_shared_cache(mut self, database: Arc<Database>) -> Self {
+        self.shared_cache = Some(database);
+        self
+    }
+
+    /// Reports changes of a room's next event (bookings, cancellations,
+    /// time shifts) detected when fetching its calendar
+    ///
+    /// With a shared cache, only the instance fetching the data reports the
+    /// change.
+    pub fn with_change_notifier(mut self, notifier: Arc<dyn Notifier>) -> Self {
+        self.change_notifier = Some(notifier);
+        self
+    }
+
+    /// Records how long parsing each calendar takes
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Drops all cached calendar data, forcing a re-fetch on next use
+    pub fn invalidate_all(&self) {
+        if let Ok(mut calendars) = self.calendars.lock() {
+            calendars.clear();
+        }
+        if let Ok(mut snapshots) = self.snapshots.lock() {
+            snapshots.clear();
+        }
+    }
+
+    /// Drops the cached calendar data of a room, forcing a re-fetch on next use
+    pub fn invalidate_room(&self, room_id: &str) {
+        if let Ok(mut calendars) = self.calendars.lock() {
+            calendars.remove(room_id);
+        }
+        if let Ok(mut snapshots) = self.snapshots.lock() {
+            snapshots.remove(room_id);
+        }
+    }
+
+    /// Returns the future events (including current) of a room's calendar
+    ///
+    /// The events of the last refresh are returned right away, the calendar
+    /// is kept up to date in the background by [`Self::refresh_all`]. Only a
+    /// calendar that was never fetched is fetched before returning.
+    pub async fn future_events(
+        &self,
+        room_id: &str,
+        url: &str,
+        source: &CalendarSource,
+        fallback: Option<&CalendarFallback>,
+        deduplication: Deduplication,
+    ) -> Result<Vec<CalendarEvent>, CalendarError> {
+        if let Some(events) = self.cached_future_events(room_id) {
+            return Ok(events);
+        }
+        self.refresh(room_id, url, source, fallback, deduplication)
+            .await
+    }
+
+    /// Refreshes a room's calendar and returns its future events (including
+    /// current)
+    ///
+    /// The calendar is fetched if the cached data is older than the refresh
+    /// interval. While it cannot be fetched, the events of the fallback
+    /// calendar are returned, if any, and the room is
+    /// [degraded](Self::is_degraded).
+    pub async fn refresh(
+        &self,
+        room_id: &str,
+        url: &str,
+        source: &CalendarSource,
+        fallback: Option<&CalendarFallback>,
+        deduplication: Deduplication,
+    ) -> Result<Vec<CalendarEvent>, CalendarError> {
+        let calendar = {
+            let mut calendars = self
+                .calendars
+                .lock()
+                .map_err(|e| CalendarError::FetchError(format!("Registry lock poisoned: {}", e)))?;
+            calendars
+                .entry(room_id.to_string())
+                .or_insert_with(|| {
+                    let mut calendar =
+                        Calendar::new(url.to_string(), self.refresh_interval_minutes)
+                            .with_source(source.clone())
+                            .with_fallback(fallback.cloned())
+                            .with_deduplication(deduplication);
+                    if let Some(metrics) = &self.metrics {
+                        calendar = calendar.with_metrics(metrics.clone());
+                    }
+                    Arc::new(tokio::sync::Mutex::new(calendar))
+                })
+                .clone()
+        };
+
+        let mut calendar = calendar.lock().await;
+        let result = match &self.shared_cache {
+            Some(db) => calendar.update_shared(db).await,
+            None => calendar.update().await,
+        };
+        if let Ok(mut health) = self.health.lock() {
+            let status = match &result {
+                Ok(_) if !calendar.is_degraded() => CalendarHealth::Ok,
+                _ => CalendarHealth::Failing,
+            };
+            health.insert(url.to_string(), status);
+        }
+        if let Ok(mut degraded) = self.degraded.lock() {
+            if result.is_ok() && calendar.is_degraded() {
+                degraded.insert(room_id.to_string());
+            } else {
+                degraded.remove(room_id);
+            }
+        }
+        self.track_next_event(room_id, &calendar.events, result?);
+        if let Ok(mut snapshots) = self.snapshots.lock() {
+            snapshots.insert(room_id.to_string(), calendar.events.clone());
+        }
+        Ok(calendar.get_future_events().into_iter().cloned().collect())
+    }
+
+    /// Health of a calendar as of its last refresh
+    pub fn health(&self, url: &str) -> CalendarHealth {
+        self.health
+            .lock()
+            .ok()
+            .and_then(|health| health.get(url).copied())
+            .unwrap_or(CalendarHealth::Unknown)
+    }
+
+    /// Whether the events of a room are those of its fallback calendar, as of
+    /// its last refresh
+    pub fn is_degraded(&self, room_id: &str) -> bool {
+        self.degraded
+            .lock()
+            .is_ok_and(|degraded| degraded.contains(room_id))
+    }
+
+    /// Future events (including current) of a room's calendar as of its last
+    /// successful refresh, without refreshing it
+    ///
+    /// Returns None if the calendar has not been fetched yet.
+    pub fn cached_future_events(&self, room_id: &str) -> Option<Vec<CalendarEvent>> {
+        let snapshots = self.snapshots.lock().ok()?;
+        let now = Local::now();
+        let events = snapshots.get(room_id)?;
+        Some(
+            events
+                .iter()
+                .filter(|e| e.end_time > now)
+                .cloned()
+                .collect(),
+        )
+    }
+
+    /// Refreshes the stale calendars of all rooms with a calendar
+    ///
+    /// Returns the number of calendars that could not be refreshed. Rooms
+    /// showing the events of their fallback calendar count as failed towards
+    /// [`Self::take_failing_calendars`].
+    pub async fn refresh_all(&self, rooms: &[Room]) -> usize {
+        let mut failed = 0;
+        for room in rooms {
+            let Some(url) = &room.calendar_url else {
+                continue;
+            };
+            let result = self
+                .refresh(
+                    &room.id,
+                    url,
+                    &room.calendar_source,
+                    room.fallback_calendar.as_ref(),
+                    room.deduplicate,
+                )
+                .await;
+            if let Err(e) = &result {
+                warn!("Failed to refresh calendar of room {}: {}", room.id, e);
+                failed += 1;
+            }
+            if let Ok(mut failures) = self.refresh_failures.lock() {
+                if result.is_err() || self.is_degraded(&room.id) {
+                    failures.entry(room.id.clone()).or_default().count += 1;
+                } else {
+                    failures.remove(&room.id);
+                }
+            }
+        }
+        failed
+    }
+
+    /// Rooms whose calendar failed to refresh at least `threshold` times in a
+    /// row, with the number of failures
+    ///
+    /// Each streak of failures is returned once, a room is returned again
+    /// only after a successful refresh.
+    pub fn take_failing_calendars(&self, threshold: u32) -> Vec<(String, u32)> {
+        let Ok(mut failures) = self.refresh_failures.lock() else {
+            return Vec::new();
+        };
+        let mut failing: Vec<_> = failures
+            .iter_mut()
+            .filter(|(_, failures)| failures.count >= threshold && !failures.reported)
+            .map(|(room_id, failures)| {
+                failures.reported = true;
+                (room_id.clone(), failures.count)
+            })
+            .collect();
+        failing.sort();
+        failing
+    }
+
+    /// Remember the next event of a room, reporting changes in fetched data
+    ///
+    /// The first refresh of a room only records its next event, there is
+    /// nothing to compare against yet.
+    fn track_next_event(&self, room_id: &str, events: &[CalendarEvent], fetched: bool) {
+        let Ok(mut next_events) = self.next_events.lock() else {
+            return;
+        };
+        let now = Local::now();
+        let next = next_event(events, now).cloned();
+        let previous = next_events.insert(room_id.to_string(), next);
+
+        if fetched
+            && let Some(notifier) = &self.change_notifier
+            && let Some(previous) = previous
+            && let Some(change) = NextEventChange::detect(previous.as_ref(), events, now)
+        {
+            notifier.notify(change.notification(room_id));
+        }
+    }
+}
+
+/// Events and warnings resulting from parsing iCalendar data
+#[derive(Debug, Clone, Default)]
+pub struct ParsedCalendar {
+    /// Parsed events, sorted by start time
+    pub events: Vec<CalendarEvent>,
+
+    /// Human-readable descriptions of events that could not be used
+    pub warnings: Vec<String>,
+
+    /// Number of events dropped because they were outside the parsed window
+    pub outside_window: usize,
+}
+
+/// Fetches raw iCalendar data from the given URL
+pub async fn fetch_calendar_data(url: &str) -> Result<String, CalendarError> {
+    let response = reqwest::get(url)
+        .await
+        .map_err(|e| CalendarError::FetchError(e.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(CalendarError::FetchError(format!(
+            "HTTP error: {}",
+            response.status()
+        )));
+    }
+
+    response
+        .text()
+        .await
+        .map_err(|e| CalendarError::FetchError(e.to_string()))
+}
+
+/// Parses iCalendar data into events
+///
+/// Keeps all events of the calendar. Room calendars are parsed with
+/// [`parse_calendar_window`] instead, which parses one event at a time and
+/// drops the events outside its window, rather than holding an unfolded copy
+/// and the parsed components of the whole feed.
+pub fn parse_calendar(calendar_data: &str) -> Result<ParsedCalendar, CalendarError> {
+    let unfolded_calendar = unfold(calendar_data);
+    let parsed_calendar = icalendar::parser::read_calendar(&unfolded_calendar)
+        .map_err(|e| CalendarError::ParseError(e.to_string()))?;
+
+    // Extract events
+    let mut events = Vec::new();
+    let mut warnings = Vec::new();
+
+    for component in parsed_calendar.components.iter() {
+        // Only process VEVENT components
+        if component.name != "VEVENT" {
+            continue;
+        }
+        match parse_event(component) {
+            Ok(event) => events.push(event),
+            Err(warning) => warnings.push(warning),
+        }
+    }
+
+    // Sort events by start time
+    events.sort_by_key(|e| e.start_time);
+
+    Ok(ParsedCalendar {
+        events,
+        warnings,
+        outside_window: 0,
+    })
+}
+
+/// Days ahead of today for which events are kept when parsing room calendars
+///
+/// One more than the longest room schedule, so that the cached events still
+/// cover it when the calendar cannot be refreshed for a while.
+pub const LOOKAHEAD_DAYS: u32 = 15;
+
+/// Time range of the events kept when parsing a calendar
+#[derive(Debug, Clone, Copy)]
+pub struct EventWindow {
+    /// Events ending at or before this time are dropped
+    pub start: DateTime<Local>,
+    /// Events starting at or after this time are dropped
+    pub end: DateTime<Local>,
+}
+
+impl EventWindow {
+    /// Events that have not ended yet, up to [`LOOKAHEAD_DAYS`] after today
+    pub fn lookahead(now: DateTime<Local>) -> Self {
+        Self::days(now, LOOKAHEAD_DAYS)
+    }
+
+    /// Events that have not ended yet, up to the given number of days after
+    /// today, e.g. today's remaining events for 1
+    pub fn days(now: DateTime<Local>, days: u32) -> Self {
+        let end = (now.date_naive() + Days::new(days.into()))
+            .and_time(NaiveTime::MIN)
+            .and_local_timezone(Local)
+            .earliest()
+            .unwrap_or(now + Duration::days(days.into()));
+        Self { start: now, end }
+    }
+
+    /// Whether an event overlaps the window
+    pub fn contains(&self, event: &CalendarEvent) -> bool {
+        event.end_time > self.start && event.start_time < self.end
+    }
+}
+
+/// Collects the content lines of one VEVENT at a time
+#[derive(Default)]
+struct EventScanner {
+    /// Unfolded lines of the event being read, if any
+    event: Option<String>,
+}
+
+impl EventScanner {
+    /// Feeds an unfolded content line, returning the event it completes
+    fn push(&mut self, line: &str) -> Option<String> {
+        if line.eq_ignore_ascii_case("BEGIN:VEVENT") {
+            self.event = Some(String::new());
+        }
+        let event = self.event.as_mut()?;
+        event.push_str(line);
+        event.push_str("\r\n");
+        if line.eq_ignore_ascii_case("END:VEVENT") {
+            return self.event.take();
+        }
+        None
+    }
+}
+
+/// Parses the events of iCalendar data that overlap a time window
+///
+/// Unlike [`parse_calendar`], the data is unfolded and parsed one event at a
+/// time, and events outside the window are dropped right away. The downloaded
+/// feed itself is still held in memory as a whole, but neither an unfolded
+/// copy of it nor the parsed components of all its events are, so that memory
+/// use beyond the feed does not grow with its size. Calendars with tens of
+/// thousands of events mostly consist of past meetings.
+pub fn parse_calendar_window(
+    calendar_data: &str,
+    window: EventWindow,
+) -> Result<ParsedCalendar, CalendarError> {
+    let mut parsed = ParsedCalendar::default();
+    let mut scanner = EventScanner::default();
+    let mut is_calendar = false;
+    let mut line = String::new();
+
+    let mut handle_line = |line: &str, parsed: &mut ParsedCalendar| {
+        is_calendar |= line.eq_ignore_ascii_case("BEGIN:VCALENDAR");
+        let Some(data) = scanner.push(line) else {
+            return Ok(());
+        };
+        let component = icalendar::parser::read_components(&data)
+            .map_err(|e| CalendarError::ParseError(e.to_string()))?
+            .into_iter()
+            .next()
+            .ok_or_else(|| CalendarError::ParseError("Empty event".to_string()))?;
+        match parse_event(&component) {
+            Ok(event) if window.contains(&event) => parsed.events.push(event),
+            Ok(_) => parsed.outside_window += 1,
+            Err(warning) => parsed.warnings.push(warning),
+        }
+        Ok(())
+    };
+
+    for physical_line in calendar_data.lines() {
+        // Folded lines continue with a space or tab (RFC 5545, section 3.1)
+        if let Some(continuation) = physical_line.strip_prefix([' ', '\t']) {
+            line.push_str(continuation);
+            continue;
+        }
+        handle_line(&line, &mut parsed)?;
+        line.clear();
+        line.push_str(physical_line);
+    }
+    handle_line(&line, &mut parsed)?;
+
+    if !is_calendar {
+        return Err(CalendarError::ParseError(
+            "Missing BEGIN:VCALENDAR".to_string(),
+        ));
+    }
+
+    parsed.events.sort_by_key(|e| e.start_time);
+    Ok(parsed)
+}
+
+/// Extracts an event from a VEVENT component
+///
+/// Returns a human-readable warning if the event cannot be used.
+fn parse_event(component: &Component) -> Result<CalendarEvent, String> {
+    // Extract event properties
+    let mut uid = None;
+    let mut summary = None;
+    let mut dtstart = None;
+    let mut dtend = None;
+    let mut location = None;
+    let mut description = None;
+    let mut categories = Vec::new();
+    let mut color = None;
+    let mut private = false;
+
+    for property in &component.properties {
+        match property.name.as_str() {
+            "UID" => uid = Some(property.val.to_string()),
+            "SUMMARY" => summary = Some(property.val.to_string()),
+            "DTSTART" => dtstart = Some(property),
+            "DTEND" => dtend = Some(property),
+            "LOCATION" => location = Some(property.val.to_string()),
+            "DESCRIPTION" => description = normalize_description(property.val.as_str()),
+            // May be given more than once, each with a comma-separated list
+            "CATEGORIES" => categories.extend(parse_categories(property.val.as_str())),
+            "COLOR" => {
+                color = Some(property.val.as_str().trim().to_ascii_lowercase())
+                    .filter(|c| !c.is_empty())
+            }
+            "CLASS" => {
+                private = matches!(
+                    property.val.as_str().trim().to_ascii_uppercase().as_str(),
+                    "PRIVATE" | "CONFIDENTIAL"
+                )
+            }
+            _ => {}
+        }
+    }
+
+    let label = summary
+        .clone()
+        .or(uid.clone())
+        .unwrap_or_else(|| "<unnamed>".to_string());
+    let Some(summary) = summary else {
+        return Err(format!("Skipped event {}: missing SUMMARY", label));
+    };
+    let (start, end) = match (dtstart, dtend) {
+        (Some(start), Some(end)) => (start, end),
+        (None, _) => return Err(format!("Skipped event {}: missing DTSTART", label)),
+        (_, None) => return Err(format!("Skipped event {}: missing DTEND", label)),
+    };
+    let (Some(dtstart), Some(dtend)) = (
+        parse_datetime_property(Some(start)),
+        parse_datetime_property(Some(end)),
+    ) else {
+        return Err(format!(
+            "Skipped event {}: unsupported date format ({} / {})",
+            label,
+            start.val.as_str(),
+            end.val.as_str()
+        ));
+    };
+
+    Ok(
+        CalendarEvent::new(summary, dtstart, dtend, location, description)
+            .with_uid(uid)
+            .with_tags(categories, color)
+            .with_private(private),
+    )
+}
+
+/// Split a `CATEGORIES` value into its (unescaped) categories
+fn parse_categories(value: &str) -> Vec<String> {
+    let mut categories = Vec::new();
+    let mut current = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => current.extend(chars.next()),
+            ',' => categories.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    categories.push(current);
+    categories
+        .into_iter()
+        .map(|category| category.trim().to_string())
+        .filter(|category| !category.is_empty())
+        .collect()
+}
+
+/// Windows time zone names used by Outlook and Exchange, with their IANA equivalents
+///
+/// Only the zones of the sites of typical deployments; other Windows names
+/// fall back to the server time zone.
+const WINDOWS_TIME_ZONES: &[(&str, Tz)] = &[
+    ("W. Europe Standard Time", Tz::Europe__Berlin),
+    ("Central Europe Standard Time", Tz::Europe__Budapest),
+    ("Romance Standard Time", Tz::Europe__Paris),
+    ("GMT Standard Time", Tz::Europe__London),
+    ("Greenwich Standard Time", Tz::Atlantic__Reykjavik),
+    ("E. Europe Standard Time", Tz::Europe__Chisinau),
+    ("FLE Standard Time", Tz::Europe__Kiev),
+    ("Eastern Standard Time", Tz::America__New_York),
+    ("Central Standard Time", Tz::America__Chicago),
+    ("Mountain Standard Time", Tz::America__Denver),
+    ("Pacific Standard Time", Tz::America__Los_Angeles),
+    ("Tokyo Standard Time", Tz::Asia__Tokyo),
+    ("India Standard Time", Tz::Asia__Kolkata),
+    ("China Standard Time", Tz::Asia__Shanghai),
+    ("AUS Eastern Standard Time", Tz::Australia__Sydney),
+    ("UTC", Tz::UTC),
+];
+
+/// Resolve the `TZID` parameter of a date-time property
+///
+/// Besides IANA names (`Europe/Zurich`), this understands the prefixed IDs
+/// some clients generate (`/mozilla.org/20050126_1/Europe/Zurich`) and common
+/// Windows names (`W. Europe Standard Time`).
+fn resolve_tzid(tzid: &str) -> Option<Tz> {
+    let tzid = tzid.trim().trim_matches('"');
+    if let Ok(tz) = tzid.parse() {
+        return Some(tz);
+    }
+    // Longest suffix of path segments that is a known zone, e.g. `America/Argentina/Salta`
+    let segments: Vec<&str> = tzid.split('/').filter(|s| !s.is_empty()).collect();
+    (0..segments.len())
+        .find_map(|start| segments[start..].join("/").parse().ok())
+        .or_else(|| {
+            WINDOWS_TIME_ZONES
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(tzid))
+                .map(|(_, tz)| *tz)
+        })
+}
+
+/// Time zones already reported as unknown
+static UNKNOWN_TZIDS: LazyLock<Mutex<HashSet<String>>> = LazyLock::new(Mutex::default);
+
+/// Returns true the first time an unknown time zone is seen, so that it is
+/// reported once rather than for every event of every refresh
+fn first_unknown_tzid(tzid: &str) -> bool {
+    match UNKNOWN_TZIDS.lock() {
+        Ok(mut seen) => seen.insert(tzid.to_string()),
+        Err(poisoned) => poisoned.into_inner().insert(tzid.to_string()),
+    }
+}
+
+/// Resolve a wall-clock time in a time zone, following RFC 5545
+///
+/// Times that occur twice when the clocks go back refer to the first
+/// occurrence; times skipped when the clocks go forward are interpreted with
+/// the offset before the gap, i.e. 02:30 becomes 03:30.
+fn resolve_local_time<T: TimeZone>(tz: &T, dt: NaiveDateTime) -> Option<DateTime<T>> {
+    match tz.from_local_datetime(&dt) {
+        LocalResult::Single(dt) => Some(dt),
+        LocalResult::Ambiguous(earliest, _) => Some(earliest),
+        LocalResult::None => {
+            let before = tz
+                .from_local_datetime(&(dt - Duration::hours(1)))
+                .earliest()?;
+            Some(before + Duration::hours(1))
+        }
+    }
+}
+
 /// Helper function to parse datetime from iCalendar property
+///
+/// Date-times are UTC (`...Z`), in the zone of a `TZID` parameter, or
+/// floating, i.e. in the server time zone like dates of all-day events.
 fn parse_datetime_property(
     property: Option<&icalendar::parser::Property>,
 ) -> Option<DateTime<Local>> {
@@ -229,33 +1333,39 @@ fn parse_datetime_property(
     let value = property.val.to_string();
 
     // Try parsing as UTC time (ends with Z)
-    if value.ends_with('Z') {
-        if let Ok(dt) = DateTime::parse_from_str(&value, "%Y%m%dT%H%M%SZ") {
-            return Some(dt.with_timezone(&Local));
-        }
+    if value.ends_with('Z')
+        && let Ok(dt) = NaiveDateTime::parse_from_str(&value, "%Y%m%dT%H%M%SZ")
+    {
+        return Some(dt.and_utc().with_timezone(&Local));
     }
 
-    // Try parsing as local time
-    if value.contains('T') {
-        if let Ok(dt) = chrono::NaiveDateTime::parse_from_str(&value, "%Y%m%dT%H%M%S") {
-            return Some(
-                Local
-                    .from_local_datetime(&dt)
-                    .single()
-                    .unwrap_or_else(|| Local::now()),
-            );
+    if value.contains('T')
+        && let Ok(dt) = NaiveDateTime::parse_from_str(&value, "%Y%m%dT%H%M%S")
+    {
+        let tzid = property
+            .params
+            .iter()
+            .find(|param| param.key.as_str().eq_ignore_ascii_case("TZID"))
+            .and_then(|param| param.val.as_ref());
+        if let Some(tzid) = tzid {
+            match resolve_tzid(tzid.as_str()) {
+                Some(tz) => {
+                    return resolve_local_time(&tz, dt).map(|dt| dt.with_timezone(&Local));
+                }
+                None if first_unknown_tzid(tzid.as_str()) => warn!(
+                    "Unknown time zone {}, using the server time zone",
+                    tzid.as_str()
+                ),
+                None => {}
+            }
         }
+        // Floating or unknown time zone, taken as local time
+        return resolve_local_time(&Local, dt);
     }
 
     // Try parsing as date (all-day event)
     if let Ok(date) = chrono::NaiveDate::parse_from_str(&value, "%Y%m%d") {
-        let naive_dt = date.and_hms_opt(0, 0, 0).unwrap();
-        return Some(
-            Local
-                .from_local_datetime(&naive_dt)
-                .single()
-                .unwrap_or_else(|| Local::now()),
-        );
+        return resolve_local_time(&Local, date.and_time(NaiveTime::MIN));
     }
 
     None
@@ -283,4 +1393,375 @@ mod tests {
         assert_eq!(event.duration_minutes, 90);
         assert_eq!(event.format_time_range(), "09:00 - 10:30");
     }
+
+    #[tokio::test]
+    async fn test_secret_env_prefix() {
+        let caldav = |name: &str| CalendarSource::CalDav {
+            username: Some("displays".to_string()),
+            password_env: Some(name.to_string()),
+        };
+        assert!(caldav("TRMNL_CALENDAR_SECRET_CALDAV").validate().is_ok());
+        assert!(caldav("ACCESS_TOKEN").validate().is_err());
+        assert!(caldav("TRMNL_CALENDAR_SECRET_").validate().is_err());
+        assert!(CalendarSource::Ical.validate().is_ok());
+        let microsoft = CalendarSource::Microsoft {
+            tenant_id: "contoso.onmicrosoft.com".to_string(),
+            client_id: "app-1".to_string(),
+            client_secret_env: "DATABASE_URL".to_string(),
+        };
+        assert!(microsoft.validate().is_err());
+
+        // Sources that were not validated do not read other variables either
+        let error = caldav("ACCESS_TOKEN")
+            .fetch("http://127.0.0.1:9/calendar/")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("is not a calendar secret"));
+    }
+
+    #[test]
+    fn test_deduplicate() {
+        let at = |hour: u32| Local.with_ymd_and_hms(2024, 3, 4, hour, 0, 0).unwrap();
+        let event = |name: &str, uid: Option<&str>, start: u32| {
+            CalendarEvent::new(name.to_string(), at(start), at(start + 1), None, None)
+                .with_uid(uid.map(str::to_string))
+        };
+        let events = vec![
+            event("Standup", Some("a"), 9),
+            // Same meeting from a second source
+            event("Standup", Some("a"), 9),
+            // Next instance of the recurring meeting
+            event("Standup", Some("a"), 10),
+            // Same meeting, but copied into another calendar with a new UID
+            event(" standup ", Some("b"), 9),
+            event("Review", None, 9),
+        ];
+
+        let names = |mode| {
+            deduplicate(events.clone(), mode)
+                .iter()
+                .map(|e| format!("{}@{}", e.name.trim(), e.start_time.format("%H")))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(Deduplication::Off).len(), 5);
+        assert_eq!(
+            names(Deduplication::Uid),
+            ["Standup@09", "Standup@10", "standup@09", "Review@09"]
+        );
+        assert_eq!(
+            names(Deduplication::Fuzzy),
+            ["Standup@09", "Standup@10", "Review@09"]
+        );
+    }
+
+    #[test]
+    fn test_parse_calendar() {
+        let data = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+UID:1\r
+SUMMARY:Standup\r
+DESCRIPTION:<p>Daily sync</p>\\n________________________________\\nMicrosoft Teams\r
+  meeting\\nJoin on your computer\r
+DTSTART:20240304T090000Z\r
+DTEND:20240304T091500Z\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:2\r
+SUMMARY:Planning\r
+DTSTART:20240304T080000\r
+DTEND:20240304T083000\r
+LOCATION:Room A\r
+CATEGORIES:Maintenance,Facilities\\, HVAC\r
+CATEGORIES:External client\r
+COLOR:Red\r
+CLASS:CONFIDENTIAL\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:3\r
+DTSTART:20240304T080000\r
+DTEND:20240304T083000\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+        let parsed = parse_calendar(data).unwrap();
+
+        assert_eq!(parsed.events.len(), 2);
+        assert_eq!(parsed.events[0].name, "Planning");
+        assert_eq!(parsed.events[0].location.as_deref(), Some("Room A"));
+        assert_eq!(parsed.events[0].uid.as_deref(), Some("2"));
+        assert_eq!(
+            parsed.events[0].tags().collect::<Vec<_>>(),
+            vec!["Maintenance", "Facilities, HVAC", "External client", "red"]
+        );
+        assert!(parsed.events[1].categories.is_empty());
+        assert!(parsed.events[0].private);
+        assert!(!parsed.events[1].private);
+        assert_eq!(parsed.events[1].name, "Standup");
+        assert_eq!(parsed.events[1].duration_minutes, 15);
+        assert_eq!(parsed.events[1].description.as_deref(), Some("Daily sync"));
+        assert_eq!(parsed.warnings, vec!["Skipped event 3: missing SUMMARY"]);
+    }
+
+    #[test]
+    fn test_unknown_tzid_reported_once() {
+        assert!(first_unknown_tzid("Venus/Maxwell_Montes"));
+        assert!(!first_unknown_tzid("Venus/Maxwell_Montes"));
+        assert!(first_unknown_tzid("Venus/Ishtar_Terra"));
+    }
+
+    #[test]
+    fn test_parse_tzid_datetimes() {
+        let start_of = |dtstart: &str| {
+            let data = format!(
+                "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:Meeting\r\n{}\r\nDTEND:20300101T000000Z\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+                dtstart
+            );
+            parse_calendar(&data).unwrap().events[0]
+                .start_time
+                .with_timezone(&Utc)
+        };
+        let utc = |y, m, d, h, min| Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap();
+
+        // Winter and summer time
+        assert_eq!(
+            start_of("DTSTART;TZID=Europe/Zurich:20240304T090000"),
+            utc(2024, 3, 4, 8, 0)
+        );
+        assert_eq!(
+            start_of("DTSTART;TZID=Europe/Zurich:20240704T090000"),
+            utc(2024, 7, 4, 7, 0)
+        );
+        assert_eq!(
+            start_of("DTSTART;TZID=\"America/New_York\":20240704T090000"),
+            utc(2024, 7, 4, 13, 0)
+        );
+
+        // Skipped when the clocks go forward, taken as 03:30 summer time
+        assert_eq!(
+            start_of("DTSTART;TZID=Europe/Zurich:20240331T023000"),
+            utc(2024, 3, 31, 1, 30)
+        );
+        // Occurring twice when the clocks go back, the first one is meant
+        assert_eq!(
+            start_of("DTSTART;TZID=Europe/Zurich:20241027T023000"),
+            utc(2024, 10, 27, 0, 30)
+        );
+        assert_eq!(
+            start_of("DTSTART;TZID=Europe/Zurich:20241027T033000"),
+            utc(2024, 10, 27, 2, 30)
+        );
+
+        // Client-specific time zone IDs
+        assert_eq!(
+            start_of("DTSTART;TZID=/mozilla.org/20050126_1/Europe/Zurich:20240704T090000"),
+            utc(2024, 7, 4, 7, 0)
+        );
+        assert_eq!(
+            start_of("DTSTART;TZID=W. Europe Standard Time:20240704T090000"),
+            utc(2024, 7, 4, 7, 0)
+        );
+
+        // Unknown time zones are taken as server time
+        let floating = NaiveDateTime::parse_from_str("20240704T090000", "%Y%m%dT%H%M%S").unwrap();
+        assert_eq!(
+            start_of("DTSTART;TZID=Mars/Olympus_Mons:20240704T090000"),
+            resolve_local_time(&Local, floating).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_calendar_window() {
+        let event = |uid: &str, start: &str, end: &str| {
+            format!(
+                "BEGIN:VEVENT\r\nUID:{uid}\r\nSUMMARY:Meeting\r\n  {uid}\r\n\
+                 DTSTART:{start}\r\nDTEND:{end}\r\n\
+                 BEGIN:VALARM\r\nACTION:DISPLAY\r\nEND:VALARM\r\nEND:VEVENT\r\n"
+            )
+        };
+        let data = format!(
+            "BEGIN:VCALENDAR\r\nVERSION:2.0\r\n{}{}{}{}{}END:VCALENDAR\r\n",
+            event("past", "20240301T090000Z", "20240301T100000Z"),
+            event("ongoing", "20240304T080000Z", "20240304T100000Z"),
+            event("later", "20240310T090000Z", "20240310T100000Z"),
+            event("far", "20240501T090000Z", "20240501T100000Z"),
+            "BEGIN:VEVENT\r\nUID:broken\r\nEND:VEVENT\r\n",
+        );
+        let window = EventWindow {
+            start: Utc.with_ymd_and_hms(2024, 3, 4, 9, 0, 0).unwrap().into(),
+            end: Utc.with_ymd_and_hms(2024, 3, 19, 0, 0, 0).unwrap().into(),
+        };
+
+        let parsed = parse_calendar_window(&data, window).unwrap();
+        let names: Vec<_> = parsed.events.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["Meeting ongoing", "Meeting later"]);
+        assert_eq!(parsed.outside_window, 2);
+        assert_eq!(parsed.warnings, ["Skipped event broken: missing SUMMARY"]);
+
+        // Same events as parsing the whole calendar
+        let all = parse_calendar(&data).unwrap();
+        assert_eq!(all.events.len(), 4);
+        assert_eq!(all.events[1].name, parsed.events[0].name);
+        assert_eq!(all.events[1].start_time, parsed.events[0].start_time);
+
+        assert!(parse_calendar_window("<html></html>", window).is_err());
+    }
+
+    /// Notifier recording the titles of delivered notifications
+    #[derive(Default)]
+    struct RecordingNotifier(Mutex<Vec<String>>);
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&self, notification: crate::notify::Notification) {
+            self.0.lock().unwrap().push(notification.title);
+        }
+    }
+
+    #[test]
+    fn test_next_event_change_is_reported() {
+        let notifier = Arc::new(RecordingNotifier::default());
+        let registry = CalendarRegistry::new(5).with_change_notifier(notifier.clone());
+        let start = Local::now() + chrono::Duration::hours(2);
+        let standup = CalendarEvent::new(
+            "Standup".to_string(),
+            start,
+            start + chrono::Duration::minutes(15),
+            None,
+            None,
+        );
+
+        // Nothing to compare against on the first refresh
+        let events = vec![standup];
+        registry.track_next_event("room-a", &events, true);
+        registry.track_next_event("room-a", &events, true);
+        assert!(notifier.0.lock().unwrap().is_empty());
+
+        // Changes in data that was not fetched by this instance are not reported
+        registry.track_next_event("room-a", &[], false);
+        assert!(notifier.0.lock().unwrap().is_empty());
+
+        registry.track_next_event("room-a", &[], true);
+        registry.track_next_event("room-a", &events, true);
+        assert_eq!(*notifier.0.lock().unwrap(), ["Meeting booked in room-a"]);
+    }
+
+    #[tokio::test]
+    async fn test_calendar_health() {
+        let registry = CalendarRegistry::new(5);
+        // Nothing listens on port 1
+        let url = "http://127.0.0.1:1/room-a.ics";
+        assert_eq!(registry.health(url), CalendarHealth::Unknown);
+
+        let result = registry
+            .future_events(
+                "room-a",
+                url,
+                &CalendarSource::default(),
+                None,
+                Deduplication::default(),
+            )
+            .await;
+        assert!(result.is_err());
+        assert_eq!(registry.health(url), CalendarHealth::Failing);
+        assert!(registry.cached_future_events("room-a").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_calendar_fallback() {
+        let start = Utc::now() + chrono::Duration::hours(1);
+        let ics = format!(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:1\r\nSUMMARY:Standup\r\nDTSTART:{}\r\nDTEND:{}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+            start.format("%Y%m%dT%H%M%SZ"),
+            (start + chrono::Duration::minutes(15)).format("%Y%m%dT%H%M%SZ"),
+        );
+        let router =
+            axum::Router::new().route("/room-a.ics", axum::routing::get(move || async { ics }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let fallback = CalendarFallback {
+            url: format!("http://{}/room-a.ics", listener.local_addr().unwrap()),
+            source: CalendarSource::default(),
+        };
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        // Nothing listens on port 1
+        let url = "http://127.0.0.1:1/room-a.ics";
+        let registry = CalendarRegistry::new(5);
+        let events = registry
+            .future_events(
+                "room-a",
+                url,
+                &CalendarSource::default(),
+                Some(&fallback),
+                Deduplication::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].name, "Standup");
+        assert!(registry.is_degraded("room-a"));
+        assert_eq!(registry.health(url), CalendarHealth::Failing);
+
+        // Requests get the cached events while the calendar is being refreshed
+        let calendar = registry.calendars.lock().unwrap()["room-a"].clone();
+        let _refreshing = calendar.lock().await;
+        let cached = tokio::time::timeout(
+            std::time::Duration::from_secs(1),
+            registry.future_events(
+                "room-a",
+                url,
+                &CalendarSource::default(),
+                Some(&fallback),
+                Deduplication::default(),
+            ),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached[0].name, "Standup");
+
+        // Without a reachable fallback, the error of the primary calendar remains
+        let mut calendar =
+            Calendar::new(url.to_string(), 5).with_fallback(Some(CalendarFallback {
+                url: "http://127.0.0.1:1/fallback.ics".to_string(),
+                source: CalendarSource::default(),
+            }));
+        assert!(calendar.update().await.is_err());
+        assert!(!calendar.is_degraded());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_all() {
+        let rooms = crate::rooms::parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+            calendar_url = "http://127.0.0.1:1/room-a.ics"
+
+            [[rooms]]
+            id = "room-b"
+            name = "Room B"
+            "#,
+        )
+        .unwrap();
+        let registry = CalendarRegistry::new(5);
+
+        // Rooms without a calendar are skipped
+        assert_eq!(registry.refresh_all(&rooms).await, 1);
+        assert_eq!(
+            registry.health("http://127.0.0.1:1/room-a.ics"),
+            CalendarHealth::Failing
+        );
+
+        // Failing calendars are taken once per streak of failures
+        assert!(registry.take_failing_calendars(2).is_empty());
+        registry.refresh_all(&rooms).await;
+        assert_eq!(
+            registry.take_failing_calendars(2),
+            [("room-a".to_string(), 2)]
+        );
+        registry.refresh_all(&rooms).await;
+        assert!(registry.take_failing_calendars(2).is_empty());
+    }
 }
@@ -0,0 +1,120 @@
+use rand::Rng;
+
+use crate::device_id::DeviceId;
+
+/// Characters used in claim codes, without easily confused ones (0/O, 1/I/L)
+const CLAIM_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+
+/// Number of characters in a claim code
+const CLAIM_CODE_LENGTH: usize = 8;
+
+/// Generate a random one-time claim code, formatted as `XXXX-XXXX`
+pub fn generate_claim_code() -> String {
+    let mut rng = rand::thread_rng();
+    let chars: String = (0..CLAIM_CODE_LENGTH)
+        .map(|_| CLAIM_CODE_ALPHABET[rng.gen_range(0..CLAIM_CODE_ALPHABET.len())] as char)
+        .collect();
+    format!("{}-{}", &chars[..4], &chars[4..])
+}
+
+/// Characters used in device API keys
+const API_KEY_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+/// Number of characters in a device API key
+const API_KEY_LENGTH: usize = 32;
+
+/// Generate a random API key for a provisioned device
+pub fn generate_api_key() -> String {
+    let mut rng = rand::thread_rng();
+    (0..API_KEY_LENGTH)
+        .map(|_| API_KEY_ALPHABET[rng.gen_range(0..API_KEY_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Normalize a claim code as entered by an installer
+///
+/// Case, whitespace and dashes are ignored, so `abcd efgh` matches `ABCD-EFGH`.
+pub fn normalize_claim_code(code: &str) -> String {
+    let chars: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if chars.len() == CLAIM_CODE_LENGTH {
+        format!("{}-{}", &chars[..4], &chars[4..])
+    } else {
+        chars
+    }
+}
+
+/// Parse a pre-provisioning CSV with `device_id,room_id` lines
+///
+/// Empty lines, `#` comments and a leading header line are skipped. Errors
+/// name the offending line number.
+pub fn parse_provisioning_csv(data: &str) -> Result<Vec<(String, String)>, String> {
+    let mut entries = Vec::new();
+    for (index, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [device_id, room_id] = fields[..] else {
+            return Err(format!(
+                "Line {}: expected 2 fields (device_id,room_id), got {}",
+                index + 1,
+                fields.len()
+            ));
+        };
+        if entries.is_empty() && device_id.eq_ignore_ascii_case("device_id") {
+            continue;
+        }
+        if device_id.is_empty() || room_id.is_empty() {
+            return Err(format!("Line {}: empty device or room ID", index + 1));
+        }
+        let device_id: DeviceId = device_id
+            .parse()
+            .map_err(|e| format!("Line {}: {}", index + 1, e))?;
+        entries.push((device_id.into_string(), room_id.to_string()));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claim_code_format() {
+        let code = generate_claim_code();
+        assert_eq!(code.len(), 9);
+        assert_eq!(normalize_claim_code(&code), code);
+        assert_eq!(normalize_claim_code(" abcd efgh "), "ABCD-EFGH");
+        assert_eq!(normalize_claim_code("abcd-efgh"), "ABCD-EFGH");
+    }
+
+    #[test]
+    fn test_api_key_format() {
+        assert_eq!(generate_api_key().len(), API_KEY_LENGTH);
+    }
+
+    #[test]
+    fn test_parse_provisioning_csv() {
+        let entries = parse_provisioning_csv(
+            "device_id,room_id\n# lobby\naa:bb:cc:dd:ee:ff, room-a\n\n11:22:33:44:55:66,room-b\n",
+        )
+        .unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ("AA:BB:CC:DD:EE:FF".to_string(), "room-a".to_string()),
+                ("11:22:33:44:55:66".to_string(), "room-b".to_string()),
+            ]
+        );
+
+        let err = parse_provisioning_csv("aa:bb:cc:dd:ee:ff\n").unwrap_err();
+        assert!(err.starts_with("Line 1:"));
+        let err = parse_provisioning_csv("aa:bb:cc:dd:ee:ff,room-a\nlobby,room-b\n").unwrap_err();
+        assert!(err.starts_with("Line 2: Invalid device ID"));
+    }
+}
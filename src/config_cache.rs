@@ -0,0 +1,301 @@
+//! In-memory cache of the configuration read on every display request
+//!
+//! Devices poll every few minutes, and each poll needs the device's room
+//! assignment and settings, the active broadcast, the open issue reports, the
+//! layout experiments and whether the server is under maintenance.
+//! Rather than querying them every time, a snapshot is loaded lazily on the
+//! first request after it became stale. A snapshot is stale when the
+//! configuration version of the database changed, i.e. after a write through
+//! this instance, or after [`CONFIG_CACHE_TTL_SECS`], to pick up writes of
+//! other instances sharing the database.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use crate::database::{
+    BroadcastRecord, Database, DeviceRecord, DndRecord, IssueReportRecord, MaintenanceRecord,
+};
+use crate::experiments::{Experiment, ExperimentState, Variant};
+use crate::render::layout::Layout;
+use crate::rooms::Room;
+
+/// Time after which the cached configuration is reloaded even without local writes
+pub const CONFIG_CACHE_TTL_SECS: u64 = 5;
+
+/// Configuration needed to serve displays
+///
+/// Device records are as of loading the snapshot, so their check-in fields
+/// such as `last_seen_at` are not current.
+#[derive(Debug, Default)]
+pub struct DisplayConfig {
+    devices: HashMap<String, DeviceRecord>,
+    /// Broadcasts that were active when the snapshot was loaded, newest first
+    broadcasts: Vec<BroadcastRecord>,
+    /// Open issue reports by room, newest first
+    open_issues: HashMap<String, Vec<IssueReportRecord>>,
+    /// Do-not-disturb marks of meetings that had not ended when the snapshot
+    /// was loaded, by room
+    dnd: HashMap<String, DndRecord>,
+    /// Layout experiments that were not rolled back, oldest first
+    experiments: Vec<Experiment>,
+    /// Maintenance in progress when the snapshot was loaded
+    maintenance: Option<MaintenanceRecord>,
+}
+
+impl DisplayConfig {
+    /// Load the configuration from the database
+    fn load(database: &Database) -> Result<Self> {
+        let devices = database
+            .list_devices()
+            .context("Failed to list devices")?
+            .into_iter()
+            .map(|device| (device.id.clone(), device))
+            .collect();
+        let broadcasts = database
+            .list_active_broadcasts()
+            .context("Failed to list active broadcasts")?;
+        let mut open_issues: HashMap<String, Vec<IssueReportRecord>> = HashMap::new();
+        for issue in database
+            .list_open_issue_reports(None)
+            .context("Failed to list issue reports")?
+        {
+            open_issues
+                .entry(issue.room_id.clone())
+                .or_default()
+                .push(issue);
+        }
+        let dnd = database
+            .list_active_dnd()
+            .context("Failed to list do-not-disturb marks")?
+            .into_iter()
+            .map(|mark| (mark.room_id.clone(), mark))
+            .collect();
+        let experiments = database
+            .list_experiments()
+            .context("Failed to list experiments")?
+            .into_iter()
+            .filter(|experiment| experiment.state != ExperimentState::RolledBack)
+            .collect();
+        let maintenance = database
+            .maintenance()
+            .context("Failed to get maintenance")?;
+        Ok(Self {
+            devices,
+            broadcasts,
+            open_issues,
+            dnd,
+            experiments,
+            maintenance,
+        })
+    }
+
+    /// A registered device
+    pub fn device(&self, device_id: &str) -> Option<&DeviceRecord> {
+        self.devices.get(device_id)
+    }
+
+    /// The maintenance in progress, if any
+    pub fn maintenance(&self) -> Option<&MaintenanceRecord> {
+        self.maintenance.as_ref()
+    }
+
+    /// The broadcast active at the given Unix timestamp, if any
+    pub fn active_broadcast(&self, now: i64) -> Option<&BroadcastRecord> {
+        self.broadcasts
+            .iter()
+            .find(|broadcast| broadcast.expires_at > now)
+    }
+
+    /// The do-not-disturb mark of a room's meeting at the given Unix timestamp, if any
+    pub fn active_dnd(&self, room_id: &str, now: i64) -> Option<&DndRecord> {
+        self.dnd.get(room_id).filter(|mark| mark.expires_at > now)
+    }
+
+    /// Layout of a device of the given room, with the experiment and variant
+    /// that decided it, see [`Room::layout_with_experiments`]
+    pub fn device_layout(
+        &self,
+        room: &Room,
+        device_id: &str,
+    ) -> (Layout, Option<(&Experiment, Variant)>) {
+        room.layout_with_experiments(&self.experiments, device_id)
+    }
+
+    /// The most recent open issue report of a room
+    pub fn latest_issue(&self, room_id: &str) -> Option<&IssueReportRecord> {
+        self.open_issues
+            .get(room_id)
+            .and_then(|issues| issues.first())
+    }
+}
+
+struct Snapshot {
+    version: u64,
+    loaded_at: Instant,
+    config: Arc<DisplayConfig>,
+}
+
+/// Lazily refreshed [`DisplayConfig`]
+pub struct ConfigCache {
+    database: Arc<Database>,
+    ttl: Duration,
+    snapshot: RwLock<Option<Snapshot>>,
+}
+
+impl ConfigCache {
+    /// Create a cache reloading the configuration after `ttl` at the latest
+    pub fn new(database: Arc<Database>, ttl: Duration) -> Self {
+        Self {
+            database,
+            ttl,
+            snapshot: RwLock::new(None),
+        }
+    }
+
+    /// The current configuration, loaded from the database on the blocking
+    /// threads if the cached one is stale
+    pub async fn get(self: &Arc<Self>) -> Result<Arc<DisplayConfig>> {
+        if let Some(config) = self.fresh(self.database.config_version())? {
+            return Ok(config);
+        }
+        let cache = self.clone();
+        tokio::task::spawn_blocking(move || cache.get_blocking())
+            .await
+            .context("Configuration task failed")?
+    }
+
+    /// The current configuration, loaded from the database if the cached one is stale
+    pub fn get_blocking(&self) -> Result<Arc<DisplayConfig>> {
+        let version = self.database.config_version();
+        if let Some(config) = self.fresh(version)? {
+            return Ok(config);
+        }
+
+        let mut cached = self
+            .snapshot
+            .write()
+            .map_err(|e| anyhow::anyhow!("Failed to acquire lock on configuration cache: {}", e))?;
+        // Another request may have reloaded it in the meantime
+        if let Some(config) = self.is_fresh(&cached, version) {
+            return Ok(config);
+        }
+        let config = Arc::new(DisplayConfig::load(&self.database)?);
+        *cached = Some(Snapshot {
+            version,
+            loaded_at: Instant::now(),
+            config: config.clone(),
+        });
+        Ok(config)
+    }
+
+    /// The cached configuration, unless it is stale
+    fn fresh(&self, version: u64) -> Result<Option<Arc<DisplayConfig>>> {
+        let cached = self
+            .snapshot
+            .read()
+            .map_err(|e| anyhow::anyhow!("Failed to acquire lock on configuration cache: {}", e))?;
+        Ok(self.is_fresh(&cached, version))
+    }
+
+    fn is_fresh(&self, snapshot: &Option<Snapshot>, version: u64) -> Option<Arc<DisplayConfig>> {
+        snapshot.as_ref().and_then(|snapshot| {
+            (snapshot.version == version && snapshot.loaded_at.elapsed() < self.ttl)
+                .then(|| snapshot.config.clone())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::DeviceCheckIn;
+    use crate::health::CRITICAL_BATTERY_VOLTAGE;
+
+    #[test]
+    fn test_config_cache_invalidation() {
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        db.register_device("AA:BB:CC:DD:EE:FF").unwrap();
+
+        let cache = ConfigCache::new(db.clone(), Duration::from_secs(3600));
+        let config = cache.get_blocking().unwrap();
+        assert!(config.device("AA:BB:CC:DD:EE:FF").is_some());
+        assert!(config.active_broadcast(0).is_none());
+        // Unchanged configuration is not reloaded
+        assert!(Arc::ptr_eq(&config, &cache.get_blocking().unwrap()));
+        // Check-ins are no configuration changes
+        db.record_device_check_in(
+            "AA:BB:CC:DD:EE:FF",
+            &DeviceCheckIn {
+                battery_voltage: Some(4.1),
+                ..Default::default()
+            },
+            CRITICAL_BATTERY_VOLTAGE,
+        )
+        .unwrap();
+        assert!(Arc::ptr_eq(&config, &cache.get_blocking().unwrap()));
+
+        // Writes invalidate the cache
+        let broadcast = db.create_broadcast("Fire drill", 600, "test").unwrap();
+        db.create_issue_report("room-a", "projector", "").unwrap();
+        let config = cache.get_blocking().unwrap();
+        assert_eq!(
+            config.active_broadcast(broadcast.created_at).unwrap().id,
+            broadcast.id
+        );
+        assert!(config.active_broadcast(broadcast.expires_at).is_none());
+        assert_eq!(config.latest_issue("room-a").unwrap().category, "projector");
+        assert!(config.latest_issue("room-b").is_none());
+        assert!(config.maintenance().is_none());
+
+        db.start_maintenance(Some("Back at 8:00"), "test").unwrap();
+        let config = cache.get_blocking().unwrap();
+        assert_eq!(
+            config.maintenance().unwrap().message.as_deref(),
+            Some("Back at 8:00")
+        );
+        assert!(db.end_maintenance("test").unwrap());
+        assert!(!db.end_maintenance("test").unwrap());
+        assert!(cache.get_blocking().unwrap().maintenance().is_none());
+
+        let mark = db
+            .set_room_dnd("room-a", "Board meeting", i64::MAX, "test")
+            .unwrap();
+        let config = cache.get_blocking().unwrap();
+        assert_eq!(
+            config.active_dnd("room-a", mark.set_at).unwrap().meeting,
+            "Board meeting"
+        );
+        assert!(config.active_dnd("room-b", mark.set_at).is_none());
+        assert!(db.clear_room_dnd("room-a", "test").unwrap());
+        assert!(!db.clear_room_dnd("room-a", "test").unwrap());
+        assert!(
+            cache
+                .get_blocking()
+                .unwrap()
+                .active_dnd("room-a", mark.set_at)
+                .is_none()
+        );
+
+        // Writes of other instances are picked up after the TTL
+        let cache = ConfigCache::new(db.clone(), Duration::ZERO);
+        let config = cache.get_blocking().unwrap();
+        assert!(!Arc::ptr_eq(&config, &cache.get_blocking().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_config_cache_get() {
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        db.register_device("AA:BB:CC:DD:EE:FF").unwrap();
+
+        let cache = Arc::new(ConfigCache::new(db.clone(), Duration::from_secs(3600)));
+        let config = cache.get().await.unwrap();
+        assert!(config.device("AA:BB:CC:DD:EE:FF").is_some());
+        assert!(Arc::ptr_eq(&config, &cache.get().await.unwrap()));
+        assert!(Arc::ptr_eq(&config, &cache.get_blocking().unwrap()));
+    }
+}
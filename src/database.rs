@@ -1,77 +1,183 @@
 use std::{
     path::Path,
-    sync::{Arc, Mutex},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
 };
 
-use anyhow::{Context, Result};
-use log::info;
-use rusqlite::{Connection, params};
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+
+use crate::api::types::LogLevel;
+use crate::experiments::{Experiment, ExperimentState};
+use crate::health::{BATTERY_HISTORY_SECS, BATTERY_SAMPLES, HEALTH_WINDOW_SECS, battery_critical};
+use crate::render::layout::Layout;
+use crate::rooms::Room;
+use crate::utilization::DailyUtilization;
+
+mod migrations;
+mod pool;
+#[cfg(feature = "postgres")]
+mod postgres;
+mod sqlite;
+pub mod storage;
+
+pub use pool::PoolTimeout;
+use storage::{Storage, StorageConnection, params};
+pub use storage::{is_postgres_url, redact_url};
+
+/// Connections per database pool, unless configured otherwise
+pub const DEFAULT_POOL_SIZE: usize = 4;
 
 /// Database connection and operations wrapper
 pub struct Database {
-    conn: Mutex<Connection>,
+    storage: Box<dyn Storage>,
+    /// Incremented by every write to the configuration read when serving displays
+    config_version: AtomicU64,
 }
 
 impl Database {
-    /// Create a new database connection and initialize tables
-    pub fn new(db_path: &str) -> Result<Self> {
-        let conn = Connection::open(db_path)
-            .with_context(|| format!("Failed to open database at {}", db_path))?;
+    /// Open a database with the default pool size and migrate it to the
+    /// latest schema
+    ///
+    /// `url` is the path of a SQLite database or a `postgres://` URL.
+    pub fn new(url: &str) -> Result<Self> {
+        Self::with_pool_size(url, DEFAULT_POOL_SIZE)
+    }
+
+    /// Open a database with a pool of `pool_size` connections and migrate it
+    /// to the latest schema
+    pub fn with_pool_size(url: &str, pool_size: usize) -> Result<Self> {
+        let database = Self::connect(url, pool_size)?;
+
+        let version = database.schema_version()?;
+        if version > migrations::latest_version() {
+            bail!(
+                "Database schema version {} is newer than the supported version {}, upgrade the server",
+                version,
+                migrations::latest_version()
+            );
+        }
+        let mut conn = database.conn()?;
+        migrations::migrate(
+            &mut *conn,
+            migrations::MIGRATIONS,
+            migrations::latest_version(),
+        )?;
 
-        // Create devices table if it doesn't exist
+        // Provisioned devices set up before keys were stored per device
         conn.execute(
-            "CREATE TABLE IF NOT EXISTS devices (
-                id TEXT PRIMARY KEY,
-                registered_at INTEGER NOT NULL
-            )",
-            [],
+            "UPDATE devices SET api_key = (
+                SELECT api_key FROM provisioned_devices
+                WHERE device_id = devices.id COLLATE NOCASE
+             )
+             WHERE api_key IS NULL AND api_key_revoked_at IS NULL",
+            &[],
         )
-        .context("Failed to create devices table")?;
+        .context("Failed to migrate API keys of provisioned devices")?;
 
+        // Created outside of the migrations, as legacy IDs may clash until
+        // an admin removes one of the devices
+        if let Err(e) = conn.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS devices_id_nocase ON devices (id COLLATE NOCASE)",
+            &[],
+        ) {
+            warn!(
+                "Failed to create unique device ID index, IDs of legacy devices clash: {}",
+                e
+            );
+        }
+
+        drop(conn);
+        Ok(database)
+    }
+
+    /// Open a database without migrating it
+    ///
+    /// Used to check a database, queries fail unless it is at the
+    /// [latest schema version](Self::latest_schema_version).
+    pub fn connect(url: &str, pool_size: usize) -> Result<Self> {
         Ok(Self {
-            conn: Mutex::new(conn),
+            storage: storage::open(url, pool_size)?,
+            config_version: AtomicU64::new(0),
         })
     }
 
+    /// Schema version of the database, 0 for a new database
+    pub fn schema_version(&self) -> Result<u32> {
+        // In a transaction, as instances starting at the same time would
+        // race to create the table of the schema version
+        let mut conn = self.conn()?;
+        let mut tx = conn.transaction()?;
+        let version = migrations::schema_version(&mut tx)?;
+        tx.commit()?;
+        Ok(version)
+    }
+
+    /// Schema version of the current code
+    pub fn latest_schema_version() -> u32 {
+        migrations::latest_version()
+    }
+
+    /// Take a connection from the pool
+    fn conn(&self) -> Result<Box<dyn StorageConnection + '_>> {
+        self.storage.connection()
+    }
+
+    /// Run blocking database work on Tokio's blocking threads
+    ///
+    /// Async handlers use this, so that waiting for a connection or the disk
+    /// doesn't stall the other requests on the runtime.
+    pub async fn run_blocking<T, E, F>(self: &Arc<Self>, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&Database) -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: From<anyhow::Error> + Send + 'static,
+    {
+        let database = self.clone();
+        tokio::task::spawn_blocking(move || f(&database))
+            .await
+            .context("Database task failed")?
+    }
+
+    /// Version of the display configuration, i.e. devices, broadcasts, issue
+    /// reports and rooms
+    ///
+    /// It changes with every write through this connection, so that cached
+    /// configuration can be invalidated. Writes by other instances sharing the
+    /// database are not noticed.
+    pub fn config_version(&self) -> u64 {
+        self.config_version.load(Ordering::Acquire)
+    }
+
+    fn bump_config_version(&self) {
+        self.config_version.fetch_add(1, Ordering::AcqRel);
+    }
+
     /// Register a new device or update an existing one
     pub fn register_device(&self, device_id: &str) -> Result<()> {
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|e| anyhow::anyhow!("Failed to acquire lock on database connection: {}", e))?;
+        let mut conn = self.conn()?;
 
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .context("Failed to get current timestamp")?
-            .as_secs() as i64;
+        let now = unix_now()?;
 
         conn.execute(
-            "INSERT OR REPLACE INTO devices (id, registered_at) VALUES (?1, ?2)",
+            "INSERT INTO devices (id, registered_at) VALUES (?1, ?2)
+             ON CONFLICT (id) DO UPDATE SET registered_at = excluded.registered_at",
             params![device_id, now],
         )
         .with_context(|| format!("Failed to register device {}", device_id))?;
+        self.bump_config_version();
 
         Ok(())
     }
 
     /// Check if a device exists in the database
     pub fn device_exists(&self, device_id: &str) -> Result<bool> {
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|e| anyhow::anyhow!("Failed to acquire lock on database connection: {}", e))?;
+        let mut conn = self.conn()?;
 
-        let mut stmt = conn
-            .prepare("SELECT 1 FROM devices WHERE id = ?1")
-            .with_context(|| {
-                format!(
-                    "Failed to prepare statement to check device existence: {}",
-                    device_id
-                )
-            })?;
-
-        let exists = stmt
-            .exists(params![device_id])
+        let exists = conn
+            .exists("SELECT 1 FROM devices WHERE id = ?1", params![device_id])
             .with_context(|| format!("Failed to check if device exists: {}", device_id))?;
 
         Ok(exists)
@@ -79,30 +185,2143 @@ impl Database {
 
     /// Retrieves a device by its ID
     pub fn get_device(&self, device_id: &str) -> Result<Option<DeviceRecord>> {
-        let conn = self
-            .conn
-            .lock()
-            .map_err(|e| anyhow::anyhow!("Failed to acquire lock on database connection: {}", e))?;
-
-        let mut stmt = conn
-            .prepare("SELECT id, registered_at FROM devices WHERE id = ?1")
-            .with_context(|| format!("Failed to prepare statement to get device: {}", device_id))?;
+        let mut conn = self.conn()?;
 
-        let mut rows = stmt
-            .query(params![device_id])
+        let rows = conn
+            .query(
+                "SELECT id, registered_at, room_id, model, last_payload_format, last_payload_bytes,
+                 image_delivery, last_seen_at, battery_voltage, api_key, api_key_revoked_at,
+                 battery_critical, retired_at, replaced_by, name, firmware_version, image_format,
+                 display_alert_at, rssi, next_check_in_at, check_in_interval, offline_alert_at
+                 FROM devices WHERE id = ?1",
+                params![device_id],
+            )
             .with_context(|| format!("Failed to execute query for device: {}", device_id))?;
 
-        if let Some(row) = rows.next().context("Failed to read database row")? {
+        if let Some(row) = rows.first() {
             Ok(Some(DeviceRecord {
                 id: row.get(0).context("Failed to get ID field from row")?,
                 registered_at: row
                     .get(1)
                     .context("Failed to get registered_at field from row")?,
+                room_id: row.get(2).context("Failed to get room_id field from row")?,
+                model: row.get(3).context("Failed to get model field from row")?,
+                last_payload_format: row
+                    .get(4)
+                    .context("Failed to get last_payload_format field from row")?,
+                last_payload_bytes: row
+                    .get(5)
+                    .context("Failed to get last_payload_bytes field from row")?,
+                image_delivery: row
+                    .get(6)
+                    .context("Failed to get image_delivery field from row")?,
+                last_seen_at: row
+                    .get(7)
+                    .context("Failed to get last_seen_at field from row")?,
+                battery_voltage: row
+                    .get(8)
+                    .context("Failed to get battery_voltage field from row")?,
+                api_key: row.get(9).context("Failed to get api_key field from row")?,
+                api_key_revoked_at: row
+                    .get(10)
+                    .context("Failed to get api_key_revoked_at field from row")?,
+                battery_critical: row
+                    .get(11)
+                    .context("Failed to get battery_critical field from row")?,
+                retired_at: row
+                    .get(12)
+                    .context("Failed to get retired_at field from row")?,
+                replaced_by: row
+                    .get(13)
+                    .context("Failed to get replaced_by field from row")?,
+                name: row.get(14).context("Failed to get name field from row")?,
+                firmware_version: row
+                    .get(15)
+                    .context("Failed to get firmware_version field from row")?,
+                image_format: row
+                    .get(16)
+                    .context("Failed to get image_format field from row")?,
+                display_alert_at: row
+                    .get(17)
+                    .context("Failed to get display_alert_at field from row")?,
+                rssi: row.get(18).context("Failed to get rssi field from row")?,
+                next_check_in_at: row
+                    .get(19)
+                    .context("Failed to get next_check_in_at field from row")?,
+                check_in_interval: row
+                    .get(20)
+                    .context("Failed to get check_in_interval field from row")?,
+                offline_alert_at: row
+                    .get(21)
+                    .context("Failed to get offline_alert_at field from row")?,
             }))
         } else {
             Ok(None)
         }
     }
+
+    /// Lists all registered devices, ordered by ID
+    pub fn list_devices(&self) -> Result<Vec<DeviceRecord>> {
+        let mut conn = self.conn()?;
+
+        let devices = conn
+            .query_map(
+                "SELECT id, registered_at, room_id, model, last_payload_format, last_payload_bytes,
+                 image_delivery, last_seen_at, battery_voltage, api_key, api_key_revoked_at,
+                 battery_critical, retired_at, replaced_by, name, firmware_version, image_format,
+                 display_alert_at, rssi, next_check_in_at, check_in_interval, offline_alert_at
+                 FROM devices ORDER BY id",
+                &[],
+                |row| {
+                    Ok(DeviceRecord {
+                        id: row.get(0)?,
+                        registered_at: row.get(1)?,
+                        room_id: row.get(2)?,
+                        model: row.get(3)?,
+                        last_payload_format: row.get(4)?,
+                        last_payload_bytes: row.get(5)?,
+                        image_delivery: row.get(6)?,
+                        last_seen_at: row.get(7)?,
+                        battery_voltage: row.get(8)?,
+                        api_key: row.get(9)?,
+                        api_key_revoked_at: row.get(10)?,
+                        battery_critical: row.get(11)?,
+                        retired_at: row.get(12)?,
+                        replaced_by: row.get(13)?,
+                        name: row.get(14)?,
+                        firmware_version: row.get(15)?,
+                        image_format: row.get(16)?,
+                        display_alert_at: row.get(17)?,
+                        rssi: row.get(18)?,
+                        next_check_in_at: row.get(19)?,
+                        check_in_interval: row.get(20)?,
+                        offline_alert_at: row.get(21)?,
+                    })
+                },
+            )
+            .context("Failed to execute query to list devices")?;
+
+        Ok(devices)
+    }
+
+    /// Records the model of a device and the image last served to it
+    pub fn record_device_payload(
+        &self,
+        device_id: &str,
+        model: Option<&str>,
+        format: &str,
+        bytes: usize,
+    ) -> Result<()> {
+        let mut conn = self.conn()?;
+
+        conn.execute(
+            "UPDATE devices SET model = COALESCE(?2, model), last_payload_format = ?3,
+             last_payload_bytes = ?4 WHERE id = ?1",
+            params![device_id, model, format, bytes as i64],
+        )
+        .with_context(|| format!("Failed to record payload of device {}", device_id))?;
+
+        Ok(())
+    }
+
+    /// Records when a device is expected to check in next, and the interval
+    /// of its check-ins from then on
+    pub fn record_device_schedule(
+        &self,
+        device_id: &str,
+        next_check_in_at: i64,
+        interval_seconds: u32,
+    ) -> Result<()> {
+        let mut conn = self.conn()?;
+
+        conn.execute(
+            "UPDATE devices SET next_check_in_at = ?2, check_in_interval = ?3 WHERE id = ?1",
+            params![device_id, next_check_in_at, interval_seconds],
+        )
+        .with_context(|| format!("Failed to record schedule of device {}", device_id))?;
+
+        Ok(())
+    }
+
+    /// Records a check-in of a device, with the state it reported
+    ///
+    /// Values the device did not report are left as they were. A device
+    /// reported offline is back online. Voltages are
+    /// kept for [`BATTERY_HISTORY_SECS`] to derive the battery state from.
+    /// Returns whether the battery is below the warning voltage.
+    pub fn record_device_check_in(
+        &self,
+        device_id: &str,
+        check_in: &DeviceCheckIn,
+        warning_voltage: f64,
+    ) -> Result<bool> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.transaction()?;
+
+        let now = unix_now()?;
+        tx.execute(
+            "UPDATE devices SET last_seen_at = ?2, offline_alert_at = NULL,
+             battery_voltage = COALESCE(?3, battery_voltage), rssi = COALESCE(?4, rssi),
+             firmware_version = COALESCE(?5, firmware_version) WHERE id = ?1",
+            params![
+                device_id,
+                now,
+                check_in.battery_voltage,
+                check_in.rssi,
+                check_in.firmware_version
+            ],
+        )
+        .with_context(|| format!("Failed to record check-in of device {}", device_id))?;
+        let was_critical: bool = tx
+            .query_row(
+                "SELECT battery_critical FROM devices WHERE id = ?1",
+                params![device_id],
+                |row| row.get(0),
+            )
+            .with_context(|| format!("Failed to get battery state of device {}", device_id))?
+            .unwrap_or(false);
+        let Some(voltage) = check_in.battery_voltage else {
+            tx.commit().context("Failed to commit check-in")?;
+            return Ok(was_critical);
+        };
+
+        tx.execute(
+            "INSERT INTO battery_readings (device_id, voltage, recorded_at) VALUES (?1, ?2, ?3)",
+            params![device_id, voltage, now],
+        )
+        .with_context(|| format!("Failed to record battery voltage of device {}", device_id))?;
+        tx.execute(
+            "DELETE FROM battery_readings WHERE device_id = ?1 AND recorded_at < ?2",
+            params![device_id, now - BATTERY_HISTORY_SECS],
+        )
+        .context("Failed to delete old battery readings")?;
+        let mut readings: Vec<f64> = tx
+            .query_map(
+                "SELECT voltage FROM battery_readings WHERE device_id = ?1
+                 ORDER BY recorded_at DESC, rowid DESC LIMIT ?2",
+                params![device_id, BATTERY_SAMPLES as i64],
+                |row| row.get(0),
+            )
+            .context("Failed to list battery readings")?;
+        readings.reverse();
+
+        let critical = battery_critical(was_critical, &readings, warning_voltage);
+        if critical != was_critical {
+            tx.execute(
+                "UPDATE devices SET battery_critical = ?2 WHERE id = ?1",
+                params![device_id, critical],
+            )
+            .with_context(|| format!("Failed to update battery state of device {}", device_id))?;
+        }
+        tx.commit().context("Failed to commit check-in")?;
+
+        Ok(critical)
+    }
+
+    /// Sets or (with `None`) resets the image delivery mode of a device
+    ///
+    /// Returns false if there is no such device. The action is recorded in the
+    /// audit log.
+    pub fn set_device_image_delivery(
+        &self,
+        device_id: &str,
+        image_delivery: Option<&str>,
+        changed_by: &str,
+    ) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        let updated = tx
+            .execute(
+                "UPDATE devices SET image_delivery = ?2 WHERE id = ?1",
+                params![device_id, image_delivery],
+            )
+            .with_context(|| format!("Failed to set image delivery of device {}", device_id))?;
+        if updated > 0 {
+            insert_audit_entry(
+                &mut tx,
+                now,
+                changed_by,
+                "device.image_delivery",
+                &format!(
+                    "device={} image_delivery={}",
+                    device_id,
+                    image_delivery.unwrap_or("default")
+                ),
+            )?;
+        }
+        tx.commit()
+            .context("Failed to commit image delivery change")?;
+        self.bump_config_version();
+
+        Ok(updated > 0)
+    }
+
+    /// Sets or (with `None`) resets the image format of a device
+    ///
+    /// Returns false if there is no such device. The action is recorded in the
+    /// audit log.
+    pub fn set_device_image_format(
+        &self,
+        device_id: &str,
+        image_format: Option<&str>,
+        changed_by: &str,
+    ) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        let updated = tx
+            .execute(
+                "UPDATE devices SET image_format = ?2 WHERE id = ?1",
+                params![device_id, image_format],
+            )
+            .with_context(|| format!("Failed to set image format of device {}", device_id))?;
+        if updated > 0 {
+            insert_audit_entry(
+                &mut tx,
+                now,
+                changed_by,
+                "device.image_format",
+                &format!(
+                    "device={} image_format={}",
+                    device_id,
+                    image_format.unwrap_or("default")
+                ),
+            )?;
+        }
+        tx.commit()
+            .context("Failed to commit image format change")?;
+        self.bump_config_version();
+
+        Ok(updated > 0)
+    }
+
+    /// Sets the API key of a device that has neither a key nor a revoked one
+    ///
+    /// Returns false if the device has a key already or its key was revoked,
+    /// so that a key is never handed out twice.
+    pub fn set_device_api_key(&self, device_id: &str, api_key: &str) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let updated = conn
+            .execute(
+                "UPDATE devices SET api_key = ?2
+                 WHERE id = ?1 AND api_key IS NULL AND api_key_revoked_at IS NULL",
+                params![device_id, api_key],
+            )
+            .with_context(|| format!("Failed to set API key of device {}", device_id))?;
+        self.bump_config_version();
+
+        Ok(updated > 0)
+    }
+
+    /// Revokes the API key of a device, returning whether the device exists
+    ///
+    /// The device is rejected, on setup as well, until its key is reset. The
+    /// action is recorded in the audit log.
+    pub fn revoke_device_api_key(&self, device_id: &str, revoked_by: &str) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        let updated = tx
+            .execute(
+                "UPDATE devices SET api_key = NULL, api_key_revoked_at = ?2 WHERE id = ?1",
+                params![device_id, now],
+            )
+            .with_context(|| format!("Failed to revoke API key of device {}", device_id))?;
+        if updated > 0 {
+            insert_audit_entry(
+                &mut tx,
+                now,
+                revoked_by,
+                "device.api_key.revoke",
+                &format!("device={}", device_id),
+            )?;
+        }
+        tx.commit().context("Failed to commit API key revocation")?;
+        self.bump_config_version();
+
+        Ok(updated > 0)
+    }
+
+    /// Drops the current or revoked API key of a device, so that it receives
+    /// a new key on its next setup
+    ///
+    /// Returns false if there is no such device. The action is recorded in the
+    /// audit log.
+    pub fn reset_device_api_key(&self, device_id: &str, reset_by: &str) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        let updated = tx
+            .execute(
+                "UPDATE devices SET api_key = NULL, api_key_revoked_at = NULL
+                 WHERE id = ?1 AND retired_at IS NULL",
+                params![device_id],
+            )
+            .with_context(|| format!("Failed to reset API key of device {}", device_id))?;
+        if updated > 0 {
+            insert_audit_entry(
+                &mut tx,
+                now,
+                reset_by,
+                "device.api_key.reset",
+                &format!("device={}", device_id),
+            )?;
+        }
+        tx.commit().context("Failed to commit API key reset")?;
+        self.bump_config_version();
+
+        Ok(updated > 0)
+    }
+
+    /// Sets or (with `None`) clears the name of a device
+    ///
+    /// Returns false if there is no such device. The action is recorded in the
+    /// audit log.
+    pub fn rename_device(
+        &self,
+        device_id: &str,
+        name: Option<&str>,
+        renamed_by: &str,
+    ) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        let updated = tx
+            .execute(
+                "UPDATE devices SET name = ?2 WHERE id = ?1",
+                params![device_id, name],
+            )
+            .with_context(|| format!("Failed to rename device {}", device_id))?;
+        if updated > 0 {
+            insert_audit_entry(
+                &mut tx,
+                now,
+                renamed_by,
+                "device.rename",
+                &format!("device={} name={}", device_id, name.unwrap_or("-")),
+            )?;
+        }
+        tx.commit().context("Failed to commit device rename")?;
+        self.bump_config_version();
+
+        Ok(updated > 0)
+    }
+
+    /// Deletes a device with its room assignment, battery readings and logs
+    ///
+    /// A deleted device has to be set up again before it is served. Its
+    /// provisioning, if any, is kept, so that it can be set up with its
+    /// provisioned key. Returns false if there was no such device. The action
+    /// is recorded in the audit log.
+    pub fn delete_device(&self, device_id: &str, deleted_by: &str) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        // The ID as stored, device IDs match case-insensitively everywhere
+        let Some(device_id) = tx
+            .query_row(
+                "SELECT id FROM devices WHERE id = ?1 COLLATE NOCASE",
+                params![device_id],
+                |row| row.get::<String>(0),
+            )
+            .with_context(|| format!("Failed to get device {}", device_id))?
+        else {
+            // Nothing to delete, the transaction is rolled back
+            return Ok(false);
+        };
+        tx.execute(
+            "DELETE FROM room_devices WHERE device_id = ?1 COLLATE NOCASE",
+            params![device_id],
+        )
+        .with_context(|| format!("Failed to delete room assignment of device {}", device_id))?;
+        tx.execute(
+            "DELETE FROM battery_readings WHERE device_id = ?1 COLLATE NOCASE",
+            params![device_id],
+        )
+        .with_context(|| format!("Failed to delete battery readings of device {}", device_id))?;
+        tx.execute(
+            "DELETE FROM device_logs WHERE device_id = ?1 COLLATE NOCASE",
+            params![device_id],
+        )
+        .with_context(|| format!("Failed to delete logs of device {}", device_id))?;
+        tx.execute("DELETE FROM devices WHERE id = ?1", params![device_id])
+            .with_context(|| format!("Failed to delete device {}", device_id))?;
+        insert_audit_entry(
+            &mut tx,
+            now,
+            deleted_by,
+            "device.delete",
+            &format!("device={}", device_id),
+        )?;
+        tx.commit().context("Failed to commit device deletion")?;
+        self.bump_config_version();
+
+        Ok(true)
+    }
+
+    /// Transfers the room, settings and logs of a device to its replacement
+    ///
+    /// The replacement takes over the room assignment (`room_id`, the room the
+    /// old device is shown in) and the image delivery mode, and the old device
+    /// is retired: it loses its room and its API key is revoked. Returns false
+    /// if either device does not exist or was retired already. The action is
+    /// recorded in the audit log.
+    pub fn adopt_device(
+        &self,
+        old_id: &str,
+        new_id: &str,
+        room_id: Option<&str>,
+        adopted_by: &str,
+    ) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        let mut active = |id: &str| {
+            tx.query_row(
+                "SELECT image_delivery FROM devices WHERE id = ?1 AND retired_at IS NULL",
+                params![id],
+                |row| row.get::<Option<String>>(0),
+            )
+            .with_context(|| format!("Failed to get device {}", id))
+        };
+        let Some(image_delivery) = active(old_id)? else {
+            return Ok(false);
+        };
+        if active(new_id)?.is_none() {
+            return Ok(false);
+        }
+
+        tx.execute(
+            "UPDATE devices SET room_id = ?2, image_delivery = ?3 WHERE id = ?1",
+            params![new_id, room_id, image_delivery],
+        )
+        .with_context(|| format!("Failed to update device {}", new_id))?;
+        tx.execute(
+            "DELETE FROM room_devices WHERE device_id = ?1",
+            params![new_id],
+        )
+        .context("Failed to remove previous room assignment")?;
+        tx.execute(
+            "UPDATE room_devices SET device_id = ?2 WHERE device_id = ?1",
+            params![old_id, new_id],
+        )
+        .context("Failed to transfer room assignment")?;
+        tx.execute(
+            "UPDATE device_logs SET device_id = ?2 WHERE device_id = ?1 COLLATE NOCASE",
+            params![old_id, new_id],
+        )
+        .context("Failed to transfer device logs")?;
+        tx.execute(
+            "UPDATE devices SET retired_at = ?2, replaced_by = ?3, room_id = NULL,
+             api_key = NULL, api_key_revoked_at = ?2 WHERE id = ?1",
+            params![old_id, now, new_id],
+        )
+        .with_context(|| format!("Failed to retire device {}", old_id))?;
+        insert_audit_entry(
+            &mut tx,
+            now,
+            adopted_by,
+            "device.adopt",
+            &format!(
+                "old={} new={} room={}",
+                old_id,
+                new_id,
+                room_id.unwrap_or("-")
+            ),
+        )?;
+        tx.commit().context("Failed to commit device adoption")?;
+        self.bump_config_version();
+
+        Ok(true)
+    }
+
+    /// Creates a broadcast shown on all devices until it expires or is cleared
+    ///
+    /// Any previously active broadcast is superseded. The action is recorded in
+    /// the audit log.
+    pub fn create_broadcast(
+        &self,
+        message: &str,
+        duration_secs: i64,
+        triggered_by: &str,
+    ) -> Result<BroadcastRecord> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let expires_at = now.saturating_add(duration_secs);
+
+        let mut tx = conn.transaction()?;
+        tx.execute(
+            "UPDATE broadcasts SET cleared_at = ?1 WHERE cleared_at IS NULL",
+            params![now],
+        )
+        .context("Failed to clear previous broadcasts")?;
+        let id: i64 = tx
+            .query_row(
+                "INSERT INTO broadcasts (message, created_at, expires_at, triggered_by)
+                 VALUES (?1, ?2, ?3, ?4) RETURNING id",
+                params![message, now, expires_at, triggered_by],
+                |row| row.get(0),
+            )
+            .context("Failed to insert broadcast")?
+            .context("No ID of inserted broadcast")?;
+        insert_audit_entry(
+            &mut tx,
+            now,
+            triggered_by,
+            "broadcast.create",
+            &format!("id={} expires_at={} message={}", id, expires_at, message),
+        )?;
+        tx.commit().context("Failed to commit broadcast")?;
+        self.bump_config_version();
+
+        Ok(BroadcastRecord {
+            id,
+            message: message.to_string(),
+            created_at: now,
+            expires_at,
+            triggered_by: triggered_by.to_string(),
+        })
+    }
+
+    /// Returns the currently active broadcast, if any
+    pub fn active_broadcast(&self) -> Result<Option<BroadcastRecord>> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let rows = conn
+            .query(
+                "SELECT id, message, created_at, expires_at, triggered_by FROM broadcasts
+                 WHERE cleared_at IS NULL AND expires_at > ?1
+                 ORDER BY id DESC LIMIT 1",
+                params![now],
+            )
+            .context("Failed to execute query for active broadcast")?;
+
+        if let Some(row) = rows.first() {
+            Ok(Some(BroadcastRecord {
+                id: row.get(0).context("Failed to get ID field from row")?,
+                message: row.get(1).context("Failed to get message field from row")?,
+                created_at: row
+                    .get(2)
+                    .context("Failed to get created_at field from row")?,
+                expires_at: row
+                    .get(3)
+                    .context("Failed to get expires_at field from row")?,
+                triggered_by: row
+                    .get(4)
+                    .context("Failed to get triggered_by field from row")?,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Lists the broadcasts that are not cleared or expired, newest first
+    ///
+    /// The first one is the active broadcast; the others become active when
+    /// it expires.
+    pub fn list_active_broadcasts(&self) -> Result<Vec<BroadcastRecord>> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let broadcasts = conn
+            .query_map(
+                "SELECT id, message, created_at, expires_at, triggered_by FROM broadcasts
+                 WHERE cleared_at IS NULL AND expires_at > ?1
+                 ORDER BY id DESC",
+                params![now],
+                |row| {
+                    Ok(BroadcastRecord {
+                        id: row.get(0)?,
+                        message: row.get(1)?,
+                        created_at: row.get(2)?,
+                        expires_at: row.get(3)?,
+                        triggered_by: row.get(4)?,
+                    })
+                },
+            )
+            .context("Failed to execute query to list active broadcasts")?;
+
+        Ok(broadcasts)
+    }
+
+    /// Clears the active broadcast, returning whether there was one
+    ///
+    /// The action is recorded in the audit log.
+    pub fn clear_broadcast(&self, cleared_by: &str) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        let cleared = tx
+            .execute(
+                "UPDATE broadcasts SET cleared_at = ?1
+                 WHERE cleared_at IS NULL AND expires_at > ?1",
+                params![now],
+            )
+            .context("Failed to clear broadcasts")?;
+        insert_audit_entry(
+            &mut tx,
+            now,
+            cleared_by,
+            "broadcast.clear",
+            &format!("cleared={}", cleared),
+        )?;
+        tx.commit().context("Failed to commit broadcast clearing")?;
+        self.bump_config_version();
+
+        Ok(cleared > 0)
+    }
+
+    /// Puts the server into maintenance mode until it is cleared
+    ///
+    /// Replaces the message of a maintenance in progress, keeping its start.
+    /// The action is recorded in the audit log.
+    pub fn start_maintenance(
+        &self,
+        message: Option<&str>,
+        started_by: &str,
+    ) -> Result<MaintenanceRecord> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO maintenance (id, message, started_at, started_by) VALUES (1, ?1, ?2, ?3)
+             ON CONFLICT (id) DO UPDATE SET message = ?1, started_by = ?3",
+            params![message, now, started_by],
+        )
+        .context("Failed to start maintenance")?;
+        let started_at: i64 = tx
+            .query_row(
+                "SELECT started_at FROM maintenance WHERE id = 1",
+                &[],
+                |row| row.get(0),
+            )
+            .context("Failed to get start of maintenance")?
+            .context("Maintenance not found")?;
+        insert_audit_entry(
+            &mut tx,
+            now,
+            started_by,
+            "maintenance.start",
+            &format!("message={}", message.unwrap_or_default()),
+        )?;
+        tx.commit().context("Failed to commit maintenance")?;
+        self.bump_config_version();
+
+        Ok(MaintenanceRecord {
+            message: message.map(str::to_string),
+            started_at,
+            started_by: started_by.to_string(),
+        })
+    }
+
+    /// Returns the maintenance in progress, if any
+    pub fn maintenance(&self) -> Result<Option<MaintenanceRecord>> {
+        let mut conn = self.conn()?;
+
+        conn.query_row(
+            "SELECT message, started_at, started_by FROM maintenance WHERE id = 1",
+            &[],
+            |row| {
+                Ok(MaintenanceRecord {
+                    message: row.get(0)?,
+                    started_at: row.get(1)?,
+                    started_by: row.get(2)?,
+                })
+            },
+        )
+        .context("Failed to get maintenance")
+    }
+
+    /// Ends maintenance mode, returning false if the server was not in it
+    pub fn end_maintenance(&self, ended_by: &str) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        let ended = tx
+            .execute("DELETE FROM maintenance", &[])
+            .context("Failed to end maintenance")?;
+        if ended == 0 {
+            return Ok(false);
+        }
+        insert_audit_entry(&mut tx, now, ended_by, "maintenance.end", "")?;
+        tx.commit().context("Failed to commit end of maintenance")?;
+        self.bump_config_version();
+
+        Ok(true)
+    }
+
+    /// Marks the current meeting of a room as do-not-disturb until it ends
+    ///
+    /// Replaces an earlier mark of the room. The action is recorded in the
+    /// audit log.
+    pub fn set_room_dnd(
+        &self,
+        room_id: &str,
+        meeting: &str,
+        expires_at: i64,
+        set_by: &str,
+    ) -> Result<DndRecord> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO room_dnd (room_id, meeting, set_at, expires_at, set_by)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (room_id) DO UPDATE SET meeting = excluded.meeting,
+                set_at = excluded.set_at, expires_at = excluded.expires_at,
+                set_by = excluded.set_by",
+            params![room_id, meeting, now, expires_at, set_by],
+        )
+        .with_context(|| format!("Failed to set do-not-disturb of room {}", room_id))?;
+        insert_audit_entry(
+            &mut tx,
+            now,
+            set_by,
+            "room.dnd_set",
+            &format!(
+                "room={} expires_at={} meeting={}",
+                room_id, expires_at, meeting
+            ),
+        )?;
+        tx.commit().context("Failed to commit do-not-disturb")?;
+        self.bump_config_version();
+
+        Ok(DndRecord {
+            room_id: room_id.to_string(),
+            meeting: meeting.to_string(),
+            set_at: now,
+            expires_at,
+            set_by: set_by.to_string(),
+        })
+    }
+
+    /// Lists the do-not-disturb marks of meetings that have not ended yet
+    pub fn list_active_dnd(&self) -> Result<Vec<DndRecord>> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let marks = conn
+            .query_map(
+                "SELECT room_id, meeting, set_at, expires_at, set_by FROM room_dnd
+                 WHERE expires_at > ?1 ORDER BY room_id",
+                params![now],
+                |row| {
+                    Ok(DndRecord {
+                        room_id: row.get(0)?,
+                        meeting: row.get(1)?,
+                        set_at: row.get(2)?,
+                        expires_at: row.get(3)?,
+                        set_by: row.get(4)?,
+                    })
+                },
+            )
+            .context("Failed to execute query to list do-not-disturb marks")?;
+
+        Ok(marks)
+    }
+
+    /// Clears the do-not-disturb mark of a room, returning whether it was active
+    ///
+    /// The action is recorded in the audit log.
+    pub fn clear_room_dnd(&self, room_id: &str, cleared_by: &str) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        let active = tx
+            .execute(
+                "DELETE FROM room_dnd WHERE room_id = ?1 AND expires_at > ?2",
+                params![room_id, now],
+            )
+            .with_context(|| format!("Failed to clear do-not-disturb of room {}", room_id))?;
+        // Expired marks are of no use anymore
+        tx.execute("DELETE FROM room_dnd WHERE room_id = ?1", params![room_id])
+            .with_context(|| format!("Failed to clear do-not-disturb of room {}", room_id))?;
+        if active > 0 {
+            insert_audit_entry(
+                &mut tx,
+                now,
+                cleared_by,
+                "room.dnd_clear",
+                &format!("room={}", room_id),
+            )?;
+        }
+        tx.commit()
+            .context("Failed to commit do-not-disturb clearing")?;
+        self.bump_config_version();
+
+        Ok(active > 0)
+    }
+
+    /// Records an issue reported for a room
+    pub fn create_issue_report(
+        &self,
+        room_id: &str,
+        category: &str,
+        description: &str,
+    ) -> Result<IssueReportRecord> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let id = conn
+            .query_row(
+                "INSERT INTO issue_reports (room_id, category, description, created_at)
+                 VALUES (?1, ?2, ?3, ?4) RETURNING id",
+                params![room_id, category, description, now],
+                |row| row.get(0),
+            )
+            .with_context(|| format!("Failed to record issue report for room {}", room_id))?
+            .context("No ID of recorded issue report")?;
+        self.bump_config_version();
+
+        Ok(IssueReportRecord {
+            id,
+            room_id: room_id.to_string(),
+            category: category.to_string(),
+            description: description.to_string(),
+            created_at: now,
+        })
+    }
+
+    /// Lists unresolved issue reports, newest first, optionally for one room only
+    pub fn list_open_issue_reports(&self, room_id: Option<&str>) -> Result<Vec<IssueReportRecord>> {
+        let mut conn = self.conn()?;
+
+        let reports = conn
+            .query_map(
+                "SELECT id, room_id, category, description, created_at FROM issue_reports
+                 WHERE resolved_at IS NULL AND (room_id = ?1 OR ?1 IS NULL)
+                 ORDER BY id DESC",
+                params![room_id],
+                |row| {
+                    Ok(IssueReportRecord {
+                        id: row.get(0)?,
+                        room_id: row.get(1)?,
+                        category: row.get(2)?,
+                        description: row.get(3)?,
+                        created_at: row.get(4)?,
+                    })
+                },
+            )
+            .context("Failed to execute query to list issue reports")?;
+
+        Ok(reports)
+    }
+
+    /// Marks an issue report as resolved, returning whether it was open
+    pub fn resolve_issue_report(&self, id: i64) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let updated = conn
+            .execute(
+                "UPDATE issue_reports SET resolved_at = ?1 WHERE id = ?2 AND resolved_at IS NULL",
+                params![now, id],
+            )
+            .with_context(|| format!("Failed to resolve issue report {}", id))?;
+        self.bump_config_version();
+
+        Ok(updated > 0)
+    }
+
+    /// Returns cached raw calendar data for a URL, if any
+    pub fn cached_calendar(&self, url: &str) -> Result<Option<CachedCalendarRecord>> {
+        let mut conn = self.conn()?;
+
+        let rows = conn
+            .query(
+                "SELECT data, fetched_at FROM calendar_cache WHERE url = ?1",
+                params![url],
+            )
+            .context("Failed to execute query for cached calendar")?;
+
+        if let Some(row) = rows.first() {
+            Ok(Some(CachedCalendarRecord {
+                data: row.get(0).context("Failed to get data field from row")?,
+                fetched_at: row
+                    .get(1)
+                    .context("Failed to get fetched_at field from row")?,
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Stores raw calendar data fetched from a URL
+    pub fn store_cached_calendar(&self, url: &str, data: &str) -> Result<()> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        conn.execute(
+            "INSERT INTO calendar_cache (url, data, fetched_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (url) DO UPDATE SET
+                data = excluded.data, fetched_at = excluded.fetched_at",
+            params![url, data, now],
+        )
+        .with_context(|| format!("Failed to cache calendar data for {}", url))?;
+
+        Ok(())
+    }
+
+    /// Drops the cached data of a calendar
+    pub fn clear_cached_calendar(&self, url: &str) -> Result<()> {
+        let mut conn = self.conn()?;
+
+        conn.execute("DELETE FROM calendar_cache WHERE url = ?1", params![url])
+            .with_context(|| format!("Failed to clear cached calendar data for {}", url))?;
+
+        Ok(())
+    }
+
+    /// Creates one-time claim codes
+    ///
+    /// Codes without a device can be claimed by any device presenting them
+    /// and expire after `expires_in_secs`. Codes bound to a device (from a
+    /// pre-provisioning list) are matched by device ID and do not expire. The
+    /// action is recorded in the audit log.
+    pub fn create_claim_codes(
+        &self,
+        codes: &[NewClaimCode],
+        expires_in_secs: Option<i64>,
+        created_by: &str,
+    ) -> Result<Vec<ClaimCodeRecord>> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let expires_at = expires_in_secs.map(|secs| now.saturating_add(secs));
+        let mut tx = conn.transaction()?;
+        let mut records = Vec::with_capacity(codes.len());
+        for code in codes {
+            // Pre-provisioning a device again replaces its unclaimed code
+            if let Some(device_id) = &code.device_id {
+                tx.execute(
+                    "DELETE FROM claim_codes
+                     WHERE device_id = ?1 COLLATE NOCASE AND claimed_at IS NULL",
+                    params![device_id],
+                )
+                .context("Failed to replace previous claim code")?;
+            }
+            tx.execute(
+                "INSERT INTO claim_codes (code, room_id, device_id, created_at, expires_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![code.code, code.room_id, code.device_id, now, expires_at],
+            )
+            .with_context(|| format!("Failed to create claim code for room {}", code.room_id))?;
+            records.push(ClaimCodeRecord {
+                code: code.code.clone(),
+                room_id: code.room_id.clone(),
+                device_id: code.device_id.clone(),
+                expires_at,
+            });
+        }
+        insert_audit_entry(
+            &mut tx,
+            now,
+            created_by,
+            "claim_code.create",
+            &format!("count={}", codes.len()),
+        )?;
+        tx.commit().context("Failed to commit claim codes")?;
+
+        Ok(records)
+    }
+
+    /// Lists claim codes that have not been claimed or expired yet
+    pub fn list_open_claim_codes(&self) -> Result<Vec<ClaimCodeRecord>> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let codes = conn
+            .query_map(
+                "SELECT code, room_id, device_id, expires_at FROM claim_codes
+                 WHERE claimed_at IS NULL AND (expires_at IS NULL OR expires_at > ?1)
+                 ORDER BY created_at, code",
+                params![now],
+                |row| {
+                    Ok(ClaimCodeRecord {
+                        code: row.get(0)?,
+                        room_id: row.get(1)?,
+                        device_id: row.get(2)?,
+                        expires_at: row.get(3)?,
+                    })
+                },
+            )
+            .context("Failed to execute query to list claim codes")?;
+
+        Ok(codes)
+    }
+
+    /// Claims a device with a claim code, binding it to the code's room
+    ///
+    /// Without a code, a code pre-provisioned for the device is used. Marking
+    /// the code as used and registering the device happen atomically, so a
+    /// code can only ever be claimed once. Returns the used code, or `None` if
+    /// no valid code matched.
+    pub fn claim_device(
+        &self,
+        device_id: &str,
+        code: Option<&str>,
+    ) -> Result<Option<ClaimCodeRecord>> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        let claim = {
+            let rows = tx
+                .query(
+                    "SELECT code, room_id, device_id, expires_at FROM claim_codes
+                     WHERE claimed_at IS NULL AND (expires_at IS NULL OR expires_at > ?3)
+                     AND ((code = ?1 AND ?1 IS NOT NULL
+                           AND (device_id IS NULL OR device_id = ?2 COLLATE NOCASE))
+                          OR (?1 IS NULL AND device_id = ?2 COLLATE NOCASE))
+                     LIMIT 1",
+                    params![code, device_id, now],
+                )
+                .context("Failed to execute query for claim code")?;
+            match rows.first() {
+                Some(row) => Some(ClaimCodeRecord {
+                    code: row.get(0).context("Failed to get code field from row")?,
+                    room_id: row.get(1).context("Failed to get room_id field from row")?,
+                    device_id: row
+                        .get(2)
+                        .context("Failed to get device_id field from row")?,
+                    expires_at: row
+                        .get(3)
+                        .context("Failed to get expires_at field from row")?,
+                }),
+                None => None,
+            }
+        };
+        let Some(claim) = claim else {
+            return Ok(None);
+        };
+
+        tx.execute(
+            "UPDATE claim_codes SET claimed_at = ?1, device_id = ?2 WHERE code = ?3",
+            params![now, device_id, claim.code],
+        )
+        .with_context(|| format!("Failed to mark claim code {} as used", claim.code))?;
+        tx.execute(
+            "INSERT INTO devices (id, registered_at, room_id) VALUES (?1, ?2, ?3)
+             ON CONFLICT (id) DO UPDATE SET
+                registered_at = excluded.registered_at, room_id = excluded.room_id",
+            params![device_id, now, claim.room_id],
+        )
+        .with_context(|| format!("Failed to register claimed device {}", device_id))?;
+        tx.commit().context("Failed to commit device claim")?;
+        self.bump_config_version();
+
+        Ok(Some(claim))
+    }
+
+    /// Provisions a batch of devices, each with a claim code and an API key
+    ///
+    /// Provisioning is idempotent: a device that was provisioned before
+    /// keeps its claim code and API key, only its room and metadata are
+    /// updated (the `claim_code` and `api_key` of the entry are ignored). A
+    /// device that already claimed its code is moved to the new room. Returns
+    /// the records together with whether the device was newly provisioned.
+    /// The batch is applied atomically and recorded in the audit log.
+    pub fn provision_devices(
+        &self,
+        devices: &[NewProvisionedDevice],
+        provisioned_by: &str,
+    ) -> Result<Vec<(ProvisionedDeviceRecord, bool)>> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        let mut records = Vec::with_capacity(devices.len());
+        for device in devices {
+            let existing = select_provisioned_device(&mut tx, &device.device_id)?;
+            let created = existing.is_none();
+            match existing {
+                Some(existing) => {
+                    tx.execute(
+                        "UPDATE provisioned_devices SET room_id = ?2, label = ?3, model = ?4,
+                            firmware_channel = ?5, updated_at = ?6
+                         WHERE device_id = ?1",
+                        params![
+                            device.device_id,
+                            device.room_id,
+                            device.label,
+                            device.model,
+                            device.firmware_channel,
+                            now
+                        ],
+                    )
+                    .with_context(|| {
+                        format!("Failed to update provisioned device {}", device.device_id)
+                    })?;
+                    if existing.claimed {
+                        tx.execute(
+                            "UPDATE devices SET room_id = ?2 WHERE id = ?1 COLLATE NOCASE",
+                            params![device.device_id, device.room_id],
+                        )
+                        .with_context(|| format!("Failed to move device {}", device.device_id))?;
+                    } else {
+                        // The code may also have been replaced by a CSV import
+                        tx.execute(
+                            "INSERT INTO claim_codes (code, room_id, device_id, created_at)
+                             VALUES (?1, ?2, ?3, ?4)
+                             ON CONFLICT (code) DO UPDATE SET room_id = excluded.room_id",
+                            params![existing.claim_code, device.room_id, device.device_id, now],
+                        )
+                        .with_context(|| {
+                            format!("Failed to update claim code of {}", device.device_id)
+                        })?;
+                    }
+                }
+                None => {
+                    tx.execute(
+                        "DELETE FROM claim_codes
+                         WHERE device_id = ?1 COLLATE NOCASE AND claimed_at IS NULL",
+                        params![device.device_id],
+                    )
+                    .context("Failed to replace previous claim code")?;
+                    tx.execute(
+                        "INSERT INTO claim_codes (code, room_id, device_id, created_at)
+                         VALUES (?1, ?2, ?3, ?4)",
+                        params![device.claim_code, device.room_id, device.device_id, now],
+                    )
+                    .with_context(|| {
+                        format!("Failed to create claim code for {}", device.device_id)
+                    })?;
+                    tx.execute(
+                        "INSERT INTO provisioned_devices (device_id, room_id, label, model,
+                            firmware_channel, claim_code, api_key, created_at, updated_at)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8)",
+                        params![
+                            device.device_id,
+                            device.room_id,
+                            device.label,
+                            device.model,
+                            device.firmware_channel,
+                            device.claim_code,
+                            device.api_key,
+                            now
+                        ],
+                    )
+                    .with_context(|| format!("Failed to provision device {}", device.device_id))?;
+                }
+            }
+            let record = select_provisioned_device(&mut tx, &device.device_id)?
+                .with_context(|| format!("Provisioned device {} not found", device.device_id))?;
+            records.push((record, created));
+        }
+        insert_audit_entry(
+            &mut tx,
+            now,
+            provisioned_by,
+            "device.provision",
+            &format!("count={}", devices.len()),
+        )?;
+        tx.commit().context("Failed to commit provisioning")?;
+        self.bump_config_version();
+
+        Ok(records)
+    }
+
+    /// Lists all provisioned devices, ordered by device ID
+    pub fn list_provisioned_devices(&self) -> Result<Vec<ProvisionedDeviceRecord>> {
+        let mut conn = self.conn()?;
+
+        let devices = conn
+            .query_map(
+                &format!("{} ORDER BY p.device_id", PROVISIONED_DEVICE_QUERY),
+                &[],
+                provisioned_device_from_row,
+            )
+            .context("Failed to execute query to list provisioned devices")?;
+
+        Ok(devices)
+    }
+
+    /// Retrieves a provisioned device by its ID
+    pub fn get_provisioned_device(
+        &self,
+        device_id: &str,
+    ) -> Result<Option<ProvisionedDeviceRecord>> {
+        let mut conn = self.conn()?;
+
+        select_provisioned_device(&mut *conn, device_id)
+    }
+
+    /// Stores a batch of device log entries in one transaction
+    pub fn insert_device_logs(&self, entries: &[DeviceLogEntry]) -> Result<()> {
+        let mut conn = self.conn()?;
+
+        let mut tx = conn.transaction()?;
+        for entry in entries {
+            tx.execute(
+                "INSERT INTO device_logs
+                 (device_id, message, received_at, authenticated, logged_at, level)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    entry.device_id,
+                    entry.message,
+                    entry.received_at,
+                    entry.authenticated,
+                    entry.logged_at,
+                    entry.level.map(|level| level.as_str())
+                ],
+            )
+            .with_context(|| format!("Failed to store log of device {}", entry.device_id))?;
+        }
+        tx.commit().context("Failed to commit device logs")?;
+
+        Ok(())
+    }
+
+    /// Lists the latest device log entries matching the query, newest first
+    pub fn list_device_logs(
+        &self,
+        query: &DeviceLogQuery,
+        limit: usize,
+    ) -> Result<Vec<DeviceLogEntry>> {
+        let mut conn = self.conn()?;
+
+        let entries = conn
+            .query_map(
+                "SELECT device_id, message, received_at, authenticated, logged_at, level
+                 FROM device_logs
+                 WHERE (device_id = ?1 COLLATE NOCASE OR ?1 IS NULL)
+                 AND (authenticated = ?2 OR ?2 IS NULL)
+                 AND (CASE level
+                     WHEN 'error' THEN 0 WHEN 'warn' THEN 1 WHEN 'info' THEN 2 WHEN 'debug' THEN 3
+                 END <= ?3 OR ?3 IS NULL)
+                 AND (COALESCE(logged_at, received_at) >= ?4 OR ?4 IS NULL)
+                 AND (COALESCE(logged_at, received_at) < ?5 OR ?5 IS NULL)
+                 ORDER BY id DESC LIMIT ?6",
+                params![
+                    query.device_id,
+                    query.authenticated,
+                    query.level.map(|level| level as i64),
+                    query.since,
+                    query.until,
+                    limit as i64
+                ],
+                device_log_from_row,
+            )
+            .context("Failed to execute query to list device logs")?;
+
+        Ok(entries)
+    }
+
+    /// Deletes the device log entries received before the given Unix
+    /// timestamp, and the oldest entries beyond the given number
+    ///
+    /// A `max_entries` of 0 keeps any number of entries. Returns the number of
+    /// deleted entries.
+    pub fn prune_device_logs(&self, received_before: i64, max_entries: usize) -> Result<usize> {
+        let mut conn = self.conn()?;
+
+        let mut deleted = conn
+            .execute(
+                "DELETE FROM device_logs WHERE received_at < ?1",
+                params![received_before],
+            )
+            .context("Failed to delete old device logs")?;
+        if max_entries > 0 {
+            deleted += conn
+                .execute(
+                    "DELETE FROM device_logs WHERE id <= (
+                        SELECT id FROM device_logs ORDER BY id DESC LIMIT 1 OFFSET ?1
+                     )",
+                    params![max_entries as i64],
+                )
+                .context("Failed to delete excess device logs")?;
+        }
+
+        Ok(deleted)
+    }
+
+    /// Lists the device log entries received since the given Unix timestamp, oldest first
+    pub fn device_logs_since(&self, since: i64) -> Result<Vec<DeviceLogEntry>> {
+        let mut conn = self.conn()?;
+
+        let entries = conn
+            .query_map(
+                "SELECT device_id, message, received_at, authenticated, logged_at, level
+                 FROM device_logs WHERE received_at >= ?1 ORDER BY id",
+                params![since],
+                device_log_from_row,
+            )
+            .context("Failed to execute query to list recent device logs")?;
+
+        Ok(entries)
+    }
+
+    /// Records a display request of a device at the given Unix timestamp
+    ///
+    /// Display requests are kept for [`HEALTH_WINDOW_SECS`], for the display
+    /// watchdog to correlate with the device logs.
+    pub fn record_display_fetch(&self, device_id: &str, fetched_at: i64) -> Result<()> {
+        let mut conn = self.conn()?;
+
+        conn.execute(
+            "INSERT INTO display_fetches (device_id, fetched_at) VALUES (?1, ?2)",
+            params![device_id, fetched_at],
+        )
+        .with_context(|| format!("Failed to record display fetch of device {}", device_id))?;
+        conn.execute(
+            "DELETE FROM display_fetches WHERE fetched_at < ?1",
+            params![fetched_at - HEALTH_WINDOW_SECS],
+        )
+        .context("Failed to delete old display fetches")?;
+
+        Ok(())
+    }
+
+    /// Lists the display requests since the given Unix timestamp as device ID
+    /// and time, oldest first
+    pub fn display_fetches_since(&self, since: i64) -> Result<Vec<(String, i64)>> {
+        let mut conn = self.conn()?;
+
+        let fetches = conn
+            .query_map(
+                "SELECT device_id, fetched_at FROM display_fetches
+                 WHERE fetched_at >= ?1 ORDER BY fetched_at, rowid",
+                params![since],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .context("Failed to execute query to list display fetches")?;
+
+        Ok(fetches)
+    }
+
+    /// Sets or (with `None`) clears the time since when a device fails to
+    /// show the images it fetches
+    pub fn set_device_display_alert(&self, device_id: &str, since: Option<i64>) -> Result<()> {
+        let mut conn = self.conn()?;
+
+        conn.execute(
+            "UPDATE devices SET display_alert_at = ?2 WHERE id = ?1",
+            params![device_id, since],
+        )
+        .with_context(|| format!("Failed to set display alert of device {}", device_id))?;
+
+        Ok(())
+    }
+
+    /// Sets or (with `None`) clears the time since when a device is reported
+    /// offline
+    pub fn set_device_offline_alert(&self, device_id: &str, since: Option<i64>) -> Result<()> {
+        let mut conn = self.conn()?;
+
+        conn.execute(
+            "UPDATE devices SET offline_alert_at = ?2 WHERE id = ?1",
+            params![device_id, since],
+        )
+        .with_context(|| format!("Failed to set offline alert of device {}", device_id))?;
+
+        Ok(())
+    }
+
+    /// Tries to acquire or renew the named lease for `ttl_seconds`
+    ///
+    /// Returns true if `holder` now holds the lease. A lease held by another
+    /// instance can only be taken over once it has expired, so at most one
+    /// instance runs leader-only tasks at any time.
+    pub fn try_acquire_lease(&self, name: &str, holder: &str, ttl_seconds: i64) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let acquired = conn
+            .execute(
+                "INSERT INTO leases (name, holder, expires_at) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (name) DO UPDATE SET holder = excluded.holder, expires_at = excluded.expires_at
+                 WHERE leases.holder = excluded.holder OR leases.expires_at <= ?4",
+                params![name, holder, now + ttl_seconds, now],
+            )
+            .with_context(|| format!("Failed to acquire lease {}", name))?;
+
+        Ok(acquired > 0)
+    }
+
+    /// Release a lease held by the given holder, letting it lapse at the given
+    /// Unix timestamp at the latest
+    ///
+    /// Leases of other holders are left alone.
+    pub fn release_lease(&self, name: &str, holder: &str, lapses_at: i64) -> Result<()> {
+        let mut conn = self.conn()?;
+
+        conn.execute(
+            "UPDATE leases SET expires_at = ?3
+             WHERE name = ?1 AND holder = ?2 AND expires_at > ?3",
+            params![name, holder, lapses_at],
+        )
+        .with_context(|| format!("Failed to release lease {}", name))?;
+
+        Ok(())
+    }
+
+    /// Records a run of a scheduled job, keeping the given number of the most
+    /// recent runs of the job
+    pub fn record_job_run(&self, run: &JobRun, keep: usize) -> Result<()> {
+        let mut conn = self.conn()?;
+
+        conn.execute(
+            "INSERT INTO job_runs
+             (job, instance_id, manual, started_at, finished_at, succeeded, message)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                run.job,
+                run.instance_id,
+                run.manual,
+                run.started_at,
+                run.finished_at,
+                run.succeeded,
+                run.message
+            ],
+        )
+        .with_context(|| format!("Failed to record run of job {}", run.job))?;
+        conn.execute(
+            "DELETE FROM job_runs WHERE job = ?1 AND id <= (
+                SELECT id FROM job_runs WHERE job = ?1 ORDER BY id DESC LIMIT 1 OFFSET ?2
+             )",
+            params![run.job, keep as i64],
+        )
+        .with_context(|| format!("Failed to delete old runs of job {}", run.job))?;
+
+        Ok(())
+    }
+
+    /// Lists the most recent runs of a job, newest first
+    pub fn list_job_runs(&self, job: &str, limit: usize) -> Result<Vec<JobRun>> {
+        let mut conn = self.conn()?;
+
+        let runs = conn
+            .query_map(
+                "SELECT job, instance_id, manual, started_at, finished_at, succeeded, message
+                 FROM job_runs WHERE job = ?1 ORDER BY id DESC LIMIT ?2",
+                params![job, limit as i64],
+                |row| {
+                    Ok(JobRun {
+                        job: row.get(0)?,
+                        instance_id: row.get(1)?,
+                        manual: row.get(2)?,
+                        started_at: row.get(3)?,
+                        finished_at: row.get(4)?,
+                        succeeded: row.get(5)?,
+                        message: row.get(6)?,
+                    })
+                },
+            )
+            .context("Failed to execute query to list job runs")?;
+
+        Ok(runs)
+    }
+
+    /// Pauses a scheduled job on all instances
+    ///
+    /// The action is recorded in the audit log.
+    pub fn pause_job(&self, job: &str, paused_by: &str) -> Result<()> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO paused_jobs (job, paused_at, paused_by) VALUES (?1, ?2, ?3)
+             ON CONFLICT (job) DO NOTHING",
+            params![job, now, paused_by],
+        )
+        .with_context(|| format!("Failed to pause job {}", job))?;
+        insert_audit_entry(
+            &mut tx,
+            now,
+            paused_by,
+            "job.pause",
+            &format!("job={}", job),
+        )?;
+        tx.commit().context("Failed to commit pause of job")?;
+
+        Ok(())
+    }
+
+    /// Resumes a paused job, returns false if it was not paused
+    ///
+    /// The action is recorded in the audit log.
+    pub fn resume_job(&self, job: &str, resumed_by: &str) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        let resumed = tx
+            .execute("DELETE FROM paused_jobs WHERE job = ?1", params![job])
+            .with_context(|| format!("Failed to resume job {}", job))?;
+        if resumed == 0 {
+            return Ok(false);
+        }
+        insert_audit_entry(
+            &mut tx,
+            now,
+            resumed_by,
+            "job.resume",
+            &format!("job={}", job),
+        )?;
+        tx.commit().context("Failed to commit resumption of job")?;
+
+        Ok(true)
+    }
+
+    /// Returns who paused a job and when, if it is paused
+    pub fn job_pause(&self, job: &str) -> Result<Option<(String, i64)>> {
+        let mut conn = self.conn()?;
+
+        conn.query_row(
+            "SELECT paused_by, paused_at FROM paused_jobs WHERE job = ?1",
+            params![job],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .with_context(|| format!("Failed to get pause of job {}", job))
+    }
+
+    /// Lists the rooms stored in the database, with their devices
+    ///
+    /// Once rooms have been imported, the database is the source of truth for
+    /// the room configuration instead of the rooms file.
+    pub fn list_rooms(&self) -> Result<Vec<Room>> {
+        let mut conn = self.conn()?;
+
+        let devices = conn
+            .query_map(
+                "SELECT device_id, room_id FROM room_devices ORDER BY rowid",
+                &[],
+                |row| Ok((row.get::<String>(0)?, row.get::<String>(1)?)),
+            )
+            .context("Failed to execute query for room devices")?;
+
+        let rows = conn
+            .query(
+                "SELECT id, name, calendar_url, settings FROM rooms ORDER BY rowid",
+                &[],
+            )
+            .context("Failed to execute query for rooms")?;
+
+        let mut rooms = Vec::new();
+        for row in &rows {
+            let id: String = row.get(0).context("Failed to get id field from row")?;
+            let settings: String = row
+                .get(3)
+                .context("Failed to get settings field from row")?;
+            let mut fields: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_str(&settings)
+                    .with_context(|| format!("Invalid settings of room {}", id))?;
+            fields.insert(
+                "name".to_string(),
+                row.get::<String>(1)
+                    .context("Failed to get name field from row")?
+                    .into(),
+            );
+            fields.insert(
+                "calendar_url".to_string(),
+                row.get::<Option<String>>(2)
+                    .context("Failed to get calendar_url field from row")?
+                    .into(),
+            );
+            fields.insert(
+                "devices".to_string(),
+                devices
+                    .iter()
+                    .filter(|(_, room_id)| *room_id == id)
+                    .map(|(device_id, _)| device_id.clone())
+                    .collect::<Vec<_>>()
+                    .into(),
+            );
+            fields.insert("id".to_string(), id.clone().into());
+            rooms.push(
+                serde_json::from_value(fields.into())
+                    .with_context(|| format!("Invalid settings of room {}", id))?,
+            );
+        }
+
+        Ok(rooms)
+    }
+
+    /// Stores the given rooms, replacing existing rooms with the same ID
+    ///
+    /// Used to seed the database from the rooms file. Rooms not contained in
+    /// `rooms` are kept. The action is recorded in the audit log.
+    pub fn import_rooms(&self, rooms: &[Room], imported_by: &str) -> Result<()> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        for room in rooms {
+            write_room(&mut tx, room, now)?;
+        }
+        insert_audit_entry(
+            &mut tx,
+            now,
+            imported_by,
+            "rooms.import",
+            &format!("count={}", rooms.len()),
+        )?;
+        tx.commit().context("Failed to commit room import")?;
+        self.bump_config_version();
+
+        Ok(())
+    }
+
+    /// Creates or replaces a room, including its device assignments
+    ///
+    /// The action is recorded in the audit log.
+    pub fn save_room(&self, room: &Room, saved_by: &str) -> Result<()> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        write_room(&mut tx, room, now)?;
+        insert_audit_entry(
+            &mut tx,
+            now,
+            saved_by,
+            "room.save",
+            &format!("id={}", room.id),
+        )?;
+        tx.commit().context("Failed to commit room")?;
+        self.bump_config_version();
+
+        Ok(())
+    }
+
+    /// Deletes a room and its device assignments
+    ///
+    /// Returns false if there was no such room. The action is recorded in the
+    /// audit log.
+    pub fn delete_room(&self, room_id: &str, deleted_by: &str) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM room_devices WHERE room_id = ?1",
+            params![room_id],
+        )
+        .with_context(|| format!("Failed to delete devices of room {}", room_id))?;
+        let deleted = tx
+            .execute("DELETE FROM rooms WHERE id = ?1", params![room_id])
+            .with_context(|| format!("Failed to delete room {}", room_id))?;
+        if deleted > 0 {
+            insert_audit_entry(
+                &mut tx,
+                now,
+                deleted_by,
+                "room.delete",
+                &format!("id={}", room_id),
+            )?;
+        }
+        tx.commit().context("Failed to commit room deletion")?;
+        self.bump_config_version();
+
+        Ok(deleted > 0)
+    }
+
+    /// Starts a layout experiment, returning `None` if an experiment of that
+    /// name exists
+    ///
+    /// The action is recorded in the audit log.
+    pub fn create_experiment(
+        &self,
+        name: &str,
+        layout: Layout,
+        percent: u8,
+        room_id: Option<&str>,
+        created_by: &str,
+    ) -> Result<Option<Experiment>> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        let created = tx
+            .execute(
+                "INSERT INTO experiments (name, layout, percent, room_id, state, created_at, created_by)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) ON CONFLICT (name) DO NOTHING",
+                params![
+                    name,
+                    layout.as_str(),
+                    percent,
+                    room_id,
+                    ExperimentState::Running.as_str(),
+                    now,
+                    created_by
+                ],
+            )
+            .with_context(|| format!("Failed to insert experiment {}", name))?;
+        if created == 0 {
+            return Ok(None);
+        }
+        insert_audit_entry(
+            &mut tx,
+            now,
+            created_by,
+            "experiment.create",
+            &format!(
+                "name={} layout={} percent={} room={}",
+                name,
+                layout.as_str(),
+                percent,
+                room_id.unwrap_or("-")
+            ),
+        )?;
+        tx.commit().context("Failed to commit experiment")?;
+        self.bump_config_version();
+
+        Ok(Some(Experiment {
+            name: name.to_string(),
+            layout,
+            percent,
+            room_id: room_id.map(str::to_string),
+            state: ExperimentState::Running,
+            created_at: now,
+            created_by: created_by.to_string(),
+        }))
+    }
+
+    /// Lists all experiments, oldest first
+    pub fn list_experiments(&self) -> Result<Vec<Experiment>> {
+        let mut conn = self.conn()?;
+
+        let rows = conn
+            .query_map(
+                "SELECT name, layout, percent, room_id, state, created_at, created_by
+                 FROM experiments ORDER BY created_at, name",
+                &[],
+                |row| {
+                    Ok((
+                        row.get::<String>(0)?,
+                        row.get::<String>(1)?,
+                        row.get::<u8>(2)?,
+                        row.get::<Option<String>>(3)?,
+                        row.get::<String>(4)?,
+                        row.get::<i64>(5)?,
+                        row.get::<String>(6)?,
+                    ))
+                },
+            )
+            .context("Failed to execute query to list experiments")?;
+
+        rows.into_iter()
+            .map(
+                |(name, layout, percent, room_id, state, created_at, created_by)| {
+                    Ok(Experiment {
+                        layout: layout
+                            .parse()
+                            .map_err(|e| anyhow::anyhow!("Experiment {}: {}", name, e))?,
+                        state: state
+                            .parse()
+                            .map_err(|e| anyhow::anyhow!("Experiment {}: {}", name, e))?,
+                        name,
+                        percent,
+                        room_id,
+                        created_at,
+                        created_by,
+                    })
+                },
+            )
+            .collect()
+    }
+
+    /// Promotes or rolls back an experiment, returning false if there is no
+    /// such experiment
+    ///
+    /// The action is recorded in the audit log.
+    pub fn set_experiment_state(
+        &self,
+        name: &str,
+        state: ExperimentState,
+        changed_by: &str,
+    ) -> Result<bool> {
+        let mut conn = self.conn()?;
+
+        let now = unix_now()?;
+        let mut tx = conn.transaction()?;
+        let updated = tx
+            .execute(
+                "UPDATE experiments SET state = ?2 WHERE name = ?1",
+                params![name, state.as_str()],
+            )
+            .with_context(|| format!("Failed to update experiment {}", name))?;
+        if updated > 0 {
+            insert_audit_entry(
+                &mut tx,
+                now,
+                changed_by,
+                "experiment.state",
+                &format!("name={} state={}", name, state.as_str()),
+            )?;
+        }
+        tx.commit().context("Failed to commit experiment state")?;
+        self.bump_config_version();
+
+        Ok(updated > 0)
+    }
+
+    /// Stores the utilization of rooms on single days, replacing the figures
+    /// recorded before for the same room and day
+    pub fn record_room_utilization(&self, days: &[DailyUtilization]) -> Result<()> {
+        let mut conn = self.conn()?;
+
+        let mut tx = conn.transaction()?;
+        for day in days {
+            tx.execute(
+                "INSERT INTO room_utilization (room_id, day, meetings, busy_minutes)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (room_id, day) DO UPDATE SET
+                    meetings = excluded.meetings, busy_minutes = excluded.busy_minutes",
+                params![
+                    day.room_id,
+                    day.day.format("%Y-%m-%d").to_string(),
+                    day.meetings,
+                    day.busy_minutes
+                ],
+            )
+            .with_context(|| {
+                format!(
+                    "Failed to record utilization of room {} on {}",
+                    day.room_id, day.day
+                )
+            })?;
+        }
+        tx.commit().context("Failed to commit transaction")?;
+
+        Ok(())
+    }
+
+    /// Lists the recorded utilization of a room from the given day on, oldest
+    /// first
+    pub fn room_utilization(
+        &self,
+        room_id: &str,
+        since: chrono::NaiveDate,
+    ) -> Result<Vec<DailyUtilization>> {
+        let mut conn = self.conn()?;
+
+        let rows = conn
+            .query_map(
+                "SELECT room_id, day, meetings, busy_minutes FROM room_utilization
+                 WHERE room_id = ?1 AND day >= ?2 ORDER BY day",
+                params![room_id, since.format("%Y-%m-%d").to_string()],
+                |row| {
+                    Ok((
+                        row.get::<String>(0)?,
+                        row.get::<String>(1)?,
+                        row.get::<u32>(2)?,
+                        row.get::<u32>(3)?,
+                    ))
+                },
+            )
+            .context("Failed to execute query to list room utilization")?;
+
+        rows.into_iter()
+            .map(|(room_id, day, meetings, busy_minutes)| {
+                Ok(DailyUtilization {
+                    day: day
+                        .parse()
+                        .with_context(|| format!("Invalid utilization day {}", day))?,
+                    room_id,
+                    meetings,
+                    busy_minutes,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Write a room and its device assignments
+///
+/// A device can only be assigned to one room, assigning it to this room
+/// removes it from any other.
+fn write_room(conn: &mut dyn StorageConnection, room: &Room, now: i64) -> Result<()> {
+    let mut settings = match serde_json::to_value(room)
+        .with_context(|| format!("Failed to serialize room {}", room.id))?
+    {
+        serde_json::Value::Object(fields) => fields,
+        _ => return Err(anyhow::anyhow!("Room {} is not an object", room.id)),
+    };
+    for field in ["id", "name", "calendar_url", "devices"] {
+        settings.remove(field);
+    }
+    let settings = serde_json::Value::Object(settings).to_string();
+
+    conn.execute(
+        "INSERT INTO rooms (id, name, calendar_url, settings, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (id) DO UPDATE SET
+            name = excluded.name, calendar_url = excluded.calendar_url,
+            settings = excluded.settings, updated_at = excluded.updated_at",
+        params![room.id, room.name, room.calendar_url, settings, now],
+    )
+    .with_context(|| format!("Failed to store room {}", room.id))?;
+    conn.execute(
+        "DELETE FROM room_devices WHERE room_id = ?1",
+        params![room.id],
+    )
+    .with_context(|| format!("Failed to clear devices of room {}", room.id))?;
+    for device_id in &room.devices {
+        conn.execute(
+            "INSERT INTO room_devices (device_id, room_id) VALUES (?1, ?2)
+             ON CONFLICT (device_id) DO UPDATE SET room_id = excluded.room_id",
+            params![device_id, room.id],
+        )
+        .with_context(|| format!("Failed to assign device {} to room {}", device_id, room.id))?;
+    }
+    Ok(())
+}
+
+/// Query for provisioned devices, including whether their claim code was used
+const PROVISIONED_DEVICE_QUERY: &str =
+    "SELECT p.device_id, p.room_id, p.label, p.model, p.firmware_channel, p.claim_code,
+        p.api_key, c.claimed_at IS NOT NULL
+     FROM provisioned_devices p LEFT JOIN claim_codes c ON c.code = p.claim_code";
+
+fn provisioned_device_from_row(row: &storage::Row) -> Result<ProvisionedDeviceRecord> {
+    Ok(ProvisionedDeviceRecord {
+        device_id: row.get(0)?,
+        room_id: row.get(1)?,
+        label: row.get(2)?,
+        model: row.get(3)?,
+        firmware_channel: row.get(4)?,
+        claim_code: row.get(5)?,
+        api_key: row.get(6)?,
+        claimed: row.get(7)?,
+    })
+}
+
+fn select_provisioned_device(
+    conn: &mut dyn StorageConnection,
+    device_id: &str,
+) -> Result<Option<ProvisionedDeviceRecord>> {
+    conn.query_row(
+        &format!("{} WHERE p.device_id = ?1", PROVISIONED_DEVICE_QUERY),
+        params![device_id],
+        provisioned_device_from_row,
+    )
+    .with_context(|| format!("Failed to get provisioned device {}", device_id))
+}
+
+/// Device to be provisioned, see [`Database::provision_devices`]
+#[derive(Debug, Clone)]
+pub struct NewProvisionedDevice {
+    /// Device ID (MAC address), normalized
+    pub device_id: String,
+    /// Room the device is bound to
+    pub room_id: String,
+    /// Free text label, e.g. the mounting position
+    pub label: Option<String>,
+    /// Device model, e.g. `og`
+    pub model: Option<String>,
+    /// Firmware release channel, e.g. `stable`
+    pub firmware_channel: String,
+    /// Claim code used if the device is new
+    pub claim_code: String,
+    /// API key used if the device is new
+    pub api_key: String,
+}
+
+/// Record of a provisioned device in the database
+#[derive(Debug, Clone)]
+pub struct ProvisionedDeviceRecord {
+    /// Device ID (MAC address)
+    pub device_id: String,
+    /// Room the device is bound to
+    pub room_id: String,
+    /// Free text label, e.g. the mounting position
+    pub label: Option<String>,
+    /// Device model, e.g. `og`
+    pub model: Option<String>,
+    /// Firmware release channel, e.g. `stable`
+    pub firmware_channel: String,
+    /// Claim code bound to the device
+    pub claim_code: String,
+    /// API key returned to the device on setup
+    pub api_key: String,
+    /// Whether the device has claimed its code, i.e. completed setup
+    pub claimed: bool,
+}
+
+/// Log message sent by a device
+#[derive(Debug, Clone, Default)]
+pub struct DeviceLogEntry {
+    /// Device ID as sent in the `ID` header, `unknown` if missing
+    pub device_id: String,
+    /// The log message
+    pub message: String,
+    /// Unix timestamp when the message was received
+    pub received_at: i64,
+    /// Whether the request carried the device's key, i.e. the device ID is not spoofed
+    pub authenticated: bool,
+    /// Unix timestamp when the device logged the message, if it said so
+    pub logged_at: Option<i64>,
+    /// Severity of the message, if the device said so
+    pub level: Option<LogLevel>,
+}
+
+/// Filter for listing device log entries
+///
+/// Entries are filtered by the time they were logged, or received if the
+/// device did not say when it logged them.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceLogQuery<'a> {
+    /// Only entries of this device
+    pub device_id: Option<&'a str>,
+    /// Only authenticated (`true`) or unauthenticated (`false`) entries
+    pub authenticated: Option<bool>,
+    /// Only entries of this level or more severe
+    pub level: Option<LogLevel>,
+    /// Only entries from this Unix timestamp on
+    pub since: Option<i64>,
+    /// Only entries before this Unix timestamp
+    pub until: Option<i64>,
+}
+
+/// Claim code to be created, see [`Database::create_claim_codes`]
+#[derive(Debug, Clone)]
+pub struct NewClaimCode {
+    /// The code itself, normalized
+    pub code: String,
+    /// Room the claiming device is bound to
+    pub room_id: String,
+    /// Device the code is pre-provisioned for, if any
+    pub device_id: Option<String>,
+}
+
+/// Record of a claim code in the database
+#[derive(Debug, Clone)]
+pub struct ClaimCodeRecord {
+    /// The claim code
+    pub code: String,
+    /// Room the claiming device is bound to
+    pub room_id: String,
+    /// Device the code is pre-provisioned for or was claimed by
+    pub device_id: Option<String>,
+    /// Unix timestamp when the code expires, if it does
+    pub expires_at: Option<i64>,
+}
+
+/// Raw calendar data cached in the database
+#[derive(Debug, Clone)]
+pub struct CachedCalendarRecord {
+    /// Raw iCalendar data
+    pub data: String,
+    /// Unix timestamp when the data was fetched
+    pub fetched_at: i64,
 }
 
 /// Record of a device in the database
@@ -113,18 +2332,696 @@ pub struct DeviceRecord {
     pub id: String,
     /// Unix timestamp when the device was registered
     pub registered_at: i64,
+    /// Room the device was bound to when it was claimed, if any
+    pub room_id: Option<String>,
+    /// Device model as last reported by the device
+    pub model: Option<String>,
+    /// Format of the last image served to the device, e.g. `bmp`
+    pub last_payload_format: Option<String>,
+    /// Size of the last image served to the device, in bytes
+    pub last_payload_bytes: Option<i64>,
+    /// Image delivery mode set by an admin, overriding the configured one
+    pub image_delivery: Option<String>,
+    /// Unix timestamp of the last display request of the device
+    pub last_seen_at: Option<i64>,
+    /// Battery voltage last reported by the device
+    pub battery_voltage: Option<f64>,
+    /// API key of the device, `None` for devices set up before they got keys
+    /// of their own and for devices whose key was revoked
+    pub api_key: Option<String>,
+    /// Unix timestamp when the API key of the device was revoked
+    pub api_key_revoked_at: Option<i64>,
+    /// Whether the battery is critically low, see [`crate::health::battery_critical`]
+    pub battery_critical: bool,
+    /// Unix timestamp when the device was replaced by another one
+    pub retired_at: Option<i64>,
+    /// Device that replaced this one
+    pub replaced_by: Option<String>,
+    /// Name given by an admin, e.g. where the device is mounted
+    pub name: Option<String>,
+    /// Firmware version as last reported by the device
+    pub firmware_version: Option<String>,
+    /// Image format set by an admin, overriding the negotiated one
+    pub image_format: Option<String>,
+    /// Unix timestamp since when the device fails to show the images it
+    /// fetches, see [`crate::server::watchdog`]
+    pub display_alert_at: Option<i64>,
+    /// WiFi signal strength last reported by the device, in dBm
+    pub rssi: Option<i64>,
+    /// Unix timestamp when the device is expected to check in next
+    pub next_check_in_at: Option<i64>,
+    /// Seconds between check-ins the device was last told, see
+    /// [`crate::health::missed_check_ins`]
+    pub check_in_interval: Option<i64>,
+    /// Unix timestamp since when the device is reported offline, see
+    /// [`crate::server::alerts`]
+    pub offline_alert_at: Option<i64>,
+}
+
+/// State a device reports in the headers of a display request
+#[derive(Debug, Clone, Default)]
+pub struct DeviceCheckIn<'a> {
+    /// Battery voltage (`Battery-Voltage`)
+    pub battery_voltage: Option<f64>,
+    /// WiFi signal strength in dBm (`RSSI`)
+    pub rssi: Option<i64>,
+    /// Firmware version (`FW-Version`)
+    pub firmware_version: Option<&'a str>,
+}
+
+/// Record of a fleet-wide broadcast message
+#[derive(Debug, Clone)]
+pub struct BroadcastRecord {
+    /// Broadcast identifier
+    pub id: i64,
+    /// Message shown on all devices
+    pub message: String,
+    /// Unix timestamp when the broadcast was created
+    pub created_at: i64,
+    /// Unix timestamp when the broadcast expires
+    pub expires_at: i64,
+    /// Who triggered the broadcast
+    pub triggered_by: String,
+}
+
+/// Run of a scheduled job
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobRun {
+    pub job: String,
+    /// Instance that ran the job
+    pub instance_id: String,
+    /// Whether an admin started the run, rather than the schedule
+    pub manual: bool,
+    /// Unix timestamp when the run started
+    pub started_at: i64,
+    /// Unix timestamp when the run finished
+    pub finished_at: i64,
+    pub succeeded: bool,
+    /// Summary of the run, or the error if it failed
+    pub message: Option<String>,
+}
+
+/// Record of the maintenance mode of the server
+#[derive(Debug, Clone)]
+pub struct MaintenanceRecord {
+    /// Message shown on all devices below the maintenance notice
+    pub message: Option<String>,
+    /// Unix timestamp when the maintenance started
+    pub started_at: i64,
+    /// Who started the maintenance
+    pub started_by: String,
+}
+
+/// Record of a meeting marked as do-not-disturb
+#[derive(Debug, Clone)]
+pub struct DndRecord {
+    /// Room the meeting takes place in
+    pub room_id: String,
+    /// Name of the meeting, as of marking it
+    pub meeting: String,
+    /// Unix timestamp when the meeting was marked
+    pub set_at: i64,
+    /// Unix timestamp when the meeting ends, which clears the mark
+    pub expires_at: i64,
+    /// Who marked the meeting
+    pub set_by: String,
+}
+
+/// Record of an issue reported for a room
+#[derive(Debug, Clone)]
+pub struct IssueReportRecord {
+    /// Report identifier
+    pub id: i64,
+    /// Room the issue was reported for
+    pub room_id: String,
+    /// Issue category, e.g. `projector`
+    pub category: String,
+    /// Free text description
+    pub description: String,
+    /// Unix timestamp when the issue was reported
+    pub created_at: i64,
+}
+
+/// Current Unix timestamp in seconds
+fn unix_now() -> Result<i64> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("Failed to get current timestamp")?
+        .as_secs() as i64)
+}
+
+/// Device log entry of a row with the columns `device_id, message,
+/// received_at, authenticated, logged_at, level`
+fn device_log_from_row(row: &storage::Row) -> Result<DeviceLogEntry> {
+    Ok(DeviceLogEntry {
+        device_id: row.get(0)?,
+        message: row.get(1)?,
+        received_at: row.get(2)?,
+        authenticated: row.get(3)?,
+        logged_at: row.get(4)?,
+        level: row
+            .get::<Option<String>>(5)?
+            .and_then(|level| level.parse().ok()),
+    })
+}
+
+/// Insert an entry into the audit log
+fn insert_audit_entry(
+    conn: &mut dyn StorageConnection,
+    timestamp: i64,
+    actor: &str,
+    action: &str,
+    details: &str,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO audit_log (timestamp, actor, action, details) VALUES (?1, ?2, ?3, ?4)",
+        params![timestamp, actor, action, details],
+    )
+    .with_context(|| format!("Failed to write audit log entry for {}", action))?;
+    Ok(())
 }
 
 /// Initialize the database with error handling
-pub fn init_database(db_path: &str) -> Result<Arc<Database>> {
-    if !Path::new(db_path).exists() {
-        info!("Creating new database at {}", db_path);
+pub fn init_database(url: &str, pool_size: usize) -> Result<Arc<Database>> {
+    if is_postgres_url(url) {
+        info!("Connecting to Postgres database {}", redact_url(url));
+    } else if !Path::new(url).exists() {
+        info!("Creating new database at {}", url);
     } else {
-        info!("Using existing database at {}", db_path);
+        info!("Using existing database at {}", url);
     }
 
-    let db = Database::new(db_path)
-        .with_context(|| format!("Failed to initialize database at {}", db_path))?;
+    let db = Database::with_pool_size(url, pool_size)
+        .with_context(|| format!("Failed to initialize database at {}", redact_url(url)))?;
 
     Ok(Arc::new(db))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::CRITICAL_BATTERY_VOLTAGE;
+
+    #[test]
+    fn test_lease_is_exclusive() {
+        let db = Database::new(":memory:").unwrap();
+
+        assert!(db.try_acquire_lease("rollover", "instance-a", 60).unwrap());
+        assert!(!db.try_acquire_lease("rollover", "instance-b", 60).unwrap());
+        // The holder can renew its own lease
+        assert!(db.try_acquire_lease("rollover", "instance-a", 60).unwrap());
+
+        // Expired leases can be taken over
+        assert!(db.try_acquire_lease("other", "instance-a", -1).unwrap());
+        assert!(db.try_acquire_lease("other", "instance-b", 60).unwrap());
+
+        // Released leases can be taken over once they lapse
+        db.release_lease("rollover", "instance-b", 0).unwrap();
+        assert!(!db.try_acquire_lease("rollover", "instance-b", 60).unwrap());
+        db.release_lease("rollover", "instance-a", 0).unwrap();
+        assert!(db.try_acquire_lease("rollover", "instance-b", 60).unwrap());
+    }
+
+    #[test]
+    fn test_record_device_check_in() {
+        let db = Database::new(":memory:").unwrap();
+        db.register_device("AA:BB:CC:DD:EE:FF").unwrap();
+        db.record_device_check_in(
+            "AA:BB:CC:DD:EE:FF",
+            &DeviceCheckIn {
+                battery_voltage: Some(4.1),
+                rssi: Some(-67),
+                firmware_version: Some("1.5.2"),
+            },
+            CRITICAL_BATTERY_VOLTAGE,
+        )
+        .unwrap();
+        // Values missing in a later request are kept
+        db.record_device_check_in(
+            "AA:BB:CC:DD:EE:FF",
+            &DeviceCheckIn {
+                rssi: Some(-72),
+                ..Default::default()
+            },
+            CRITICAL_BATTERY_VOLTAGE,
+        )
+        .unwrap();
+
+        let device = db.get_device("AA:BB:CC:DD:EE:FF").unwrap().unwrap();
+        assert!(device.last_seen_at.is_some());
+        assert_eq!(device.battery_voltage, Some(4.1));
+        assert_eq!(device.rssi, Some(-72));
+        assert_eq!(device.firmware_version.as_deref(), Some("1.5.2"));
+    }
+
+    #[test]
+    fn test_battery_state_follows_readings() {
+        let db = Database::new(":memory:").unwrap();
+        db.register_device("AA:BB:CC:DD:EE:FF").unwrap();
+        let check_in = |voltage| {
+            db.record_device_check_in(
+                "AA:BB:CC:DD:EE:FF",
+                &DeviceCheckIn {
+                    battery_voltage: voltage,
+                    ..Default::default()
+                },
+                CRITICAL_BATTERY_VOLTAGE,
+            )
+            .unwrap()
+        };
+
+        assert!(!check_in(Some(3.2)));
+        assert!(!check_in(Some(3.2)));
+        assert!(check_in(Some(3.2)));
+        // Requests without a voltage keep the state
+        assert!(check_in(None));
+        let device = db.get_device("AA:BB:CC:DD:EE:FF").unwrap().unwrap();
+        assert!(device.battery_critical);
+        assert_eq!(device.battery_voltage, Some(3.2));
+
+        // Battery replaced
+        assert!(check_in(Some(4.1)));
+        assert!(!check_in(Some(4.1)));
+        assert!(!db.list_devices().unwrap()[0].battery_critical);
+    }
+
+    #[test]
+    fn test_delete_device() {
+        let db = Database::new(":memory:").unwrap();
+        db.register_device("AA:BB:CC:DD:EE:FF").unwrap();
+        db.record_device_check_in(
+            "AA:BB:CC:DD:EE:FF",
+            &DeviceCheckIn {
+                battery_voltage: Some(3.2),
+                ..Default::default()
+            },
+            CRITICAL_BATTERY_VOLTAGE,
+        )
+        .unwrap();
+        db.insert_device_logs(&[DeviceLogEntry {
+            device_id: "aa:bb:cc:dd:ee:ff".to_string(),
+            message: "Display refreshed".to_string(),
+            received_at: 0,
+            authenticated: true,
+            ..Default::default()
+        }])
+        .unwrap();
+        assert!(
+            db.rename_device("AA:BB:CC:DD:EE:FF", Some("Lobby"), "test")
+                .unwrap()
+        );
+        let device = db.get_device("AA:BB:CC:DD:EE:FF").unwrap().unwrap();
+        assert_eq!(device.name.as_deref(), Some("Lobby"));
+
+        // Unknown devices leave everything in place
+        assert!(!db.delete_device("AA:BB:CC:DD:EE:00", "test").unwrap());
+        assert_eq!(
+            db.list_device_logs(&DeviceLogQuery::default(), 10)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // The ID matches case-insensitively, like everywhere else
+        assert!(db.delete_device("aa:bb:cc:dd:ee:ff", "test").unwrap());
+        assert!(!db.device_exists("AA:BB:CC:DD:EE:FF").unwrap());
+        assert!(
+            db.list_device_logs(&DeviceLogQuery::default(), 10)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(!db.delete_device("AA:BB:CC:DD:EE:FF", "test").unwrap());
+        assert!(!db.rename_device("AA:BB:CC:DD:EE:FF", None, "test").unwrap());
+
+        // A device set up again starts afresh
+        db.register_device("AA:BB:CC:DD:EE:FF").unwrap();
+        let device = db.get_device("AA:BB:CC:DD:EE:FF").unwrap().unwrap();
+        assert_eq!(device.name, None);
+        assert_eq!(device.last_seen_at, None);
+        assert!(
+            !db.record_device_check_in(
+                "AA:BB:CC:DD:EE:FF",
+                &DeviceCheckIn {
+                    battery_voltage: Some(3.2),
+                    ..Default::default()
+                },
+                CRITICAL_BATTERY_VOLTAGE
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_broadcast_survives_restart() {
+        let path = std::env::temp_dir().join(format!("trmnl-broadcast-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let created = {
+            let db = Database::new(path).unwrap();
+            db.create_broadcast("Fire drill at 10:00", 3600, "admin")
+                .unwrap()
+        };
+
+        // The expiry is part of the stored record, no timer needs to be restored
+        let db = Database::new(path).unwrap();
+        let active = db.active_broadcast().unwrap().unwrap();
+        assert_eq!(active.id, created.id);
+        assert_eq!(active.expires_at, created.expires_at);
+
+        db.create_broadcast("Expired", -1, "admin").unwrap();
+        drop(db);
+        assert!(
+            Database::new(path)
+                .unwrap()
+                .active_broadcast()
+                .unwrap()
+                .is_none()
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_device_log_filters() {
+        let db = Database::new(":memory:").unwrap();
+        let entry = |message: &str, logged_at, level| DeviceLogEntry {
+            device_id: "AA:BB:CC:DD:EE:FF".to_string(),
+            message: message.to_string(),
+            received_at: 1_700_000_300,
+            authenticated: true,
+            logged_at,
+            level,
+        };
+        db.insert_device_logs(&[
+            entry(
+                "Rebooting after panic",
+                Some(1_700_000_000),
+                Some(LogLevel::Error),
+            ),
+            entry("WiFi weak", Some(1_700_000_100), Some(LogLevel::Warn)),
+            entry(
+                "Display refreshed",
+                Some(1_700_000_200),
+                Some(LogLevel::Info),
+            ),
+            entry("Unsynced clock", None, None),
+        ])
+        .unwrap();
+
+        let messages = |query: DeviceLogQuery| -> Vec<String> {
+            db.list_device_logs(&query, 10)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.message)
+                .collect()
+        };
+        assert_eq!(
+            messages(DeviceLogQuery {
+                level: Some(LogLevel::Warn),
+                ..Default::default()
+            }),
+            ["WiFi weak", "Rebooting after panic"]
+        );
+        // Entries without a time of their own are filtered by their receipt
+        assert_eq!(
+            messages(DeviceLogQuery {
+                device_id: Some("aa:bb:cc:dd:ee:ff"),
+                since: Some(1_700_000_100),
+                until: Some(1_700_000_300),
+                ..Default::default()
+            }),
+            ["Display refreshed", "WiFi weak"]
+        );
+        assert_eq!(
+            messages(DeviceLogQuery {
+                since: Some(1_700_000_300),
+                ..Default::default()
+            }),
+            ["Unsynced clock"]
+        );
+        let stored = db.list_device_logs(&DeviceLogQuery::default(), 10).unwrap();
+        assert_eq!(stored[3].logged_at, Some(1_700_000_000));
+        assert_eq!(stored[3].level, Some(LogLevel::Error));
+    }
+
+    #[test]
+    fn test_claim_code_is_single_use() {
+        let db = Database::new(":memory:").unwrap();
+        let code = NewClaimCode {
+            code: "ABCD-EFGH".to_string(),
+            room_id: "room-a".to_string(),
+            device_id: None,
+        };
+        db.create_claim_codes(&[code], Some(3600), "admin").unwrap();
+
+        assert!(db.claim_device("AA:BB", Some("WRONG")).unwrap().is_none());
+        let claim = db
+            .claim_device("AA:BB", Some("ABCD-EFGH"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(claim.room_id, "room-a");
+        assert_eq!(
+            db.get_device("AA:BB").unwrap().unwrap().room_id.as_deref(),
+            Some("room-a")
+        );
+        assert!(
+            db.claim_device("CC:DD", Some("ABCD-EFGH"))
+                .unwrap()
+                .is_none()
+        );
+
+        // Re-registering keeps the room binding
+        db.register_device("AA:BB").unwrap();
+        assert_eq!(
+            db.get_device("AA:BB").unwrap().unwrap().room_id.as_deref(),
+            Some("room-a")
+        );
+    }
+
+    #[test]
+    fn test_pre_provisioned_claim() {
+        let db = Database::new(":memory:").unwrap();
+        let code = NewClaimCode {
+            code: "PROV-0001".to_string(),
+            room_id: "room-b".to_string(),
+            device_id: Some("AA:BB".to_string()),
+        };
+        db.create_claim_codes(&[code], None, "admin").unwrap();
+
+        assert!(db.claim_device("CC:DD", None).unwrap().is_none());
+        assert!(
+            db.claim_device("CC:DD", Some("PROV-0001"))
+                .unwrap()
+                .is_none()
+        );
+        let claim = db.claim_device("aa:bb", None).unwrap().unwrap();
+        assert_eq!(claim.room_id, "room-b");
+    }
+
+    #[test]
+    fn test_provisioning_is_idempotent() {
+        let db = Database::new(":memory:").unwrap();
+        let device = |room_id: &str, code: &str| NewProvisionedDevice {
+            device_id: "AA:BB:CC:DD:EE:FF".to_string(),
+            room_id: room_id.to_string(),
+            label: Some("Door left".to_string()),
+            model: Some("og".to_string()),
+            firmware_channel: "stable".to_string(),
+            claim_code: code.to_string(),
+            api_key: format!("key-{}", code),
+        };
+
+        let (first, created) = db
+            .provision_devices(&[device("room-a", "PROV-0001")], "script")
+            .unwrap()
+            .remove(0);
+        assert!(created);
+        assert!(!first.claimed);
+
+        // Re-submission keeps the code and key, but moves the device
+        let (second, created) = db
+            .provision_devices(&[device("room-b", "PROV-0002")], "script")
+            .unwrap()
+            .remove(0);
+        assert!(!created);
+        assert_eq!(second.claim_code, "PROV-0001");
+        assert_eq!(second.api_key, "key-PROV-0001");
+        assert_eq!(db.list_open_claim_codes().unwrap().len(), 1);
+
+        let claim = db.claim_device("aa:bb:cc:dd:ee:ff", None).unwrap().unwrap();
+        assert_eq!(claim.room_id, "room-b");
+        let record = db
+            .get_provisioned_device("aa:bb:cc:dd:ee:ff")
+            .unwrap()
+            .unwrap();
+        assert!(record.claimed);
+
+        // Claimed devices are moved directly
+        db.provision_devices(&[device("room-a", "PROV-0003")], "script")
+            .unwrap();
+        let device = db.get_device("aa:bb:cc:dd:ee:ff").unwrap().unwrap();
+        assert_eq!(device.room_id.as_deref(), Some("room-a"));
+        assert_eq!(db.list_provisioned_devices().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_room_import_and_edit() {
+        let db = Database::new(":memory:").unwrap();
+        let rooms =
+            crate::rooms::parse_rooms(&std::fs::read_to_string("rooms.example.toml").unwrap())
+                .unwrap();
+        db.import_rooms(&rooms, "import-config").unwrap();
+
+        let stored = db.list_rooms().unwrap();
+        assert_eq!(stored.len(), rooms.len());
+        assert_eq!(stored[0].id, rooms[0].id);
+        assert_eq!(stored[0].devices, rooms[0].devices);
+        assert_eq!(stored[0].capacity, rooms[0].capacity);
+        assert_eq!(
+            stored[0].refresh.boundary_rate,
+            rooms[0].refresh.boundary_rate
+        );
+
+        // Moving a device to another room removes it from the first one
+        let mut room_b = stored[1].clone();
+        room_b.devices = rooms[0].devices.clone();
+        db.save_room(&room_b, "admin").unwrap();
+        let stored = db.list_rooms().unwrap();
+        assert!(stored[0].devices.is_empty());
+        assert_eq!(stored[1].devices, rooms[0].devices);
+
+        assert!(db.delete_room(&rooms[0].id, "admin").unwrap());
+        assert!(!db.delete_room(&rooms[0].id, "admin").unwrap());
+        assert_eq!(db.list_rooms().unwrap().len(), rooms.len() - 1);
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn test_postgres() {
+        // Needs a Postgres database, which it empties first
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            eprintln!("TEST_DATABASE_URL is not set, skipping the Postgres test");
+            return;
+        };
+        {
+            // Start from an empty database
+            let db = Database::connect(&url, 1).unwrap();
+            let mut conn = db.conn().unwrap();
+            migrations::migrate(&mut *conn, migrations::MIGRATIONS, 0).unwrap();
+        }
+        let db = Database::new(&url).unwrap();
+        assert_eq!(
+            db.schema_version().unwrap(),
+            Database::latest_schema_version()
+        );
+
+        db.register_device("AA:BB:CC:DD:EE:FF").unwrap();
+        db.record_device_check_in(
+            "AA:BB:CC:DD:EE:FF",
+            &DeviceCheckIn {
+                battery_voltage: Some(4.1),
+                rssi: Some(-67),
+                firmware_version: Some("1.5.2"),
+            },
+            CRITICAL_BATTERY_VOLTAGE,
+        )
+        .unwrap();
+        let device = db.get_device("AA:BB:CC:DD:EE:FF").unwrap().unwrap();
+        assert_eq!(device.battery_voltage, Some(4.1));
+        assert_eq!(device.rssi, Some(-67));
+        assert!(!device.battery_critical);
+        db.record_device_schedule("AA:BB:CC:DD:EE:FF", 1_700_000_300, 300)
+            .unwrap();
+        db.set_device_offline_alert("AA:BB:CC:DD:EE:FF", Some(1_700_001_000))
+            .unwrap();
+        let device = db.get_device("AA:BB:CC:DD:EE:FF").unwrap().unwrap();
+        assert_eq!(device.check_in_interval, Some(300));
+        assert_eq!(device.offline_alert_at, Some(1_700_001_000));
+
+        let broadcast = db.create_broadcast("Fire drill", 3600, "admin").unwrap();
+        assert_eq!(db.active_broadcast().unwrap().unwrap().id, broadcast.id);
+        let report = db
+            .create_issue_report("room-a", "projector", "No signal")
+            .unwrap();
+        assert_eq!(db.list_open_issue_reports(Some("room-a")).unwrap().len(), 1);
+        assert!(db.resolve_issue_report(report.id).unwrap());
+
+        db.set_room_dnd("room-a", "Board meeting", i64::MAX, "admin")
+            .unwrap();
+        db.set_room_dnd("room-a", "Offsite", i64::MAX, "admin")
+            .unwrap();
+        assert_eq!(db.list_active_dnd().unwrap()[0].meeting, "Offsite");
+        db.store_cached_calendar("https://example.com/a.ics", "v1")
+            .unwrap();
+        db.store_cached_calendar("https://example.com/a.ics", "v2")
+            .unwrap();
+        let cached = db.cached_calendar("https://example.com/a.ics").unwrap();
+        assert_eq!(cached.unwrap().data, "v2");
+
+        let code = NewClaimCode {
+            code: "ABCD-EFGH".to_string(),
+            room_id: "room-a".to_string(),
+            device_id: None,
+        };
+        db.create_claim_codes(&[code], Some(3600), "admin").unwrap();
+        let claim = db.claim_device("CC:DD", Some("ABCD-EFGH")).unwrap();
+        assert_eq!(claim.unwrap().room_id, "room-a");
+
+        db.insert_device_logs(&[DeviceLogEntry {
+            device_id: "aa:bb:cc:dd:ee:ff".to_string(),
+            message: "WiFi weak".to_string(),
+            received_at: 1_700_000_000,
+            authenticated: true,
+            logged_at: Some(1_700_000_000),
+            level: Some(LogLevel::Warn),
+        }])
+        .unwrap();
+        // Device IDs of logs are compared case-insensitively
+        let logs = db
+            .list_device_logs(
+                &DeviceLogQuery {
+                    device_id: Some("AA:BB:CC:DD:EE:FF"),
+                    ..Default::default()
+                },
+                10,
+            )
+            .unwrap();
+        assert_eq!(logs.len(), 1);
+
+        assert!(db.try_acquire_lease("rollover", "instance-a", 60).unwrap());
+        assert!(!db.try_acquire_lease("rollover", "instance-b", 60).unwrap());
+
+        let run = JobRun {
+            job: "rollover".to_string(),
+            instance_id: "instance-a".to_string(),
+            manual: true,
+            started_at: 1_700_000_000,
+            finished_at: 1_700_000_001,
+            succeeded: false,
+            message: None,
+        };
+        db.record_job_run(&run, 10).unwrap();
+        assert_eq!(db.list_job_runs("rollover", 10).unwrap(), [run]);
+
+        let day = chrono::NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let utilization = |meetings| DailyUtilization {
+            room_id: "room-a".to_string(),
+            day,
+            meetings,
+            busy_minutes: 90,
+        };
+        db.record_room_utilization(&[utilization(2)]).unwrap();
+        db.record_room_utilization(&[utilization(3)]).unwrap();
+        assert_eq!(
+            db.room_utilization("room-a", day).unwrap(),
+            [utilization(3)]
+        );
+
+        let rooms =
+            crate::rooms::parse_rooms(&std::fs::read_to_string("rooms.example.toml").unwrap())
+                .unwrap();
+        db.import_rooms(&rooms, "import-config").unwrap();
+        let stored = db.list_rooms().unwrap();
+        assert_eq!(stored.len(), rooms.len());
+        assert_eq!(stored[0].id, rooms[0].id);
+        assert_eq!(stored[0].devices, rooms[0].devices);
+    }
+}
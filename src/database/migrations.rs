@@ -0,0 +1,1020 @@
+//! Versioned schema migrations
+//!
+//! The schema version of a database is tracked in the `schema_migrations`
+//! table, with a row per applied migration. When a database is opened, the
+//! migrations newer than its version are applied in order, each in a
+//! transaction of its own, so that a failed migration leaves the database at
+//! the previous version and instances starting at the same time do not apply
+//! a migration twice. Every migration can be reverted, so that tests can check
+//! the upgrade of a database of an older version.
+//!
+//! Databases created before the schema was versioned are at version 0 but
+//! have some of the tables of the first migration, which therefore only
+//! creates the tables and columns that are missing. Later migrations can rely
+//! on the schema of the previous ones.
+
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+
+use super::storage::{Dialect, StorageConnection, params};
+use crate::device_id::DeviceId;
+
+/// Change of the schema
+pub struct Migration {
+    /// Version of the schema after the migration, counting from 1
+    pub version: u32,
+    /// Short description, e.g. `initial`
+    pub name: &'static str,
+    /// Apply the migration
+    pub up: fn(&mut dyn StorageConnection) -> Result<()>,
+    /// Revert the migration
+    pub down: fn(&mut dyn StorageConnection) -> Result<()>,
+}
+
+/// Migrations of the schema, ordered by version
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        up: initial_up,
+        down: initial_down,
+    },
+    Migration {
+        version: 2,
+        name: "device_check_ins",
+        up: device_check_ins_up,
+        down: device_check_ins_down,
+    },
+    Migration {
+        version: 3,
+        name: "normalize_device_ids",
+        up: normalize_device_ids_up,
+        down: normalize_device_ids_down,
+    },
+];
+
+/// Schema version of the current code
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// Create the table tracking the applied migrations if it doesn't exist
+fn create_migrations_table(conn: &mut dyn StorageConnection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at BIGINT NOT NULL
+        )",
+        &[],
+    )
+    .context("Failed to create schema_migrations table")?;
+    Ok(())
+}
+
+/// Schema version of a database, 0 if no migration was applied
+pub fn schema_version(conn: &mut dyn StorageConnection) -> Result<u32> {
+    create_migrations_table(conn)?;
+    let version: Option<u32> = conn
+        .query_row("SELECT MAX(version) FROM schema_migrations", &[], |row| {
+            row.get(0)
+        })
+        .context("Failed to get schema version")?
+        .flatten();
+    Ok(version.unwrap_or(0))
+}
+
+/// Apply or revert migrations until the database is at the target version
+///
+/// Migrations newer than the database's version are applied in ascending
+/// order, and applied migrations newer than the target are reverted in
+/// descending order.
+pub fn migrate(
+    conn: &mut dyn StorageConnection,
+    migrations: &[Migration],
+    target: u32,
+) -> Result<()> {
+    loop {
+        // The version is read in the transaction, as another instance may
+        // have migrated the database in the meantime
+        let mut tx = conn.transaction().context("Failed to start migration")?;
+        let version = schema_version(&mut tx)?;
+        if version < target {
+            let Some(migration) = migrations.iter().find(|m| m.version > version) else {
+                bail!("No migration to schema version {}", target);
+            };
+            info!(
+                "Migrating database to schema version {} ({})",
+                migration.version, migration.name
+            );
+            (migration.up)(&mut tx).with_context(|| {
+                format!(
+                    "Failed to migrate database to schema version {} ({})",
+                    migration.version, migration.name
+                )
+            })?;
+            tx.execute(
+                "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, ?3)",
+                params![
+                    migration.version,
+                    migration.name,
+                    chrono::Utc::now().timestamp()
+                ],
+            )
+            .context("Failed to record migration")?;
+        } else if version > target {
+            let Some(migration) = migrations.iter().find(|m| m.version == version) else {
+                bail!("Unknown schema version {}", version);
+            };
+            info!(
+                "Reverting database schema version {} ({})",
+                migration.version, migration.name
+            );
+            (migration.down)(&mut tx).with_context(|| {
+                format!(
+                    "Failed to revert database schema version {} ({})",
+                    migration.version, migration.name
+                )
+            })?;
+            tx.execute(
+                "DELETE FROM schema_migrations WHERE version = ?1",
+                params![migration.version],
+            )
+            .context("Failed to record migration")?;
+        } else {
+            return Ok(());
+        }
+        tx.commit().context("Failed to commit migration")?;
+    }
+}
+
+/// Schema as of the introduction of versioned migrations
+fn initial_up(conn: &mut dyn StorageConnection) -> Result<()> {
+    match conn.dialect() {
+        Dialect::Sqlite => initial_up_sqlite(conn),
+        Dialect::Postgres => initial_up_postgres(conn),
+    }
+}
+
+fn initial_up_sqlite(conn: &mut dyn StorageConnection) -> Result<()> {
+    // Create devices table if it doesn't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS devices (
+            id TEXT PRIMARY KEY,
+            registered_at INTEGER NOT NULL,
+            room_id TEXT,
+            model TEXT,
+            last_payload_format TEXT,
+            last_payload_bytes INTEGER,
+            image_delivery TEXT
+        )",
+        &[],
+    )
+    .context("Failed to create devices table")?;
+    add_column_if_missing(conn, "devices", "room_id", "TEXT")?;
+    add_column_if_missing(conn, "devices", "model", "TEXT")?;
+    add_column_if_missing(conn, "devices", "last_payload_format", "TEXT")?;
+    add_column_if_missing(conn, "devices", "last_payload_bytes", "INTEGER")?;
+    add_column_if_missing(conn, "devices", "image_delivery", "TEXT")?;
+    add_column_if_missing(conn, "devices", "last_seen_at", "INTEGER")?;
+    add_column_if_missing(conn, "devices", "battery_voltage", "REAL")?;
+    add_column_if_missing(conn, "devices", "api_key", "TEXT")?;
+    add_column_if_missing(conn, "devices", "api_key_revoked_at", "INTEGER")?;
+    add_column_if_missing(
+        conn,
+        "devices",
+        "battery_critical",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+    add_column_if_missing(conn, "devices", "retired_at", "INTEGER")?;
+    add_column_if_missing(conn, "devices", "replaced_by", "TEXT")?;
+    add_column_if_missing(conn, "devices", "name", "TEXT")?;
+    add_column_if_missing(conn, "devices", "firmware_version", "TEXT")?;
+    add_column_if_missing(conn, "devices", "image_format", "TEXT")?;
+    add_column_if_missing(conn, "devices", "display_alert_at", "INTEGER")?;
+    add_column_if_missing(conn, "devices", "rssi", "INTEGER")?;
+
+    // Create battery readings table if it doesn't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS battery_readings (
+            device_id TEXT NOT NULL,
+            voltage REAL NOT NULL,
+            recorded_at INTEGER NOT NULL
+        )",
+        &[],
+    )
+    .context("Failed to create battery_readings table")?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS battery_readings_device
+         ON battery_readings (device_id, recorded_at)",
+        &[],
+    )
+    .context("Failed to create battery_readings index")?;
+
+    // Create display fetches table if it doesn't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS display_fetches (
+            device_id TEXT NOT NULL,
+            fetched_at INTEGER NOT NULL
+        )",
+        &[],
+    )
+    .context("Failed to create display_fetches table")?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS display_fetches_time ON display_fetches (fetched_at)",
+        &[],
+    )
+    .context("Failed to create display_fetches index")?;
+
+    // Create broadcasts table if it doesn't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS broadcasts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            message TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            triggered_by TEXT NOT NULL,
+            cleared_at INTEGER
+        )",
+        &[],
+    )
+    .context("Failed to create broadcasts table")?;
+
+    // Create maintenance mode table if it doesn't exist, with at most one row
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS maintenance (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            message TEXT,
+            started_at INTEGER NOT NULL,
+            started_by TEXT NOT NULL
+        )",
+        &[],
+    )
+    .context("Failed to create maintenance table")?;
+
+    // Create layout experiments table if it doesn't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS experiments (
+            name TEXT PRIMARY KEY,
+            layout TEXT NOT NULL,
+            percent INTEGER NOT NULL,
+            room_id TEXT,
+            state TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            created_by TEXT NOT NULL
+        )",
+        &[],
+    )
+    .context("Failed to create experiments table")?;
+
+    // Create room utilization table if it doesn't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS room_utilization (
+            room_id TEXT NOT NULL,
+            day TEXT NOT NULL,
+            meetings INTEGER NOT NULL,
+            busy_minutes INTEGER NOT NULL,
+            PRIMARY KEY (room_id, day)
+        )",
+        &[],
+    )
+    .context("Failed to create room utilization table")?;
+
+    // Create do-not-disturb table if it doesn't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS room_dnd (
+            room_id TEXT PRIMARY KEY,
+            meeting TEXT NOT NULL,
+            set_at INTEGER NOT NULL,
+            expires_at INTEGER NOT NULL,
+            set_by TEXT NOT NULL
+        )",
+        &[],
+    )
+    .context("Failed to create room_dnd table")?;
+
+    // Create audit log table if it doesn't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp INTEGER NOT NULL,
+            actor TEXT NOT NULL,
+            action TEXT NOT NULL,
+            details TEXT NOT NULL
+        )",
+        &[],
+    )
+    .context("Failed to create audit_log table")?;
+
+    // Create issue reports table if it doesn't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS issue_reports (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            room_id TEXT NOT NULL,
+            category TEXT NOT NULL,
+            description TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            resolved_at INTEGER
+        )",
+        &[],
+    )
+    .context("Failed to create issue_reports table")?;
+
+    // Create calendar cache table if it doesn't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS calendar_cache (
+            url TEXT PRIMARY KEY,
+            data TEXT NOT NULL,
+            fetched_at INTEGER NOT NULL
+        )",
+        &[],
+    )
+    .context("Failed to create calendar_cache table")?;
+
+    // Create leases table if it doesn't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS leases (
+            name TEXT PRIMARY KEY,
+            holder TEXT NOT NULL,
+            expires_at INTEGER NOT NULL
+        )",
+        &[],
+    )
+    .context("Failed to create leases table")?;
+
+    // Create job tables if they don't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS job_runs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            job TEXT NOT NULL,
+            instance_id TEXT NOT NULL,
+            manual INTEGER NOT NULL,
+            started_at INTEGER NOT NULL,
+            finished_at INTEGER NOT NULL,
+            succeeded INTEGER NOT NULL,
+            message TEXT
+        )",
+        &[],
+    )
+    .context("Failed to create job_runs table")?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS job_runs_job ON job_runs (job, id)",
+        &[],
+    )
+    .context("Failed to create job_runs index")?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS paused_jobs (
+            job TEXT PRIMARY KEY,
+            paused_at INTEGER NOT NULL,
+            paused_by TEXT NOT NULL
+        )",
+        &[],
+    )
+    .context("Failed to create paused_jobs table")?;
+
+    // Create claim codes table if it doesn't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS claim_codes (
+            code TEXT PRIMARY KEY,
+            room_id TEXT NOT NULL,
+            device_id TEXT,
+            created_at INTEGER NOT NULL,
+            expires_at INTEGER,
+            claimed_at INTEGER
+        )",
+        &[],
+    )
+    .context("Failed to create claim_codes table")?;
+
+    // Create rooms tables if they don't exist. Settings other than the
+    // name and calendar are stored as JSON, so that new room settings
+    // don't require schema changes.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS rooms (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            calendar_url TEXT,
+            settings TEXT NOT NULL,
+            updated_at INTEGER NOT NULL
+        )",
+        &[],
+    )
+    .context("Failed to create rooms table")?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS room_devices (
+            device_id TEXT PRIMARY KEY COLLATE NOCASE,
+            room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE
+        )",
+        &[],
+    )
+    .context("Failed to create room_devices table")?;
+
+    // Create device logs table if it doesn't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS device_logs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            device_id TEXT NOT NULL,
+            message TEXT NOT NULL,
+            received_at INTEGER NOT NULL
+        )",
+        &[],
+    )
+    .context("Failed to create device_logs table")?;
+    add_column_if_missing(
+        conn,
+        "device_logs",
+        "authenticated",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+    add_column_if_missing(conn, "device_logs", "logged_at", "INTEGER")?;
+    add_column_if_missing(conn, "device_logs", "level", "TEXT")?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS device_logs_device ON device_logs (device_id, id)",
+        &[],
+    )
+    .context("Failed to create device_logs index")?;
+
+    // Create provisioned devices table if it doesn't exist
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS provisioned_devices (
+            device_id TEXT PRIMARY KEY COLLATE NOCASE,
+            room_id TEXT NOT NULL,
+            label TEXT,
+            model TEXT,
+            firmware_channel TEXT NOT NULL,
+            claim_code TEXT NOT NULL,
+            api_key TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        )",
+        &[],
+    )
+    .context("Failed to create provisioned_devices table")?;
+    Ok(())
+}
+
+/// Schema of a new Postgres database
+///
+/// Postgres databases are versioned from the start, so the tables are
+/// created as they are. Columns hold the same values as in SQLite: booleans
+/// are integers, and tables listed in insertion order have a `rowid` column
+/// standing in for SQLite's implicit one. Device IDs are compared
+/// case-insensitively with the `nocase` collation, like SQLite's `NOCASE`.
+fn initial_up_postgres(conn: &mut dyn StorageConnection) -> Result<()> {
+    for statement in [
+        "CREATE COLLATION IF NOT EXISTS nocase (
+            provider = icu, locale = 'und-u-ks-level2', deterministic = false
+        )",
+        "CREATE TABLE IF NOT EXISTS devices (
+            id TEXT PRIMARY KEY,
+            registered_at BIGINT NOT NULL,
+            room_id TEXT,
+            model TEXT,
+            last_payload_format TEXT,
+            last_payload_bytes BIGINT,
+            image_delivery TEXT,
+            last_seen_at BIGINT,
+            battery_voltage DOUBLE PRECISION,
+            api_key TEXT,
+            api_key_revoked_at BIGINT,
+            battery_critical BIGINT NOT NULL DEFAULT 0,
+            retired_at BIGINT,
+            replaced_by TEXT,
+            name TEXT,
+            firmware_version TEXT,
+            image_format TEXT,
+            display_alert_at BIGINT,
+            rssi BIGINT
+        )",
+        "CREATE TABLE IF NOT EXISTS battery_readings (
+            rowid BIGSERIAL PRIMARY KEY,
+            device_id TEXT NOT NULL,
+            voltage DOUBLE PRECISION NOT NULL,
+            recorded_at BIGINT NOT NULL
+        )",
+        "CREATE INDEX IF NOT EXISTS battery_readings_device
+         ON battery_readings (device_id, recorded_at)",
+        "CREATE TABLE IF NOT EXISTS display_fetches (
+            rowid BIGSERIAL PRIMARY KEY,
+            device_id TEXT NOT NULL,
+            fetched_at BIGINT NOT NULL
+        )",
+        "CREATE INDEX IF NOT EXISTS display_fetches_time ON display_fetches (fetched_at)",
+        "CREATE TABLE IF NOT EXISTS broadcasts (
+            id BIGSERIAL PRIMARY KEY,
+            message TEXT NOT NULL,
+            created_at BIGINT NOT NULL,
+            expires_at BIGINT NOT NULL,
+            triggered_by TEXT NOT NULL,
+            cleared_at BIGINT
+        )",
+        "CREATE TABLE IF NOT EXISTS maintenance (
+            id BIGINT PRIMARY KEY CHECK (id = 1),
+            message TEXT,
+            started_at BIGINT NOT NULL,
+            started_by TEXT NOT NULL
+        )",
+        "CREATE TABLE IF NOT EXISTS experiments (
+            name TEXT PRIMARY KEY,
+            layout TEXT NOT NULL,
+            percent BIGINT NOT NULL,
+            room_id TEXT,
+            state TEXT NOT NULL,
+            created_at BIGINT NOT NULL,
+            created_by TEXT NOT NULL
+        )",
+        "CREATE TABLE IF NOT EXISTS room_utilization (
+            room_id TEXT NOT NULL,
+            day TEXT NOT NULL,
+            meetings BIGINT NOT NULL,
+            busy_minutes BIGINT NOT NULL,
+            PRIMARY KEY (room_id, day)
+        )",
+        "CREATE TABLE IF NOT EXISTS room_dnd (
+            room_id TEXT PRIMARY KEY,
+            meeting TEXT NOT NULL,
+            set_at BIGINT NOT NULL,
+            expires_at BIGINT NOT NULL,
+            set_by TEXT NOT NULL
+        )",
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id BIGSERIAL PRIMARY KEY,
+            timestamp BIGINT NOT NULL,
+            actor TEXT NOT NULL,
+            action TEXT NOT NULL,
+            details TEXT NOT NULL
+        )",
+        "CREATE TABLE IF NOT EXISTS issue_reports (
+            id BIGSERIAL PRIMARY KEY,
+            room_id TEXT NOT NULL,
+            category TEXT NOT NULL,
+            description TEXT NOT NULL,
+            created_at BIGINT NOT NULL,
+            resolved_at BIGINT
+        )",
+        "CREATE TABLE IF NOT EXISTS calendar_cache (
+            url TEXT PRIMARY KEY,
+            data TEXT NOT NULL,
+            fetched_at BIGINT NOT NULL
+        )",
+        "CREATE TABLE IF NOT EXISTS leases (
+            name TEXT PRIMARY KEY,
+            holder TEXT NOT NULL,
+            expires_at BIGINT NOT NULL
+        )",
+        "CREATE TABLE IF NOT EXISTS job_runs (
+            id BIGSERIAL PRIMARY KEY,
+            job TEXT NOT NULL,
+            instance_id TEXT NOT NULL,
+            manual BIGINT NOT NULL,
+            started_at BIGINT NOT NULL,
+            finished_at BIGINT NOT NULL,
+            succeeded BIGINT NOT NULL,
+            message TEXT
+        )",
+        "CREATE INDEX IF NOT EXISTS job_runs_job ON job_runs (job, id)",
+        "CREATE TABLE IF NOT EXISTS paused_jobs (
+            job TEXT PRIMARY KEY,
+            paused_at BIGINT NOT NULL,
+            paused_by TEXT NOT NULL
+        )",
+        "CREATE TABLE IF NOT EXISTS claim_codes (
+            code TEXT PRIMARY KEY,
+            room_id TEXT NOT NULL,
+            device_id TEXT,
+            created_at BIGINT NOT NULL,
+            expires_at BIGINT,
+            claimed_at BIGINT
+        )",
+        "CREATE TABLE IF NOT EXISTS rooms (
+            rowid BIGSERIAL UNIQUE,
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            calendar_url TEXT,
+            settings TEXT NOT NULL,
+            updated_at BIGINT NOT NULL
+        )",
+        "CREATE TABLE IF NOT EXISTS room_devices (
+            rowid BIGSERIAL UNIQUE,
+            device_id TEXT COLLATE nocase PRIMARY KEY,
+            room_id TEXT NOT NULL REFERENCES rooms (id) ON DELETE CASCADE
+        )",
+        "CREATE TABLE IF NOT EXISTS device_logs (
+            id BIGSERIAL PRIMARY KEY,
+            device_id TEXT NOT NULL,
+            message TEXT NOT NULL,
+            received_at BIGINT NOT NULL,
+            authenticated BIGINT NOT NULL DEFAULT 0,
+            logged_at BIGINT,
+            level TEXT
+        )",
+        "CREATE INDEX IF NOT EXISTS device_logs_device ON device_logs (device_id, id)",
+        "CREATE TABLE IF NOT EXISTS provisioned_devices (
+            device_id TEXT COLLATE nocase PRIMARY KEY,
+            room_id TEXT NOT NULL,
+            label TEXT,
+            model TEXT,
+            firmware_channel TEXT NOT NULL,
+            claim_code TEXT NOT NULL,
+            api_key TEXT NOT NULL,
+            created_at BIGINT NOT NULL,
+            updated_at BIGINT NOT NULL
+        )",
+    ] {
+        conn.execute(statement, &[]).with_context(|| {
+            format!(
+                "Failed to create schema: {}",
+                statement.lines().next().unwrap_or_default()
+            )
+        })?;
+    }
+    Ok(())
+}
+
+fn initial_down(conn: &mut dyn StorageConnection) -> Result<()> {
+    for table in [
+        "room_devices",
+        "rooms",
+        "devices",
+        "battery_readings",
+        "display_fetches",
+        "broadcasts",
+        "maintenance",
+        "experiments",
+        "room_utilization",
+        "room_dnd",
+        "audit_log",
+        "issue_reports",
+        "calendar_cache",
+        "leases",
+        "job_runs",
+        "paused_jobs",
+        "claim_codes",
+        "device_logs",
+        "provisioned_devices",
+    ] {
+        conn.execute(&format!("DROP TABLE IF EXISTS {}", table), &[])
+            .with_context(|| format!("Failed to drop {} table", table))?;
+    }
+    if conn.dialect() == Dialect::Postgres {
+        conn.execute("DROP COLLATION IF EXISTS nocase", &[])
+            .context("Failed to drop nocase collation")?;
+    }
+    Ok(())
+}
+
+/// Columns of the devices table to tell when a device misses its check-ins
+const CHECK_IN_COLUMNS: [&str; 3] = ["next_check_in_at", "check_in_interval", "offline_alert_at"];
+
+/// When each device is expected to check in next, and since when it is
+/// reported offline
+fn device_check_ins_up(conn: &mut dyn StorageConnection) -> Result<()> {
+    for column in CHECK_IN_COLUMNS {
+        conn.execute(
+            &format!("ALTER TABLE devices ADD COLUMN {} BIGINT", column),
+            &[],
+        )
+        .with_context(|| format!("Failed to add {} column to devices table", column))?;
+    }
+    Ok(())
+}
+
+fn device_check_ins_down(conn: &mut dyn StorageConnection) -> Result<()> {
+    for column in CHECK_IN_COLUMNS {
+        conn.execute(&format!("ALTER TABLE devices DROP COLUMN {}", column), &[])
+            .with_context(|| format!("Failed to drop {} column of devices table", column))?;
+    }
+    Ok(())
+}
+
+/// Columns holding device IDs, and whether their values are unique
+const DEVICE_ID_COLUMNS: [(&str, &str, bool); 8] = [
+    ("devices", "id", true),
+    ("devices", "replaced_by", false),
+    ("battery_readings", "device_id", false),
+    ("display_fetches", "device_id", false),
+    ("device_logs", "device_id", false),
+    ("claim_codes", "device_id", false),
+    ("provisioned_devices", "device_id", true),
+    ("room_devices", "device_id", true),
+];
+
+/// Rewrite the device IDs stored before IDs were normalized, so that IDs
+/// differing only in case or spelling cannot be registered twice
+///
+/// IDs that are neither a MAC address nor a UUID are kept, as are IDs whose
+/// normalized form is stored already where IDs are unique; both are logged.
+fn normalize_device_ids_up(conn: &mut dyn StorageConnection) -> Result<()> {
+    let mut ids = BTreeSet::new();
+    for (table, column, _) in DEVICE_ID_COLUMNS {
+        ids.extend(
+            conn.query_map(
+                &format!("SELECT DISTINCT {column} FROM {table} WHERE {column} IS NOT NULL"),
+                &[],
+                |row| row.get::<String>(0),
+            )
+            .with_context(|| format!("Failed to list device IDs in {} table", table))?,
+        );
+    }
+
+    let mut renamed = HashMap::new();
+    for id in ids {
+        let Ok(normalized) = id.parse::<DeviceId>() else {
+            warn!(
+                "Keeping device ID {}, it is neither a MAC address nor a UUID",
+                id
+            );
+            continue;
+        };
+        let normalized = normalized.into_string();
+        if normalized == id {
+            continue;
+        }
+        let mut clashes = false;
+        for (table, column, _) in DEVICE_ID_COLUMNS.iter().filter(|(_, _, unique)| *unique) {
+            let count: i64 = conn
+                .query_row(
+                    &format!(
+                        "SELECT COUNT(*) FROM {table}
+                         WHERE {column} = ?1 COLLATE NOCASE AND {column} <> ?2
+                         AND EXISTS (SELECT 1 FROM {table} WHERE {column} = ?2)"
+                    ),
+                    params![normalized, id],
+                    |row| row.get(0),
+                )?
+                .unwrap_or(0);
+            clashes |= count > 0;
+        }
+        if clashes {
+            warn!(
+                "Not normalizing device ID {}, {} is registered already",
+                id, normalized
+            );
+            continue;
+        }
+        for (table, column, _) in DEVICE_ID_COLUMNS {
+            conn.execute(
+                &format!("UPDATE {table} SET {column} = ?2 WHERE {column} = ?1"),
+                params![id, normalized],
+            )
+            .with_context(|| format!("Failed to normalize device ID {} in {} table", id, table))?;
+        }
+        info!("Normalized device ID {} to {}", id, normalized);
+        renamed.insert(id, normalized);
+    }
+
+    // Layouts of single devices are kept in the settings of their room
+    let rooms = conn
+        .query_map("SELECT id, settings FROM rooms", &[], |row| {
+            Ok((row.get::<String>(0)?, row.get::<String>(1)?))
+        })
+        .context("Failed to list rooms")?;
+    for (room_id, settings) in rooms {
+        let mut settings: serde_json::Value = serde_json::from_str(&settings)
+            .with_context(|| format!("Invalid settings of room {}", room_id))?;
+        let Some(layouts) = settings
+            .get_mut("device_layouts")
+            .and_then(|layouts| layouts.as_object_mut())
+        else {
+            continue;
+        };
+        if !layouts.keys().any(|id| renamed.contains_key(id)) {
+            continue;
+        }
+        *layouts = std::mem::take(layouts)
+            .into_iter()
+            .map(|(id, layout)| (renamed.get(&id).cloned().unwrap_or(id), layout))
+            .collect();
+        conn.execute(
+            "UPDATE rooms SET settings = ?2 WHERE id = ?1",
+            params![room_id, settings.to_string()],
+        )
+        .with_context(|| format!("Failed to normalize device IDs of room {}", room_id))?;
+    }
+    Ok(())
+}
+
+/// The IDs as stored before are not kept, normalized IDs work all the same
+fn normalize_device_ids_down(_: &mut dyn StorageConnection) -> Result<()> {
+    Ok(())
+}
+
+/// Add a column to an existing table, unless it's already there
+///
+/// Tables are created with all columns, this only upgrades databases created
+/// before the schema was versioned.
+fn add_column_if_missing(
+    conn: &mut dyn StorageConnection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<()> {
+    let exists = conn
+        .query_map(&format!("PRAGMA table_info({})", table), &[], |row| {
+            row.get::<String>(1)
+        })
+        .with_context(|| format!("Failed to read columns of {} table", table))?
+        .iter()
+        .any(|name| name == column);
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            &[],
+        )
+        .with_context(|| format!("Failed to add {} column to {} table", column, table))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use crate::database::sqlite::SqliteStorage;
+    use crate::database::storage::Storage;
+
+    fn tables(conn: &mut dyn StorageConnection) -> Vec<String> {
+        conn.query_map(
+            "SELECT name FROM sqlite_master
+             WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+            &[],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_migrate_up_and_down() {
+        let migrations = [
+            Migration {
+                version: 1,
+                name: "first",
+                up: |conn| {
+                    conn.execute("CREATE TABLE first (id INTEGER)", &[])
+                        .map(drop)
+                },
+                down: |conn| conn.execute("DROP TABLE first", &[]).map(drop),
+            },
+            Migration {
+                version: 2,
+                name: "second",
+                up: |conn| {
+                    conn.execute("CREATE TABLE second (id INTEGER)", &[])
+                        .map(drop)
+                },
+                down: |conn| conn.execute("DROP TABLE second", &[]).map(drop),
+            },
+        ];
+        let storage = SqliteStorage::open(":memory:", 1).unwrap();
+        let mut conn = storage.connection().unwrap();
+        assert_eq!(schema_version(&mut *conn).unwrap(), 0);
+
+        migrate(&mut *conn, &migrations, 2).unwrap();
+        assert_eq!(schema_version(&mut *conn).unwrap(), 2);
+        assert_eq!(tables(&mut *conn), ["first", "schema_migrations", "second"]);
+
+        migrate(&mut *conn, &migrations, 1).unwrap();
+        assert_eq!(schema_version(&mut *conn).unwrap(), 1);
+        assert_eq!(tables(&mut *conn), ["first", "schema_migrations"]);
+
+        assert!(migrate(&mut *conn, &migrations, 3).is_err());
+        assert_eq!(schema_version(&mut *conn).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_failed_migration_is_rolled_back() {
+        let migrations = [Migration {
+            version: 1,
+            name: "broken",
+            up: |conn| {
+                conn.execute("CREATE TABLE broken (id INTEGER)", &[])?;
+                bail!("Disk full")
+            },
+            down: |_| Ok(()),
+        }];
+        let storage = SqliteStorage::open(":memory:", 1).unwrap();
+        let mut conn = storage.connection().unwrap();
+        assert!(migrate(&mut *conn, &migrations, 1).is_err());
+        assert_eq!(schema_version(&mut *conn).unwrap(), 0);
+        assert_eq!(tables(&mut *conn), ["schema_migrations"]);
+    }
+
+    #[test]
+    fn test_migrations_are_reversible() {
+        let storage = SqliteStorage::open(":memory:", 1).unwrap();
+        let mut conn = storage.connection().unwrap();
+        migrate(&mut *conn, MIGRATIONS, latest_version()).unwrap();
+        migrate(&mut *conn, MIGRATIONS, 0).unwrap();
+        assert_eq!(tables(&mut *conn), ["schema_migrations"]);
+        migrate(&mut *conn, MIGRATIONS, latest_version()).unwrap();
+        assert!(tables(&mut *conn).contains(&"devices".to_string()));
+    }
+
+    #[test]
+    fn test_unversioned_database_is_upgraded() {
+        let path = std::env::temp_dir().join(format!("trmnl-migrations-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        {
+            let conn = rusqlite::Connection::open(path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE devices (id TEXT PRIMARY KEY, registered_at INTEGER NOT NULL);
+                 INSERT INTO devices VALUES ('AA:BB:CC:DD:EE:FF', 1700000000);",
+            )
+            .unwrap();
+        }
+
+        let db = Database::new(path).unwrap();
+        let device = db.get_device("AA:BB:CC:DD:EE:FF").unwrap().unwrap();
+        assert_eq!(device.registered_at, 1_700_000_000);
+        assert_eq!(device.api_key, None);
+        drop(db);
+
+        // Databases of newer versions are not touched
+        {
+            let storage = SqliteStorage::open(path, 1).unwrap();
+            let mut conn = storage.connection().unwrap();
+            assert_eq!(schema_version(&mut *conn).unwrap(), latest_version());
+            conn.execute(
+                "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, 'future', 0)",
+                params![latest_version() + 1],
+            )
+            .unwrap();
+        }
+        assert!(Database::new(path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_legacy_device_ids_are_normalized() {
+        let storage = SqliteStorage::open(":memory:", 1).unwrap();
+        let mut conn = storage.connection().unwrap();
+        migrate(&mut *conn, MIGRATIONS, 2).unwrap();
+        for sql in [
+            "INSERT INTO devices (id, registered_at) VALUES
+                ('aa:bb:cc:dd:ee:ff', 0),
+                ('{123E4567-E89B-12D3-A456-426614174000}', 0),
+                ('test-device', 0),
+                ('11-22-33-44-55-66', 0),
+                ('112233445566', 0)",
+            "INSERT INTO display_fetches (device_id, fetched_at) VALUES ('aa:bb:cc:dd:ee:ff', 0)",
+            "INSERT INTO device_logs (device_id, message, received_at)
+             VALUES ('aa:bb:cc:dd:ee:ff', 'Booted', 0)",
+            "INSERT INTO claim_codes (code, room_id, device_id, created_at)
+             VALUES ('ABCD-EFGH', 'room-a', 'aa-bb-cc-dd-ee-ff', 0)",
+            "INSERT INTO provisioned_devices (device_id, room_id, firmware_channel,
+                 claim_code, api_key, created_at, updated_at)
+             VALUES ('a1b2c3d4e5f6', 'room-a', 'stable', 'ABCD-EFGH', 'key', 0, 0)",
+            "INSERT INTO rooms (id, name, settings, updated_at)
+             VALUES ('room-a', 'Room A', '{\"device_layouts\":{\"aa:bb:cc:dd:ee:ff\":\"compact\"}}', 0)",
+            "INSERT INTO room_devices (device_id, room_id) VALUES ('aa:bb:cc:dd:ee:ff', 'room-a')",
+        ] {
+            conn.execute(sql, &[]).unwrap();
+        }
+
+        migrate(&mut *conn, MIGRATIONS, latest_version()).unwrap();
+        let ids = |conn: &mut dyn StorageConnection, table: &str| -> Vec<String> {
+            conn.query_map(
+                &format!("SELECT device_id FROM {} ORDER BY device_id", table),
+                &[],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        let devices: Vec<String> = conn
+            .query_map("SELECT id FROM devices ORDER BY id", &[], |row| row.get(0))
+            .unwrap();
+        // Same device in another spelling, cannot be normalized as well
+        assert_eq!(
+            devices,
+            [
+                "112233445566",
+                "11:22:33:44:55:66",
+                "123e4567-e89b-12d3-a456-426614174000",
+                "AA:BB:CC:DD:EE:FF",
+                "test-device",
+            ]
+        );
+        for table in [
+            "display_fetches",
+            "device_logs",
+            "claim_codes",
+            "room_devices",
+        ] {
+            assert_eq!(ids(&mut *conn, table), ["AA:BB:CC:DD:EE:FF"], "{}", table);
+        }
+        assert_eq!(
+            ids(&mut *conn, "provisioned_devices"),
+            ["A1:B2:C3:D4:E5:F6"]
+        );
+        let settings: String = conn
+            .query_row("SELECT settings FROM rooms", &[], |row| row.get(0))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            settings,
+            r#"{"device_layouts":{"AA:BB:CC:DD:EE:FF":"compact"}}"#
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Pool of database connections
+//!
+//! Every database operation takes a connection from the pool and returns it
+//! when done, so that requests don't wait for each other while the database
+//! is busy. A request waiting longer than [`CONNECTION_TIMEOUT`] fails with
+//! [`PoolTimeout`], which handlers report as overload.
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use thiserror::Error;
+
+/// How long a request waits for a connection while all are in use
+pub const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Error of a request for a connection that none was returned in time for
+#[derive(Debug, Error)]
+#[error("Timed out waiting for a database connection")]
+pub struct PoolTimeout;
+
+/// Fixed set of connections to one database
+pub struct ConnectionPool<C> {
+    idle: Mutex<Vec<C>>,
+    returned: Condvar,
+    timeout: Duration,
+}
+
+impl<C> ConnectionPool<C> {
+    /// Pool of the given connections
+    pub fn new(connections: Vec<C>) -> Self {
+        Self {
+            idle: Mutex::new(connections),
+            returned: Condvar::new(),
+            timeout: CONNECTION_TIMEOUT,
+        }
+    }
+
+    /// Wait for a connection for the given time rather than [`CONNECTION_TIMEOUT`]
+    #[cfg(test)]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Take a connection, waiting until one is returned if all are in use
+    pub fn get(&self) -> Result<PooledConnection<'_, C>> {
+        let deadline = Instant::now() + self.timeout;
+        let mut idle = self
+            .idle
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to acquire lock on connection pool: {}", e))?;
+        loop {
+            if let Some(conn) = idle.pop() {
+                return Ok(PooledConnection {
+                    pool: self,
+                    conn: Some(conn),
+                });
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(PoolTimeout.into());
+            }
+            idle = self
+                .returned
+                .wait_timeout(idle, remaining)
+                .map_err(|e| anyhow::anyhow!("Failed to wait for database connection: {}", e))?
+                .0;
+        }
+    }
+
+    /// Number of connections not in use
+    #[cfg(test)]
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+}
+
+/// Connection taken from a pool, returned to it when dropped
+pub struct PooledConnection<'a, C> {
+    pool: &'a ConnectionPool<C>,
+    conn: Option<C>,
+}
+
+impl<C> Deref for PooledConnection<'_, C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        self.conn
+            .as_ref()
+            .expect("connection is returned on drop only")
+    }
+}
+
+impl<C> DerefMut for PooledConnection<'_, C> {
+    fn deref_mut(&mut self) -> &mut C {
+        self.conn
+            .as_mut()
+            .expect("connection is returned on drop only")
+    }
+}
+
+impl<C> Drop for PooledConnection<'_, C> {
+    fn drop(&mut self) {
+        if let (Some(conn), Ok(mut idle)) = (self.conn.take(), self.pool.idle.lock()) {
+            idle.push(conn);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn test_pool_hands_out_each_connection_once() {
+        let pool = Arc::new(ConnectionPool::new(vec![1, 2]));
+
+        let first = pool.get().unwrap();
+        let second = pool.get().unwrap();
+        assert_ne!(*first, *second);
+
+        // A third request waits until a connection is returned
+        let waiting = std::thread::spawn({
+            let pool = pool.clone();
+            move || *pool.get().unwrap()
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+        let returned = *first;
+        drop(first);
+        assert_eq!(waiting.join().unwrap(), returned);
+        drop(second);
+        assert_eq!(pool.idle(), 2);
+    }
+
+    #[test]
+    fn test_waiting_for_a_connection_times_out() {
+        let pool = ConnectionPool::new(vec![1]).with_timeout(Duration::from_millis(50));
+
+        let conn = pool.get().unwrap();
+        let error = pool.get().err().unwrap();
+        assert!(error.is::<PoolTimeout>());
+        drop(conn);
+        assert!(pool.get().is_ok());
+    }
+}
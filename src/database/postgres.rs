@@ -0,0 +1,325 @@
+//! Postgres storage
+//!
+//! The `postgres` client blocks on an async runtime of its own, which must not
+//! happen on the threads of the server's runtime. Each connection of the pool
+//! is therefore driven by a thread of its own, running the statements sent to
+//! it. A lost connection is reestablished with the next statement, unless it
+//! was lost during a transaction, which fails instead.
+//!
+//! Transactions take a transaction-level advisory lock, so that they are
+//! serialized across all instances like SQLite's immediate transactions.
+//!
+//! With the `tls` feature, connections are encrypted as the URL's `sslmode`
+//! asks for. Without it, `sslmode=require` is rejected, and `sslmode=prefer`
+//! (the default) connects unencrypted.
+
+#[cfg(feature = "tls")]
+mod tls;
+
+use std::{collections::HashMap, sync::mpsc};
+
+use anyhow::{Context, Result, anyhow, bail};
+use postgres::{
+    Client, Config, Statement,
+    types::{ToSql, Type},
+};
+
+use super::pool::{ConnectionPool, PooledConnection};
+use super::storage::{Dialect, Row, Storage, StorageConnection, Value, numbered_dollar_params};
+
+/// Key of the advisory lock serializing transactions
+const TRANSACTION_LOCK: i64 = 0x0074_726d_6e6c;
+
+/// Connector of the connections
+#[cfg(feature = "tls")]
+type Connector = tls::NativeTlsConnector;
+#[cfg(not(feature = "tls"))]
+type Connector = postgres::NoTls;
+
+/// Connector of the connections, rejecting a configuration it cannot serve
+#[cfg(feature = "tls")]
+fn connector(_config: &Config) -> Result<Connector> {
+    let connector = native_tls::TlsConnector::new().context("Failed to set up TLS for Postgres")?;
+    Ok(tls::NativeTlsConnector::new(connector))
+}
+
+/// Connector of the connections, rejecting a configuration it cannot serve
+#[cfg(not(feature = "tls"))]
+fn connector(config: &Config) -> Result<Connector> {
+    // Without a TLS connector, `prefer` falls back to an unencrypted
+    // connection, while `require` would fail on every connection attempt
+    if matches!(config.get_ssl_mode(), postgres::config::SslMode::Require) {
+        bail!(
+            "TLS connections to Postgres require the tls feature, \
+             use sslmode=disable or sslmode=prefer"
+        );
+    }
+    Ok(postgres::NoTls)
+}
+
+/// Pool of connections to a Postgres database
+pub struct PostgresStorage {
+    pool: ConnectionPool<Worker>,
+}
+
+impl PostgresStorage {
+    /// Connect to the database at `url` with a pool of `size` connections
+    pub fn open(url: &str, size: usize) -> Result<Self> {
+        let config: Config = url.parse().context("Invalid Postgres URL")?;
+        let connector = connector(&config)?;
+        let connections = (0..size.max(1))
+            .map(|_| Worker::spawn(config.clone(), Connector::clone(&connector)))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            pool: ConnectionPool::new(connections),
+        })
+    }
+}
+
+impl Storage for PostgresStorage {
+    fn connection(&self) -> Result<Box<dyn StorageConnection + '_>> {
+        Ok(Box::new(self.pool.get()?))
+    }
+}
+
+type Job = Box<dyn FnOnce(&mut Session) + Send>;
+
+/// Handle of the thread driving a connection
+pub struct Worker {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl Worker {
+    /// Start the thread and connect
+    fn spawn(config: Config, connector: Connector) -> Result<Self> {
+        let (jobs, received) = mpsc::channel::<Job>();
+        std::thread::Builder::new()
+            .name("postgres".to_string())
+            .spawn(move || {
+                let mut session = Session {
+                    config,
+                    connector,
+                    client: None,
+                    statements: HashMap::new(),
+                    in_transaction: false,
+                };
+                for job in received {
+                    job(&mut session);
+                }
+            })
+            .context("Failed to start Postgres connection thread")?;
+        let worker = Self { jobs };
+        worker.run(|session| session.client().map(|_| ()))?;
+        Ok(worker)
+    }
+
+    /// Run `job` on the connection and wait for its result
+    fn run<T: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut Session) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let (result, received) = mpsc::sync_channel(1);
+        self.jobs
+            .send(Box::new(move |session| {
+                let _ = result.send(job(session));
+            }))
+            .map_err(|_| anyhow!("Postgres connection thread exited"))?;
+        received
+            .recv()
+            .map_err(|_| anyhow!("Postgres connection thread exited"))?
+    }
+}
+
+/// Connection state, owned by the thread of a [`Worker`]
+struct Session {
+    config: Config,
+    connector: Connector,
+    client: Option<Client>,
+    /// Prepared statements by SQL, of the current client
+    statements: HashMap<String, Statement>,
+    in_transaction: bool,
+}
+
+impl Session {
+    /// Client of the connection, connecting if there is none
+    fn client(&mut self) -> Result<&mut Client> {
+        if self.client.as_ref().is_none_or(Client::is_closed) {
+            if self.in_transaction {
+                bail!("Lost the connection to Postgres during a transaction");
+            }
+            self.statements.clear();
+            self.client = None;
+            let client = self
+                .config
+                .connect(Connector::clone(&self.connector))
+                .context("Failed to connect to Postgres")?;
+            self.client = Some(client);
+        }
+        Ok(self.client.as_mut().expect("connected above"))
+    }
+
+    /// Prepared statement and parameters of `sql`, converted to the types of
+    /// the statement's parameters
+    fn prepare(
+        &mut self,
+        sql: &str,
+        params: &[Value],
+    ) -> Result<(Statement, Vec<Box<dyn ToSql + Sync>>)> {
+        let statement = match self.statements.get(sql) {
+            Some(statement) => statement.clone(),
+            None => {
+                let statement = self
+                    .client()?
+                    .prepare(&numbered_dollar_params(sql))
+                    .context("Failed to prepare statement")?;
+                self.statements.insert(sql.to_string(), statement.clone());
+                statement
+            }
+        };
+        let params = params
+            .iter()
+            .zip(statement.params())
+            .map(|(value, ty)| to_sql(value, ty))
+            .collect::<Result<_>>()?;
+        Ok((statement, params))
+    }
+
+    fn execute(&mut self, sql: &str, params: &[Value]) -> Result<usize> {
+        let (statement, params) = self.prepare(sql, params)?;
+        let params: Vec<&(dyn ToSql + Sync)> = params.iter().map(|param| &**param).collect();
+        Ok(self.client()?.execute(&statement, &params)? as usize)
+    }
+
+    fn query(&mut self, sql: &str, params: &[Value]) -> Result<Vec<Row>> {
+        let (statement, params) = self.prepare(sql, params)?;
+        let params: Vec<&(dyn ToSql + Sync)> = params.iter().map(|param| &**param).collect();
+        self.client()?
+            .query(&statement, &params)?
+            .iter()
+            .map(|row| {
+                let values = row
+                    .columns()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, column)| from_sql(row, i, column.type_()))
+                    .collect::<Result<_>>()?;
+                Ok(Row(values))
+            })
+            .collect()
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        self.client()?
+            .batch_execute(&format!(
+                "BEGIN; SELECT pg_advisory_xact_lock({})",
+                TRANSACTION_LOCK
+            ))
+            .context("Failed to start transaction")?;
+        self.in_transaction = true;
+        Ok(())
+    }
+
+    fn end_transaction(&mut self, statement: &str) -> Result<()> {
+        self.in_transaction = false;
+        match &mut self.client {
+            Some(client) if !client.is_closed() => Ok(client.batch_execute(statement)?),
+            _ => bail!("Lost the connection to Postgres during a transaction"),
+        }
+    }
+}
+
+/// Parameter of the given type
+fn to_sql(value: &Value, ty: &Type) -> Result<Box<dyn ToSql + Sync>> {
+    let mismatch = || anyhow!("Cannot pass {:?} as a parameter of type {}", value, ty);
+    Ok(match (value, ty) {
+        (Value::Null, &Type::INT2) => Box::new(None::<i16>),
+        (Value::Null, &Type::INT4) => Box::new(None::<i32>),
+        (Value::Null, &Type::INT8) => Box::new(None::<i64>),
+        (Value::Null, &Type::FLOAT4) => Box::new(None::<f32>),
+        (Value::Null, &Type::FLOAT8) => Box::new(None::<f64>),
+        (Value::Null, &Type::BOOL) => Box::new(None::<bool>),
+        (Value::Null, _) => Box::new(None::<String>),
+        (Value::Integer(i), &Type::INT2) => Box::new(i16::try_from(*i)?),
+        (Value::Integer(i), &Type::INT4) => Box::new(i32::try_from(*i)?),
+        (Value::Integer(i), &Type::INT8) => Box::new(*i),
+        (Value::Integer(i), &Type::FLOAT8) => Box::new(*i as f64),
+        (Value::Integer(i), &Type::BOOL) => Box::new(*i != 0),
+        (Value::Real(f), &Type::FLOAT4) => Box::new(*f as f32),
+        (Value::Real(f), &Type::FLOAT8) => Box::new(*f),
+        (Value::Text(text), &Type::TEXT | &Type::VARCHAR | &Type::BPCHAR | &Type::NAME) => {
+            Box::new(text.clone())
+        }
+        _ => return Err(mismatch()),
+    })
+}
+
+/// Value of a column of the given type
+fn from_sql(row: &postgres::Row, index: usize, ty: &Type) -> Result<Value> {
+    let value = match *ty {
+        Type::INT2 => row
+            .try_get::<_, Option<i16>>(index)?
+            .map(i64::from)
+            .map(Value::Integer),
+        Type::INT4 => row
+            .try_get::<_, Option<i32>>(index)?
+            .map(i64::from)
+            .map(Value::Integer),
+        Type::INT8 => row.try_get::<_, Option<i64>>(index)?.map(Value::Integer),
+        Type::FLOAT4 => row
+            .try_get::<_, Option<f32>>(index)?
+            .map(f64::from)
+            .map(Value::Real),
+        Type::FLOAT8 => row.try_get::<_, Option<f64>>(index)?.map(Value::Real),
+        Type::BOOL => row
+            .try_get::<_, Option<bool>>(index)?
+            .map(|b| Value::Integer(b.into())),
+        Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME => {
+            row.try_get::<_, Option<String>>(index)?.map(Value::Text)
+        }
+        _ => bail!("Unsupported type {} of column {}", ty, index),
+    };
+    Ok(value.unwrap_or(Value::Null))
+}
+
+impl StorageConnection for PooledConnection<'_, Worker> {
+    fn dialect(&self) -> Dialect {
+        Dialect::Postgres
+    }
+
+    fn execute(&mut self, sql: &str, params: &[Value]) -> Result<usize> {
+        let (sql, params) = (sql.to_string(), params.to_vec());
+        self.run(move |session| session.execute(&sql, &params))
+    }
+
+    fn query(&mut self, sql: &str, params: &[Value]) -> Result<Vec<Row>> {
+        let (sql, params) = (sql.to_string(), params.to_vec());
+        self.run(move |session| session.query(&sql, &params))
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        self.run(Session::begin)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.run(|session| session.end_transaction("COMMIT"))
+            .context("Failed to commit transaction")
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        self.run(|session| session.end_transaction("ROLLBACK"))
+            .context("Failed to roll back transaction")
+    }
+}
+
+#[cfg(all(test, not(feature = "tls")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sslmode_require_is_rejected() {
+        let error = PostgresStorage::open("postgres://trmnl@localhost/trmnl?sslmode=require", 1)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("require the tls feature"));
+    }
+}
@@ -0,0 +1,171 @@
+//! TLS connections to Postgres, with native-tls
+//!
+//! The server certificate is verified against the system's trusted
+//! certificates (with OpenSSL, `SSL_CERT_FILE` adds one), so that
+//! `sslmode=prefer` and `sslmode=require` do not accept any certificate.
+
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use postgres::tls::{ChannelBinding, MakeTlsConnect, TlsConnect, TlsStream};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Connector of TLS connections to Postgres
+#[derive(Clone)]
+pub struct NativeTlsConnector(tokio_native_tls::TlsConnector);
+
+impl NativeTlsConnector {
+    pub fn new(connector: native_tls::TlsConnector) -> Self {
+        Self(connector.into())
+    }
+}
+
+impl<S> MakeTlsConnect<S> for NativeTlsConnector
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Stream = NativeTlsStream<S>;
+    type TlsConnect = DomainTlsConnector;
+    type Error = native_tls::Error;
+
+    fn make_tls_connect(&mut self, domain: &str) -> Result<DomainTlsConnector, native_tls::Error> {
+        Ok(DomainTlsConnector {
+            connector: self.0.clone(),
+            domain: domain.to_string(),
+        })
+    }
+}
+
+/// Connector of TLS connections to one host, whose certificate must match it
+pub struct DomainTlsConnector {
+    connector: tokio_native_tls::TlsConnector,
+    domain: String,
+}
+
+impl<S> TlsConnect<S> for DomainTlsConnector
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Stream = NativeTlsStream<S>;
+    type Error = native_tls::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<NativeTlsStream<S>, native_tls::Error>> + Send>>;
+
+    fn connect(self, stream: S) -> Self::Future {
+        Box::pin(async move {
+            self.connector
+                .connect(&self.domain, stream)
+                .await
+                .map(NativeTlsStream)
+        })
+    }
+}
+
+/// TLS connection to Postgres
+pub struct NativeTlsStream<S>(tokio_native_tls::TlsStream<S>);
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for NativeTlsStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for NativeTlsStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> TlsStream for NativeTlsStream<S> {
+    /// Hash of the server certificate, so that SCRAM authentication is bound
+    /// to this connection
+    fn channel_binding(&self) -> ChannelBinding {
+        match self.0.get_ref().tls_server_end_point() {
+            Ok(Some(end_point)) => ChannelBinding::tls_server_end_point(end_point),
+            _ => ChannelBinding::none(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::tls::self_signed_certificate;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    /// Accept one TLS connection with the certificate and echo what it reads
+    async fn echo_server(cert: &[u8], key: &[u8]) -> u16 {
+        let identity = native_tls::Identity::from_pkcs8(cert, key).unwrap();
+        let acceptor =
+            tokio_native_tls::TlsAcceptor::from(native_tls::TlsAcceptor::new(identity).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            if let Ok(mut stream) = acceptor.accept(stream).await {
+                let mut buf = [0u8; 4];
+                stream.read_exact(&mut buf).await.unwrap();
+                stream.write_all(&buf).await.unwrap();
+            }
+        });
+        port
+    }
+
+    async fn connect(
+        connector: native_tls::TlsConnector,
+        port: u16,
+    ) -> Result<NativeTlsStream<TcpStream>, native_tls::Error> {
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut connector = NativeTlsConnector::new(connector);
+        MakeTlsConnect::<TcpStream>::make_tls_connect(&mut connector, "localhost")
+            .unwrap()
+            .connect(stream)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_handshake() {
+        let (cert, key) = self_signed_certificate();
+        let port = echo_server(&cert, &key).await;
+        let trusted = native_tls::TlsConnector::builder()
+            .add_root_certificate(native_tls::Certificate::from_pem(&cert).unwrap())
+            .build()
+            .unwrap();
+        let mut stream = connect(trusted, port).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_certificate_is_rejected() {
+        let (cert, key) = self_signed_certificate();
+        let port = echo_server(&cert, &key).await;
+        let connector = native_tls::TlsConnector::new().unwrap();
+        assert!(connect(connector, port).await.is_err());
+    }
+}
@@ -0,0 +1,148 @@
+//! SQLite storage
+//!
+//! File databases are opened in WAL mode, in which readers don't block the
+//! writer and vice versa; writers still take turns, waiting up to
+//! [`BUSY_TIMEOUT`] for each other. In-memory databases are private to their
+//! connection, so their pool has a single connection.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use rusqlite::{
+    Connection, params_from_iter,
+    types::{ToSqlOutput, ValueRef},
+};
+
+use super::pool::{ConnectionPool, PooledConnection};
+use super::storage::{Dialect, Row, Storage, StorageConnection, Value};
+
+/// How long a write waits for the writes of other connections
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pool of connections to a SQLite database
+pub struct SqliteStorage {
+    pool: ConnectionPool<Connection>,
+}
+
+impl SqliteStorage {
+    /// Open a pool of `size` connections to the database at `path`
+    pub fn open(path: &str, size: usize) -> Result<Self> {
+        let size = if path == ":memory:" { 1 } else { size.max(1) };
+        let mut connections = Vec::with_capacity(size);
+        for _ in 0..size {
+            let conn = Connection::open(path)
+                .with_context(|| format!("Failed to open database at {}", path))?;
+            connections.push(configure(conn, path)?);
+        }
+        Ok(Self {
+            pool: ConnectionPool::new(connections),
+        })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn connection(&self) -> Result<Box<dyn StorageConnection + '_>> {
+        Ok(Box::new(self.pool.get()?))
+    }
+}
+
+/// Set up a connection of the pool
+fn configure(conn: Connection, path: &str) -> Result<Connection> {
+    conn.busy_timeout(BUSY_TIMEOUT)
+        .context("Failed to set database busy timeout")?;
+    if path != ":memory:" {
+        conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))
+            .context("Failed to enable WAL mode")?;
+    }
+    Ok(conn)
+}
+
+impl StorageConnection for PooledConnection<'_, Connection> {
+    fn dialect(&self) -> Dialect {
+        Dialect::Sqlite
+    }
+
+    fn execute(&mut self, sql: &str, params: &[Value]) -> Result<usize> {
+        let mut stmt = self.prepare_cached(sql)?;
+        Ok(stmt.execute(params_from_iter(params))?)
+    }
+
+    fn query(&mut self, sql: &str, params: &[Value]) -> Result<Vec<Row>> {
+        let mut stmt = self.prepare_cached(sql)?;
+        let columns = stmt.column_count();
+        let mut rows = stmt.query(params_from_iter(params))?;
+        let mut result = Vec::new();
+        while let Some(row) = rows.next()? {
+            let values = (0..columns)
+                .map(|i| {
+                    Ok(match row.get_ref(i)? {
+                        ValueRef::Null => Value::Null,
+                        ValueRef::Integer(i) => Value::Integer(i),
+                        ValueRef::Real(f) => Value::Real(f),
+                        ValueRef::Text(text) => Value::Text(String::from_utf8(text.to_vec())?),
+                        ValueRef::Blob(_) => bail!("Unsupported blob in column {}", i),
+                    })
+                })
+                .collect::<Result<_>>()?;
+            result.push(Row(values));
+        }
+        Ok(result)
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        self.execute_batch("BEGIN IMMEDIATE")
+            .context("Failed to start transaction")
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.execute_batch("COMMIT")
+            .context("Failed to commit transaction")
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        self.execute_batch("ROLLBACK")
+            .context("Failed to roll back transaction")
+    }
+}
+
+impl rusqlite::ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            Value::Null => ToSqlOutput::Borrowed(ValueRef::Null),
+            Value::Integer(i) => ToSqlOutput::Borrowed(ValueRef::Integer(*i)),
+            Value::Real(f) => ToSqlOutput::Borrowed(ValueRef::Real(*f)),
+            Value::Text(text) => ToSqlOutput::Borrowed(ValueRef::Text(text.as_bytes())),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_database_uses_wal() {
+        let path = std::env::temp_dir().join(format!("trmnl-sqlite-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let storage = SqliteStorage::open(path, 2).unwrap();
+        assert_eq!(storage.pool.idle(), 2);
+
+        let mut conn = storage.connection().unwrap();
+        let journal_mode = conn
+            .query_row("PRAGMA journal_mode", &[], |row| row.get::<String>(0))
+            .unwrap();
+        assert_eq!(journal_mode.as_deref(), Some("wal"));
+
+        drop(conn);
+        drop(storage);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+        }
+    }
+
+    #[test]
+    fn test_memory_pool_has_one_connection() {
+        let storage = SqliteStorage::open(":memory:", 4).unwrap();
+        assert_eq!(storage.pool.idle(), 1);
+    }
+}
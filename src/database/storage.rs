@@ -0,0 +1,390 @@
+//! Storage backends of the database
+//!
+//! The queries of [`Database`](super::Database) are written once, in the SQL
+//! understood by both SQLite and Postgres, with numbered parameters (`?1`).
+//! A [`Storage`] hands out connections to run them on: [`SqliteStorage`] for a
+//! database file, and the Postgres storage (requires the `postgres` feature)
+//! for a database shared by instances that have no common volume.
+//!
+//! [`SqliteStorage`]: super::sqlite::SqliteStorage
+
+use anyhow::{Context, Result, bail};
+
+/// SQL dialect of a storage, for the few statements that differ, e.g. the
+/// schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    Sqlite,
+    Postgres,
+}
+
+/// Storage the database lives in
+pub trait Storage: Send + Sync {
+    /// Take a connection, waiting until one is free
+    fn connection(&self) -> Result<Box<dyn StorageConnection + '_>>;
+}
+
+/// Connection to a storage
+pub trait StorageConnection {
+    fn dialect(&self) -> Dialect;
+
+    /// Run a statement, returning the number of changed rows
+    fn execute(&mut self, sql: &str, params: &[Value]) -> Result<usize>;
+
+    /// Run a query, returning all rows
+    fn query(&mut self, sql: &str, params: &[Value]) -> Result<Vec<Row>>;
+
+    /// Start a transaction
+    ///
+    /// Transactions are serialized with the transactions of all other
+    /// connections, also those of other instances, like SQLite's immediate
+    /// transactions.
+    fn begin(&mut self) -> Result<()>;
+
+    fn commit(&mut self) -> Result<()>;
+
+    fn rollback(&mut self) -> Result<()>;
+}
+
+impl dyn StorageConnection + '_ {
+    /// Run a query, returning the first row mapped by `f`, if any
+    pub fn query_row<T>(
+        &mut self,
+        sql: &str,
+        params: &[Value],
+        f: impl FnOnce(&Row) -> Result<T>,
+    ) -> Result<Option<T>> {
+        self.query(sql, params)?.first().map(f).transpose()
+    }
+
+    /// Run a query, returning all rows mapped by `f`
+    pub fn query_map<T>(
+        &mut self,
+        sql: &str,
+        params: &[Value],
+        f: impl FnMut(&Row) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        self.query(sql, params)?.iter().map(f).collect()
+    }
+
+    /// Run a query, returning whether it has any rows
+    pub fn exists(&mut self, sql: &str, params: &[Value]) -> Result<bool> {
+        Ok(!self.query(sql, params)?.is_empty())
+    }
+
+    /// Start a transaction, rolled back unless it is committed
+    pub fn transaction(&mut self) -> Result<Transaction<'_>> {
+        self.begin()?;
+        Ok(Transaction {
+            conn: self,
+            done: false,
+        })
+    }
+}
+
+/// Transaction on a connection, rolled back when dropped uncommitted
+pub struct Transaction<'a> {
+    conn: &'a mut (dyn StorageConnection + 'a),
+    done: bool,
+}
+
+impl Transaction<'_> {
+    pub fn commit(mut self) -> Result<()> {
+        self.done = true;
+        self.conn.commit()
+    }
+}
+
+impl StorageConnection for Transaction<'_> {
+    fn dialect(&self) -> Dialect {
+        self.conn.dialect()
+    }
+
+    fn execute(&mut self, sql: &str, params: &[Value]) -> Result<usize> {
+        self.conn.execute(sql, params)
+    }
+
+    fn query(&mut self, sql: &str, params: &[Value]) -> Result<Vec<Row>> {
+        self.conn.query(sql, params)
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        bail!("Transactions cannot be nested")
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        bail!("Transactions cannot be nested")
+    }
+
+    fn rollback(&mut self) -> Result<()> {
+        bail!("Transactions cannot be nested")
+    }
+}
+
+impl<'a> std::ops::Deref for Transaction<'a> {
+    type Target = dyn StorageConnection + 'a;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn
+    }
+}
+
+impl std::ops::DerefMut for Transaction<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.conn
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.conn.rollback();
+        }
+    }
+}
+
+/// Value of a parameter or column
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+/// Row returned by a query
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row(pub Vec<Value>);
+
+impl Row {
+    /// Value of the column at `index`, converted to `T`
+    pub fn get<T: FromValue>(&self, index: usize) -> Result<T> {
+        let value = self
+            .0
+            .get(index)
+            .with_context(|| format!("No column {} in row", index))?;
+        T::from_value(value).with_context(|| format!("Invalid value in column {}", index))
+    }
+}
+
+/// Conversion of a parameter into a [`Value`]
+pub trait ToValue {
+    fn to_value(&self) -> Value;
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Value {
+        self.as_ref().map_or(Value::Null, ToValue::to_value)
+    }
+}
+
+impl ToValue for str {
+    fn to_value(&self) -> Value {
+        Value::Text(self.to_string())
+    }
+}
+
+impl ToValue for String {
+    fn to_value(&self) -> Value {
+        Value::Text(self.clone())
+    }
+}
+
+impl ToValue for bool {
+    fn to_value(&self) -> Value {
+        Value::Integer(i64::from(*self))
+    }
+}
+
+impl ToValue for f64 {
+    fn to_value(&self) -> Value {
+        Value::Real(*self)
+    }
+}
+
+macro_rules! integer_values {
+    ($($ty:ty),*) => {
+        $(
+            impl ToValue for $ty {
+                fn to_value(&self) -> Value {
+                    Value::Integer(i64::from(*self))
+                }
+            }
+
+            impl FromValue for $ty {
+                fn from_value(value: &Value) -> Result<Self> {
+                    match value {
+                        Value::Integer(i) => Ok(<$ty>::try_from(*i)?),
+                        value => bail!("Expected an integer, got {:?}", value),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+integer_values!(i32, i64, u8, u32);
+
+/// Conversion of a column from a [`Value`]
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self>;
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Text(text) => Ok(text.clone()),
+            value => bail!("Expected text, got {:?}", value),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Integer(i) => Ok(*i != 0),
+            value => bail!("Expected a boolean, got {:?}", value),
+        }
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self> {
+        match value {
+            Value::Real(f) => Ok(*f),
+            Value::Integer(i) => Ok(*i as f64),
+            value => bail!("Expected a number, got {:?}", value),
+        }
+    }
+}
+
+/// Parameters of a statement, e.g. `params![device_id, now]`
+macro_rules! params {
+    ($($param:expr),* $(,)?) => {
+        &[$($crate::database::storage::ToValue::to_value(&$param)),*]
+            as &[$crate::database::storage::Value]
+    };
+}
+
+pub(crate) use params;
+
+/// Whether the database URL points to a Postgres database
+pub fn is_postgres_url(url: &str) -> bool {
+    url.starts_with("postgres://") || url.starts_with("postgresql://")
+}
+
+/// Open the storage at `url`, with at most `pool_size` connections
+///
+/// The URL is the path of a SQLite database, optionally prefixed with
+/// `sqlite://`, or a `postgres://` URL.
+pub fn open(url: &str, pool_size: usize) -> Result<Box<dyn Storage>> {
+    if is_postgres_url(url) {
+        #[cfg(feature = "postgres")]
+        return Ok(Box::new(super::postgres::PostgresStorage::open(
+            url, pool_size,
+        )?));
+        #[cfg(not(feature = "postgres"))]
+        return Err(anyhow::anyhow!(
+            "Postgres database requested, but the server was built without the postgres feature"
+        ));
+    }
+    let path = url.strip_prefix("sqlite://").unwrap_or(url);
+    Ok(Box::new(super::sqlite::SqliteStorage::open(
+        path, pool_size,
+    )?))
+}
+
+/// Database URL with the password left out, for logging
+pub fn redact_url(url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_string();
+    };
+    match rest.split_once('@') {
+        Some((user_info, host)) if user_info.contains(':') => {
+            let user = user_info.split(':').next().unwrap_or_default();
+            format!("{}://{}:***@{}", scheme, user, host)
+        }
+        _ => url.to_string(),
+    }
+}
+
+/// Rewrite the numbered parameters of a statement from `?1` to `$1`
+///
+/// Question marks in string literals are kept.
+pub fn numbered_dollar_params(sql: &str) -> String {
+    let mut rewritten = String::with_capacity(sql.len());
+    let mut in_literal = false;
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => in_literal = !in_literal,
+            '?' if !in_literal && chars.peek().is_some_and(char::is_ascii_digit) => {
+                rewritten.push('$');
+                continue;
+            }
+            _ => {}
+        }
+        rewritten.push(c);
+    }
+    rewritten
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbered_dollar_params() {
+        assert_eq!(
+            numbered_dollar_params("SELECT ?1, '?2', ?12 WHERE a = 'it''s?' AND b = ?3"),
+            "SELECT $1, '?2', $12 WHERE a = 'it''s?' AND b = $3"
+        );
+    }
+
+    #[test]
+    fn test_redact_url() {
+        assert_eq!(
+            redact_url("postgres://trmnl:secret@db:5432/trmnl"),
+            "postgres://trmnl:***@db:5432/trmnl"
+        );
+        assert_eq!(redact_url("postgres://db/trmnl"), "postgres://db/trmnl");
+        assert_eq!(redact_url("devices.db"), "devices.db");
+    }
+
+    #[test]
+    fn test_transaction_rolls_back_when_dropped() {
+        let storage = open(":memory:", 1).unwrap();
+        let mut conn = storage.connection().unwrap();
+        conn.execute("CREATE TABLE t (x INTEGER)", &[]).unwrap();
+
+        let mut tx = conn.transaction().unwrap();
+        tx.execute("INSERT INTO t (x) VALUES (?1)", params![1])
+            .unwrap();
+        drop(tx);
+        assert!(!conn.exists("SELECT x FROM t", &[]).unwrap());
+
+        let mut tx = conn.transaction().unwrap();
+        tx.execute("INSERT INTO t (x) VALUES (?1)", params![2])
+            .unwrap();
+        tx.commit().unwrap();
+        let rows = conn
+            .query_map("SELECT x FROM t", &[], |row| row.get::<i64>(0))
+            .unwrap();
+        assert_eq!(rows, [2]);
+    }
+}
@@ -0,0 +1,236 @@
+//! Normalization of calendar event descriptions
+//!
+//! Descriptions from Google Calendar and Outlook often contain HTML and long
+//! boilerplate blocks with meeting join instructions, neither of which is of
+//! any use on a display.
+
+/// Maximum length of a normalized description, in characters
+pub const MAX_DESCRIPTION_CHARS: usize = 500;
+
+/// Lines at which the boilerplate of meeting invitations starts
+///
+/// Everything from the first line containing one of these is removed.
+const BOILERPLATE_MARKERS: &[&str] = &[
+    "Microsoft Teams meeting",
+    "Microsoft Teams Need help?",
+    "Join on your computer",
+    "Join Zoom Meeting",
+    "Join with Google Meet",
+    "-::~:~::~:",
+    "Join Webex meeting",
+];
+
+/// Normalize an event description for display
+///
+/// Unescapes iCalendar text escapes, strips HTML tags, removes meeting
+/// invitation boilerplate, collapses whitespace and limits the length.
+/// Returns `None` if nothing is left.
+pub fn normalize_description(raw: &str) -> Option<String> {
+    let text = strip_html(&unescape_ical(raw));
+
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        if is_boilerplate_start(line) {
+            break;
+        }
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        // Keep at most one blank line between paragraphs
+        if line.is_empty() && lines.last().is_none_or(|l: &String| l.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+
+    let text = lines.join("\n");
+    if text.is_empty() {
+        return None;
+    }
+    Some(truncate(&text, MAX_DESCRIPTION_CHARS))
+}
+
+/// Undo the escaping of iCalendar TEXT values (RFC 5545, section 3.3.11)
+fn unescape_ical(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Remove HTML tags, keeping line breaks of block elements
+///
+/// Contents of `<style>` and `<script>` elements are dropped, common entities
+/// are decoded. Text without tags is returned unchanged (apart from entities).
+fn strip_html(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut skip_until: Option<&str> = None;
+
+    while let Some(start) = rest.find('<') {
+        if skip_until.is_none() {
+            result.push_str(&rest[..start]);
+        }
+        let Some(end) = rest[start..].find('>') else {
+            // Not a tag, e.g. "a < b"
+            if skip_until.is_none() {
+                result.push_str(&rest[start..]);
+            }
+            rest = "";
+            break;
+        };
+        let tag = rest[start + 1..start + end].trim().to_ascii_lowercase();
+        rest = &rest[start + end + 1..];
+
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or("");
+        if let Some(until) = skip_until {
+            if tag == until {
+                skip_until = None;
+            }
+            continue;
+        }
+        match name {
+            "style" if !tag.starts_with('/') => skip_until = Some("/style"),
+            "script" if !tag.starts_with('/') => skip_until = Some("/script"),
+            "br" => result.push('\n'),
+            // Adjacent block elements, e.g. `</p><ul><li>`, start a single new line
+            "p" | "div" | "li" | "tr" | "h1" | "h2" | "h3" | "h4" if !result.ends_with('\n') => {
+                result.push('\n')
+            }
+            _ => {}
+        }
+    }
+    if skip_until.is_none() {
+        result.push_str(rest);
+    }
+    decode_entities(&result)
+}
+
+/// Decode the HTML entities commonly found in calendar descriptions
+fn decode_entities(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => entity
+                    .strip_prefix("#x")
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+            c.map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Returns true if the line starts a meeting invitation boilerplate block
+fn is_boilerplate_start(line: &str) -> bool {
+    let line = line.trim();
+    // Teams and Outlook separate the invitation with a line of underscores
+    (line.len() >= 10 && line.chars().all(|c| c == '_'))
+        || BOILERPLATE_MARKERS
+            .iter()
+            .any(|marker| line.contains(marker))
+}
+
+/// Limit text to `max_chars` characters, marking truncation with an ellipsis
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    truncated.truncate(truncated.trim_end().len());
+    truncated.push('…');
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_html() {
+        assert_eq!(
+            normalize_description(
+                "<html><head><style>p { color: red; }</style></head><body>\
+                 <p>Agenda:</p><ul><li>Budget &amp; planning</li><li>Q&amp;A</li></ul>\
+                 </body></html>"
+            )
+            .as_deref(),
+            Some("Agenda:\nBudget & planning\nQ&A")
+        );
+        assert_eq!(
+            normalize_description("a < b &unknown; &#228;").as_deref(),
+            Some("a < b &unknown; ä")
+        );
+    }
+
+    #[test]
+    fn test_remove_boilerplate() {
+        let teams = "Quarterly review\\n\\n\\n\
+                     ________________________________________________\\n\
+                     Microsoft Teams meeting\\n\
+                     Join on your computer, mobile app or room device\\n\
+                     Click here to join the meeting";
+        assert_eq!(
+            normalize_description(teams).as_deref(),
+            Some("Quarterly review")
+        );
+        assert_eq!(
+            normalize_description(
+                "-::~:~::~:~::-\\nJoin with Google Meet: https://meet.google.com/x"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_collapse_and_truncate() {
+        assert_eq!(
+            normalize_description("  Bring   laptops\\,  please  \\n\\n\\n\\nThanks ").as_deref(),
+            Some("Bring laptops, please\n\nThanks")
+        );
+
+        let long = "word ".repeat(200);
+        let normalized = normalize_description(&long).unwrap();
+        assert_eq!(normalized.chars().count(), MAX_DESCRIPTION_CHARS);
+        assert!(normalized.ends_with("word…"));
+    }
+}
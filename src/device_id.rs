@@ -0,0 +1,180 @@
+//! Device IDs
+//!
+//! TRMNL devices identify themselves by the MAC address of their WiFi chip,
+//! e.g. `AA:BB:CC:DD:EE:FF`. Some clone devices have no such address and send
+//! a UUID instead, e.g. `123e4567-e89b-12d3-a456-426614174000`. Both are
+//! accepted in their common spellings and normalized, so that a device is
+//! recognized however it or an admin writes its ID: MAC addresses in upper
+//! case with colons, UUIDs in lower case with hyphens. Devices whose ID has
+//! neither form, e.g. ones registered before IDs were validated, keep working
+//! under their ID as sent.
+
+use serde::{Deserialize, Serialize};
+
+/// Form of a device ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceIdKind {
+    /// MAC address, e.g. `AA:BB:CC:DD:EE:FF`
+    Mac,
+    /// UUID, e.g. `123e4567-e89b-12d3-a456-426614174000`
+    Uuid,
+}
+
+impl DeviceIdKind {
+    /// Form of a stored ID, `None` for legacy IDs of neither form
+    pub fn of(id: &str) -> Option<DeviceIdKind> {
+        DeviceId::lenient(id).kind()
+    }
+}
+
+/// Device ID in its normalized form
+///
+/// Parsing accepts MAC addresses and UUIDs only, [`DeviceId::lenient`] also
+/// legacy IDs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeviceId {
+    id: String,
+    /// Form of the ID, `None` for a legacy ID
+    kind: Option<DeviceIdKind>,
+}
+
+impl DeviceId {
+    /// The normalized ID, or the trimmed ID as given if it has neither form
+    pub fn lenient(id: &str) -> Self {
+        id.parse().unwrap_or_else(|_| DeviceId {
+            id: id.trim().to_string(),
+            kind: None,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.id
+    }
+
+    pub fn kind(&self) -> Option<DeviceIdKind> {
+        self.kind
+    }
+
+    pub fn into_string(self) -> String {
+        self.id
+    }
+}
+
+impl std::fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.id)
+    }
+}
+
+impl std::str::FromStr for DeviceId {
+    type Err = String;
+
+    /// Parse a MAC address with colons, dashes or without separators, or a
+    /// UUID with or without hyphens and braces, ignoring case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        let unbraced = trimmed
+            .strip_prefix('{')
+            .and_then(|id| id.strip_suffix('}'))
+            .unwrap_or(trimmed);
+        let digits: String = unbraced
+            .chars()
+            .filter(|c| *c != ':' && *c != '-')
+            .collect();
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!(
+                "Invalid device ID: {} (expected a MAC address or UUID)",
+                s
+            ));
+        }
+        match digits.len() {
+            12 if unbraced.len() == trimmed.len() => {
+                let digits = digits.to_ascii_uppercase();
+                let octets: Vec<&str> = (0..6).map(|i| &digits[2 * i..2 * i + 2]).collect();
+                Ok(DeviceId {
+                    id: octets.join(":"),
+                    kind: Some(DeviceIdKind::Mac),
+                })
+            }
+            32 if !unbraced.contains(':') => {
+                let digits = digits.to_ascii_lowercase();
+                Ok(DeviceId {
+                    id: format!(
+                        "{}-{}-{}-{}-{}",
+                        &digits[..8],
+                        &digits[8..12],
+                        &digits[12..16],
+                        &digits[16..20],
+                        &digits[20..]
+                    ),
+                    kind: Some(DeviceIdKind::Uuid),
+                })
+            }
+            _ => Err(format!(
+                "Invalid device ID: {} (expected a MAC address or UUID)",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(id: &str) -> Option<String> {
+        id.parse::<DeviceId>().ok().map(DeviceId::into_string)
+    }
+
+    #[test]
+    fn test_parse_mac() {
+        assert_eq!(
+            parse("aa:bb:cc:dd:ee:ff").as_deref(),
+            Some("AA:BB:CC:DD:EE:FF")
+        );
+        assert_eq!(
+            parse("AA-BB-CC-00-11-22").as_deref(),
+            Some("AA:BB:CC:00:11:22")
+        );
+        assert_eq!(
+            parse(" aabbccddeeff ").as_deref(),
+            Some("AA:BB:CC:DD:EE:FF")
+        );
+        assert_eq!(parse("AA:BB:CC:DD:EE"), None);
+        assert_eq!(parse("GG:BB:CC:DD:EE:FF"), None);
+        assert_eq!(parse("{aabbccddeeff}"), None);
+        assert_eq!(
+            DeviceIdKind::of("AA:BB:CC:DD:EE:FF"),
+            Some(DeviceIdKind::Mac)
+        );
+    }
+
+    #[test]
+    fn test_parse_uuid() {
+        let uuid = "123e4567-e89b-12d3-a456-426614174000";
+        assert_eq!(parse(uuid).as_deref(), Some(uuid));
+        assert_eq!(
+            parse("{123E4567-E89B-12D3-A456-426614174000}").as_deref(),
+            Some(uuid)
+        );
+        assert_eq!(
+            parse("123e4567e89b12d3a456426614174000").as_deref(),
+            Some(uuid)
+        );
+        assert_eq!(parse("123e4567-e89b-12d3-a456-42661417400"), None);
+        assert_eq!(
+            parse("12:3e:45:67:e8:9b:12:d3:a4:56:42:66:14:17:40:00"),
+            None
+        );
+        assert_eq!(DeviceIdKind::of(uuid), Some(DeviceIdKind::Uuid));
+
+        assert_eq!(parse("test-device"), None);
+        assert_eq!(DeviceIdKind::of("test-device"), None);
+        assert_eq!(DeviceId::lenient(" test-device ").as_str(), "test-device");
+        assert_eq!(
+            DeviceId::lenient(uuid.to_uppercase().as_str()).as_str(),
+            uuid
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Stable short codes of the errors devices and API clients run into
+//!
+//! Error messages change between releases and are too long to read off a
+//! display, so errors carry a short code like `CAL-01` as well. The code is
+//! returned in API error bodies and rendered on the display, so that support
+//! can diagnose a problem from a photo of the screen. Codes are never reused
+//! for a different error.
+
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+
+use crate::bmp::BmpError;
+use crate::calendar::CalendarError;
+
+/// Short code of an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// CAL-01, the room calendar could not be fetched
+    CalendarFetch,
+    /// CAL-02, the room calendar is no valid iCalendar data
+    CalendarParse,
+    /// CAL-03, the room calendar contains no events
+    CalendarEmpty,
+    /// REN-01, the image could not be encoded
+    RenderFailed,
+    /// REN-02, the font file is missing or unreadable
+    FontMissing,
+    /// REN-03, the font file is no valid TrueType font
+    FontInvalid,
+    /// DEV-01, the device has not been set up
+    DeviceNotRegistered,
+    /// DEV-03, the device is not assigned to any room
+    DeviceUnassigned,
+    /// AUTH-01, missing or invalid credentials
+    Unauthorized,
+    /// REQ-01, the request is invalid
+    BadRequest,
+    /// REQ-02, too many requests of the device or client
+    RateLimited,
+    /// REQ-03, the request is too large to be processed
+    TooLarge,
+    /// SRV-01, an unexpected server error
+    Internal,
+    /// SRV-02, the server configuration is invalid
+    Config,
+    /// SRV-03, the server is overloaded
+    Overloaded,
+    /// SRV-04, the TRMNL cloud a request was forwarded to failed
+    Upstream,
+    /// SRV-05, a background service of the server is not running
+    Unavailable,
+}
+
+impl ErrorCode {
+    /// The code as shown to users, e.g. `CAL-01`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::CalendarFetch => "CAL-01",
+            ErrorCode::CalendarParse => "CAL-02",
+            ErrorCode::CalendarEmpty => "CAL-03",
+            ErrorCode::RenderFailed => "REN-01",
+            ErrorCode::FontMissing => "REN-02",
+            ErrorCode::FontInvalid => "REN-03",
+            ErrorCode::DeviceNotRegistered => "DEV-01",
+            ErrorCode::DeviceUnassigned => "DEV-03",
+            ErrorCode::Unauthorized => "AUTH-01",
+            ErrorCode::BadRequest => "REQ-01",
+            ErrorCode::RateLimited => "REQ-02",
+            ErrorCode::TooLarge => "REQ-03",
+            ErrorCode::Internal => "SRV-01",
+            ErrorCode::Config => "SRV-02",
+            ErrorCode::Overloaded => "SRV-03",
+            ErrorCode::Upstream => "SRV-04",
+            ErrorCode::Unavailable => "SRV-05",
+        }
+    }
+
+    /// Code of the first error with a code in the chain of an error
+    pub fn of(error: &anyhow::Error) -> Option<Self> {
+        error.chain().find_map(|cause| {
+            if let Some(e) = cause.downcast_ref::<CalendarError>() {
+                Some(e.code())
+            } else {
+                cause.downcast_ref::<BmpError>().map(BmpError::code)
+            }
+        })
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn test_error_code_of_chain() {
+        let error = Err::<(), _>(CalendarError::FetchError("timeout".to_string()))
+            .context("Failed to get calendar")
+            .unwrap_err();
+        assert_eq!(ErrorCode::of(&error), Some(ErrorCode::CalendarFetch));
+
+        let error = anyhow::Error::new(BmpError::InvalidFont("font.ttf".to_string()))
+            .context("Failed to generate BMP image");
+        assert_eq!(ErrorCode::of(&error), Some(ErrorCode::FontInvalid));
+
+        assert_eq!(ErrorCode::of(&anyhow::anyhow!("Database locked")), None);
+        assert_eq!(
+            serde_json::to_value(ErrorCode::DeviceUnassigned).unwrap(),
+            "DEV-03"
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Local};
+use log::{debug, warn};
+use serde::Serialize;
+
+use crate::calendar::CalendarEvent;
+use crate::mqtt::MqttPublisher;
+use crate::notify::{Notification, Notifier};
+
+/// Kind of the notifications of next event changes
+pub const NEXT_EVENT_CHANGED: &str = "room.next_event_changed";
+
+/// Kind of change of a room's next event
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NextEventChangeKind {
+    /// A meeting was booked before the previous next event (or into a free calendar)
+    Booked,
+    /// The previous next event disappeared from the calendar
+    Cancelled,
+    /// The previous next event moved to a different time
+    Rescheduled,
+}
+
+/// Change of a room's next event between two calendar refreshes
+#[derive(Debug, Clone, Serialize)]
+pub struct NextEventChange {
+    pub kind: NextEventChangeKind,
+    /// Next event before the refresh
+    pub old: Option<CalendarEvent>,
+    /// Next event after the refresh
+    pub new: Option<CalendarEvent>,
+}
+
+/// The next event of a calendar (sorted by start time) that has not started yet
+pub fn next_event(events: &[CalendarEvent], now: DateTime<Local>) -> Option<&CalendarEvent> {
+    events.iter().find(|e| e.start_time > now)
+}
+
+impl NextEventChange {
+    /// Compare the next event before a refresh with the refreshed events
+    ///
+    /// Returns `None` if the next event is unchanged, or if it only changed
+    /// because the previous next event has started in the meantime.
+    pub fn detect(
+        old: Option<&CalendarEvent>,
+        events: &[CalendarEvent],
+        now: DateTime<Local>,
+    ) -> Option<Self> {
+        let new = next_event(events, now);
+        let kind = match (old, new) {
+            (None, None) => return None,
+            (Some(old), _) if old.start_time <= now => return None,
+            (None, Some(_)) => NextEventChangeKind::Booked,
+            (Some(_), None) => NextEventChangeKind::Cancelled,
+            (Some(old), Some(new)) if old.name == new.name => {
+                if old.start_time == new.start_time && old.end_time == new.end_time {
+                    return None;
+                }
+                NextEventChangeKind::Rescheduled
+            }
+            (Some(old), Some(_)) => {
+                let still_booked = events
+                    .iter()
+                    .any(|e| e.name == old.name && e.start_time == old.start_time);
+                if still_booked {
+                    NextEventChangeKind::Booked
+                } else {
+                    NextEventChangeKind::Cancelled
+                }
+            }
+        };
+        Some(Self {
+            kind,
+            old: old.cloned(),
+            new: new.cloned(),
+        })
+    }
+
+    /// Notification describing this change, with the old and new event as details
+    pub fn notification(&self, room_id: &str) -> Notification {
+        let (title, message) = match (self.kind, &self.old, &self.new) {
+            (NextEventChangeKind::Rescheduled, Some(old), Some(new)) => (
+                "Meeting rescheduled",
+                format!(
+                    "{} moved from {} to {}",
+                    new.name,
+                    old.start_time.format("%Y-%m-%d %H:%M"),
+                    new.start_time.format("%Y-%m-%d %H:%M")
+                ),
+            ),
+            (NextEventChangeKind::Cancelled, Some(old), _) => (
+                "Meeting cancelled",
+                format!(
+                    "{} at {} was cancelled",
+                    old.name,
+                    old.start_time.format("%Y-%m-%d %H:%M")
+                ),
+            ),
+            (_, _, Some(new)) => (
+                "Meeting booked",
+                format!(
+                    "{} was booked for {}",
+                    new.name,
+                    new.start_time.format("%Y-%m-%d %H:%M")
+                ),
+            ),
+            _ => ("Next meeting changed", String::new()),
+        };
+        Notification {
+            kind: NEXT_EVENT_CHANGED.to_string(),
+            title: format!("{} in {}", title, room_id),
+            message,
+            room_id: Some(room_id.to_string()),
+            device_id: None,
+            details: serde_json::to_value(self).ok(),
+        }
+    }
+}
+
+/// Notifier that also publishes next event changes to MQTT
+///
+/// The [details](Notification::details) of every change are published as
+/// JSON to `<prefix>/rooms/<id>/next_event`, not retained, since they describe
+/// an event rather than a state. All notifications are passed on to the inner
+/// notifier.
+pub struct MqttChangeNotifier {
+    inner: Arc<dyn Notifier>,
+    mqtt: MqttPublisher,
+    topic_prefix: String,
+}
+
+impl MqttChangeNotifier {
+    pub fn new(inner: Arc<dyn Notifier>, mqtt: MqttPublisher, topic_prefix: &str) -> Self {
+        Self {
+            inner,
+            mqtt,
+            topic_prefix: topic_prefix.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl Notifier for MqttChangeNotifier {
+    fn notify(&self, notification: Notification) {
+        if notification.kind == NEXT_EVENT_CHANGED
+            && let (Some(room_id), Some(details)) = (&notification.room_id, &notification.details)
+        {
+            let topic = format!("{}/rooms/{}/next_event", self.topic_prefix, room_id);
+            let payload = details.to_string();
+            let mqtt = self.mqtt.clone();
+            tokio::spawn(async move {
+                match mqtt.send(&topic, payload.as_bytes(), false).await {
+                    Ok(()) => debug!("Published next event change to MQTT topic {}", topic),
+                    Err(e) => warn!("Failed to publish to MQTT topic {}: {:#}", topic, e),
+                }
+            });
+        }
+        self.inner.notify(notification);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    #[derive(Default)]
+    struct RecordingNotifier(std::sync::Mutex<Vec<String>>);
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&self, notification: Notification) {
+            self.0.lock().unwrap().push(notification.title);
+        }
+    }
+
+    fn event(name: &str, start: (u32, u32), end: (u32, u32)) -> CalendarEvent {
+        CalendarEvent::new(
+            name.to_string(),
+            Local
+                .with_ymd_and_hms(2024, 3, 4, start.0, start.1, 0)
+                .unwrap(),
+            Local.with_ymd_and_hms(2024, 3, 4, end.0, end.1, 0).unwrap(),
+            None,
+            None,
+        )
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 3, 4, hour, minute, 0).unwrap()
+    }
+
+    fn kind(old: Option<&CalendarEvent>, events: &[CalendarEvent]) -> Option<NextEventChangeKind> {
+        NextEventChange::detect(old, events, at(9, 0)).map(|c| c.kind)
+    }
+
+    #[test]
+    fn test_detect_next_event_change() {
+        let standup = event("Standup", (14, 0), (15, 0));
+        let planning = event("Planning", (16, 0), (17, 0));
+        let only_standup = vec![standup.clone()];
+
+        assert_eq!(kind(Some(&standup), &only_standup), None);
+        assert_eq!(kind(None, &only_standup), Some(NextEventChangeKind::Booked));
+        assert_eq!(
+            kind(
+                Some(&standup),
+                &[planning.clone(), event("Review", (17, 0), (18, 0))]
+            ),
+            Some(NextEventChangeKind::Cancelled)
+        );
+        assert_eq!(
+            kind(Some(&standup), &[]),
+            Some(NextEventChangeKind::Cancelled)
+        );
+        assert_eq!(
+            kind(Some(&standup), &[event("Standup", (14, 30), (15, 30))]),
+            Some(NextEventChangeKind::Rescheduled)
+        );
+        assert_eq!(
+            kind(
+                Some(&standup),
+                &[event("Interview", (10, 0), (11, 0)), standup.clone()]
+            ),
+            Some(NextEventChangeKind::Booked)
+        );
+
+        // The next event changing because the previous one started is no change
+        assert!(
+            NextEventChange::detect(Some(&standup), &[standup.clone(), planning], at(14, 5))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_change_notification() {
+        let change =
+            NextEventChange::detect(Some(&event("Standup", (14, 0), (15, 0))), &[], at(9, 0))
+                .unwrap();
+        let notification = change.notification("room-a");
+
+        assert_eq!(notification.title, "Meeting cancelled in room-a");
+        assert_eq!(
+            notification.message,
+            "Standup at 2024-03-04 14:00 was cancelled"
+        );
+        let details = notification.details.unwrap();
+        assert_eq!(details["kind"], "cancelled");
+        assert_eq!(details["old"]["name"], "Standup");
+        assert!(details["new"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_mqtt_change_notifier() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut header = [0u8; 2];
+            stream.read_exact(&mut header).await.unwrap();
+            let mut connect = vec![0u8; header[1] as usize];
+            stream.read_exact(&mut connect).await.unwrap();
+            stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).await.unwrap();
+            String::from_utf8_lossy(&rest).to_string()
+        });
+
+        let inner = Arc::new(RecordingNotifier::default());
+        let mqtt = MqttPublisher::from_url(&format!("mqtt://127.0.0.1:{}", port), "test").unwrap();
+        let notifier = MqttChangeNotifier::new(inner.clone(), mqtt, "office/");
+        let change =
+            NextEventChange::detect(Some(&event("Standup", (14, 0), (15, 0))), &[], at(9, 0))
+                .unwrap();
+        notifier.notify(change.notification("room-a"));
+
+        let published = broker.await.unwrap();
+        assert!(published.contains("office/rooms/room-a/next_event{"));
+        assert!(published.contains(r#""kind":"cancelled""#));
+        assert_eq!(*inner.0.lock().unwrap(), ["Meeting cancelled in room-a"]);
+    }
+}
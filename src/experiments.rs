@@ -0,0 +1,157 @@
+//! Layout experiments on part of the fleet
+//!
+//! An experiment shows an alternative layout on a percentage of the devices,
+//! e.g. `focus` on 10% of the displays, to trial a layout change before it is
+//! rolled out. Devices are assigned by a stable hash of the experiment name and
+//! device ID, so a device stays in its variant across restarts and instances,
+//! and different experiments pick different devices. Once promoted, the
+//! experiment's layout is shown on all devices it applies to; rolled back, it
+//! has no effect anymore.
+//!
+//! Layouts set for single devices in the room configuration take precedence
+//! over experiments.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::render::layout::Layout;
+
+/// State of an experiment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExperimentState {
+    /// The layout is shown on a percentage of the devices
+    Running,
+    /// The layout is shown on all devices
+    Promoted,
+    /// The experiment has no effect anymore
+    RolledBack,
+}
+
+impl ExperimentState {
+    /// Name of the state, as stored in the database
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExperimentState::Running => "running",
+            ExperimentState::Promoted => "promoted",
+            ExperimentState::RolledBack => "rolled_back",
+        }
+    }
+}
+
+impl std::str::FromStr for ExperimentState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "running" => Ok(ExperimentState::Running),
+            "promoted" => Ok(ExperimentState::Promoted),
+            "rolled_back" => Ok(ExperimentState::RolledBack),
+            _ => Err(format!("Unknown experiment state: {}", s)),
+        }
+    }
+}
+
+/// Group of devices of an experiment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Variant {
+    /// Devices showing the layout of their room
+    Control,
+    /// Devices showing the layout of the experiment
+    Variant,
+}
+
+impl Variant {
+    /// Name of the variant, as used in metric labels
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Variant::Control => "control",
+            Variant::Variant => "variant",
+        }
+    }
+}
+
+/// Layout experiment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Experiment {
+    /// Unique name, e.g. `focus-at-doors`
+    pub name: String,
+    /// Layout shown on the devices of the variant
+    pub layout: Layout,
+    /// Share of the devices in the variant, in percent
+    pub percent: u8,
+    /// Room the experiment is limited to, all rooms if `None`
+    pub room_id: Option<String>,
+    pub state: ExperimentState,
+    /// Unix timestamp when the experiment was started
+    pub created_at: i64,
+    /// Admin who started the experiment
+    pub created_by: String,
+}
+
+impl Experiment {
+    /// Variant a device of the given room is in, `None` if the experiment
+    /// does not apply to it
+    pub fn variant(&self, room_id: &str, device_id: &str) -> Option<Variant> {
+        if self.room_id.as_deref().is_some_and(|id| id != room_id) {
+            return None;
+        }
+        match self.state {
+            ExperimentState::Running if bucket(&self.name, device_id) < self.percent => {
+                Some(Variant::Variant)
+            }
+            ExperimentState::Running => Some(Variant::Control),
+            ExperimentState::Promoted => Some(Variant::Variant),
+            ExperimentState::RolledBack => None,
+        }
+    }
+}
+
+/// Bucket from 0 to 99 of a device in an experiment
+///
+/// Derived from a SHA-256 hash rather than the standard library's hasher,
+/// whose output may change between Rust versions.
+pub fn bucket(experiment: &str, device_id: &str) -> u8 {
+    let digest = Sha256::new()
+        .chain_update(experiment.as_bytes())
+        .chain_update(b":")
+        .chain_update(device_id.to_ascii_uppercase().as_bytes())
+        .finalize();
+    let value = u64::from_be_bytes(digest[..8].try_into().expect("digest has 32 bytes"));
+    (value % 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn experiment(percent: u8, state: ExperimentState) -> Experiment {
+        Experiment {
+            name: "focus-at-doors".to_string(),
+            layout: Layout::Focus,
+            percent,
+            room_id: None,
+            state,
+            created_at: 1_700_000_000,
+            created_by: "admin".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_bucket() {
+        // Stable across runs and independent of the case of the device ID
+        assert_eq!(
+            bucket("focus-at-doors", "aa:bb:cc:dd:ee:ff"),
+            bucket("focus-at-doors", "AA:BB:CC:DD:EE:FF")
+        );
+
+        // Roughly the configured share of a fleet is in the variant
+        let experiment = experiment(20, ExperimentState::Running);
+        let in_variant = (0..1000)
+            .map(|i| format!("00:00:00:00:{:02X}:{:02X}", i / 256, i % 256))
+            .filter(|id| experiment.variant("room-a", id) == Some(Variant::Variant))
+            .count();
+        assert!((150..250).contains(&in_variant), "{}", in_variant);
+    }
+}
@@ -0,0 +1,489 @@
+//! Google Calendar backend, authenticated with a service account
+//!
+//! Reads room calendars through the Calendar API instead of an iCal export.
+//! The calendar has to be shared with the service account, or the service
+//! account acts as a user of the workspace with domain-wide delegation. Either
+//! way, the events come with their titles, which exports often replace with
+//! "busy" for events that are not public.
+//!
+//! The events are converted to iCalendar data, so that they are parsed,
+//! cached and shared between instances like those of any other source.
+
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::{Context, Result};
+use base64::{Engine as _, engine::general_purpose};
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::calendar::{CalendarError, EventWindow};
+
+/// Base URL of the Google Calendar API
+const API_URL: &str = "https://www.googleapis.com/calendar/v3";
+
+/// Scope of the access tokens, reading calendars only
+const SCOPE: &str = "https://www.googleapis.com/auth/calendar.readonly";
+
+/// Lifetime of the signed assertions, the longest Google accepts
+const ASSERTION_LIFETIME_SECS: i64 = 3600;
+
+/// Access tokens are renewed this long before they expire
+const TOKEN_RENEWAL_SECS: i64 = 60;
+
+/// Events requested per page, the most the API returns
+const PAGE_SIZE: u32 = 2500;
+
+/// Key of a service account, as downloaded from the Google Cloud console
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServiceAccountKey {
+    pub client_email: String,
+    /// RSA private key in PEM format
+    pub private_key: String,
+    /// Endpoint signed assertions are exchanged for access tokens at
+    pub token_uri: String,
+}
+
+impl ServiceAccountKey {
+    /// Load a key from its JSON file
+    pub fn load(path: &str) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read service account key {}", path))?;
+        serde_json::from_str(&json).with_context(|| format!("Invalid service account key {}", path))
+    }
+
+    /// Signed JWT asserting the identity of the service account (RFC 7523)
+    ///
+    /// With a `subject`, the service account acts as that user.
+    fn assertion(&self, subject: Option<&str>, now: i64) -> Result<String> {
+        let encode =
+            |value: serde_json::Value| general_purpose::URL_SAFE_NO_PAD.encode(value.to_string());
+        let mut claims = json!({
+            "iss": self.client_email,
+            "scope": SCOPE,
+            "aud": self.token_uri,
+            "iat": now,
+            "exp": now + ASSERTION_LIFETIME_SECS,
+        });
+        if let Some(subject) = subject {
+            claims["sub"] = subject.into();
+        }
+        let message = format!(
+            "{}.{}",
+            encode(json!({ "alg": "RS256", "typ": "JWT" })),
+            encode(claims)
+        );
+
+        let key = PKey::private_key_from_pem(self.private_key.as_bytes())
+            .context("Invalid private key in service account key")?;
+        let mut signer =
+            Signer::new(MessageDigest::sha256(), &key).context("Failed to create signer")?;
+        signer
+            .update(message.as_bytes())
+            .context("Failed to sign assertion")?;
+        let signature = signer.sign_to_vec().context("Failed to sign assertion")?;
+        Ok(format!(
+            "{}.{}",
+            message,
+            general_purpose::URL_SAFE_NO_PAD.encode(signature)
+        ))
+    }
+}
+
+/// Response of the token endpoint
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+}
+
+/// Access tokens by service account and subject, with their expiry
+fn token_cache() -> &'static Mutex<HashMap<String, (String, i64)>> {
+    static TOKENS: OnceLock<Mutex<HashMap<String, (String, i64)>>> = OnceLock::new();
+    TOKENS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Access token of the service account, from the cache if still valid
+async fn access_token(
+    client: &reqwest::Client,
+    key: &ServiceAccountKey,
+    subject: Option<&str>,
+) -> Result<String, CalendarError> {
+    let cache_key = format!("{} {}", key.client_email, subject.unwrap_or_default());
+    let now = Utc::now().timestamp();
+    if let Ok(tokens) = token_cache().lock()
+        && let Some((token, expires_at)) = tokens.get(&cache_key)
+        && *expires_at > now + TOKEN_RENEWAL_SECS
+    {
+        return Ok(token.clone());
+    }
+
+    let assertion = key
+        .assertion(subject, now)
+        .map_err(|e| CalendarError::FetchError(format!("{:#}", e)))?;
+    let response = client
+        .post(&key.token_uri)
+        .form(&[
+            ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+            ("assertion", assertion.as_str()),
+        ])
+        .send()
+        .await
+        .map_err(|e| CalendarError::FetchError(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(CalendarError::FetchError(format!(
+            "Google token endpoint returned {}",
+            response.status()
+        )));
+    }
+    let token: TokenResponse = response
+        .json()
+        .await
+        .map_err(|e| CalendarError::FetchError(format!("Invalid token response: {}", e)))?;
+
+    if let Ok(mut tokens) = token_cache().lock() {
+        tokens.insert(
+            cache_key,
+            (token.access_token.clone(), now + token.expires_in),
+        );
+    }
+    Ok(token.access_token)
+}
+
+/// Page of the events list
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EventsPage {
+    #[serde(default)]
+    items: Vec<GoogleEvent>,
+    next_page_token: Option<String>,
+}
+
+/// Event as returned by the Calendar API
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleEvent {
+    id: String,
+    #[serde(rename = "iCalUID")]
+    ical_uid: Option<String>,
+    status: Option<String>,
+    summary: Option<String>,
+    description: Option<String>,
+    location: Option<String>,
+    visibility: Option<String>,
+    start: EventTime,
+    end: EventTime,
+}
+
+/// Start or end of an event, a date for all-day events
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EventTime {
+    date_time: Option<DateTime<FixedOffset>>,
+    date: Option<NaiveDate>,
+}
+
+impl EventTime {
+    /// Property of the time, e.g. `DTSTART:20240304T090000Z`
+    fn property(&self, name: &str) -> Option<String> {
+        match (self.date_time, self.date) {
+            (Some(time), _) => Some(format!(
+                "{}:{}",
+                name,
+                time.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ")
+            )),
+            (None, Some(date)) => Some(format!("{};VALUE=DATE:{}", name, date.format("%Y%m%d"))),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Fetches the events of a time window from a Google calendar
+///
+/// Returns the events as iCalendar data, like an export of the calendar
+/// would be.
+pub async fn fetch_google_data(
+    calendar_id: &str,
+    key_path: &str,
+    subject: Option<&str>,
+    window: EventWindow,
+) -> Result<String, CalendarError> {
+    let key = ServiceAccountKey::load(key_path)
+        .map_err(|e| CalendarError::FetchError(format!("{:#}", e)))?;
+    fetch_events(API_URL, calendar_id, &key, subject, window).await
+}
+
+/// Fetches the events of a time window from the API at the given base URL
+async fn fetch_events(
+    api_url: &str,
+    calendar_id: &str,
+    key: &ServiceAccountKey,
+    subject: Option<&str>,
+    window: EventWindow,
+) -> Result<String, CalendarError> {
+    let client = reqwest::Client::new();
+    let token = access_token(&client, key, subject).await?;
+    // Calendar IDs are e-mail addresses, which need escaping in the path
+    let mut url = reqwest::Url::parse(api_url)
+        .map_err(|e| CalendarError::FetchError(format!("Invalid API URL {}: {}", api_url, e)))?;
+    url.path_segments_mut()
+        .map_err(|_| CalendarError::FetchError(format!("Invalid API URL {}", api_url)))?
+        .pop_if_empty()
+        .extend(["calendars", calendar_id, "events"]);
+
+    let mut events = Vec::new();
+    let mut page_token = None;
+    loop {
+        let mut query = vec![
+            ("timeMin", window.start.to_rfc3339()),
+            ("timeMax", window.end.to_rfc3339()),
+            // Recurring events as their occurrences
+            ("singleEvents", "true".to_string()),
+            ("orderBy", "startTime".to_string()),
+            ("maxResults", PAGE_SIZE.to_string()),
+        ];
+        if let Some(page_token) = page_token.take() {
+            query.push(("pageToken", page_token));
+        }
+        let response = client
+            .get(url.clone())
+            .bearer_auth(&token)
+            .query(&query)
+            .send()
+            .await
+            .map_err(|e| CalendarError::FetchError(e.to_string()))?;
+        if !response.status().is_success() {
+            return Err(CalendarError::FetchError(format!(
+                "HTTP error: {}",
+                response.status()
+            )));
+        }
+        let page: EventsPage = response
+            .json()
+            .await
+            .map_err(|e| CalendarError::ParseError(format!("Invalid events response: {}", e)))?;
+        events.extend(page.items);
+        match page.next_page_token {
+            Some(next) => page_token = Some(next),
+            None => break,
+        }
+    }
+
+    Ok(events_to_ical(&events))
+}
+
+/// iCalendar data of the given events
+///
+/// Cancelled events are left out. Titles and locations are taken verbatim,
+/// except for line breaks, like the parser reads them.
+fn events_to_ical(events: &[GoogleEvent]) -> String {
+    let single_line = |text: &str| text.replace(['\r', '\n'], " ");
+    let mut ical = String::from(
+        "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//trmnl-meeting-room-display//Google//EN\r\n",
+    );
+    for event in events {
+        if event.status.as_deref() == Some("cancelled") {
+            continue;
+        }
+        let (Some(start), Some(end)) =
+            (event.start.property("DTSTART"), event.end.property("DTEND"))
+        else {
+            continue;
+        };
+        let mut lines = vec![
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}", event.ical_uid.as_deref().unwrap_or(&event.id)),
+            start,
+            end,
+            // Events without a title are shown as such in Google Calendar
+            format!(
+                "SUMMARY:{}",
+                single_line(event.summary.as_deref().unwrap_or("(No title)"))
+            ),
+        ];
+        if let Some(location) = &event.location {
+            lines.push(format!("LOCATION:{}", single_line(location)));
+        }
+        if let Some(description) = &event.description {
+            let escaped = description
+                .replace('\\', "\\\\")
+                .replace("\r\n", "\\n")
+                .replace('\n', "\\n");
+            lines.push(format!("DESCRIPTION:{}", escaped));
+        }
+        if matches!(
+            event.visibility.as_deref(),
+            Some("private" | "confidential")
+        ) {
+            lines.push("CLASS:PRIVATE".to_string());
+        }
+        lines.push("END:VEVENT".to_string());
+        for line in lines {
+            ical.push_str(&line);
+            ical.push_str("\r\n");
+        }
+    }
+    ical.push_str("END:VCALENDAR\r\n");
+    ical
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Form, Json, Router,
+        extract::Query,
+        http::{HeaderMap, StatusCode},
+        routing::{get, post},
+    };
+    use chrono::TimeZone;
+    use openssl::{rsa::Rsa, sign::Verifier};
+
+    use super::*;
+    use crate::calendar::parse_calendar_window;
+
+    fn window() -> EventWindow {
+        EventWindow {
+            start: Utc.with_ymd_and_hms(2024, 3, 4, 0, 0, 0).unwrap().into(),
+            end: Utc.with_ymd_and_hms(2024, 3, 6, 0, 0, 0).unwrap().into(),
+        }
+    }
+
+    fn key(token_uri: &str) -> (ServiceAccountKey, PKey<openssl::pkey::Private>) {
+        let private = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let key = ServiceAccountKey {
+            client_email: format!(
+                "displays-{}@example.iam.gserviceaccount.com",
+                rand::random::<u32>()
+            ),
+            private_key: String::from_utf8(private.private_key_to_pem_pkcs8().unwrap()).unwrap(),
+            token_uri: token_uri.to_string(),
+        };
+        (key, private)
+    }
+
+    #[test]
+    fn test_assertion() {
+        let (key, private) = key("https://oauth2.googleapis.com/token");
+        let assertion = key
+            .assertion(Some("rooms@example.com"), 1_700_000_000)
+            .unwrap();
+
+        let parts: Vec<&str> = assertion.split('.').collect();
+        assert_eq!(parts.len(), 3);
+        let claims: serde_json::Value =
+            serde_json::from_slice(&general_purpose::URL_SAFE_NO_PAD.decode(parts[1]).unwrap())
+                .unwrap();
+        assert_eq!(claims["iss"], key.client_email.as_str());
+        assert_eq!(claims["sub"], "rooms@example.com");
+        assert_eq!(claims["exp"], 1_700_003_600);
+
+        let signature = general_purpose::URL_SAFE_NO_PAD.decode(parts[2]).unwrap();
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &private).unwrap();
+        verifier
+            .update(format!("{}.{}", parts[0], parts[1]).as_bytes())
+            .unwrap();
+        assert!(verifier.verify(&signature).unwrap());
+    }
+
+    #[test]
+    fn test_events_to_ical() {
+        let events: Vec<GoogleEvent> = serde_json::from_value(json!([
+            {
+                "id": "a1",
+                "iCalUID": "standup@google.com",
+                "summary": "Standup, daily",
+                "description": "Agenda:\n1. Status",
+                "start": {"dateTime": "2024-03-04T10:00:00+01:00"},
+                "end": {"dateTime": "2024-03-04T10:15:00+01:00"},
+                "visibility": "private"
+            },
+            {
+                "id": "a2",
+                "status": "cancelled",
+                "summary": "Cancelled",
+                "start": {"dateTime": "2024-03-04T11:00:00+01:00"},
+                "end": {"dateTime": "2024-03-04T12:00:00+01:00"}
+            },
+            {
+                "id": "a3",
+                "start": {"date": "2024-03-05"},
+                "end": {"date": "2024-03-06"}
+            }
+        ]))
+        .unwrap();
+
+        let ical = events_to_ical(&events);
+        let parsed = parse_calendar_window(&ical, window()).unwrap();
+        assert_eq!(parsed.events.len(), 2);
+        let standup = &parsed.events[0];
+        assert_eq!(standup.name, "Standup, daily");
+        assert_eq!(
+            standup.start_time,
+            Utc.with_ymd_and_hms(2024, 3, 4, 9, 0, 0).unwrap()
+        );
+        assert_eq!(standup.description.as_deref(), Some("Agenda:\n1. Status"));
+        assert!(standup.private);
+        assert_eq!(parsed.events[1].name, "(No title)");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_events() {
+        let router = Router::new()
+            .route(
+                "/token",
+                post(|Form(form): Form<HashMap<String, String>>| async move {
+                    assert_eq!(
+                        form["grant_type"],
+                        "urn:ietf:params:oauth:grant-type:jwt-bearer"
+                    );
+                    Json(json!({"access_token": "token-1", "expires_in": 3600}))
+                }),
+            )
+            .route(
+                "/calendars/:id/events",
+                get(
+                    |headers: HeaderMap, Query(query): Query<HashMap<String, String>>| async move {
+                        if headers.get("authorization").unwrap() != "Bearer token-1" {
+                            return Err(StatusCode::UNAUTHORIZED);
+                        }
+                        assert_eq!(query["singleEvents"], "true");
+                        let event = |id: &str, hour: u32| {
+                            json!({
+                                "id": id,
+                                "summary": format!("Meeting {}", id),
+                                "start": {"dateTime": format!("2024-03-04T{:02}:00:00Z", hour)},
+                                "end": {"dateTime": format!("2024-03-04T{:02}:30:00Z", hour)}
+                            })
+                        };
+                        // Two pages of one event each
+                        Ok(Json(match query.get("pageToken") {
+                            None => json!({"items": [event("a", 9)], "nextPageToken": "p2"}),
+                            Some(_) => json!({"items": [event("b", 10)]}),
+                        }))
+                    },
+                ),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let (key, _) = key(&format!("{}/token", base));
+        let ical = fetch_events(
+            &base,
+            "c_1@resource.calendar.google.com",
+            &key,
+            None,
+            window(),
+        )
+        .await
+        .unwrap();
+        let parsed = parse_calendar_window(&ical, window()).unwrap();
+        let names: Vec<&str> = parsed.events.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["Meeting a", "Meeting b"]);
+    }
+}
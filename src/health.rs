@@ -0,0 +1,455 @@
+//! Device health derived from the firmware logs and check-in telemetry
+//!
+//! Log messages are classified into reboots, WiFi reconnects, failed image
+//! fetches and images that could not be drawn. The counts over the last day,
+//! together with the battery voltage and signal strength last reported, make up
+//! a health score from 0 (broken) to 100 (no problems), so that the displays in
+//! need of attention stand out in a large fleet.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Time span of the logs the health score is computed from, in seconds
+pub const HEALTH_WINDOW_SECS: i64 = 24 * 60 * 60;
+
+/// Score penalty per reboot, a few of them in a day indicate a boot loop
+const REBOOT_PENALTY: u32 = 10;
+/// Score penalty per WiFi reconnect
+const WIFI_RECONNECT_PENALTY: u32 = 2;
+/// Score penalty per failed image fetch
+const FETCH_FAILURE_PENALTY: u32 = 5;
+/// Score penalty per image that could not be drawn
+const RENDER_ERROR_PENALTY: u32 = 5;
+/// Score penalty of a device low on battery
+const LOW_BATTERY_PENALTY: u32 = 20;
+/// Score penalty of a device with a weak WiFi signal
+const WEAK_SIGNAL_PENALTY: u32 = 10;
+
+/// Signal strength below which the WiFi signal of a device is weak, in dBm
+pub const WEAK_SIGNAL_RSSI: i64 = -80;
+
+/// Devices without a display request for longer than this are offline, in seconds
+pub const OFFLINE_AFTER_SECS: i64 = 60 * 60;
+
+/// Number of fetched images in a row a device failed to draw, from which the
+/// display watchdog flags it
+pub const FAILED_DISPLAYS_THRESHOLD: u32 = 3;
+
+/// Time a device may be late for a check-in before it counts as missed, in
+/// seconds, e.g. to reconnect to WiFi
+pub const CHECK_IN_GRACE_SECS: i64 = 60;
+
+/// Battery voltage below which a device is low on battery
+pub const LOW_BATTERY_VOLTAGE: f64 = 3.5;
+
+/// Battery voltage below which the display asks for a battery replacement,
+/// unless another threshold is configured
+pub const CRITICAL_BATTERY_VOLTAGE: f64 = 3.3;
+
+/// Battery voltage from which a battery counts as replaced, e.g. a charged one
+pub const BATTERY_RECOVERED_VOLTAGE: f64 = 3.9;
+
+/// Battery voltage at which the battery level is shown as 0%
+pub const EMPTY_BATTERY_VOLTAGE: f64 = 3.0;
+
+/// Battery voltage at which the battery level is shown as 100%
+pub const FULL_BATTERY_VOLTAGE: f64 = 4.1;
+
+/// Time span battery voltage readings are kept for, in seconds
+pub const BATTERY_HISTORY_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// Number of recent voltage readings the battery state is derived from
+pub const BATTERY_SAMPLES: usize = 3;
+
+/// Whether the battery of a device is critically low
+///
+/// Single readings fluctuate with temperature and load (e.g. during a WiFi
+/// transmission), so the state follows the median of the last
+/// [`BATTERY_SAMPLES`] readings, oldest first. Between the warning and the
+/// recovered voltage the previous state is kept, so that the notice does not
+/// flicker on and off; it disappears once a new battery is detected.
+pub fn battery_critical(was_critical: bool, readings: &[f64], warning_voltage: f64) -> bool {
+    let Some(start) = readings.len().checked_sub(BATTERY_SAMPLES) else {
+        return was_critical;
+    };
+    let mut recent = readings[start..].to_vec();
+    recent.sort_by(f64::total_cmp);
+    let median = recent[recent.len() / 2];
+    if median < warning_voltage {
+        true
+    } else if median >= BATTERY_RECOVERED_VOLTAGE {
+        false
+    } else {
+        was_critical
+    }
+}
+
+/// Battery level in percent, interpolated linearly from the voltage
+///
+/// Only a rough indication, as the discharge curve of a LiPo battery is not
+/// linear, but good enough to tell a fresh battery from an old one.
+pub fn battery_percent(voltage: f64) -> u8 {
+    let level = (voltage - EMPTY_BATTERY_VOLTAGE) / (FULL_BATTERY_VOLTAGE - EMPTY_BATTERY_VOLTAGE);
+    (level.clamp(0.0, 1.0) * 100.0).round() as u8
+}
+
+/// Connection status of a registered device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceStatus {
+    /// Requested an image within [`OFFLINE_AFTER_SECS`]
+    Online,
+    /// Requested images before, but not recently
+    Offline,
+    /// Set up, but never requested an image
+    Pending,
+}
+
+impl DeviceStatus {
+    /// Status of a device last seen at the given Unix timestamp
+    pub fn of(last_seen_at: Option<i64>, now: i64) -> Self {
+        match last_seen_at {
+            Some(seen) if now - seen <= OFFLINE_AFTER_SECS => DeviceStatus::Online,
+            Some(_) => DeviceStatus::Offline,
+            None => DeviceStatus::Pending,
+        }
+    }
+}
+
+/// Kind of problem reported in a log message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogIssue {
+    Reboot,
+    WifiReconnect,
+    FetchFailure,
+    /// The image was fetched, but could not be decoded or drawn
+    RenderError,
+}
+
+impl LogIssue {
+    /// Classify a single log message, e.g. `WiFi connection failed, retrying`
+    pub fn classify(message: &str) -> Option<Self> {
+        let message = message.to_lowercase();
+        let has = |words: &[&str]| words.iter().any(|word| message.contains(word));
+        if has(&["reboot", "booting", "brownout", "panic", "reset reason"]) {
+            Some(LogIssue::Reboot)
+        } else if has(&["wifi", "wi-fi"]) && has(&["reconnect", "disconnect", "fail", "lost"]) {
+            Some(LogIssue::WifiReconnect)
+        } else if has(&["download", "fetch", "http"]) && has(&["fail", "error", "timeout"]) {
+            Some(LogIssue::FetchFailure)
+        } else if has(&["draw", "render", "decod", "display", "bmp", "png", "image"])
+            && has(&["fail", "error", "invalid", "corrupt", "unsupported"])
+        {
+            Some(LogIssue::RenderError)
+        } else {
+            None
+        }
+    }
+}
+
+/// Problem counts of a device
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceHealth {
+    pub reboots: u32,
+    pub wifi_reconnects: u32,
+    pub fetch_failures: u32,
+    #[serde(default)]
+    pub render_errors: u32,
+    /// The last reported battery voltage is below [`LOW_BATTERY_VOLTAGE`]
+    #[serde(default)]
+    pub low_battery: bool,
+    /// The last reported signal strength is below [`WEAK_SIGNAL_RSSI`]
+    #[serde(default)]
+    pub weak_signal: bool,
+}
+
+impl DeviceHealth {
+    /// Count the problems in a log request body
+    pub fn record(&mut self, body: &str) {
+        for message in log_messages(body) {
+            match LogIssue::classify(&message) {
+                Some(LogIssue::Reboot) => self.reboots += 1,
+                Some(LogIssue::WifiReconnect) => self.wifi_reconnects += 1,
+                Some(LogIssue::FetchFailure) => self.fetch_failures += 1,
+                Some(LogIssue::RenderError) => self.render_errors += 1,
+                None => {}
+            }
+        }
+    }
+
+    /// Take the battery voltage and signal strength of the last check-in into account
+    pub fn record_telemetry(&mut self, battery_voltage: Option<f64>, rssi: Option<i64>) {
+        self.low_battery = battery_voltage.is_some_and(|voltage| voltage < LOW_BATTERY_VOLTAGE);
+        self.weak_signal = rssi.is_some_and(|rssi| rssi < WEAK_SIGNAL_RSSI);
+    }
+
+    /// Health score from 0 (broken) to 100 (no problems)
+    pub fn score(&self) -> u8 {
+        let penalty = self.reboots * REBOOT_PENALTY
+            + self.wifi_reconnects * WIFI_RECONNECT_PENALTY
+            + self.fetch_failures * FETCH_FAILURE_PENALTY
+            + self.render_errors * RENDER_ERROR_PENALTY
+            + u32::from(self.low_battery) * LOW_BATTERY_PENALTY
+            + u32::from(self.weak_signal) * WEAK_SIGNAL_PENALTY;
+        100u32.saturating_sub(penalty) as u8
+    }
+}
+
+/// Health of all devices with log entries, keyed by upper-case device ID
+///
+/// The entries are pairs of device ID and logged message.
+pub fn fleet_health<'a>(
+    entries: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> HashMap<String, DeviceHealth> {
+    let mut health: HashMap<String, DeviceHealth> = HashMap::new();
+    for (device_id, message) in entries {
+        health
+            .entry(device_id.to_ascii_uppercase())
+            .or_default()
+            .record(message);
+    }
+    health
+}
+
+/// Number of the latest images in a row a device fetched but failed to draw
+///
+/// A fetch failed if a render error was logged after it and before the next
+/// fetch. A fetch followed by another one without a render error in between
+/// was drawn and ends the streak. The logs of the latest fetch may still be
+/// on their way, so it only counts once it failed. Both lists are Unix
+/// timestamps, oldest first.
+pub fn failed_displays(fetches: &[i64], render_errors: &[i64]) -> u32 {
+    let mut failed = 0;
+    for (i, &fetched_at) in fetches.iter().enumerate() {
+        let next = fetches.get(i + 1).copied();
+        let has_error = render_errors
+            .iter()
+            .any(|&at| at >= fetched_at && next.is_none_or(|next| at < next));
+        if has_error {
+            failed += 1;
+        } else if next.is_some() {
+            failed = 0;
+        }
+    }
+    failed
+}
+
+/// Number of check-ins a device missed by `now`
+///
+/// The device was expected at `next_check_in_at` and every `interval_seconds`
+/// after that, each with a grace of [`CHECK_IN_GRACE_SECS`].
+pub fn missed_check_ins(next_check_in_at: i64, interval_seconds: i64, now: i64) -> u32 {
+    let late = now - next_check_in_at - CHECK_IN_GRACE_SECS;
+    if late <= 0 {
+        return 0;
+    }
+    u32::try_from(1 + late / interval_seconds.max(1)).unwrap_or(u32::MAX)
+}
+
+/// Suggested fix for a device that fails to draw the images it fetches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Remediation {
+    /// Pin the image format to BMP, which every firmware version can draw
+    SwitchToBmp,
+    /// Update the firmware, or power-cycle the device if it is up to date
+    UpdateFirmware,
+}
+
+impl Remediation {
+    /// Remediation for a device that was last served an image of the given format
+    pub fn for_format(last_payload_format: Option<&str>) -> Self {
+        match last_payload_format {
+            Some(format) if format != "bmp" => Remediation::SwitchToBmp,
+            _ => Remediation::UpdateFirmware,
+        }
+    }
+
+    /// Instructions for the admin
+    pub fn description(&self) -> &'static str {
+        match self {
+            Remediation::SwitchToBmp => "Set the image format of the device to bmp",
+            Remediation::UpdateFirmware => "Update the firmware or power-cycle the device",
+        }
+    }
+}
+
+/// Single messages of a log request body
+///
+/// The firmware sends a JSON document with a `log_message` per entry; other
+/// bodies are taken line by line.
+pub fn log_messages(body: &str) -> Vec<String> {
+    fn collect(value: &serde_json::Value, messages: &mut Vec<String>) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, value) in map {
+                    match value {
+                        serde_json::Value::String(message) if key == "log_message" => {
+                            messages.push(message.clone())
+                        }
+                        _ => collect(value, messages),
+                    }
+                }
+            }
+            serde_json::Value::Array(values) => {
+                values.iter().for_each(|value| collect(value, messages))
+            }
+            _ => {}
+        }
+    }
+
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(value @ (serde_json::Value::Object(_) | serde_json::Value::Array(_))) => {
+            let mut messages = Vec::new();
+            collect(&value, &mut messages);
+            messages
+        }
+        _ => body.lines().map(str::to_string).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_log_messages() {
+        assert_eq!(
+            LogIssue::classify("Brownout detector was triggered"),
+            Some(LogIssue::Reboot)
+        );
+        assert_eq!(
+            LogIssue::classify("WiFi connection failed, retrying"),
+            Some(LogIssue::WifiReconnect)
+        );
+        assert_eq!(
+            LogIssue::classify("HTTP error 500 while downloading image"),
+            Some(LogIssue::FetchFailure)
+        );
+        assert_eq!(
+            LogIssue::classify("Failed to decode BMP: invalid header"),
+            Some(LogIssue::RenderError)
+        );
+        assert_eq!(LogIssue::classify("Display refreshed"), None);
+        assert_eq!(LogIssue::classify("WiFi connected"), None);
+    }
+
+    #[test]
+    fn test_device_status() {
+        let now = 1_700_000_000;
+        assert_eq!(DeviceStatus::of(Some(now - 60), now), DeviceStatus::Online);
+        assert_eq!(
+            DeviceStatus::of(Some(now - OFFLINE_AFTER_SECS - 1), now),
+            DeviceStatus::Offline
+        );
+        assert_eq!(DeviceStatus::of(None, now), DeviceStatus::Pending);
+    }
+
+    #[test]
+    fn test_fleet_health() {
+        let firmware_log = r#"{"log":{"logs_array":[
+            {"log_id":1,"log_message":"Rebooting after panic"},
+            {"log_id":2,"log_message":"Failed to fetch image: timeout"},
+            {"log_id":3,"log_message":"Display refreshed"},
+            {"log_id":4,"log_message":"PNG decode error, skipping draw"}
+        ]}}"#;
+        let health = fleet_health([
+            ("aa:bb:cc:dd:ee:ff", firmware_log),
+            ("AA:BB:CC:DD:EE:FF", "WiFi lost\nWiFi reconnect attempt 2"),
+            ("00:11:22:33:44:55", "Display refreshed"),
+        ]);
+
+        let flaky = &health["AA:BB:CC:DD:EE:FF"];
+        assert_eq!(
+            *flaky,
+            DeviceHealth {
+                reboots: 1,
+                wifi_reconnects: 2,
+                fetch_failures: 1,
+                render_errors: 1,
+                ..DeviceHealth::default()
+            }
+        );
+        assert_eq!(flaky.score(), 100 - 10 - 2 * 2 - 5 - 5);
+        assert_eq!(health["00:11:22:33:44:55"].score(), 100);
+
+        let mut telemetry = DeviceHealth::default();
+        telemetry.record_telemetry(Some(3.4), Some(-85));
+        assert!(telemetry.low_battery && telemetry.weak_signal);
+        assert_eq!(telemetry.score(), 100 - 20 - 10);
+        telemetry.record_telemetry(Some(3.9), None);
+        assert_eq!(telemetry.score(), 100);
+
+        let boot_loop = DeviceHealth {
+            reboots: 20,
+            ..DeviceHealth::default()
+        };
+        assert_eq!(boot_loop.score(), 0);
+    }
+
+    #[test]
+    fn test_failed_displays() {
+        // Fetches every 5 minutes, each followed by a render error
+        let fetches = [0, 300, 600, 900];
+        assert_eq!(failed_displays(&fetches, &[10, 310, 610]), 3);
+        // A fetch drawn without error ends the streak
+        assert_eq!(failed_displays(&fetches, &[10, 610]), 1);
+        assert_eq!(failed_displays(&fetches, &[10, 310]), 0);
+        // The logs of the latest fetch may not have arrived yet
+        assert_eq!(failed_displays(&fetches, &[10, 310, 610, 910]), 4);
+        assert_eq!(failed_displays(&[], &[10]), 0);
+
+        assert_eq!(
+            Remediation::for_format(Some("png")),
+            Remediation::SwitchToBmp
+        );
+        assert_eq!(
+            Remediation::for_format(Some("bmp")),
+            Remediation::UpdateFirmware
+        );
+        assert_eq!(Remediation::for_format(None), Remediation::UpdateFirmware);
+    }
+
+    #[test]
+    fn test_missed_check_ins() {
+        // Expected at 1000 and then every 300 seconds
+        assert_eq!(missed_check_ins(1000, 300, 900), 0);
+        assert_eq!(missed_check_ins(1000, 300, 1000 + CHECK_IN_GRACE_SECS), 0);
+        assert_eq!(missed_check_ins(1000, 300, 1100), 1);
+        assert_eq!(missed_check_ins(1000, 300, 1400), 2);
+        assert_eq!(missed_check_ins(1000, 300, 1700), 3);
+        assert_eq!(missed_check_ins(1000, 0, 1100), 41);
+    }
+
+    #[test]
+    fn test_battery_critical() {
+        let critical = |was_critical, readings: &[f64]| {
+            battery_critical(was_critical, readings, CRITICAL_BATTERY_VOLTAGE)
+        };
+
+        // Too few readings keep the previous state
+        assert!(!critical(false, &[3.0, 3.0]));
+        assert!(critical(true, &[]));
+
+        // A single low reading under load is no reason for a notice
+        assert!(!critical(false, &[3.6, 3.1, 3.6]));
+        assert!(critical(false, &[3.4, 3.2, 3.25]));
+
+        // Recovering a little does not clear the notice, a new battery does
+        assert!(critical(true, &[3.2, 3.4, 3.5]));
+        assert!(critical(true, &[3.2, 4.1, 3.3]));
+        assert!(!critical(true, &[3.2, 4.1, 4.15]));
+
+        // A higher threshold warns earlier
+        assert!(battery_critical(false, &[3.4, 3.45, 3.5], 3.6));
+    }
+
+    #[test]
+    fn test_battery_percent() {
+        assert_eq!(battery_percent(2.5), 0);
+        assert_eq!(battery_percent(3.55), 50);
+        assert_eq!(battery_percent(4.1), 100);
+        assert_eq!(battery_percent(4.3), 100);
+    }
+}
@@ -0,0 +1,496 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use log::info;
+use sha2::{Digest, Sha256};
+
+/// Storage for rendered images served via hosted URLs
+///
+/// Images are stored under content-addressed names (see [`image_name`]), so
+/// storing the same frame twice is harmless and every instance of a
+/// multi-instance deployment can serve any frame from a shared backend.
+#[async_trait]
+pub trait ImageStore: Send + Sync {
+    /// Store image data under the given name
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<()>;
+
+    /// Retrieve the image stored under the given name
+    async fn get(&self, name: &str) -> Result<Option<Vec<u8>>>;
+}
+
+/// Image store backend selection
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImageStoreConfig {
+    /// Keep the most recent images in memory
+    Memory,
+    /// Store images as files in a directory
+    Disk { path: String },
+    /// Store images in an S3-compatible bucket (requires the `s3` feature)
+    S3 {
+        bucket: String,
+        region: String,
+        /// Custom endpoint, e.g. for MinIO
+        endpoint: Option<String>,
+        /// Key prefix for stored images
+        prefix: String,
+    },
+}
+
+/// How rendered images are delivered to devices
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageDelivery {
+    /// Embed the image in the display response as a data URL
+    Inline,
+    /// Store the image and return a URL it can be downloaded from
+    Hosted,
+    /// Like [`ImageDelivery::Hosted`], but the image is sent with chunked
+    /// transfer encoding, for proxies that stall on larger plain responses
+    Chunked,
+}
+
+impl ImageDelivery {
+    /// Name of the mode, as used in the configuration
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImageDelivery::Inline => "inline",
+            ImageDelivery::Hosted => "hosted",
+            ImageDelivery::Chunked => "chunked",
+        }
+    }
+
+    /// Pick the delivery mode for a device
+    ///
+    /// A mode set by an admin always wins. Otherwise the configured default is
+    /// used, unless the device lists the modes it supports (in the
+    /// `Image-Delivery` header, e.g. `hosted, inline`) and the default is not
+    /// among them, in which case the first supported mode is used.
+    pub fn negotiate(
+        default: ImageDelivery,
+        device_setting: Option<ImageDelivery>,
+        supported: Option<&str>,
+    ) -> ImageDelivery {
+        if let Some(mode) = device_setting {
+            return mode;
+        }
+        let supported: Vec<ImageDelivery> = supported
+            .into_iter()
+            .flat_map(|modes| modes.split(','))
+            .filter_map(|mode| mode.trim().parse().ok())
+            .collect();
+        if supported.is_empty() || supported.contains(&default) {
+            default
+        } else {
+            supported[0]
+        }
+    }
+}
+
+impl std::str::FromStr for ImageDelivery {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "inline" => Ok(ImageDelivery::Inline),
+            "hosted" => Ok(ImageDelivery::Hosted),
+            "chunked" => Ok(ImageDelivery::Chunked),
+            other => Err(anyhow::anyhow!(
+                "Unknown IMAGE_DELIVERY: {} (expected inline, hosted or chunked)",
+                other
+            )),
+        }
+    }
+}
+
+/// Short hash of data, 16 hex digits
+fn content_hash(data: &[u8]) -> String {
+    let hash = Sha256::digest(data);
+    hash[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Content-addressed name for image data
+pub fn image_name(data: &[u8], extension: &str) -> String {
+    format!("{}.{}", content_hash(data), extension)
+}
+
+/// Filename of image data reported to devices, e.g. `room-a-9f3c2a1b04d7e6c5.bmp`
+///
+/// Firmware compares it with the filename of the image it shows and skips
+/// downloading unchanged images, so it changes exactly when the image does.
+pub fn display_filename(prefix: &str, data: &[u8], extension: &str) -> String {
+    format!("{}-{}", prefix, image_name(data, extension))
+}
+
+/// Directory of the static files served under `/static`
+pub const STATIC_DIR: &str = "static";
+
+/// Content hashes of the static files, for cache-busting URLs
+///
+/// Files are hashed once when the server starts, so that firmware and proxies
+/// fetch a replaced file, e.g. a new setup logo, after the next restart.
+#[derive(Debug, Default)]
+pub struct StaticAssets {
+    /// Hashes by path relative to the static directory, with `/` separators
+    hashes: HashMap<String, String>,
+}
+
+impl StaticAssets {
+    /// Hash all files below a directory
+    ///
+    /// A missing directory has no files, their URLs then carry no hash.
+    pub fn load(dir: &str) -> Result<Self> {
+        let mut assets = Self::default();
+        let root = PathBuf::from(dir);
+        if root.is_dir() {
+            assets.load_dir(&root, "")?;
+        }
+        Ok(assets)
+    }
+
+    fn load_dir(&mut self, dir: &Path, prefix: &str) -> Result<()> {
+        let entries = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read static directory {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry
+                .with_context(|| format!("Failed to read static directory {}", dir.display()))?;
+            let path = entry.path();
+            let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            if path.is_dir() {
+                self.load_dir(&path, &format!("{}/", name))?;
+            } else {
+                let data = std::fs::read(&path)
+                    .with_context(|| format!("Failed to read static file {}", path.display()))?;
+                self.hashes.insert(name, content_hash(&data));
+            }
+        }
+        Ok(())
+    }
+
+    /// Public URL of a static file, with the hash of its content as `v` parameter
+    pub fn url(&self, server_url: &str, path: &str) -> String {
+        match self.hashes.get(path) {
+            Some(hash) => format!("{}/static/{}?v={}", server_url, path, hash),
+            None => format!("{}/static/{}", server_url, path),
+        }
+    }
+}
+
+/// Returns true if the name is a plausible stored image name
+///
+/// Prevents path traversal in the disk backend and arbitrary keys in S3.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+        && !name.starts_with('.')
+}
+
+/// Create the image store for the given configuration
+pub fn create_image_store(config: &ImageStoreConfig) -> Result<Arc<dyn ImageStore>> {
+    match config {
+        ImageStoreConfig::Memory => Ok(Arc::new(MemoryImageStore::new(256))),
+        ImageStoreConfig::Disk { path } => Ok(Arc::new(DiskImageStore::new(path)?)),
+        #[cfg(feature = "s3")]
+        ImageStoreConfig::S3 {
+            bucket,
+            region,
+            endpoint,
+            prefix,
+        } => Ok(Arc::new(s3::S3ImageStore::new(
+            bucket,
+            region,
+            endpoint.as_deref(),
+            prefix,
+        )?)),
+        #[cfg(not(feature = "s3"))]
+        ImageStoreConfig::S3 { .. } => Err(anyhow::anyhow!(
+            "S3 image store requested, but the server was built without the s3 feature"
+        )),
+    }
+}
+
+/// In-memory image store keeping a bounded number of the most recent images
+pub struct MemoryImageStore {
+    images: Mutex<StoredImages>,
+    capacity: usize,
+}
+
+/// Images held by a [`MemoryImageStore`], with names in insertion order
+#[derive(Default)]
+struct StoredImages {
+    data: HashMap<String, Vec<u8>>,
+    order: VecDeque<String>,
+}
+
+impl MemoryImageStore {
+    /// Create a store holding at most `capacity` images
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            images: Mutex::new(StoredImages::default()),
+            capacity,
+        }
+    }
+}
+
+#[async_trait]
+impl ImageStore for MemoryImageStore {
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        let mut guard = self
+            .images
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to acquire lock on image store: {}", e))?;
+        let images = &mut *guard;
+
+        if images.data.insert(name.to_string(), data).is_none() {
+            images.order.push_back(name.to_string());
+        }
+        while images.order.len() > self.capacity {
+            if let Some(oldest) = images.order.pop_front() {
+                images.data.remove(&oldest);
+            }
+        }
+        Ok(())
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let guard = self
+            .images
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to acquire lock on image store: {}", e))?;
+        Ok(guard.data.get(name).cloned())
+    }
+}
+
+/// Image store writing files to a local directory
+pub struct DiskImageStore {
+    path: PathBuf,
+}
+
+impl DiskImageStore {
+    /// Create a store in the given directory, creating it if necessary
+    pub fn new(path: &str) -> Result<Self> {
+        std::fs::create_dir_all(path)
+            .with_context(|| format!("Failed to create image store directory {}", path))?;
+        info!("Storing rendered images in {}", path);
+        Ok(Self {
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+#[async_trait]
+impl ImageStore for DiskImageStore {
+    async fn put(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        if !is_valid_name(name) {
+            return Err(anyhow::anyhow!("Invalid image name: {}", name));
+        }
+        let path = self.path.join(name);
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(());
+        }
+
+        // Write to a temporary file first, so that readers never see partial images
+        let tmp_path = self.path.join(format!(".{}.tmp", name));
+        tokio::fs::write(&tmp_path, data)
+            .await
+            .with_context(|| format!("Failed to write image {}", tmp_path.display()))?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .with_context(|| format!("Failed to move image into place at {}", path.display()))
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        if !is_valid_name(name) {
+            return Ok(None);
+        }
+        match tokio::fs::read(self.path.join(name)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read image {}", name)),
+        }
+    }
+}
+
+#[cfg(feature = "s3")]
+mod s3 {
+    use anyhow::{Context, Result};
+    use async_trait::async_trait;
+    use s3::{Bucket, Region, creds::Credentials};
+
+    use super::{ImageStore, is_valid_name};
+
+    /// Image store backed by an S3-compatible bucket (AWS S3, MinIO, ...)
+    ///
+    /// Credentials are taken from the standard `AWS_ACCESS_KEY_ID` and
+    /// `AWS_SECRET_ACCESS_KEY` environment variables.
+    pub struct S3ImageStore {
+        bucket: Box<Bucket>,
+        prefix: String,
+    }
+
+    impl S3ImageStore {
+        pub fn new(
+            bucket: &str,
+            region: &str,
+            endpoint: Option<&str>,
+            prefix: &str,
+        ) -> Result<Self> {
+            let region = match endpoint {
+                Some(endpoint) => Region::Custom {
+                    region: region.to_string(),
+                    endpoint: endpoint.to_string(),
+                },
+                None => region.parse().context("Invalid S3 region")?,
+            };
+            let credentials = Credentials::from_env().context("Failed to load S3 credentials")?;
+            let mut bucket =
+                Bucket::new(bucket, region, credentials).context("Failed to set up S3 bucket")?;
+            if endpoint.is_some() {
+                bucket = bucket.with_path_style();
+            }
+            Ok(Self {
+                bucket,
+                prefix: prefix.to_string(),
+            })
+        }
+
+        fn key(&self, name: &str) -> String {
+            format!("{}{}", self.prefix, name)
+        }
+    }
+
+    #[async_trait]
+    impl ImageStore for S3ImageStore {
+        async fn put(&self, name: &str, data: Vec<u8>) -> Result<()> {
+            if !is_valid_name(name) {
+                return Err(anyhow::anyhow!("Invalid image name: {}", name));
+            }
+            self.bucket
+                .put_object(self.key(name), &data)
+                .await
+                .with_context(|| format!("Failed to upload image {}", name))?;
+            Ok(())
+        }
+
+        async fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+            if !is_valid_name(name) {
+                return Ok(None);
+            }
+            match self.bucket.get_object(self.key(name)).await {
+                Ok(response) if response.status_code() == 200 => {
+                    Ok(Some(response.bytes().to_vec()))
+                }
+                Ok(_) => Ok(None),
+                Err(s3::error::S3Error::HttpFailWithBody(404, _)) => Ok(None),
+                Err(e) => Err(e).with_context(|| format!("Failed to download image {}", name)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate_image_delivery() {
+        use ImageDelivery::*;
+
+        assert_eq!(ImageDelivery::negotiate(Inline, None, None), Inline);
+        assert_eq!(ImageDelivery::negotiate(Inline, Some(Hosted), None), Hosted);
+        assert_eq!(
+            ImageDelivery::negotiate(Inline, Some(Inline), Some("hosted")),
+            Inline
+        );
+        assert_eq!(
+            ImageDelivery::negotiate(Inline, None, Some("chunked, hosted")),
+            Chunked
+        );
+        assert_eq!(
+            ImageDelivery::negotiate(Hosted, None, Some("inline,hosted")),
+            Hosted
+        );
+        assert_eq!(
+            ImageDelivery::negotiate(Hosted, None, Some("carrier-pigeon")),
+            Hosted
+        );
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_evicts_oldest() {
+        let store = MemoryImageStore::new(2);
+        store.put("a.bmp", vec![1]).await.unwrap();
+        store.put("b.bmp", vec![2]).await.unwrap();
+        store.put("c.bmp", vec![3]).await.unwrap();
+
+        assert_eq!(store.get("a.bmp").await.unwrap(), None);
+        assert_eq!(store.get("c.bmp").await.unwrap(), Some(vec![3]));
+    }
+
+    #[test]
+    fn test_static_asset_urls() {
+        let dir = std::env::temp_dir().join("trmnl-static-assets-test");
+        std::fs::create_dir_all(dir.join("logos")).unwrap();
+        std::fs::write(dir.join("setup.bmp"), b"logo").unwrap();
+        std::fs::write(dir.join("logos").join("acme.bmp"), b"acme").unwrap();
+
+        let assets = StaticAssets::load(dir.to_str().unwrap()).unwrap();
+        let url = assets.url("http://localhost", "setup.bmp");
+        assert_eq!(
+            url,
+            format!(
+                "http://localhost/static/setup.bmp?v={}",
+                content_hash(b"logo")
+            )
+        );
+        assert!(
+            assets
+                .url("http://localhost", "logos/acme.bmp")
+                .ends_with(&content_hash(b"acme"))
+        );
+        assert_eq!(
+            assets.url("http://localhost", "missing.bmp"),
+            "http://localhost/static/missing.bmp"
+        );
+
+        // A replaced file gets a new URL
+        std::fs::write(dir.join("setup.bmp"), b"new logo").unwrap();
+        let assets = StaticAssets::load(dir.to_str().unwrap()).unwrap();
+        assert_ne!(assets.url("http://localhost", "setup.bmp"), url);
+
+        let _ = std::fs::remove_dir_all(dir);
+        let missing = StaticAssets::load("no-such-static-dir").unwrap();
+        assert_eq!(
+            missing.url("http://localhost", "setup.bmp"),
+            "http://localhost/static/setup.bmp"
+        );
+    }
+
+    #[test]
+    fn test_display_filename() {
+        let name = display_filename("room-a", b"frame", "bmp");
+        assert_eq!(name, format!("room-a-{}", image_name(b"frame", "bmp")));
+        assert_eq!(name.len(), "room-a-".len() + 16 + ".bmp".len());
+        assert_ne!(name, display_filename("room-a", b"other frame", "bmp"));
+    }
+
+    #[tokio::test]
+    async fn test_disk_store_round_trip() {
+        let dir = std::env::temp_dir().join("trmnl-image-store-test");
+        let store = DiskImageStore::new(dir.to_str().unwrap()).unwrap();
+
+        let name = image_name(b"frame", "bmp");
+        store.put(&name, b"frame".to_vec()).await.unwrap();
+        assert_eq!(store.get(&name).await.unwrap(), Some(b"frame".to_vec()));
+        assert_eq!(store.get("../etc/passwd").await.unwrap(), None);
+        assert!(store.put("../evil", vec![]).await.is_err());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,369 @@
+//! Translated labels shown on the displays
+//!
+//! Label packs are [Fluent](https://projectfluent.org) resources, one per
+//! language. The packs in `locales/` are embedded into the binary. Packs in a
+//! custom labels directory (`<language>.ftl`) override single messages of an
+//! embedded pack or add a new language. The packs can be replaced at runtime
+//! through [`SharedLabels`], e.g. after the labels directory changed.
+
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    fmt, fs,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, Result};
+use fluent_bundle::{FluentArgs, FluentResource, FluentValue, concurrent::FluentBundle};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use unic_langid::LanguageIdentifier;
+
+/// Language used for messages missing in a room's label pack
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// Label packs embedded into the binary
+const EMBEDDED_PACKS: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.ftl")),
+    ("de", include_str!("../locales/de.ftl")),
+    ("fr", include_str!("../locales/fr.ftl")),
+    ("it", include_str!("../locales/it.ftl")),
+    ("es", include_str!("../locales/es.ftl")),
+    ("ja", include_str!("../locales/ja.ftl")),
+];
+
+type Bundle = FluentBundle<FluentResource>;
+
+/// All label packs, by language
+#[derive(Clone)]
+pub struct Labels {
+    bundles: Arc<HashMap<String, Bundle>>,
+}
+
+impl Labels {
+    /// The embedded label packs
+    pub fn embedded() -> Self {
+        Self {
+            bundles: Arc::new(embedded_bundles()),
+        }
+    }
+
+    /// The embedded label packs, extended by the packs in a custom labels directory
+    pub fn load(custom_dir: Option<&str>) -> Result<Self> {
+        let Some(dir) = custom_dir else {
+            return Ok(Self::embedded());
+        };
+
+        let mut bundles = embedded_bundles();
+        let entries = fs::read_dir(dir)
+            .with_context(|| format!("Failed to read labels directory {}", dir))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "ftl") {
+                continue;
+            }
+            let Some(language) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let source = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read label pack {}", path.display()))?;
+            add_pack(&mut bundles, language, source)
+                .with_context(|| format!("Invalid label pack {}", path.display()))?;
+            info!("Loaded custom labels for language {}", language);
+        }
+        Ok(Self {
+            bundles: Arc::new(bundles),
+        })
+    }
+
+    /// Returns true if there is a label pack for the language (or its base language)
+    pub fn has_language(&self, language: &str) -> bool {
+        self.bundle(language).is_some()
+    }
+
+    /// Labels in the given language, e.g. `de` or `de-CH`
+    ///
+    /// A regional variant without a pack of its own uses the pack of the
+    /// base language. Unknown languages get the default language.
+    pub fn pack(&self, language: &str) -> LabelPack<'_> {
+        LabelPack {
+            bundle: self.bundle(language),
+            fallback: &self.bundles[DEFAULT_LANGUAGE],
+        }
+    }
+
+    fn bundle(&self, language: &str) -> Option<&Bundle> {
+        self.bundles.get(language).or_else(|| {
+            let base = language.split(['-', '_']).next()?;
+            self.bundles.get(base)
+        })
+    }
+}
+
+impl Default for Labels {
+    fn default() -> Self {
+        Self::embedded()
+    }
+}
+
+impl fmt::Debug for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut languages: Vec<&String> = self.bundles.keys().collect();
+        languages.sort();
+        f.debug_struct("Labels")
+            .field("languages", &languages)
+            .finish()
+    }
+}
+
+/// Label packs that can be replaced at runtime
+///
+/// Readers take a cheap snapshot, so a concurrent update never changes the
+/// labels in the middle of a request.
+#[derive(Debug, Clone, Default)]
+pub struct SharedLabels(Arc<RwLock<Labels>>);
+
+impl SharedLabels {
+    pub fn new(labels: Labels) -> Self {
+        Self(Arc::new(RwLock::new(labels)))
+    }
+
+    /// The current label packs
+    pub fn snapshot(&self) -> Labels {
+        match self.0.read() {
+            Ok(labels) => labels.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Replace all label packs, returning the previous ones
+    pub fn replace(&self, labels: Labels) -> Labels {
+        let mut guard = match self.0.write() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        std::mem::replace(&mut *guard, labels)
+    }
+}
+
+fn embedded_bundles() -> HashMap<String, Bundle> {
+    let mut bundles = HashMap::new();
+    for (language, source) in EMBEDDED_PACKS {
+        add_pack(&mut bundles, language, source.to_string())
+            .expect("Embedded label packs are valid");
+    }
+    bundles
+}
+
+/// Add a label pack, overriding the messages of an existing pack of the same language
+fn add_pack(bundles: &mut HashMap<String, Bundle>, language: &str, source: String) -> Result<()> {
+    let resource = FluentResource::try_new(source)
+        .map_err(|(_, errors)| anyhow::anyhow!("Syntax errors: {:?}", errors))?;
+    let bundle = match bundles.entry(language.to_string()) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let id: LanguageIdentifier = language
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid language {}: {}", language, e))?;
+            let mut bundle = FluentBundle::new_concurrent(vec![id]);
+            // The display font has no glyphs for the Unicode isolation marks
+            bundle.set_use_isolating(false);
+            entry.insert(bundle)
+        }
+    };
+    bundle.add_resource_overriding(resource);
+    Ok(())
+}
+
+/// Arguments with which messages are test-formatted by [`validate_pack`]
+const SAMPLE_ARGS: &[(&str, &str)] = &[
+    ("minutes", "5"),
+    ("next", "Weekly"),
+    ("time", "10:00"),
+    ("floor", "3"),
+    ("count", "8"),
+    ("summary", "Projector broken"),
+    ("code", "CAL-01"),
+];
+
+/// Result of validating a label pack
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PackValidation {
+    /// Syntax and formatting errors, the pack is unusable if there are any
+    pub errors: Vec<String>,
+    /// Messages of the default language missing in the pack, shown in English
+    pub missing: Vec<String>,
+    /// Messages in the pack that are never shown
+    pub unknown: Vec<String>,
+}
+
+/// IDs of the messages defined in a label pack
+fn message_ids(source: &str) -> Vec<&str> {
+    source
+        .lines()
+        .filter(|line| line.starts_with(|c: char| c.is_ascii_lowercase()))
+        .filter_map(|line| line.split_once('=').map(|(id, _)| id.trim()))
+        .collect()
+}
+
+/// Check a label pack before deploying it to the labels directory
+///
+/// Every message is formatted with sample arguments, so that references to
+/// arguments the server does not provide are reported as well.
+pub fn validate_pack(language: &str, source: &str) -> PackValidation {
+    let known = message_ids(EMBEDDED_PACKS[0].1);
+    let mut validation = PackValidation {
+        unknown: message_ids(source)
+            .into_iter()
+            .filter(|id| !known.contains(id))
+            .map(str::to_string)
+            .collect(),
+        ..PackValidation::default()
+    };
+
+    let mut bundles = HashMap::new();
+    if let Err(e) = add_pack(&mut bundles, language, source.to_string()) {
+        validation.errors.push(format!("{:#}", e));
+        return validation;
+    }
+    let bundle = &bundles[language];
+    let mut args = FluentArgs::new();
+    for (name, value) in SAMPLE_ARGS {
+        match value.parse::<i64>() {
+            Ok(number) => args.set(*name, number),
+            Err(_) => args.set(*name, *value),
+        }
+    }
+    for id in known {
+        let Some(pattern) = bundle.get_message(id).and_then(|message| message.value()) else {
+            validation.missing.push(id.to_string());
+            continue;
+        };
+        let mut errors = Vec::new();
+        bundle.format_pattern(pattern, Some(&args), &mut errors);
+        validation
+            .errors
+            .extend(errors.iter().map(|e| format!("{}: {}", id, e)));
+    }
+    validation
+}
+
+/// Labels in one language
+#[derive(Clone, Copy)]
+pub struct LabelPack<'a> {
+    bundle: Option<&'a Bundle>,
+    fallback: &'a Bundle,
+}
+
+impl LabelPack<'_> {
+    /// Text of a message without arguments
+    pub fn text(&self, id: &str) -> String {
+        self.format(id, &[])
+    }
+
+    /// Text of a message with arguments
+    ///
+    /// Messages missing in this pack are taken from the default language; if
+    /// they are missing there as well, the message ID is returned.
+    pub fn format(&self, id: &str, args: &[(&str, FluentValue)]) -> String {
+        let mut fluent_args = FluentArgs::new();
+        for (name, value) in args {
+            fluent_args.set(*name, value.clone());
+        }
+        self.bundle
+            .into_iter()
+            .chain([self.fallback])
+            .find_map(|bundle| {
+                let pattern = bundle.get_message(id)?.value()?;
+                let mut errors = Vec::new();
+                let text = bundle.format_pattern(pattern, Some(&fluent_args), &mut errors);
+                if !errors.is_empty() {
+                    warn!("Failed to format label {}: {:?}", id, errors);
+                }
+                Some(text.into_owned())
+            })
+            .unwrap_or_else(|| id.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_embedded_packs_are_complete() {
+        let labels = Labels::embedded();
+        let ids = message_ids(EMBEDDED_PACKS[0].1);
+        assert!(ids.contains(&"status-free"));
+        for (language, _) in EMBEDDED_PACKS {
+            let bundle = labels.bundle(language).unwrap();
+            for id in &ids {
+                assert!(bundle.has_message(id), "{} is missing in {}", id, language);
+            }
+        }
+    }
+
+    #[test]
+    fn test_format_labels() {
+        let labels = Labels::embedded();
+        let en = labels.pack("en");
+        assert_eq!(en.format("header-seats", &[("count", 1.into())]), "1 seat");
+        assert_eq!(en.format("header-seats", &[("count", 8.into())]), "8 seats");
+
+        let de = labels.pack("de-CH");
+        assert_eq!(de.text("status-busy"), "BELEGT");
+        assert_eq!(
+            de.format("banner-ends-in", &[("minutes", 5.into())]),
+            "Endet in 5 Min."
+        );
+
+        // Unknown languages and messages fall back
+        assert_eq!(labels.pack("xx").text("status-free"), "FREE");
+        assert!(!labels.has_language("xx"));
+        assert_eq!(de.text("no-such-label"), "no-such-label");
+    }
+
+    #[test]
+    fn test_validate_pack() {
+        for (language, source) in EMBEDDED_PACKS {
+            assert_eq!(
+                validate_pack(language, source),
+                PackValidation::default(),
+                "{}",
+                language
+            );
+        }
+
+        let validation = validate_pack(
+            "nl",
+            "status-free = VRIJ\nbanner-ends-in = Nog { $minuten } min\nstatus-gone = WEG\n",
+        );
+        assert_eq!(validation.errors.len(), 1);
+        assert!(validation.errors[0].starts_with("banner-ends-in: "));
+        assert!(validation.missing.contains(&"status-busy".to_string()));
+        assert_eq!(validation.unknown, ["status-gone"]);
+
+        let validation = validate_pack("nl", "status-free = {");
+        assert_eq!(validation.errors.len(), 1);
+        assert!(validation.missing.is_empty());
+    }
+
+    #[test]
+    fn test_custom_labels() {
+        let dir = std::env::temp_dir().join(format!("trmnl-labels-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("de.ftl"), "status-free = VERFÜGBAR\n").unwrap();
+        fs::write(dir.join("nl.ftl"), "status-free = VRIJ\n").unwrap();
+        fs::write(dir.join("README.md"), "not a label pack").unwrap();
+
+        let labels = Labels::load(dir.to_str()).unwrap();
+        assert_eq!(labels.pack("de").text("status-free"), "VERFÜGBAR");
+        assert_eq!(labels.pack("de").text("status-busy"), "BELEGT");
+        assert_eq!(labels.pack("nl").text("status-free"), "VRIJ");
+        assert_eq!(labels.pack("nl").text("status-busy"), "BUSY");
+
+        fs::write(dir.join("fr.ftl"), "status-free = {").unwrap();
+        assert!(Labels::load(dir.to_str()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(Labels::load(dir.to_str()).is_err());
+    }
+}
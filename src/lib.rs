@@ -1,4 +1,34 @@
+pub mod agenda;
+pub mod api;
 pub mod bmp;
+pub mod caldav;
 pub mod calendar;
+pub mod claim;
+pub mod config_cache;
 pub mod database;
+pub mod description;
+pub mod device_id;
+pub mod error_code;
+pub mod error_report;
+pub mod event_changes;
+pub mod experiments;
+pub mod google;
+pub mod health;
+pub mod image_store;
+pub mod labels;
+pub mod log_ingest;
+pub mod metrics;
+pub mod microsoft;
+pub mod mqtt;
+pub mod notify;
+pub mod refresh;
+pub mod render;
+pub mod rollover;
+pub mod rooms;
+pub mod schedule;
 pub mod server;
+pub mod signing;
+pub mod status;
+pub mod telemetry;
+pub mod utilization;
+pub mod week;
@@ -0,0 +1,353 @@
+//! Buffered ingestion of device logs
+//!
+//! Devices tend to flush their logs at the same time, e.g. after a network
+//! outage. Log requests therefore only queue the entries in a bounded buffer,
+//! and a background writer stores them in batches. When the buffer is full,
+//! new entries are rejected so that devices retry later.
+//!
+//! The firmware sends its logs as a JSON document with an array of entries,
+//! each with a message and usually the time it was logged and a level. These
+//! are stored as separate entries, so that they can be filtered; other bodies
+//! are stored as a whole.
+//!
+//! Entries are kept for `LOG_RETENTION_DAYS` and at most `LOG_MAX_ENTRIES` of
+//! them, so that the database does not grow forever on a small machine.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use log::{debug, error};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::api::types::LogLevel;
+use crate::database::{Database, DeviceLogEntry};
+use crate::error_report::{ErrorReporter, report_task_failure, spawn_supervised};
+
+/// Maximum number of entries stored in one database transaction
+const MAX_BATCH_SIZE: usize = 100;
+
+/// How log requests without the device's key are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogAuth {
+    /// Store them, flagged as unauthenticated, to capture logs of misconfigured devices
+    #[default]
+    Permissive,
+    /// Reject them
+    Strict,
+}
+
+impl std::str::FromStr for LogAuth {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "permissive" => Ok(LogAuth::Permissive),
+            "strict" => Ok(LogAuth::Strict),
+            other => Err(anyhow::anyhow!(
+                "Unknown LOG_AUTH: {} (expected permissive or strict)",
+                other
+            )),
+        }
+    }
+}
+
+/// How often device log entries beyond the retention limits are deleted, by default
+pub const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Timestamps before 2020 are sent by devices whose clock is not set yet
+const MIN_LOG_TIMESTAMP: i64 = 1_577_836_800;
+
+/// Level as spelled by the firmware, e.g. `WARNING` or `E`
+fn firmware_level(level: &str) -> Option<LogLevel> {
+    match level.trim().to_ascii_lowercase().as_str() {
+        "error" | "err" | "e" | "fatal" | "critical" => Some(LogLevel::Error),
+        "warn" | "warning" | "w" => Some(LogLevel::Warn),
+        "info" | "i" => Some(LogLevel::Info),
+        "debug" | "d" | "verbose" | "v" | "trace" => Some(LogLevel::Debug),
+        _ => None,
+    }
+}
+
+/// Entries of a log request body
+///
+/// Every object with a `log_message` in a JSON body is an entry, with its
+/// `creation_timestamp` and `log_level` if present. Bodies without such
+/// objects are a single entry.
+pub fn parse_log_body(
+    device_id: &str,
+    body: &str,
+    received_at: i64,
+    authenticated: bool,
+) -> Vec<DeviceLogEntry> {
+    fn collect(
+        value: &serde_json::Value,
+        entries: &mut Vec<(String, Option<i64>, Option<LogLevel>)>,
+    ) {
+        match value {
+            serde_json::Value::Object(map) => {
+                if let Some(serde_json::Value::String(message)) = map.get("log_message") {
+                    let logged_at = ["creation_timestamp", "timestamp"]
+                        .iter()
+                        .find_map(|key| map.get(*key).and_then(serde_json::Value::as_i64))
+                        .filter(|timestamp| *timestamp >= MIN_LOG_TIMESTAMP);
+                    let level = ["log_level", "level"]
+                        .iter()
+                        .find_map(|key| map.get(*key).and_then(serde_json::Value::as_str))
+                        .and_then(firmware_level);
+                    entries.push((message.clone(), logged_at, level));
+                } else {
+                    map.values().for_each(|value| collect(value, entries));
+                }
+            }
+            serde_json::Value::Array(values) => {
+                values.iter().for_each(|value| collect(value, entries))
+            }
+            _ => {}
+        }
+    }
+
+    let mut parsed = Vec::new();
+    if let Ok(value) = serde_json::from_str::<serde_json::Value>(body) {
+        collect(&value, &mut parsed);
+    }
+    if parsed.is_empty() {
+        parsed.push((body.to_string(), None, None));
+    }
+    parsed
+        .into_iter()
+        .map(|(message, logged_at, level)| DeviceLogEntry {
+            device_id: device_id.to_string(),
+            message,
+            received_at,
+            authenticated,
+            logged_at,
+            level,
+        })
+        .collect()
+}
+
+/// Handle for queueing device log entries
+#[derive(Debug, Clone)]
+pub struct LogIngest {
+    sender: mpsc::Sender<DeviceLogEntry>,
+}
+
+/// Reason entries were not queued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmitError {
+    /// The log buffer is full, retrying later may succeed
+    Full,
+    /// The request has more entries than the log buffer holds, so that
+    /// retrying never succeeds
+    TooLarge,
+    /// The background writer is not running
+    Closed,
+}
+
+impl LogIngest {
+    /// Start the background writer with a buffer for `capacity` entries
+    ///
+    /// Must be called within a Tokio runtime.
+    pub fn start(database: Arc<Database>, capacity: usize, errors: Arc<dyn ErrorReporter>) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        spawn_supervised(
+            "log_writer",
+            errors.clone(),
+            run_writer(database, receiver, errors),
+        );
+        Self { sender }
+    }
+
+    /// Queue the entries of a log request for writing
+    ///
+    /// Either all entries are queued or none, so that a device retrying a
+    /// rejected request does not store some of them twice.
+    pub fn submit(&self, entries: Vec<DeviceLogEntry>) -> Result<(), SubmitError> {
+        if entries.len() > self.sender.max_capacity() {
+            return Err(SubmitError::TooLarge);
+        }
+        match self.sender.try_reserve_many(entries.len()) {
+            Ok(permits) => {
+                for (permit, entry) in permits.zip(entries) {
+                    permit.send(entry);
+                }
+                Ok(())
+            }
+            Err(TrySendError::Full(_)) => Err(SubmitError::Full),
+            Err(TrySendError::Closed(_)) => {
+                error!("Device log writer is not running");
+                Err(SubmitError::Closed)
+            }
+        }
+    }
+}
+
+/// Store queued entries in batches until all senders are gone
+async fn run_writer(
+    database: Arc<Database>,
+    mut receiver: mpsc::Receiver<DeviceLogEntry>,
+    errors: Arc<dyn ErrorReporter>,
+) {
+    let mut batch = Vec::with_capacity(MAX_BATCH_SIZE);
+    while receiver.recv_many(&mut batch, MAX_BATCH_SIZE).await > 0 {
+        let entries = std::mem::take(&mut batch);
+        let count = entries.len();
+        let database = database.clone();
+        match tokio::task::spawn_blocking(move || database.insert_device_logs(&entries)).await {
+            Ok(Ok(())) => debug!("Stored {} device log entries", count),
+            Ok(Err(e)) => report_task_failure(
+                errors.as_ref(),
+                "log_writer",
+                format!("Failed to store {} device log entries: {:#}", count, e),
+            ),
+            Err(e) => report_task_failure(
+                errors.as_ref(),
+                "log_writer",
+                format!("Device log writer failed: {}", e),
+            ),
+        }
+    }
+}
+
+/// Delete the device log entries older than `retention_days` and the oldest
+/// ones beyond `max_entries`, where 0 disables either limit
+///
+/// Returns the number of deleted entries.
+pub fn apply_retention(
+    database: &Database,
+    retention_days: u32,
+    max_entries: usize,
+    now: i64,
+) -> Result<usize> {
+    let received_before = match retention_days {
+        0 => i64::MIN,
+        days => now - i64::from(days) * 24 * 60 * 60,
+    };
+    database.prune_device_logs(received_before, max_entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::DeviceLogQuery;
+    use crate::error_report::LogErrorReporter;
+
+    fn entry(message: &str) -> DeviceLogEntry {
+        DeviceLogEntry {
+            device_id: "AA:BB".to_string(),
+            message: message.to_string(),
+            received_at: 1700000000,
+            authenticated: true,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_full_buffer_sheds_load() {
+        let database = Arc::new(Database::new(":memory:").unwrap());
+        let logs = LogIngest::start(database.clone(), 2, Arc::new(LogErrorReporter));
+
+        // The writer only runs once this task yields
+        assert_eq!(logs.submit(vec![entry("one")]), Ok(()));
+        assert_eq!(logs.submit(vec![entry("two")]), Ok(()));
+        assert_eq!(logs.submit(vec![entry("three")]), Err(SubmitError::Full));
+
+        for _ in 0..100 {
+            if database
+                .list_device_logs(&DeviceLogQuery::default(), 10)
+                .unwrap()
+                .len()
+                == 2
+            {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let stored = database
+            .list_device_logs(
+                &DeviceLogQuery {
+                    device_id: Some("aa:bb"),
+                    ..Default::default()
+                },
+                10,
+            )
+            .unwrap();
+        assert_eq!(stored.len(), 2);
+        assert_eq!(stored[0].message, "two");
+        assert_eq!(logs.submit(vec![entry("four")]), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_batch_larger_than_buffer() {
+        let database = Arc::new(Database::new(":memory:").unwrap());
+        let logs = LogIngest::start(database, 2, Arc::new(LogErrorReporter));
+
+        let entries = vec![entry("one"), entry("two"), entry("three")];
+        assert_eq!(logs.submit(entries), Err(SubmitError::TooLarge));
+        assert_eq!(logs.submit(vec![entry("one"), entry("two")]), Ok(()));
+        assert_eq!(logs.submit(vec![entry("three")]), Err(SubmitError::Full));
+    }
+
+    #[test]
+    fn test_parse_log_body() {
+        let body = r#"{"log":{"logs_array":[
+            {"creation_timestamp":1700000000,"log_id":1,"log_message":"Rebooting after panic","log_level":"ERROR"},
+            {"creation_timestamp":12,"log_id":2,"log_message":"WiFi connected","device_status_stamp":{"wifi_rssi_level":-60}}
+        ]}}"#;
+        let entries = parse_log_body("AA:BB", body, 1700000100, true);
+        let parsed: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.message.as_str(), entry.logged_at, entry.level))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                (
+                    "Rebooting after panic",
+                    Some(1700000000),
+                    Some(LogLevel::Error)
+                ),
+                // Timestamps of devices whose clock is not set are dropped
+                ("WiFi connected", None, None),
+            ]
+        );
+        assert!(entries.iter().all(|entry| entry.received_at == 1700000100));
+
+        // Other bodies are kept as a whole
+        for body in ["WiFi lost\nReconnecting", r#"{"status":"ok"}"#] {
+            let entries = parse_log_body("AA:BB", body, 1700000100, false);
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].message, body);
+        }
+    }
+
+    #[test]
+    fn test_apply_retention() {
+        let database = Database::new(":memory:").unwrap();
+        let now = 1_700_000_000;
+        let day = 24 * 60 * 60;
+        let entries: Vec<_> = [now - 40 * day, now - 10 * day, now - day, now]
+            .into_iter()
+            .map(|received_at| DeviceLogEntry {
+                received_at,
+                ..entry("log")
+            })
+            .collect();
+        database.insert_device_logs(&entries).unwrap();
+        let stored = || {
+            database
+                .list_device_logs(&DeviceLogQuery::default(), 10)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.received_at)
+                .collect::<Vec<_>>()
+        };
+
+        // Disabled limits keep everything
+        assert_eq!(apply_retention(&database, 0, 0, now).unwrap(), 0);
+        assert_eq!(apply_retention(&database, 30, 0, now).unwrap(), 1);
+        assert_eq!(stored(), [now, now - day, now - 10 * day]);
+        assert_eq!(apply_retention(&database, 30, 2, now).unwrap(), 1);
+        assert_eq!(stored(), [now, now - day]);
+    }
+}
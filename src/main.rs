@@ -1,60 +1,175 @@
-use std::process;
+use std::{process, sync::Arc};
 
 use anyhow::Context;
-use log::{error, info};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-use crate::{
-    database::init_database,
-    server::{config::Config, start_server},
+use clap::{Parser, Subcommand};
+use log::{error, info, warn};
+use tracing_subscriber::{fmt::format::FmtSpan, layer::SubscriberExt, util::SubscriberInitExt};
+
+use trmnl_meeting_room_display::{
+    database::{Database, init_database, redact_url},
+    rooms::{Room, load_rooms, rooms_to_toml},
+    server::{config::Config, dry_run::dry_run, start_server},
+    utilization::{MAX_UTILIZATION_DAYS, record_utilization},
 };
 
-mod bmp;
-mod calendar;
-mod database;
-mod server;
+#[derive(Parser, Debug)]
+#[command(
+    about = "Meeting room display server for TRMNL devices",
+    args_conflicts_with_subcommands = true
+)]
+struct Args {
+    /// Check the configuration, database migrations, calendars and rendering
+    /// of every room, print a report and exit instead of serving requests
+    ///
+    /// The database is migrated on a temporary copy and left untouched.
+    #[arg(long)]
+    dry_run: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Import rooms and their devices from the rooms file into the database
+    ///
+    /// Afterwards, the database is the source of truth for the room
+    /// configuration and the rooms file is no longer read.
+    ImportConfig {
+        /// Rooms file to import (defaults to ROOMS_PATH)
+        #[arg(long)]
+        path: Option<String>,
+    },
+    /// Print the room configuration stored in the database as TOML
+    ExportConfig,
+    /// Import the utilization of the rooms on past days from their calendars
+    ///
+    /// Days recorded before are overwritten, so the command can be run again
+    /// with a longer range.
+    BackfillUtilization {
+        /// Number of days before today to import
+        #[arg(
+            long,
+            default_value = "90",
+            value_parser = clap::value_parser!(u32).range(1..=i64::from(MAX_UTILIZATION_DAYS)),
+        )]
+        days: u32,
+        /// Only import this room
+        #[arg(long)]
+        room: Option<String>,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize logger and tracing
+    let args = Args::parse();
+
+    // Initialize logger and tracing. With LOG_SPAN_TIMINGS set, the duration of
+    // every enabled span (e.g. the render stages at debug level) is logged.
+    let span_events = if std::env::var_os("LOG_SPAN_TIMINGS").is_some() {
+        FmtSpan::CLOSE
+    } else {
+        FmtSpan::NONE
+    };
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "info,tower_http=trace".into()),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_subscriber::fmt::layer().with_span_events(span_events))
         .init();
 
-    // Initialize configuration
-    if let Err(e) = Config::init().context("Failed to initialize configuration") {
-        error!("Configuration error: {:#}", e);
-        process::exit(1);
-    }
-
-    let config = Config::get().expect("Configuration should be initialized");
+    // Read configuration
+    let config = match Config::from_env().context("Failed to initialize configuration") {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Configuration error: {:#}", e);
+            process::exit(1);
+        }
+    };
     info!("Configuration loaded successfully");
     info!("Server host: {}", config.server_host);
     info!("Server port: {}", config.server_port);
     info!("Server URL: {}", config.server_url);
-    info!("Database path: {}", config.database_path);
+    info!("Database: {}", redact_url(&config.database_url));
     info!("Font path: {}", config.font_path);
+    info!("Image signing enabled: {}", config.image_signer.is_some());
+    info!("Image delivery: {:?}", config.image_delivery);
+    if config.admin_token.is_none() {
+        warn!("ADMIN_TOKEN is not set, the admin API and dashboard are disabled");
+    }
+
+    if args.dry_run {
+        let report = dry_run(Arc::new(config)).await;
+        print!("{}", report);
+        process::exit(if report.passed() { 0 } else { 1 });
+    }
 
     // Initialize database
-    let database =
-        match init_database(&config.database_path).context("Failed to initialize database") {
-            Ok(db) => {
-                info!("Database initialized successfully");
-                db
-            }
-            Err(e) => {
-                error!("Database initialization error: {:#}", e);
-                process::exit(1);
-            }
-        };
+    let database = match init_database(&config.database_url, config.database_pool_size)
+        .context("Failed to initialize database")
+    {
+        Ok(db) => {
+            info!("Database initialized successfully");
+            db
+        }
+        Err(e) => {
+            error!("Database initialization error: {:#}", e);
+            process::exit(1);
+        }
+    };
+
+    match args.command {
+        Some(Command::ImportConfig { path }) => {
+            let path = path.as_deref().unwrap_or(&config.rooms_path);
+            return Ok(import_config(&database, path)?);
+        }
+        Some(Command::ExportConfig) => {
+            print!("{}", rooms_to_toml(&database.list_rooms()?)?);
+            return Ok(());
+        }
+        Some(Command::BackfillUtilization { .. }) | None => {}
+    }
+
+    // Rooms imported into the database take precedence over the rooms file
+    let db_rooms = database
+        .list_rooms()
+        .context("Failed to load rooms from database")?;
+    if db_rooms.is_empty() {
+        info!(
+            "Rooms configured: {} (from {})",
+            config.rooms.snapshot().len(),
+            config.rooms_path
+        );
+    } else {
+        info!("Rooms configured: {} (from database)", db_rooms.len());
+        config.rooms.replace(db_rooms);
+    }
+    // Backfilling reads the calendars of the rooms in effect
+    if let Some(Command::BackfillUtilization { days, room }) = args.command {
+        return Ok(backfill_utilization(&database, &config.rooms.snapshot(), days, room).await?);
+    }
+    for room in config.rooms.snapshot().iter() {
+        if !config.labels.snapshot().has_language(&room.language) {
+            warn!(
+                "No labels for language {} of room {}, using English",
+                room.language, room.id
+            );
+        }
+    }
+
+    if let Some(maintenance) = database
+        .maintenance()
+        .context("Failed to get maintenance")?
+    {
+        warn!(
+            "Server under maintenance since {} (started by {}), end it with DELETE /api/admin/maintenance",
+            maintenance.started_at, maintenance.started_by
+        );
+    }
 
     // Start the web server
     info!("Starting server...");
-    if let Err(e) = start_server(database).await {
+    if let Err(e) = start_server(database, config).await {
         error!("Server error: {:#}", e);
         return Err(e.into());
     }
@@ -62,23 +177,103 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// Seed the database with the rooms from the given rooms file
+fn import_config(database: &Database, path: &str) -> anyhow::Result<()> {
+    let rooms = load_rooms(path)?;
+    if rooms.is_empty() {
+        return Err(anyhow::anyhow!("No rooms found in {}", path));
+    }
+    database
+        .import_rooms(&rooms, "import-config")
+        .context("Failed to import rooms")?;
+    info!(
+        "Imported {} rooms from {} into the database",
+        rooms.len(),
+        path
+    );
+    Ok(())
+}
+
+/// Seed the utilization statistics with the past days of the room calendars
+async fn backfill_utilization(
+    database: &Arc<Database>,
+    rooms: &[Room],
+    days: u32,
+    room: Option<String>,
+) -> anyhow::Result<()> {
+    let rooms: Vec<Room> = match room {
+        Some(id) => vec![
+            rooms
+                .iter()
+                .find(|room| room.id == id)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("Unknown room {}", id))?,
+        ],
+        None => rooms.to_vec(),
+    };
+    let recorded = record_utilization(database, &rooms, days, chrono::Local::now()).await?;
+    info!(
+        "Imported the utilization of {} rooms on the last {} days",
+        recorded, days
+    );
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{fs, sync::Arc};
+    use std::{
+        fs,
+        sync::{Arc, Mutex},
+    };
 
+    use async_trait::async_trait;
     use axum::{
+        Router,
         body::Body,
         http::{Request, StatusCode},
     };
+    use base64::{Engine as _, engine::general_purpose};
     use dotenv::dotenv;
     use tower::util::ServiceExt;
 
-    use crate::{
-        database::Database,
+    use trmnl_meeting_room_display::{
+        api::types::{
+            BroadcastResponse, ByosDisplayResponse, ClaimCode, DeviceAdoption, DeviceInfo,
+            DeviceLog, DisplayResponse, ExperimentInfo, FleetSummary, IssueReport, JobInfo,
+            JobRunInfo, LogLevel, MaintenanceResponse, PrometheusTargetGroup, ProvisionedDevice,
+            SetupResponse,
+        },
+        bmp::{Dither, ImageConfig, ImageFormat},
+        calendar::CalendarSource,
+        database::{
+            DEFAULT_POOL_SIZE, Database, DeviceCheckIn, DeviceLogEntry, DeviceLogQuery,
+            NewProvisionedDevice,
+        },
+        device_id::DeviceIdKind,
+        error_report::{ErrorEvent, ErrorReporter},
+        experiments::ExperimentState,
+        health::{CRITICAL_BATTERY_VOLTAGE, DeviceStatus},
+        image_store::{ImageDelivery, ImageStore, ImageStoreConfig, display_filename, image_name},
+        labels::{Labels, SharedLabels},
+        log_ingest::LogAuth,
+        refresh::RefreshRates,
+        render::{ImageRenderer, RendererConfig},
+        rooms::{Room, SharedRooms, parse_rooms},
         server::{
-            handlers::{DisplayResponse, SetupResponse},
-            test_app,
+            AppState,
+            admin::{CalendarTestResponse, LabelPackTestResponse},
+            config::Config,
+            create_app,
+            dry_run::{CheckOutcome, check_rooms},
+            handlers::{MAINTENANCE_REFRESH_RATE, hosted_image_url},
+            label_reload::reload_labels,
+            prerender::prerender,
+            proxy::{Proxy, ProxyConfig},
+            scheduler::Job,
         },
+        signing::ImageSigner,
+        utilization::{DailyUtilization, WeeklyUtilization},
+        week::Week,
     };
 
     /// Helper function to get the access token for tests
@@ -87,13 +282,99 @@ mod tests {
         std::env::var("ACCESS_TOKEN").unwrap_or_else(|_| "your-secret-access-token".to_string())
     }
 
+    /// Token of the admin API in the tests
+    const TEST_ADMIN_TOKEN: &str = "test-admin-token";
+
+    /// Remove a test database with its WAL files, which are left behind if
+    /// the database is still open
+    fn remove_test_database(path: &str) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = fs::remove_file(format!("{}{}", path, suffix));
+        }
+    }
+
+    /// Configuration of the tests
+    fn test_config() -> Config {
+        Config {
+            server_host: "127.0.0.1".to_string(),
+            server_port: 8080,
+            server_url: "http://127.0.0.1:8080".to_string(),
+            tls: None,
+            database_url: "test_devices.db".to_string(),
+            database_pool_size: DEFAULT_POOL_SIZE,
+            access_token: get_test_access_token(),
+            admin_token: Some(TEST_ADMIN_TOKEN.to_string()),
+            font_path: "assets/fonts/BlockKie.ttf".to_string(),
+            refresh: RefreshRates {
+                default_rate: 200,
+                jitter_percent: 0,
+            },
+            calendar_refresh_minutes: 5,
+            rooms: SharedRooms::new(
+                parse_rooms(
+                    r#"
+                    [[rooms]]
+                    id = "room-a"
+                    name = "Room A"
+                    devices = ["00:11:22:33:44:55"]
+                    show_issue_badge = true
+                    "#,
+                )
+                .unwrap(),
+            ),
+            rooms_path: "rooms.toml".to_string(),
+            image_signer: Some(
+                ImageSigner::from_base64("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=").unwrap(),
+            ),
+            notify_webhook_urls: Vec::new(),
+            notify_missed_check_ins: 3,
+            notify_calendar_failures: 3,
+            mqtt_url: None,
+            mqtt_topic_prefix: "trmnl".to_string(),
+            image_store: ImageStoreConfig::Memory,
+            image_delivery: ImageDelivery::Inline,
+            image_format: ImageFormat::Bmp,
+            image_url_ttl_seconds: 3600,
+            instance_id: "test".to_string(),
+            require_claim_code: false,
+            labels: SharedLabels::new(Labels::embedded()),
+            labels_dir: None,
+            log_buffer_size: 1000,
+            log_auth: LogAuth::Permissive,
+            log_retention_days: 30,
+            log_max_entries: 100_000,
+            job_schedules: Vec::new(),
+            error_sink: None,
+            proxy: None,
+            renderer: RendererConfig::Local,
+            render_cache_ttl_seconds: 60,
+            dither: Dither::None,
+            telemetry_url: None,
+            battery_warning_voltage: CRITICAL_BATTERY_VOLTAGE,
+            inline_cache_size: 32,
+            rate_limit_device_per_minute: 0,
+            rate_limit_address_per_minute: 0,
+            trust_forwarded_for: false,
+        }
+    }
+
+    /// Helper function to create the app state with a test configuration
+    fn test_state(database: Arc<Database>) -> AppState {
+        AppState::new(database, Arc::new(test_config())).unwrap()
+    }
+
+    /// Helper function to create the app with a test configuration
+    fn test_app(database: Arc<Database>) -> Router {
+        create_app(test_state(database))
+    }
+
     #[tokio::test]
     async fn test_setup_endpoint_success() {
         let test_db_path = "test_devices.db";
         let access_token = get_test_access_token();
 
         // Ensure test database doesn't exist
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
 
         let db = Arc::new(Database::new(test_db_path).unwrap());
         let app = test_app(db.clone());
@@ -114,7 +395,7 @@ mod tests {
         assert!(resp.status().is_success());
 
         // Clean up
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
     }
 
     #[tokio::test]
@@ -123,7 +404,7 @@ mod tests {
         let access_token = get_test_access_token();
 
         // Ensure test database doesn't exist
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
 
         let db = Arc::new(Database::new(test_db_path).unwrap());
         let app = test_app(db.clone());
@@ -144,7 +425,7 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED); // Method Not Allowed - POST method not allowed
 
         // Clean up
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
     }
 
     #[tokio::test]
@@ -153,7 +434,7 @@ mod tests {
         let access_token = get_test_access_token();
 
         // Ensure test database doesn't exist
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
 
         let db = Arc::new(Database::new(test_db_path).unwrap());
 
@@ -182,12 +463,17 @@ mod tests {
             .unwrap();
         let response: DisplayResponse = serde_json::from_slice(&body).unwrap();
 
-        assert_eq!(response.filename, "demo.bmp");
         assert!(response.image_url.starts_with("data:image/bmp;base64,"));
+        // The filename changes with the image
+        let image = general_purpose::STANDARD
+            .decode(&response.image_url["data:image/bmp;base64,".len()..])
+            .unwrap();
+        assert_eq!(response.filename, display_filename("room-a", &image, "bmp"));
         assert_eq!(response.image_url_timeout, 0);
+        assert!(response.image_signature.is_some());
 
         // Clean up
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
     }
 
     #[tokio::test]
@@ -195,7 +481,7 @@ mod tests {
         let test_db_path = "test_devices_invalid.db";
 
         // Ensure test database doesn't exist
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
 
         let db = Arc::new(Database::new(test_db_path).unwrap());
         let app = test_app(db.clone());
@@ -216,7 +502,7 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::UNAUTHORIZED); // Unauthorized
 
         // Clean up
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
     }
 
     #[tokio::test]
@@ -225,7 +511,7 @@ mod tests {
         let access_token = get_test_access_token();
 
         // Ensure test database doesn't exist
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
 
         let db = Arc::new(Database::new(test_db_path).unwrap());
         let app = test_app(db.clone());
@@ -245,7 +531,108 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::UNAUTHORIZED); // Unauthorized
 
         // Clean up
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_setup_endpoint_uuid_device() {
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        let app = test_app(db.clone());
+        let access_token = get_test_access_token();
+
+        let setup = |id: &str| {
+            Request::builder()
+                .uri("/api/setup/")
+                .method("GET")
+                .header("ID", id)
+                .header("Access-Token", &access_token)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // UUIDs are registered in their normalized form
+        let resp = app
+            .clone()
+            .oneshot(setup("{123E4567-E89B-12D3-A456-426614174000}"))
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+        // The same device in another form, which got its key already
+        let resp = app
+            .clone()
+            .oneshot(setup("123e4567e89b12d3a456426614174000"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Legacy IDs of neither form are registered as sent
+        let resp = app.clone().oneshot(setup("test-device")).await.unwrap();
+        assert!(resp.status().is_success());
+
+        let req = Request::builder()
+            .uri("/api/admin/devices")
+            .method("GET")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let devices: Vec<DeviceInfo> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(devices.len(), 2);
+        let uuid = devices
+            .iter()
+            .find(|device| device.id == "123e4567-e89b-12d3-a456-426614174000")
+            .unwrap();
+        assert_eq!(uuid.id_type, Some(DeviceIdKind::Uuid));
+        let legacy = devices
+            .iter()
+            .find(|device| device.id == "test-device")
+            .unwrap();
+        assert_eq!(legacy.id_type, None);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_checks_rooms() {
+        let state = test_state(Arc::new(Database::new(":memory:").unwrap()));
+        let rooms = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+
+            [[rooms]]
+            id = "room-b"
+            name = "Room B"
+            language = "rm"
+            calendar_url = "http://127.0.0.1:1/room-b.ics"
+            "#,
+        )
+        .unwrap();
+
+        let checks = check_rooms(state, &rooms).await;
+        let outcomes: Vec<(&str, &str)> = checks
+            .iter()
+            .map(|check| {
+                let outcome = match check.outcome {
+                    CheckOutcome::Passed(_) => "ok",
+                    CheckOutcome::Warning(_) => "warn",
+                    CheckOutcome::Failed(_) => "fail",
+                };
+                (check.name.as_str(), outcome)
+            })
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("render room-a", "ok"),
+                ("labels room-b", "warn"),
+                ("calendar room-b", "fail"),
+                // Rooms whose calendar cannot be fetched still render
+                ("render room-b", "ok"),
+            ]
+        );
     }
 
     #[tokio::test]
@@ -253,7 +640,7 @@ mod tests {
         let test_db_path = "test_health.db";
 
         // Ensure test database doesn't exist
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
 
         let db = Arc::new(Database::new(test_db_path).unwrap());
         let app = test_app(db.clone());
@@ -270,7 +657,7 @@ mod tests {
         assert!(resp.status().is_success());
 
         // Clean up
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
     }
 
     #[tokio::test]
@@ -278,7 +665,7 @@ mod tests {
         let test_db_path = "test_static.db";
 
         // Ensure test database doesn't exist
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
 
         let db = Arc::new(Database::new(test_db_path).unwrap());
         let app = test_app(db.clone());
@@ -306,7 +693,7 @@ mod tests {
         assert_eq!(bytes[1], 0x4d);
 
         // Clean up
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
     }
 
     #[tokio::test]
@@ -315,7 +702,7 @@ mod tests {
         let access_token = get_test_access_token();
 
         // Ensure test database doesn't exist
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
 
         let db = Arc::new(Database::new(test_db_path).unwrap());
         let app = test_app(db.clone());
@@ -341,14 +728,2699 @@ mod tests {
             .unwrap();
         let response: SetupResponse = serde_json::from_slice(&body).unwrap();
 
-        // Check that image_url contains the full server URL
+        // Check that image_url contains the full server URL and the logo's hash
+        let logo = fs::read("static/setup-logo.bmp").unwrap();
+        let hash = image_name(&logo, "bmp");
         assert_eq!(
             response.image_url,
-            "http://127.0.0.1:8080/static/setup-logo.bmp"
+            format!(
+                "http://127.0.0.1:8080/static/setup-logo.bmp?v={}",
+                hash.trim_end_matches(".bmp")
+            )
+        );
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_export_devices_prometheus_sd() {
+        let test_db_path = "test_export.db";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device("00:11:22:33:44:55").unwrap();
+        db.register_device("66:77:88:99:AA:BB").unwrap();
+
+        let app = test_app(db.clone());
+
+        // Create test request with valid headers
+        let req = Request::builder()
+            .uri("/api/admin/devices/export?format=prometheus_sd")
+            .method("GET")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+
+        // Send request and get response
+        let resp = app.oneshot(req).await.unwrap();
+        assert!(resp.status().is_success());
+
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let groups: Vec<PrometheusTargetGroup> = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].targets, vec!["00:11:22:33:44:55"]);
+        assert_eq!(groups[0].labels["__meta_trmnl_room"], "room-a");
+        assert_eq!(groups[1].targets, vec!["66:77:88:99:AA:BB"]);
+        assert!(!groups[1].labels.contains_key("__meta_trmnl_room"));
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_export_devices_unsupported_format() {
+        let test_db_path = "test_export_format.db";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        let app = test_app(db.clone());
+
+        let req = Request::builder()
+            .uri("/api/admin/devices/export?format=csv")
+            .method("GET")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_display_endpoint_v1() {
+        let test_db_path = "test_devices_display_v1.db";
+        let access_token = get_test_access_token();
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device("00:11:22:33:44:55").unwrap();
+
+        // Versioned path and Api-Version header on the unversioned path
+        for (uri, version) in [("/api/v1/display", None), ("/api/display", Some("1"))] {
+            let app = test_app(db.clone());
+
+            let mut req = Request::builder()
+                .uri(uri)
+                .method("GET")
+                .header("ID", "00:11:22:33:44:55")
+                .header("Access-Token", &access_token);
+            if let Some(version) = version {
+                req = req.header("Api-Version", version);
+            }
+
+            let resp = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+            assert!(resp.status().is_success());
+
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let response: ByosDisplayResponse = serde_json::from_slice(&body).unwrap();
+            assert_eq!(response.status, 0);
+            assert_eq!(response.special_function, "none");
+            assert!(!response.update_firmware);
+        }
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_display_endpoint_unsupported_api_version() {
+        let test_db_path = "test_devices_display_version.db";
+        let access_token = get_test_access_token();
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device("00:11:22:33:44:55").unwrap();
+        let app = test_app(db.clone());
+
+        let req = Request::builder()
+            .uri("/api/display")
+            .method("GET")
+            .header("ID", "00:11:22:33:44:55")
+            .header("Access-Token", access_token)
+            .header("Api-Version", "7")
+            .body(Body::empty())
+            .unwrap();
+
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_calendar_test_endpoint_unreachable() {
+        let test_db_path = "test_calendar_test.db";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        let app = test_app(db.clone());
+
+        let req = Request::builder()
+            .uri("/api/admin/calendars/test")
+            .method("POST")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"url": "http://127.0.0.1:1/calendar.ics"}"#))
+            .unwrap();
+
+        let resp = app.oneshot(req).await.unwrap();
+        assert!(resp.status().is_success());
+
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: CalendarTestResponse = serde_json::from_slice(&body).unwrap();
+        assert!(!response.ok);
+        assert!(
+            response
+                .error
+                .unwrap()
+                .starts_with("Failed to fetch calendar")
+        );
+        assert!(response.events.is_empty());
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_label_pack_test_endpoint() {
+        let test_db_path = "test_label_pack_test.db";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        let app = test_app(db.clone());
+
+        let req = Request::builder()
+            .uri("/api/admin/labels/nl/test")
+            .method("POST")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .header("Content-Type", "text/plain")
+            .body(Body::from(
+                "status-free = VRIJ\nstatus-busy = { $who } BEZET\n",
+            ))
+            .unwrap();
+
+        let resp = app.oneshot(req).await.unwrap();
+        assert!(resp.status().is_success());
+
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: LabelPackTestResponse = serde_json::from_slice(&body).unwrap();
+        assert!(!response.ok);
+        assert_eq!(response.validation.errors.len(), 1);
+        assert!(response.validation.errors[0].starts_with("status-busy: "));
+        assert!(
+            response
+                .validation
+                .missing
+                .contains(&"agenda-today".to_string())
+        );
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_lifecycle() {
+        let test_db_path = "test_broadcast.db";
+        let access_token = get_test_access_token();
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device("00:11:22:33:44:55").unwrap();
+
+        let display_filename = |db: Arc<Database>| {
+            let access_token = access_token.clone();
+            async move {
+                let req = Request::builder()
+                    .uri("/api/display")
+                    .method("GET")
+                    .header("ID", "00:11:22:33:44:55")
+                    .header("Access-Token", access_token)
+                    .body(Body::empty())
+                    .unwrap();
+                let resp = test_app(db).oneshot(req).await.unwrap();
+                let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<DisplayResponse>(&body)
+                    .unwrap()
+                    .filename
+            }
+        };
+
+        // Creating a broadcast requires the Admin-User header
+        let req = Request::builder()
+            .uri("/api/admin/broadcast")
+            .method("POST")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"message": "Evacuate"}"#))
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Durations whose seconds overflow are rejected
+        let req = Request::builder()
+            .uri("/api/admin/broadcast")
+            .method("POST")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .header("Admin-User", "facilities")
+            .header("Content-Type", "application/json")
+            .body(Body::from(format!(
+                r#"{{"message": "Evacuate", "duration_minutes": {}}}"#,
+                i64::MAX
+            )))
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = Request::builder()
+            .uri("/api/admin/broadcast")
+            .method("POST")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .header("Admin-User", "facilities")
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                r#"{"message": "Evacuate - assemble at parking lot B", "duration_minutes": 30}"#,
+            ))
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let broadcast: BroadcastResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(broadcast.triggered_by, "facilities");
+
+        assert!(
+            display_filename(db.clone())
+                .await
+                .starts_with(&format!("broadcast-{}-", broadcast.id))
         );
-        assert!(response.image_url.starts_with("http://"));
+
+        // Clear the broadcast
+        let req = Request::builder()
+            .uri("/api/admin/broadcast")
+            .method("DELETE")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .header("Admin-User", "facilities")
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        assert!(display_filename(db.clone()).await.starts_with("room-a-"));
 
         // Clean up
-        let _ = fs::remove_file(test_db_path);
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_image_signing_key_endpoint() {
+        let test_db_path = "test_signing_key.db";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        let app = test_app(db.clone());
+
+        let req = Request::builder()
+            .uri("/api/image-signing-key")
+            .method("GET")
+            .body(Body::empty())
+            .unwrap();
+
+        let resp = app.oneshot(req).await.unwrap();
+        assert!(resp.status().is_success());
+
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response["algorithm"], "ed25519");
+        assert_eq!(
+            response["public_key"],
+            test_config().image_signer.as_ref().unwrap().public_key()
+        );
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_hosted_image_endpoint() {
+        let test_db_path = "test_hosted_image.db";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        let state = test_state(db.clone());
+        let name = image_name(b"BM test", "bmp");
+        state.images.put(&name, b"BM test".to_vec()).await.unwrap();
+        let config = state.config.clone();
+        let app = create_app(state);
+
+        // Image signing is enabled, so the URL must be signed
+        let url = hosted_image_url(&config, &name);
+        let path = url.strip_prefix("http://127.0.0.1:8080").unwrap();
+        let req = Request::builder()
+            .uri(path)
+            .method("GET")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert!(resp.status().is_success());
+        assert_eq!(resp.headers()["content-type"], "image/bmp");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"BM test");
+
+        let req = Request::builder()
+            .uri(format!("/images/{}", name))
+            .method("GET")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let url = hosted_image_url(&config, "0000000000000000.bmp");
+        let path = url.strip_prefix("http://127.0.0.1:8080").unwrap();
+        let req = Request::builder()
+            .uri(path)
+            .method("GET")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    /// Image store whose backend is down
+    struct FailingImageStore;
+
+    #[async_trait]
+    impl ImageStore for FailingImageStore {
+        async fn put(&self, _name: &str, _data: Vec<u8>) -> anyhow::Result<()> {
+            Err(anyhow::anyhow!("Bucket unavailable"))
+        }
+
+        async fn get(&self, _name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+            Err(anyhow::anyhow!("Bucket unavailable"))
+        }
+    }
+
+    /// Error reporter keeping the events in memory
+    #[derive(Default)]
+    struct RecordingReporter(Mutex<Vec<ErrorEvent>>);
+
+    impl ErrorReporter for RecordingReporter {
+        fn report(&self, event: ErrorEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_reported() {
+        let test_db_path = "test_error_report.db";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        let mut state = test_state(db.clone());
+        let reporter = Arc::new(RecordingReporter::default());
+        state.images = Arc::new(FailingImageStore);
+        state.errors = reporter.clone();
+        let config = state.config.clone();
+        let app = create_app(state);
+
+        let url = hosted_image_url(&config, "0000000000000000.bmp");
+        let path = url.strip_prefix("http://127.0.0.1:8080").unwrap();
+        let req = Request::builder()
+            .uri(path)
+            .method("GET")
+            .header("ID", "00:11:22:33:44:55")
+            .header("X-Request-Id", "proxy-123")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(resp.headers()["x-request-id"], "proxy-123");
+
+        {
+            let events = reporter.0.lock().unwrap();
+            assert_eq!(events.len(), 1);
+            let event = &events[0];
+            assert_eq!(event.request_id.as_deref(), Some("proxy-123"));
+            assert!(event.message.contains("Bucket unavailable"));
+            assert_eq!(event.tags["status"], "500");
+            assert_eq!(event.tags["device_id"], "00:11:22:33:44:55");
+            assert_eq!(event.tags["room_id"], "room-a");
+        }
+
+        // Client errors are not reported, but still get a request ID
+        let req = Request::builder()
+            .uri("/api/display")
+            .method("GET")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(resp.headers()["x-request-id"].len(), 16);
+        assert_eq!(reporter.0.lock().unwrap().len(), 1);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_per_device_image_delivery() {
+        let test_db_path = "test_image_delivery.db";
+        let access_token = get_test_access_token();
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device("00:11:22:33:44:55").unwrap();
+        let app = test_app(db.clone());
+        let display = |supported: Option<&str>| {
+            let mut req = Request::builder()
+                .uri("/api/display")
+                .header("ID", "00:11:22:33:44:55")
+                .header("Access-Token", &access_token);
+            if let Some(supported) = supported {
+                req = req.header("Image-Delivery", supported);
+            }
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<DisplayResponse>(&body)
+                    .unwrap()
+                    .image_url
+            }
+        };
+
+        // The configured mode is inline, unless the device does not support it
+        assert!(display(None).await.starts_with("data:image/bmp;base64,"));
+        assert!(
+            display(Some("hosted"))
+                .await
+                .starts_with("http://127.0.0.1:8080/images/")
+        );
+
+        let set_delivery = |body: &'static str| {
+            let req = Request::builder()
+                .uri("/api/admin/devices/00:11:22:33:44:55/image-delivery")
+                .method("PUT")
+                .header("Access-Token", TEST_ADMIN_TOKEN)
+                .header("Admin-User", "alice")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+        let resp = set_delivery(r#"{"image_delivery": "teleport"}"#)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = set_delivery(r#"{"image_delivery": "chunked"}"#)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        // The admin setting wins over the device's preference
+        let url = display(Some("inline")).await;
+        assert!(url.ends_with("&chunked=true"));
+        let req = Request::builder()
+            .uri(url.strip_prefix("http://127.0.0.1:8080").unwrap())
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get("content-length").is_none());
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.starts_with(b"BM"));
+
+        let resp = set_delivery(r#"{"image_delivery": null}"#).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert!(display(None).await.starts_with("data:image/bmp;base64,"));
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_png_negotiation() {
+        let test_db_path = "test_png_negotiation.db";
+        let access_token = get_test_access_token();
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device("00:11:22:33:44:55").unwrap();
+        let app = test_app(db.clone());
+        let display = |accept: Option<&str>| {
+            let mut req = Request::builder()
+                .uri("/api/display")
+                .header("ID", "00:11:22:33:44:55")
+                .header("Access-Token", &access_token);
+            if let Some(accept) = accept {
+                req = req.header("Accept", accept);
+            }
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<DisplayResponse>(&body).unwrap()
+            }
+        };
+
+        assert!(
+            display(Some("application/json"))
+                .await
+                .image_url
+                .starts_with("data:image/bmp;base64,")
+        );
+        let response = display(Some("image/png, image/bmp")).await;
+        assert!(response.filename.ends_with(".png"));
+        let png = general_purpose::STANDARD
+            .decode(
+                response
+                    .image_url
+                    .strip_prefix("data:image/png;base64,")
+                    .unwrap(),
+            )
+            .unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+        let device = db.get_device("00:11:22:33:44:55").unwrap().unwrap();
+        assert_eq!(device.last_payload_format.as_deref(), Some("png"));
+        assert_eq!(device.last_payload_bytes, Some(png.len() as i64));
+
+        // The admin setting wins over the Accept header
+        let set_format = |body: &'static str| {
+            let req = Request::builder()
+                .uri("/api/admin/devices/00:11:22:33:44:55/image-format")
+                .method("PUT")
+                .header("Access-Token", TEST_ADMIN_TOKEN)
+                .header("Admin-User", "alice")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+        let resp = set_format(r#"{"image_format": "gif"}"#).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = set_format(r#"{"image_format": "bmp"}"#).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert!(display(Some("image/png")).await.filename.ends_with(".bmp"));
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_payload_size_tracking() {
+        let test_db_path = "test_payload_size.db";
+        let access_token = get_test_access_token();
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device("00:11:22:33:44:55").unwrap();
+        let app = test_app(db.clone());
+
+        let req = Request::builder()
+            .uri("/api/display")
+            .method("GET")
+            .header("ID", "00:11:22:33:44:55")
+            .header("Access-Token", &access_token)
+            .header("Model", "og")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert!(resp.status().is_success());
+
+        // Polling again gets the cached base64 payload
+        let req = Request::builder()
+            .uri("/api/display")
+            .method("GET")
+            .header("ID", "00:11:22:33:44:55")
+            .header("Access-Token", &access_token)
+            .header("Model", "og")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert!(resp.status().is_success());
+
+        let req = Request::builder()
+            .uri("/api/admin/devices")
+            .method("GET")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert!(resp.status().is_success());
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let devices: Vec<DeviceInfo> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].room_id.as_deref(), Some("room-a"));
+        assert_eq!(devices[0].model.as_deref(), Some("og"));
+        assert_eq!(devices[0].last_payload_format.as_deref(), Some("bmp"));
+        let bytes = devices[0].last_payload_bytes.unwrap();
+        assert!(bytes > 0);
+
+        let req = Request::builder()
+            .uri("/metrics")
+            .method("GET")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert!(resp.status().is_success());
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let metrics = String::from_utf8(body.to_vec()).unwrap();
+        assert!(metrics.contains(&format!(
+            "trmnl_image_payload_bytes_sum{{format=\"bmp\",model=\"og\"}} {}",
+            2 * bytes
+        )));
+        assert!(metrics.contains("trmnl_inline_payload_cache_total{result=\"hit\"} 1\n"));
+        assert!(metrics.contains("trmnl_inline_payload_cache_total{result=\"miss\"} 1\n"));
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_claim_code_flow() {
+        let test_db_path = "test_claim_code.db";
+        let access_token = get_test_access_token();
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+
+        let req = Request::builder()
+            .uri("/api/admin/claim-codes")
+            .method("POST")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .header("Admin-User", "installer")
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"room_id": "room-a"}"#))
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let claim_code: ClaimCode = serde_json::from_slice(&body).unwrap();
+        assert_eq!(claim_code.room_id, "room-a");
+
+        // Unknown rooms are rejected
+        let req = Request::builder()
+            .uri("/api/admin/claim-codes")
+            .method("POST")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .header("Admin-User", "installer")
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"room_id": "room-z"}"#))
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // As are expiries whose seconds overflow
+        let req = Request::builder()
+            .uri("/api/admin/claim-codes")
+            .method("POST")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .header("Admin-User", "installer")
+            .header("Content-Type", "application/json")
+            .body(Body::from(format!(
+                r#"{{"room_id": "room-a", "expires_in_hours": {}}}"#,
+                i64::MAX
+            )))
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let setup = |code: &str| {
+            Request::builder()
+                .uri("/api/setup/")
+                .method("GET")
+                .header("ID", "AA:BB:CC:DD:EE:FF")
+                .header("Access-Token", &access_token)
+                .header("Claim-Code", code)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let resp = test_app(db.clone())
+            .oneshot(setup("WRONG-CODE"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Codes are case and dash insensitive
+        let entered = claim_code.code.replace('-', " ").to_lowercase();
+        let resp = test_app(db.clone()).oneshot(setup(&entered)).await.unwrap();
+        assert!(resp.status().is_success());
+        let device = db.get_device("AA:BB:CC:DD:EE:FF").unwrap().unwrap();
+        assert_eq!(device.room_id.as_deref(), Some("room-a"));
+
+        // Codes can only be used once
+        let resp = test_app(db.clone())
+            .oneshot(setup(&claim_code.code))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_provisioning_flow() {
+        let test_db_path = "test_provisioning.db";
+        let access_token = get_test_access_token();
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+
+        let provision = |body: &'static str| {
+            Request::builder()
+                .uri("/api/admin/provisioning")
+                .method("POST")
+                .header("Access-Token", TEST_ADMIN_TOKEN)
+                .header("Admin-User", "deploy-script")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let batch = r#"{"devices": [
+            {"mac": "aa-bb-cc-00-00-01", "room_id": "room-a", "label": "Door", "model": "og"},
+            {"mac": "AA:BB:CC:00:00:02", "room_id": "room-a", "firmware_channel": "beta"}
+        ]}"#;
+
+        let resp = test_app(db.clone())
+            .oneshot(provision(batch))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let first: Vec<ProvisionedDevice> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[0].device_id, "AA:BB:CC:00:00:01");
+        assert_eq!(first[1].firmware_channel, "beta");
+        assert!(first.iter().all(|d| d.created && !d.claimed));
+
+        // Re-submission returns the same codes and keys
+        let resp = test_app(db.clone())
+            .oneshot(provision(batch))
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let second: Vec<ProvisionedDevice> = serde_json::from_slice(&body).unwrap();
+        assert!(second.iter().all(|d| !d.created));
+        assert_eq!(second[0].claim_code, first[0].claim_code);
+        assert_eq!(second[0].api_key, first[0].api_key);
+
+        // Invalid batches are rejected as a whole
+        for body in [
+            r#"{"devices": [{"mac": "not-a-mac", "room_id": "room-a"}]}"#,
+            r#"{"devices": [{"mac": "AA:BB:CC:00:00:03", "room_id": "room-z"}]}"#,
+            r#"{"devices": [{"mac": "AA:BB:CC:00:00:03", "room_id": "room-a"},
+                            {"mac": "aabbcc000003", "room_id": "room-a"}]}"#,
+        ] {
+            let resp = test_app(db.clone()).oneshot(provision(body)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+        assert_eq!(db.list_provisioned_devices().unwrap().len(), 2);
+
+        // Setup claims the pre-bound code and returns the device's API key
+        let req = Request::builder()
+            .uri("/api/setup/")
+            .method("GET")
+            .header("ID", "AA:BB:CC:00:00:01")
+            .header("Access-Token", &access_token)
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let setup: SetupResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(setup.api_key, first[0].api_key);
+        let device = db.get_device("AA:BB:CC:00:00:01").unwrap().unwrap();
+        assert_eq!(device.room_id.as_deref(), Some("room-a"));
+
+        // The device displays with its own key, not with the shared token
+        let display = |token: &str| {
+            Request::builder()
+                .uri("/api/display")
+                .method("GET")
+                .header("ID", "AA:BB:CC:00:00:01")
+                .header("Access-Token", token)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let resp = test_app(db.clone())
+            .oneshot(display(&setup.api_key))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test_app(db.clone())
+            .oneshot(display(&access_token))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_per_device_api_keys() {
+        let test_db_path = "test_device_api_keys.db";
+        let access_token = get_test_access_token();
+        let device_id = "AA:BB:CC:00:00:10";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        let app = test_app(db.clone());
+        let request = |method: &str, uri: &str, token: &str| {
+            Request::builder()
+                .uri(uri)
+                .method(method)
+                .header("ID", device_id)
+                .header("Access-Token", token)
+                .header("Admin-User", "facilities")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let setup = || async {
+            let resp = app
+                .clone()
+                .oneshot(request("GET", "/api/setup/", &access_token))
+                .await
+                .unwrap();
+            if resp.status() != StatusCode::OK {
+                return Err(resp.status());
+            }
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            Ok(serde_json::from_slice::<SetupResponse>(&body)
+                .unwrap()
+                .api_key)
+        };
+        let display = |token: String| {
+            let app = app.clone();
+            async move {
+                app.oneshot(request("GET", "/api/display", &token))
+                    .await
+                    .unwrap()
+                    .status()
+            }
+        };
+        let api_key_request = |method: &str, id: &str| {
+            let uri = format!("/api/admin/devices/{}/api-key", id);
+            app.clone().oneshot(request(method, &uri, TEST_ADMIN_TOKEN))
+        };
+
+        // Setup issues a key of the device's own, which replaces the shared token
+        let api_key = setup().await.unwrap();
+        assert_ne!(api_key, access_token);
+        assert_eq!(display(api_key.clone()).await, StatusCode::OK);
+        assert_eq!(
+            display(access_token.clone()).await,
+            StatusCode::UNAUTHORIZED
+        );
+        // The key is only handed out once
+        assert_eq!(setup().await, Err(StatusCode::UNAUTHORIZED));
+
+        // A revoked device is rejected, on setup as well, until its key is reset
+        assert_eq!(
+            api_key_request("DELETE", device_id).await.unwrap().status(),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            api_key_request("DELETE", "AA:BB:CC:00:00:99")
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(display(api_key.clone()).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            display(access_token.clone()).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(setup().await, Err(StatusCode::UNAUTHORIZED));
+
+        assert_eq!(
+            api_key_request("POST", device_id).await.unwrap().status(),
+            StatusCode::NO_CONTENT
+        );
+        let new_key = setup().await.unwrap();
+        assert_ne!(new_key, api_key);
+        assert_eq!(display(new_key).await, StatusCode::OK);
+        assert_eq!(display(api_key).await, StatusCode::UNAUTHORIZED);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_device_management() {
+        let test_db_path = "test_device_management.db";
+        let device_id = "AA:BB:CC:00:00:20";
+        let stale_id = "AA:BB:CC:00:00:21";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        for id in [device_id, stale_id] {
+            db.register_device(id).unwrap();
+            db.set_device_api_key(id, "old-key").unwrap();
+        }
+        db.record_device_check_in(
+            device_id,
+            &DeviceCheckIn::default(),
+            CRITICAL_BATTERY_VOLTAGE,
+        )
+        .unwrap();
+
+        let request = |method: &str, uri: &str, body: &str| {
+            Request::builder()
+                .uri(uri)
+                .method(method)
+                .header("Access-Token", TEST_ADMIN_TOKEN)
+                .header("Admin-User", "facilities")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let send = |method: &str, uri: &str, body: &str| {
+            test_app(db.clone()).oneshot(request(method, uri, body))
+        };
+        let json = |resp: axum::response::Response| async move {
+            assert_eq!(resp.status(), StatusCode::OK);
+            axum::body::to_bytes(resp.into_body(), usize::MAX)
+                .await
+                .unwrap()
+        };
+        let device_uri = format!("/api/admin/devices/{}", device_id);
+
+        // Rename
+        let resp = send(
+            "PUT",
+            &format!("{}/name", device_uri),
+            r#"{"name": " Lobby "}"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let long_name = format!(r#"{{"name": "{}"}}"#, "x".repeat(101));
+        let resp = send("PUT", &format!("{}/name", device_uri), &long_name)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = send("GET", &device_uri, "").await.unwrap();
+        let device: DeviceInfo = serde_json::from_slice(&json(resp).await).unwrap();
+        assert_eq!(device.name.as_deref(), Some("Lobby"));
+        assert_eq!(device.status, DeviceStatus::Online);
+        assert!(device.last_seen_at.is_some());
+
+        // Devices that never checked in
+        let resp = send("GET", "/api/admin/devices?status=pending", "")
+            .await
+            .unwrap();
+        let devices: Vec<DeviceInfo> = serde_json::from_slice(&json(resp).await).unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].id, stale_id);
+
+        // Reset API key
+        let resp = send("POST", &format!("{}/api-key", device_uri), "")
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let device = db.get_device(device_id).unwrap().unwrap();
+        assert_eq!(device.api_key, None);
+        assert_eq!(device.api_key_revoked_at, None);
+
+        // Delete
+        let stale_uri = format!("/api/admin/devices/{}", stale_id);
+        let resp = send("DELETE", &stale_uri, "").await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        for (method, uri) in [
+            ("GET", stale_uri.clone()),
+            ("DELETE", stale_uri.clone()),
+            ("POST", format!("{}/api-key", stale_uri)),
+            ("PUT", format!("{}/name", stale_uri)),
+        ] {
+            let resp = send(method, &uri, r#"{"name": null}"#).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{} {}", method, uri);
+        }
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_unknown_devices() {
+        let test_db_path = "test_proxy_unknown_devices.db";
+        let access_token = get_test_access_token();
+        let known_id = "00:11:22:33:44:55";
+        let unknown_id = "AA:BB:CC:00:00:30";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device(known_id).unwrap();
+        db.register_device("AA:BB:CC:DD:EE:FF").unwrap();
+
+        // Stand-in for the TRMNL cloud, echoing what it received
+        let upstream = Router::new().fallback(|request: Request<Body>| async move {
+            let id = request.headers().get("ID").cloned();
+            let echo = format!(
+                "upstream {} {}",
+                request.uri(),
+                id.as_ref().and_then(|id| id.to_str().ok()).unwrap_or("-")
+            );
+            ([("X-Upstream", "trmnl")], echo)
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        let app_with_upstream = |upstream: String| {
+            let mut state = test_state(db.clone());
+            let config = ProxyConfig {
+                upstream,
+                routes: vec!["/api/current_screen".to_string()],
+            };
+            state.proxy = Some(Arc::new(Proxy::new(config).unwrap()));
+            create_app(state)
+        };
+        let app = app_with_upstream(upstream_url);
+        let request = |uri: &str, id: &str| {
+            Request::builder()
+                .uri(uri)
+                .header("ID", id)
+                .header("Access-Token", &access_token)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let send = |request: Request<Body>| {
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(request).await.unwrap();
+                let status = resp.status();
+                let upstream = resp.headers().contains_key("X-Upstream");
+                let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, upstream, String::from_utf8_lossy(&body).to_string())
+            }
+        };
+
+        // Unknown devices are forwarded as they are
+        let (status, upstream, body) = send(request("/api/display?x=1", unknown_id)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(upstream);
+        assert_eq!(body, format!("upstream /api/display?x=1 {}", unknown_id));
+        let (_, upstream, _) = send(request("/api/setup/", unknown_id)).await;
+        assert!(upstream);
+
+        // Known devices and devices set up with a claim code are served here
+        let (status, upstream, _) = send(request("/api/display", known_id)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!upstream);
+        // Also when they send their ID in another spelling
+        let (status, upstream, _) = send(request("/api/display", "aa-bb-cc-dd-ee-ff")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(!upstream);
+        let mut claim = request("/api/setup/", unknown_id);
+        claim
+            .headers_mut()
+            .insert("Claim-Code", "ABCD-1234".parse().unwrap());
+        let (status, upstream, _) = send(claim).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(!upstream);
+
+        // Configured routes are forwarded for all devices
+        let (_, upstream, body) = send(request("/api/current_screen", known_id)).await;
+        assert!(upstream);
+        assert_eq!(body, format!("upstream /api/current_screen {}", known_id));
+
+        // An unreachable upstream
+        let resp = app_with_upstream("http://127.0.0.1:1".to_string())
+            .oneshot(request("/api/display", unknown_id))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["error_code"], "SRV-04");
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_admin_token() {
+        let test_db_path = "test_admin_token.db";
+        remove_test_database(test_db_path);
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+
+        let request = |token: &str| {
+            Request::builder()
+                .uri("/api/admin/devices")
+                .header("Access-Token", token)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let app = test_app(db.clone());
+        let resp = app
+            .clone()
+            .oneshot(request(TEST_ADMIN_TOKEN))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        // The token shared with the devices does not give access
+        let resp = app
+            .clone()
+            .oneshot(request(&get_test_access_token()))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Without an admin token, the admin API is disabled
+        let config = Config {
+            admin_token: None,
+            ..test_config()
+        };
+        let app = create_app(AppState::new(db, Arc::new(config)).unwrap());
+        let resp = app.oneshot(request(TEST_ADMIN_TOKEN)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_admin_dashboard() {
+        let test_db_path = "test_admin_dashboard.db";
+        let access_token = get_test_access_token();
+        let device_id = "00:11:22:33:44:55";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device(device_id).unwrap();
+        let app = test_app(db.clone());
+
+        // Check in with firmware version, battery voltage and signal strength
+        let req = Request::builder()
+            .uri("/api/display")
+            .header("ID", device_id)
+            .header("Access-Token", &access_token)
+            .header("FW-Version", "1.5.2")
+            .header("Battery-Voltage", "3.92")
+            .header("RSSI", "-67")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let req = Request::builder()
+            .uri(format!("/api/admin/devices/{}", device_id))
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let device: DeviceInfo = serde_json::from_slice(&body).unwrap();
+        assert!(device.last_seen_at.is_some());
+        assert_eq!(device.battery_voltage, Some(3.92));
+        assert_eq!(device.rssi, Some(-67));
+        assert_eq!(device.firmware_version.as_deref(), Some("1.5.2"));
+
+        // Browsers are asked for the admin token as password
+        let req = Request::builder()
+            .uri("/admin")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert!(resp.headers().contains_key("WWW-Authenticate"));
+
+        let credentials = general_purpose::STANDARD.encode(format!("admin:{}", TEST_ADMIN_TOKEN));
+        let req = Request::builder()
+            .uri("/admin")
+            .header("Authorization", format!("Basic {}", credentials))
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let html = String::from_utf8_lossy(&body);
+        assert!(html.contains(device_id));
+        assert!(html.contains("Room A"));
+        assert!(html.contains("1.5.2"));
+        assert!(html.contains("3.92 V"));
+        assert!(html.contains("-67 dBm"));
+        assert!(html.contains(&format!("/admin/devices/{}/preview.bmp", device_id)));
+
+        // Preview of the current image
+        let req = Request::builder()
+            .uri(format!("/admin/devices/{}/preview.bmp", device_id))
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["Content-Type"], "image/bmp");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.starts_with(b"BM"));
+
+        let req = Request::builder()
+            .uri("/admin/devices/AA:BB:CC:00:00:40/preview.bmp")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // Preview of the image of a room, for browsers
+        let req = Request::builder()
+            .uri("/preview/room-a.png")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let req = Request::builder()
+            .uri("/preview/room-a.png")
+            .header("Authorization", format!("Basic {}", credentials))
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["Content-Type"], "image/png");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.starts_with(b"\x89PNG"));
+
+        for uri in ["/preview/unknown.png", "/preview/room-a.bmp"] {
+            let req = Request::builder()
+                .uri(uri)
+                .header("Access-Token", TEST_ADMIN_TOKEN)
+                .body(Body::empty())
+                .unwrap();
+            let resp = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_status_page() {
+        let test_db_path = "test_status_page.db";
+        let device_id = "00:11:22:33:44:55";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device(device_id).unwrap();
+        db.record_device_check_in(
+            device_id,
+            &DeviceCheckIn::default(),
+            CRITICAL_BATTERY_VOLTAGE,
+        )
+        .unwrap();
+        db.register_device("AA:BB:CC:00:00:50").unwrap();
+
+        // No authentication required
+        let req = Request::builder()
+            .uri("/status")
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let html = String::from_utf8_lossy(&body);
+        assert!(html.contains("All systems operational"));
+        assert!(html.contains("1 of 2 online"));
+        assert!(html.contains("Up for less than a minute"));
+
+        // Devices and rooms are not identified
+        assert!(!html.contains(device_id));
+        assert!(!html.contains("Room A"));
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits() {
+        let test_db_path = "test_rate_limits.db";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device("AA:BB:CC:DD:EE:FF").unwrap();
+        db.register_device("11:22:33:44:55:66").unwrap();
+        let config = Config {
+            rate_limit_device_per_minute: 1,
+            rate_limit_address_per_minute: 1,
+            trust_forwarded_for: true,
+            ..test_config()
+        };
+        let app = create_app(AppState::new(db, Arc::new(config)).unwrap());
+
+        let display = |device_id: &str, access_token: &str| {
+            Request::builder()
+                .uri("/api/display")
+                .header("ID", device_id)
+                .header("Access-Token", access_token)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let access_token = get_test_access_token();
+        let resp = app
+            .clone()
+            .oneshot(display("AA:BB:CC:DD:EE:FF", &access_token))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        // The same device in another spelling shares the limit
+        let resp = app
+            .clone()
+            .oneshot(display("aa:bb:cc:dd:ee:ff", &access_token))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers()["retry-after"], "60");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error["error_code"], "REQ-02");
+        // Requests failing to authenticate do not use up the device's limit,
+        // neither do the spoofed logs accepted with LOG_AUTH=permissive
+        for _ in 0..2 {
+            let resp = app
+                .clone()
+                .oneshot(display("11:22:33:44:55:66", "forged"))
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            let log = Request::builder()
+                .method("POST")
+                .uri("/api/log")
+                .header("ID", "11:22:33:44:55:66")
+                .header("Content-Type", "text/plain")
+                .body(Body::from("spoofed"))
+                .unwrap();
+            let resp = app.clone().oneshot(log).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        }
+        let resp = app
+            .clone()
+            .oneshot(display("11:22:33:44:55:66", &access_token))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Unauthenticated routes are limited per client address
+        let status = |address: &str| {
+            Request::builder()
+                .uri("/status")
+                .header("X-Forwarded-For", address)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let resp = app.clone().oneshot(status("192.0.2.1")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = app.clone().oneshot(status("192.0.2.1")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let resp = app.clone().oneshot(status("192.0.2.2")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_adopt_device() {
+        let test_db_path = "test_adopt_device.db";
+        // The old device is assigned to room-a in the rooms file
+        let old_id = "00:11:22:33:44:55";
+        let new_id = "00:11:22:33:44:66";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        for (id, key) in [(old_id, "old-key"), (new_id, "new-key")] {
+            db.register_device(id).unwrap();
+            db.set_device_api_key(id, key).unwrap();
+        }
+        db.set_device_image_delivery(old_id, Some("hosted"), "test")
+            .unwrap();
+        db.insert_device_logs(&[DeviceLogEntry {
+            device_id: old_id.to_string(),
+            message: "Rebooting after panic".to_string(),
+            received_at: 0,
+            authenticated: true,
+            ..Default::default()
+        }])
+        .unwrap();
+
+        let adopt = |replaces: &str| {
+            Request::builder()
+                .uri(format!("/api/admin/devices/{}/adopt", new_id))
+                .method("POST")
+                .header("Access-Token", TEST_ADMIN_TOKEN)
+                .header("Admin-User", "facilities")
+                .header("Content-Type", "application/json")
+                .body(Body::from(format!(r#"{{"replaces": "{}"}}"#, replaces)))
+                .unwrap()
+        };
+        let resp = test_app(db.clone()).oneshot(adopt(old_id)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let adoption: DeviceAdoption = serde_json::from_slice(&body).unwrap();
+        assert_eq!(adoption.replaced, old_id);
+        assert_eq!(adoption.room_id.as_deref(), Some("room-a"));
+
+        // The new device took over room, settings and history
+        let new_device = db.get_device(new_id).unwrap().unwrap();
+        assert_eq!(new_device.room_id.as_deref(), Some("room-a"));
+        assert_eq!(new_device.image_delivery.as_deref(), Some("hosted"));
+        assert_eq!(new_device.api_key.as_deref(), Some("new-key"));
+        assert_eq!(
+            db.list_device_logs(
+                &DeviceLogQuery {
+                    device_id: Some(new_id),
+                    ..Default::default()
+                },
+                10
+            )
+            .unwrap()
+            .len(),
+            1
+        );
+        let old_device = db.get_device(old_id).unwrap().unwrap();
+        assert_eq!(old_device.replaced_by.as_deref(), Some(new_id));
+        assert!(old_device.retired_at.is_some());
+
+        let display = |id: &str, key: &str| {
+            Request::builder()
+                .uri("/api/display")
+                .header("ID", id)
+                .header("Access-Token", key)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let resp = test_app(db.clone())
+            .oneshot(display(old_id, "old-key"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = test_app(db.clone())
+            .oneshot(display(new_id, "new-key"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Retired and unknown devices cannot be adopted
+        let resp = test_app(db.clone()).oneshot(adopt(old_id)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = test_app(db.clone())
+            .oneshot(adopt("00:11:22:33:44:77"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_room_badge_endpoint() {
+        let test_db_path = "test_room_badge.db";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+
+        let req = Request::builder()
+            .uri("/rooms/room-a/badge.svg")
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "image/svg+xml");
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let svg = String::from_utf8(body.to_vec()).unwrap();
+        // The test room has no calendar
+        assert!(svg.contains(">Room A</text>"));
+        assert!(svg.contains(">UNKNOWN</text>"));
+
+        let req = Request::builder()
+            .uri("/rooms/room-z/badge.svg")
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_device_logs_flag_spoofed_entries() {
+        let test_db_path = "test_device_log_auth.db";
+        let access_token = get_test_access_token();
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device("AA:BB:CC:DD:EE:01").unwrap();
+        db.provision_devices(
+            &[NewProvisionedDevice {
+                device_id: "AA:BB:CC:DD:EE:02".to_string(),
+                room_id: "room-a".to_string(),
+                label: None,
+                model: None,
+                firmware_channel: "stable".to_string(),
+                claim_code: "PROV-0001".to_string(),
+                api_key: "device-key".to_string(),
+            }],
+            "test",
+        )
+        .unwrap();
+
+        let app = test_app(db.clone());
+        for (device_id, token, message) in [
+            (
+                "AA:BB:CC:DD:EE:01",
+                Some(access_token.as_str()),
+                "shared token",
+            ),
+            ("AA:BB:CC:DD:EE:02", Some("device-key"), "own key"),
+            // Provisioned devices cannot be impersonated with the shared token
+            (
+                "AA:BB:CC:DD:EE:02",
+                Some(access_token.as_str()),
+                "impersonated",
+            ),
+            (
+                "AA:BB:CC:DD:EE:03",
+                Some(access_token.as_str()),
+                "unregistered",
+            ),
+            ("AA:BB:CC:DD:EE:01", None, "no token"),
+        ] {
+            let mut req = Request::builder()
+                .uri("/api/log")
+                .method("POST")
+                .header("ID", device_id)
+                .header("Content-Type", "text/plain");
+            if let Some(token) = token {
+                req = req.header("Access-Token", token);
+            }
+            let resp = app
+                .clone()
+                .oneshot(req.body(Body::from(message)).unwrap())
+                .await
+                .unwrap();
+            // Logs of misconfigured devices are still accepted by default
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        }
+
+        let list = |query: &str| {
+            let req = Request::builder()
+                .uri(format!("/api/admin/logs{}", query))
+                .header("Access-Token", TEST_ADMIN_TOKEN)
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<Vec<DeviceLog>>(&body).unwrap()
+            }
+        };
+        let mut logs = Vec::new();
+        for _ in 0..50 {
+            logs = list("").await;
+            if logs.len() == 5 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        let flags: Vec<(&str, bool)> = logs
+            .iter()
+            .map(|log| (log.message.as_str(), log.authenticated))
+            .collect();
+        assert_eq!(
+            flags,
+            [
+                ("no token", false),
+                ("unregistered", false),
+                ("impersonated", false),
+                ("own key", true),
+                ("shared token", true),
+            ]
+        );
+
+        let spoofed = list("?authenticated=false&device_id=aa:bb:cc:dd:ee:02").await;
+        assert_eq!(spoofed.len(), 1);
+        assert_eq!(spoofed[0].message, "impersonated");
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_device_log_entries() {
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        db.register_device("AA:BB:CC:DD:EE:01").unwrap();
+        let app = test_app(db.clone());
+        let access_token = get_test_access_token();
+
+        let body = r#"{"log":{"logs_array":[
+            {"creation_timestamp":1700000000,"log_id":1,"log_message":"Rebooting after panic","log_level":"error"},
+            {"creation_timestamp":1700000060,"log_id":2,"log_message":"WiFi weak","log_level":"warn"},
+            {"creation_timestamp":1700000120,"log_id":3,"log_message":"Display refreshed","log_level":"info"}
+        ]}}"#;
+        let req = Request::builder()
+            .uri("/api/log")
+            .method("POST")
+            .header("ID", "AA:BB:CC:DD:EE:01")
+            .header("Access-Token", &access_token)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+        let list = |query: &str| {
+            let req = Request::builder()
+                .uri(format!(
+                    "/api/admin/devices/AA:BB:CC:DD:EE:01/logs{}",
+                    query
+                ))
+                .header("Access-Token", TEST_ADMIN_TOKEN)
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<Vec<DeviceLog>>(&body).unwrap()
+            }
+        };
+        let mut logs = Vec::new();
+        for _ in 0..50 {
+            logs = list("").await;
+            if logs.len() == 3 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        // Every entry of the firmware log is stored on its own
+        assert_eq!(logs.len(), 3);
+        assert_eq!(logs[2].message, "Rebooting after panic");
+        assert_eq!(logs[2].logged_at, Some(1700000000));
+        assert_eq!(logs[2].level, Some(LogLevel::Error));
+
+        let recent_problems = list("?level=warn&since=1700000030").await;
+        assert_eq!(recent_problems.len(), 1);
+        assert_eq!(recent_problems[0].message, "WiFi weak");
+        assert!(list("?until=1700000000").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fleet_summary() {
+        let test_db_path = "test_fleet_summary.db";
+        let access_token = get_test_access_token();
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        for id in [
+            "00:11:22:33:44:55",
+            "AA:BB:CC:DD:EE:01",
+            "AA:BB:CC:DD:EE:02",
+        ] {
+            db.register_device(id).unwrap();
+        }
+        db.record_device_check_in(
+            "AA:BB:CC:DD:EE:01",
+            &DeviceCheckIn {
+                battery_voltage: Some(4.1),
+                ..Default::default()
+            },
+            CRITICAL_BATTERY_VOLTAGE,
+        )
+        .unwrap();
+
+        // Display requests record the check-in and battery voltage
+        let req = Request::builder()
+            .uri("/api/display")
+            .header("ID", "00:11:22:33:44:55")
+            .header("Access-Token", &access_token)
+            .header("Battery-Voltage", "3.3")
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let device = db.get_device("00:11:22:33:44:55").unwrap().unwrap();
+        assert!(device.last_seen_at.is_some());
+        assert_eq!(device.battery_voltage, Some(3.3));
+
+        let req = Request::builder()
+            .uri("/api/admin/summary")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let summary: FleetSummary = serde_json::from_slice(&body).unwrap();
+        assert_eq!(summary.devices.total, 3);
+        assert_eq!(summary.devices.online, 2);
+        assert_eq!(summary.devices.pending, 1);
+        assert_eq!(summary.devices.low_battery, 1);
+        // The test room has no calendar
+        assert_eq!(summary.rooms.total, 1);
+        assert_eq!(summary.rooms.unknown, 1);
+        assert_eq!(summary.calendars.total, 0);
+
+        let req = Request::builder()
+            .uri("/api/admin/summary")
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_device_list_sorted_by_health() {
+        let test_db_path = "test_device_health.db";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        for id in [
+            "00:11:22:33:44:55",
+            "AA:BB:CC:DD:EE:01",
+            "AA:BB:CC:DD:EE:02",
+        ] {
+            db.register_device(id).unwrap();
+        }
+        let now = chrono::Utc::now().timestamp();
+        let log = |device_id: &str, message: &str| DeviceLogEntry {
+            device_id: device_id.to_string(),
+            message: message.to_string(),
+            received_at: now,
+            authenticated: true,
+            ..Default::default()
+        };
+        db.insert_device_logs(&[
+            log("aa:bb:cc:dd:ee:02", "Rebooting\nWiFi connection failed"),
+            log("AA:BB:CC:DD:EE:01", "Failed to fetch image"),
+        ])
+        .unwrap();
+
+        let req = Request::builder()
+            .uri("/api/admin/devices?sort=health&limit=2")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let devices: Vec<DeviceInfo> = serde_json::from_slice(&body).unwrap();
+        let ids: Vec<&str> = devices.iter().map(|d| d.id.as_str()).collect();
+        assert_eq!(ids, ["AA:BB:CC:DD:EE:02", "AA:BB:CC:DD:EE:01"]);
+        assert_eq!(devices[0].health_score, 88);
+        assert_eq!(devices[0].health.reboots, 1);
+
+        let req = Request::builder()
+            .uri("/api/admin/devices?sort=health&order=desc")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let devices: Vec<DeviceInfo> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(devices[0].id, "00:11:22:33:44:55");
+        assert_eq!(devices[0].health_score, 100);
+
+        let req = Request::builder()
+            .uri("/api/admin/devices?sort=battery")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_room_admin_flow() {
+        let test_db_path = "test_room_admin.db";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        let app = test_app(db.clone());
+
+        let req = Request::builder()
+            .uri("/api/admin/rooms/export")
+            .method("GET")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let rooms = parse_rooms(std::str::from_utf8(&body).unwrap()).unwrap();
+        assert_eq!(rooms[0].id, "room-a");
+
+        let put_room = |id: &str, room: &Room| {
+            Request::builder()
+                .uri(format!("/api/admin/rooms/{}", id))
+                .method("PUT")
+                .header("Access-Token", TEST_ADMIN_TOKEN)
+                .header("Admin-User", "facilities")
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::to_vec(room).unwrap()))
+                .unwrap()
+        };
+
+        // Rooms can only be edited once they are managed in the database
+        let resp = app
+            .clone()
+            .oneshot(put_room("room-a", &rooms[0]))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        db.import_rooms(&rooms, "import-config").unwrap();
+        let resp = app
+            .clone()
+            .oneshot(put_room("room-b", &rooms[0]))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = app
+            .clone()
+            .oneshot(put_room("room-a", &rooms[0]))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Calendar sources cannot read arbitrary environment variables
+        let leaking = Room {
+            calendar_source: CalendarSource::CalDav {
+                username: None,
+                password_env: Some("ACCESS_TOKEN".to_string()),
+            },
+            ..rooms[0].clone()
+        };
+        let resp = app
+            .clone()
+            .oneshot(put_room("room-a", &leaking))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = Request::builder()
+            .uri("/api/admin/rooms")
+            .method("GET")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let listed: Vec<Room> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(listed[0].devices, rooms[0].devices);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_issue_report_flow() {
+        let test_db_path = "test_issue_report.db";
+
+        // Ensure test database doesn't exist
+        remove_test_database(test_db_path);
+
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+
+        // Unknown rooms have no report page
+        let req = Request::builder()
+            .uri("/report/unknown")
+            .method("GET")
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // Submit a report for a known room
+        let req = Request::builder()
+            .uri("/report/room-a")
+            .method("POST")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from("category=projector&description=broken"))
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert!(resp.status().is_success());
+
+        // The report shows up in the admin list
+        let req = Request::builder()
+            .uri("/api/admin/issues?room=room-a")
+            .method("GET")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let issues: Vec<IssueReport> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].category, "projector");
+        assert_eq!(issues[0].description, "broken");
+
+        // Resolve it
+        let req = Request::builder()
+            .uri(format!("/api/admin/issues/{}/resolve", issues[0].id))
+            .method("POST")
+            .header("Access-Token", TEST_ADMIN_TOKEN)
+            .body(Body::empty())
+            .unwrap();
+        let resp = test_app(db.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert!(db.list_open_issue_reports(None).unwrap().is_empty());
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_room_dnd() {
+        let test_db_path = "test_room_dnd.db";
+        remove_test_database(test_db_path);
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+
+        let request = |method: &str, room: &str| {
+            Request::builder()
+                .uri(format!("/api/admin/rooms/{}/dnd", room))
+                .method(method)
+                .header("Access-Token", TEST_ADMIN_TOKEN)
+                .header("Admin-User", "facilities")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Unknown rooms and rooms without a meeting in progress are rejected
+        let resp = test_app(db.clone())
+            .oneshot(request("PUT", "unknown"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let resp = test_app(db.clone())
+            .oneshot(request("PUT", "room-a"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // Clearing an active mark
+        db.set_room_dnd("room-a", "Board meeting", i64::MAX, "facilities")
+            .unwrap();
+        let resp = test_app(db.clone())
+            .oneshot(request("DELETE", "room-a"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let resp = test_app(db.clone())
+            .oneshot(request("DELETE", "room-a"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_prerender_skips_unchanged_frames() {
+        let test_db_path = "test_prerender.db";
+        remove_test_database(test_db_path);
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        let state = test_state(db.clone());
+        let rooms = state.config.rooms.snapshot();
+
+        let stats = prerender(&state, &rooms).await.unwrap();
+        assert_eq!((stats.rendered, stats.skipped, stats.failed), (1, 0, 0));
+        let fingerprint = state.frames.fingerprint("room-a").unwrap();
+        assert!(state.frames.get(fingerprint).is_some());
+
+        // Nothing changed
+        let stats = prerender(&state, &rooms).await.unwrap();
+        assert_eq!((stats.rendered, stats.skipped), (0, 1));
+        assert_eq!(state.frames.fingerprint("room-a"), Some(fingerprint));
+
+        // A broadcast changes the frame
+        db.create_broadcast("Fire drill", 600, "test").unwrap();
+        let stats = prerender(&state, &rooms).await.unwrap();
+        assert_eq!((stats.rendered, stats.skipped), (1, 0));
+        assert_ne!(state.frames.fingerprint("room-a"), Some(fingerprint));
+        assert!(state.frames.get(fingerprint).is_none());
+
+        let metrics = state.metrics.render();
+        assert!(metrics.contains("trmnl_prerender_frames_total{result=\"rendered\"} 2"));
+        assert!(metrics.contains("trmnl_prerender_frames_total{result=\"skipped\"} 1"));
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    /// Renderer counting the frames it renders
+    #[derive(Default)]
+    struct CountingRenderer(std::sync::atomic::AtomicUsize);
+
+    #[async_trait]
+    impl ImageRenderer for CountingRenderer {
+        async fn render(&self, _config: ImageConfig) -> anyhow::Result<Vec<u8>> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(b"frame".to_vec())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prerender_configured_format() {
+        let test_db_path = "test_prerender_format.db";
+        remove_test_database(test_db_path);
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device("00:11:22:33:44:55").unwrap();
+        let config = Config {
+            image_format: ImageFormat::Png,
+            ..test_config()
+        };
+        let mut state = AppState::new(db, Arc::new(config)).unwrap();
+        let renderer = Arc::new(CountingRenderer::default());
+        state.renderer = renderer.clone();
+        let rooms = state.config.rooms.snapshot();
+
+        let stats = prerender(&state, &rooms).await.unwrap();
+        assert_eq!(stats.rendered, 1);
+        assert_eq!(renderer.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // The display request finds the pre-rendered PNG frame
+        let req = Request::builder()
+            .uri("/api/display")
+            .header("ID", "00:11:22:33:44:55")
+            .header("Access-Token", get_test_access_token())
+            .body(Body::empty())
+            .unwrap();
+        let resp = create_app(state).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: DisplayResponse = serde_json::from_slice(&body).unwrap();
+        assert!(response.image_url.starts_with("data:image/png;base64,"));
+        assert_eq!(renderer.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    /// Renderer keeping the headers of the frames it renders
+    #[derive(Default)]
+    struct RecordingRenderer(std::sync::Mutex<Vec<Vec<String>>>);
+
+    #[async_trait]
+    impl ImageRenderer for RecordingRenderer {
+        async fn render(&self, config: ImageConfig) -> anyhow::Result<Vec<u8>> {
+            let badges = config
+                .header
+                .map(|header| header.badges)
+                .unwrap_or_default();
+            self.0.lock().unwrap().push(badges);
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_degraded_calendar_badge() {
+        let start = chrono::Utc::now() + chrono::Duration::hours(1);
+        let ics = format!(
+            "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nUID:1\r\nSUMMARY:Standup\r\nDTSTART:{}\r\nDTEND:{}\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n",
+            start.format("%Y%m%dT%H%M%SZ"),
+            (start + chrono::Duration::minutes(15)).format("%Y%m%dT%H%M%SZ"),
+        );
+        let router = Router::new().route("/room-a.ics", axum::routing::get(move || async { ics }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let fallback_url = format!("http://{}/room-a.ics", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let test_db_path = "test_degraded_calendar_badge.db";
+        remove_test_database(test_db_path);
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        let mut state = test_state(db);
+        let renderer = Arc::new(RecordingRenderer::default());
+        state.renderer = renderer.clone();
+        // Nothing listens on port 1
+        let rooms = parse_rooms(&format!(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+            calendar_url = "http://127.0.0.1:1/room-a.ics"
+            equipment = ["tv"]
+
+            [rooms.fallback_calendar]
+            url = "{}"
+            "#,
+            fallback_url
+        ))
+        .unwrap();
+
+        let stats = prerender(&state, &rooms).await.unwrap();
+        assert_eq!(stats.rendered, 1);
+        assert!(state.calendars.is_degraded("room-a"));
+        let badges = renderer.0.lock().unwrap().pop().unwrap();
+        assert_eq!(badges, ["BACKUP CALENDAR", "TV"]);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_reload_labels() {
+        let test_db_path = "test_reload_labels.db";
+        remove_test_database(test_db_path);
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        let state = test_state(db.clone());
+        let dir = std::env::temp_dir().join(format!("trmnl-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir_path = dir.to_str().unwrap();
+
+        std::fs::write(dir.join("de.ftl"), "status-free = VERFÜGBAR\n").unwrap();
+        reload_labels(&state, dir_path).await.unwrap();
+        let labels = state.config.labels.snapshot();
+        assert_eq!(labels.pack("de").text("status-free"), "VERFÜGBAR");
+        assert!(state.frames.fingerprint("room-a").is_some());
+
+        // Invalid packs are not swapped in
+        std::fs::write(dir.join("de.ftl"), "status-free = { $unknown }\n").unwrap();
+        assert!(reload_labels(&state, dir_path).await.is_err());
+        std::fs::write(dir.join("de.ftl"), "status-free = {").unwrap();
+        assert!(reload_labels(&state, dir_path).await.is_err());
+        let labels = state.config.labels.snapshot();
+        assert_eq!(labels.pack("de").text("status-free"), "VERFÜGBAR");
+
+        // Clean up
+        std::fs::remove_dir_all(&dir).unwrap();
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_layout_experiments() {
+        let test_db_path = "test_experiments.db";
+        let access_token = get_test_access_token();
+        remove_test_database(test_db_path);
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device("00:11:22:33:44:55").unwrap();
+        let state = test_state(db.clone());
+
+        let admin_request = |method: &str, uri: &str, body: &str| {
+            Request::builder()
+                .uri(uri)
+                .method(method)
+                .header("Access-Token", TEST_ADMIN_TOKEN)
+                .header("Admin-User", "admin")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let list_experiments = |state: AppState| {
+            let req = admin_request("GET", "/api/admin/experiments", "");
+            async move {
+                let resp = create_app(state).oneshot(req).await.unwrap();
+                let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<Vec<ExperimentInfo>>(&body).unwrap()
+            }
+        };
+
+        let experiment = r#"{"name": "focus-everywhere", "layout": "focus", "percent": 100}"#;
+        let resp = create_app(state.clone())
+            .oneshot(admin_request("POST", "/api/admin/experiments", experiment))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let resp = create_app(state.clone())
+            .oneshot(admin_request("POST", "/api/admin/experiments", experiment))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let resp = create_app(state.clone())
+            .oneshot(admin_request(
+                "POST",
+                "/api/admin/experiments",
+                r#"{"name": "days", "layout": "days", "percent": 0}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let experiments = list_experiments(state.clone()).await;
+        assert_eq!(experiments.len(), 1);
+        assert_eq!(experiments[0].experiment.state, ExperimentState::Running);
+        assert_eq!(experiments[0].variant_devices, 1);
+
+        // Renders of devices in the experiment are tracked by variant
+        let req = Request::builder()
+            .uri("/api/display")
+            .method("GET")
+            .header("ID", "00:11:22:33:44:55")
+            .header("Access-Token", &access_token)
+            .body(Body::empty())
+            .unwrap();
+        let resp = create_app(state.clone()).oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(state.metrics.render().contains(
+            "trmnl_experiment_render_seconds_count{experiment=\"focus-everywhere\",variant=\"variant\"} 1"
+        ));
+
+        let resp = create_app(state.clone())
+            .oneshot(admin_request(
+                "POST",
+                "/api/admin/experiments/focus-everywhere/rollback",
+                "",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let experiments = list_experiments(state.clone()).await;
+        assert_eq!(experiments[0].experiment.state, ExperimentState::RolledBack);
+        assert_eq!(experiments[0].variant_devices, 0);
+
+        let resp = create_app(state.clone())
+            .oneshot(admin_request(
+                "POST",
+                "/api/admin/experiments/unknown/promote",
+                "",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_room_utilization() {
+        let test_db_path = "test_utilization.db";
+        remove_test_database(test_db_path);
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        let yesterday = chrono::Local::now().date_naive() - chrono::Days::new(1);
+        let day = |day, busy_minutes| DailyUtilization {
+            room_id: "room-a".to_string(),
+            day,
+            meetings: 2,
+            busy_minutes,
+        };
+        db.record_room_utilization(&[
+            day(yesterday - chrono::Days::new(60), 120),
+            day(yesterday, 90),
+        ])
+        .unwrap();
+        let state = test_state(db.clone());
+
+        let get_utilization = |uri: &str| {
+            let req = Request::builder()
+                .uri(uri)
+                .method("GET")
+                .header("Access-Token", TEST_ADMIN_TOKEN)
+                .body(Body::empty())
+                .unwrap();
+            create_app(state.clone()).oneshot(req)
+        };
+
+        let resp = get_utilization("/api/admin/rooms/room-a/utilization")
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let days: Vec<DailyUtilization> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(days, [day(yesterday, 90)]);
+
+        let resp = get_utilization("/api/admin/rooms/room-a/utilization?days=90")
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let days: Vec<DailyUtilization> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(days.len(), 2);
+
+        let resp = get_utilization("/api/admin/rooms/room-a/utilization?days=90&group=week")
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let weeks: Vec<WeeklyUtilization> = serde_json::from_slice(&body).unwrap();
+        let working_days = [yesterday - chrono::Days::new(60), yesterday]
+            .iter()
+            .filter(|day| !Week::default().is_weekend(chrono::Datelike::weekday(*day)))
+            .count();
+        assert_eq!(weeks.len(), working_days);
+
+        // Windows reaching beyond the kept history are rejected
+        let resp = get_utilization("/api/admin/rooms/room-a/utilization?days=4000000000")
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = get_utilization("/api/admin/rooms/unknown/utilization")
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_mode() {
+        let test_db_path = "test_maintenance.db";
+        let access_token = get_test_access_token();
+        let device_id = "00:11:22:33:44:55";
+        remove_test_database(test_db_path);
+        let db = Arc::new(Database::new(test_db_path).unwrap());
+        db.register_device(device_id).unwrap();
+        let app = test_app(db.clone());
+
+        let admin_request = |method: &str, body: &str| {
+            Request::builder()
+                .uri("/api/admin/maintenance")
+                .method(method)
+                .header("Access-Token", TEST_ADMIN_TOKEN)
+                .header("Admin-User", "admin")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let display = || {
+            Request::builder()
+                .uri("/api/display")
+                .header("ID", device_id)
+                .header("Access-Token", &access_token)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let display_response = |resp: axum::response::Response| async move {
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<DisplayResponse>(&body).unwrap()
+        };
+
+        let resp = app.clone().oneshot(admin_request("GET", "")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let regular = display_response(app.clone().oneshot(display()).await.unwrap()).await;
+
+        let resp = app
+            .clone()
+            .oneshot(admin_request("PUT", r#"{"message": "Back at 8:00"}"#))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let maintenance: MaintenanceResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(maintenance.message.as_deref(), Some("Back at 8:00"));
+        assert_eq!(maintenance.started_by, "admin");
+
+        // Devices show the maintenance notice and poll rarely
+        let during = display_response(app.clone().oneshot(display()).await.unwrap()).await;
+        assert!(
+            during
+                .filename
+                .starts_with(&format!("maintenance-{}-", maintenance.started_at))
+        );
+        assert!(during.refresh_rate >= MAINTENANCE_REFRESH_RATE);
+
+        // The mode is kept in the database, across restarts
+        let app = test_app(Arc::new(Database::new(test_db_path).unwrap()));
+        let resp = app.clone().oneshot(admin_request("GET", "")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = app
+            .clone()
+            .oneshot(admin_request("DELETE", ""))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let after = display_response(app.clone().oneshot(display()).await.unwrap()).await;
+        assert_eq!(after.filename, regular.filename);
+        let resp = app.oneshot(admin_request("DELETE", "")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        // Clean up
+        remove_test_database(test_db_path);
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_jobs() {
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        let state = test_state(db.clone());
+        state.scheduler.add(Job::new(
+            "nightly_export",
+            "0 3 * * *".parse().unwrap(),
+            |_| async { Ok("exported 3 rooms".to_string()) },
+        ));
+        state.scheduler.add(
+            Job::new("flaky", "@every 1h".parse().unwrap(), |_| async {
+                Err(anyhow::anyhow!("upstream unavailable"))
+            })
+            .exclusive(),
+        );
+        let app = create_app(state);
+
+        let admin_request = |method: &str, uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .method(method)
+                .header("Access-Token", TEST_ADMIN_TOKEN)
+                .header("Admin-User", "admin")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let json = |resp: axum::response::Response| async move {
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+        };
+        let runs = |name: &str| {
+            let app = app.clone();
+            let uri = format!("/api/admin/jobs/{}/runs", name);
+            async move {
+                // Jobs run in the background, wait for the run to be recorded
+                for _ in 0..50 {
+                    let resp = app
+                        .clone()
+                        .oneshot(admin_request("GET", &uri))
+                        .await
+                        .unwrap();
+                    let runs: Vec<JobRunInfo> = serde_json::from_value(json(resp).await).unwrap();
+                    if !runs.is_empty() {
+                        return runs;
+                    }
+                    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                }
+                Vec::new()
+            }
+        };
+
+        let resp = app
+            .clone()
+            .oneshot(admin_request("GET", "/api/admin/jobs"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let jobs: Vec<JobInfo> = serde_json::from_value(json(resp).await).unwrap();
+        let names: Vec<_> = jobs.iter().map(|job| job.name.as_str()).collect();
+        assert_eq!(names, ["flaky", "nightly_export"]);
+        assert_eq!(jobs[1].schedule, "0 3 * * *");
+        assert!(jobs[0].exclusive && !jobs[0].paused && jobs[0].last_run.is_none());
+
+        // Paused jobs can still be run on demand
+        let resp = app
+            .clone()
+            .oneshot(admin_request("PUT", "/api/admin/jobs/nightly_export/pause"))
+            .await
+            .unwrap();
+        let job: JobInfo = serde_json::from_value(json(resp).await).unwrap();
+        assert!(job.paused);
+        assert_eq!(job.paused_by.as_deref(), Some("admin"));
+        let resp = app
+            .clone()
+            .oneshot(admin_request("POST", "/api/admin/jobs/nightly_export/run"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        let runs_of_export = runs("nightly_export").await;
+        assert_eq!(runs_of_export.len(), 1);
+        assert!(runs_of_export[0].manual && runs_of_export[0].succeeded);
+        assert_eq!(
+            runs_of_export[0].message.as_deref(),
+            Some("exported 3 rooms")
+        );
+
+        let resp = app
+            .clone()
+            .oneshot(admin_request(
+                "DELETE",
+                "/api/admin/jobs/nightly_export/pause",
+            ))
+            .await
+            .unwrap();
+        let job: JobInfo = serde_json::from_value(json(resp).await).unwrap();
+        assert!(!job.paused);
+        assert!(job.last_run.is_some());
+
+        // Failures are recorded with their error
+        app.clone()
+            .oneshot(admin_request("POST", "/api/admin/jobs/flaky/run"))
+            .await
+            .unwrap();
+        let runs_of_flaky = runs("flaky").await;
+        assert!(!runs_of_flaky[0].succeeded);
+        assert_eq!(
+            runs_of_flaky[0].message.as_deref(),
+            Some("upstream unavailable")
+        );
+
+        let resp = app
+            .clone()
+            .oneshot(admin_request("POST", "/api/admin/jobs/unknown/run"))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
     }
 }
@@ -0,0 +1,519 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+/// Upper bounds of the image payload size histogram buckets, in bytes
+const PAYLOAD_SIZE_BUCKETS: &[u64] = &[1024, 2048, 4096, 8192, 16384, 32768, 65536, 131072, 262144];
+
+/// Upper bounds of the calendar parse duration histogram buckets, in milliseconds
+const CALENDAR_PARSE_BUCKETS: &[u64] = &[5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/// Upper bounds of the image render duration histogram buckets, in milliseconds
+const RENDER_BUCKETS: &[u64] = &[1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500];
+
+/// Histogram with fixed buckets, in the Prometheus sense (cumulative counts)
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Number of observations per bucket (not cumulative)
+    buckets: Vec<u64>,
+    sum: u64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, bounds: &[u64], value: u64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; bounds.len()];
+        }
+        if let Some(index) = bounds.iter().position(|bound| value <= *bound) {
+            self.buckets[index] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// In-process metrics, exposed in the Prometheus text format
+///
+/// Metrics are per instance; Prometheus aggregates across instances.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Image payload sizes keyed by (format, device model)
+    payload_sizes: Mutex<BTreeMap<(String, String), Histogram>>,
+    /// Device log entries rejected because the log buffer was full
+    device_logs_dropped: AtomicU64,
+    /// Room frames pre-rendered because they changed
+    prerender_rendered: AtomicU64,
+    /// Room frames not pre-rendered because they were unchanged
+    prerender_skipped: AtomicU64,
+    /// Room frames that failed to pre-render
+    prerender_failed: AtomicU64,
+    /// Calendar parse durations, in milliseconds
+    calendar_parse: Mutex<Histogram>,
+    /// Parsed calendar events kept because they are in the look-ahead window
+    calendar_events_kept: AtomicU64,
+    /// Parsed calendar events dropped because they are outside the look-ahead window
+    calendar_events_outside_window: AtomicU64,
+    /// Durations of producing the images of devices in layout experiments,
+    /// in milliseconds, keyed by (experiment, variant)
+    experiment_renders: Mutex<BTreeMap<(String, String), Histogram>>,
+    /// Inline payloads served from the payload cache
+    inline_cache_hits: AtomicU64,
+    /// Inline payloads encoded because they were not cached
+    inline_cache_misses: AtomicU64,
+    /// Frames served to devices that were unchanged since their previous request
+    display_frames_unchanged: AtomicU64,
+    /// Frames served to devices that changed since their previous request
+    display_frames_changed: AtomicU64,
+    /// Images taken from the render cache
+    render_cache_hits: AtomicU64,
+    /// Images rendered because they were not cached
+    render_cache_misses: AtomicU64,
+    /// Requests rejected because their device exceeded its rate limit
+    rate_limited_devices: AtomicU64,
+    /// Requests rejected because their client address exceeded its rate limit
+    rate_limited_addresses: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the size of an encoded image served to a device
+    pub fn observe_payload_size(&self, format: &str, model: &str, bytes: usize) {
+        if let Ok(mut sizes) = self.payload_sizes.lock() {
+            sizes
+                .entry((format.to_string(), model.to_string()))
+                .or_default()
+                .observe(PAYLOAD_SIZE_BUCKETS, bytes as u64);
+        }
+    }
+
+    /// Record a device log entry rejected because the log buffer was full
+    pub fn inc_device_logs_dropped(&self) {
+        self.device_logs_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the outcome of a pre-render run
+    pub fn record_prerender(&self, rendered: usize, skipped: usize, failed: usize) {
+        self.prerender_rendered
+            .fetch_add(rendered as u64, Ordering::Relaxed);
+        self.prerender_skipped
+            .fetch_add(skipped as u64, Ordering::Relaxed);
+        self.prerender_failed
+            .fetch_add(failed as u64, Ordering::Relaxed);
+    }
+
+    /// Record the parsing of a calendar feed
+    pub fn observe_calendar_parse(&self, duration: Duration, kept: usize, outside_window: usize) {
+        if let Ok(mut histogram) = self.calendar_parse.lock() {
+            histogram.observe(CALENDAR_PARSE_BUCKETS, duration.as_millis() as u64);
+        }
+        self.calendar_events_kept
+            .fetch_add(kept as u64, Ordering::Relaxed);
+        self.calendar_events_outside_window
+            .fetch_add(outside_window as u64, Ordering::Relaxed);
+    }
+
+    /// Record the time taken to produce the image of a device in a layout
+    /// experiment, whether rendered or pre-rendered
+    pub fn observe_experiment_render(&self, experiment: &str, variant: &str, duration: Duration) {
+        if let Ok(mut renders) = self.experiment_renders.lock() {
+            renders
+                .entry((experiment.to_string(), variant.to_string()))
+                .or_default()
+                .observe(RENDER_BUCKETS, duration.as_millis() as u64);
+        }
+    }
+
+    /// Record whether the base64 payload of an inline image was cached
+    pub fn record_inline_cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.inline_cache_hits
+        } else {
+            &self.inline_cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record whether the frame served to a device changed since its previous
+    /// request
+    pub fn record_display_frame(&self, unchanged: bool) {
+        let counter = if unchanged {
+            &self.display_frames_unchanged
+        } else {
+            &self.display_frames_changed
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record whether a rendered image was taken from the render cache
+    pub fn record_render_cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.render_cache_hits
+        } else {
+            &self.render_cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a request rejected by the rate limit of its device, or else of
+    /// its client address
+    pub fn record_rate_limited(&self, per_device: bool) {
+        let counter = if per_device {
+            &self.rate_limited_devices
+        } else {
+            &self.rate_limited_addresses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let name = "trmnl_image_payload_bytes";
+        let _ = writeln!(
+            out,
+            "# HELP {} Size of encoded images served to devices",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+
+        if let Ok(sizes) = self.payload_sizes.lock() {
+            for ((format, model), histogram) in sizes.iter() {
+                let labels = format!(
+                    "format=\"{}\",model=\"{}\"",
+                    escape_label(format),
+                    escape_label(model)
+                );
+                let mut cumulative = 0;
+                for (bound, count) in PAYLOAD_SIZE_BUCKETS.iter().zip(&histogram.buckets) {
+                    cumulative += count;
+                    let _ = writeln!(
+                        out,
+                        "{}_bucket{{{},le=\"{}\"}} {}",
+                        name, labels, bound, cumulative
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{{},le=\"+Inf\"}} {}",
+                    name, labels, histogram.count
+                );
+                let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+                let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+            }
+        }
+
+        let name = "trmnl_device_logs_dropped_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Device log entries rejected because the log buffer was full",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        let _ = writeln!(
+            out,
+            "{} {}",
+            name,
+            self.device_logs_dropped.load(Ordering::Relaxed)
+        );
+
+        let name = "trmnl_prerender_frames_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Room frames checked after calendar refreshes, by outcome",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (result, count) in [
+            ("rendered", &self.prerender_rendered),
+            ("skipped", &self.prerender_skipped),
+            ("failed", &self.prerender_failed),
+        ] {
+            let _ = writeln!(
+                out,
+                "{}{{result=\"{}\"}} {}",
+                name,
+                result,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        let name = "trmnl_inline_payload_cache_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Base64 payloads of inline images, by whether they were cached",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (result, count) in [
+            ("hit", &self.inline_cache_hits),
+            ("miss", &self.inline_cache_misses),
+        ] {
+            let _ = writeln!(
+                out,
+                "{}{{result=\"{}\"}} {}",
+                name,
+                result,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        let name = "trmnl_render_cache_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Images to render, by whether they were taken from the render cache",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (result, count) in [
+            ("hit", &self.render_cache_hits),
+            ("miss", &self.render_cache_misses),
+        ] {
+            let _ = writeln!(
+                out,
+                "{}{{result=\"{}\"}} {}",
+                name,
+                result,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        let name = "trmnl_rate_limited_requests_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Requests rejected by a rate limit, by what they were limited by",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (limit, count) in [
+            ("device", &self.rate_limited_devices),
+            ("address", &self.rate_limited_addresses),
+        ] {
+            let _ = writeln!(
+                out,
+                "{}{{limit=\"{}\"}} {}",
+                name,
+                limit,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        let name = "trmnl_display_frames_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Frames served to devices, by whether they changed since the previous request",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (result, count) in [
+            ("changed", &self.display_frames_changed),
+            ("unchanged", &self.display_frames_unchanged),
+        ] {
+            let _ = writeln!(
+                out,
+                "{}{{result=\"{}\"}} {}",
+                name,
+                result,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        let name = "trmnl_calendar_parse_seconds";
+        let _ = writeln!(out, "# HELP {} Time taken to parse calendar feeds", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        if let Ok(histogram) = self.calendar_parse.lock()
+            && histogram.count > 0
+        {
+            let mut cumulative = 0;
+            for (bound, count) in CALENDAR_PARSE_BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{le=\"{}\"}} {}",
+                    name,
+                    *bound as f64 / 1000.0,
+                    cumulative
+                );
+            }
+            let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count);
+            let _ = writeln!(out, "{}_sum {}", name, histogram.sum as f64 / 1000.0);
+            let _ = writeln!(out, "{}_count {}", name, histogram.count);
+        }
+
+        let name = "trmnl_calendar_events_parsed_total";
+        let _ = writeln!(
+            out,
+            "# HELP {} Events read from calendar feeds, by whether they were in the look-ahead window",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for (result, count) in [
+            ("kept", &self.calendar_events_kept),
+            ("outside_window", &self.calendar_events_outside_window),
+        ] {
+            let _ = writeln!(
+                out,
+                "{}{{result=\"{}\"}} {}",
+                name,
+                result,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        let name = "trmnl_experiment_render_seconds";
+        let _ = writeln!(
+            out,
+            "# HELP {} Time taken to produce the images of devices in layout experiments",
+            name
+        );
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        if let Ok(renders) = self.experiment_renders.lock() {
+            for ((experiment, variant), histogram) in renders.iter() {
+                let labels = format!(
+                    "experiment=\"{}\",variant=\"{}\"",
+                    escape_label(experiment),
+                    escape_label(variant)
+                );
+                let mut cumulative = 0;
+                for (bound, count) in RENDER_BUCKETS.iter().zip(&histogram.buckets) {
+                    cumulative += count;
+                    let _ = writeln!(
+                        out,
+                        "{}_bucket{{{},le=\"{}\"}} {}",
+                        name,
+                        labels,
+                        *bound as f64 / 1000.0,
+                        cumulative
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{{},le=\"+Inf\"}} {}",
+                    name, labels, histogram.count
+                );
+                let _ = writeln!(
+                    out,
+                    "{}_sum{{{}}} {}",
+                    name,
+                    labels,
+                    histogram.sum as f64 / 1000.0
+                );
+                let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+            }
+        }
+        out
+    }
+}
+
+/// Escape a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_size_histogram() {
+        let metrics = Metrics::new();
+        metrics.observe_payload_size("bmp", "og", 3000);
+        metrics.observe_payload_size("bmp", "og", 48000);
+        metrics.observe_payload_size("bmp", "og", 1_000_000);
+
+        let out = metrics.render();
+        assert!(out.contains("# TYPE trmnl_image_payload_bytes histogram"));
+        assert!(out.contains(
+            "trmnl_image_payload_bytes_bucket{format=\"bmp\",model=\"og\",le=\"2048\"} 0"
+        ));
+        assert!(out.contains(
+            "trmnl_image_payload_bytes_bucket{format=\"bmp\",model=\"og\",le=\"4096\"} 1"
+        ));
+        assert!(out.contains(
+            "trmnl_image_payload_bytes_bucket{format=\"bmp\",model=\"og\",le=\"65536\"} 2"
+        ));
+        assert!(out.contains(
+            "trmnl_image_payload_bytes_bucket{format=\"bmp\",model=\"og\",le=\"+Inf\"} 3"
+        ));
+        assert!(out.contains("trmnl_image_payload_bytes_sum{format=\"bmp\",model=\"og\"} 1051000"));
+
+        metrics.inc_device_logs_dropped();
+        assert!(
+            metrics
+                .render()
+                .contains("trmnl_device_logs_dropped_total 1\n")
+        );
+
+        metrics.record_inline_cache(true);
+        metrics.record_inline_cache(true);
+        metrics.record_inline_cache(false);
+        let out = metrics.render();
+        assert!(out.contains("trmnl_inline_payload_cache_total{result=\"hit\"} 2\n"));
+        assert!(out.contains("trmnl_inline_payload_cache_total{result=\"miss\"} 1\n"));
+
+        metrics.record_display_frame(false);
+        metrics.record_display_frame(true);
+        metrics.record_display_frame(true);
+        let out = metrics.render();
+        assert!(out.contains("trmnl_display_frames_total{result=\"changed\"} 1\n"));
+        assert!(out.contains("trmnl_display_frames_total{result=\"unchanged\"} 2\n"));
+
+        metrics.record_rate_limited(true);
+        let out = metrics.render();
+        assert!(out.contains("trmnl_rate_limited_requests_total{limit=\"device\"} 1\n"));
+        assert!(out.contains("trmnl_rate_limited_requests_total{limit=\"address\"} 0\n"));
+    }
+
+    #[test]
+    fn test_calendar_parse_histogram() {
+        let metrics = Metrics::new();
+        metrics.observe_calendar_parse(Duration::from_millis(40), 12, 0);
+        metrics.observe_calendar_parse(Duration::from_millis(1200), 30, 39970);
+
+        let out = metrics.render();
+        assert!(out.contains("trmnl_calendar_parse_seconds_bucket{le=\"0.025\"} 0\n"));
+        assert!(out.contains("trmnl_calendar_parse_seconds_bucket{le=\"0.05\"} 1\n"));
+        assert!(out.contains("trmnl_calendar_parse_seconds_bucket{le=\"2.5\"} 2\n"));
+        assert!(out.contains("trmnl_calendar_parse_seconds_sum 1.24\n"));
+        assert!(out.contains("trmnl_calendar_events_parsed_total{result=\"kept\"} 42\n"));
+        assert!(
+            out.contains("trmnl_calendar_events_parsed_total{result=\"outside_window\"} 39970\n")
+        );
+    }
+
+    #[test]
+    fn test_experiment_render_histogram() {
+        let metrics = Metrics::new();
+        metrics.observe_experiment_render("focus", "control", Duration::from_millis(2));
+        metrics.observe_experiment_render("focus", "variant", Duration::from_millis(300));
+        metrics.observe_experiment_render("focus", "variant", Duration::from_millis(40));
+
+        let out = metrics.render();
+        assert!(out.contains(
+            "trmnl_experiment_render_seconds_bucket{experiment=\"focus\",variant=\"control\",le=\"0.005\"} 1\n"
+        ));
+        assert!(out.contains(
+            "trmnl_experiment_render_seconds_bucket{experiment=\"focus\",variant=\"variant\",le=\"0.05\"} 1\n"
+        ));
+        assert!(out.contains(
+            "trmnl_experiment_render_seconds_count{experiment=\"focus\",variant=\"variant\"} 2\n"
+        ));
+    }
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label("a\"b\\c\n"), "a\\\"b\\\\c\\n");
+    }
+}
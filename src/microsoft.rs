@@ -0,0 +1,443 @@
+//! Microsoft 365 backend, reading room mailboxes through Microsoft Graph
+//!
+//! Meeting rooms in Exchange Online are room mailboxes, whose calendars can
+//! rarely be published as iCal feeds. An app registration with the
+//! `Calendars.Read` application permission reads them instead, authenticated
+//! with the client credentials flow. Access can be limited to the room
+//! mailboxes with an application access policy in Exchange.
+//!
+//! The events are converted to iCalendar data, so that they are parsed,
+//! cached and shared between instances like those of any other source.
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+use chrono::{NaiveDateTime, Utc};
+use serde::Deserialize;
+
+use crate::calendar::{CalendarError, EventWindow};
+
+/// Base URL of Microsoft Graph
+const API_URL: &str = "https://graph.microsoft.com/v1.0";
+
+/// Base URL of the Microsoft identity platform
+const LOGIN_URL: &str = "https://login.microsoftonline.com";
+
+/// Scope of the access tokens, i.e. the application permissions granted to
+/// the app registration
+const SCOPE: &str = "https://graph.microsoft.com/.default";
+
+/// Access tokens are renewed this long before they expire
+const TOKEN_RENEWAL_SECS: i64 = 60;
+
+/// Events requested per page
+const PAGE_SIZE: u32 = 500;
+
+/// App registration in Microsoft Entra ID
+#[derive(Debug, Clone)]
+pub struct AppCredentials {
+    /// Directory (tenant) ID or domain, e.g. `contoso.onmicrosoft.com`
+    pub tenant_id: String,
+    /// Application (client) ID
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+/// Response of the token endpoint
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: i64,
+}
+
+/// Access tokens by tenant and client, with their expiry
+fn token_cache() -> &'static Mutex<HashMap<String, (String, i64)>> {
+    static TOKENS: OnceLock<Mutex<HashMap<String, (String, i64)>>> = OnceLock::new();
+    TOKENS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Access token of the app, from the cache if still valid
+async fn access_token(
+    client: &reqwest::Client,
+    login_url: &str,
+    credentials: &AppCredentials,
+) -> Result<String, CalendarError> {
+    let cache_key = format!("{} {}", credentials.tenant_id, credentials.client_id);
+    let now = Utc::now().timestamp();
+    if let Ok(tokens) = token_cache().lock()
+        && let Some((token, expires_at)) = tokens.get(&cache_key)
+        && *expires_at > now + TOKEN_RENEWAL_SECS
+    {
+        return Ok(token.clone());
+    }
+
+    let response = client
+        .post(format!(
+            "{}/{}/oauth2/v2.0/token",
+            login_url.trim_end_matches('/'),
+            credentials.tenant_id
+        ))
+        .form(&[
+            ("grant_type", "client_credentials"),
+            ("client_id", credentials.client_id.as_str()),
+            ("client_secret", credentials.client_secret.as_str()),
+            ("scope", SCOPE),
+        ])
+        .send()
+        .await
+        .map_err(|e| CalendarError::FetchError(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(CalendarError::FetchError(format!(
+            "Microsoft token endpoint returned {}",
+            response.status()
+        )));
+    }
+    let token: TokenResponse = response
+        .json()
+        .await
+        .map_err(|e| CalendarError::FetchError(format!("Invalid token response: {}", e)))?;
+
+    if let Ok(mut tokens) = token_cache().lock() {
+        tokens.insert(
+            cache_key,
+            (token.access_token.clone(), now + token.expires_in),
+        );
+    }
+    Ok(token.access_token)
+}
+
+/// Page of a calendar view
+#[derive(Deserialize)]
+struct EventsPage {
+    #[serde(default)]
+    value: Vec<GraphEvent>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+}
+
+/// Event as returned by Microsoft Graph
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphEvent {
+    id: String,
+    #[serde(rename = "iCalUId")]
+    ical_uid: Option<String>,
+    subject: Option<String>,
+    body_preview: Option<String>,
+    location: Option<Location>,
+    /// `normal`, `personal`, `private` or `confidential`
+    sensitivity: Option<String>,
+    #[serde(default)]
+    is_all_day: bool,
+    #[serde(default)]
+    is_cancelled: bool,
+    start: EventTime,
+    end: EventTime,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Location {
+    display_name: Option<String>,
+}
+
+/// Start or end of an event, in the time zone asked for with the `Prefer`
+/// header, i.e. UTC
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EventTime {
+    /// Time without offset, e.g. `2024-03-04T09:00:00.0000000`
+    date_time: String,
+}
+
+impl EventTime {
+    /// Property of the time, e.g. `DTSTART:20240304T090000Z`, only the date
+    /// for all-day events
+    fn property(&self, name: &str, all_day: bool) -> Option<String> {
+        let time = NaiveDateTime::parse_from_str(&self.date_time, "%Y-%m-%dT%H:%M:%S%.f").ok()?;
+        Some(if all_day {
+            format!("{};VALUE=DATE:{}", name, time.format("%Y%m%d"))
+        } else {
+            format!("{}:{}", name, time.format("%Y%m%dT%H%M%SZ"))
+        })
+    }
+}
+
+/// Fetches the events of a time window from the calendar of a room mailbox
+///
+/// Returns the events as iCalendar data, like an export of the calendar
+/// would be.
+pub async fn fetch_microsoft_data(
+    mailbox: &str,
+    credentials: &AppCredentials,
+    window: EventWindow,
+) -> Result<String, CalendarError> {
+    fetch_events(API_URL, LOGIN_URL, mailbox, credentials, window).await
+}
+
+/// Fetches the events of a time window from Graph at the given base URLs
+async fn fetch_events(
+    api_url: &str,
+    login_url: &str,
+    mailbox: &str,
+    credentials: &AppCredentials,
+    window: EventWindow,
+) -> Result<String, CalendarError> {
+    let client = reqwest::Client::new();
+    let token = access_token(&client, login_url, credentials).await?;
+    let mut url = reqwest::Url::parse(api_url)
+        .map_err(|e| CalendarError::FetchError(format!("Invalid API URL {}: {}", api_url, e)))?;
+    url.path_segments_mut()
+        .map_err(|_| CalendarError::FetchError(format!("Invalid API URL {}", api_url)))?
+        .pop_if_empty()
+        .extend(["users", mailbox, "calendarView"]);
+    // The calendar view has recurring events expanded to their occurrences
+    url.query_pairs_mut()
+        .append_pair("startDateTime", &window.start.to_rfc3339())
+        .append_pair("endDateTime", &window.end.to_rfc3339())
+        .append_pair("$top", &PAGE_SIZE.to_string());
+
+    let mut events = Vec::new();
+    let mut next = Some(url.to_string());
+    while let Some(url) = next.take() {
+        let response = client
+            .get(&url)
+            .bearer_auth(&token)
+            .header("Prefer", "outlook.timezone=\"UTC\"")
+            .send()
+            .await
+            .map_err(|e| CalendarError::FetchError(e.to_string()))?;
+        if !response.status().is_success() {
+            return Err(CalendarError::FetchError(format!(
+                "HTTP error: {}",
+                response.status()
+            )));
+        }
+        let page: EventsPage = response
+            .json()
+            .await
+            .map_err(|e| CalendarError::ParseError(format!("Invalid events response: {}", e)))?;
+        events.extend(page.value);
+        next = page.next_link;
+    }
+
+    Ok(events_to_ical(&events))
+}
+
+/// iCalendar data of the given events
+///
+/// Cancelled events are left out. Titles and locations are taken verbatim,
+/// except for line breaks, like the parser reads them.
+fn events_to_ical(events: &[GraphEvent]) -> String {
+    let single_line = |text: &str| text.replace(['\r', '\n'], " ");
+    let mut ical = String::from(
+        "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//trmnl-meeting-room-display//Microsoft//EN\r\n",
+    );
+    for event in events {
+        if event.is_cancelled {
+            continue;
+        }
+        let (Some(start), Some(end)) = (
+            event.start.property("DTSTART", event.is_all_day),
+            event.end.property("DTEND", event.is_all_day),
+        ) else {
+            continue;
+        };
+        let mut lines = vec![
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}", event.ical_uid.as_deref().unwrap_or(&event.id)),
+            start,
+            end,
+            format!(
+                "SUMMARY:{}",
+                single_line(event.subject.as_deref().unwrap_or("(No title)"))
+            ),
+        ];
+        if let Some(location) = event
+            .location
+            .as_ref()
+            .and_then(|location| location.display_name.as_deref())
+            .filter(|name| !name.is_empty())
+        {
+            lines.push(format!("LOCATION:{}", single_line(location)));
+        }
+        if let Some(description) = event.body_preview.as_deref().filter(|d| !d.is_empty()) {
+            let escaped = description
+                .replace('\\', "\\\\")
+                .replace("\r\n", "\\n")
+                .replace('\n', "\\n");
+            lines.push(format!("DESCRIPTION:{}", escaped));
+        }
+        if matches!(
+            event.sensitivity.as_deref(),
+            Some("private" | "confidential")
+        ) {
+            lines.push("CLASS:PRIVATE".to_string());
+        }
+        lines.push("END:VEVENT".to_string());
+        for line in lines {
+            ical.push_str(&line);
+            ical.push_str("\r\n");
+        }
+    }
+    ical.push_str("END:VCALENDAR\r\n");
+    ical
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Form, Json, Router,
+        extract::{Path, Query},
+        http::{HeaderMap, StatusCode},
+        routing::{get, post},
+    };
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+    use crate::calendar::parse_calendar_window;
+
+    fn window() -> EventWindow {
+        EventWindow {
+            start: Utc.with_ymd_and_hms(2024, 3, 4, 0, 0, 0).unwrap().into(),
+            end: Utc.with_ymd_and_hms(2024, 3, 6, 0, 0, 0).unwrap().into(),
+        }
+    }
+
+    #[test]
+    fn test_events_to_ical() {
+        let events: Vec<GraphEvent> = serde_json::from_value(json!([
+            {
+                "id": "AAMkAD1",
+                "iCalUId": "040000008200E00074C5B7101A82E008",
+                "subject": "Standup, daily",
+                "bodyPreview": "Agenda:\r\n1. Status",
+                "location": {"displayName": "Matterhorn"},
+                "sensitivity": "private",
+                "start": {"dateTime": "2024-03-04T09:00:00.0000000", "timeZone": "UTC"},
+                "end": {"dateTime": "2024-03-04T09:15:00.0000000", "timeZone": "UTC"}
+            },
+            {
+                "id": "AAMkAD2",
+                "subject": "Cancelled",
+                "isCancelled": true,
+                "start": {"dateTime": "2024-03-04T11:00:00.0000000", "timeZone": "UTC"},
+                "end": {"dateTime": "2024-03-04T12:00:00.0000000", "timeZone": "UTC"}
+            },
+            {
+                "id": "AAMkAD3",
+                "isAllDay": true,
+                "location": {"displayName": ""},
+                "start": {"dateTime": "2024-03-05T00:00:00.0000000", "timeZone": "UTC"},
+                "end": {"dateTime": "2024-03-06T00:00:00.0000000", "timeZone": "UTC"}
+            }
+        ]))
+        .unwrap();
+
+        let ical = events_to_ical(&events);
+        let parsed = parse_calendar_window(&ical, window()).unwrap();
+        assert_eq!(parsed.events.len(), 2);
+        let standup = &parsed.events[0];
+        assert_eq!(standup.name, "Standup, daily");
+        assert_eq!(
+            standup.start_time,
+            Utc.with_ymd_and_hms(2024, 3, 4, 9, 0, 0).unwrap()
+        );
+        assert_eq!(standup.location.as_deref(), Some("Matterhorn"));
+        assert_eq!(standup.description.as_deref(), Some("Agenda:\n1. Status"));
+        assert!(standup.private);
+        assert_eq!(parsed.events[1].name, "(No title)");
+        assert_eq!(parsed.events[1].location, None);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_events() {
+        let router = Router::new()
+            .route(
+                "/:tenant/oauth2/v2.0/token",
+                post(
+                    |Path(tenant): Path<String>,
+                     Form(form): Form<HashMap<String, String>>| async move {
+                        assert_eq!(tenant, "contoso.onmicrosoft.com");
+                        if form["grant_type"] != "client_credentials"
+                            || form["client_secret"] != "secret"
+                        {
+                            return Err(StatusCode::UNAUTHORIZED);
+                        }
+                        Ok(Json(json!({"access_token": "token-1", "expires_in": 3600})))
+                    },
+                ),
+            )
+            .route(
+                "/users/:mailbox/calendarView",
+                get(
+                    |Path(mailbox): Path<String>,
+                     headers: HeaderMap,
+                     Query(query): Query<HashMap<String, String>>| async move {
+                        if headers.get("authorization").unwrap() != "Bearer token-1" {
+                            return Err(StatusCode::UNAUTHORIZED);
+                        }
+                        assert_eq!(mailbox, "matterhorn@contoso.com");
+                        assert_eq!(headers.get("prefer").unwrap(), "outlook.timezone=\"UTC\"");
+                        let event = |id: &str, hour: u32| {
+                            json!({
+                                "id": id,
+                                "subject": format!("Meeting {}", id),
+                                "start": {"dateTime": format!("2024-03-04T{:02}:00:00.0000000", hour)},
+                                "end": {"dateTime": format!("2024-03-04T{:02}:30:00.0000000", hour)}
+                            })
+                        };
+                        // Two pages of one event each
+                        Ok(Json(match query.get("$skip") {
+                            None => {
+                                assert!(query["startDateTime"].starts_with("2024-03-04T00:00:00"));
+                                json!({
+                                "value": [event("a", 9)],
+                                "@odata.nextLink": format!(
+                                    "http://{}/users/{}/calendarView?$skip=1",
+                                    headers.get("host").unwrap().to_str().unwrap(),
+                                    mailbox
+                                )
+                                })
+                            }
+                            Some(_) => json!({"value": [event("b", 10)]}),
+                        }))
+                    },
+                ),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let credentials = AppCredentials {
+            tenant_id: "contoso.onmicrosoft.com".to_string(),
+            client_id: format!("client-{}", rand::random::<u32>()),
+            client_secret: "secret".to_string(),
+        };
+        let ical = fetch_events(
+            &base,
+            &base,
+            "matterhorn@contoso.com",
+            &credentials,
+            window(),
+        )
+        .await
+        .unwrap();
+        let parsed = parse_calendar_window(&ical, window()).unwrap();
+        let names: Vec<&str> = parsed.events.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["Meeting a", "Meeting b"]);
+
+        let wrong = AppCredentials {
+            client_secret: "wrong".to_string(),
+            client_id: format!("client-{}", rand::random::<u32>()),
+            ..credentials
+        };
+        let error = fetch_events(&base, &base, "matterhorn@contoso.com", &wrong, window())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, CalendarError::FetchError(_)));
+    }
+}
@@ -0,0 +1,366 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use chrono::{DateTime, Datelike, Duration, Local, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::agenda::BusinessHours;
+use crate::calendar::CalendarEvent;
+use crate::week::Week;
+
+/// Shortest refresh rate in seconds sent to devices
+///
+/// Devices polling more often would drain their battery within days.
+pub const MIN_REFRESH_RATE: u32 = 10;
+
+/// Longest refresh rate in seconds sent to devices, a day
+pub const MAX_REFRESH_RATE: u32 = 24 * 60 * 60;
+
+/// Largest jitter, in percent of the refresh rate
+pub const MAX_REFRESH_JITTER_PERCENT: u32 = 50;
+
+/// Checks that a refresh rate in seconds is within the bounds sent to devices
+fn check_rate(name: &str, rate: u32) -> Result<(), String> {
+    if (MIN_REFRESH_RATE..=MAX_REFRESH_RATE).contains(&rate) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} must be between {} and {} seconds, got {}",
+            name, MIN_REFRESH_RATE, MAX_REFRESH_RATE, rate
+        ))
+    }
+}
+
+/// Refresh rates sent to devices in display responses
+///
+/// Every refresh rate is clamped to [`MIN_REFRESH_RATE`] and
+/// [`MAX_REFRESH_RATE`], and shortened by a jitter derived from the device ID.
+/// Devices set up at the same time, e.g. after a power outage, would otherwise
+/// keep polling in lockstep. The jitter only makes devices poll early, so that
+/// they never sleep through a meeting boundary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RefreshRates {
+    /// Refresh rate in seconds of devices without a room calendar
+    pub default_rate: u32,
+    /// Largest share of the refresh rate, in percent, by which devices poll early
+    pub jitter_percent: u32,
+}
+
+impl Default for RefreshRates {
+    fn default() -> Self {
+        Self {
+            default_rate: 200,
+            jitter_percent: 10,
+        }
+    }
+}
+
+impl RefreshRates {
+    pub fn new(default_rate: u32, jitter_percent: u32) -> Result<Self, String> {
+        check_rate("Default refresh rate", default_rate)?;
+        if jitter_percent > MAX_REFRESH_JITTER_PERCENT {
+            return Err(format!(
+                "Refresh jitter must be at most {} percent, got {}",
+                MAX_REFRESH_JITTER_PERCENT, jitter_percent
+            ));
+        }
+        Ok(Self {
+            default_rate,
+            jitter_percent,
+        })
+    }
+
+    /// Clamps a refresh rate in seconds to the bounds sent to devices
+    pub fn clamp(rate: u32) -> u32 {
+        rate.clamp(MIN_REFRESH_RATE, MAX_REFRESH_RATE)
+    }
+
+    /// Refresh rate in seconds sent to the given device instead of `rate`
+    pub fn for_device(&self, device_id: &str, rate: u32) -> u32 {
+        let rate = Self::clamp(rate);
+        let max_jitter = u64::from(rate) * u64::from(self.jitter_percent) / 100;
+        if max_jitter == 0 {
+            return rate;
+        }
+        let mut hasher = DefaultHasher::new();
+        device_id.to_ascii_uppercase().hash(&mut hasher);
+        let jitter = hasher.finish() % (max_jitter + 1);
+        (rate - jitter as u32).max(MIN_REFRESH_RATE)
+    }
+}
+
+/// Policy deciding how long a device should sleep before polling again
+///
+/// Around meeting boundaries (start or end) the display should flip promptly,
+/// so the refresh rate is short. During a meeting and in free stretches it is
+/// longer, but never so long that the device sleeps through the next boundary.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RefreshPolicy {
+    /// Refresh rate in seconds close to a meeting start or end
+    pub boundary_rate: u32,
+    /// Refresh rate in seconds while a meeting is in progress
+    pub meeting_rate: u32,
+    /// Refresh rate in seconds while the room is free
+    pub free_rate: u32,
+    /// Minutes before and after a meeting boundary in which `boundary_rate` applies
+    pub boundary_window_minutes: i64,
+    /// Let devices sleep outside business hours, see [`RefreshPolicy::sleep_until`]
+    pub off_hours_sleep: bool,
+}
+
+impl Default for RefreshPolicy {
+    fn default() -> Self {
+        Self {
+            boundary_rate: 60,
+            meeting_rate: 900,
+            free_rate: 900,
+            boundary_window_minutes: 10,
+            off_hours_sleep: false,
+        }
+    }
+}
+
+impl RefreshPolicy {
+    /// Checks that the refresh rates are within the bounds sent to devices
+    pub fn validate(&self) -> Result<(), String> {
+        check_rate("boundary_rate", self.boundary_rate)?;
+        check_rate("meeting_rate", self.meeting_rate)?;
+        check_rate("free_rate", self.free_rate)
+    }
+
+    /// Compute the refresh rate (in seconds) for the given events at the given time
+    pub fn refresh_rate(&self, events: &[CalendarEvent], now: DateTime<Local>) -> u32 {
+        let window = Duration::minutes(self.boundary_window_minutes);
+        let boundaries = events.iter().flat_map(|e| [e.start_time, e.end_time]);
+
+        let mut next_window_start = None;
+        for boundary in boundaries {
+            if boundary - window <= now && now <= boundary + window {
+                return self.boundary_rate;
+            }
+            if boundary - window > now {
+                let start = boundary - window;
+                next_window_start =
+                    Some(next_window_start.map_or(start, |s: DateTime<Local>| s.min(start)));
+            }
+        }
+
+        let in_meeting = events
+            .iter()
+            .any(|e| now >= e.start_time && now < e.end_time);
+        let rate = if in_meeting {
+            self.meeting_rate
+        } else {
+            self.free_rate
+        };
+
+        // Wake up in time for the next boundary window
+        match next_window_start {
+            Some(start) => {
+                let until = (start - now).num_seconds().max(0) as u32;
+                rate.min(until.max(self.boundary_rate))
+            }
+            None => rate,
+        }
+    }
+
+    /// Time until which a device may sleep, if the room is in an off-hours window
+    ///
+    /// Outside business hours, firmware supporting scheduled wake-ups can skip
+    /// all polls until business hours start again, or until the boundary
+    /// window of an off-hours meeting or the `next_change` of the screen,
+    /// whichever comes first. Sleeping is only suggested if it saves at least
+    /// one regular poll.
+    pub fn sleep_until(
+        &self,
+        events: &[CalendarEvent],
+        now: DateTime<Local>,
+        hours: &BusinessHours,
+        week: &Week,
+        next_change: Option<DateTime<Local>>,
+    ) -> Option<DateTime<Local>> {
+        if !self.off_hours_sleep {
+            return None;
+        }
+        let business_start = next_business_start(now, hours, week)?;
+
+        let window = Duration::minutes(self.boundary_window_minutes);
+        let mut wake = business_start;
+        for boundary in events.iter().flat_map(|e| [e.start_time, e.end_time]) {
+            if boundary + window < now {
+                continue;
+            }
+            if boundary - window <= now {
+                return None;
+            }
+            wake = wake.min(boundary - window);
+        }
+        if events
+            .iter()
+            .any(|e| now >= e.start_time && now < e.end_time)
+        {
+            return None;
+        }
+        if let Some(change) = next_change {
+            wake = wake.min(change);
+        }
+
+        (wake - now > Duration::seconds(self.free_rate as i64)).then_some(wake)
+    }
+}
+
+/// Start of the next business hours after `now`, or None during business hours
+fn next_business_start(
+    now: DateTime<Local>,
+    hours: &BusinessHours,
+    week: &Week,
+) -> Option<DateTime<Local>> {
+    for offset in 0..=7 {
+        let date = now.date_naive() + Duration::days(offset);
+        if !hours.is_business_day(date.weekday(), week) {
+            continue;
+        }
+        // Business hours starting in a DST gap start at the first valid instant
+        let Some(start) = Local
+            .from_local_datetime(&date.and_time(hours.start))
+            .earliest()
+        else {
+            continue;
+        };
+        if now < start {
+            return Some(start);
+        }
+        if offset == 0 && now.time() < hours.end {
+            return None;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn event(start: (u32, u32), end: (u32, u32)) -> CalendarEvent {
+        CalendarEvent::new(
+            "Meeting".to_string(),
+            Local
+                .with_ymd_and_hms(2024, 3, 4, start.0, start.1, 0)
+                .unwrap(),
+            Local.with_ymd_and_hms(2024, 3, 4, end.0, end.1, 0).unwrap(),
+            None,
+            None,
+        )
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 3, 4, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_refresh_rates_for_device() {
+        let rates = RefreshRates::new(900, 10).unwrap();
+        assert_eq!(RefreshRates::clamp(1), MIN_REFRESH_RATE);
+        assert_eq!(RefreshRates::clamp(7 * 24 * 60 * 60), MAX_REFRESH_RATE);
+
+        // Devices poll up to 10% early, each by its own but stable jitter
+        let a = rates.for_device("AA:BB:CC:DD:EE:FF", 900);
+        assert!((810..=900).contains(&a), "{}", a);
+        assert_eq!(rates.for_device("aa:bb:cc:dd:ee:ff", 900), a);
+        let jittered: std::collections::HashSet<_> = (0..20)
+            .map(|i| rates.for_device(&format!("AA:BB:CC:DD:EE:{:02X}", i), 900))
+            .collect();
+        assert!(jittered.len() > 1);
+        assert_eq!(rates.for_device("AA:BB:CC:DD:EE:FF", 1), MIN_REFRESH_RATE);
+
+        let exact = RefreshRates::new(900, 0).unwrap();
+        assert_eq!(exact.for_device("AA:BB:CC:DD:EE:FF", 900), 900);
+
+        assert!(RefreshRates::new(5, 10).is_err());
+        assert!(RefreshRates::new(900, 80).is_err());
+        assert!(
+            RefreshPolicy {
+                boundary_rate: 0,
+                ..RefreshPolicy::default()
+            }
+            .validate()
+            .is_err()
+        );
+        assert!(RefreshPolicy::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_refresh_rate_around_boundaries() {
+        let policy = RefreshPolicy::default();
+        let events = vec![event((10, 0), (12, 0))];
+
+        // Shortly before start and shortly after end
+        assert_eq!(policy.refresh_rate(&events, at(9, 55)), 60);
+        assert_eq!(policy.refresh_rate(&events, at(12, 8)), 60);
+
+        // Mid-meeting
+        assert_eq!(policy.refresh_rate(&events, at(11, 0)), 900);
+
+        // Free afterwards, no further meetings
+        assert_eq!(policy.refresh_rate(&events, at(14, 0)), 900);
+    }
+
+    #[test]
+    fn test_sleep_until_off_hours() {
+        let policy = RefreshPolicy {
+            off_hours_sleep: true,
+            ..RefreshPolicy::default()
+        };
+        let hours = BusinessHours::default();
+        let week = Week::default();
+        let events = vec![event((10, 0), (11, 0))];
+        let sleep_until = |now| policy.sleep_until(&events, now, &hours, &week, None);
+        let tuesday_morning = Local.with_ymd_and_hms(2024, 3, 5, 8, 0, 0).unwrap();
+
+        // 2024-03-04 is a Monday
+        assert_eq!(sleep_until(at(20, 0)), Some(tuesday_morning));
+        assert_eq!(sleep_until(at(6, 0)), Some(at(8, 0)));
+        assert_eq!(sleep_until(at(9, 0)), None);
+
+        // Not worth sleeping, the next regular poll is after business hours start
+        assert_eq!(sleep_until(at(7, 50)), None);
+
+        // Friday evening sleeps through the weekend
+        let friday_evening = Local.with_ymd_and_hms(2024, 3, 8, 19, 0, 0).unwrap();
+        assert_eq!(
+            sleep_until(friday_evening),
+            Some(Local.with_ymd_and_hms(2024, 3, 11, 8, 0, 0).unwrap())
+        );
+
+        // Off-hours meetings and state changes wake the device earlier
+        let evening = vec![event((21, 0), (22, 0))];
+        assert_eq!(
+            policy.sleep_until(&evening, at(19, 0), &hours, &week, None),
+            Some(at(20, 50))
+        );
+        assert_eq!(
+            policy.sleep_until(&evening, at(21, 30), &hours, &week, None),
+            None
+        );
+        assert_eq!(
+            policy.sleep_until(&events, at(20, 0), &hours, &week, Some(at(23, 0))),
+            Some(at(23, 0))
+        );
+
+        // Disabled by default
+        assert_eq!(
+            RefreshPolicy::default().sleep_until(&events, at(20, 0), &hours, &week, None),
+            None
+        );
+    }
+
+    #[test]
+    fn test_refresh_rate_does_not_sleep_through_boundary() {
+        let policy = RefreshPolicy::default();
+        let events = vec![event((10, 0), (11, 0))];
+
+        // Boundary window opens at 09:50, five minutes from now
+        assert_eq!(policy.refresh_rate(&events, at(9, 45)), 300);
+    }
+}
@@ -0,0 +1,281 @@
+//! Rendering of display images, in-process or offloaded to a render service
+//!
+//! Large fleets can delegate rendering to an external service: the
+//! [`ImageConfig`] of every frame is POSTed to it as JSON, and it responds with
+//! the encoded image. Everything else, e.g. pre-rendering, change detection and
+//! delivery, stays in this server. How the blocks of a room screen are
+//! arranged is decided by its [`layout`].
+//!
+//! Either renderer can be wrapped in a [`CachingRenderer`], so that devices of
+//! a room polling at about the same time get the frame rendered once.
+
+pub mod layout;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use tracing::Span;
+
+use crate::bmp::{BmpError, ImageConfig, Renderer};
+use crate::metrics::Metrics;
+
+/// Timeout of requests to an external renderer
+const HTTP_RENDER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Renderer of display images
+#[async_trait]
+pub trait ImageRenderer: Send + Sync {
+    /// Render the image described by the configuration, as a monochrome BMP
+    async fn render(&self, config: ImageConfig) -> Result<Vec<u8>>;
+
+    /// Drop cached images, if the renderer caches any
+    fn clear_cache(&self) {}
+}
+
+/// Renderer backend selection
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RendererConfig {
+    /// Render in this process
+    Local,
+    /// POST the image configuration to an external render service
+    Http { url: String },
+}
+
+/// Renderer running on the blocking thread pool of this process
+#[derive(Default)]
+pub struct LocalRenderer {
+    renderer: Arc<Renderer>,
+}
+
+impl LocalRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ImageRenderer for LocalRenderer {
+    async fn render(&self, config: ImageConfig) -> Result<Vec<u8>> {
+        let renderer = self.renderer.clone();
+        // The blocking thread does not inherit the span of the request
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || span.in_scope(|| renderer.render(&config)))
+            .await
+            .context("Render task failed")?
+    }
+}
+
+/// Renderer delegating to an external render service
+///
+/// The service receives the [`ImageConfig`] as JSON and must respond with the
+/// encoded image as body of a `2xx` response. Font and watermark paths are
+/// passed on as configured, so they must be valid for the service.
+pub struct HttpRenderer {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl HttpRenderer {
+    /// Create a renderer posting to the given URL
+    pub fn new(url: String) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(HTTP_RENDER_TIMEOUT)
+            .build()
+            .context("Failed to create renderer HTTP client")?;
+        Ok(Self { url, client })
+    }
+}
+
+#[async_trait]
+impl ImageRenderer for HttpRenderer {
+    async fn render(&self, config: ImageConfig) -> Result<Vec<u8>> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&config)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| BmpError::Remote(format!("{}: {}", self.url, e)))?;
+        let image = response
+            .bytes()
+            .await
+            .map_err(|e| BmpError::Remote(format!("{}: {}", self.url, e)))?;
+        if image.is_empty() {
+            return Err(BmpError::Remote(format!("{}: Empty image", self.url)).into());
+        }
+        Ok(image.to_vec())
+    }
+}
+
+/// Renderer keeping the images it rendered for a while
+///
+/// Images are kept by [`ImageConfig::fingerprint`], which covers the room, the
+/// layout and everything shown, e.g. the current time, for `ttl` after they
+/// were rendered.
+pub struct CachingRenderer {
+    inner: Arc<dyn ImageRenderer>,
+    ttl: Duration,
+    /// Rendered images by fingerprint, with the time they were rendered
+    images: Mutex<HashMap<u64, (Vec<u8>, Instant)>>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl CachingRenderer {
+    pub fn new(inner: Arc<dyn ImageRenderer>, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            images: Mutex::new(HashMap::new()),
+            metrics: None,
+        }
+    }
+
+    /// Records whether images were taken from the cache
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+}
+
+#[async_trait]
+impl ImageRenderer for CachingRenderer {
+    async fn render(&self, config: ImageConfig) -> Result<Vec<u8>> {
+        let fingerprint = config.fingerprint();
+        let cached = self.images.lock().ok().and_then(|images| {
+            images
+                .get(&fingerprint)
+                .filter(|(_, rendered_at)| rendered_at.elapsed() < self.ttl)
+                .map(|(image, _)| image.clone())
+        });
+        if let Some(metrics) = &self.metrics {
+            metrics.record_render_cache(cached.is_some());
+        }
+        if let Some(image) = cached {
+            return Ok(image);
+        }
+
+        let image = self.inner.render(config).await?;
+        if let Ok(mut images) = self.images.lock() {
+            images.retain(|_, (_, rendered_at)| rendered_at.elapsed() < self.ttl);
+            images.insert(fingerprint, (image.clone(), Instant::now()));
+        }
+        Ok(image)
+    }
+
+    fn clear_cache(&self) {
+        if let Ok(mut images) = self.images.lock() {
+            images.clear();
+        }
+    }
+}
+
+/// Create the renderer for the given configuration
+pub fn create_renderer(config: &RendererConfig) -> Result<Arc<dyn ImageRenderer>> {
+    match config {
+        RendererConfig::Local => Ok(Arc::new(LocalRenderer::new())),
+        RendererConfig::Http { url } => Ok(Arc::new(HttpRenderer::new(url.clone())?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{Json, Router, http::StatusCode, routing::post};
+
+    use super::*;
+    use crate::bmp::generate_bmp;
+    use crate::error_code::ErrorCode;
+
+    /// Serve a stand-in render service, returning its URL
+    async fn serve(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/render", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        url
+    }
+
+    fn image_config() -> ImageConfig {
+        ImageConfig {
+            text: "Room A\nFree".to_string(),
+            ..ImageConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http_renderer() {
+        // Render service built on this crate, rendering what it receives
+        let url = serve(Router::new().route(
+            "/render",
+            post(|Json(config): Json<ImageConfig>| async move { generate_bmp(&config).unwrap() }),
+        ))
+        .await;
+
+        let renderer = create_renderer(&RendererConfig::Http { url }).unwrap();
+        let image = renderer.render(image_config()).await.unwrap();
+        assert_eq!(image, generate_bmp(&image_config()).unwrap());
+        let local = create_renderer(&RendererConfig::Local).unwrap();
+        assert_eq!(local.render(image_config()).await.unwrap(), image);
+    }
+
+    /// Renderer counting its renders
+    #[derive(Default)]
+    struct CountingRenderer(std::sync::atomic::AtomicUsize);
+
+    #[async_trait]
+    impl ImageRenderer for CountingRenderer {
+        async fn render(&self, _config: ImageConfig) -> Result<Vec<u8>> {
+            let count = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(vec![count as u8])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_caching_renderer() {
+        let inner = Arc::new(CountingRenderer::default());
+        let metrics = Arc::new(Metrics::new());
+        let renderer = CachingRenderer::new(inner.clone(), Duration::from_millis(200))
+            .with_metrics(metrics.clone());
+
+        assert_eq!(renderer.render(image_config()).await.unwrap(), [0]);
+        assert_eq!(renderer.render(image_config()).await.unwrap(), [0]);
+        let other = ImageConfig {
+            text: "Room B\nFree".to_string(),
+            ..ImageConfig::default()
+        };
+        assert_eq!(renderer.render(other).await.unwrap(), [1]);
+        assert!(
+            metrics
+                .render()
+                .contains("trmnl_render_cache_total{result=\"hit\"} 1\n")
+        );
+
+        // Expired images are rendered again
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(renderer.render(image_config()).await.unwrap(), [2]);
+
+        // As are all images after clearing the cache
+        renderer.clear_cache();
+        assert_eq!(renderer.render(image_config()).await.unwrap(), [3]);
+    }
+
+    #[tokio::test]
+    async fn test_http_renderer_failure() {
+        let url = serve(Router::new().route(
+            "/render",
+            post(|| async { (StatusCode::SERVICE_UNAVAILABLE, "busy") }),
+        ))
+        .await;
+
+        let error = HttpRenderer::new(url)
+            .unwrap()
+            .render(image_config())
+            .await
+            .unwrap_err();
+        assert_eq!(ErrorCode::of(&error), Some(ErrorCode::RenderFailed));
+    }
+}
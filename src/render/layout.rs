@@ -0,0 +1,190 @@
+//! Layouts of the display image
+//!
+//! A layout decides which blocks of a room screen are shown and how the
+//! height of the image is divided between them. Header and banner are shared
+//! by all layouts and take the top of the image; the layout arranges the
+//! blocks below them:
+//!
+//! - `agenda`: the list of meetings under a heading with the room status,
+//!   followed by the footer
+//! - `focus`: the meeting in progress (or the room being free) in large text,
+//!   the next meetings below it and a footer with clock and battery level,
+//!   e.g. for displays next to the door that are read from a distance
+//! - `days`: the meetings of the next days, under a heading per day, e.g. for
+//!   rooms with only a few meetings a week
+//! - `overview`: the status of the other rooms, under a heading per floor and
+//!   zone, e.g. for lobby displays
+//!
+//! Rooms can additionally show their status in large text, as an inverted
+//! band between the banner and the blocks of the layout.
+
+use serde::{Deserialize, Serialize};
+
+/// Share of the height below the header and banner taken by the current
+/// meeting in the focus layout, in percent
+const FOCUS_CURRENT_PERCENT: i32 = 55;
+
+/// Arrangement of the blocks of a room screen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    /// List of meetings with the room status in its heading
+    #[default]
+    Agenda,
+    /// Large current meeting, next meetings and a status bar
+    Focus,
+    /// Meetings of the next days, under a heading per day
+    Days,
+    /// Status of the other rooms, under a heading per floor
+    Overview,
+}
+
+impl Layout {
+    /// Name of the layout, as used in the configuration
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Layout::Agenda => "agenda",
+            Layout::Focus => "focus",
+            Layout::Days => "days",
+            Layout::Overview => "overview",
+        }
+    }
+
+    /// Vertical bands of the blocks between `top`, i.e. the bottom of header
+    /// and banner, and the footer of the given height
+    pub fn bands(&self, top: i32, height: i32, footer_height: i32) -> Bands {
+        let footer = Band {
+            top: (height - footer_height).max(top),
+            bottom: height,
+        };
+        match self {
+            Layout::Agenda | Layout::Days | Layout::Overview => Bands {
+                current: None,
+                list: Band {
+                    top,
+                    bottom: footer.top,
+                },
+                footer,
+            },
+            Layout::Focus => {
+                let split = top + (footer.top - top) * FOCUS_CURRENT_PERCENT / 100;
+                Bands {
+                    current: Some(Band { top, bottom: split }),
+                    list: Band {
+                        top: split,
+                        bottom: footer.top,
+                    },
+                    footer,
+                }
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "agenda" => Ok(Layout::Agenda),
+            "focus" => Ok(Layout::Focus),
+            "days" => Ok(Layout::Days),
+            "overview" => Ok(Layout::Overview),
+            _ => Err(format!(
+                "Unknown layout: {} (expected agenda, focus, days or overview)",
+                s
+            )),
+        }
+    }
+}
+
+/// Vertical extent of a block, in pixels from the top of the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Band {
+    pub top: i32,
+    pub bottom: i32,
+}
+
+impl Band {
+    pub fn height(&self) -> i32 {
+        self.bottom - self.top
+    }
+}
+
+/// Bands of the blocks of a layout, top to bottom
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bands {
+    /// Current meeting, if the layout shows it separately
+    pub current: Option<Band>,
+    /// List of meetings
+    pub list: Band,
+    /// Footer with badges, footer text and status bar
+    pub footer: Band,
+}
+
+/// Meeting in progress, or the room being free, as shown by the focus layout
+#[derive(Hash, Serialize, Deserialize)]
+pub struct CurrentMeeting {
+    /// Room status, e.g. `BUSY until 11:00`
+    pub status: String,
+    /// Name of the meeting in progress
+    pub title: Option<String>,
+}
+
+/// Clock and battery level, shown centered in the footer by the focus layout
+#[derive(Hash, Serialize, Deserialize)]
+pub struct StatusBar {
+    /// Time of rendering, e.g. `14:05`
+    pub clock: String,
+    /// Battery level of the device, in percent
+    pub battery_percent: Option<u8>,
+}
+
+impl StatusBar {
+    /// Text of the status bar, e.g. `14:05 | 83%`
+    pub fn text(&self) -> String {
+        match self.battery_percent {
+            Some(percent) => format!("{} | {}%", self.clock, percent),
+            None => self.clock.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_bands() {
+        let bands = Layout::Agenda.bands(60, 480, 50);
+        assert_eq!(bands.current, None);
+        assert_eq!((bands.list.top, bands.list.bottom), (60, 430));
+        assert_eq!((bands.footer.top, bands.footer.bottom), (430, 480));
+
+        let bands = Layout::Focus.bands(60, 480, 50);
+        let current = bands.current.unwrap();
+        assert_eq!(current.top, 60);
+        assert_eq!(current.bottom, bands.list.top);
+        assert_eq!(bands.list.bottom, 430);
+        assert!(current.height() > 100);
+
+        // Headers taller than the image leave empty bands rather than negative ones
+        let bands = Layout::Focus.bands(500, 480, 50);
+        assert_eq!(bands.list.height(), 0);
+    }
+
+    #[test]
+    fn test_parse_layout() {
+        assert_eq!("Focus".parse::<Layout>().unwrap(), Layout::Focus);
+        assert_eq!(" agenda".parse::<Layout>().unwrap(), Layout::Agenda);
+        assert_eq!("days".parse::<Layout>().unwrap(), Layout::Days);
+        assert_eq!("overview".parse::<Layout>().unwrap(), Layout::Overview);
+        assert!("grid".parse::<Layout>().is_err());
+
+        let status = StatusBar {
+            clock: "14:05".to_string(),
+            battery_percent: Some(83),
+        };
+        assert_eq!(status.text(), "14:05 | 83%");
+    }
+}
@@ -0,0 +1,192 @@
+use chrono::{DateTime, Duration, Local, Offset, TimeZone, Utc};
+use log::{debug, info};
+
+use crate::error_report::report_task_failure;
+use crate::rooms::Room;
+use crate::server::AppState;
+
+/// Next instant after `now` at which the local date or the UTC offset changes
+///
+/// Date-dependent data ("today's" meetings) must be recomputed at local
+/// midnight, and rendered times at DST transitions.
+pub fn next_rollover<Tz: TimeZone>(now: &DateTime<Tz>) -> DateTime<Tz> {
+    let tz = now.timezone();
+
+    // Next local midnight. In zones that switch DST at midnight, the local
+    // midnight may not exist, so fall back to the first valid instant after it.
+    let tomorrow = now
+        .date_naive()
+        .succ_opt()
+        .expect("date out of range")
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    let midnight = (0..4)
+        .find_map(|hour| {
+            tz.from_local_datetime(&(tomorrow + Duration::hours(hour)))
+                .earliest()
+        })
+        .unwrap_or_else(|| now.clone() + Duration::hours(24));
+
+    // Offset transition before midnight, found by bisection
+    let offset_at = |t: &DateTime<Tz>| t.offset().fix();
+    if offset_at(now) == offset_at(&midnight) {
+        return midnight;
+    }
+    let (mut low, mut high) = (now.clone(), midnight);
+    while high.clone() - low.clone() > Duration::seconds(1) {
+        let mid = low.clone() + Duration::seconds((high.clone() - low.clone()).num_seconds() / 2);
+        if offset_at(&mid) == offset_at(now) {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    high
+}
+
+/// Longest wait between two checks for due rollovers, so that rooms added or
+/// moved to another time zone in the meantime are picked up
+const MAX_ROLLOVER_WAIT: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Next rollover of a room, in its time zone or else the server's
+pub fn next_room_rollover(room: &Room, now: DateTime<Utc>) -> DateTime<Utc> {
+    match room.time_zone {
+        Some(tz) => next_rollover(&now.with_timezone(&tz)).with_timezone(&Utc),
+        None => next_rollover(&now.with_timezone(&Local)).with_timezone(&Utc),
+    }
+}
+
+/// Background task that invalidates the cached data of each room at its
+/// rollover
+///
+/// The room's calendar is re-fetched and its frame rendered anew on the next
+/// display request, so that the date change is reflected even if the regular
+/// refresh interval has not elapsed. Every instance drops its in-memory data,
+/// but only the instance holding the room's rollover lease clears the calendar
+/// data shared via the database.
+pub async fn run_rollover_task(state: AppState) {
+    loop {
+        let started = Utc::now();
+        let rooms = state.config.rooms.snapshot();
+        let wait = rooms
+            .iter()
+            .map(|room| next_room_rollover(room, started))
+            .min()
+            .map_or(MAX_ROLLOVER_WAIT, |next| {
+                (next - started)
+                    .to_std()
+                    .unwrap_or_default()
+                    .min(MAX_ROLLOVER_WAIT)
+            });
+        tokio::time::sleep(wait + std::time::Duration::from_secs(1)).await;
+
+        let now = Utc::now();
+        for room in rooms
+            .iter()
+            .filter(|room| next_room_rollover(room, started) <= now)
+        {
+            roll_over(&state, room).await;
+        }
+    }
+}
+
+/// Drop the cached calendar and rendered images of a room
+async fn roll_over(state: &AppState, room: &Room) {
+    let lease = format!("rollover:{}", room.id);
+    let holder = state.config.instance_id.clone();
+    match state
+        .database
+        .run_blocking(move |db| db.try_acquire_lease(&lease, &holder, 60))
+        .await
+    {
+        Ok(true) => {
+            let urls = room
+                .calendar_url
+                .iter()
+                .chain(room.fallback_calendar.iter().map(|fallback| &fallback.url));
+            for url in urls {
+                let url = url.clone();
+                if let Err(e) = state
+                    .database
+                    .run_blocking(move |db| db.clear_cached_calendar(&url))
+                    .await
+                {
+                    report_task_failure(
+                        state.errors.as_ref(),
+                        "rollover",
+                        format!("Failed to clear shared calendar cache: {:#}", e),
+                    );
+                }
+            }
+        }
+        Ok(false) => debug!(
+            "Rollover lease of room {} held by another instance",
+            room.id
+        ),
+        Err(e) => report_task_failure(
+            state.errors.as_ref(),
+            "rollover",
+            format!("Failed to acquire rollover lease: {:#}", e),
+        ),
+    }
+
+    info!("Day rollover of room {}, invalidating cached data", room.id);
+    state.calendars.invalidate_room(&room.id);
+    state.frames.remove_room(&room.id);
+    state.renderer.clear_cache();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Europe::Zurich;
+
+    #[test]
+    fn test_next_rollover_midnight() {
+        let now = Zurich.with_ymd_and_hms(2024, 3, 4, 18, 30, 0).unwrap();
+        assert_eq!(
+            next_rollover(&now),
+            Zurich.with_ymd_and_hms(2024, 3, 5, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_next_rollover_dst_transition() {
+        // Clocks go forward at 02:00 local time on 2024-03-31
+        let now = Zurich.with_ymd_and_hms(2024, 3, 31, 0, 30, 0).unwrap();
+        let next = next_rollover(&now);
+        assert_eq!(next, Zurich.with_ymd_and_hms(2024, 3, 31, 3, 0, 0).unwrap());
+
+        // And back at 03:00 local time on 2024-10-27
+        let now = Zurich.with_ymd_and_hms(2024, 10, 27, 1, 0, 0).unwrap();
+        let next = next_rollover(&now);
+        assert_eq!(next.naive_utc().to_string(), "2024-10-27 01:00:00");
+    }
+
+    #[test]
+    fn test_next_room_rollover() {
+        let rooms = crate::rooms::parse_rooms(
+            r#"
+            [[rooms]]
+            id = "zurich"
+            name = "Zurich"
+            time_zone = "Europe/Zurich"
+
+            [[rooms]]
+            id = "new-york"
+            name = "New York"
+            time_zone = "America/New_York"
+            "#,
+        )
+        .unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 3, 4, 20, 0, 0).unwrap();
+        assert_eq!(
+            next_room_rollover(&rooms[0], now),
+            Utc.with_ymd_and_hms(2024, 3, 4, 23, 0, 0).unwrap()
+        );
+        assert_eq!(
+            next_room_rollover(&rooms[1], now),
+            Utc.with_ymd_and_hms(2024, 3, 5, 5, 0, 0).unwrap()
+        );
+    }
+}
@@ -0,0 +1,821 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, Result};
+use chrono_tz::Tz;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::agenda::BusinessHours;
+use crate::bmp::ItemStyle;
+use crate::calendar::{CalendarEvent, CalendarFallback, CalendarSource, Deduplication};
+use crate::device_id::DeviceId;
+use crate::experiments::{Experiment, Variant};
+use crate::labels::{DEFAULT_LANGUAGE, LabelPack};
+use crate::refresh::RefreshPolicy;
+use crate::render::layout::Layout;
+use crate::week::Week;
+
+/// A meeting room and the display devices installed in it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Room {
+    /// Unique room identifier (used in URLs and labels)
+    pub id: String,
+
+    /// Human-readable room name
+    pub name: String,
+
+    /// Optional iCal URL of the room's calendar, or URL of its CalDAV collection
+    #[serde(default)]
+    pub calendar_url: Option<String>,
+
+    /// How the room's calendar is fetched from `calendar_url`
+    #[serde(default)]
+    pub calendar_source: CalendarSource,
+
+    /// Calendar used while the room's calendar cannot be fetched, e.g. the
+    /// published ICS feed of a calendar fetched through the Graph API
+    #[serde(default)]
+    pub fallback_calendar: Option<CalendarFallback>,
+
+    /// How duplicate events in the room's calendar are detected
+    #[serde(default)]
+    pub deduplicate: Deduplication,
+
+    /// IDs (MAC addresses) of the devices assigned to this room
+    #[serde(default)]
+    pub devices: Vec<String>,
+
+    /// Whether to show the latest open issue report on the room's displays
+    #[serde(default)]
+    pub show_issue_badge: bool,
+
+    /// Minutes before the end of a meeting in which a warning banner is shown (0 disables it)
+    #[serde(default = "default_end_warning_minutes")]
+    pub end_warning_minutes: i64,
+
+    /// Refresh rate policy for the room's devices
+    #[serde(default)]
+    pub refresh: RefreshPolicy,
+
+    /// Name shown in the display header, if different from `name`
+    #[serde(default)]
+    pub display_name: Option<String>,
+
+    /// Floor the room is on, e.g. `3` or `Ground floor`
+    #[serde(default)]
+    pub floor: Option<String>,
+
+    /// Zone of the floor the room is in, e.g. `North wing`
+    #[serde(default)]
+    pub zone: Option<String>,
+
+    /// Number of seats in the room
+    #[serde(default)]
+    pub capacity: Option<u32>,
+
+    /// Equipment available in the room, shown as badges in the display header
+    #[serde(default)]
+    pub equipment: Vec<Equipment>,
+
+    /// Custom text shown in the display header, after floor and capacity
+    #[serde(default)]
+    pub header_text: Option<String>,
+
+    /// Custom text shown in the bottom right corner of the display
+    #[serde(default)]
+    pub footer_text: Option<String>,
+
+    /// Business hours, used to pick the meetings shown after the last one of the day
+    #[serde(default)]
+    pub business_hours: BusinessHours,
+
+    /// Background elements of the room's displays
+    #[serde(default)]
+    pub background: BackgroundSettings,
+
+    /// First day of the week and weekend days at the room's site
+    #[serde(default)]
+    pub week: Week,
+
+    /// Time zone of the room's site, e.g. `Europe/Zurich`, in which its days
+    /// start; the time zone of the server if not set
+    #[serde(default, with = "time_zone", skip_serializing_if = "Option::is_none")]
+    pub time_zone: Option<Tz>,
+
+    /// Language of the labels on the room's displays, e.g. `de` or `fr-CH`
+    #[serde(default = "default_language")]
+    pub language: String,
+
+    /// Whether meeting titles are published in the room's schedule feed,
+    /// otherwise only the times are (titles of private meetings never are)
+    #[serde(default)]
+    pub public_titles: bool,
+
+    /// Visual treatment of agenda lines by event category or color, e.g. `maintenance = "hatched"`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub category_styles: BTreeMap<String, ItemStyle>,
+
+    /// Layout of the room's displays
+    #[serde(default)]
+    pub layout: Layout,
+
+    /// Whether to show the room status in large text below the header, e.g.
+    /// `BUSY until 14:30` or `FREE for 45 min`
+    #[serde(default)]
+    pub status_indicator: bool,
+
+    /// Days, including today, whose meetings the `days` layout shows
+    #[serde(default = "default_upcoming_days")]
+    pub upcoming_days: u32,
+
+    /// Maximum number of meetings the `days` layout shows
+    #[serde(default = "default_upcoming_events")]
+    pub upcoming_events: usize,
+
+    /// Layout of individual devices, by device ID, e.g. `focus` for a display at the door
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub device_layouts: BTreeMap<String, Layout>,
+
+    /// Floor whose rooms the `overview` layout shows, e.g. for one lobby
+    /// display per floor; rooms of all floors if not set
+    #[serde(default)]
+    pub overview_floor: Option<String>,
+}
+
+/// Background elements of a room's displays
+///
+/// They make free and busy screens distinguishable even from an angle at
+/// which the text is not readable.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackgroundSettings {
+    /// Hatch the background while a meeting is in progress
+    pub busy_hatch: bool,
+    /// Image shown as a light watermark, e.g. the company logo
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub watermark: Option<String>,
+}
+
+fn default_end_warning_minutes() -> i64 {
+    5
+}
+
+fn default_upcoming_days() -> u32 {
+    3
+}
+
+fn default_upcoming_events() -> usize {
+    6
+}
+
+fn default_language() -> String {
+    DEFAULT_LANGUAGE.to_string()
+}
+
+/// Time zones by their IANA name
+mod time_zone {
+    use chrono_tz::Tz;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(tz: &Option<Tz>, serializer: S) -> Result<S::Ok, S::Error> {
+        match tz {
+            Some(tz) => serializer.serialize_str(tz.name()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Tz>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|name| {
+                name.parse()
+                    .map_err(|_| D::Error::custom(format!("unknown time zone {}", name)))
+            })
+            .transpose()
+    }
+}
+
+impl Room {
+    /// Title of the display header
+    pub fn header_title(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.name)
+    }
+
+    /// Details shown next to the header title: floor, capacity and custom text
+    pub fn header_details(&self, labels: &LabelPack) -> Option<String> {
+        let floor = self.floor_label(labels);
+        let capacity = self
+            .capacity
+            .map(|capacity| labels.format("header-seats", &[("count", capacity.into())]));
+        let details: Vec<String> = [floor, capacity, self.header_text.clone()]
+            .into_iter()
+            .flatten()
+            .collect();
+        (!details.is_empty()).then(|| details.join(" | "))
+    }
+
+    /// Floor of the room as shown on displays, e.g. `Floor 3` for `3`
+    pub fn floor_label(&self, labels: &LabelPack) -> Option<String> {
+        self.floor.as_ref().map(|floor| {
+            if floor.chars().all(|c| c.is_ascii_digit()) {
+                labels.format("header-floor", &[("floor", floor.as_str().into())])
+            } else {
+                floor.clone()
+            }
+        })
+    }
+
+    /// Floor and zone of the room, e.g. `Floor 3 | North wing`
+    pub fn location(&self, labels: &LabelPack) -> Option<String> {
+        let parts: Vec<String> = [self.floor_label(labels), self.zone.clone()]
+            .into_iter()
+            .flatten()
+            .collect();
+        (!parts.is_empty()).then(|| parts.join(" | "))
+    }
+
+    /// Whether the room is on the given floor, ignoring case and surrounding
+    /// whitespace
+    pub fn on_floor(&self, floor: &str) -> bool {
+        self.floor
+            .as_deref()
+            .is_some_and(|own| own.trim().eq_ignore_ascii_case(floor.trim()))
+    }
+
+    /// Whether the room's name or display name contains the given text,
+    /// ignoring case
+    pub fn matches_search(&self, query: &str) -> bool {
+        let query = query.trim().to_lowercase();
+        [Some(&self.name), self.display_name.as_ref()]
+            .into_iter()
+            .flatten()
+            .any(|name| name.to_lowercase().contains(&query))
+    }
+
+    /// Visual treatment of an event in the agenda
+    ///
+    /// The first of the event's categories (or its color) with a configured
+    /// style wins. Categories are matched case-insensitively.
+    pub fn event_style(&self, event: &CalendarEvent) -> ItemStyle {
+        event
+            .tags()
+            .find_map(|tag| {
+                self.category_styles
+                    .iter()
+                    .find(|(category, _)| category.eq_ignore_ascii_case(tag))
+                    .map(|(_, style)| *style)
+            })
+            .unwrap_or_default()
+    }
+
+    /// Checks the sources of the room's calendar and fallback calendar
+    pub fn validate_calendars(&self) -> Result<(), String> {
+        self.calendar_source.validate()?;
+        if let Some(fallback) = &self.fallback_calendar {
+            fallback.source.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Normalize the IDs of the devices of this room, see [`DeviceId`]
+    ///
+    /// IDs that are neither a MAC address nor a UUID are kept as they are.
+    pub fn normalize_device_ids(&mut self) {
+        for id in &mut self.devices {
+            *id = DeviceId::lenient(id).into_string();
+        }
+        self.device_layouts = std::mem::take(&mut self.device_layouts)
+            .into_iter()
+            .map(|(id, layout)| (DeviceId::lenient(&id).into_string(), layout))
+            .collect();
+    }
+
+    /// Whether a layout is set for the given device of this room
+    pub fn has_device_layout(&self, device_id: &str) -> bool {
+        self.device_layouts
+            .keys()
+            .any(|id| id.eq_ignore_ascii_case(device_id))
+    }
+
+    /// Layout of the given device of this room
+    pub fn layout_for(&self, device_id: &str) -> Layout {
+        self.device_layouts
+            .iter()
+            .find(|(id, _)| id.eq_ignore_ascii_case(device_id))
+            .map_or(self.layout, |(_, layout)| *layout)
+    }
+
+    /// Layout of the given device of this room, with the experiment and
+    /// variant that decided it, if any
+    ///
+    /// The first experiment applying to the device wins. Layouts set for the
+    /// device take precedence over experiments.
+    pub fn layout_with_experiments<'a>(
+        &self,
+        experiments: &'a [Experiment],
+        device_id: &str,
+    ) -> (Layout, Option<(&'a Experiment, Variant)>) {
+        if self.has_device_layout(device_id) {
+            return (self.layout_for(device_id), None);
+        }
+        let assignment = experiments.iter().find_map(|experiment| {
+            experiment
+                .variant(&self.id, device_id)
+                .map(|variant| (experiment, variant))
+        });
+        match assignment {
+            Some((experiment, Variant::Variant)) => {
+                (experiment.layout, Some((experiment, Variant::Variant)))
+            }
+            _ => (self.layout, assignment),
+        }
+    }
+
+    /// Returns true if the given device is assigned to this room
+    pub fn has_device(&self, device_id: &str) -> bool {
+        self.devices
+            .iter()
+            .any(|d| d.eq_ignore_ascii_case(device_id))
+    }
+}
+
+/// Equipment a room can have
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Equipment {
+    /// TV or projector for presentations
+    Tv,
+    /// Whiteboard
+    Whiteboard,
+    /// Video conferencing system
+    #[serde(alias = "video_conferencing")]
+    Vc,
+}
+
+impl Equipment {
+    /// Short label shown in the display header badge
+    pub fn badge(&self) -> &'static str {
+        match self {
+            Equipment::Tv => "TV",
+            Equipment::Whiteboard => "WB",
+            Equipment::Vc => "VC",
+        }
+    }
+}
+
+impl std::str::FromStr for Equipment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "tv" => Ok(Equipment::Tv),
+            "whiteboard" => Ok(Equipment::Whiteboard),
+            "vc" | "video_conferencing" => Ok(Equipment::Vc),
+            other => Err(format!("Unknown equipment: {}", other)),
+        }
+    }
+}
+
+/// Structure of the rooms file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RoomsFile {
+    #[serde(default)]
+    rooms: Vec<Room>,
+}
+
+/// Parse room definitions from TOML
+pub fn parse_rooms(toml_str: &str) -> Result<Vec<Room>> {
+    let mut file: RoomsFile = toml::from_str(toml_str).context("Failed to parse rooms file")?;
+    for room in &mut file.rooms {
+        room.normalize_device_ids();
+        room.refresh
+            .validate()
+            .map_err(|e| anyhow::anyhow!("Invalid refresh policy of room {}: {}", room.id, e))?;
+        room.validate_calendars()
+            .map_err(|e| anyhow::anyhow!("Invalid calendar of room {}: {}", room.id, e))?;
+    }
+    Ok(file.rooms)
+}
+
+/// Load room definitions from the given TOML file
+///
+/// A missing file is not an error, it simply means that no rooms are configured.
+pub fn load_rooms(path: &str) -> Result<Vec<Room>> {
+    if !Path::new(path).exists() {
+        info!("No rooms file found at {}, no rooms configured", path);
+        return Ok(Vec::new());
+    }
+
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read rooms file {}", path))?;
+    parse_rooms(&content).with_context(|| format!("Invalid rooms file {}", path))
+}
+
+/// Serialize room definitions to TOML, in the format of the rooms file
+pub fn rooms_to_toml(rooms: &[Room]) -> Result<String> {
+    let file = RoomsFile {
+        rooms: rooms.to_vec(),
+    };
+    toml::to_string_pretty(&file).context("Failed to serialize rooms")
+}
+
+/// Room definitions that can be replaced at runtime
+///
+/// Readers take a cheap snapshot, so a concurrent update never changes the
+/// rooms in the middle of a request.
+#[derive(Debug, Clone, Default)]
+pub struct SharedRooms(Arc<RwLock<Arc<Vec<Room>>>>);
+
+impl SharedRooms {
+    pub fn new(rooms: Vec<Room>) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(rooms))))
+    }
+
+    /// The current room definitions
+    pub fn snapshot(&self) -> Arc<Vec<Room>> {
+        match self.0.read() {
+            Ok(rooms) => rooms.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Replace all room definitions
+    pub fn replace(&self, rooms: Vec<Room>) {
+        let mut guard = match self.0.write() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *guard = Arc::new(rooms);
+    }
+}
+
+/// Find the room a device is assigned to
+pub fn room_for_device<'a>(rooms: &'a [Room], device_id: &str) -> Option<&'a Room> {
+    rooms.iter().find(|room| room.has_device(device_id))
+}
+
+/// Find the room of a device, preferring the room it was bound to when claimed
+pub fn resolve_device_room<'a>(
+    rooms: &'a [Room],
+    device_id: &str,
+    claimed_room_id: Option<&str>,
+) -> Option<&'a Room> {
+    claimed_room_id
+        .and_then(|room_id| rooms.iter().find(|room| room.id == room_id))
+        .or_else(|| room_for_device(rooms, device_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::experiments::ExperimentState;
+    use crate::labels::Labels;
+
+    #[test]
+    fn test_parse_rooms() {
+        let rooms = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+            calendar_url = "https://example.com/room-a.ics"
+            devices = ["AA:BB:CC:DD:EE:FF"]
+
+            [[rooms]]
+            id = "room-b"
+            name = "Room B"
+            time_zone = "America/New_York"
+
+            [rooms.refresh]
+            boundary_rate = 30
+
+            [[rooms]]
+            id = "room-c"
+            name = "Room C"
+            calendar_url = "room-c@contoso.com"
+
+            [rooms.calendar_source]
+            type = "microsoft"
+            tenant_id = "contoso.onmicrosoft.com"
+            client_id = "app-1"
+            client_secret_env = "TRMNL_CALENDAR_SECRET_GRAPH"
+
+            [rooms.fallback_calendar]
+            url = "https://example.com/room-c.ics"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(rooms.len(), 3);
+        assert_eq!(rooms[1].calendar_url, None);
+        assert_eq!(rooms[0].calendar_source, CalendarSource::Ical);
+        assert_eq!(
+            rooms[2].calendar_source,
+            CalendarSource::Microsoft {
+                tenant_id: "contoso.onmicrosoft.com".to_string(),
+                client_id: "app-1".to_string(),
+                client_secret_env: "TRMNL_CALENDAR_SECRET_GRAPH".to_string(),
+            }
+        );
+        assert_eq!(rooms[0].fallback_calendar, None);
+        assert_eq!(
+            rooms[2].fallback_calendar,
+            Some(CalendarFallback {
+                url: "https://example.com/room-c.ics".to_string(),
+                source: CalendarSource::Ical,
+            })
+        );
+        assert!(rooms[1].devices.is_empty());
+        assert_eq!(rooms[0].refresh.boundary_rate, 60);
+        assert_eq!(rooms[1].refresh.boundary_rate, 30);
+        assert_eq!(rooms[1].refresh.meeting_rate, 900);
+        assert_eq!(rooms[0].time_zone, None);
+        assert_eq!(rooms[1].time_zone, Some(chrono_tz::America::New_York));
+
+        let room = room_for_device(&rooms, "aa:bb:cc:dd:ee:ff").unwrap();
+        assert_eq!(room.id, "room-a");
+        assert!(room_for_device(&rooms, "00:11:22:33:44:55").is_none());
+
+        // Calendar secrets are only read from variables meant for them
+        let error = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+            calendar_url = "https://dav.example.com/room-a/"
+
+            [rooms.fallback_calendar]
+            url = "https://dav.example.com/room-a-fallback/"
+
+            [rooms.fallback_calendar.source]
+            type = "caldav"
+            password_env = "ACCESS_TOKEN"
+            "#,
+        )
+        .unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("ACCESS_TOKEN"),
+            "{:#}",
+            error
+        );
+
+        // Refresh rates the devices would not honor are rejected
+        let error = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+
+            [rooms.refresh]
+            free_rate = 1
+            "#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("free_rate"), "{}", error);
+
+        let error = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+            time_zone = "Europe/Atlantis"
+            "#,
+        )
+        .unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("unknown time zone"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_header_metadata() {
+        let rooms = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+            display_name = "Matterhorn"
+            floor = "3"
+            capacity = 8
+            header_text = "Keys at reception"
+            equipment = ["tv", "video_conferencing"]
+
+            [[rooms]]
+            id = "room-b"
+            name = "Room B"
+            floor = "Ground floor"
+            zone = "North wing"
+            capacity = 1
+            "#,
+        )
+        .unwrap();
+
+        let labels = Labels::embedded();
+        let en = labels.pack("en");
+        assert_eq!(rooms[0].header_title(), "Matterhorn");
+        assert_eq!(
+            rooms[0].header_details(&en).as_deref(),
+            Some("Floor 3 | 8 seats | Keys at reception")
+        );
+        assert_eq!(
+            rooms[0].header_details(&labels.pack("ja")).as_deref(),
+            Some("3階 | 8席 | Keys at reception")
+        );
+        assert_eq!(rooms[0].equipment, [Equipment::Tv, Equipment::Vc]);
+        assert!(rooms[1].equipment.is_empty());
+        assert_eq!(rooms[1].header_title(), "Room B");
+        assert_eq!(
+            rooms[1].header_details(&en).as_deref(),
+            Some("Ground floor | 1 seat")
+        );
+
+        assert_eq!(rooms[0].location(&en).as_deref(), Some("Floor 3"));
+        assert_eq!(
+            rooms[1].location(&en).as_deref(),
+            Some("Ground floor | North wing")
+        );
+        assert!(rooms[0].on_floor(" 3"));
+        assert!(rooms[1].on_floor("ground FLOOR"));
+        assert!(!rooms[1].on_floor("3"));
+        assert!(rooms[0].matches_search("matter"));
+        assert!(rooms[0].matches_search("room a"));
+        assert!(!rooms[1].matches_search("matter"));
+    }
+
+    #[test]
+    fn test_event_style() {
+        let rooms = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+
+            [rooms.category_styles]
+            maintenance = "hatched"
+            "External client" = "bold"
+            red = "bold"
+            "#,
+        )
+        .unwrap();
+        let room = &rooms[0];
+
+        let now = chrono::Local::now();
+        let event = |categories: &[&str], color: Option<&str>| {
+            CalendarEvent::new("Event".to_string(), now, now, None, None).with_tags(
+                categories.iter().map(|c| c.to_string()).collect(),
+                color.map(str::to_string),
+            )
+        };
+        assert_eq!(room.event_style(&event(&[], None)), ItemStyle::Regular);
+        assert_eq!(
+            room.event_style(&event(&["Maintenance"], None)),
+            ItemStyle::Hatched
+        );
+        assert_eq!(
+            room.event_style(&event(&["external client", "maintenance"], None)),
+            ItemStyle::Bold
+        );
+        assert_eq!(
+            room.event_style(&event(&["Team"], Some("red"))),
+            ItemStyle::Bold
+        );
+        assert!(
+            parse_rooms(
+                "[[rooms]]\nid = \"a\"\nname = \"A\"\n[rooms.category_styles]\nx = \"blinking\""
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_layouts() {
+        let rooms = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+            devices = ["AA:BB:CC:DD:EE:FF", "00-11-22-33-44-55"]
+
+            [rooms.device_layouts]
+            "aabbccddeeff" = "focus"
+
+            [[rooms]]
+            id = "room-b"
+            name = "Room B"
+            layout = "focus"
+
+            [[rooms]]
+            id = "room-c"
+            name = "Room C"
+            layout = "days"
+            upcoming_days = 5
+            status_indicator = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(rooms[0].layout, Layout::Agenda);
+        assert_eq!(rooms[0].devices, ["AA:BB:CC:DD:EE:FF", "00:11:22:33:44:55"]);
+        assert_eq!(rooms[0].layout_for("AA:BB:CC:DD:EE:FF"), Layout::Focus);
+        assert_eq!(rooms[0].layout_for("00:11:22:33:44:55"), Layout::Agenda);
+        assert_eq!(rooms[1].layout_for("00:11:22:33:44:55"), Layout::Focus);
+        assert_eq!((rooms[1].upcoming_days, rooms[1].upcoming_events), (3, 6));
+        assert_eq!(rooms[2].layout, Layout::Days);
+        assert!(rooms[2].status_indicator && !rooms[1].status_indicator);
+        assert_eq!((rooms[2].upcoming_days, rooms[2].upcoming_events), (5, 6));
+        assert!(parse_rooms("[[rooms]]\nid = \"a\"\nname = \"A\"\nlayout = \"grid\"").is_err());
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let content = fs::read_to_string("rooms.example.toml").unwrap();
+        let rooms = parse_rooms(&content).unwrap();
+        let exported = rooms_to_toml(&rooms).unwrap();
+        let reimported = parse_rooms(&exported).unwrap();
+
+        assert_eq!(reimported.len(), rooms.len());
+        assert_eq!(reimported[0].equipment, rooms[0].equipment);
+        assert_eq!(reimported[0].devices, rooms[0].devices);
+        assert_eq!(
+            reimported[0].business_hours.days,
+            rooms[0].business_hours.days
+        );
+        assert_eq!(reimported[0].week, rooms[0].week);
+        assert_eq!(reimported[0].time_zone, Some(chrono_tz::Europe::Zurich));
+        assert_eq!(reimported[0].category_styles, rooms[0].category_styles);
+        assert_eq!(reimported[0].layout, rooms[0].layout);
+        assert_eq!(reimported[0].device_layouts, rooms[0].device_layouts);
+        assert_eq!(reimported[1].calendar_source, rooms[1].calendar_source);
+        assert!(matches!(
+            &rooms[1].calendar_source,
+            CalendarSource::CalDav { username: Some(username), .. } if username == "displays"
+        ));
+        assert_eq!(rooms_to_toml(&reimported).unwrap(), exported);
+    }
+
+    fn experiment(percent: u8, state: ExperimentState) -> Experiment {
+        Experiment {
+            name: "focus-at-doors".to_string(),
+            layout: Layout::Focus,
+            percent,
+            room_id: None,
+            state,
+            created_at: 1_700_000_000,
+            created_by: "admin".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_layout_with_experiments() {
+        let rooms = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+            layout = "agenda"
+
+            [rooms.device_layouts]
+            "AA:BB:CC:DD:EE:FF" = "days"
+            "#,
+        )
+        .unwrap();
+        let room = &rooms[0];
+
+        let promoted = [experiment(10, ExperimentState::Promoted)];
+        let (layout, assignment) = room.layout_with_experiments(&promoted, "00:11:22:33:44:55");
+        assert_eq!(layout, Layout::Focus);
+        assert_eq!(assignment.unwrap().1, Variant::Variant);
+        // Device layouts take precedence
+        assert_eq!(
+            room.layout_with_experiments(&promoted, "aa:bb:cc:dd:ee:ff"),
+            (Layout::Days, None)
+        );
+
+        let running = [experiment(0, ExperimentState::Running)];
+        let (layout, assignment) = room.layout_with_experiments(&running, "00:11:22:33:44:55");
+        assert_eq!(layout, Layout::Agenda);
+        assert_eq!(assignment.unwrap().1, Variant::Control);
+
+        let rolled_back = [experiment(100, ExperimentState::RolledBack)];
+        assert_eq!(
+            room.layout_with_experiments(&rolled_back, "00:11:22:33:44:55"),
+            (Layout::Agenda, None)
+        );
+
+        // Experiments limited to another room do not apply
+        let other_room = [Experiment {
+            room_id: Some("room-b".to_string()),
+            ..experiment(100, ExperimentState::Running)
+        }];
+        assert_eq!(
+            room.layout_with_experiments(&other_room, "00:11:22:33:44:55"),
+            (Layout::Agenda, None)
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Schedules of periodic jobs
+//!
+//! A schedule is either an interval, e.g. `@every 5m`, or a cron expression
+//! with five fields in local time: minute, hour, day of month, month and day of
+//! week, e.g. `*/15 7-19 * * 1-5` for every quarter hour during office hours.
+//! Fields are `*`, values, ranges and lists of them, each optionally with a
+//! step, e.g. `0-30/10,45`. Days of week run from 0 (Sunday) to 7 (Sunday
+//! again). Like in cron, a day matches if either the day of month or the day
+//! of week matches, unless one of them is `*`. The shorthands `@hourly`,
+//! `@daily`, `@weekly` and `@monthly` are accepted as well.
+
+use std::{fmt, str::FromStr, time::Duration};
+
+use chrono::{DateTime, Datelike, Days, Local, NaiveTime, TimeZone};
+
+/// Days searched for the next time of a cron expression, so that expressions
+/// that never match, e.g. `0 0 30 2 *`, give up eventually
+const SEARCH_DAYS: u64 = 5 * 366;
+
+/// When a job runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// At a fixed interval
+    Every(Duration),
+    /// At the times matching a cron expression
+    Cron(CronSchedule),
+}
+
+impl Schedule {
+    /// Interval schedule of the given number of minutes
+    pub fn every_minutes(minutes: u64) -> Self {
+        Schedule::Every(Duration::from_secs(minutes * 60))
+    }
+
+    /// First time after `after` the schedule is due
+    ///
+    /// Returns `None` for cron expressions that never match.
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Schedule::Every(interval) => Some(after + chrono::Duration::from_std(*interval).ok()?),
+            Schedule::Cron(cron) => cron.next_after(after),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Every(interval) => {
+                let secs = interval.as_secs();
+                match secs {
+                    s if s % 86_400 == 0 => write!(f, "@every {}d", s / 86_400),
+                    s if s % 3600 == 0 => write!(f, "@every {}h", s / 3600),
+                    s if s % 60 == 0 => write!(f, "@every {}m", s / 60),
+                    s => write!(f, "@every {}s", s),
+                }
+            }
+            Schedule::Cron(cron) => f.write_str(&cron.expression),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(interval) = s.strip_prefix("@every") {
+            return parse_interval(interval.trim()).map(Schedule::Every);
+        }
+        let expression = match s {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            s => s,
+        };
+        expression.parse().map(Schedule::Cron)
+    }
+}
+
+/// Parse an interval like `90s`, `5m`, `2h` or `1d`
+fn parse_interval(interval: &str) -> Result<Duration, String> {
+    let split = interval
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(interval.len());
+    let (value, unit) = interval.split_at(split);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("Invalid interval: {}", interval))?;
+    let seconds = match unit {
+        "s" => value,
+        "m" => value * 60,
+        "h" => value * 60 * 60,
+        "d" => value * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "Invalid interval: {} (expected a number with s, m, h or d)",
+                interval
+            ));
+        }
+    };
+    if seconds == 0 {
+        return Err("Interval must not be zero".to_string());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Cron expression with five fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    /// Expression as written, for display
+    expression: String,
+    minutes: u64,
+    hours: u32,
+    /// Bits 1 to 31
+    days_of_month: u32,
+    /// Bits 1 to 12
+    months: u16,
+    /// Bits 0 (Sunday) to 6
+    days_of_week: u8,
+    /// Whether the day of month is `*`
+    any_day_of_month: bool,
+    /// Whether the day of week is `*`
+    any_day_of_week: bool,
+}
+
+impl CronSchedule {
+    fn matches_day(&self, date: chrono::NaiveDate) -> bool {
+        if self.months & (1 << date.month()) == 0 {
+            return false;
+        }
+        let day_of_month = self.days_of_month & (1 << date.day()) != 0;
+        let day_of_week = self.days_of_week & (1 << date.weekday().num_days_from_sunday()) != 0;
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, _) => day_of_week,
+            (false, true) => day_of_month,
+            (false, false) => day_of_month || day_of_week,
+        }
+    }
+
+    /// First matching minute after `after`
+    ///
+    /// Times skipped by a DST transition are left out, and times repeated by
+    /// one match once.
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = after.naive_local().date();
+        for offset in 0..SEARCH_DAYS {
+            let date = start + Days::new(offset);
+            if !self.matches_day(date) {
+                continue;
+            }
+            for hour in (0..24).filter(|hour| self.hours & (1 << hour) != 0) {
+                for minute in (0..60).filter(|minute| self.minutes & (1 << minute) != 0) {
+                    let Some(time) = NaiveTime::from_hms_opt(hour, minute, 0) else {
+                        continue;
+                    };
+                    let Some(at) = Local.from_local_datetime(&date.and_time(time)).earliest()
+                    else {
+                        continue;
+                    };
+                    if at > after {
+                        return Some(at);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Parse a field of a cron expression into a bit set of its values
+fn parse_field(field: &str, name: &str, min: u32, max: u32) -> Result<u64, String> {
+    let invalid = || format!("Invalid {} field: {}", name, field);
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(invalid)?,
+            ),
+            None => (part, 1),
+        };
+        let (from, to) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((from, to)) => (
+                    from.parse().map_err(|_| invalid())?,
+                    to.parse().map_err(|_| invalid())?,
+                ),
+                // A single value with a step runs from the value to the end
+                None => {
+                    let value = range.parse().map_err(|_| invalid())?;
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        if from < min || to > max || from > to {
+            return Err(format!(
+                "Invalid {} field: {} (values from {} to {})",
+                name, field, min, max
+            ));
+        }
+        for value in (from..=to).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl FromStr for CronSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            return Err(format!(
+                "Invalid schedule: {} (expected five cron fields or @every with an interval)",
+                s
+            ));
+        };
+        let mut days_of_week_bits = parse_field(days_of_week, "day of week", 0, 7)?;
+        // Sunday is 0 or 7
+        if days_of_week_bits & (1 << 7) != 0 {
+            days_of_week_bits = (days_of_week_bits | 1) & 0x7f;
+        }
+        Ok(CronSchedule {
+            expression: fields.join(" "),
+            minutes: parse_field(minutes, "minute", 0, 59)?,
+            hours: parse_field(hours, "hour", 0, 23)? as u32,
+            days_of_month: parse_field(days_of_month, "day of month", 1, 31)? as u32,
+            months: parse_field(months, "month", 1, 12)? as u16,
+            days_of_week: days_of_week_bits as u8,
+            any_day_of_month: days_of_month == "*",
+            any_day_of_week: days_of_week == "*",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        // 2024-03-04 is a Monday
+        Local
+            .with_ymd_and_hms(2024, 3, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn next(schedule: &str, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        schedule.parse::<Schedule>().unwrap().next_after(after)
+    }
+
+    #[test]
+    fn test_cron_schedule() {
+        assert_eq!(next("*/15 * * * *", at(4, 9, 7)), Some(at(4, 9, 15)));
+        // Strictly after, so that a job does not run twice in a minute
+        assert_eq!(next("*/15 * * * *", at(4, 9, 15)), Some(at(4, 9, 30)));
+        assert_eq!(next("0 7-19 * * 1-5", at(8, 20, 0)), Some(at(11, 7, 0)));
+        assert_eq!(next("30 2 * * 0", at(4, 9, 0)), Some(at(10, 2, 30)));
+        assert_eq!(next("30 2 * * 7", at(4, 9, 0)), Some(at(10, 2, 30)));
+        assert_eq!(next("0-30/10,45 9 * * *", at(4, 9, 21)), Some(at(4, 9, 30)));
+        assert_eq!(next("0-30/10,45 9 * * *", at(4, 9, 31)), Some(at(4, 9, 45)));
+        // Day of month or day of week, as both are restricted
+        assert_eq!(next("0 0 10 * 2", at(4, 9, 0)), Some(at(5, 0, 0)));
+        assert_eq!(next("@daily", at(4, 9, 0)), Some(at(5, 0, 0)));
+        assert_eq!(next("0 0 30 2 *", at(4, 9, 0)), None);
+    }
+
+    #[test]
+    fn test_interval_schedule() {
+        let schedule: Schedule = "@every 90s".parse().unwrap();
+        assert_eq!(schedule, Schedule::Every(Duration::from_secs(90)));
+        assert_eq!(
+            schedule.next_after(at(4, 9, 0)),
+            Some(at(4, 9, 1) + chrono::Duration::seconds(30))
+        );
+        assert_eq!(Schedule::every_minutes(60).to_string(), "@every 1h");
+        assert_eq!(
+            "*/5  *  * * *".parse::<Schedule>().unwrap().to_string(),
+            "*/5 * * * *"
+        );
+    }
+
+    #[test]
+    fn test_invalid_schedules() {
+        for schedule in [
+            "@every",
+            "@every 0m",
+            "@every 5 minutes",
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "@yearly",
+        ] {
+            assert!(schedule.parse::<Schedule>().is_err(), "{}", schedule);
+        }
+    }
+}
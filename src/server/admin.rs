@@ -0,0 +1,1746 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::config::Config;
+use super::errors::AppError;
+use super::extract::Admin;
+use crate::api::types::LogLevel;
+use crate::api::types::{
+    AdoptDeviceRequest, BroadcastRequest, BroadcastResponse, CalendarSummary, ClaimCode,
+    ClaimCodeRequest, DeviceAdoption, DeviceInfo, DeviceLog, DeviceNameRequest, DeviceSummary,
+    DisplayAlert, ExperimentInfo, ExperimentRequest, FleetSummary, ImageDeliveryRequest,
+    ImageFormatRequest, IssueReport, MaintenanceRequest, MaintenanceResponse,
+    PrometheusTargetGroup, ProvisionedDevice, ProvisioningRequest, RoomSummary,
+};
+use crate::bmp::ImageFormat;
+use crate::calendar::{
+    CalendarEvent, CalendarHealth, CalendarRegistry, CalendarSource, parse_calendar,
+};
+use crate::claim::{generate_api_key, generate_claim_code, parse_provisioning_csv};
+use crate::database::{
+    BroadcastRecord, ClaimCodeRecord, Database, DeviceLogEntry, DeviceLogQuery, DeviceRecord,
+    IssueReportRecord, MaintenanceRecord, NewClaimCode, NewProvisionedDevice,
+    ProvisionedDeviceRecord,
+};
+use crate::device_id::{DeviceId, DeviceIdKind};
+use crate::experiments::{Experiment, ExperimentState, Variant};
+use crate::health::{
+    DeviceHealth, DeviceStatus, HEALTH_WINDOW_SECS, LOW_BATTERY_VOLTAGE, Remediation, fleet_health,
+};
+use crate::image_store::ImageDelivery;
+use crate::labels::{PackValidation, validate_pack};
+use crate::rooms::{Room, resolve_device_room, rooms_to_toml};
+use crate::status::RoomState;
+use crate::utilization::{MAX_UTILIZATION_DAYS, first_past_day, weekly_utilization};
+
+/// Extract the name of the admin performing an audited action
+pub fn extract_admin_user(headers: &HeaderMap) -> Result<String, AppError> {
+    let user = headers
+        .get("Admin-User")
+        .ok_or_else(|| AppError::BadRequest("Missing Admin-User header".to_string()))?
+        .to_str()
+        .map_err(|e| AppError::BadRequest(format!("Invalid Admin-User header format: {}", e)))?
+        .trim();
+    if user.is_empty() {
+        return Err(AppError::BadRequest("Empty Admin-User header".to_string()));
+    }
+    Ok(user.to_string())
+}
+
+/// Query parameters of the device export endpoint
+#[derive(Deserialize)]
+pub struct ExportParams {
+    /// Export format, currently only `prometheus_sd` is supported
+    pub format: String,
+}
+
+/// Column the device list is sorted by
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceSort {
+    #[default]
+    Id,
+    Name,
+    RegisteredAt,
+    /// Time of the last display request, devices that never checked in first
+    LastSeen,
+    Room,
+    Model,
+    PayloadBytes,
+    /// Health score, i.e. the devices with the most problems first
+    Health,
+    Reboots,
+    WifiReconnects,
+    FetchFailures,
+    RenderErrors,
+}
+
+/// Sort order of the device list
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Query parameters of the device list endpoint
+#[derive(Deserialize)]
+pub struct DeviceListParams {
+    #[serde(default)]
+    pub sort: DeviceSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    /// Only devices with this status, e.g. `offline` for stale devices
+    pub status: Option<DeviceStatus>,
+    /// Maximum number of devices to return, e.g. `5` for the worst five
+    pub limit: Option<usize>,
+}
+
+impl DeviceInfo {
+    /// Device as of its record, with the room it is shown in
+    fn new(device: DeviceRecord, rooms: &[Room], mut health: DeviceHealth, now: i64) -> Self {
+        health.record_telemetry(device.battery_voltage, device.rssi);
+        let display_alert = device.display_alert_at.map(|since| {
+            let remediation = Remediation::for_format(device.last_payload_format.as_deref());
+            DisplayAlert {
+                since,
+                remediation,
+                description: remediation.description().to_string(),
+            }
+        });
+        Self {
+            // Retired devices may still be listed in the rooms file
+            room_id: resolve_device_room(rooms, &device.id, device.room_id.as_deref())
+                .filter(|_| device.retired_at.is_none())
+                .map(|room| room.id.clone()),
+            id_type: DeviceIdKind::of(&device.id),
+            id: device.id,
+            registered_at: device.registered_at,
+            model: device.model,
+            firmware_version: device.firmware_version,
+            last_payload_format: device.last_payload_format,
+            last_payload_bytes: device.last_payload_bytes,
+            health_score: health.score(),
+            health,
+            image_delivery: device.image_delivery,
+            image_format: device.image_format,
+            replaced_by: device.replaced_by,
+            name: device.name,
+            last_seen_at: device.last_seen_at,
+            battery_voltage: device.battery_voltage,
+            rssi: device.rssi,
+            status: DeviceStatus::of(device.last_seen_at, now),
+            display_alert,
+        }
+    }
+
+    fn compare(&self, other: &Self, sort: DeviceSort) -> std::cmp::Ordering {
+        match sort {
+            DeviceSort::Id => self.id.cmp(&other.id),
+            DeviceSort::Name => self.name.cmp(&other.name),
+            DeviceSort::RegisteredAt => self.registered_at.cmp(&other.registered_at),
+            DeviceSort::LastSeen => self.last_seen_at.cmp(&other.last_seen_at),
+            DeviceSort::Room => self.room_id.cmp(&other.room_id),
+            DeviceSort::Model => self.model.cmp(&other.model),
+            DeviceSort::PayloadBytes => self.last_payload_bytes.cmp(&other.last_payload_bytes),
+            DeviceSort::Health => self.health_score.cmp(&other.health_score),
+            DeviceSort::Reboots => self.health.reboots.cmp(&other.health.reboots),
+            DeviceSort::WifiReconnects => self
+                .health
+                .wifi_reconnects
+                .cmp(&other.health.wifi_reconnects),
+            DeviceSort::FetchFailures => {
+                self.health.fetch_failures.cmp(&other.health.fetch_failures)
+            }
+            DeviceSort::RenderErrors => self.health.render_errors.cmp(&other.health.render_errors),
+        }
+    }
+}
+
+/// Device ID and message of each log entry, for [`fleet_health`]
+fn log_messages(logs: &[DeviceLogEntry]) -> impl Iterator<Item = (&str, &str)> {
+    logs.iter()
+        .map(|entry| (entry.device_id.as_str(), entry.message.as_str()))
+}
+
+/// Device list endpoint handler
+///
+/// Devices can be sorted by any column, e.g. `?sort=health&limit=5` lists the
+/// five devices with the lowest health score. Ties are broken by device ID.
+pub async fn list_devices_handler(
+    _: Admin,
+    Query(params): Query<DeviceListParams>,
+    State(db): State<Arc<Database>>,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let now = chrono::Utc::now().timestamp();
+        let logs = db
+            .device_logs_since(now - HEALTH_WINDOW_SECS)
+            .context("Failed to get device logs")
+            .map_err(AppError::from)?;
+        let mut health = fleet_health(log_messages(&logs));
+        let rooms = config.rooms.snapshot();
+        let mut devices: Vec<DeviceInfo> = db
+            .list_devices()
+            .context("Failed to list devices")
+            .map_err(AppError::from)?
+            .into_iter()
+            .map(|device| {
+                let health = health
+                    .remove(&device.id.to_ascii_uppercase())
+                    .unwrap_or_default();
+                DeviceInfo::new(device, &rooms, health, now)
+            })
+            .filter(|device| params.status.is_none_or(|status| device.status == status))
+            .collect();
+
+        devices.sort_by(|a, b| {
+            let ordering = a.compare(b, params.sort);
+            let ordering = match params.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            };
+            ordering.then_with(|| a.id.cmp(&b.id))
+        });
+        if let Some(limit) = params.limit {
+            devices.truncate(limit);
+        }
+
+        Ok(Json(devices))
+    })
+    .await
+}
+
+/// Default number of entries returned by the device log endpoint
+const DEFAULT_LOG_LIMIT: usize = 100;
+/// Maximum number of entries returned by the device log endpoint
+const MAX_LOG_LIMIT: usize = 1000;
+
+/// Query parameters of the device log endpoints
+#[derive(Deserialize)]
+pub struct DeviceLogParams {
+    /// Only entries of this device, ignored by the endpoint of a single device
+    pub device_id: Option<String>,
+    /// Only authenticated (`true`) or unauthenticated (`false`) entries
+    pub authenticated: Option<bool>,
+    /// Only entries of this level or more severe, e.g. `warn` for warnings and errors
+    pub level: Option<LogLevel>,
+    /// Only entries logged from this Unix timestamp on
+    pub since: Option<i64>,
+    /// Only entries logged before this Unix timestamp
+    pub until: Option<i64>,
+    /// Maximum number of entries to return, newest first
+    pub limit: Option<usize>,
+}
+
+/// Device log entries matching the query parameters
+fn device_logs(
+    db: &Database,
+    device_id: Option<&str>,
+    params: &DeviceLogParams,
+) -> Result<Vec<DeviceLog>, AppError> {
+    let query = DeviceLogQuery {
+        device_id,
+        authenticated: params.authenticated,
+        level: params.level,
+        since: params.since,
+        until: params.until,
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_LOG_LIMIT).min(MAX_LOG_LIMIT);
+    Ok(db
+        .list_device_logs(&query, limit)
+        .context("Failed to list device logs")
+        .map_err(AppError::from)?
+        .into_iter()
+        .map(DeviceLog::from)
+        .collect())
+}
+
+/// Device log endpoint handler
+pub async fn list_device_logs_handler(
+    _: Admin,
+    Query(params): Query<DeviceLogParams>,
+    State(db): State<Arc<Database>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| Ok(Json(device_logs(db, params.device_id.as_deref(), &params)?)))
+        .await
+}
+
+/// Log endpoint handler of a single device
+pub async fn device_logs_handler(
+    _: Admin,
+    Path(device_id): Path<String>,
+    Query(params): Query<DeviceLogParams>,
+    State(db): State<Arc<Database>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| Ok(Json(device_logs(db, Some(&device_id), &params)?)))
+        .await
+}
+
+/// Device endpoint handler
+pub async fn get_device_handler(
+    _: Admin,
+    Path(device_id): Path<String>,
+    State(db): State<Arc<Database>>,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let Some(device) = db
+            .get_device(&device_id)
+            .with_context(|| format!("Failed to get device {}", device_id))
+            .map_err(AppError::from)?
+        else {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        };
+        let now = chrono::Utc::now().timestamp();
+        let logs = db
+            .device_logs_since(now - HEALTH_WINDOW_SECS)
+            .context("Failed to get device logs")
+            .map_err(AppError::from)?;
+        let health = fleet_health(log_messages(&logs))
+            .remove(&device.id.to_ascii_uppercase())
+            .unwrap_or_default();
+        let rooms = config.rooms.snapshot();
+
+        Ok(Json(DeviceInfo::new(device, &rooms, health, now)).into_response())
+    })
+    .await
+}
+
+/// Maximum length of a device name
+const MAX_DEVICE_NAME_LEN: usize = 100;
+
+/// Device name endpoint handler
+pub async fn rename_device_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(device_id): Path<String>,
+    State(db): State<Arc<Database>>,
+    Json(request): Json<DeviceNameRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        let name = request
+            .name
+            .as_deref()
+            .map(str::trim)
+            .filter(|name| !name.is_empty());
+        if name.is_some_and(|name| name.chars().count() > MAX_DEVICE_NAME_LEN) {
+            return Err(AppError::BadRequest(format!(
+                "Device name must not be longer than {} characters",
+                MAX_DEVICE_NAME_LEN
+            )));
+        }
+        let renamed = db
+            .rename_device(&device_id, name, &admin_user)
+            .with_context(|| format!("Failed to rename device {}", device_id))
+            .map_err(AppError::from)?;
+
+        if renamed {
+            info!(
+                "Device {} renamed to {} by {}",
+                device_id,
+                name.unwrap_or("-"),
+                admin_user
+            );
+            Ok(StatusCode::NO_CONTENT)
+        } else {
+            Ok(StatusCode::NOT_FOUND)
+        }
+    })
+    .await
+}
+
+/// Device deletion endpoint handler
+///
+/// Removes a stale device, e.g. one that was thrown away. A device that is
+/// still in use can set up again, but loses its room if it was claimed.
+pub async fn delete_device_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(device_id): Path<String>,
+    State(db): State<Arc<Database>>,
+    State(calendars): State<Arc<CalendarRegistry>>,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        let deleted = db
+            .delete_device(&device_id, &admin_user)
+            .with_context(|| format!("Failed to delete device {}", device_id))
+            .map_err(AppError::from)?;
+        if !deleted {
+            return Ok(StatusCode::NOT_FOUND);
+        }
+        // Room assignments in the database changed
+        let rooms_in_database = !db
+            .list_rooms()
+            .context("Failed to list rooms")
+            .map_err(AppError::from)?
+            .is_empty();
+        if rooms_in_database {
+            reload_rooms(db, &config, &calendars)?;
+        }
+
+        info!("Device {} deleted by {}", device_id, admin_user);
+        Ok(StatusCode::NO_CONTENT)
+    })
+    .await
+}
+
+/// Device image delivery endpoint handler
+///
+/// Overrides the configured image delivery mode for one device, e.g. for a
+/// device on a guest network that blocks data URLs.
+pub async fn set_image_delivery_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(device_id): Path<String>,
+    State(db): State<Arc<Database>>,
+    Json(request): Json<ImageDeliveryRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        let mode = request
+            .image_delivery
+            .as_deref()
+            .map(|mode| {
+                mode.parse::<ImageDelivery>()
+                    .map_err(|e| AppError::BadRequest(e.to_string()))
+            })
+            .transpose()?;
+        let updated = db
+            .set_device_image_delivery(&device_id, mode.map(|mode| mode.as_str()), &admin_user)
+            .with_context(|| format!("Failed to set image delivery of device {}", device_id))
+            .map_err(AppError::from)?;
+
+        if updated {
+            info!(
+                "Image delivery of device {} set to {} by {}",
+                device_id,
+                mode.map_or("default", |mode| mode.as_str()),
+                admin_user
+            );
+            Ok(StatusCode::NO_CONTENT)
+        } else {
+            Ok(StatusCode::NOT_FOUND)
+        }
+    })
+    .await
+}
+
+/// Device image format endpoint handler
+///
+/// Pins the image format of one device, e.g. PNG for a device whose firmware
+/// supports it but does not announce it in the `Accept` header.
+pub async fn set_image_format_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(device_id): Path<String>,
+    State(db): State<Arc<Database>>,
+    Json(request): Json<ImageFormatRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        let format = request
+            .image_format
+            .as_deref()
+            .map(|format| {
+                format
+                    .parse::<ImageFormat>()
+                    .map_err(|e| AppError::BadRequest(e.to_string()))
+            })
+            .transpose()?;
+        let updated = db
+            .set_device_image_format(
+                &device_id,
+                format.map(|format| format.as_str()),
+                &admin_user,
+            )
+            .with_context(|| format!("Failed to set image format of device {}", device_id))
+            .map_err(AppError::from)?;
+
+        if updated {
+            info!(
+                "Image format of device {} set to {} by {}",
+                device_id,
+                format.map_or("default", |format| format.as_str()),
+                admin_user
+            );
+            Ok(StatusCode::NO_CONTENT)
+        } else {
+            Ok(StatusCode::NOT_FOUND)
+        }
+    })
+    .await
+}
+
+/// Device API key revocation endpoint handler
+///
+/// Rejects a lost or compromised device, on setup as well, until its key is
+/// reset.
+pub async fn revoke_device_api_key_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(device_id): Path<String>,
+    State(db): State<Arc<Database>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        let revoked = db
+            .revoke_device_api_key(&device_id, &admin_user)
+            .with_context(|| format!("Failed to revoke API key of device {}", device_id))
+            .map_err(AppError::from)?;
+
+        if revoked {
+            info!("API key of device {} revoked by {}", device_id, admin_user);
+            Ok(StatusCode::NO_CONTENT)
+        } else {
+            Ok(StatusCode::NOT_FOUND)
+        }
+    })
+    .await
+}
+
+/// Device API key reset endpoint handler
+///
+/// Drops the current or revoked key of a device, e.g. when it leaked or the
+/// device was found again, so that the device can be set up again and
+/// receives a new key. Until then, the device uses the shared access token.
+pub async fn reset_device_api_key_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(device_id): Path<String>,
+    State(db): State<Arc<Database>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        let Some(device) = db
+            .get_device(&device_id)
+            .with_context(|| format!("Failed to get device {}", device_id))
+            .map_err(AppError::from)?
+        else {
+            return Ok(StatusCode::NOT_FOUND);
+        };
+        if device.retired_at.is_some() {
+            return Err(AppError::BadRequest(format!(
+                "Device {} was retired",
+                device.id
+            )));
+        }
+        let reset = db
+            .reset_device_api_key(&device.id, &admin_user)
+            .with_context(|| format!("Failed to reset API key of device {}", device.id))
+            .map_err(AppError::from)?;
+        if !reset {
+            return Ok(StatusCode::NOT_FOUND);
+        }
+
+        info!("API key of device {} reset by {}", device.id, admin_user);
+        Ok(StatusCode::NO_CONTENT)
+    })
+    .await
+}
+
+/// Device adoption endpoint handler
+///
+/// Transfers the room assignment, settings and logs of a replaced device to
+/// the new unit, which must have been set up already, and retires the old one.
+pub async fn adopt_device_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(device_id): Path<String>,
+    State(db): State<Arc<Database>>,
+    State(calendars): State<Arc<CalendarRegistry>>,
+    State(config): State<Arc<Config>>,
+    Json(request): Json<AdoptDeviceRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        if request.replaces.eq_ignore_ascii_case(&device_id) {
+            return Err(AppError::BadRequest(
+                "A device cannot replace itself".to_string(),
+            ));
+        }
+        let Some(old_device) = db
+            .get_device(&request.replaces)
+            .with_context(|| format!("Failed to get device {}", request.replaces))
+            .map_err(AppError::from)?
+        else {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        };
+        if old_device.retired_at.is_some() {
+            return Err(AppError::BadRequest(format!(
+                "Device {} was retired already",
+                old_device.id
+            )));
+        }
+
+        // Carry over the room from the rooms file as well, as a claimed room
+        let rooms = config.rooms.snapshot();
+        let room_id = resolve_device_room(&rooms, &old_device.id, old_device.room_id.as_deref())
+            .map(|room| room.id.clone());
+        let adopted = db
+            .adopt_device(&old_device.id, &device_id, room_id.as_deref(), &admin_user)
+            .with_context(|| format!("Failed to adopt device {}", old_device.id))
+            .map_err(AppError::from)?;
+        if !adopted {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        }
+        // Room assignments in the database changed
+        let rooms_in_database = !db
+            .list_rooms()
+            .context("Failed to list rooms")
+            .map_err(AppError::from)?
+            .is_empty();
+        if rooms_in_database {
+            reload_rooms(db, &config, &calendars)?;
+        }
+
+        info!(
+            "Device {} replaced by {} by {}",
+            old_device.id, device_id, admin_user
+        );
+        Ok(Json(DeviceAdoption {
+            device_id,
+            replaced: old_device.id,
+            room_id,
+        })
+        .into_response())
+    })
+    .await
+}
+
+/// Device export endpoint handler
+pub async fn export_devices_handler(
+    _: Admin,
+    Query(params): Query<ExportParams>,
+    State(db): State<Arc<Database>>,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        if params.format != "prometheus_sd" {
+            return Err(AppError::BadRequest(format!(
+                "Unsupported export format: {}",
+                params.format
+            )));
+        }
+
+        let devices: Vec<DeviceRecord> = db
+            .list_devices()
+            .context("Failed to list devices")
+            .map_err(AppError::from)?
+            .into_iter()
+            .filter(|device| device.retired_at.is_none())
+            .collect();
+
+        info!("Exporting {} devices as Prometheus targets", devices.len());
+
+        // One target group per device, so that every device carries its own labels
+        let rooms = config.rooms.snapshot();
+        let groups: Vec<PrometheusTargetGroup> = devices
+            .into_iter()
+            .map(|device| {
+                let mut labels = BTreeMap::new();
+                labels.insert("__meta_trmnl_device_id".to_string(), device.id.clone());
+                labels.insert(
+                    "__meta_trmnl_registered_at".to_string(),
+                    device.registered_at.to_string(),
+                );
+                if let Some(room) =
+                    resolve_device_room(&rooms, &device.id, device.room_id.as_deref())
+                {
+                    labels.insert("__meta_trmnl_room".to_string(), room.id.clone());
+                    labels.insert("__meta_trmnl_room_name".to_string(), room.name.clone());
+                }
+                PrometheusTargetGroup {
+                    targets: vec![device.id],
+                    labels,
+                }
+            })
+            .collect();
+
+        Ok(Json(groups))
+    })
+    .await
+}
+
+/// Calendar source to test
+#[derive(Deserialize)]
+pub struct CalendarTestRequest {
+    /// iCal URL of the calendar, or URL of its CalDAV collection
+    pub url: String,
+    /// How the calendar is fetched from the URL
+    #[serde(default)]
+    pub source: CalendarSource,
+    /// Maximum number of upcoming events to return
+    #[serde(default = "default_calendar_test_limit")]
+    pub limit: usize,
+}
+
+fn default_calendar_test_limit() -> usize {
+    5
+}
+
+/// Result of a calendar test
+#[derive(Serialize, Deserialize)]
+pub struct CalendarTestResponse {
+    /// Whether the calendar could be fetched and parsed
+    pub ok: bool,
+    /// Error message if the calendar could not be fetched or parsed
+    pub error: Option<String>,
+    /// Total number of parsed events (past and future)
+    pub event_count: usize,
+    /// The first upcoming events (including a currently running one)
+    pub events: Vec<CalendarEvent>,
+    /// Problems found while parsing the calendar
+    pub warnings: Vec<String>,
+}
+
+/// Result of a label pack test
+#[derive(Serialize, Deserialize)]
+pub struct LabelPackTestResponse {
+    /// Whether the pack can be used, i.e. has no errors
+    pub ok: bool,
+    #[serde(flatten)]
+    pub validation: PackValidation,
+}
+
+/// Label pack test endpoint handler
+///
+/// Validates a label pack (the request body, in the Fluent format) for the
+/// given language, so that admins can check a pack before putting it into the
+/// labels directory.
+pub async fn test_label_pack_handler(
+    _: Admin,
+    Path(language): Path<String>,
+    body: String,
+) -> Result<impl IntoResponse, AppError> {
+    let validation = validate_pack(&language, &body);
+    Ok(Json(LabelPackTestResponse {
+        ok: validation.errors.is_empty(),
+        validation,
+    }))
+}
+
+/// Calendar test endpoint handler
+///
+/// Fetches and parses a calendar immediately, so that admins can validate a
+/// feed before assigning it to a room.
+pub async fn test_calendar_handler(
+    _: Admin,
+    Json(request): Json<CalendarTestRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    request
+        .source
+        .validate()
+        .map_err(|e| AppError::BadRequest(format!("Invalid calendar source: {}", e)))?;
+    info!("Testing calendar {}", request.url);
+
+    let parsed = match request.source.fetch(&request.url).await {
+        Ok(data) => parse_calendar(&data),
+        Err(e) => Err(e),
+    };
+
+    let response = match parsed {
+        Ok(parsed) => {
+            let now = Local::now();
+            let mut warnings = parsed.warnings;
+            let events: Vec<CalendarEvent> = parsed
+                .events
+                .iter()
+                .filter(|e| e.end_time > now)
+                .take(request.limit)
+                .cloned()
+                .collect();
+            if events.is_empty() {
+                warnings.push("Calendar contains no upcoming events".to_string());
+            }
+            CalendarTestResponse {
+                ok: true,
+                error: None,
+                event_count: parsed.events.len(),
+                events,
+                warnings,
+            }
+        }
+        Err(e) => CalendarTestResponse {
+            ok: false,
+            error: Some(e.to_string()),
+            event_count: 0,
+            events: Vec::new(),
+            warnings: Vec::new(),
+        },
+    };
+
+    Ok(Json(response))
+}
+
+/// Broadcast creation endpoint handler
+///
+/// Every device shows the message on its next poll, bypassing the regular
+/// rendering, until the broadcast expires or is cleared.
+pub async fn create_broadcast_handler(
+    _: Admin,
+    headers: HeaderMap,
+    State(db): State<Arc<Database>>,
+    Json(request): Json<BroadcastRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        if request.message.trim().is_empty() {
+            return Err(AppError::BadRequest(
+                "Broadcast message must not be empty".to_string(),
+            ));
+        }
+        if request.duration_minutes <= 0 {
+            return Err(AppError::BadRequest(
+                "Broadcast duration must be positive".to_string(),
+            ));
+        }
+        let duration_secs = request
+            .duration_minutes
+            .checked_mul(60)
+            .ok_or_else(|| AppError::BadRequest("Broadcast duration is too long".to_string()))?;
+
+        let broadcast = db
+            .create_broadcast(request.message.trim(), duration_secs, &admin_user)
+            .context("Failed to create broadcast")
+            .map_err(AppError::from)?;
+
+        warn!(
+            "Broadcast {} triggered by {}: {}",
+            broadcast.id, broadcast.triggered_by, broadcast.message
+        );
+
+        Ok((
+            StatusCode::CREATED,
+            Json(BroadcastResponse::from(broadcast)),
+        ))
+    })
+    .await
+}
+
+/// Broadcast clearing endpoint handler
+pub async fn clear_broadcast_handler(
+    _: Admin,
+    headers: HeaderMap,
+    State(db): State<Arc<Database>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        let cleared = db
+            .clear_broadcast(&admin_user)
+            .context("Failed to clear broadcast")
+            .map_err(AppError::from)?;
+
+        if cleared {
+            info!("Broadcast cleared by {}", admin_user);
+            Ok(StatusCode::NO_CONTENT)
+        } else {
+            Ok(StatusCode::NOT_FOUND)
+        }
+    })
+    .await
+}
+
+/// Maintenance status endpoint handler
+pub async fn get_maintenance_handler(
+    _: Admin,
+    State(db): State<Arc<Database>>,
+) -> Result<Response, AppError> {
+    db.run_blocking(move |db| {
+        let maintenance = db
+            .maintenance()
+            .context("Failed to get maintenance")
+            .map_err(AppError::from)?;
+
+        Ok(match maintenance {
+            Some(maintenance) => Json(MaintenanceResponse::from(maintenance)).into_response(),
+            None => StatusCode::NOT_FOUND.into_response(),
+        })
+    })
+    .await
+}
+
+/// Maintenance start endpoint handler
+///
+/// Every device shows the maintenance notice on its next poll and polls
+/// rarely, and calendars are not fetched anymore, until the maintenance is
+/// ended. Restarts do not end it.
+pub async fn start_maintenance_handler(
+    _: Admin,
+    headers: HeaderMap,
+    State(db): State<Arc<Database>>,
+    Json(request): Json<MaintenanceRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        let message = request
+            .message
+            .as_deref()
+            .map(str::trim)
+            .filter(|message| !message.is_empty());
+        let maintenance = db
+            .start_maintenance(message, &admin_user)
+            .context("Failed to start maintenance")
+            .map_err(AppError::from)?;
+
+        warn!("Maintenance started by {}", maintenance.started_by);
+
+        Ok(Json(MaintenanceResponse::from(maintenance)))
+    })
+    .await
+}
+
+/// Maintenance end endpoint handler
+pub async fn end_maintenance_handler(
+    _: Admin,
+    headers: HeaderMap,
+    State(db): State<Arc<Database>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        let ended = db
+            .end_maintenance(&admin_user)
+            .context("Failed to end maintenance")
+            .map_err(AppError::from)?;
+
+        if ended {
+            info!("Maintenance ended by {}", admin_user);
+            Ok(StatusCode::NO_CONTENT)
+        } else {
+            Ok(StatusCode::NOT_FOUND)
+        }
+    })
+    .await
+}
+
+/// Maximum length of experiment names
+const MAX_EXPERIMENT_NAME_LENGTH: usize = 64;
+
+/// Experiment with the number of registered devices in each variant
+fn experiment_info(
+    experiment: Experiment,
+    devices: &[DeviceRecord],
+    rooms: &[Room],
+) -> ExperimentInfo {
+    let (mut control_devices, mut variant_devices) = (0, 0);
+    for device in devices.iter().filter(|device| device.retired_at.is_none()) {
+        let Some(room) = resolve_device_room(rooms, &device.id, device.room_id.as_deref()) else {
+            continue;
+        };
+        if room.has_device_layout(&device.id) {
+            continue;
+        }
+        match experiment.variant(&room.id, &device.id) {
+            Some(Variant::Control) => control_devices += 1,
+            Some(Variant::Variant) => variant_devices += 1,
+            None => {}
+        }
+    }
+    ExperimentInfo {
+        experiment,
+        control_devices,
+        variant_devices,
+    }
+}
+
+/// Experiment list endpoint handler
+pub async fn list_experiments_handler(
+    _: Admin,
+    State(db): State<Arc<Database>>,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let devices = db
+            .list_devices()
+            .context("Failed to list devices")
+            .map_err(AppError::from)?;
+        let rooms = config.rooms.snapshot();
+        let experiments: Vec<ExperimentInfo> = db
+            .list_experiments()
+            .context("Failed to list experiments")
+            .map_err(AppError::from)?
+            .into_iter()
+            .map(|experiment| experiment_info(experiment, &devices, &rooms))
+            .collect();
+
+        Ok(Json(experiments))
+    })
+    .await
+}
+
+/// Experiment creation endpoint handler
+///
+/// The experiment's layout is shown on the given share of the devices from
+/// their next poll on.
+pub async fn create_experiment_handler(
+    _: Admin,
+    headers: HeaderMap,
+    State(db): State<Arc<Database>>,
+    State(config): State<Arc<Config>>,
+    Json(request): Json<ExperimentRequest>,
+) -> Result<Response, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        let name = request.name.trim();
+        let valid_name = !name.is_empty()
+            && name.len() <= MAX_EXPERIMENT_NAME_LENGTH
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            return Err(AppError::BadRequest(format!(
+                "Experiment names consist of up to {} letters, digits, dashes and underscores",
+                MAX_EXPERIMENT_NAME_LENGTH
+            )));
+        }
+        if !(1..=100).contains(&request.percent) {
+            return Err(AppError::BadRequest(
+                "Experiment percentage must be between 1 and 100".to_string(),
+            ));
+        }
+        let rooms = config.rooms.snapshot();
+        if let Some(room_id) = &request.room_id
+            && !rooms.iter().any(|room| &room.id == room_id)
+        {
+            return Err(AppError::BadRequest(format!("Unknown room: {}", room_id)));
+        }
+
+        let Some(experiment) = db
+            .create_experiment(
+                name,
+                request.layout,
+                request.percent,
+                request.room_id.as_deref(),
+                &admin_user,
+            )
+            .context("Failed to create experiment")
+            .map_err(AppError::from)?
+        else {
+            return Ok(StatusCode::CONFLICT.into_response());
+        };
+
+        info!(
+            "Experiment {} started by {}: {} on {}% of the devices",
+            experiment.name,
+            admin_user,
+            experiment.layout.as_str(),
+            experiment.percent
+        );
+
+        let devices = db
+            .list_devices()
+            .context("Failed to list devices")
+            .map_err(AppError::from)?;
+        Ok((
+            StatusCode::CREATED,
+            Json(experiment_info(experiment, &devices, &rooms)),
+        )
+            .into_response())
+    })
+    .await
+}
+
+/// Change the state of an experiment, for the promote and roll back endpoints
+fn set_experiment_state(
+    headers: HeaderMap,
+    name: &str,
+    db: &Database,
+    state: ExperimentState,
+) -> Result<StatusCode, AppError> {
+    let admin_user = extract_admin_user(&headers)?;
+
+    let updated = db
+        .set_experiment_state(name, state, &admin_user)
+        .context("Failed to update experiment")
+        .map_err(AppError::from)?;
+
+    if updated {
+        info!(
+            "Experiment {} set to {} by {}",
+            name,
+            state.as_str(),
+            admin_user
+        );
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+/// Experiment promotion endpoint handler
+///
+/// The experiment's layout is shown on all devices it applies to.
+pub async fn promote_experiment_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    State(db): State<Arc<Database>>,
+) -> Result<StatusCode, AppError> {
+    db.run_blocking(move |db| set_experiment_state(headers, &name, db, ExperimentState::Promoted))
+        .await
+}
+
+/// Experiment roll back endpoint handler
+///
+/// All devices show the layout of their room again.
+pub async fn rollback_experiment_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    State(db): State<Arc<Database>>,
+) -> Result<StatusCode, AppError> {
+    db.run_blocking(move |db| set_experiment_state(headers, &name, db, ExperimentState::RolledBack))
+        .await
+}
+
+/// Query parameters of the issue list endpoint
+#[derive(Deserialize)]
+pub struct IssueListParams {
+    /// Only list issues of this room
+    pub room: Option<String>,
+}
+
+/// Open issue list endpoint handler
+pub async fn list_issues_handler(
+    _: Admin,
+    Query(params): Query<IssueListParams>,
+    State(db): State<Arc<Database>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let issues: Vec<IssueReport> = db
+            .list_open_issue_reports(params.room.as_deref())
+            .context("Failed to list issue reports")
+            .map_err(AppError::from)?
+            .into_iter()
+            .map(IssueReport::from)
+            .collect();
+
+        Ok(Json(issues))
+    })
+    .await
+}
+
+/// Issue resolution endpoint handler
+pub async fn resolve_issue_handler(
+    _: Admin,
+    Path(id): Path<i64>,
+    State(db): State<Arc<Database>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let resolved = db
+            .resolve_issue_report(id)
+            .context("Failed to resolve issue report")
+            .map_err(AppError::from)?;
+
+        if resolved {
+            info!("Issue report {} resolved", id);
+            Ok(StatusCode::NO_CONTENT)
+        } else {
+            Ok(StatusCode::NOT_FOUND)
+        }
+    })
+    .await
+}
+
+/// Ensure that a room is configured
+fn check_room_exists(config: &Config, room_id: &str) -> Result<(), AppError> {
+    if config
+        .rooms
+        .snapshot()
+        .iter()
+        .any(|room| room.id == room_id)
+    {
+        Ok(())
+    } else {
+        Err(AppError::BadRequest(format!("Unknown room: {}", room_id)))
+    }
+}
+
+/// Claim code creation endpoint handler
+pub async fn create_claim_code_handler(
+    _: Admin,
+    headers: HeaderMap,
+    State(db): State<Arc<Database>>,
+    State(config): State<Arc<Config>>,
+    Json(request): Json<ClaimCodeRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        check_room_exists(&config, &request.room_id)?;
+        if request.expires_in_hours <= 0 {
+            return Err(AppError::BadRequest(
+                "Claim code expiry must be positive".to_string(),
+            ));
+        }
+        let expires_in_secs = request
+            .expires_in_hours
+            .checked_mul(3600)
+            .ok_or_else(|| AppError::BadRequest("Claim code expiry is too long".to_string()))?;
+
+        let code = NewClaimCode {
+            code: generate_claim_code(),
+            room_id: request.room_id,
+            device_id: None,
+        };
+        let mut records = db
+            .create_claim_codes(&[code], Some(expires_in_secs), &admin_user)
+            .context("Failed to create claim code")
+            .map_err(AppError::from)?;
+        let record = records.remove(0);
+
+        info!(
+            "Claim code for room {} created by {}",
+            record.room_id, admin_user
+        );
+
+        Ok((StatusCode::CREATED, Json(ClaimCode::from(record))))
+    })
+    .await
+}
+
+/// Open claim code list endpoint handler
+pub async fn list_claim_codes_handler(
+    _: Admin,
+    State(db): State<Arc<Database>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let codes: Vec<ClaimCode> = db
+            .list_open_claim_codes()
+            .context("Failed to list claim codes")
+            .map_err(AppError::from)?
+            .into_iter()
+            .map(ClaimCode::from)
+            .collect();
+
+        Ok(Json(codes))
+    })
+    .await
+}
+
+/// Pre-provisioning import endpoint handler
+///
+/// Takes a CSV with `device_id,room_id` lines and creates a claim code bound to
+/// each device, so that the devices are assigned to their rooms on setup
+/// without entering a code.
+pub async fn import_claim_codes_handler(
+    _: Admin,
+    headers: HeaderMap,
+    State(db): State<Arc<Database>>,
+    State(config): State<Arc<Config>>,
+    body: String,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        let entries = parse_provisioning_csv(&body).map_err(AppError::BadRequest)?;
+        for (_, room_id) in &entries {
+            check_room_exists(&config, room_id)?;
+        }
+        let codes: Vec<NewClaimCode> = entries
+            .into_iter()
+            .map(|(device_id, room_id)| NewClaimCode {
+                code: generate_claim_code(),
+                room_id,
+                device_id: Some(device_id),
+            })
+            .collect();
+
+        let records = db
+            .create_claim_codes(&codes, None, &admin_user)
+            .context("Failed to import pre-provisioned devices")
+            .map_err(AppError::from)?;
+
+        info!(
+            "{} pre-provisioned devices imported by {}",
+            records.len(),
+            admin_user
+        );
+
+        let codes: Vec<ClaimCode> = records.into_iter().map(ClaimCode::from).collect();
+        Ok((StatusCode::CREATED, Json(codes)))
+    })
+    .await
+}
+
+/// Device provisioning endpoint handler
+///
+/// Provisions a batch of devices in one call, e.g. all displays of a floor.
+/// Each device gets a claim code bound to it and an API key. Submitting the
+/// same batch again returns the same codes and keys, changed rooms and
+/// metadata are applied.
+pub async fn provision_devices_handler(
+    _: Admin,
+    headers: HeaderMap,
+    State(db): State<Arc<Database>>,
+    State(config): State<Arc<Config>>,
+    Json(request): Json<ProvisioningRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        let mut devices: Vec<NewProvisionedDevice> = Vec::with_capacity(request.devices.len());
+        for entry in request.devices {
+            let device_id = entry
+                .mac
+                .parse::<DeviceId>()
+                .map_err(AppError::BadRequest)?
+                .into_string();
+            if devices.iter().any(|d| d.device_id == device_id) {
+                return Err(AppError::BadRequest(format!(
+                    "Device {} is listed more than once",
+                    device_id
+                )));
+            }
+            check_room_exists(&config, &entry.room_id)?;
+            if entry.firmware_channel.trim().is_empty() {
+                return Err(AppError::BadRequest(
+                    "Firmware channel must not be empty".to_string(),
+                ));
+            }
+            devices.push(NewProvisionedDevice {
+                device_id,
+                room_id: entry.room_id,
+                label: entry.label,
+                model: entry.model,
+                firmware_channel: entry.firmware_channel.trim().to_string(),
+                claim_code: generate_claim_code(),
+                api_key: generate_api_key(),
+            });
+        }
+
+        let records = db
+            .provision_devices(&devices, &admin_user)
+            .context("Failed to provision devices")
+            .map_err(AppError::from)?;
+
+        info!("{} devices provisioned by {}", records.len(), admin_user);
+
+        let devices: Vec<ProvisionedDevice> = records
+            .into_iter()
+            .map(|(record, created)| ProvisionedDevice {
+                created,
+                ..ProvisionedDevice::from(record)
+            })
+            .collect();
+        Ok(Json(devices))
+    })
+    .await
+}
+
+/// Provisioned device list endpoint handler
+pub async fn list_provisioned_devices_handler(
+    _: Admin,
+    State(db): State<Arc<Database>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let devices: Vec<ProvisionedDevice> = db
+            .list_provisioned_devices()
+            .context("Failed to list provisioned devices")
+            .map_err(AppError::from)?
+            .into_iter()
+            .map(ProvisionedDevice::from)
+            .collect();
+
+        Ok(Json(devices))
+    })
+    .await
+}
+
+/// Room list endpoint handler
+///
+/// Lists the rooms currently in effect, whether from the database or the
+/// rooms file.
+pub async fn list_rooms_handler(
+    _: Admin,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(config.rooms.snapshot().to_vec()))
+}
+
+/// Room export endpoint handler
+///
+/// Returns the rooms currently in effect in the format of the rooms file, so
+/// that changes made at runtime can be reviewed and versioned.
+pub async fn export_rooms_handler(
+    _: Admin,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, AppError> {
+    let toml = rooms_to_toml(&config.rooms.snapshot()).map_err(AppError::from)?;
+    Ok(([(header::CONTENT_TYPE, "application/toml")], toml))
+}
+
+/// Default number of days returned by the room utilization endpoint
+const DEFAULT_UTILIZATION_DAYS: u32 = 30;
+
+/// Period the room utilization is reported by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UtilizationGroup {
+    #[default]
+    Day,
+    /// Weeks of the room, without the weekend
+    Week,
+}
+
+/// Query parameters of the room utilization endpoint
+#[derive(Deserialize)]
+pub struct UtilizationParams {
+    /// Number of days before today to return
+    pub days: Option<u32>,
+    #[serde(default)]
+    pub group: UtilizationGroup,
+}
+
+/// Room utilization endpoint handler
+pub async fn room_utilization_handler(
+    _: Admin,
+    Path(room_id): Path<String>,
+    Query(params): Query<UtilizationParams>,
+    State(db): State<Arc<Database>>,
+    State(config): State<Arc<Config>>,
+) -> Result<Response, AppError> {
+    db.run_blocking(move |db| {
+        let Some(room) = config
+            .rooms
+            .snapshot()
+            .iter()
+            .find(|room| room.id == room_id)
+            .cloned()
+        else {
+            return Ok(StatusCode::NOT_FOUND.into_response());
+        };
+        let days = params.days.unwrap_or(DEFAULT_UTILIZATION_DAYS);
+        if days > MAX_UTILIZATION_DAYS {
+            return Err(AppError::BadRequest(format!(
+                "days must be at most {}",
+                MAX_UTILIZATION_DAYS
+            )));
+        }
+        let since = first_past_day(chrono::Local::now(), days, room.time_zone)
+            .map_err(|e| AppError::BadRequest(format!("{:#}", e)))?;
+        let utilization = db
+            .room_utilization(&room_id, since)
+            .with_context(|| format!("Failed to get utilization of room {}", room_id))
+            .map_err(AppError::from)?;
+
+        match params.group {
+            UtilizationGroup::Day => Ok(Json(utilization).into_response()),
+            UtilizationGroup::Week => {
+                Ok(Json(weekly_utilization(&utilization, &room.week)).into_response())
+            }
+        }
+    })
+    .await
+}
+
+/// Ensure that the room configuration is managed in the database
+///
+/// Editing a single room while the rooms file is in effect would silently
+/// drop all other rooms, so the rooms file has to be imported first.
+fn check_rooms_in_database(db: &Database, config: &Config) -> Result<(), AppError> {
+    let rooms = db
+        .list_rooms()
+        .context("Failed to list rooms")
+        .map_err(AppError::from)?;
+    if rooms.is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "Rooms are configured in {}, run import-config to manage them via the API",
+            config.rooms_path
+        )));
+    }
+    Ok(())
+}
+
+/// Apply the room configuration stored in the database
+fn reload_rooms(
+    db: &Database,
+    config: &Config,
+    calendars: &CalendarRegistry,
+) -> Result<(), AppError> {
+    let rooms = db
+        .list_rooms()
+        .context("Failed to reload rooms")
+        .map_err(AppError::from)?;
+    config.rooms.replace(rooms);
+    // Calendars are cached per room, a changed calendar URL must be fetched anew
+    calendars.invalidate_all();
+    Ok(())
+}
+
+/// Room creation and update endpoint handler
+pub async fn save_room_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(room_id): Path<String>,
+    State(db): State<Arc<Database>>,
+    State(calendars): State<Arc<CalendarRegistry>>,
+    State(config): State<Arc<Config>>,
+    Json(mut room): Json<Room>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+
+        if room.id != room_id {
+            return Err(AppError::BadRequest(format!(
+                "Room ID {} does not match the URL",
+                room.id
+            )));
+        }
+        if room.name.trim().is_empty() {
+            return Err(AppError::BadRequest(
+                "Room name must not be empty".to_string(),
+            ));
+        }
+        room.refresh
+            .validate()
+            .map_err(|e| AppError::BadRequest(format!("Invalid refresh policy: {}", e)))?;
+        room.normalize_device_ids();
+        room.validate_calendars()
+            .map_err(|e| AppError::BadRequest(format!("Invalid calendar: {}", e)))?;
+        check_rooms_in_database(db, &config)?;
+
+        db.save_room(&room, &admin_user)
+            .with_context(|| format!("Failed to save room {}", room.id))
+            .map_err(AppError::from)?;
+        reload_rooms(db, &config, &calendars)?;
+
+        info!("Room {} saved by {}", room.id, admin_user);
+        Ok(Json(room))
+    })
+    .await
+}
+
+/// Room deletion endpoint handler
+pub async fn delete_room_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(room_id): Path<String>,
+    State(db): State<Arc<Database>>,
+    State(calendars): State<Arc<CalendarRegistry>>,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| {
+        let admin_user = extract_admin_user(&headers)?;
+        check_rooms_in_database(db, &config)?;
+
+        let deleted = db
+            .delete_room(&room_id, &admin_user)
+            .with_context(|| format!("Failed to delete room {}", room_id))
+            .map_err(AppError::from)?;
+        reload_rooms(db, &config, &calendars)?;
+
+        if deleted {
+            info!("Room {} deleted by {}", room_id, admin_user);
+            Ok(StatusCode::NO_CONTENT)
+        } else {
+            Ok(StatusCode::NOT_FOUND)
+        }
+    })
+    .await
+}
+
+/// Fleet summary endpoint handler
+///
+/// Only looks at stored state and cached calendars, so it is cheap enough to
+/// be polled by status pages.
+pub async fn summary_handler(
+    _: Admin,
+    State(db): State<Arc<Database>>,
+    State(calendars): State<Arc<CalendarRegistry>>,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, AppError> {
+    db.run_blocking(move |db| Ok(Json(fleet_summary(db, &calendars, &config)?)))
+        .await
+}
+
+/// Device, room and calendar counts of the fleet
+pub(super) fn fleet_summary(
+    db: &Database,
+    calendars: &CalendarRegistry,
+    config: &Config,
+) -> Result<FleetSummary, AppError> {
+    let now = chrono::Utc::now().timestamp();
+    let mut devices = DeviceSummary::default();
+    for device in db
+        .list_devices()
+        .context("Failed to list devices")
+        .map_err(AppError::from)?
+        .into_iter()
+        .filter(|device| device.retired_at.is_none())
+    {
+        devices.total += 1;
+        match DeviceStatus::of(device.last_seen_at, now) {
+            DeviceStatus::Online => devices.online += 1,
+            DeviceStatus::Offline => devices.offline += 1,
+            DeviceStatus::Pending => devices.pending += 1,
+        }
+        if device
+            .battery_voltage
+            .is_some_and(|voltage| voltage < LOW_BATTERY_VOLTAGE)
+        {
+            devices.low_battery += 1;
+        }
+    }
+    let not_set_up = db
+        .list_provisioned_devices()
+        .context("Failed to list provisioned devices")
+        .map_err(AppError::from)?
+        .into_iter()
+        .filter(|device| !device.claimed)
+        .count();
+    devices.total += not_set_up;
+    devices.pending += not_set_up;
+
+    let local_now = Local::now();
+    let rooms_snapshot = config.rooms.snapshot();
+    let mut rooms = RoomSummary::default();
+    for room in rooms_snapshot.iter() {
+        rooms.total += 1;
+        let Some(url) = &room.calendar_url else {
+            rooms.unknown += 1;
+            continue;
+        };
+        let degraded = calendars.is_degraded(&room.id);
+        if calendars.health(url) == CalendarHealth::Failing && !degraded {
+            rooms.error += 1;
+            continue;
+        }
+        if degraded {
+            rooms.degraded += 1;
+        }
+        match calendars.cached_future_events(&room.id) {
+            Some(events) => {
+                match RoomState::resolve(&events, local_now, room.end_warning_minutes) {
+                    RoomState::Free { .. } => rooms.free += 1,
+                    _ => rooms.busy += 1,
+                }
+            }
+            None => rooms.unknown += 1,
+        }
+    }
+
+    let urls: BTreeSet<&String> = rooms_snapshot
+        .iter()
+        .filter_map(|room| room.calendar_url.as_ref())
+        .collect();
+    let mut sources = CalendarSummary {
+        total: urls.len(),
+        ..CalendarSummary::default()
+    };
+    for url in urls {
+        match calendars.health(url) {
+            CalendarHealth::Ok => sources.ok += 1,
+            CalendarHealth::Failing => sources.failing += 1,
+            CalendarHealth::Unknown => sources.unknown += 1,
+        }
+    }
+
+    Ok(FleetSummary {
+        devices,
+        rooms,
+        calendars: sources,
+    })
+}
+
+impl From<DeviceLogEntry> for DeviceLog {
+    fn from(entry: DeviceLogEntry) -> Self {
+        Self {
+            device_id: entry.device_id,
+            message: entry.message,
+            received_at: entry.received_at,
+            authenticated: entry.authenticated,
+            logged_at: entry.logged_at,
+            level: entry.level,
+        }
+    }
+}
+
+impl From<BroadcastRecord> for BroadcastResponse {
+    fn from(record: BroadcastRecord) -> Self {
+        Self {
+            id: record.id,
+            message: record.message,
+            expires_at: record.expires_at,
+            triggered_by: record.triggered_by,
+        }
+    }
+}
+
+impl From<MaintenanceRecord> for MaintenanceResponse {
+    fn from(record: MaintenanceRecord) -> Self {
+        Self {
+            message: record.message,
+            started_at: record.started_at,
+            started_by: record.started_by,
+        }
+    }
+}
+
+impl From<IssueReportRecord> for IssueReport {
+    fn from(record: IssueReportRecord) -> Self {
+        Self {
+            id: record.id,
+            room_id: record.room_id,
+            category: record.category,
+            description: record.description,
+            created_at: record.created_at,
+        }
+    }
+}
+
+impl From<ClaimCodeRecord> for ClaimCode {
+    fn from(record: ClaimCodeRecord) -> Self {
+        Self {
+            code: record.code,
+            room_id: record.room_id,
+            device_id: record.device_id,
+            expires_at: record.expires_at,
+        }
+    }
+}
+
+impl From<ProvisionedDeviceRecord> for ProvisionedDevice {
+    fn from(record: ProvisionedDeviceRecord) -> Self {
+        Self {
+            device_id: record.device_id,
+            room_id: record.room_id,
+            label: record.label,
+            model: record.model,
+            firmware_channel: record.firmware_channel,
+            claim_code: record.claim_code,
+            api_key: record.api_key,
+            claimed: record.claimed,
+            created: false,
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Alerts about devices that went offline and calendars that keep failing
+//!
+//! Each display response tells the device when to check in next, which is
+//! recorded with the device. Once a device missed `NOTIFY_MISSED_CHECK_INS`
+//! check-ins in a row, the operators are notified and the device is flagged
+//! until it checks in again, so that they are notified once per outage.
+//! Devices that are told to sleep, e.g. overnight, are only expected back
+//! when they wake up. Check-ins expected before the server started are not
+//! counted, as the devices could not check in while it was down.
+//!
+//! Calendars are refreshed by every instance, which counts the failed
+//! refreshes of each room's calendar. As the alerts run as an exclusive job,
+//! the failures are reported by one instance, once per streak of
+//! `NOTIFY_CALENDAR_FAILURES` failed refreshes. When the job moves to another
+//! instance, a streak already reported may be reported once more.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::warn;
+
+use crate::calendar::CalendarRegistry;
+use crate::database::Database;
+use crate::health::missed_check_ins;
+use crate::notify::{Notification, Notifier};
+use crate::rooms::{Room, resolve_device_room};
+
+/// How often devices and calendars are checked, by default
+pub const ALERTS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Flag and report the devices that missed `threshold` check-ins in a row
+///
+/// Only check-ins expected from `since` on are counted. Returns the IDs of the
+/// newly flagged devices. The flag is cleared by the next check-in of the
+/// device.
+pub fn check_offline_devices(
+    database: &Database,
+    rooms: &[Room],
+    notifier: &dyn Notifier,
+    threshold: u32,
+    since: i64,
+    now: i64,
+) -> Result<Vec<String>> {
+    let mut flagged = Vec::new();
+    for device in database.list_devices().context("Failed to list devices")? {
+        let (Some(next_check_in_at), Some(interval)) =
+            (device.next_check_in_at, device.check_in_interval)
+        else {
+            continue;
+        };
+        if device.retired_at.is_some() || device.offline_alert_at.is_some() {
+            continue;
+        }
+        let missed = missed_check_ins(next_check_in_at.max(since), interval, now);
+        if missed < threshold {
+            continue;
+        }
+
+        warn!("Device {} missed its last {} check-ins", device.id, missed);
+        database.set_device_offline_alert(&device.id, Some(now))?;
+        let room = resolve_device_room(rooms, &device.id, device.room_id.as_deref());
+        notifier.notify(Notification {
+            kind: "device.offline".to_string(),
+            title: format!(
+                "Display {} is offline",
+                device.name.as_deref().unwrap_or(&device.id)
+            ),
+            message: format!(
+                "The device missed its last {} check-ins, it may be out of battery or WiFi range.",
+                missed
+            ),
+            room_id: room.map(|room| room.id.clone()),
+            device_id: Some(device.id.clone()),
+            details: Some(serde_json::json!({
+                "last_seen_at": device.last_seen_at,
+                "missed_check_ins": missed,
+            })),
+        });
+        flagged.push(device.id);
+    }
+    Ok(flagged)
+}
+
+/// Report the rooms whose calendar failed to refresh `threshold` times in a
+/// row, see [`CalendarRegistry::take_failing_calendars`]
+///
+/// Returns the IDs of the reported rooms.
+pub fn check_calendars(
+    calendars: &CalendarRegistry,
+    rooms: &[Room],
+    notifier: &dyn Notifier,
+    threshold: u32,
+) -> Vec<String> {
+    let mut reported = Vec::new();
+    for (room_id, failures) in calendars.take_failing_calendars(threshold) {
+        let name = rooms
+            .iter()
+            .find(|room| room.id == room_id)
+            .map_or(room_id.as_str(), |room| room.name.as_str());
+        warn!(
+            "Calendar of room {} failed to refresh {} times in a row",
+            room_id, failures
+        );
+        notifier.notify(Notification {
+            kind: "room.calendar_failing".to_string(),
+            title: format!("Calendar of {} cannot be fetched", name),
+            message: format!(
+                "The last {} refreshes of the calendar failed, the display shows outdated or fallback events.",
+                failures
+            ),
+            room_id: Some(room_id.clone()),
+            device_id: None,
+            details: Some(serde_json::json!({ "failed_refreshes": failures })),
+        });
+        reported.push(room_id);
+    }
+    reported
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::database::DeviceCheckIn;
+    use crate::health::CRITICAL_BATTERY_VOLTAGE;
+    use crate::rooms::parse_rooms;
+
+    /// Notifier keeping the titles of the notifications
+    #[derive(Default)]
+    struct RecordingNotifier(Mutex<Vec<String>>);
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&self, notification: Notification) {
+            self.0.lock().unwrap().push(notification.title);
+        }
+    }
+
+    #[test]
+    fn test_check_offline_devices() {
+        let db = Database::new(":memory:").unwrap();
+        db.register_device("AA:BB:CC:DD:EE:FF").unwrap();
+        // Never checked in, so not expected either
+        db.register_device("11:22:33:44:55:66").unwrap();
+        let rooms = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+            devices = ["AA:BB:CC:DD:EE:FF"]
+            "#,
+        )
+        .unwrap();
+        let notifier = RecordingNotifier::default();
+
+        // Expected every 5 minutes from now on
+        let now = 1_700_000_000;
+        db.record_device_schedule("AA:BB:CC:DD:EE:FF", now + 300, 300)
+            .unwrap();
+        let check = |now| check_offline_devices(&db, &rooms, &notifier, 3, 0, now).unwrap();
+        assert!(check(now + 900).is_empty());
+
+        assert_eq!(check(now + 1000), ["AA:BB:CC:DD:EE:FF"]);
+        let device = db.get_device("AA:BB:CC:DD:EE:FF").unwrap().unwrap();
+        assert_eq!(device.offline_alert_at, Some(now + 1000));
+        assert_eq!(
+            *notifier.0.lock().unwrap(),
+            ["Display AA:BB:CC:DD:EE:FF is offline"]
+        );
+
+        // Offline devices are reported once
+        assert!(check(now + 2000).is_empty());
+        assert_eq!(notifier.0.lock().unwrap().len(), 1);
+
+        // A check-in clears the flag
+        db.record_device_check_in(
+            "AA:BB:CC:DD:EE:FF",
+            &DeviceCheckIn::default(),
+            CRITICAL_BATTERY_VOLTAGE,
+        )
+        .unwrap();
+        let device = db.get_device("AA:BB:CC:DD:EE:FF").unwrap().unwrap();
+        assert_eq!(device.offline_alert_at, None);
+    }
+
+    #[test]
+    fn test_check_offline_devices_after_restart() {
+        let db = Database::new(":memory:").unwrap();
+        db.register_device("AA:BB:CC:DD:EE:FF").unwrap();
+        let notifier = RecordingNotifier::default();
+
+        // Expected back an hour before the server started again
+        let now = 1_700_000_000;
+        db.record_device_schedule("AA:BB:CC:DD:EE:FF", now - 3600, 300)
+            .unwrap();
+        let check = |now| check_offline_devices(&db, &[], &notifier, 3, 1_700_000_000, now);
+        assert!(check(now + 60).unwrap().is_empty());
+        assert!(check(now + 600).unwrap().is_empty());
+
+        // Missed three check-ins since the start
+        assert_eq!(check(now + 700).unwrap(), ["AA:BB:CC:DD:EE:FF"]);
+    }
+
+    #[tokio::test]
+    async fn test_check_calendars() {
+        let rooms = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+            calendar_url = "http://127.0.0.1:1/room-a.ics"
+            "#,
+        )
+        .unwrap();
+        let calendars = CalendarRegistry::new(5);
+        let notifier = RecordingNotifier::default();
+
+        // Nothing listens on port 1
+        calendars.refresh_all(&rooms).await;
+        assert!(check_calendars(&calendars, &rooms, &notifier, 2).is_empty());
+        calendars.refresh_all(&rooms).await;
+        assert_eq!(
+            check_calendars(&calendars, &rooms, &notifier, 2),
+            ["room-a"]
+        );
+        assert_eq!(
+            *notifier.0.lock().unwrap(),
+            ["Calendar of Room A cannot be fetched"]
+        );
+    }
+}
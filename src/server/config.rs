@@ -1,8 +1,24 @@
-use std::{env, sync::OnceLock};
+use std::env;
 
 use anyhow::Result;
 use dotenv::dotenv;
 
+use crate::bmp::{Dither, ImageFormat};
+use crate::database::DEFAULT_POOL_SIZE;
+use crate::error_report::ErrorSink;
+use crate::health::{BATTERY_RECOVERED_VOLTAGE, CRITICAL_BATTERY_VOLTAGE, EMPTY_BATTERY_VOLTAGE};
+use crate::image_store::{ImageDelivery, ImageStoreConfig};
+use crate::labels::{Labels, SharedLabels};
+use crate::log_ingest::LogAuth;
+use crate::refresh::RefreshRates;
+use crate::render::RendererConfig;
+use crate::rooms::{SharedRooms, load_rooms};
+use crate::schedule::Schedule;
+use crate::signing::ImageSigner;
+
+use super::proxy::ProxyConfig;
+use super::tls::TlsConfig;
+
 /// Application configuration
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -12,69 +28,340 @@ pub struct Config {
     pub server_port: u16,
     /// Public server URL for external access
     pub server_url: String,
-    /// Database file path
-    pub database_path: String,
+    /// Certificate and key if the server serves HTTPS itself
+    pub tls: Option<TlsConfig>,
+    /// Path of the SQLite database file, or URL of a Postgres database
+    pub database_url: String,
+    /// Connections of the database pool
+    pub database_pool_size: usize,
     /// Access token for API authentication
     pub access_token: String,
+    /// Token of the admin API and dashboard, which are disabled without one
+    pub admin_token: Option<String>,
     /// Font path for BMP generation
     pub font_path: String,
-    /// Image refresh rate in seconds
-    pub refresh_rate: u32,
+    /// Refresh rates sent to devices
+    pub refresh: RefreshRates,
+    /// How often room calendars are refreshed, in minutes
+    pub calendar_refresh_minutes: u64,
+    /// Path to the TOML file with room definitions
+    pub rooms_path: String,
+    /// Configured meeting rooms, from the rooms file or the database
+    pub rooms: SharedRooms,
+    /// Signer for served images, if image signing is enabled
+    pub image_signer: Option<ImageSigner>,
+    /// Webhook URLs notifications are POSTed to, none to only log them
+    pub notify_webhook_urls: Vec<String>,
+    /// Missed check-ins after which a device is reported offline, 0 to never
+    /// report devices offline
+    pub notify_missed_check_ins: u32,
+    /// Failed refreshes in a row after which a room's calendar is reported as
+    /// failing, 0 to never report calendars
+    pub notify_calendar_failures: u32,
+    /// MQTT broker room states (e.g. do-not-disturb) are published to, if any
+    pub mqtt_url: Option<String>,
+    /// Prefix of the MQTT topics, e.g. `trmnl` for `trmnl/rooms/<id>/dnd`
+    pub mqtt_topic_prefix: String,
+    /// Storage backend for hosted images
+    pub image_store: ImageStoreConfig,
+    /// How rendered images are delivered to devices
+    pub image_delivery: ImageDelivery,
+    /// File format of images for devices that do not ask for one
+    pub image_format: ImageFormat,
+    /// How long signed hosted image URLs stay valid, in seconds
+    pub image_url_ttl_seconds: i64,
+    /// Identifier of this server instance, used for leader election
+    pub instance_id: String,
+    /// Whether new devices must present a claim code during setup
+    pub require_claim_code: bool,
+    /// Label packs for the display texts, including custom translations
+    pub labels: SharedLabels,
+    /// Directory with custom label packs, watched for changes
+    pub labels_dir: Option<String>,
+    /// Number of device log entries buffered before log requests are rejected
+    pub log_buffer_size: usize,
+    /// Whether log requests must carry the device's key
+    pub log_auth: LogAuth,
+    /// Days device log entries are kept, 0 keeps them forever
+    pub log_retention_days: u32,
+    /// Maximum number of device log entries kept, 0 for no limit
+    pub log_max_entries: usize,
+    /// Schedules of jobs replacing their built-in ones, by job name
+    pub job_schedules: Vec<(String, Schedule)>,
+    /// Error tracker 5xx responses, panics and background task failures are reported to
+    pub error_sink: Option<ErrorSink>,
+    /// Passthrough of unknown devices to the TRMNL cloud, if enabled
+    pub proxy: Option<ProxyConfig>,
+    /// Where display images are rendered
+    pub renderer: RendererConfig,
+    /// How long rendered images are kept for identical frames, in seconds
+    pub render_cache_ttl_seconds: u64,
+    /// How shades of gray, e.g. of watermarks, are reduced to black and white
+    pub dither: Dither,
+    /// Endpoint anonymous usage statistics are reported to, if opted in
+    pub telemetry_url: Option<String>,
+    /// Battery voltage below which displays ask for a battery replacement
+    pub battery_warning_voltage: f64,
+    /// Number of base64 payloads of inline images kept for repeated polls
+    pub inline_cache_size: usize,
+    /// Display and log requests allowed per device and minute, 0 for no limit
+    pub rate_limit_device_per_minute: u32,
+    /// Requests to unauthenticated routes allowed per client address and
+    /// minute, 0 for no limit
+    pub rate_limit_address_per_minute: u32,
+    /// Whether the client address is taken from the `X-Forwarded-For` header
+    /// of a reverse proxy
+    pub trust_forwarded_for: bool,
 }
 
-// Global config instance
-static CONFIG: OnceLock<Config> = OnceLock::new();
-
 impl Config {
-    /// Initialize configuration from environment variables
-    pub fn init() -> Result<&'static Config> {
+    /// Read the configuration from environment variables
+    pub fn from_env() -> Result<Config> {
         // Load .env file if it exists
         let _ = dotenv();
 
         // Get configuration from environment or use defaults
-        let config = Config {
+        let rooms_path = get_env_or_default("ROOMS_PATH", "rooms.toml".to_string());
+        let rooms = load_rooms(&rooms_path)?;
+        let image_signer = get_env_or::<String>("IMAGE_SIGNING_KEY")
+            .map(|key| ImageSigner::from_base64(&key))
+            .transpose()?;
+        let labels_dir = get_env_or::<String>("LABELS_DIR");
+        let labels = SharedLabels::new(Labels::load(labels_dir.as_deref())?);
+        Ok(Config {
             server_host: get_env_or_default("SERVER_HOST", "127.0.0.1".to_string()),
             server_port: get_env_or_default("SERVER_PORT", 8080),
             server_url: get_env_or("SERVER_URL")
                 .ok_or_else(|| anyhow::anyhow!("SERVER_URL environment variable is required"))?,
-            database_path: get_env_or_default("DATABASE_PATH", "devices.db".to_string()),
+            tls: tls_from_env()?,
+            database_url: get_env_or("DATABASE_URL")
+                .or_else(|| get_env_or("DATABASE_PATH"))
+                .unwrap_or_else(|| "devices.db".to_string()),
+            database_pool_size: get_env_or_default("DATABASE_POOL_SIZE", DEFAULT_POOL_SIZE),
             access_token: get_env_or("ACCESS_TOKEN")
                 .ok_or_else(|| anyhow::anyhow!("ACCESS_TOKEN environment variable is required"))?,
+            admin_token: admin_token_from_env()?,
             font_path: get_env_or_default("FONT_PATH", "assets/fonts/BlockKie.ttf".to_string()),
-            refresh_rate: get_env_or_default("REFRESH_RATE", 200),
-        };
+            refresh: refresh_rates_from_env()?,
+            calendar_refresh_minutes: get_env_or_default("CALENDAR_REFRESH_MINUTES", 5),
+            rooms_path,
+            rooms: SharedRooms::new(rooms),
+            image_signer,
+            notify_webhook_urls: notify_webhook_urls_from_env(),
+            notify_missed_check_ins: get_env_or_default("NOTIFY_MISSED_CHECK_INS", 3),
+            notify_calendar_failures: get_env_or_default("NOTIFY_CALENDAR_FAILURES", 3),
+            mqtt_url: get_env_or("MQTT_URL"),
+            mqtt_topic_prefix: get_env_or_default("MQTT_TOPIC_PREFIX", "trmnl".to_string()),
+            image_store: image_store_from_env()?,
+            image_delivery: get_env_or_default("IMAGE_DELIVERY", "inline".to_string()).parse()?,
+            image_format: get_env_or_default("IMAGE_FORMAT", "bmp".to_string()).parse()?,
+            image_url_ttl_seconds: get_env_or_default("IMAGE_URL_TTL_SECONDS", 3600),
+            instance_id: get_env_or("INSTANCE_ID").unwrap_or_else(default_instance_id),
+            require_claim_code: get_env_or_default("REQUIRE_CLAIM_CODE", false),
+            labels,
+            labels_dir,
+            log_buffer_size: get_env_or_default("LOG_BUFFER_SIZE", 1000),
+            log_auth: get_env_or_default("LOG_AUTH", "permissive".to_string()).parse()?,
+            log_retention_days: get_env_or_default("LOG_RETENTION_DAYS", 30),
+            log_max_entries: get_env_or_default("LOG_MAX_ENTRIES", 100_000),
+            job_schedules: job_schedules_from_env()?,
+            error_sink: error_sink_from_env()?,
+            proxy: proxy_from_env(),
+            renderer: renderer_from_env()?,
+            render_cache_ttl_seconds: get_env_or_default("RENDER_CACHE_TTL_SECONDS", 60),
+            dither: get_env_or_default("DITHER", "none".to_string()).parse()?,
+            telemetry_url: telemetry_url_from_env(),
+            battery_warning_voltage: battery_warning_voltage_from_env()?,
+            inline_cache_size: get_env_or_default("INLINE_CACHE_SIZE", 32),
+            rate_limit_device_per_minute: get_env_or_default("RATE_LIMIT_DEVICE_PER_MINUTE", 20),
+            rate_limit_address_per_minute: get_env_or_default("RATE_LIMIT_ADDRESS_PER_MINUTE", 60),
+            trust_forwarded_for: get_env_or_default("TRUST_FORWARDED_FOR", false),
+        })
+    }
+}
+
+/// Image store configuration from the `IMAGE_STORE*` and `S3_*` variables
+fn image_store_from_env() -> Result<ImageStoreConfig> {
+    match get_env_or_default("IMAGE_STORE", "memory".to_string()).as_str() {
+        "memory" => Ok(ImageStoreConfig::Memory),
+        "disk" => Ok(ImageStoreConfig::Disk {
+            path: get_env_or_default("IMAGE_STORE_PATH", "images".to_string()),
+        }),
+        "s3" => Ok(ImageStoreConfig::S3 {
+            bucket: get_env_or("S3_BUCKET").ok_or_else(|| {
+                anyhow::anyhow!("S3_BUCKET environment variable is required for IMAGE_STORE=s3")
+            })?,
+            region: get_env_or_default("S3_REGION", "us-east-1".to_string()),
+            endpoint: get_env_or("S3_ENDPOINT"),
+            prefix: get_env_or_default("S3_PREFIX", String::new()),
+        }),
+        other => Err(anyhow::anyhow!(
+            "Unknown IMAGE_STORE: {} (expected memory, disk or s3)",
+            other
+        )),
+    }
+}
 
-        // Store in global state
-        CONFIG.get_or_init(|| config);
-        Ok(CONFIG.get().unwrap())
+/// Admin token from `ADMIN_TOKEN`, which must differ from the `ACCESS_TOKEN`
+/// shared with the devices
+fn admin_token_from_env() -> Result<Option<String>> {
+    let admin_token = get_env_or::<String>("ADMIN_TOKEN").filter(|token| !token.is_empty());
+    if admin_token.is_some() && admin_token == get_env_or("ACCESS_TOKEN") {
+        return Err(anyhow::anyhow!(
+            "ADMIN_TOKEN must differ from ACCESS_TOKEN, which the devices know"
+        ));
     }
+    Ok(admin_token)
+}
+
+/// Certificate and key from `TLS_CERT_PATH` and `TLS_KEY_PATH`, which must be
+/// set together, and only on builds with the `tls` feature
+fn tls_from_env() -> Result<Option<TlsConfig>> {
+    match (
+        get_env_or::<String>("TLS_CERT_PATH").filter(|path| !path.is_empty()),
+        get_env_or::<String>("TLS_KEY_PATH").filter(|path| !path.is_empty()),
+    ) {
+        (Some(_), _) | (_, Some(_)) if !cfg!(feature = "tls") => Err(anyhow::anyhow!(
+            "TLS_CERT_PATH and TLS_KEY_PATH need a server built with the tls feature"
+        )),
+        (Some(cert_path), Some(key_path)) => Ok(Some(TlsConfig {
+            cert_path,
+            key_path,
+        })),
+        (None, None) => Ok(None),
+        _ => Err(anyhow::anyhow!(
+            "TLS_CERT_PATH and TLS_KEY_PATH must be set together"
+        )),
+    }
+}
 
-    /// Get global configuration, initializing if necessary
-    pub fn get() -> Result<&'static Config> {
-        if let Some(config) = CONFIG.get() {
-            Ok(config)
-        } else {
-            // In test environment, provide a default config
-            if cfg!(test) {
-                let test_config = Config {
-                    server_host: "127.0.0.1".to_string(),
-                    server_port: 8080,
-                    server_url: "http://127.0.0.1:8080".to_string(),
-                    database_path: "test_devices.db".to_string(),
-                    access_token: std::env::var("ACCESS_TOKEN")
-                        .unwrap_or_else(|_| "your-secret-access-token".to_string()),
-                    font_path: "assets/fonts/BlockKie.ttf".to_string(),
-                    refresh_rate: 200,
-                };
-                CONFIG.get_or_init(|| test_config);
-                Ok(CONFIG.get().unwrap())
-            } else {
-                Config::init()
-            }
-        }
+/// Error tracker from `SENTRY_DSN` or, for a generic HTTP sink, `ERROR_REPORT_URL`
+fn error_sink_from_env() -> Result<Option<ErrorSink>> {
+    match (
+        get_env_or::<String>("SENTRY_DSN"),
+        get_env_or::<String>("ERROR_REPORT_URL"),
+    ) {
+        (Some(_), Some(_)) => Err(anyhow::anyhow!(
+            "SENTRY_DSN and ERROR_REPORT_URL must not both be set"
+        )),
+        (Some(dsn), None) => ErrorSink::from_sentry_dsn(&dsn).map(Some),
+        (None, Some(url)) => Ok(Some(ErrorSink::Http { url })),
+        (None, None) => Ok(None),
     }
 }
 
+/// Renderer configuration from the `RENDERER` and `RENDERER_URL` variables
+fn renderer_from_env() -> Result<RendererConfig> {
+    match get_env_or_default("RENDERER", "local".to_string()).as_str() {
+        "local" => Ok(RendererConfig::Local),
+        "http" => Ok(RendererConfig::Http {
+            url: get_env_or("RENDERER_URL").ok_or_else(|| {
+                anyhow::anyhow!("RENDERER_URL environment variable is required for RENDERER=http")
+            })?,
+        }),
+        other => Err(anyhow::anyhow!(
+            "Unknown RENDERER: {} (expected local or http)",
+            other
+        )),
+    }
+}
+
+/// Passthrough to the TRMNL cloud from `TRMNL_PROXY_URL` and `TRMNL_PROXY_ROUTES`
+fn proxy_from_env() -> Option<ProxyConfig> {
+    let upstream = get_env_or::<String>("TRMNL_PROXY_URL").filter(|url| !url.is_empty())?;
+    let routes = get_env_or::<String>("TRMNL_PROXY_ROUTES")
+        .map(|routes| {
+            routes
+                .split(',')
+                .map(str::trim)
+                .filter(|route| !route.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(ProxyConfig { upstream, routes })
+}
+
+/// Webhook URLs from `NOTIFY_WEBHOOK_URL`, separated by commas
+fn notify_webhook_urls_from_env() -> Vec<String> {
+    get_env_or::<String>("NOTIFY_WEBHOOK_URL")
+        .map(|urls| {
+            urls.split(',')
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Usage statistics endpoint from `TELEMETRY_URL`, unless `DO_NOT_TRACK` is set
+fn telemetry_url_from_env() -> Option<String> {
+    let do_not_track = get_env_or::<String>("DO_NOT_TRACK")
+        .is_some_and(|value| !value.is_empty() && value != "0" && value != "false");
+    if do_not_track {
+        return None;
+    }
+    get_env_or::<String>("TELEMETRY_URL").filter(|url| !url.is_empty())
+}
+
+/// Battery warning threshold from `BATTERY_WARNING_VOLTAGE`
+///
+/// Must be above the voltage of an empty battery and below the one of a
+/// replaced battery, from which the warning disappears again.
+fn battery_warning_voltage_from_env() -> Result<f64> {
+    let voltage = get_env_or_default("BATTERY_WARNING_VOLTAGE", CRITICAL_BATTERY_VOLTAGE);
+    if voltage <= EMPTY_BATTERY_VOLTAGE || voltage >= BATTERY_RECOVERED_VOLTAGE {
+        return Err(anyhow::anyhow!(
+            "BATTERY_WARNING_VOLTAGE must be between {} and {} V, got {}",
+            EMPTY_BATTERY_VOLTAGE,
+            BATTERY_RECOVERED_VOLTAGE,
+            voltage
+        ));
+    }
+    Ok(voltage)
+}
+
+/// Refresh rates from `REFRESH_RATE` and `REFRESH_JITTER_PERCENT`
+fn refresh_rates_from_env() -> Result<RefreshRates> {
+    let defaults = RefreshRates::default();
+    RefreshRates::new(
+        get_env_or_default("REFRESH_RATE", defaults.default_rate),
+        get_env_or_default("REFRESH_JITTER_PERCENT", defaults.jitter_percent),
+    )
+    .map_err(|e| anyhow::anyhow!("Invalid REFRESH_RATE or REFRESH_JITTER_PERCENT: {}", e))
+}
+
+/// Job schedules from `JOB_SCHEDULES`, e.g. `watchdog=*/10 * * * *;log_retention=@daily`
+fn job_schedules_from_env() -> Result<Vec<(String, Schedule)>> {
+    let Some(schedules) = get_env_or::<String>("JOB_SCHEDULES") else {
+        return Ok(Vec::new());
+    };
+    schedules
+        .split(';')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (name, schedule) = entry.split_once('=').ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Invalid JOB_SCHEDULES entry: {} (expected name=schedule)",
+                    entry
+                )
+            })?;
+            let schedule = schedule
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid schedule of job {}: {}", name.trim(), e))?;
+            Ok((name.trim().to_string(), schedule))
+        })
+        .collect()
+}
+
+/// Instance identifier derived from the host name and process ID
+fn default_instance_id() -> String {
+    let host = env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    format!("{}-{}", host, std::process::id())
+}
+
 /// Get an environment variable or return a default value
 fn get_env_or_default<T: std::str::FromStr>(key: &str, default: T) -> T {
     get_env_or(key).unwrap_or(default)
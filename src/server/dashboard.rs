@@ -0,0 +1,298 @@
+//! Admin dashboard, an HTML overview of the fleet
+//!
+//! Lists the registered devices with their last check-in, battery, signal
+//! strength and firmware, and a preview of the image each device would
+//! currently receive. Devices
+//! that fail to draw their images are flagged with a suggested fix. Room
+//! previews show the image of a room as PNG, to design layouts in a browser.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{Html, IntoResponse, Response},
+};
+use base64::{Engine as _, engine::general_purpose};
+use chrono::{DateTime, Local};
+
+use super::AppState;
+use super::config::Config;
+use super::errors::AppError;
+use super::extract::{Admin, constant_time_eq};
+use super::handlers::device_frame;
+use super::report::{escape_html, page};
+use crate::bmp::ImageFormat;
+use crate::database::DeviceRecord;
+use crate::health::{DeviceStatus, LOW_BATTERY_VOLTAGE, Remediation};
+use crate::render::layout::Layout;
+use crate::rooms::{Room, resolve_device_room};
+
+/// Seconds after which browsers reload the dashboard
+const REFRESH_SECS: u32 = 60;
+
+/// Realm of the HTTP basic authentication of the dashboard
+const REALM: &str = "TRMNL admin";
+
+/// Whether a dashboard request carries the admin token
+///
+/// Browsers cannot send the `Access-Token` header when navigating, so the
+/// token is also accepted as password of HTTP basic authentication, with any
+/// user name.
+fn is_authorized(headers: &HeaderMap, config: &Config) -> bool {
+    let basic_password = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|credentials| general_purpose::STANDARD.decode(credentials.trim()).ok())
+        .and_then(|credentials| String::from_utf8(credentials).ok())
+        .and_then(|credentials| {
+            credentials
+                .split_once(':')
+                .map(|(_, password)| password.to_string())
+        });
+    let Some(admin_token) = &config.admin_token else {
+        return false;
+    };
+    basic_password.is_some_and(|password| constant_time_eq(&password, admin_token))
+        || Admin::check(headers, config).is_ok()
+}
+
+/// Response asking the browser for credentials
+fn challenge() -> Response {
+    let mut response =
+        AppError::Auth("Dashboard requires the admin token".to_string()).into_response();
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        HeaderValue::from_str(&format!("Basic realm=\"{}\"", REALM))
+            .expect("Realm is a valid header value"),
+    );
+    response
+}
+
+/// Format a Unix timestamp in local time
+fn format_time(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|time| {
+            time.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+/// Table row of a device
+fn device_row(device: &DeviceRecord, rooms: &[Room], now: i64) -> String {
+    let room = resolve_device_room(rooms, &device.id, device.room_id.as_deref())
+        .filter(|_| device.retired_at.is_none())
+        .map_or("-", |room| room.name.as_str());
+    let check_in = match DeviceStatus::of(device.last_seen_at, now) {
+        DeviceStatus::Pending => "never".to_string(),
+        DeviceStatus::Online => format_time(device.last_seen_at.unwrap_or_default()),
+        DeviceStatus::Offline => format!(
+            "{} (offline)",
+            format_time(device.last_seen_at.unwrap_or_default())
+        ),
+    };
+    let battery = match device.battery_voltage {
+        Some(voltage) if device.battery_critical => format!("{:.2} V (replace)", voltage),
+        Some(voltage) if voltage < LOW_BATTERY_VOLTAGE => format!("{:.2} V (low)", voltage),
+        Some(voltage) => format!("{:.2} V", voltage),
+        None => "-".to_string(),
+    };
+    let signal = device
+        .rssi
+        .map_or("-".to_string(), |rssi| format!("{} dBm", rssi));
+    let alert = match device.display_alert_at {
+        Some(since) => format!(
+            "<br><strong>Not drawing images since {}</strong><br><small>{}</small>",
+            format_time(since),
+            Remediation::for_format(device.last_payload_format.as_deref()).description()
+        ),
+        None => String::new(),
+    };
+    let preview = match &device.replaced_by {
+        Some(replaced_by) => format!("Replaced by {}", escape_html(replaced_by)),
+        None => format!(
+            "<img src=\"/admin/devices/{}/preview.bmp\" width=\"400\" height=\"240\" alt=\"Preview\">",
+            escape_html(&device.id)
+        ),
+    };
+    format!(
+        "<tr><td>{name}<br><small>{id}</small></td><td>{room}</td><td>{model}</td>\
+         <td>{firmware}</td><td>{check_in}{alert}</td><td>{battery}</td><td>{signal}</td><td>{preview}</td></tr>",
+        name = escape_html(device.name.as_deref().unwrap_or("-")),
+        id = escape_html(&device.id),
+        room = escape_html(room),
+        model = escape_html(device.model.as_deref().unwrap_or("-")),
+        firmware = escape_html(device.firmware_version.as_deref().unwrap_or("-")),
+        check_in = check_in,
+        alert = alert,
+        battery = battery,
+        signal = signal,
+        preview = preview,
+    )
+}
+
+/// Dashboard page listing all devices
+pub async fn dashboard_handler(
+    headers: HeaderMap,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let config = &state.config;
+    if !is_authorized(&headers, config) {
+        return Ok(challenge());
+    }
+
+    let devices = state
+        .database
+        .run_blocking(|db| {
+            db.list_devices()
+                .context("Failed to list devices")
+                .map_err(AppError::from)
+        })
+        .await?;
+    let rooms = config.rooms.snapshot();
+    let now = chrono::Utc::now().timestamp();
+    let rows: String = devices
+        .iter()
+        .map(|device| device_row(device, &rooms, now))
+        .collect();
+    let body = format!(
+        "<h1>Devices</h1>\n\
+         <table border=\"1\" cellpadding=\"4\">\n\
+         <tr><th>Device</th><th>Room</th><th>Model</th><th>Firmware</th>\
+         <th>Last check-in</th><th>Battery</th><th>Signal</th><th>Current image</th></tr>\n\
+         {rows}\n</table>\n\
+         <p><small>{count} devices, updated {updated}</small></p>",
+        rows = rows,
+        count = devices.len(),
+        updated = format_time(now),
+    );
+
+    Ok((
+        [(header::REFRESH, REFRESH_SECS.to_string())],
+        Html(page("Devices", &body)),
+    )
+        .into_response())
+}
+
+/// Image a device would receive with its next display request
+///
+/// Does not count as a check-in of the device.
+pub async fn device_preview_handler(
+    headers: HeaderMap,
+    Path(device_id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let config = &state.config;
+    if !is_authorized(&headers, config) {
+        return Ok(challenge());
+    }
+
+    let display_config = state
+        .display_config
+        .get()
+        .await
+        .context("Failed to load display configuration")
+        .map_err(AppError::from)?;
+    let Some(device) = display_config.device(&device_id) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let rooms = config.rooms.snapshot();
+    let room = resolve_device_room(&rooms, &device.id, device.room_id.as_deref());
+    let layout = room.map_or(Layout::default(), |room| {
+        display_config.device_layout(room, &device.id).0
+    });
+    let frame = device_frame(
+        room,
+        &display_config,
+        config,
+        &state.calendars,
+        layout,
+        device.battery_critical,
+        device.battery_voltage,
+    )
+    .await;
+
+    let fingerprint = frame.image_config.fingerprint();
+    let bmp_data = match state.frames.get(fingerprint) {
+        Some(bmp_data) => Arc::unwrap_or_clone(bmp_data),
+        None => state
+            .renderer
+            .render(frame.image_config)
+            .await
+            .with_context(|| format!("Failed to render preview of device {}", device_id))
+            .map_err(AppError::from)?,
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/bmp"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        bmp_data,
+    )
+        .into_response())
+}
+
+/// Image a device of a room would receive right now, as PNG
+///
+/// Served at `/preview/<room>.png`. Rendered with the layout of the room,
+/// without the battery warning or layout experiments of individual devices.
+pub async fn room_preview_handler(
+    headers: HeaderMap,
+    Path(file_name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let config = &state.config;
+    if !is_authorized(&headers, config) {
+        return Ok(challenge());
+    }
+
+    let rooms = config.rooms.snapshot();
+    let Some(room) = file_name
+        .strip_suffix(".png")
+        .and_then(|room_id| rooms.iter().find(|room| room.id == room_id))
+    else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let display_config = state
+        .display_config
+        .get()
+        .await
+        .context("Failed to load display configuration")
+        .map_err(AppError::from)?;
+    let mut frame = device_frame(
+        Some(room),
+        &display_config,
+        config,
+        &state.calendars,
+        room.layout,
+        false,
+        None,
+    )
+    .await;
+    frame.image_config.format = ImageFormat::Png;
+
+    let fingerprint = frame.image_config.fingerprint();
+    let png_data = match state.frames.get(fingerprint) {
+        Some(png_data) => Arc::unwrap_or_clone(png_data),
+        None => state
+            .renderer
+            .render(frame.image_config)
+            .await
+            .with_context(|| format!("Failed to render preview of room {}", room.id))
+            .map_err(AppError::from)?,
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/png"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        png_data,
+    )
+        .into_response())
+}
@@ -0,0 +1,371 @@
+//! Do-not-disturb for the meeting in progress in a room
+//!
+//! An admin can mark the current meeting of a room as do-not-disturb. Until
+//! the meeting ends, the room's displays show a prominent banner instead of
+//! the "ends in" warning. If an MQTT broker is configured, the state of every
+//! room is published as retained `on` or `off` to `<prefix>/rooms/<id>/dnd`,
+//! e.g. for lights next to the door.
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Context;
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::Local;
+use log::{debug, warn};
+use tracing::info;
+
+use super::AppState;
+use super::admin::extract_admin_user;
+use super::errors::AppError;
+use super::extract::Admin;
+use crate::api::types::DndResponse;
+use crate::config_cache::DisplayConfig;
+use crate::database::DndRecord;
+use crate::error_report::report_task_failure;
+use crate::mqtt::MqttPublisher;
+use crate::rooms::Room;
+
+/// How often the published states are checked, e.g. for meetings that ended
+const SYNC_INTERVAL_SECS: u64 = 30;
+
+/// Publisher of the do-not-disturb state of the rooms
+pub struct DndPublisher {
+    mqtt: Option<MqttPublisher>,
+    topic_prefix: String,
+    /// State last published successfully per room
+    ///
+    /// Held while publishing, so that the states of a room are sent one at a
+    /// time and in order.
+    published: tokio::sync::Mutex<HashMap<String, bool>>,
+}
+
+impl DndPublisher {
+    /// Publisher to the given broker, or one that publishes nothing
+    pub fn new(mqtt: Option<MqttPublisher>, topic_prefix: &str) -> Self {
+        Self {
+            mqtt,
+            topic_prefix: topic_prefix.trim_end_matches('/').to_string(),
+            published: tokio::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Publish the state of every room whose state changed since it was last
+    /// published
+    ///
+    /// The first call publishes the state of all rooms. A state that fails to
+    /// publish is sent again by the next call.
+    pub async fn sync(&self, rooms: &[Room], display_config: &DisplayConfig, now: i64) {
+        let mut published = self.published.lock().await;
+        self.publish_changes(&mut published, rooms, display_config, now)
+            .await;
+    }
+
+    async fn publish_changes(
+        &self,
+        published: &mut HashMap<String, bool>,
+        rooms: &[Room],
+        display_config: &DisplayConfig,
+        now: i64,
+    ) {
+        let Some(mqtt) = &self.mqtt else {
+            return;
+        };
+        for room in rooms {
+            let dnd = display_config.active_dnd(&room.id, now).is_some();
+            if published.get(&room.id) == Some(&dnd) {
+                continue;
+            }
+            let topic = format!("{}/rooms/{}/dnd", self.topic_prefix, room.id);
+            let payload = if dnd { "on" } else { "off" };
+            match mqtt.send(&topic, payload.as_bytes(), true).await {
+                Ok(()) => {
+                    debug!("Published {} to MQTT topic {}", payload, topic);
+                    published.insert(room.id.clone(), dnd);
+                }
+                Err(e) => warn!("Failed to publish to MQTT topic {}: {:#}", topic, e),
+            }
+        }
+    }
+
+    /// Like [`DndPublisher::sync`], with the current configuration of the server
+    ///
+    /// The configuration is read once it is this call's turn to publish, so
+    /// that concurrent calls cannot publish an outdated state last.
+    async fn sync_state(&self, state: &AppState) -> anyhow::Result<()> {
+        let mut published = self.published.lock().await;
+        let display_config = state
+            .display_config
+            .get()
+            .await
+            .context("Failed to load display configuration")?;
+        self.publish_changes(
+            &mut published,
+            &state.config.rooms.snapshot(),
+            &display_config,
+            chrono::Utc::now().timestamp(),
+        )
+        .await;
+        Ok(())
+    }
+
+    /// Publish the changed states in the background, e.g. after a request
+    /// changed one, so that the response does not wait for the broker
+    fn spawn_sync(state: &AppState) {
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = state.dnd.sync_state(&state).await {
+                warn!("Failed to publish do-not-disturb states: {:#}", e);
+            }
+        });
+    }
+}
+
+/// Do-not-disturb endpoint handler
+///
+/// Marks the meeting in progress in a room. Rejected if the room has no
+/// meeting in progress.
+pub async fn set_dnd_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let config = &state.config;
+    let admin_user = extract_admin_user(&headers)?;
+
+    let rooms = config.rooms.snapshot();
+    let Some(room) = rooms.iter().find(|room| room.id == room_id) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let Some(url) = &room.calendar_url else {
+        return Err(AppError::BadRequest(format!(
+            "Room {} has no calendar",
+            room.id
+        )));
+    };
+    let events = state
+        .calendars
+        .future_events(
+            &room.id,
+            url,
+            &room.calendar_source,
+            room.fallback_calendar.as_ref(),
+            room.deduplicate,
+        )
+        .await
+        .with_context(|| format!("Failed to get calendar of room {}", room.id))
+        .map_err(AppError::from)?;
+    let now = Local::now();
+    let Some(current) = events
+        .iter()
+        .find(|e| now >= e.start_time && now < e.end_time)
+    else {
+        return Err(AppError::BadRequest(format!(
+            "Room {} has no meeting in progress",
+            room.id
+        )));
+    };
+
+    let (room_id, meeting, ends_at, by) = (
+        room.id.clone(),
+        current.name.clone(),
+        current.end_time.timestamp(),
+        admin_user.clone(),
+    );
+    let mark = state
+        .database
+        .run_blocking(move |db| {
+            db.set_room_dnd(&room_id, &meeting, ends_at, &by)
+                .context("Failed to set do-not-disturb")
+                .map_err(AppError::from)
+        })
+        .await?;
+    info!(
+        "Meeting {} in room {} marked as do-not-disturb by {}",
+        mark.meeting, mark.room_id, admin_user
+    );
+    DndPublisher::spawn_sync(&state);
+
+    Ok(Json(DndResponse::from(mark)).into_response())
+}
+
+/// Do-not-disturb clearing endpoint handler
+pub async fn clear_dnd_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(room_id): Path<String>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, AppError> {
+    let admin_user = extract_admin_user(&headers)?;
+
+    let cleared = {
+        let (room_id, by) = (room_id.clone(), admin_user.clone());
+        state
+            .database
+            .run_blocking(move |db| {
+                db.clear_room_dnd(&room_id, &by)
+                    .context("Failed to clear do-not-disturb")
+                    .map_err(AppError::from)
+            })
+            .await?
+    };
+    if !cleared {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+
+    info!(
+        "Do-not-disturb of room {} cleared by {}",
+        room_id, admin_user
+    );
+    DndPublisher::spawn_sync(&state);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Background task publishing do-not-disturb states, e.g. once a marked
+/// meeting ended or another instance marked a meeting
+pub async fn run_dnd_task(state: AppState) {
+    let mut interval = tokio::time::interval(Duration::from_secs(SYNC_INTERVAL_SECS));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if let Err(e) = state.dnd.sync_state(&state).await {
+            report_task_failure(
+                state.errors.as_ref(),
+                "dnd",
+                format!("Failed to publish do-not-disturb states: {:#}", e),
+            );
+        }
+    }
+}
+
+impl From<DndRecord> for DndResponse {
+    fn from(record: DndRecord) -> Self {
+        Self {
+            room_id: record.room_id,
+            meeting: record.meeting,
+            expires_at: record.expires_at,
+            set_by: record.set_by,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::config_cache::ConfigCache;
+    use crate::database::Database;
+    use crate::rooms::parse_rooms;
+
+    #[tokio::test]
+    async fn test_publish_changes() {
+        // Broker accepting every connection, forwarding the packets it got
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, mut received) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    let mut header = [0u8; 2];
+                    stream.read_exact(&mut header).await.unwrap();
+                    let mut connect = vec![0u8; header[1] as usize];
+                    stream.read_exact(&mut connect).await.unwrap();
+                    stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+                    let mut rest = Vec::new();
+                    stream.read_to_end(&mut rest).await.unwrap();
+                    sender
+                        .send(String::from_utf8_lossy(&rest).to_string())
+                        .unwrap();
+                });
+            }
+        });
+
+        let mqtt = MqttPublisher::from_url(&format!("mqtt://127.0.0.1:{}", port), "test").unwrap();
+        let publisher = DndPublisher::new(Some(mqtt), "office/");
+        let rooms = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+            "#,
+        )
+        .unwrap();
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        let cache = ConfigCache::new(db.clone(), Duration::from_secs(3600));
+
+        // The first sync publishes every room
+        publisher
+            .sync(&rooms, &cache.get_blocking().unwrap(), 0)
+            .await;
+        assert!(
+            received
+                .recv()
+                .await
+                .unwrap()
+                .contains("office/rooms/room-a/dndoff")
+        );
+
+        let expires_at = chrono::Utc::now().timestamp() + 600;
+        let mark = db
+            .set_room_dnd("room-a", "Board meeting", expires_at, "test")
+            .unwrap();
+        publisher
+            .sync(&rooms, &cache.get_blocking().unwrap(), mark.set_at)
+            .await;
+        assert!(
+            received
+                .recv()
+                .await
+                .unwrap()
+                .contains("office/rooms/room-a/dndon")
+        );
+        // Unchanged states are not published again
+        publisher
+            .sync(&rooms, &cache.get_blocking().unwrap(), mark.set_at)
+            .await;
+        // The mark ends with the meeting
+        publisher
+            .sync(&rooms, &cache.get_blocking().unwrap(), expires_at)
+            .await;
+        assert!(received.recv().await.unwrap().contains("dndoff"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_publish_is_retried() {
+        // Nothing listens on the port of a dropped listener
+        let port = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let mqtt = MqttPublisher::from_url(&format!("mqtt://127.0.0.1:{}", port), "test").unwrap();
+        let publisher = DndPublisher::new(Some(mqtt), "office");
+        let rooms = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+            "#,
+        )
+        .unwrap();
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        let cache = ConfigCache::new(db, Duration::from_secs(3600));
+
+        publisher
+            .sync(&rooms, &cache.get_blocking().unwrap(), 0)
+            .await;
+        assert!(publisher.published.lock().await.is_empty());
+    }
+}
@@ -0,0 +1,348 @@
+//! Validation of a deployment without serving requests
+//!
+//! With `--dry-run`, the server starts up as far as it can without touching
+//! the deployment: the database migrations run on a copy of the database (a
+//! Postgres database is only checked to be at the latest schema version), the
+//! calendar of every room is fetched and the frame of every room is rendered.
+//! Instead of listening for requests, it prints a report and exits, with a
+//! non-zero status if anything failed. This is meant for CI, to validate the
+//! configuration of a deployment before it is rolled out.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, bail};
+use rusqlite::{Connection, OpenFlags};
+
+use super::AppState;
+use super::config::Config;
+use super::handlers::device_frame;
+use crate::calendar::CalendarRegistry;
+use crate::database::{Database, init_database, is_postgres_url};
+use crate::rooms::Room;
+
+/// Outcome of a single check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckOutcome {
+    Passed(String),
+    /// Not fatal, the server would start, e.g. a missing label pack
+    Warning(String),
+    Failed(String),
+}
+
+/// Check of a dry run, e.g. the calendar of a room
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    /// What was checked, e.g. `calendar room-a`
+    pub name: String,
+    pub outcome: CheckOutcome,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, outcome: CheckOutcome) -> Self {
+        Self {
+            name: name.into(),
+            outcome,
+        }
+    }
+}
+
+/// Checks of a dry run, in the order they ran
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DryRunReport {
+    pub checks: Vec<Check>,
+}
+
+impl DryRunReport {
+    /// Whether no check failed
+    pub fn passed(&self) -> bool {
+        !self
+            .checks
+            .iter()
+            .any(|check| matches!(check.outcome, CheckOutcome::Failed(_)))
+    }
+}
+
+impl fmt::Display for DryRunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let (status, details) = match &check.outcome {
+                CheckOutcome::Passed(details) => ("ok", details),
+                CheckOutcome::Warning(details) => ("WARN", details),
+                CheckOutcome::Failed(details) => ("FAIL", details),
+            };
+            writeln!(f, "{:<4} {}: {}", status, check.name, details)?;
+        }
+        let failed = self
+            .checks
+            .iter()
+            .filter(|check| matches!(check.outcome, CheckOutcome::Failed(_)))
+            .count();
+        if failed == 0 {
+            writeln!(f, "Dry run passed ({} checks)", self.checks.len())
+        } else {
+            writeln!(
+                f,
+                "Dry run failed ({} of {} checks)",
+                failed,
+                self.checks.len()
+            )
+        }
+    }
+}
+
+/// Copy a SQLite database to a path that must not exist yet
+///
+/// The copy is a consistent snapshot, even while a server writes to the
+/// database.
+fn copy_database(path: &str, copy: &Path) -> Result<()> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Failed to open database at {}", path))?;
+    conn.execute(
+        "VACUUM INTO ?1",
+        [copy.to_str().context("Non-UTF-8 temporary path")?],
+    )
+    .with_context(|| format!("Failed to copy database at {}", path))?;
+    Ok(())
+}
+
+/// Temporary copy of the database, removed with its WAL files when dropped
+struct DatabaseCopy(PathBuf);
+
+impl Drop for DatabaseCopy {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.0.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Open a Postgres database for the checks, if it needs no migration
+///
+/// The database is shared with the running instances and cannot be copied.
+/// If it would be migrated, the checks run on a new in-memory database
+/// instead, with a warning.
+fn open_postgres(config: &Config) -> Result<(Arc<Database>, CheckOutcome)> {
+    let database = Database::connect(&config.database_url, config.database_pool_size)?;
+    let version = database.schema_version()?;
+    let latest = Database::latest_schema_version();
+    if version > latest {
+        bail!(
+            "Database schema version {} is newer than the supported version {}",
+            version,
+            latest
+        );
+    }
+    if version == latest {
+        return Ok((
+            Arc::new(database),
+            CheckOutcome::Passed(format!("schema version {}", version)),
+        ));
+    }
+    Ok((
+        init_database(":memory:", 1)?,
+        CheckOutcome::Warning(format!(
+            "would be migrated from schema version {} to {}, checked with a new database",
+            version, latest
+        )),
+    ))
+}
+
+/// Run all checks of a dry run with the given configuration
+pub async fn dry_run(config: Arc<Config>) -> DryRunReport {
+    let mut report = DryRunReport::default();
+
+    let copy =
+        DatabaseCopy(std::env::temp_dir().join(format!("trmnl-dry-run-{}.db", std::process::id())));
+    let _ = std::fs::remove_file(&copy.0);
+    let database = if is_postgres_url(&config.database_url) {
+        open_postgres(&config)
+    } else {
+        let copied =
+            if config.database_url != ":memory:" && Path::new(&config.database_url).exists() {
+                copy_database(&config.database_url, &copy.0)
+                    .map(|_| format!("migrated a copy of {}", config.database_url))
+            } else {
+                Ok("created a new database".to_string())
+            };
+        copied.and_then(|details| {
+            let path = copy.0.to_str().context("Non-UTF-8 temporary path")?;
+            init_database(path, config.database_pool_size)
+                .map(|database| (database, CheckOutcome::Passed(details)))
+        })
+    };
+    let database = match database {
+        Ok((database, outcome)) => {
+            report.checks.push(Check::new("database", outcome));
+            database
+        }
+        Err(e) => {
+            report.checks.push(Check::new(
+                "database",
+                CheckOutcome::Failed(format!("{:#}", e)),
+            ));
+            return report;
+        }
+    };
+
+    // Rooms imported into the database take precedence over the rooms file
+    match database.list_rooms() {
+        Ok(rooms) if !rooms.is_empty() => {
+            config.rooms.replace(rooms);
+        }
+        Ok(_) => {}
+        Err(e) => {
+            report.checks.push(Check::new(
+                "rooms",
+                CheckOutcome::Failed(format!("Failed to load rooms from database: {:#}", e)),
+            ));
+            return report;
+        }
+    }
+    let rooms = config.rooms.snapshot();
+    report.checks.push(Check::new(
+        "rooms",
+        if rooms.is_empty() {
+            CheckOutcome::Failed(format!("no rooms configured in {}", config.rooms_path))
+        } else {
+            CheckOutcome::Passed(format!("{} rooms", rooms.len()))
+        },
+    ));
+
+    match AppState::new(database, config.clone()) {
+        Ok(state) => report.checks.extend(check_rooms(state, &rooms).await),
+        Err(e) => report.checks.push(Check::new(
+            "server",
+            CheckOutcome::Failed(format!("{:#}", e)),
+        )),
+    }
+    report
+}
+
+/// Fetch the calendar and render the frame of every room
+///
+/// Calendars are always fetched, rather than read from the cache shared by
+/// the instances of a deployment.
+pub async fn check_rooms(mut state: AppState, rooms: &[Room]) -> Vec<Check> {
+    state.calendars = Arc::new(CalendarRegistry::new(state.config.calendar_refresh_minutes));
+    let mut checks = Vec::new();
+    let display_config = match state.display_config.get().await {
+        Ok(display_config) => display_config,
+        Err(e) => {
+            checks.push(Check::new(
+                "display configuration",
+                CheckOutcome::Failed(format!("{:#}", e)),
+            ));
+            return checks;
+        }
+    };
+
+    for room in rooms {
+        if !state.config.labels.snapshot().has_language(&room.language) {
+            checks.push(Check::new(
+                format!("labels {}", room.id),
+                CheckOutcome::Warning(format!(
+                    "no labels for language {}, using English",
+                    room.language
+                )),
+            ));
+        }
+
+        if let Some(url) = &room.calendar_url {
+            let events = state
+                .calendars
+                .refresh(
+                    &room.id,
+                    url,
+                    &room.calendar_source,
+                    room.fallback_calendar.as_ref(),
+                    room.deduplicate,
+                )
+                .await;
+            checks.push(Check::new(
+                format!("calendar {}", room.id),
+                match events {
+                    Ok(events) => CheckOutcome::Passed(format!("{} upcoming events", events.len())),
+                    Err(e) => CheckOutcome::Failed(e.to_string()),
+                },
+            ));
+        }
+
+        let frame = device_frame(
+            Some(room),
+            &display_config,
+            &state.config,
+            &state.calendars,
+            room.layout,
+            false,
+            None,
+        )
+        .await;
+        checks.push(Check::new(
+            format!("render {}", room.id),
+            match state.renderer.render(frame.image_config).await {
+                Ok(image) => CheckOutcome::Passed(format!("{} bytes", image.len())),
+                Err(e) => CheckOutcome::Failed(format!("{:#}", e)),
+            },
+        ));
+    }
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_database() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("trmnl-dry-run-source-{}.db", std::process::id()));
+        let copy = dir.join(format!("trmnl-dry-run-copy-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&copy);
+        {
+            let db = Database::new(path.to_str().unwrap()).unwrap();
+            db.register_device("AA:BB:CC:DD:EE:FF").unwrap();
+        }
+
+        copy_database(path.to_str().unwrap(), &copy).unwrap();
+        let db = Database::new(copy.to_str().unwrap()).unwrap();
+        assert!(db.get_device("AA:BB:CC:DD:EE:FF").unwrap().is_some());
+        drop(db);
+        drop(DatabaseCopy(path));
+        drop(DatabaseCopy(copy));
+    }
+
+    #[test]
+    fn test_report() {
+        let mut report = DryRunReport {
+            checks: vec![
+                Check::new("rooms", CheckOutcome::Passed("2 rooms".to_string())),
+                Check::new(
+                    "labels room-a",
+                    CheckOutcome::Warning("no labels for language rm".to_string()),
+                ),
+            ],
+        };
+        assert!(report.passed());
+        assert_eq!(
+            report.to_string(),
+            "ok   rooms: 2 rooms\nWARN labels room-a: no labels for language rm\nDry run passed (2 checks)\n"
+        );
+
+        report.checks.push(Check::new(
+            "calendar room-a",
+            CheckOutcome::Failed("timeout".to_string()),
+        ));
+        assert!(!report.passed());
+        assert!(
+            report
+                .to_string()
+                .ends_with("Dry run failed (1 of 3 checks)\n")
+        );
+    }
+}
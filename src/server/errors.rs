@@ -1,50 +1,203 @@
 use anyhow::Error as AnyhowError;
 use axum::{
     Json,
-    http::StatusCode,
+    http::{HeaderValue, StatusCode, header},
     response::{IntoResponse, Response},
 };
 use serde::Serialize;
 use thiserror::Error;
 
+use crate::database::PoolTimeout;
+use crate::error_code::ErrorCode;
+
+/// Seconds after which clients should retry an overloaded endpoint
+const RETRY_AFTER_SECONDS: &str = "5";
+
 /// Custom API error type
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("Authentication failed: {0}")]
     Auth(String),
 
+    #[error("Authentication failed: device {0} not registered")]
+    UnknownDevice(String),
+
     #[error("Configuration error: {0}")]
     Config(String),
 
     #[error("Bad request: {0}")]
     BadRequest(String),
 
+    #[error("Payload too large: {0}")]
+    TooLarge(String),
+
+    #[error("Too many requests: {0}")]
+    Overloaded(String),
+
+    #[error("Too many requests, retry in {0} seconds")]
+    RateLimited(u64),
+
+    #[error("Upstream error: {0}")]
+    Upstream(String),
+
+    #[error("Service unavailable: {0}")]
+    Unavailable(String),
+
     #[error("{0}")]
-    Anyhow(#[from] AnyhowError),
+    Anyhow(AnyhowError),
+}
+
+impl From<AnyhowError> for AppError {
+    /// Requests that found every database connection in use are rejected as overload
+    fn from(error: AnyhowError) -> Self {
+        if error.chain().any(|cause| cause.is::<PoolTimeout>()) {
+            AppError::Overloaded(PoolTimeout.to_string())
+        } else {
+            AppError::Anyhow(error)
+        }
+    }
 }
 
+impl AppError {
+    /// Short code of the error, returned in the response body
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::Auth(_) => ErrorCode::Unauthorized,
+            AppError::UnknownDevice(_) => ErrorCode::DeviceNotRegistered,
+            AppError::Config(_) => ErrorCode::Config,
+            AppError::BadRequest(_) => ErrorCode::BadRequest,
+            AppError::TooLarge(_) => ErrorCode::TooLarge,
+            AppError::Overloaded(_) => ErrorCode::Overloaded,
+            AppError::RateLimited(_) => ErrorCode::RateLimited,
+            AppError::Upstream(_) => ErrorCode::Upstream,
+            AppError::Unavailable(_) => ErrorCode::Unavailable,
+            AppError::Anyhow(e) => ErrorCode::of(e).unwrap_or(ErrorCode::Internal),
+        }
+    }
+}
+
+/// Message of the error a response was created from
+///
+/// Attached to error responses as an extension, so that 5xx responses can be
+/// reported with the underlying error.
+#[derive(Debug, Clone)]
+pub struct ErrorDetail(pub String);
+
 /// Error response structure
 #[derive(Serialize)]
 pub struct ErrorResponse {
     pub error: String,
+    /// HTTP status code
     pub code: u16,
+    /// Short code of the error, e.g. `CAL-01`
+    pub error_code: ErrorCode,
 }
 
 /// Implement conversion from AppError to axum::response::Response
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let status = match self {
-            AppError::Auth(_) => StatusCode::UNAUTHORIZED,
+            AppError::Auth(_) | AppError::UnknownDevice(_) => StatusCode::UNAUTHORIZED,
             AppError::Config(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::Overloaded(_) | AppError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             AppError::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
+        let retry_after = match &self {
+            AppError::RateLimited(seconds) => HeaderValue::from(*seconds),
+            _ => HeaderValue::from_static(RETRY_AFTER_SECONDS),
+        };
 
+        // Keep the whole context chain for error reports
+        let detail = match &self {
+            AppError::Anyhow(e) => format!("{:#}", e),
+            other => other.to_string(),
+        };
         let error_response = ErrorResponse {
             error: self.to_string(),
             code: status.as_u16(),
+            error_code: self.code(),
         };
 
-        (status, Json(error_response)).into_response()
+        let mut response = (status, Json(error_response)).into_response();
+        response.extensions_mut().insert(ErrorDetail(detail));
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, retry_after);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_status_codes() {
+        let cases = [
+            (AppError::Auth("x".into()), StatusCode::UNAUTHORIZED),
+            (
+                AppError::UnknownDevice("x".into()),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                AppError::Config("x".into()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (AppError::BadRequest("x".into()), StatusCode::BAD_REQUEST),
+            (
+                AppError::TooLarge("x".into()),
+                StatusCode::PAYLOAD_TOO_LARGE,
+            ),
+            (
+                AppError::Overloaded("x".into()),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (AppError::RateLimited(7), StatusCode::TOO_MANY_REQUESTS),
+            (AppError::Upstream("x".into()), StatusCode::BAD_GATEWAY),
+            (
+                AppError::Unavailable("x".into()),
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (
+                AppError::from(anyhow::anyhow!("x")),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+            (
+                AppError::from(anyhow::Error::new(PoolTimeout).context("Failed to list devices")),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+        ];
+        for (error, status) in cases {
+            let response = error.into_response();
+            assert_eq!(response.status(), status);
+            assert_eq!(
+                response.headers().get(header::RETRY_AFTER).is_some(),
+                status == StatusCode::TOO_MANY_REQUESTS
+            );
+        }
+    }
+
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(
+            AppError::UnknownDevice("AA:BB:CC:DD:EE:FF".into()).code(),
+            ErrorCode::DeviceNotRegistered
+        );
+        let calendar_error = anyhow::Error::new(crate::calendar::CalendarError::NoEventsError)
+            .context("Failed to get calendar");
+        assert_eq!(
+            AppError::from(calendar_error).code(),
+            ErrorCode::CalendarEmpty
+        );
+        assert_eq!(
+            AppError::from(anyhow::anyhow!("x")).code(),
+            ErrorCode::Internal
+        );
     }
 }
@@ -0,0 +1,233 @@
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{HeaderMap, request::Parts},
+};
+use tracing::info;
+
+use super::config::Config;
+use super::errors::AppError;
+use crate::database::DeviceRecord;
+use crate::device_id::DeviceId;
+
+/// Value of a header that must be present, rejected as an authentication error otherwise
+pub fn required_header<'a>(headers: &'a HeaderMap, name: &str) -> Result<&'a str, AppError> {
+    headers
+        .get(name)
+        .ok_or_else(|| AppError::Auth(format!("Missing {} header", name)))?
+        .to_str()
+        .map_err(|e| AppError::Auth(format!("Invalid {} header format: {}", name, e)))
+}
+
+/// Compare a secret in constant time, so that response times do not reveal
+/// how much of a guessed token is right
+///
+/// Only the length of the secret may leak.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Normalized device ID from the `ID` header
+///
+/// Legacy IDs that are neither a MAC address nor a UUID are passed through as
+/// sent, so that devices registered before IDs were validated keep working.
+/// Empty IDs are rejected as bad requests.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for DeviceId {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let id = DeviceId::lenient(required_header(&parts.headers, "ID")?);
+        if id.as_str().is_empty() {
+            return Err(AppError::BadRequest("Empty ID header".to_string()));
+        }
+        Ok(id)
+    }
+}
+
+/// Proof that the request carries the configured `Access-Token`
+///
+/// Put it before other extractors, so that unauthenticated requests are
+/// rejected before anything else is looked at.
+#[derive(Debug, Clone, Copy)]
+pub struct Authorized;
+
+impl Authorized {
+    /// Check the `Access-Token` header against the configured token
+    pub fn check(headers: &HeaderMap, config: &Config) -> Result<Self, AppError> {
+        let token = required_header(headers, "Access-Token")?;
+        if !constant_time_eq(token, &config.access_token) {
+            info!("Header validation failed");
+            return Err(AppError::Auth("Invalid Access-Token".to_string()));
+        }
+        Ok(Authorized)
+    }
+
+    /// Check the `Access-Token` header of a device against its API key
+    ///
+    /// Devices set up before they got a key of their own use the configured
+    /// token until their next setup.
+    pub fn check_device(
+        headers: &HeaderMap,
+        config: &Config,
+        device: &DeviceRecord,
+    ) -> Result<Self, AppError> {
+        let token = required_header(headers, "Access-Token")?;
+        if let Some(replaced_by) = &device.replaced_by {
+            info!("Rejected retired device {}", device.id);
+            return Err(AppError::Auth(format!(
+                "Device {} was replaced by {}",
+                device.id, replaced_by
+            )));
+        }
+        let valid = match (&device.api_key, device.api_key_revoked_at) {
+            (Some(api_key), _) => constant_time_eq(token, api_key),
+            (None, Some(_)) => {
+                info!("Rejected device {} with revoked API key", device.id);
+                return Err(AppError::Auth(format!(
+                    "API key of device {} was revoked",
+                    device.id
+                )));
+            }
+            (None, None) => constant_time_eq(token, &config.access_token),
+        };
+        if !valid {
+            info!("Header validation failed for device {}", device.id);
+            return Err(AppError::Auth("Invalid Access-Token".to_string()));
+        }
+        Ok(Authorized)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Authorized
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // Reject requests without a token without touching the configuration
+        required_header(&parts.headers, "Access-Token")?;
+        let config = Arc::<Config>::from_ref(state);
+        Authorized::check(&parts.headers, &config)
+    }
+}
+
+/// Proof that the request carries the configured `ADMIN_TOKEN` as its
+/// `Access-Token`
+///
+/// Required by the admin API. The `ACCESS_TOKEN` shared with the devices is
+/// not accepted, and without an `ADMIN_TOKEN` the admin API is disabled. Put
+/// it before other extractors, like [`Authorized`].
+#[derive(Debug, Clone, Copy)]
+pub struct Admin;
+
+impl Admin {
+    /// Check the `Access-Token` header against the configured admin token
+    pub fn check(headers: &HeaderMap, config: &Config) -> Result<Self, AppError> {
+        let token = required_header(headers, "Access-Token")?;
+        let Some(admin_token) = &config.admin_token else {
+            return Err(AppError::Auth(
+                "Admin API is disabled, set ADMIN_TOKEN to enable it".to_string(),
+            ));
+        };
+        if !constant_time_eq(token, admin_token) {
+            info!("Admin token validation failed");
+            return Err(AppError::Auth("Invalid Access-Token".to_string()));
+        }
+        Ok(Admin)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Admin
+where
+    S: Send + Sync,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        required_header(&parts.headers, "Access-Token")?;
+        let config = Arc::<Config>::from_ref(state);
+        Admin::check(&parts.headers, &config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderValue, Request};
+
+    use super::*;
+
+    fn parts(headers: &[(&str, HeaderValue)]) -> Parts {
+        let mut request = Request::builder().uri("/api/display");
+        for (name, value) in headers {
+            request = request.header(*name, value.clone());
+        }
+        request.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("secret-token", "secret-token"));
+        assert!(!constant_time_eq("secret-token", "secret-tokem"));
+        assert!(!constant_time_eq("secret-token", "secret"));
+        assert!(constant_time_eq("", ""));
+    }
+
+    #[tokio::test]
+    async fn test_device_id_extractor() {
+        let mut with_id = parts(&[("ID", HeaderValue::from_static("aa-bb-cc-dd-ee-ff"))]);
+        let id = DeviceId::from_request_parts(&mut with_id, &())
+            .await
+            .unwrap();
+        assert_eq!(id.as_str(), "AA:BB:CC:DD:EE:FF");
+
+        // Legacy IDs are passed through
+        let mut legacy = parts(&[("ID", HeaderValue::from_static("test-device"))]);
+        let id = DeviceId::from_request_parts(&mut legacy, &())
+            .await
+            .unwrap();
+        assert_eq!(id.as_str(), "test-device");
+        assert_eq!(id.kind(), None);
+
+        let mut empty = parts(&[("ID", HeaderValue::from_static(" "))]);
+        let rejected = DeviceId::from_request_parts(&mut empty, &()).await;
+        assert!(matches!(rejected, Err(AppError::BadRequest(msg)) if msg == "Empty ID header"));
+
+        let missing = DeviceId::from_request_parts(&mut parts(&[]), &()).await;
+        assert!(matches!(missing, Err(AppError::Auth(msg)) if msg == "Missing ID header"));
+
+        let mut non_ascii = parts(&[("ID", HeaderValue::from_bytes(b"\xff").unwrap())]);
+        let invalid = DeviceId::from_request_parts(&mut non_ascii, &()).await;
+        assert!(
+            matches!(invalid, Err(AppError::Auth(msg)) if msg.starts_with("Invalid ID header"))
+        );
+    }
+
+    /// State whose configuration must not be read
+    struct NoConfig;
+
+    impl FromRef<NoConfig> for Arc<Config> {
+        fn from_ref(_: &NoConfig) -> Self {
+            unreachable!("configuration read before the token was checked")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authorized_extractor_requires_token() {
+        let missing = Authorized::from_request_parts(&mut parts(&[]), &NoConfig).await;
+        assert!(
+            matches!(missing, Err(AppError::Auth(msg)) if msg == "Missing Access-Token header")
+        );
+    }
+}
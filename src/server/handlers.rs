@@ -1,176 +1,940 @@
-use std::{fs::OpenOptions, io::Write, sync::Arc};
+use std::{sync::Arc, time::Instant};
 
 use anyhow::Context;
 use axum::{
-    body::Bytes,
-    extract::State,
-    http::{HeaderMap, StatusCode},
-    response::{IntoResponse, Json},
+    body::{Body, Bytes},
+    extract::{Extension, Path, Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Json, Response},
 };
 use base64::{Engine as _, engine::general_purpose};
-use serde::{Deserialize, Serialize};
-use tracing::{error, info, warn};
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use tracing::{Instrument, Span, debug_span, info, instrument, warn};
 
+use super::AppState;
 use super::config::Config;
 use super::errors::AppError;
-use crate::bmp::{ImageConfig, generate_bmp};
-use crate::database::Database;
-
-/// Success response structure
-#[derive(Serialize, Deserialize)]
-pub struct SetupResponse {
-    /// Status code, should be 200
-    pub status: u16,
-    /// API key for the device
-    pub api_key: String,
-    /// Friendly ID for the device
-    pub friendly_id: String,
-    /// Image to show on the setup screen (BMP, 800x480px)
-    pub image_url: String,
-}
-
-/// Display response structure
-#[derive(Serialize, Deserialize)]
-pub struct DisplayResponse {
-    pub filename: String,
-    pub image_url: String,
-    pub image_url_timeout: u32,
-    pub refresh_rate: u32,
-}
-
-/// Device structure
-#[derive(Serialize, Deserialize)]
-pub struct Device {
-    pub id: String,
-    pub registered_at: i64,
-}
-
-/// Extract device ID from headers
-pub fn extract_device_id(headers: &HeaderMap) -> Result<String, AppError> {
-    Ok(headers
-        .get("ID")
-        .ok_or_else(|| AppError::Auth("Missing ID header".to_string()))?
-        .to_str()
-        .map_err(|e| AppError::Auth(format!("Invalid ID header format: {}", e)))?
-        .to_string())
-}
-
-/// Extract and validate access token in headers
-pub fn validate_headers(headers: &HeaderMap, config: &Config) -> Result<(), AppError> {
-    // Validate access token
-    let token = headers
-        .get("Access-Token")
-        .ok_or_else(|| AppError::Auth("Missing Access-Token header".to_string()))?
-        .to_str()
-        .map_err(|e| AppError::Auth(format!("Invalid Access-Token header format: {}", e)))?;
-    if token != config.access_token {
-        info!("Header validation failed");
-        return Err(AppError::Auth("Invalid Access-Token".to_string()));
-    }
-
-    Ok(())
-}
+use super::extract::{Authorized, constant_time_eq, required_header};
+use super::rate_limit::DeviceAuthentication;
+use super::room_status::{Occupancy, room_status, status_text};
+use super::version::ApiVersion;
+use crate::agenda::{AgendaDay, agenda, upcoming_days};
+use crate::api::types::{ByosDisplayResponse, ByosSetupResponse, DisplayResponse, SetupResponse};
+use crate::bmp::{
+    AgendaItem, AgendaSection, Background, ColorDepth, Header, Icon, ImageConfig, ImageFormat,
+    ItemStyle,
+};
+use crate::calendar::{CalendarRegistry, LOOKAHEAD_DAYS};
+use crate::claim::{generate_api_key, normalize_claim_code};
+use crate::config_cache::DisplayConfig;
+use crate::database::{Database, DeviceCheckIn};
+use crate::device_id::DeviceId;
+use crate::error_code::ErrorCode;
+use crate::health::battery_percent;
+use crate::image_store::{ImageDelivery, ImageStore, display_filename, image_name};
+use crate::labels::{DEFAULT_LANGUAGE, LabelPack, Labels};
+use crate::log_ingest::{LogAuth, LogIngest, SubmitError, parse_log_body};
+use crate::metrics::Metrics;
+use crate::notify::{Notification, Notifier};
+use crate::render::layout::{CurrentMeeting, Layout, StatusBar};
+use crate::rooms::{Room, resolve_device_room};
+use crate::status::{COUNTDOWN_REFRESH_SECS, RoomState, STARTING_SOON_MINUTES, next_state_change};
 
 /// Setup endpoint handler
 pub async fn setup_handler(
+    _: Authorized,
+    device_id: DeviceId,
     headers: HeaderMap,
-    State(db): State<Arc<Database>>,
-) -> Result<impl IntoResponse, AppError> {
-    let config = Config::get()
-        .map_err(|e| AppError::Config(format!("Failed to get configuration: {}", e)))?;
-
-    let device_id = extract_device_id(&headers)?;
+    version: ApiVersion,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let device_id = device_id.into_string();
+    let (db, config) = (&state.database, &state.config);
 
     info!("Processing setup request for device: {}", device_id);
 
+    let claim_code = headers
+        .get("Claim-Code")
+        .and_then(|h| h.to_str().ok())
+        .map(normalize_claim_code)
+        .filter(|code| !code.is_empty());
+    let (id, notifier) = (device_id.clone(), state.notifier.clone());
+    let require_claim_code = config.require_claim_code;
+    let api_key = db
+        .run_blocking(move |db| {
+            register_device(db, notifier.as_ref(), &id, claim_code, require_claim_code)
+        })
+        .await?;
+    info!("Issued API key to device {}", device_id);
+
+    let response = SetupResponse {
+        status: 200,
+        api_key,
+        friendly_id: "TRMNL001".into(),
+        image_url: state.assets.url(&config.server_url, "setup-logo.bmp"),
+    };
+
+    Ok(match version {
+        ApiVersion::Legacy => Json(response).into_response(),
+        ApiVersion::V1 => Json(ByosSetupResponse::from(response)).into_response(),
+    })
+}
+
+/// Register a device set up with the given claim code and issue its API key
+fn register_device(
+    db: &Database,
+    notifier: &dyn Notifier,
+    device_id: &str,
+    claim_code: Option<String>,
+    require_claim_code: bool,
+) -> Result<String, AppError> {
     // Check if device exists before registration to determine if it's new
-    let exists = db
-        .device_exists(&device_id)
-        .with_context(|| format!("Failed to check if device exists: {}", device_id))
+    let existing = db
+        .get_device(device_id)
+        .with_context(|| format!("Failed to get device: {}", device_id))
         .map_err(AppError::from)?;
+    let exists = existing.is_some();
+
+    // Keys are only handed out once, anyone knowing the shared access token
+    // could get the key of another device otherwise
+    if let Some(device) = &existing {
+        if device.api_key.is_some() {
+            info!("Rejected setup of device {} with an API key", device_id);
+            return Err(AppError::Auth(format!(
+                "Device {} is set up already, an admin must reset its API key to set it up again",
+                device_id
+            )));
+        }
+        if device.api_key_revoked_at.is_some() {
+            info!(
+                "Rejected setup of device {} with a revoked API key",
+                device_id
+            );
+            return Err(AppError::Auth(format!(
+                "API key of device {} was revoked, an admin must reset it to set the device up again",
+                device_id
+            )));
+        }
+    }
+
+    // New devices are bound to a room with a claim code, either entered by the
+    // installer or pre-provisioned for the device. Registered devices whose
+    // key was reset can be moved to another room by presenting a new code.
+    let mut claimed_room = None;
+    if !exists || claim_code.is_some() {
+        let claim = db
+            .claim_device(device_id, claim_code.as_deref())
+            .with_context(|| format!("Failed to claim device: {}", device_id))
+            .map_err(AppError::from)?;
+        match claim {
+            Some(claim) => {
+                info!("Device {} claimed for room {}", device_id, claim.room_id);
+                claimed_room = Some(claim.room_id);
+            }
+            None if claim_code.is_some() => {
+                return Err(AppError::Auth("Invalid or expired claim code".to_string()));
+            }
+            None if require_claim_code && !exists => {
+                return Err(AppError::Auth(format!(
+                    "Device {} requires a claim code",
+                    device_id
+                )));
+            }
+            None => {}
+        }
+    }
 
     // Register device in database
-    db.register_device(&device_id)
+    db.register_device(device_id)
         .with_context(|| format!("Failed to register device: {}", device_id))
         .map_err(AppError::from)?;
     if !exists {
-        info!("Device {} registered successfully", device_id)
+        info!("Device {} registered successfully", device_id);
+        notifier.notify(Notification {
+            kind: "device.registered".to_string(),
+            title: format!("Display {} registered", device_id),
+            message: match &claimed_room {
+                Some(room_id) => format!("A new device was set up for room {}.", room_id),
+                None => "A new device was set up without a room.".to_string(),
+            },
+            room_id: claimed_room,
+            device_id: Some(device_id.to_string()),
+            details: None,
+        });
     } else {
-        info!("Device {} registration updated", device_id)
+        info!("Device {} registration updated", device_id);
+    }
+
+    // Every device gets an API key of its own, pre-provisioned devices the one
+    // they were provisioned with unless it was reset since
+    let provisioned = match existing {
+        Some(_) => None,
+        None => db
+            .get_provisioned_device(device_id)
+            .with_context(|| format!("Failed to get provisioning of device: {}", device_id))
+            .map_err(AppError::from)?,
     };
+    let api_key = provisioned
+        .map(|device| device.api_key)
+        .unwrap_or_else(generate_api_key);
+    let issued = db
+        .set_device_api_key(device_id, &api_key)
+        .with_context(|| format!("Failed to set API key of device: {}", device_id))
+        .map_err(AppError::from)?;
+    if !issued {
+        // Another setup of the device got a key in the meantime
+        return Err(AppError::Auth(format!(
+            "Device {} is set up already, an admin must reset its API key to set it up again",
+            device_id
+        )));
+    }
+    Ok(api_key)
+}
 
-    Ok(Json(SetupResponse {
-        status: 200,
-        api_key: "my-api-key".into(),
-        friendly_id: "TRMNL001".into(),
-        image_url: format!("{}/static/setup-logo.bmp", config.server_url),
-    }))
+/// Screen state of a device derived from its room's calendar
+struct RoomScreen {
+    /// Refresh rate in seconds
+    refresh_rate: u32,
+    /// Time until which the device may sleep, see [`crate::refresh::RefreshPolicy::sleep_until`]
+    sleep_until: Option<DateTime<Local>>,
+    /// Attention banner text, if any
+    banner: Option<String>,
+    /// Upcoming meetings, if the room has a calendar
+    agenda: Option<AgendaSection>,
+    /// Meetings of the days after the agenda's, for the days layout
+    further_days: Vec<AgendaSection>,
+    /// Meeting in progress or the room being free, if the room has a calendar
+    current: Option<CurrentMeeting>,
+    /// Room status for the large indicator, if the room shows one
+    status_indicator: Option<String>,
+    /// Whether a meeting is in progress
+    busy: bool,
 }
 
-/// Display endpoint handler
-pub async fn display_handler(
-    headers: HeaderMap,
-    State(db): State<Arc<Database>>,
-) -> Result<impl IntoResponse, AppError> {
-    let config = Config::get()
-        .context("Failed to get configuration")
-        .map_err(AppError::from)?;
+/// Maximum number of meetings shown in the agenda
+const AGENDA_ITEMS: usize = 4;
 
-    validate_headers(&headers, &config)?;
+/// Determine refresh rate and time-based screen state for a device's room
+///
+/// Devices without a room or room calendar, or whose calendar cannot be
+/// fetched, get the globally configured refresh rate and no banner. If the
+/// calendar cannot be fetched, the agenda shows the error code instead.
+async fn device_room_screen(
+    room: Option<&Room>,
+    config: &Config,
+    calendars: &CalendarRegistry,
+    layout: Layout,
+    dnd: bool,
+) -> RoomScreen {
+    let fallback = RoomScreen {
+        refresh_rate: config.refresh.default_rate,
+        sleep_until: None,
+        banner: None,
+        agenda: None,
+        further_days: Vec::new(),
+        current: None,
+        status_indicator: None,
+        busy: false,
+    };
+    let Some(room) = room else {
+        return fallback;
+    };
+    if layout == Layout::Overview {
+        let mut sections = overview(room, config, calendars).await.into_iter();
+        return RoomScreen {
+            agenda: sections.next(),
+            further_days: sections.collect(),
+            ..fallback
+        };
+    }
+    let Some(url) = &room.calendar_url else {
+        return fallback;
+    };
 
-    let device_id = extract_device_id(&headers)?;
+    let events = match calendars
+        .future_events(
+            &room.id,
+            url,
+            &room.calendar_source,
+            room.fallback_calendar.as_ref(),
+            room.deduplicate,
+        )
+        .instrument(debug_span!("calendar_query", room_id = %room.id))
+        .await
+    {
+        Ok(events) => events,
+        Err(e) => {
+            warn!("Failed to get calendar for room {}: {}", room.id, e);
+            // Show the error code, so that support can tell from a photo what failed
+            let labels = config.labels.snapshot();
+            let labels = labels.pack(&room.language);
+            return RoomScreen {
+                agenda: Some(AgendaSection {
+                    heading: labels.text("agenda-today"),
+                    items: vec![AgendaItem::new(
+                        labels.format("error-calendar", &[("code", e.code().as_str().into())]),
+                    )],
+                    status: Some(labels.text("status-unknown")),
+                }),
+                ..fallback
+            };
+        }
+    };
 
-    info!("Processing display request for device: {}", device_id);
+    let now = Local::now();
+    let mut refresh_rate = room.refresh.refresh_rate(&events, now);
+    // Meetings marked as do-not-disturb are not nagged about their end
+    let end_warning_minutes = if dnd { 0 } else { room.end_warning_minutes };
 
-    // Check if device is registered
-    let device = db
-        .get_device(&device_id)
-        .with_context(|| format!("Failed to check if device exists: {}", device_id))
-        .map_err(AppError::from)?;
-    if device.is_none() {
-        return Err(AppError::Auth(format!(
-            "Device {} not registered",
-            device_id
-        )));
+    // Poll again in time for the next state change (e.g. the end warning)
+    let starting_soon_minutes = if room.status_indicator {
+        STARTING_SOON_MINUTES
+    } else {
+        0
+    };
+    let next_change = next_state_change(&events, now, end_warning_minutes, starting_soon_minutes);
+    if let Some(change) = next_change {
+        let until = (change - now).num_seconds().max(1) as u32;
+        refresh_rate = refresh_rate.min(until);
+    }
+    let sleep_until =
+        room.refresh
+            .sleep_until(&events, now, &room.business_hours, &room.week, next_change);
+
+    let labels = config.labels.snapshot();
+    let labels = labels.pack(&room.language);
+    let agendas = match layout {
+        Layout::Days => upcoming_days(
+            &events,
+            now,
+            room.upcoming_days.min(LOOKAHEAD_DAYS),
+            room.upcoming_events,
+        ),
+        Layout::Agenda | Layout::Focus | Layout::Overview => vec![agenda(
+            &events,
+            now,
+            &room.business_hours,
+            &room.week,
+            AGENDA_ITEMS,
+        )],
+    };
+    let mut sections = agendas.iter().map(|agenda| AgendaSection {
+        heading: agenda.day.heading(&labels),
+        items: agenda
+            .events
+            .iter()
+            .map(|e| AgendaItem {
+                text: format!("{} {}", e.start_time.format("%H:%M"), e.name),
+                tag: None,
+                style: room.event_style(e),
+            })
+            .collect(),
+        status: None,
+    });
+    let mut first = sections.next().unwrap_or_else(|| AgendaSection {
+        heading: AgendaDay::Today.heading(&labels),
+        items: Vec::new(),
+        status: None,
+    });
+    let further_days = sections.collect();
+    if first.items.is_empty() {
+        first
+            .items
+            .push(AgendaItem::new(labels.text("agenda-empty")));
+    }
+    let state = RoomState::resolve(&events, now, end_warning_minutes);
+    let status_indicator = room.status_indicator.then(|| state.indicator(&labels, now));
+    if room.status_indicator && state.shows_countdown(now) {
+        refresh_rate = refresh_rate.min(COUNTDOWN_REFRESH_SECS);
+    }
+    let mut current = current_meeting(&state, &labels, now);
+    match &status_indicator {
+        // The indicator replaces the status label of the agenda heading
+        None => first.status = Some(state.label(&labels)),
+        Some(indicator) => current.status = indicator.clone(),
+    }
+    let busy = !matches!(state, RoomState::Free { .. });
+    RoomScreen {
+        refresh_rate,
+        sleep_until,
+        banner: if dnd && busy {
+            Some(labels.text("banner-dnd"))
+        } else {
+            state.banner(&labels)
+        },
+        busy,
+        agenda: Some(first),
+        further_days,
+        current: Some(current),
+        status_indicator,
+    }
+}
+
+/// Status of the rooms shown by the overview layout of a room, under a
+/// heading per floor and zone
+///
+/// The rooms are limited to the room's `overview_floor`, if set, and keep
+/// their order of the room configuration. Busy rooms are hatched.
+async fn overview(
+    room: &Room,
+    config: &Config,
+    calendars: &CalendarRegistry,
+) -> Vec<AgendaSection> {
+    let labels = config.labels.snapshot();
+    let labels = labels.pack(&room.language);
+    let now = Local::now();
+    let rooms = config.rooms.snapshot();
+    let mut sections: Vec<AgendaSection> = Vec::new();
+    for other in rooms.iter().filter(|other| {
+        other.id != room.id
+            && other.layout != Layout::Overview
+            && room
+                .overview_floor
+                .as_deref()
+                .is_none_or(|floor| other.on_floor(floor))
+    }) {
+        let status = room_status(other, calendars);
+        let item = AgendaItem {
+            text: other.header_title().to_string(),
+            tag: Some(status_text(&status, &labels, now)),
+            style: if status.occupancy == Occupancy::Busy {
+                ItemStyle::Hatched
+            } else {
+                ItemStyle::Regular
+            },
+        };
+        let heading = other
+            .location(&labels)
+            .unwrap_or_else(|| labels.text("overview-other-rooms"));
+        match sections
+            .iter_mut()
+            .find(|section| section.heading == heading)
+        {
+            Some(section) => section.items.push(item),
+            None => sections.push(AgendaSection {
+                heading,
+                items: vec![item],
+                status: None,
+            }),
+        }
+    }
+    if sections.is_empty() {
+        sections.push(AgendaSection {
+            heading: labels.text("overview-other-rooms"),
+            items: vec![AgendaItem::new(labels.text("overview-empty"))],
+            status: None,
+        });
+    }
+    sections
+}
+
+/// Block of the meeting in progress, e.g. `BUSY until 11:00` and its name
+fn current_meeting(state: &RoomState, labels: &LabelPack, now: DateTime<Local>) -> CurrentMeeting {
+    let time = |t: DateTime<Local>| [("time", t.format("%H:%M").to_string().into())];
+    match state {
+        RoomState::Busy { current, .. } | RoomState::EndingSoon { current, .. } => CurrentMeeting {
+            status: labels.format("status-busy-until", &time(current.end_time)),
+            title: Some(current.name.clone()),
+        },
+        // Only show the time if the next meeting is today
+        RoomState::Free { next: Some(next) }
+            if next.start_time.date_naive() == now.date_naive() =>
+        {
+            CurrentMeeting {
+                status: labels.format("status-free-until", &time(next.start_time)),
+                title: None,
+            }
+        }
+        RoomState::Free { .. } => CurrentMeeting {
+            status: state.label(labels),
+            title: None,
+        },
     }
+}
+
+/// Text of the issue badge for a device, if its room shows open issues
+fn issue_badge(
+    display_config: &DisplayConfig,
+    room: Option<&Room>,
+    labels: &Labels,
+) -> Option<String> {
+    let room = room.filter(|room| room.show_issue_badge)?;
+    let issue = display_config.latest_issue(&room.id)?;
+    let summary = if issue.description.is_empty() {
+        issue.category.clone()
+    } else {
+        issue.description.chars().take(40).collect()
+    };
+    Some(
+        labels
+            .pack(&room.language)
+            .format("issue-reported", &[("summary", summary.into())]),
+    )
+}
+
+/// Screen of a device, before rendering
+pub(super) struct Frame {
+    pub image_config: ImageConfig,
+    /// Start of the filename reported to the device, e.g. the room ID
+    pub name: String,
+    /// Refresh rate in seconds
+    pub refresh_rate: u32,
+    /// Unix timestamp until which the device may sleep
+    pub sleep_until: Option<i64>,
+}
+
+/// Seconds between the requests of devices while the server is under
+/// maintenance
+pub const MAINTENANCE_REFRESH_RATE: u32 = 30 * 60;
+
+/// Determine what a device in the given room shows
+///
+/// The frame only depends on the room and layout, except for the battery
+/// notice and the battery level in the status bar of the focus layout, so
+/// devices of a room with the same layout usually share it.
+pub(super) async fn device_frame(
+    room: Option<&Room>,
+    display_config: &DisplayConfig,
+    config: &Config,
+    calendars: &CalendarRegistry,
+    layout: Layout,
+    battery_critical: bool,
+    battery_voltage: Option<f64>,
+) -> Frame {
+    // An active broadcast replaces the regular screen on every device, and so
+    // does the maintenance notice, unless there is an emergency to broadcast
+    let broadcast = display_config.active_broadcast(chrono::Utc::now().timestamp());
+    let maintenance = display_config.maintenance().filter(|_| broadcast.is_none());
+    let labels = config.labels.snapshot();
 
     // Set up image configuration using app config
-    let image_config = ImageConfig {
+    let mut image_config = ImageConfig {
         font_path: config.font_path.clone(),
         font_size: 50.0,
+        color_depth: ColorDepth::Monochrome,
+        dither: config.dither,
+        layout,
         ..ImageConfig::default()
     };
+    if broadcast.is_none() && maintenance.is_none() {
+        // Open issues concern the people in the room, so they take precedence
+        image_config.footer = issue_badge(display_config, room, &labels).or_else(|| {
+            battery_critical.then(|| {
+                let language = room.map_or(DEFAULT_LANGUAGE, |room| room.language.as_str());
+                image_config.footer_icon = Some(Icon::BatteryLow);
+                labels.pack(language).text("battery-replace")
+            })
+        });
+        if let Some(room) = room {
+            image_config.header = Some(Header {
+                title: room.header_title().to_string(),
+                details: room.header_details(&labels.pack(&room.language)),
+                badges: room
+                    .equipment
+                    .iter()
+                    .map(|e| e.badge().to_string())
+                    .collect(),
+            });
+            image_config.footer_text = room.footer_text.clone();
+        }
+    }
+    let (name, refresh_rate, sleep_until) = match (&broadcast, maintenance) {
+        (Some(broadcast), _) => {
+            image_config.text = broadcast.message.clone();
+            let remaining = broadcast.expires_at - chrono::Utc::now().timestamp();
+            (
+                format!("broadcast-{}", broadcast.id),
+                config.refresh.default_rate.min(remaining.max(1) as u32),
+                None,
+            )
+        }
+        (None, Some(maintenance)) => {
+            let language = room.map_or(DEFAULT_LANGUAGE, |room| room.language.as_str());
+            image_config.text = labels.pack(language).text("maintenance");
+            if let Some(message) = &maintenance.message {
+                image_config.text = format!("{}\n{}", image_config.text, message);
+            }
+            (
+                format!("maintenance-{}", maintenance.started_at),
+                config.refresh.default_rate.max(MAINTENANCE_REFRESH_RATE),
+                None,
+            )
+        }
+        (None, None) => {
+            if room.is_none() {
+                image_config.text = labels.pack(DEFAULT_LANGUAGE).format(
+                    "error-unassigned",
+                    &[("code", ErrorCode::DeviceUnassigned.as_str().into())],
+                );
+            }
+            let dnd = room.is_some_and(|room| {
+                display_config
+                    .active_dnd(&room.id, chrono::Utc::now().timestamp())
+                    .is_some()
+            });
+            let screen = device_room_screen(room, config, calendars, layout, dnd).await;
+            // Meetings booked in the failing calendar may be missing from the fallback
+            if let (Some(room), Some(header)) = (room, &mut image_config.header)
+                && calendars.is_degraded(&room.id)
+            {
+                header
+                    .badges
+                    .insert(0, labels.pack(&room.language).text("header-degraded"));
+            }
+            image_config.banner = screen.banner;
+            image_config.agenda = screen.agenda;
+            image_config.further_days = screen.further_days;
+            if layout == Layout::Focus {
+                // The status moves from the agenda heading to the current meeting
+                if let Some(agenda) = &mut image_config.agenda {
+                    agenda.status = None;
+                }
+                image_config.current = screen.current;
+                image_config.status_bar = room.map(|_| StatusBar {
+                    clock: Local::now().format("%H:%M").to_string(),
+                    battery_percent: battery_voltage.map(battery_percent),
+                });
+            } else {
+                // The focus layout shows the indicator's text in the current meeting
+                image_config.status_indicator = screen.status_indicator;
+            }
+            if let Some(room) = room {
+                image_config.background = Background {
+                    hatch: room.background.busy_hatch && screen.busy,
+                    watermark_path: room.background.watermark.clone(),
+                };
+            }
+            (
+                room.map_or("unassigned", |room| room.id.as_str())
+                    .to_string(),
+                screen.refresh_rate,
+                screen.sleep_until.map(|until| until.timestamp()),
+            )
+        }
+    };
+
+    Frame {
+        image_config,
+        name,
+        refresh_rate,
+        sleep_until,
+    }
+}
+
+/// Display endpoint handler
+#[instrument(
+    name = "display",
+    skip_all,
+    fields(device_id = tracing::field::Empty, room_id = tracing::field::Empty)
+)]
+pub async fn display_handler(
+    device_id: DeviceId,
+    headers: HeaderMap,
+    version: ApiVersion,
+    authentication: Option<Extension<DeviceAuthentication>>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let device_id = device_id.into_string();
+    let config = &state.config;
+    let db = &state.database;
+
+    Span::current().record("device_id", device_id.as_str());
 
-    // Generate BMP image
-    let bmp_data = generate_bmp(&image_config)
-        .with_context(|| format!("Failed to generate BMP image for device {}", device_id))
+    // Reject requests without a token without touching the configuration
+    required_header(&headers, "Access-Token")?;
+
+    info!("Processing display request for device: {}", device_id);
+
+    // Room assignments, settings, broadcasts and issues only change with admin writes
+    let display_config = state
+        .display_config
+        .get()
+        .await
+        .context("Failed to load display configuration")
         .map_err(AppError::from)?;
 
-    // Encode to base64
-    let base64_image = general_purpose::STANDARD.encode(&bmp_data);
-    let image_url = format!("data:image/bmp;base64,{}", base64_image);
+    // Check if device is registered
+    let Some(device) = display_config.device(&device_id) else {
+        return Err(AppError::UnknownDevice(device_id));
+    };
+    Authorized::check_device(&headers, config, device)?;
+    if let Some(Extension(authentication)) = authentication {
+        authentication.set();
+    }
+    let rooms = config.rooms.snapshot();
+    let room = resolve_device_room(&rooms, &device.id, device.room_id.as_deref());
+    if let Some(room) = room {
+        Span::current().record("room_id", room.id.as_str());
+    }
+
+    let battery_voltage = headers
+        .get("Battery-Voltage")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|voltage| voltage.trim().parse().ok());
+    let rssi = headers
+        .get("RSSI")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|rssi| rssi.trim().parse().ok());
+    let firmware_version = headers
+        .get("FW-Version")
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string);
+    let warning_voltage = config.battery_warning_voltage;
+    let id = device_id.clone();
+    let battery_critical = db
+        .run_blocking(move |db| {
+            let check_in = DeviceCheckIn {
+                battery_voltage,
+                rssi,
+                firmware_version: firmware_version.as_deref(),
+            };
+            let battery_critical = db.record_device_check_in(&id, &check_in, warning_voltage);
+            if let Err(e) = db.record_display_fetch(&id, chrono::Utc::now().timestamp()) {
+                warn!("Failed to record display fetch of device {}: {:#}", id, e);
+            }
+            battery_critical
+        })
+        .await
+        .inspect_err(|e| warn!("Failed to record check-in of device {}: {:#}", device_id, e))
+        .unwrap_or(device.battery_critical);
+    if battery_critical && !device.battery_critical {
+        warn!(
+            "Battery of device {} is below {} V, showing a warning on its display",
+            device_id, config.battery_warning_voltage
+        );
+        state.notifier.notify(Notification {
+            kind: "device.battery_low".to_string(),
+            title: format!(
+                "Battery of display {} is low",
+                device.name.as_deref().unwrap_or(&device.id)
+            ),
+            message: format!(
+                "The battery is below {} V and should be replaced or charged.",
+                config.battery_warning_voltage
+            ),
+            room_id: room.map(|room| room.id.clone()),
+            device_id: Some(device.id.clone()),
+            details: battery_voltage.map(|voltage| serde_json::json!({ "voltage": voltage })),
+        });
+    }
+
+    let (layout, experiment) = room.map_or((Layout::default(), None), |room| {
+        display_config.device_layout(room, &device.id)
+    });
+    let started = Instant::now();
+    let mut frame = device_frame(
+        room,
+        &display_config,
+        config,
+        &state.calendars,
+        layout,
+        battery_critical,
+        battery_voltage.or(device.battery_voltage),
+    )
+    .await;
+
+    let format_setting = device.image_format.as_deref().and_then(|format| {
+        format
+            .parse()
+            .inspect_err(|e| warn!("Ignoring image format of device {}: {}", device_id, e))
+            .ok()
+    });
+    let format = ImageFormat::negotiate(
+        config.image_format,
+        format_setting,
+        headers.get(header::ACCEPT).and_then(|h| h.to_str().ok()),
+    );
+    frame.image_config.format = format;
+
+    // Frames of rooms are usually pre-rendered after the calendar refresh,
+    // frames of a single device are kept from its previous request
+    let fingerprint = frame.image_config.fingerprint();
+    let image_data = match state.frames.get(fingerprint) {
+        Some(image_data) => image_data,
+        None => Arc::new(
+            state
+                .renderer
+                .render(frame.image_config)
+                .await
+                .with_context(|| format!("Failed to generate image for device {}", device_id))
+                .map_err(AppError::from)?,
+        ),
+    };
+    let unchanged = state
+        .frames
+        .insert_device(&device_id, fingerprint, image_data.clone());
+    state.metrics.record_display_frame(unchanged);
+    let image_data = Arc::unwrap_or_clone(image_data);
+    if let Some((experiment, variant)) = experiment {
+        state.metrics.observe_experiment_render(
+            &experiment.name,
+            variant.as_str(),
+            started.elapsed(),
+        );
+    }
+
+    // Firmware skips the download if the filename did not change
+    let filename = display_filename(&frame.name, &image_data, format.as_str());
+
+    // Track payload sizes, to see which devices still get large images
+    let model = headers.get("Model").and_then(|h| h.to_str().ok());
+    state.metrics.observe_payload_size(
+        format.as_str(),
+        model.unwrap_or("unknown"),
+        image_data.len(),
+    );
+    // The device is expected back after its refresh rate, or when it wakes up
+    let refresh_rate = config.refresh.for_device(&device_id, frame.refresh_rate);
+    let next_check_in_at = frame
+        .sleep_until
+        .unwrap_or_default()
+        .max(chrono::Utc::now().timestamp() + i64::from(refresh_rate));
+    let (id, model, size) = (
+        device_id.clone(),
+        model.map(str::to_string),
+        image_data.len(),
+    );
+    if let Err(e) = db
+        .run_blocking(move |db| {
+            if let Err(e) = db.record_device_schedule(&id, next_check_in_at, refresh_rate) {
+                warn!("Failed to record schedule of device {}: {:#}", id, e);
+            }
+            db.record_device_payload(&id, model.as_deref(), format.as_str(), size)
+        })
+        .await
+    {
+        warn!("Failed to record payload of device {}: {:#}", device_id, e);
+    }
+
+    // Sign the raw image data
+    let image_signature = config
+        .image_signer
+        .as_ref()
+        .map(|signer| signer.sign(&image_data));
+
+    let device_setting = device.image_delivery.as_deref().and_then(|mode| {
+        mode.parse()
+            .inspect_err(|e| warn!("Ignoring image delivery of device {}: {}", device_id, e))
+            .ok()
+    });
+    let delivery = ImageDelivery::negotiate(
+        config.image_delivery,
+        device_setting,
+        headers.get("Image-Delivery").and_then(|h| h.to_str().ok()),
+    );
+    let deliver_span = debug_span!("deliver", mode = ?delivery);
+    let image_url = match delivery {
+        ImageDelivery::Inline => {
+            let _deliver = deliver_span.enter();
+            // Devices of a room poll for the same frame, which is encoded once
+            let (data_url, cached) = state.payloads.get_or_encode(&frame.name, fingerprint, || {
+                let base64_image = general_purpose::STANDARD.encode(&image_data);
+                format!("data:{};base64,{}", format.content_type(), base64_image)
+            });
+            state.metrics.record_inline_cache(cached);
+            data_url.to_string()
+        }
+        ImageDelivery::Hosted | ImageDelivery::Chunked => {
+            let name = image_name(&image_data, format.as_str());
+            state
+                .images
+                .put(&name, image_data)
+                .instrument(deliver_span)
+                .await
+                .context("Failed to store rendered image")
+                .map_err(AppError::from)?;
+            let url = hosted_image_url(config, &name);
+            if delivery == ImageDelivery::Chunked {
+                let separator = if url.contains('?') { '&' } else { '?' };
+                format!("{}{}chunked=true", url, separator)
+            } else {
+                url
+            }
+        }
+    };
 
     // Create response
     let response = DisplayResponse {
-        filename: "demo.bmp".to_string(),
+        filename,
         image_url,
         image_url_timeout: 0,
-        refresh_rate: config.refresh_rate,
+        refresh_rate,
+        image_signature,
+        sleep_until: frame.sleep_until,
     };
 
-    Ok(Json(response))
+    Ok(match version {
+        ApiVersion::Legacy => Json(response).into_response(),
+        ApiVersion::V1 => Json(ByosDisplayResponse::from(response)).into_response(),
+    })
 }
 
-/// Log endpoint handler - captures and logs device log requests
-pub async fn log_handler(headers: HeaderMap, body: Bytes) -> Result<impl IntoResponse, AppError> {
-    // Note: Not validating access token for this endpoint, since we want to
-    // capture logs even for misconfigured devices.
+/// Returns true if a request carries the key of the device it claims to be from
+///
+/// Registered devices are checked like display requests. Provisioned devices
+/// may log before their first setup, with the key they were provisioned with.
+fn is_device_authenticated(
+    db: &Database,
+    config: &Config,
+    headers: &HeaderMap,
+    device_id: &str,
+) -> Result<bool, AppError> {
+    let Some(token) = headers.get("Access-Token").and_then(|h| h.to_str().ok()) else {
+        return Ok(false);
+    };
+    let device = db
+        .get_device(device_id)
+        .with_context(|| format!("Failed to get device: {}", device_id))
+        .map_err(AppError::from)?;
+    if let Some(device) = device {
+        return Ok(Authorized::check_device(headers, config, &device).is_ok());
+    }
+    let provisioned = db
+        .get_provisioned_device(device_id)
+        .with_context(|| format!("Failed to get provisioning of device: {}", device_id))
+        .map_err(AppError::from)?;
+    Ok(provisioned.is_some_and(|provisioned| constant_time_eq(token, &provisioned.api_key)))
+}
 
+/// Log endpoint handler - captures and logs device log requests
+///
+/// Entries are queued for the background log writer. When its buffer is
+/// full, the request is rejected with `429 Too Many Requests`.
+pub async fn log_handler(
+    headers: HeaderMap,
+    authentication: Option<Extension<DeviceAuthentication>>,
+    State(config): State<Arc<Config>>,
+    State(db): State<Arc<Database>>,
+    State(logs): State<LogIngest>,
+    State(metrics): State<Arc<Metrics>>,
+    body: Bytes,
+) -> Result<impl IntoResponse, AppError> {
     // Extract headers
     let device_id = headers
         .get("ID")
         .and_then(|h| h.to_str().ok())
         .unwrap_or("unknown");
+    // Logs of devices with unexpected IDs are kept under the ID as sent
+    let device_id = DeviceId::lenient(device_id).into_string();
+    let device_id = device_id.as_str();
+
+    // Logs of misconfigured devices are still captured unless LOG_AUTH=strict,
+    // but flagged so that spoofed entries can be told apart
+    let authenticated = {
+        let (config, headers, id) = (config.clone(), headers.clone(), device_id.to_string());
+        db.run_blocking(move |db| is_device_authenticated(db, &config, &headers, &id))
+            .await?
+    };
+    if let Some(Extension(authentication)) = authentication.filter(|_| authenticated) {
+        authentication.set();
+    }
+    if !authenticated && config.log_auth == LogAuth::Strict {
+        return Err(AppError::Auth(format!(
+            "Log request not authenticated for device {}",
+            device_id
+        )));
+    }
     let content_type = headers
         .get("Content-Type")
         .and_then(|h| h.to_str().ok())
@@ -198,22 +962,43 @@ pub async fn log_handler(headers: HeaderMap, body: Bytes) -> Result<impl IntoRes
     };
 
     info!(
-        "Log request: Device: {}, Content-Type: {}, Body length: {} bytes",
+        "Log request: Device: {}, Content-Type: {}, Body length: {} bytes, Authenticated: {}",
         device_id,
         content_type,
-        body.len()
+        body.len(),
+        authenticated
     );
 
-    // Append log entry to file
     if !body_str.is_empty() {
-        let log_entry = format!("[{}] {}\n", device_id, body_str);
-        if let Ok(mut file) = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open("device-log.txt")
-        {
-            if let Err(e) = file.write_all(log_entry.as_bytes()) {
-                error!("Failed to write log entry: {}", e);
+        let entries = parse_log_body(
+            device_id,
+            &body_str,
+            chrono::Utc::now().timestamp(),
+            authenticated,
+        );
+        let count = entries.len();
+        match logs.submit(entries) {
+            Ok(()) => {}
+            Err(SubmitError::Full) => {
+                metrics.inc_device_logs_dropped();
+                warn!("Log buffer full, rejecting log of device {}", device_id);
+                return Err(AppError::Overloaded("Log buffer is full".to_string()));
+            }
+            Err(SubmitError::TooLarge) => {
+                metrics.inc_device_logs_dropped();
+                warn!(
+                    "Log of device {} has {} entries, more than the log buffer holds",
+                    device_id, count
+                );
+                return Err(AppError::TooLarge(
+                    "Log has more entries than the log buffer holds".to_string(),
+                ));
+            }
+            Err(SubmitError::Closed) => {
+                metrics.inc_device_logs_dropped();
+                return Err(AppError::Unavailable(
+                    "Log writer is not running".to_string(),
+                ));
             }
         }
     }
@@ -222,6 +1007,114 @@ pub async fn log_handler(headers: HeaderMap, body: Bytes) -> Result<impl IntoRes
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// Image signing public key endpoint
+pub async fn image_signing_key_handler(
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, AppError> {
+    match &config.image_signer {
+        Some(signer) => Ok(Json(serde_json::json!({
+            "algorithm": "ed25519",
+            "public_key": signer.public_key(),
+        }))
+        .into_response()),
+        None => Ok(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+/// Public URL of a hosted image
+///
+/// If image signing is enabled, the URL carries an expiry and a signature, so
+/// that only URLs handed out by the server can be used to download images.
+pub fn hosted_image_url(config: &Config, name: &str) -> String {
+    match &config.image_signer {
+        Some(signer) => {
+            let expires = chrono::Utc::now().timestamp() + config.image_url_ttl_seconds;
+            format!(
+                "{}/images/{}?expires={}&signature={}",
+                config.server_url,
+                name,
+                expires,
+                signer.sign_image_url(name, expires)
+            )
+        }
+        None => format!("{}/images/{}", config.server_url, name),
+    }
+}
+
+/// Query parameters of signed hosted image URLs
+#[derive(Deserialize)]
+pub struct ImageUrlParams {
+    pub expires: Option<i64>,
+    pub signature: Option<String>,
+    /// Send the image with chunked transfer encoding, see [`ImageDelivery::Chunked`]
+    #[serde(default)]
+    pub chunked: bool,
+}
+
+/// Size of the chunks of images sent with chunked transfer encoding
+const IMAGE_CHUNK_SIZE: usize = 4096;
+
+/// Hosted image endpoint, serves rendered images from the image store
+pub async fn image_handler(
+    Path(name): Path<String>,
+    Query(params): Query<ImageUrlParams>,
+    State(images): State<Arc<dyn ImageStore>>,
+    State(config): State<Arc<Config>>,
+) -> Result<Response, AppError> {
+    if let Some(signer) = &config.image_signer {
+        let valid = match (params.expires, &params.signature) {
+            (Some(expires), Some(signature)) => {
+                expires > chrono::Utc::now().timestamp()
+                    && signer.verify_image_url(&name, expires, signature)
+            }
+            _ => false,
+        };
+        if !valid {
+            return Err(AppError::Auth("Invalid or expired image URL".to_string()));
+        }
+    }
+
+    let data = images
+        .get(&name)
+        .await
+        .with_context(|| format!("Failed to load image {}", name))
+        .map_err(AppError::from)?;
+
+    Ok(match data {
+        // Names are content-addressed, so images never change
+        Some(data) => {
+            let format = ImageFormat::of_file_name(&name).unwrap_or_default();
+            let headers = [
+                (header::CONTENT_TYPE, format.content_type()),
+                (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+            ];
+            if params.chunked {
+                // A streamed body has no Content-Length, so it is sent in chunks
+                let chunks: Vec<Result<Bytes, std::io::Error>> = data
+                    .chunks(IMAGE_CHUNK_SIZE)
+                    .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+                    .collect();
+                (
+                    headers,
+                    Body::from_stream(futures_util::stream::iter(chunks)),
+                )
+                    .into_response()
+            } else {
+                (headers, data).into_response()
+            }
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    })
+}
+
+/// Prometheus metrics endpoint
+pub async fn metrics_handler(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+}
+
 /// Health check endpoint
 pub async fn health_handler() -> impl IntoResponse {
     Json(serde_json::json!({
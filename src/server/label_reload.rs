@@ -0,0 +1,137 @@
+//! Hot reloading of the label packs
+//!
+//! The `labels` job watches the custom labels directory (`LABELS_DIR`). Once a
+//! pack was added, changed or removed, all packs are validated and swapped in
+//! at once. The frames of the rooms are then rendered with the new labels; if
+//! one of them fails to render, the previous packs are restored, so that a
+//! broken pack never reaches the displays. Either way the directory is only
+//! read again after its next change.
+
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+
+use super::AppState;
+use super::prerender::prerender;
+use super::scheduler::Job;
+use crate::labels::{Labels, validate_pack};
+use crate::schedule::Schedule;
+
+/// How often the labels directory is checked for changes
+pub const LABELS_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Name, modification time and size of every label pack in a directory
+type DirState = Vec<(String, SystemTime, u64)>;
+
+/// Current state of the label packs in a directory, sorted by name
+fn dir_state(dir: &str) -> Result<DirState> {
+    let mut packs = Vec::new();
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("Failed to read labels directory {}", dir))?
+    {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "ftl") {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        packs.push((
+            path.display().to_string(),
+            metadata.modified()?,
+            metadata.len(),
+        ));
+    }
+    packs.sort();
+    Ok(packs)
+}
+
+/// Validate the label packs in a directory and load them
+fn load_validated(dir: &str) -> Result<Labels> {
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("Failed to read labels directory {}", dir))?
+    {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "ftl") {
+            continue;
+        }
+        let Some(language) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let source = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read label pack {}", path.display()))?;
+        let validation = validate_pack(language, &source);
+        if !validation.errors.is_empty() {
+            bail!(
+                "Invalid label pack {}: {}",
+                path.display(),
+                validation.errors.join("; ")
+            );
+        }
+    }
+    Labels::load(Some(dir))
+}
+
+/// Swap in the label packs of a directory, restoring the previous packs if a
+/// room fails to render with them
+pub async fn reload_labels(state: &AppState, dir: &str) -> Result<String> {
+    let labels = load_validated(dir)?;
+    let rooms = state.config.rooms.snapshot();
+    let previous = state.config.labels.replace(labels);
+    let failed = match prerender(state, &rooms).await {
+        Ok(stats) if stats.failed == 0 => {
+            info!("Reloaded the label packs in {}", dir);
+            return Ok(format!(
+                "reloaded labels, {} frames rendered",
+                stats.rendered
+            ));
+        }
+        Ok(stats) => format!("{} rooms failed to render", stats.failed),
+        Err(e) => format!("{:#}", e),
+    };
+
+    state.config.labels.replace(previous);
+    if let Err(e) = prerender(state, &rooms).await {
+        warn!(
+            "Failed to render the frames with the previous labels: {:#}",
+            e
+        );
+    }
+    bail!(
+        "Restored the previous labels, {} with the labels in {}",
+        failed,
+        dir
+    )
+}
+
+/// Job reloading the label packs whenever the labels directory changes
+pub fn labels_job(dir: String) -> Job {
+    // The packs in the directory at startup are loaded already
+    let last = Arc::new(Mutex::new(dir_state(&dir).ok()));
+    Job::new(
+        "labels",
+        Schedule::Every(LABELS_POLL_INTERVAL),
+        move |state| {
+            let dir = dir.clone();
+            let last = last.clone();
+            async move {
+                let current = dir_state(&dir)?;
+                {
+                    let mut last = match last.lock() {
+                        Ok(last) => last,
+                        Err(poisoned) => poisoned.into_inner(),
+                    };
+                    if last.as_ref() == Some(&current) {
+                        return Ok("unchanged".to_string());
+                    }
+                    *last = Some(current);
+                }
+                reload_labels(&state, &dir).await
+            }
+        },
+    )
+}
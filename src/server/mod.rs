@@ -1,71 +1,499 @@
+pub mod admin;
+pub mod alerts;
 pub mod config;
+pub mod dashboard;
+pub mod dnd;
+pub mod dry_run;
 pub mod errors;
+pub mod extract;
 pub mod handlers;
+pub mod label_reload;
+pub mod payload_cache;
+pub mod prerender;
+pub mod proxy;
+pub mod rate_limit;
+pub mod report;
+pub mod request_id;
+pub mod room_status;
+pub mod scheduler;
+pub mod status_page;
+pub mod tls;
+pub mod version;
+pub mod watchdog;
 
-use std::sync::Arc;
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, Result};
 use axum::{
     Router,
-    routing::{get, post},
+    extract::FromRef,
+    middleware,
+    routing::{get, post, put},
 };
-use log::info;
+use log::{info, warn};
 use tokio::net::TcpListener;
 use tower::ServiceBuilder;
 use tower_http::{
+    catch_panic::CatchPanicLayer,
     services::ServeDir,
     trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer},
 };
 use tracing::Level;
 
+use crate::calendar::CalendarRegistry;
+use crate::config_cache::{CONFIG_CACHE_TTL_SECS, ConfigCache};
 use crate::database::Database;
+use crate::error_report::{ErrorReporter, create_error_reporter, spawn_supervised};
+use crate::event_changes::MqttChangeNotifier;
+use crate::image_store::{ImageStore, STATIC_DIR, StaticAssets, create_image_store};
+use crate::log_ingest::LogIngest;
+use crate::metrics::Metrics;
+use crate::mqtt::MqttPublisher;
+use crate::notify::{LogNotifier, Notifier, WebhookNotifier};
+use crate::render::{CachingRenderer, ImageRenderer, create_renderer};
+use crate::rollover::run_rollover_task;
+use crate::telemetry::{UsageReporter, run_telemetry_task};
+use admin::{
+    adopt_device_handler, clear_broadcast_handler, create_broadcast_handler,
+    create_claim_code_handler, create_experiment_handler, delete_device_handler,
+    delete_room_handler, device_logs_handler, end_maintenance_handler, export_devices_handler,
+    export_rooms_handler, get_device_handler, get_maintenance_handler, import_claim_codes_handler,
+    list_claim_codes_handler, list_device_logs_handler, list_devices_handler,
+    list_experiments_handler, list_issues_handler, list_provisioned_devices_handler,
+    list_rooms_handler, promote_experiment_handler, provision_devices_handler,
+    rename_device_handler, reset_device_api_key_handler, resolve_issue_handler,
+    revoke_device_api_key_handler, rollback_experiment_handler, room_utilization_handler,
+    save_room_handler, set_image_delivery_handler, set_image_format_handler,
+    start_maintenance_handler, summary_handler, test_calendar_handler, test_label_pack_handler,
+};
 use config::Config;
-use handlers::{display_handler, health_handler, log_handler, setup_handler};
+use dashboard::{dashboard_handler, device_preview_handler, room_preview_handler};
+use dnd::{DndPublisher, clear_dnd_handler, run_dnd_task, set_dnd_handler};
+use handlers::{
+    display_handler, health_handler, image_handler, image_signing_key_handler, log_handler,
+    metrics_handler, setup_handler,
+};
+use payload_cache::PayloadCache;
+use prerender::FrameCache;
+use proxy::{Proxy, proxy_requests};
+use rate_limit::{RateLimiter, RateLimits, rate_limit, run_eviction_task};
+use report::{report_form_handler, submit_report_handler};
+use request_id::{handle_panic, track_request};
+use room_status::{room_badge_handler, room_schedule_handler, room_status_handler};
+use scheduler::{
+    Job, Scheduler, builtin_jobs, list_job_runs_handler, list_jobs_handler, pause_job_handler,
+    resume_job_handler, run_job_handler,
+};
+use status_page::{StatusCache, status_page_handler};
+
+/// Shared application state
+#[derive(Clone)]
+pub struct AppState {
+    /// Server configuration
+    pub config: Arc<Config>,
+    /// Device database
+    pub database: Arc<Database>,
+    /// Cached configuration read when serving displays
+    pub display_config: Arc<ConfigCache>,
+    /// Room calendars
+    pub calendars: Arc<CalendarRegistry>,
+    /// Notification sink for facilities and operators
+    pub notifier: Arc<dyn Notifier>,
+    /// Storage for hosted images
+    pub images: Arc<dyn ImageStore>,
+    /// Content hashes of the static files
+    pub assets: Arc<StaticAssets>,
+    /// In-process metrics
+    pub metrics: Arc<Metrics>,
+    /// Buffer of device log entries awaiting storage
+    pub logs: LogIngest,
+    /// Renderer of display images, in-process or external
+    pub renderer: Arc<dyn ImageRenderer>,
+    /// Frames pre-rendered after the calendar refresh
+    pub frames: Arc<FrameCache>,
+    /// Base64 payloads of recently served inline images
+    pub payloads: Arc<PayloadCache>,
+    /// Sink for error events of failed requests and background tasks
+    pub errors: Arc<dyn ErrorReporter>,
+    /// Passthrough of unknown devices to the TRMNL cloud, if enabled
+    pub proxy: Option<Arc<Proxy>>,
+    /// When the server started, for the uptime on the status page
+    pub started_at: Instant,
+    /// Publisher of the do-not-disturb state of the rooms
+    pub dnd: Arc<DndPublisher>,
+    /// Scheduler of the periodic jobs
+    pub scheduler: Arc<Scheduler>,
+    /// Rate limits of device requests and unauthenticated routes
+    pub rate_limits: Arc<RateLimits>,
+    /// Numbers last shown on the status page
+    pub status: Arc<StatusCache>,
+}
+
+impl AppState {
+    /// Create the application state for the given database and configuration
+    ///
+    /// Starts the device log writer, so this must be called within a Tokio runtime.
+    ///
+    /// All components are public, so that tests can swap single ones afterwards.
+    pub fn new(database: Arc<Database>, config: Arc<Config>) -> Result<Self> {
+        let notifier: Arc<dyn Notifier> = match config.notify_webhook_urls.as_slice() {
+            [] => Arc::new(LogNotifier),
+            urls => Arc::new(WebhookNotifier::new(urls.to_vec())),
+        };
+        let mqtt = config
+            .mqtt_url
+            .as_deref()
+            .map(|url| MqttPublisher::from_url(url, &format!("trmnl-{}", config.instance_id)))
+            .transpose()?;
+        let change_notifier: Arc<dyn Notifier> = match &mqtt {
+            Some(mqtt) => Arc::new(MqttChangeNotifier::new(
+                notifier.clone(),
+                mqtt.clone(),
+                &config.mqtt_topic_prefix,
+            )),
+            None => notifier.clone(),
+        };
+        let metrics = Arc::new(Metrics::new());
+        let calendars = CalendarRegistry::new(config.calendar_refresh_minutes)
+            .with_shared_cache(database.clone())
+            .with_change_notifier(change_notifier)
+            .with_metrics(metrics.clone());
+        let errors = create_error_reporter(config.error_sink.as_ref());
+        let mut renderer =
+            create_renderer(&config.renderer).context("Failed to set up renderer")?;
+        if config.render_cache_ttl_seconds > 0 {
+            renderer = Arc::new(
+                CachingRenderer::new(
+                    renderer,
+                    Duration::from_secs(config.render_cache_ttl_seconds),
+                )
+                .with_metrics(metrics.clone()),
+            );
+        }
+        Ok(Self {
+            logs: LogIngest::start(database.clone(), config.log_buffer_size, errors.clone()),
+            display_config: Arc::new(ConfigCache::new(
+                database.clone(),
+                Duration::from_secs(CONFIG_CACHE_TTL_SECS),
+            )),
+            database,
+            calendars: Arc::new(calendars),
+            notifier,
+            images: create_image_store(&config.image_store)
+                .context("Failed to set up image store")?,
+            assets: Arc::new(
+                StaticAssets::load(STATIC_DIR).context("Failed to hash static files")?,
+            ),
+            metrics,
+            renderer,
+            frames: Arc::new(FrameCache::new()),
+            payloads: Arc::new(PayloadCache::new(config.inline_cache_size)),
+            errors,
+            proxy: config
+                .proxy
+                .clone()
+                .map(|proxy| Proxy::new(proxy).map(Arc::new))
+                .transpose()?,
+            started_at: Instant::now(),
+            dnd: Arc::new(DndPublisher::new(mqtt, &config.mqtt_topic_prefix)),
+            scheduler: Arc::new(Scheduler::new()),
+            rate_limits: Arc::new(RateLimits {
+                devices: RateLimiter::new(config.rate_limit_device_per_minute),
+                addresses: RateLimiter::new(config.rate_limit_address_per_minute),
+                trust_forwarded_for: config.trust_forwarded_for,
+            }),
+            status: Arc::new(StatusCache::new()),
+            config,
+        })
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Database> {
+    fn from_ref(state: &AppState) -> Self {
+        state.database.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<CalendarRegistry> {
+    fn from_ref(state: &AppState) -> Self {
+        state.calendars.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn Notifier> {
+    fn from_ref(state: &AppState) -> Self {
+        state.notifier.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn ImageStore> {
+    fn from_ref(state: &AppState) -> Self {
+        state.images.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<StaticAssets> {
+    fn from_ref(state: &AppState) -> Self {
+        state.assets.clone()
+    }
+}
+
+impl FromRef<AppState> for LogIngest {
+    fn from_ref(state: &AppState) -> Self {
+        state.logs.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Metrics> {
+    fn from_ref(state: &AppState) -> Self {
+        state.metrics.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn ImageRenderer> {
+    fn from_ref(state: &AppState) -> Self {
+        state.renderer.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn ErrorReporter> {
+    fn from_ref(state: &AppState) -> Self {
+        state.errors.clone()
+    }
+}
+
+/// Routes of the device and admin API
+///
+/// Mounted both unversioned under `/api` and versioned under `/api/v1`, see
+/// [`version::ApiVersion`].
+fn api_routes() -> Router<AppState> {
+    Router::new()
+        .route("/setup/", get(setup_handler))
+        .route("/display", get(display_handler))
+        .route("/log", post(log_handler))
+        .route("/image-signing-key", get(image_signing_key_handler))
+        .route("/rooms", get(room_status_handler))
+        .route("/admin/summary", get(summary_handler))
+        .route("/admin/devices", get(list_devices_handler))
+        .route("/admin/devices/export", get(export_devices_handler))
+        .route("/admin/logs", get(list_device_logs_handler))
+        .route(
+            "/admin/devices/:id",
+            get(get_device_handler).delete(delete_device_handler),
+        )
+        .route("/admin/devices/:id/name", put(rename_device_handler))
+        .route(
+            "/admin/devices/:id/image-delivery",
+            put(set_image_delivery_handler),
+        )
+        .route(
+            "/admin/devices/:id/image-format",
+            put(set_image_format_handler),
+        )
+        .route(
+            "/admin/devices/:id/api-key",
+            post(reset_device_api_key_handler).delete(revoke_device_api_key_handler),
+        )
+        .route("/admin/devices/:id/adopt", post(adopt_device_handler))
+        .route("/admin/devices/:id/logs", get(device_logs_handler))
+        .route("/admin/calendars/test", post(test_calendar_handler))
+        .route(
+            "/admin/labels/:language/test",
+            post(test_label_pack_handler),
+        )
+        .route(
+            "/admin/broadcast",
+            post(create_broadcast_handler).delete(clear_broadcast_handler),
+        )
+        .route(
+            "/admin/maintenance",
+            get(get_maintenance_handler)
+                .put(start_maintenance_handler)
+                .delete(end_maintenance_handler),
+        )
+        .route(
+            "/admin/experiments",
+            get(list_experiments_handler).post(create_experiment_handler),
+        )
+        .route(
+            "/admin/experiments/:name/promote",
+            post(promote_experiment_handler),
+        )
+        .route(
+            "/admin/experiments/:name/rollback",
+            post(rollback_experiment_handler),
+        )
+        .route("/admin/issues", get(list_issues_handler))
+        .route("/admin/issues/:id/resolve", post(resolve_issue_handler))
+        .route(
+            "/admin/claim-codes",
+            get(list_claim_codes_handler).post(create_claim_code_handler),
+        )
+        .route(
+            "/admin/claim-codes/import",
+            post(import_claim_codes_handler),
+        )
+        .route(
+            "/admin/provisioning",
+            get(list_provisioned_devices_handler).post(provision_devices_handler),
+        )
+        .route("/admin/rooms", get(list_rooms_handler))
+        .route("/admin/rooms/export", get(export_rooms_handler))
+        .route(
+            "/admin/rooms/:id",
+            put(save_room_handler).delete(delete_room_handler),
+        )
+        .route(
+            "/admin/rooms/:id/utilization",
+            get(room_utilization_handler),
+        )
+        .route(
+            "/admin/rooms/:id/dnd",
+            put(set_dnd_handler).delete(clear_dnd_handler),
+        )
+        .route("/admin/jobs", get(list_jobs_handler))
+        .route("/admin/jobs/:name/runs", get(list_job_runs_handler))
+        .route("/admin/jobs/:name/run", post(run_job_handler))
+        .route(
+            "/admin/jobs/:name/pause",
+            put(pause_job_handler).delete(resume_job_handler),
+        )
+}
 
 /// Create app for testing or production
-pub fn create_app(database: Arc<Database>) -> Router {
+pub fn create_app(state: AppState) -> Router {
     Router::new()
-        .route("/api/setup/", get(setup_handler))
-        .route("/api/display", get(display_handler))
-        .route("/api/log", post(log_handler))
+        .nest("/api", api_routes())
+        .nest("/api/v1", api_routes())
+        .route(
+            "/report/:room",
+            get(report_form_handler).post(submit_report_handler),
+        )
+        .route("/admin", get(dashboard_handler))
+        .route(
+            "/admin/devices/:id/preview.bmp",
+            get(device_preview_handler),
+        )
+        .route("/preview/:file", get(room_preview_handler))
+        .route("/rooms/:id/badge.svg", get(room_badge_handler))
+        .route("/rooms/:id/schedule.json", get(room_schedule_handler))
+        .route("/images/:name", get(image_handler))
         .route("/health", get(health_handler))
-        .nest_service("/static", ServeDir::new("static"))
+        .route("/status", get(status_page_handler))
+        .route("/metrics", get(metrics_handler))
+        .nest_service("/static", ServeDir::new(STATIC_DIR))
         .layer(
-            ServiceBuilder::new().layer(
-                TraceLayer::new_for_http()
-                    .make_span_with(DefaultMakeSpan::new().level(Level::DEBUG))
-                    .on_request(DefaultOnRequest::new().level(Level::INFO))
-                    .on_response(DefaultOnResponse::new().level(Level::INFO)),
-            ),
+            ServiceBuilder::new()
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(DefaultMakeSpan::new().level(Level::DEBUG))
+                        .on_request(DefaultOnRequest::new().level(Level::INFO))
+                        .on_response(DefaultOnResponse::new().level(Level::INFO)),
+                )
+                .layer(middleware::from_fn_with_state(state.clone(), track_request))
+                .layer(middleware::from_fn_with_state(state.clone(), rate_limit))
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    proxy_requests,
+                ))
+                .layer(CatchPanicLayer::custom(handle_panic)),
         )
-        .with_state(database)
+        .with_state(state)
 }
 
-/// Start the server with the given database connection
-pub async fn start_server(database: Arc<Database>) -> Result<()> {
-    // Get configuration
-    let config = Config::get().context("Failed to load configuration")?;
+/// Start the server with the given database connection and configuration
+pub async fn start_server(database: Arc<Database>, config: Config) -> Result<()> {
+    start_server_with_jobs(database, config, Vec::new()).await
+}
 
+/// Start the server with the given database connection, configuration and
+/// additional jobs
+///
+/// The jobs are scheduled next to the built-in ones, which they replace if
+/// they have the same name, and can be rescheduled with `JOB_SCHEDULES` like
+/// them.
+pub async fn start_server_with_jobs(
+    database: Arc<Database>,
+    config: Config,
+    jobs: Vec<Job>,
+) -> Result<()> {
+    let config = Arc::new(config);
     let host = &config.server_host;
     let port = config.server_port;
     let addr = format!("{}:{}", host, port);
 
     info!("Starting server at http://{}", addr);
 
+    let state = AppState::new(database, config.clone())?;
+
+    // Start background tasks
+    spawn_supervised(
+        "rollover",
+        state.errors.clone(),
+        run_rollover_task(state.clone()),
+    );
+
+    spawn_supervised(
+        "rate_limit_eviction",
+        state.errors.clone(),
+        run_eviction_task(state.rate_limits.clone()),
+    );
+
+    for job in builtin_jobs(&state).into_iter().chain(jobs) {
+        state.scheduler.add(job);
+    }
+    for (name, schedule) in &config.job_schedules {
+        if !state.scheduler.set_schedule(name, schedule.clone()) {
+            warn!("Unknown job {} in JOB_SCHEDULES", name);
+        }
+    }
+    state.scheduler.start(state.clone());
+
+    if config.mqtt_url.is_some() {
+        spawn_supervised("dnd", state.errors.clone(), run_dnd_task(state.clone()));
+    }
+
+    if let Some(url) = &config.telemetry_url {
+        info!("Reporting anonymous usage statistics to {}", url);
+        spawn_supervised(
+            "telemetry",
+            state.errors.clone(),
+            run_telemetry_task(
+                UsageReporter::new(url.clone())?,
+                state.database.clone(),
+                config.rooms.clone(),
+                config.instance_id.clone(),
+            ),
+        );
+    }
+
     // Create the app
-    let app = create_app(database);
+    let app = create_app(state);
 
     // Create listener
     let listener = TcpListener::bind(&addr)
         .await
         .context("Failed to bind to address")?;
 
-    // Start the server
-    axum::serve(listener, app).await.context("Server error")
-}
-
-/// Create test app for testing
-#[cfg(test)]
-pub fn test_app(database: Arc<Database>) -> Router {
-    create_app(database)
+    // Start the server, client addresses are needed for the rate limits
+    if let Some(tls) = &config.tls {
+        info!("Serving HTTPS with certificate {}", tls.cert_path);
+        return tls::serve_tls(listener, app, tls).await;
+    }
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .context("Server error")
 }
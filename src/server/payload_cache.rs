@@ -0,0 +1,109 @@
+//! Cache of the base64 payloads of inline images
+//!
+//! With inline delivery, every display response carries the whole image as a
+//! base64 data URL, e.g. about half a megabyte for a grayscale BMP. The devices
+//! of a room mostly poll for the same frame, so the data URLs are kept by room
+//! and [`ImageConfig::fingerprint`](crate::bmp::ImageConfig::fingerprint), and
+//! the least recently used one is dropped once the cache is full.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Data URLs of the most recently served frames
+pub struct PayloadCache {
+    /// Maximum number of data URLs kept, 0 disables the cache
+    capacity: usize,
+    inner: Mutex<Payloads>,
+}
+
+#[derive(Default)]
+struct Payloads {
+    /// Data URLs by room and fingerprint, with the tick of their last use
+    entries: HashMap<(String, u64), (Arc<str>, u64)>,
+    /// Incremented on every lookup
+    tick: u64,
+}
+
+impl PayloadCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Payloads::default()),
+        }
+    }
+
+    /// The data URL of a frame, from the cache or created with `encode`
+    ///
+    /// Returns whether the data URL was cached. Encoding happens outside the
+    /// lock, so that requests for other frames are not held up.
+    pub fn get_or_encode(
+        &self,
+        room: &str,
+        fingerprint: u64,
+        encode: impl FnOnce() -> String,
+    ) -> (Arc<str>, bool) {
+        let key = (room.to_string(), fingerprint);
+        if let Ok(mut payloads) = self.inner.lock() {
+            payloads.tick += 1;
+            let tick = payloads.tick;
+            if let Some((payload, last_used)) = payloads.entries.get_mut(&key) {
+                *last_used = tick;
+                return (payload.clone(), true);
+            }
+        }
+
+        let payload: Arc<str> = encode().into();
+        if self.capacity == 0 {
+            return (payload, false);
+        }
+        if let Ok(mut payloads) = self.inner.lock() {
+            // Another request may have encoded the same frame meanwhile
+            while !payloads.entries.contains_key(&key) && payloads.entries.len() >= self.capacity {
+                let oldest = payloads
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, (_, last_used))| *last_used)
+                    .map(|(key, _)| key.clone());
+                match oldest {
+                    Some(oldest) => payloads.entries.remove(&oldest),
+                    None => break,
+                };
+            }
+            let tick = payloads.tick;
+            payloads.entries.insert(key, (payload.clone(), tick));
+        }
+        (payload, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload_cache() {
+        let cache = PayloadCache::new(2);
+        let encode = |data: &str| {
+            let data = data.to_string();
+            move || data
+        };
+
+        let (payload, cached) = cache.get_or_encode("room-a", 1, encode("a1"));
+        assert_eq!((&*payload, cached), ("a1", false));
+        let (payload, cached) = cache.get_or_encode("room-a", 1, encode("other"));
+        assert_eq!((&*payload, cached), ("a1", true));
+
+        // The least recently used frame is dropped once the cache is full
+        cache.get_or_encode("room-b", 1, encode("b1"));
+        cache.get_or_encode("room-a", 1, encode("a1"));
+        cache.get_or_encode("room-a", 2, encode("a2"));
+        assert!(cache.get_or_encode("room-a", 1, encode("a1")).1);
+        assert!(!cache.get_or_encode("room-b", 1, encode("b1")).1);
+
+        let disabled = PayloadCache::new(0);
+        disabled.get_or_encode("room-a", 1, encode("a1"));
+        assert!(!disabled.get_or_encode("room-a", 1, encode("a1")).1);
+    }
+}
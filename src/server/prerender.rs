@@ -0,0 +1,279 @@
+//! Pre-rendering of room frames after each calendar refresh
+//!
+//! Rendering is the expensive part of a display request, and all devices of a
+//! room show the same frame. After the calendars have been refreshed, the
+//! frame of every room is determined and only the rooms whose frame changed,
+//! according to the [`ImageConfig::fingerprint`], are rendered again, in
+//! parallel. Display requests then look their frame up by fingerprint and
+//! only render on a miss, e.g. for a device showing a battery notice.
+//!
+//! The cache also keeps the frame last served to each device, so that a frame
+//! of a single device is only rendered when it changes, and unchanged frames,
+//! which the firmware does not refresh, can be counted.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use log::warn;
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use super::AppState;
+use super::handlers::device_frame;
+use crate::rooms::Room;
+
+/// Rendered frames of the rooms and devices, by fingerprint
+#[derive(Default)]
+pub struct FrameCache {
+    inner: Mutex<Frames>,
+}
+
+#[derive(Default)]
+struct Frames {
+    /// Fingerprint of the current frame of each room
+    rooms: HashMap<String, u64>,
+    /// Fingerprint of the frame last served to each device
+    devices: HashMap<String, u64>,
+    /// Encoded images by fingerprint, shared by rooms and devices with
+    /// identical frames
+    images: HashMap<u64, Arc<Vec<u8>>>,
+}
+
+impl Frames {
+    /// Drop images no room or device refers to anymore
+    fn prune(&mut self) {
+        let used: HashSet<u64> = self
+            .rooms
+            .values()
+            .chain(self.devices.values())
+            .copied()
+            .collect();
+        self.images
+            .retain(|fingerprint, _| used.contains(fingerprint));
+    }
+}
+
+impl FrameCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The encoded image of a frame, if it was pre-rendered or served before
+    pub fn get(&self, fingerprint: u64) -> Option<Arc<Vec<u8>>> {
+        self.inner.lock().ok()?.images.get(&fingerprint).cloned()
+    }
+
+    /// Fingerprint of the pre-rendered frame of a room
+    pub fn fingerprint(&self, room_id: &str) -> Option<u64> {
+        self.inner.lock().ok()?.rooms.get(room_id).copied()
+    }
+
+    /// Store the new frame of a room, replacing its previous one
+    pub fn insert(&self, room_id: &str, fingerprint: u64, image: Arc<Vec<u8>>) {
+        if let Ok(mut frames) = self.inner.lock() {
+            frames.rooms.insert(room_id.to_string(), fingerprint);
+            frames.images.insert(fingerprint, image);
+            frames.prune();
+        }
+    }
+
+    /// Store the frame served to a device, replacing its previous one
+    ///
+    /// Returns whether the frame is the one served to the device before, in
+    /// which case the filename is the same and the firmware skips the refresh.
+    pub fn insert_device(&self, device_id: &str, fingerprint: u64, image: Arc<Vec<u8>>) -> bool {
+        let Ok(mut frames) = self.inner.lock() else {
+            return false;
+        };
+        let previous = frames.devices.insert(device_id.to_string(), fingerprint);
+        if previous == Some(fingerprint) {
+            return true;
+        }
+        frames.images.insert(fingerprint, image);
+        frames.prune();
+        false
+    }
+
+    /// Drop the frame of a room, so that it is rendered anew
+    pub fn remove_room(&self, room_id: &str) {
+        if let Ok(mut frames) = self.inner.lock() {
+            frames.rooms.remove(room_id);
+            frames.prune();
+        }
+    }
+
+    /// Drop the frames of rooms that no longer exist
+    pub fn retain_rooms(&self, rooms: &[Room]) {
+        if let Ok(mut frames) = self.inner.lock() {
+            frames
+                .rooms
+                .retain(|room_id, _| rooms.iter().any(|room| &room.id == room_id));
+            frames.prune();
+        }
+    }
+}
+
+/// Outcome of a pre-render run
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PrerenderStats {
+    /// Rooms whose frame changed and was rendered
+    pub rendered: usize,
+    /// Rooms whose frame was unchanged
+    pub skipped: usize,
+    /// Rooms whose frame failed to render
+    pub failed: usize,
+}
+
+/// Render the frames of the rooms that changed since the last run
+pub async fn prerender(state: &AppState, rooms: &[Room]) -> Result<PrerenderStats> {
+    let display_config = state
+        .display_config
+        .get()
+        .await
+        .context("Failed to load display configuration")?;
+
+    let mut stats = PrerenderStats::default();
+    let mut changed = Vec::new();
+    for room in rooms {
+        let mut frame = device_frame(
+            Some(room),
+            &display_config,
+            &state.config,
+            &state.calendars,
+            room.layout,
+            false,
+            None,
+        )
+        .await;
+        // Devices without a format setting of their own get the configured one
+        frame.image_config.format = state.config.image_format;
+        let fingerprint = frame.image_config.fingerprint();
+        if state.frames.fingerprint(&room.id) == Some(fingerprint) {
+            stats.skipped += 1;
+        } else {
+            changed.push((room.id.clone(), fingerprint, frame.image_config));
+        }
+    }
+
+    // One rendering per core but one, which is left to display requests, so
+    // that they are not starved by a large fleet of rooms
+    let parallelism =
+        std::thread::available_parallelism().map_or(1, |n| n.get().saturating_sub(1).max(1));
+    let permits = Arc::new(Semaphore::new(parallelism));
+    let mut tasks = JoinSet::new();
+    for (room_id, fingerprint, image_config) in changed {
+        let permit = permits
+            .clone()
+            .acquire_owned()
+            .await
+            .context("Render semaphore closed")?;
+        let renderer = state.renderer.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            (room_id, fingerprint, renderer.render(image_config).await)
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        let (room_id, fingerprint, image) = result.context("Render task failed")?;
+        match image {
+            Ok(image) => {
+                state.frames.insert(&room_id, fingerprint, Arc::new(image));
+                stats.rendered += 1;
+            }
+            Err(e) => {
+                warn!("Failed to pre-render frame of room {}: {:#}", room_id, e);
+                stats.failed += 1;
+            }
+        }
+    }
+    state.frames.retain_rooms(rooms);
+    state
+        .metrics
+        .record_prerender(stats.rendered, stats.skipped, stats.failed);
+
+    Ok(stats)
+}
+
+/// Refresh all room calendars and pre-render the frames that changed
+///
+/// Runs as the `calendar_refresh` job, every `CALENDAR_REFRESH_MINUTES` by
+/// default. Display requests then find fresh data in the calendar registry
+/// and, usually, a rendered frame. A calendar is still fetched on demand if a
+/// request comes before its first refresh, and frames are rendered on demand
+/// if a request comes first.
+pub async fn refresh_calendars(state: &AppState) -> Result<String> {
+    // Devices show the maintenance notice rather than the calendars
+    let maintenance = state
+        .display_config
+        .get()
+        .await
+        .is_ok_and(|config| config.maintenance().is_some());
+    if maintenance {
+        return Ok("skipped during maintenance".to_string());
+    }
+    let rooms = state.config.rooms.snapshot();
+    let failed = state.calendars.refresh_all(&rooms).await;
+    let stats = prerender(state, &rooms)
+        .await
+        .context("Failed to pre-render frames")?;
+    Ok(format!(
+        "refreshed room calendars ({} failed), pre-rendered {:?}",
+        failed, stats
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rooms::parse_rooms;
+
+    #[test]
+    fn test_frame_cache() {
+        let cache = FrameCache::new();
+        cache.insert("room-a", 1, Arc::new(vec![1]));
+        cache.insert("room-b", 1, Arc::new(vec![1]));
+        assert_eq!(cache.fingerprint("room-a"), Some(1));
+        assert_eq!(cache.get(1).unwrap().as_slice(), [1]);
+
+        // Images are dropped once no room shows them anymore
+        cache.insert("room-a", 2, Arc::new(vec![2]));
+        assert!(cache.get(1).is_some());
+        cache.insert("room-b", 2, Arc::new(vec![2]));
+        assert!(cache.get(1).is_none());
+
+        let rooms = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-b"
+            name = "Room B"
+            "#,
+        )
+        .unwrap();
+        cache.retain_rooms(&rooms);
+        assert_eq!(cache.fingerprint("room-a"), None);
+        assert!(cache.get(2).is_some());
+        cache.retain_rooms(&[]);
+        assert!(cache.get(2).is_none());
+    }
+
+    #[test]
+    fn test_device_frames() {
+        let cache = FrameCache::new();
+        cache.insert("room-a", 1, Arc::new(vec![1]));
+
+        assert!(!cache.insert_device("AA:BB", 1, Arc::new(vec![1])));
+        assert!(cache.insert_device("AA:BB", 1, Arc::new(vec![1])));
+
+        // A frame of the device only, e.g. with a battery notice, is kept
+        // while the device is served it
+        assert!(!cache.insert_device("AA:BB", 3, Arc::new(vec![3])));
+        cache.insert("room-a", 2, Arc::new(vec![2]));
+        assert!(cache.get(1).is_none());
+        assert_eq!(cache.get(3).unwrap().as_slice(), [3]);
+        assert!(cache.insert_device("AA:BB", 3, Arc::new(vec![3])));
+        assert!(!cache.insert_device("AA:BB", 2, Arc::new(vec![2])));
+        assert!(cache.get(3).is_none());
+    }
+}
@@ -0,0 +1,296 @@
+//! Passthrough of device requests to the TRMNL cloud
+//!
+//! While a fleet is migrated from the TRMNL cloud to this server, all devices
+//! can already be pointed here: requests of devices this server does not know
+//! (neither registered, provisioned nor presenting a claim code) are forwarded
+//! to the upstream API unchanged, so that they keep showing their cloud
+//! playlists. Routes this server does not implement, e.g. `/api/current_screen`,
+//! can be forwarded for all devices.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use axum::{
+    body::{Body, to_bytes},
+    extract::{Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::{debug, info};
+
+use super::AppState;
+use super::errors::AppError;
+use super::extract::constant_time_eq;
+use crate::database::Database;
+use crate::device_id::DeviceId;
+
+/// Largest request body forwarded upstream, device logs are the largest ones
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Timeout of upstream requests, the cloud renders screens on demand
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Device routes whose requests of unknown devices are forwarded, below `/api`
+const DEVICE_ROUTES: &[&str] = &["/setup", "/setup/", "/display", "/log"];
+
+/// Headers only meaningful for a single connection, not forwarded
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "host",
+    "content-length",
+];
+
+/// Configuration of the passthrough to the TRMNL cloud
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// Base URL of the upstream API, e.g. `https://usetrmnl.com`
+    pub upstream: String,
+    /// Paths forwarded for all devices, e.g. `/api/current_screen`
+    pub routes: Vec<String>,
+}
+
+impl ProxyConfig {
+    /// Whether a path is forwarded for all devices
+    ///
+    /// A configured route matches itself and the paths below it.
+    fn forwards_route(&self, path: &str) -> bool {
+        self.routes.iter().any(|route| {
+            let route = route.trim_end_matches('/');
+            path.strip_prefix(route)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+/// Forwarder of requests to the upstream API
+pub struct Proxy {
+    config: ProxyConfig,
+    client: reqwest::Client,
+}
+
+impl Proxy {
+    pub fn new(config: ProxyConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(UPSTREAM_TIMEOUT)
+            .build()
+            .context("Failed to create proxy HTTP client")?;
+        Ok(Self { config, client })
+    }
+
+    /// Whether a request is forwarded rather than served by this server
+    fn forwards(&self, path: &str, unknown_device: bool) -> bool {
+        self.config.forwards_route(path) || (is_device_route(path) && unknown_device)
+    }
+
+    /// Forward a request upstream and return the upstream response
+    async fn forward(&self, request: Request) -> Result<Response, AppError> {
+        let (parts, body) = request.into_parts();
+        let path_and_query = parts
+            .uri
+            .path_and_query()
+            .map_or(parts.uri.path(), |path| path.as_str());
+        let url = format!(
+            "{}{}",
+            self.config.upstream.trim_end_matches('/'),
+            path_and_query
+        );
+        let body = to_bytes(body, MAX_BODY_BYTES)
+            .await
+            .map_err(|e| AppError::BadRequest(format!("Failed to read request body: {}", e)))?;
+
+        let method = reqwest::Method::from_bytes(parts.method.as_str().as_bytes())
+            .map_err(|e| AppError::BadRequest(format!("Invalid method: {}", e)))?;
+        let mut upstream_request = self.client.request(method, &url).body(body);
+        for (name, value) in forwarded_headers(&parts.headers) {
+            upstream_request = upstream_request.header(name.as_str(), value.as_bytes());
+        }
+        let upstream_response = upstream_request
+            .send()
+            .await
+            .map_err(|e| AppError::Upstream(format!("Request to {} failed: {}", url, e)))?;
+
+        let status = StatusCode::from_u16(upstream_response.status().as_u16())
+            .map_err(|e| AppError::Upstream(format!("Invalid status from {}: {}", url, e)))?;
+        let mut headers = HeaderMap::new();
+        for (name, value) in upstream_response.headers() {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_str().as_bytes()),
+                HeaderValue::from_bytes(value.as_bytes()),
+            ) {
+                headers.append(name, value);
+            }
+        }
+        let body = upstream_response.bytes().await.map_err(|e| {
+            AppError::Upstream(format!("Failed to read response of {}: {}", url, e))
+        })?;
+
+        let mut response = (status, Body::from(body)).into_response();
+        for (name, value) in forwarded_headers(&headers) {
+            response.headers_mut().append(name.clone(), value.clone());
+        }
+        Ok(response)
+    }
+}
+
+/// Whether a path is one of the device routes, whose requests of unknown
+/// devices are forwarded
+fn is_device_route(path: &str) -> bool {
+    let route = path
+        .strip_prefix("/api/v1")
+        .or_else(|| path.strip_prefix("/api"))
+        .unwrap_or_default();
+    DEVICE_ROUTES.contains(&route)
+}
+
+/// Whether a request comes from a device this server does not know
+///
+/// Requests without a device ID are rejected by the handlers as usual, and
+/// devices presenting a claim code are set up here. The ID is looked up in
+/// the normalized spelling the devices are stored with.
+async fn is_unknown_device(headers: &HeaderMap, db: &Arc<Database>) -> Result<bool> {
+    let Some(device_id) = headers.get("ID").and_then(|id| id.to_str().ok()) else {
+        return Ok(false);
+    };
+    if headers.contains_key("Claim-Code") {
+        return Ok(false);
+    }
+    let device_id = DeviceId::lenient(device_id).into_string();
+    let known = db
+        .run_blocking(move |db| -> Result<bool> {
+            Ok(db
+                .device_exists(&device_id)
+                .with_context(|| format!("Failed to check if device exists: {}", device_id))?
+                || db
+                    .get_provisioned_device(&device_id)
+                    .with_context(|| {
+                        format!("Failed to get provisioning of device: {}", device_id)
+                    })?
+                    .is_some())
+        })
+        .await?;
+    Ok(!known)
+}
+
+/// Remove the credentials of this server from a request before it is forwarded
+///
+/// `Authorization` never goes upstream. The `Access-Token` of a device unknown
+/// here is its TRMNL cloud key, which the cloud needs; that of a known device
+/// is a key issued by this server, and the shared access token is never
+/// passed on either.
+fn strip_credentials(headers: &mut HeaderMap, unknown_device: bool, access_token: &str) {
+    headers.remove(header::AUTHORIZATION);
+    let own_token = headers
+        .get("Access-Token")
+        .and_then(|token| token.to_str().ok())
+        .is_some_and(|token| constant_time_eq(token, access_token));
+    if !unknown_device || own_token {
+        headers.remove("Access-Token");
+    }
+}
+
+/// Headers of a request or response that are passed on
+fn forwarded_headers(headers: &HeaderMap) -> impl Iterator<Item = (&HeaderName, &HeaderValue)> {
+    headers
+        .iter()
+        .filter(|(name, _)| !HOP_BY_HOP_HEADERS.contains(&name.as_str()))
+}
+
+/// Middleware forwarding requests to the TRMNL cloud, if the proxy is enabled
+pub async fn proxy_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(proxy) = &state.proxy else {
+        return next.run(request).await;
+    };
+    let path = request.uri().path();
+    if !is_device_route(path) && !proxy.config.forwards_route(path) {
+        return next.run(request).await;
+    }
+    let unknown = match is_unknown_device(request.headers(), &state.database).await {
+        Ok(unknown) => unknown,
+        Err(e) => return AppError::from(e).into_response(),
+    };
+    if !proxy.forwards(path, unknown) {
+        return next.run(request).await;
+    }
+    let mut request = request;
+    strip_credentials(request.headers_mut(), unknown, &state.config.access_token);
+
+    let device_id = request
+        .headers()
+        .get("ID")
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or("-")
+        .to_string();
+    let path = request.uri().path().to_string();
+    match proxy.forward(request).await {
+        Ok(response) => {
+            debug!(
+                "Forwarded {} of device {} upstream: {}",
+                path,
+                device_id,
+                response.status()
+            );
+            response
+        }
+        Err(e) => {
+            info!("Failed to forward {} of device {}: {}", path, device_id, e);
+            e.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forwarded_routes() {
+        let config = ProxyConfig {
+            upstream: "https://usetrmnl.com".to_string(),
+            routes: vec![
+                "/api/current_screen".to_string(),
+                "/api/models/".to_string(),
+            ],
+        };
+        assert!(config.forwards_route("/api/current_screen"));
+        assert!(config.forwards_route("/api/models"));
+        assert!(config.forwards_route("/api/models/og"));
+        assert!(!config.forwards_route("/api/current_screens"));
+        assert!(!config.forwards_route("/api/display"));
+    }
+
+    #[test]
+    fn test_strip_credentials() {
+        let headers = |token: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("Access-Token", HeaderValue::from_str(token).unwrap());
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer x"));
+            headers
+        };
+
+        // The cloud key of an unknown device is passed on
+        let mut unknown = headers("cloud-key");
+        strip_credentials(&mut unknown, true, "shared-token");
+        assert_eq!(unknown.get("Access-Token").unwrap(), "cloud-key");
+        assert!(!unknown.contains_key(header::AUTHORIZATION));
+
+        // Keys of this server are not
+        let mut shared = headers("shared-token");
+        strip_credentials(&mut shared, true, "shared-token");
+        assert!(shared.is_empty());
+        let mut known = headers("device-key");
+        strip_credentials(&mut known, false, "shared-token");
+        assert!(known.is_empty());
+    }
+}
@@ -0,0 +1,345 @@
+//! Rate limiting of device requests and unauthenticated routes
+//!
+//! Firmware stuck in a loop can request images many times a second, each of
+//! which may go through the render path. Display and log requests are
+//! therefore limited per device ID, and the routes anyone can call without a
+//! token (issue reports, previews, badges, schedules and the status page) per
+//! client address. Each key gets a token bucket holding a minute's worth of
+//! requests, refilled continuously; a request finding the bucket empty is
+//! rejected with `429 Too Many Requests` and a `Retry-After` header.
+//!
+//! Device requests are only charged once the handler authenticated the device
+//! (see [`DeviceAuthentication`]), so that requests with a forged device ID,
+//! e.g. logs accepted with `LOG_AUTH=permissive`, cannot use up the device's
+//! requests.
+//! Buckets that refilled completely are dropped every minute.
+//!
+//! Limits are per instance. Behind a reverse proxy, every request comes from
+//! the proxy's address, unless the client address is taken from the
+//! `X-Forwarded-For` header the proxy adds.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tracing::debug;
+
+use super::AppState;
+use super::errors::AppError;
+use crate::device_id::DeviceId;
+
+/// Device routes limited per device, below `/api`
+const DEVICE_ROUTES: &[&str] = &["/display", "/log"];
+
+/// Prefixes of the unauthenticated routes limited per client address
+const UNAUTHENTICATED_ROUTES: &[&str] = &["/report/", "/preview/", "/rooms/", "/status"];
+
+/// How often buckets that refilled completely are dropped
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Token bucket of a key
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Limit of the requests per key, e.g. per device
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Limiter allowing `per_minute` requests per key, 0 for no limit
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Requests added to a bucket per second
+    fn per_second(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+
+    /// Take a request of `key` at `now` from its bucket
+    ///
+    /// Returns the time until the next request is allowed if the bucket is
+    /// empty.
+    pub fn check(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+        let Ok(mut buckets) = self.buckets.lock() else {
+            return Ok(());
+        };
+        let capacity = f64::from(self.per_minute);
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.per_second()).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_second(),
+            ))
+        }
+    }
+
+    /// Whether `key` has a request left at `now`, without taking it
+    ///
+    /// Returns the time until the next request is allowed if the bucket is
+    /// empty.
+    pub fn peek(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+        let Ok(buckets) = self.buckets.lock() else {
+            return Ok(());
+        };
+        let Some(bucket) = buckets.get(key) else {
+            return Ok(());
+        };
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        let tokens = bucket.tokens + elapsed.as_secs_f64() * self.per_second();
+        if tokens >= 1.0 {
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - tokens) / self.per_second()))
+        }
+    }
+
+    /// Drop the buckets that refilled completely by `now`
+    pub fn evict_idle(&self, now: Instant) {
+        let Ok(mut buckets) = self.buckets.lock() else {
+            return;
+        };
+        let capacity = f64::from(self.per_minute);
+        let per_second = self.per_second();
+        buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated);
+            bucket.tokens + elapsed.as_secs_f64() * per_second < capacity
+        });
+    }
+
+    /// Number of keys with a bucket
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.buckets.lock().unwrap().len()
+    }
+}
+
+/// Rate limiters of the server
+#[derive(Debug)]
+pub struct RateLimits {
+    /// Display and log requests, by device ID
+    pub devices: RateLimiter,
+    /// Requests to unauthenticated routes, by client address
+    pub addresses: RateLimiter,
+    /// Whether the client address is taken from `X-Forwarded-For`
+    pub trust_forwarded_for: bool,
+}
+
+/// Marks a device request as authenticated by its handler
+///
+/// Added to the requests of the device routes, and set by the handlers once
+/// the device proved its identity. Only such requests are charged to the
+/// device.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceAuthentication(Arc<AtomicBool>);
+
+impl DeviceAuthentication {
+    /// Mark the request as authenticated
+    pub fn set(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the handler authenticated the request
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Drop the buckets that refilled completely, every [`EVICTION_INTERVAL`]
+pub async fn run_eviction_task(limits: Arc<RateLimits>) {
+    let mut interval = tokio::time::interval(EVICTION_INTERVAL);
+    loop {
+        interval.tick().await;
+        let now = Instant::now();
+        limits.devices.evict_idle(now);
+        limits.addresses.evict_idle(now);
+    }
+}
+
+/// Address of the client of a request
+///
+/// With `trust_forwarded_for`, this is the last address of the
+/// `X-Forwarded-For` header, the one added by the reverse proxy in front of
+/// the server. Addresses added before can be forged by the client.
+fn client_address(
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+    trust_forwarded_for: bool,
+) -> Option<IpAddr> {
+    let forwarded = trust_forwarded_for
+        .then(|| headers.get("X-Forwarded-For")?.to_str().ok())
+        .flatten()
+        .and_then(|addresses| addresses.rsplit(',').next())
+        .and_then(|address| address.trim().parse().ok());
+    forwarded.or(peer.map(|peer| peer.ip()))
+}
+
+/// Middleware rejecting requests over the limit of their device or client
+/// address
+pub async fn rate_limit(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    let route = path
+        .strip_prefix("/api/v1")
+        .or_else(|| path.strip_prefix("/api"));
+    let limits = &state.rate_limits;
+    if route.is_some_and(|route| DEVICE_ROUTES.contains(&route)) {
+        // Requests without a device ID are rejected by the handlers as usual
+        let Some(device_id) = request.headers().get("ID").and_then(|id| id.to_str().ok()) else {
+            return next.run(request).await;
+        };
+        let key = DeviceId::lenient(device_id).into_string();
+        if let Err(retry_after) = limits.devices.peek(&key, Instant::now()) {
+            debug!("Rate limited {} of {}", path, key);
+            state.metrics.record_rate_limited(true);
+            return AppError::RateLimited((retry_after.as_secs_f64().ceil() as u64).max(1))
+                .into_response();
+        }
+        let authentication = DeviceAuthentication::default();
+        request.extensions_mut().insert(authentication.clone());
+        let response = next.run(request).await;
+        // Concurrent requests may have used up the bucket in the meantime,
+        // which only limits the next request
+        if authentication.is_set() {
+            let _ = limits.devices.check(&key, Instant::now());
+        }
+        return response;
+    }
+
+    let key = if UNAUTHENTICATED_ROUTES
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+    {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0);
+        let Some(address) = client_address(request.headers(), peer, limits.trust_forwarded_for)
+        else {
+            return next.run(request).await;
+        };
+        address.to_string()
+    } else {
+        return next.run(request).await;
+    };
+
+    match limits.addresses.check(&key, Instant::now()) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            debug!("Rate limited {} of {}", path, key);
+            state.metrics.record_rate_limited(false);
+            AppError::RateLimited((retry_after.as_secs_f64().ceil() as u64).max(1)).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(6);
+        let check = |key, now| {
+            limiter
+                .check(key, now)
+                .map_err(|retry_after| retry_after.as_secs_f64().round())
+        };
+        let start = Instant::now();
+        for _ in 0..6 {
+            assert!(check("AA:BB:CC:DD:EE:FF", start).is_ok());
+        }
+        assert_eq!(check("AA:BB:CC:DD:EE:FF", start), Err(10.0));
+        // Other keys have buckets of their own
+        assert!(check("11:22:33:44:55:66", start).is_ok());
+
+        // A token is added every 10 seconds
+        let later = start + Duration::from_secs(15);
+        assert!(check("AA:BB:CC:DD:EE:FF", later).is_ok());
+        assert_eq!(check("AA:BB:CC:DD:EE:FF", later), Err(5.0));
+
+        let unlimited = RateLimiter::new(0);
+        for _ in 0..100 {
+            assert!(unlimited.check("AA:BB:CC:DD:EE:FF", start).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_rate_limiter_peek_and_evict() {
+        let limiter = RateLimiter::new(6);
+        let start = Instant::now();
+        // Peeking neither takes a request nor creates a bucket
+        assert!(limiter.peek("AA:BB:CC:DD:EE:FF", start).is_ok());
+        assert_eq!(limiter.len(), 0);
+        for _ in 0..6 {
+            assert!(limiter.check("AA:BB:CC:DD:EE:FF", start).is_ok());
+        }
+        assert!(limiter.peek("AA:BB:CC:DD:EE:FF", start).is_err());
+        assert!(limiter.check("11:22:33:44:55:66", start).is_ok());
+
+        // Buckets are dropped once they refilled
+        limiter.evict_idle(start + Duration::from_secs(5));
+        assert_eq!(limiter.len(), 2);
+        limiter.evict_idle(start + Duration::from_secs(60));
+        assert_eq!(limiter.len(), 0);
+    }
+
+    #[test]
+    fn test_client_address() {
+        let peer = Some("10.0.0.1:50000".parse().unwrap());
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Forwarded-For", "1.2.3.4, 192.0.2.7".parse().unwrap());
+
+        assert_eq!(
+            client_address(&headers, peer, false),
+            Some("10.0.0.1".parse().unwrap())
+        );
+        assert_eq!(
+            client_address(&headers, peer, true),
+            Some("192.0.2.7".parse().unwrap())
+        );
+        assert_eq!(
+            client_address(&HeaderMap::new(), peer, true),
+            Some("10.0.0.1".parse().unwrap())
+        );
+        assert_eq!(client_address(&HeaderMap::new(), None, false), None);
+    }
+}
@@ -0,0 +1,181 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    Form,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use serde::Deserialize;
+use tracing::info;
+
+use super::config::Config;
+use super::errors::AppError;
+use crate::database::Database;
+use crate::notify::{Notification, Notifier};
+use crate::rooms::Room;
+
+/// Categories of issues that can be reported for a room
+pub const ISSUE_CATEGORIES: &[(&str, &str)] = &[
+    ("projector", "Projector / screen"),
+    ("video", "Video conferencing"),
+    ("audio", "Audio"),
+    ("furniture", "Furniture"),
+    ("cleanliness", "Cleanliness"),
+    ("climate", "Temperature / air"),
+    ("other", "Other"),
+];
+
+/// Maximum length of an issue description
+const MAX_DESCRIPTION_LENGTH: usize = 1000;
+
+/// Submitted issue report form
+#[derive(Deserialize)]
+pub struct IssueReportForm {
+    pub category: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// Escape text for inclusion in HTML
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Wrap page content in a minimal mobile-friendly HTML document
+pub(super) fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n</head>\n<body>\n{}\n</body>\n</html>\n",
+        escape_html(title),
+        body
+    )
+}
+
+/// Look up a configured room
+fn find_room(config: &Config, room_id: &str) -> Option<Room> {
+    config
+        .rooms
+        .snapshot()
+        .iter()
+        .find(|r| r.id == room_id)
+        .cloned()
+}
+
+/// Page shown for unknown rooms
+fn room_not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Html(page("Unknown room", "<h1>Unknown room</h1>")),
+    )
+        .into_response()
+}
+
+/// Issue report form page, the target of the QR code shown in a room
+pub async fn report_form_handler(
+    Path(room_id): Path<String>,
+    State(config): State<Arc<Config>>,
+) -> Result<Response, AppError> {
+    let Some(room) = find_room(&config, &room_id) else {
+        return Ok(room_not_found());
+    };
+
+    let options: String = ISSUE_CATEGORIES
+        .iter()
+        .map(|(value, label)| format!("<option value=\"{}\">{}</option>", value, label))
+        .collect();
+    let body = format!(
+        "<h1>Report an issue with {name}</h1>\n\
+         <form method=\"post\">\n\
+         <p><label>Category<br><select name=\"category\">{options}</select></label></p>\n\
+         <p><label>Description<br>\
+         <textarea name=\"description\" rows=\"4\" maxlength=\"{max}\"></textarea></label></p>\n\
+         <p><button type=\"submit\">Report issue</button></p>\n\
+         </form>",
+        name = escape_html(&room.name),
+        options = options,
+        max = MAX_DESCRIPTION_LENGTH,
+    );
+
+    Ok(Html(page(&format!("Report an issue: {}", room.name), &body)).into_response())
+}
+
+/// Issue report submission handler
+pub async fn submit_report_handler(
+    Path(room_id): Path<String>,
+    State(db): State<Arc<Database>>,
+    State(notifier): State<Arc<dyn Notifier>>,
+    State(config): State<Arc<Config>>,
+    Form(form): Form<IssueReportForm>,
+) -> Result<Response, AppError> {
+    let Some(room) = find_room(&config, &room_id) else {
+        return Ok(room_not_found());
+    };
+
+    let Some((category, category_label)) = ISSUE_CATEGORIES
+        .iter()
+        .find(|(value, _)| *value == form.category)
+    else {
+        return Err(AppError::BadRequest(format!(
+            "Unknown issue category: {}",
+            form.category
+        )));
+    };
+    let description: String = form
+        .description
+        .trim()
+        .chars()
+        .take(MAX_DESCRIPTION_LENGTH)
+        .collect();
+
+    let (id, text) = (room.id.clone(), description.clone());
+    let report = db
+        .run_blocking(move |db| {
+            db.create_issue_report(&id, category, &text)
+                .context("Failed to record issue report")
+        })
+        .await
+        .map_err(AppError::from)?;
+
+    info!(
+        "Issue {} reported for room {}: {}",
+        report.id, room.id, category
+    );
+
+    notifier.notify(Notification {
+        kind: "room.issue_reported".to_string(),
+        title: format!("Issue reported in {}: {}", room.name, category_label),
+        message: description,
+        room_id: Some(room.id.clone()),
+        device_id: None,
+        details: None,
+    });
+
+    Ok(Html(page(
+        "Issue reported",
+        &format!(
+            "<h1>Thank you!</h1>\n<p>The issue with {} has been reported to facilities.</p>",
+            escape_html(&room.name)
+        ),
+    ))
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html("<b>\"Tom\" & 'Jerry'</b>"),
+            "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;"
+        );
+    }
+}
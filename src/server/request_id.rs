@@ -0,0 +1,135 @@
+//! Request IDs and error reporting of failed requests
+//!
+//! Every request gets an ID, taken from the `X-Request-Id` header of a proxy
+//! in front of the server or generated. The ID is returned in the response
+//! and attached to the error events of 5xx responses.
+
+use std::any::Any;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::error;
+
+use super::AppState;
+use super::errors::{AppError, ErrorDetail};
+use crate::error_report::{ErrorEvent, panic_message};
+use crate::rooms::resolve_device_room;
+
+/// Header the request ID is read from and returned in
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request ID accepted from a client
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// ID of the current request, available to handlers as a request extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// ID from the `X-Request-Id` header if it is usable, a new one otherwise
+    fn from_headers(headers: &HeaderMap) -> Self {
+        headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| {
+                !id.is_empty()
+                    && id.len() <= MAX_REQUEST_ID_LEN
+                    && id
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            })
+            .map(|id| RequestId(id.to_string()))
+            .unwrap_or_else(|| RequestId(format!("{:016x}", rand::random::<u64>())))
+    }
+}
+
+/// Middleware assigning request IDs and reporting 5xx responses
+pub async fn track_request(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let request_id = RequestId::from_headers(request.headers());
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let device_id = request
+        .headers()
+        .get("ID")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    request.extensions_mut().insert(request_id.clone());
+
+    let mut response = next.run(request).await;
+
+    let status = response.status();
+    if status.is_server_error() {
+        let message = response
+            .extensions()
+            .get::<ErrorDetail>()
+            .map(|detail| detail.0.clone())
+            .unwrap_or_else(|| format!("{} {} failed with {}", method, path, status));
+        let mut event = ErrorEvent::new(message)
+            .with_request_id(request_id.0.clone())
+            .with_tag("source", "http")
+            .with_tag("method", method)
+            .with_tag("path", path)
+            .with_tag("status", status.as_u16().to_string());
+        if let Some(device_id) = device_id {
+            if let Some(room_id) = device_room(&state, &device_id) {
+                event = event.with_tag("room_id", room_id);
+            }
+            event = event.with_tag("device_id", device_id);
+        }
+        state.errors.report(event);
+    }
+
+    if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// Room of a device, for tagging error events
+fn device_room(state: &AppState, device_id: &str) -> Option<String> {
+    let claimed_room_id = state
+        .database
+        .get_device(device_id)
+        .ok()
+        .flatten()
+        .and_then(|device| device.room_id);
+    let rooms = state.config.rooms.snapshot();
+    resolve_device_room(&rooms, device_id, claimed_room_id.as_deref()).map(|room| room.id.clone())
+}
+
+/// Turn a panicking handler into a 500 response, which is then reported
+pub fn handle_panic(payload: Box<dyn Any + Send + 'static>) -> Response {
+    let message = format!("Handler panicked: {}", panic_message(payload.as_ref()));
+    error!("{}", message);
+    AppError::Anyhow(anyhow::anyhow!(message)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_id_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_ID_HEADER, HeaderValue::from_static("proxy-42.a_b"));
+        assert_eq!(RequestId::from_headers(&headers).0, "proxy-42.a_b");
+
+        let generated = RequestId::from_headers(&HeaderMap::new());
+        assert_eq!(generated.0.len(), 16);
+        assert_ne!(generated, RequestId::from_headers(&HeaderMap::new()));
+
+        // IDs that would be unsafe to log or forward are replaced
+        for id in ["", "with space", "a\"b", &"x".repeat(65)] {
+            headers.insert(REQUEST_ID_HEADER, HeaderValue::from_str(id).unwrap());
+            assert_ne!(RequestId::from_headers(&headers).0, id);
+        }
+    }
+}
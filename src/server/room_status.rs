@@ -0,0 +1,442 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use chrono::{DateTime, Local, NaiveTime};
+use serde::{Deserialize, Serialize};
+
+use super::config::Config;
+use super::errors::AppError;
+use crate::bmp::{StatusBadge, status_badge_svg};
+use crate::calendar::{CalendarEvent, CalendarRegistry, LOOKAHEAD_DAYS};
+use crate::labels::LabelPack;
+use crate::rooms::{Equipment, Room};
+use crate::status::RoomState;
+
+/// Query parameters of the room status endpoint
+#[derive(Deserialize)]
+pub struct RoomStatusParams {
+    /// Comma-separated equipment all returned rooms must have, e.g. `vc,tv`
+    pub equipment: Option<String>,
+    /// Minimum number of seats
+    pub min_capacity: Option<u32>,
+    /// Floor of the returned rooms, e.g. `3`
+    pub floor: Option<String>,
+    /// Text the name of the returned rooms contains, ignoring case
+    pub q: Option<String>,
+}
+
+/// Occupancy of a room
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Occupancy {
+    Free,
+    Busy,
+    /// The room has no calendar, or it could not be fetched
+    Unknown,
+}
+
+/// Status of a room as returned by the room status API
+///
+/// Only free/busy information is exposed, not meeting titles.
+#[derive(Serialize, Deserialize)]
+pub struct RoomStatus {
+    pub id: String,
+    pub name: String,
+    pub floor: Option<String>,
+    pub zone: Option<String>,
+    pub capacity: Option<u32>,
+    pub equipment: Vec<Equipment>,
+    pub occupancy: Occupancy,
+    /// End of the current meeting, if the room is busy
+    pub busy_until: Option<DateTime<Local>>,
+    /// Start of the next meeting, if any
+    pub next_meeting_at: Option<DateTime<Local>>,
+    /// Whether the status is from the room's fallback calendar, as its
+    /// calendar cannot be fetched
+    #[serde(default)]
+    pub calendar_degraded: bool,
+}
+
+/// Returns true if the room has all the given equipment and enough seats, and
+/// is on the given floor and matches the search, if any
+fn room_matches(room: &Room, params: &RoomStatusParams, equipment: &[Equipment]) -> bool {
+    equipment.iter().all(|e| room.equipment.contains(e))
+        && params
+            .min_capacity
+            .is_none_or(|min| room.capacity.is_some_and(|c| c >= min))
+        && params
+            .floor
+            .as_deref()
+            .is_none_or(|floor| room.on_floor(floor))
+        && params.q.as_deref().is_none_or(|q| room.matches_search(q))
+}
+
+/// Current status of a room from its cached calendar
+///
+/// Calendars are never fetched for the status, which anyone can query, so
+/// the occupancy of a room whose calendar was not fetched yet is unknown.
+pub(super) fn room_status(room: &Room, calendars: &CalendarRegistry) -> RoomStatus {
+    let mut status = RoomStatus {
+        id: room.id.clone(),
+        name: room.header_title().to_string(),
+        floor: room.floor.clone(),
+        zone: room.zone.clone(),
+        capacity: room.capacity,
+        equipment: room.equipment.clone(),
+        occupancy: Occupancy::Unknown,
+        busy_until: None,
+        next_meeting_at: None,
+        calendar_degraded: false,
+    };
+    if room.calendar_url.is_none() {
+        return status;
+    }
+    let Some(events) = calendars.cached_future_events(&room.id) else {
+        return status;
+    };
+    status.calendar_degraded = calendars.is_degraded(&room.id);
+
+    match RoomState::resolve(&events, Local::now(), 0) {
+        RoomState::Free { next } => {
+            status.occupancy = Occupancy::Free;
+            status.next_meeting_at = next.map(|e| e.start_time);
+        }
+        RoomState::Busy { current, next } | RoomState::EndingSoon { current, next, .. } => {
+            status.occupancy = Occupancy::Busy;
+            status.busy_until = Some(current.end_time);
+            status.next_meeting_at = next.map(|e| e.start_time);
+        }
+    }
+    status
+}
+
+/// Room status endpoint handler
+///
+/// Not authenticated, so that booking pages can query it directly.
+pub async fn room_status_handler(
+    Query(params): Query<RoomStatusParams>,
+    State(calendars): State<Arc<CalendarRegistry>>,
+    State(config): State<Arc<Config>>,
+) -> Result<impl IntoResponse, AppError> {
+    let equipment = match &params.equipment {
+        Some(list) => list
+            .split(',')
+            .filter(|e| !e.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<Equipment>, String>>()
+            .map_err(AppError::BadRequest)?,
+        None => Vec::new(),
+    };
+
+    let mut rooms = Vec::new();
+    for room in config.rooms.snapshot().iter() {
+        if room_matches(room, &params, &equipment) {
+            rooms.push(room_status(room, &calendars));
+        }
+    }
+
+    Ok(Json(rooms))
+}
+
+/// Status of a room as shown on badges and lobby displays, e.g. `BUSY until 14:00`
+pub(super) fn status_text(status: &RoomStatus, labels: &LabelPack, now: DateTime<Local>) -> String {
+    let time = |t: DateTime<Local>| [("time", t.format("%H:%M").to_string().into())];
+    match (status.occupancy, status.busy_until, status.next_meeting_at) {
+        (Occupancy::Busy, Some(until), _) => labels.format("status-busy-until", &time(until)),
+        (Occupancy::Busy, None, _) => labels.text("status-busy"),
+        // Only show the time if the next meeting is today
+        (Occupancy::Free, _, Some(next)) if next.date_naive() == now.date_naive() => {
+            labels.format("status-free-until", &time(next))
+        }
+        (Occupancy::Free, _, _) => labels.text("status-free"),
+        (Occupancy::Unknown, _, _) => labels.text("status-unknown"),
+    }
+}
+
+/// Colors of the status badge by occupancy
+fn badge_color(occupancy: Occupancy) -> &'static str {
+    match occupancy {
+        Occupancy::Free => "#4c1",
+        Occupancy::Busy => "#e05d44",
+        Occupancy::Unknown => "#9f9f9f",
+    }
+}
+
+/// Room status badge endpoint handler
+///
+/// Returns an SVG badge such as `Matterhorn | BUSY until 14:00` for embedding
+/// in wikis and intranet pages. Not authenticated, like the room status API.
+pub async fn room_badge_handler(
+    Path(room_id): Path<String>,
+    State(calendars): State<Arc<CalendarRegistry>>,
+    State(config): State<Arc<Config>>,
+) -> Result<Response, AppError> {
+    let rooms = config.rooms.snapshot();
+    let Some(room) = rooms.iter().find(|room| room.id == room_id) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let status = room_status(room, &calendars);
+    let text = status_text(
+        &status,
+        &config.labels.snapshot().pack(&room.language),
+        Local::now(),
+    );
+    let svg = status_badge_svg(&StatusBadge {
+        label: status.name,
+        status: text,
+        color: badge_color(status.occupancy),
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/svg+xml"),
+            // Embedding pages should not show a stale status for long
+            (header::CACHE_CONTROL, "max-age=60"),
+        ],
+        svg,
+    )
+        .into_response())
+}
+
+/// Days of the schedule feed if not given
+const DEFAULT_SCHEDULE_DAYS: u32 = 1;
+/// Most days of the schedule feed, calendars are not parsed further ahead
+const MAX_SCHEDULE_DAYS: u32 = LOOKAHEAD_DAYS - 1;
+
+/// Query parameters of the room schedule endpoint
+#[derive(Deserialize)]
+pub struct ScheduleParams {
+    /// Number of days, starting today
+    pub days: Option<u32>,
+}
+
+/// Meeting in a room's schedule feed
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleEntry {
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    /// Title of the meeting, null unless the room publishes titles and the
+    /// meeting is not private
+    pub title: Option<String>,
+}
+
+/// Upcoming meetings of a room as returned by the schedule feed
+#[derive(Serialize, Deserialize)]
+pub struct RoomSchedule {
+    pub id: String,
+    pub name: String,
+    pub floor: Option<String>,
+    pub zone: Option<String>,
+    pub capacity: Option<u32>,
+    /// End of the time span covered, midnight after the last day
+    pub until: DateTime<Local>,
+    /// False if the room has no calendar or it could not be fetched, so that
+    /// an empty schedule does not mean the room is free
+    pub calendar_available: bool,
+    /// Whether the meetings are from the room's fallback calendar, as its
+    /// calendar cannot be fetched
+    #[serde(default)]
+    pub calendar_degraded: bool,
+    /// Current and upcoming meetings, by start time
+    pub events: Vec<ScheduleEntry>,
+}
+
+/// Entries of the meetings overlapping the time from `now` to `until`
+fn schedule_entries(
+    room: &Room,
+    events: &[CalendarEvent],
+    now: DateTime<Local>,
+    until: DateTime<Local>,
+) -> Vec<ScheduleEntry> {
+    events
+        .iter()
+        .filter(|event| event.end_time > now && event.start_time < until)
+        .map(|event| ScheduleEntry {
+            start: event.start_time,
+            end: event.end_time,
+            title: (room.public_titles && !event.private).then(|| event.name.clone()),
+        })
+        .collect()
+}
+
+/// Room schedule endpoint handler
+///
+/// Returns the meetings of today and the following days as JSON, for
+/// signage systems that cannot read iCalendar. Not authenticated, like the
+/// room status API, so meeting titles are only included for rooms with
+/// `public_titles`.
+pub async fn room_schedule_handler(
+    Path(room_id): Path<String>,
+    Query(params): Query<ScheduleParams>,
+    State(calendars): State<Arc<CalendarRegistry>>,
+    State(config): State<Arc<Config>>,
+) -> Result<Response, AppError> {
+    let days = params.days.unwrap_or(DEFAULT_SCHEDULE_DAYS);
+    if !(1..=MAX_SCHEDULE_DAYS).contains(&days) {
+        return Err(AppError::BadRequest(format!(
+            "days must be between 1 and {}",
+            MAX_SCHEDULE_DAYS
+        )));
+    }
+    let rooms = config.rooms.snapshot();
+    let Some(room) = rooms.iter().find(|room| room.id == room_id) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let now = Local::now();
+    let until = (now.date_naive() + chrono::Days::new(days.into()))
+        .and_time(NaiveTime::MIN)
+        .and_local_timezone(Local)
+        .earliest()
+        .unwrap_or(now);
+    // Calendars are never fetched for the unauthenticated schedule
+    let events = room
+        .calendar_url
+        .as_ref()
+        .and_then(|_| calendars.cached_future_events(&room.id));
+    let schedule = RoomSchedule {
+        id: room.id.clone(),
+        name: room.header_title().to_string(),
+        floor: room.floor.clone(),
+        zone: room.zone.clone(),
+        capacity: room.capacity,
+        until,
+        calendar_available: events.is_some(),
+        calendar_degraded: events.is_some() && calendars.is_degraded(&room.id),
+        events: schedule_entries(room, events.as_deref().unwrap_or_default(), now, until),
+    };
+
+    Ok((
+        // Signage polls frequently, a minute of staleness is fine
+        [(header::CACHE_CONTROL, "max-age=60")],
+        Json(schedule),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::rooms::parse_rooms;
+
+    #[test]
+    fn test_room_matches() {
+        let rooms = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "big"
+            name = "Big"
+            floor = "3"
+            capacity = 12
+            equipment = ["tv", "vc"]
+
+            [[rooms]]
+            id = "small"
+            name = "Small"
+            equipment = ["whiteboard"]
+            "#,
+        )
+        .unwrap();
+
+        let params = |min_capacity, floor: Option<&str>, q: Option<&str>| RoomStatusParams {
+            equipment: None,
+            min_capacity,
+            floor: floor.map(str::to_string),
+            q: q.map(str::to_string),
+        };
+        assert!(room_matches(
+            &rooms[0],
+            &params(Some(10), None, None),
+            &[Equipment::Vc]
+        ));
+        assert!(!room_matches(
+            &rooms[0],
+            &params(None, None, None),
+            &[Equipment::Whiteboard]
+        ));
+        assert!(!room_matches(&rooms[0], &params(Some(20), None, None), &[]));
+        assert!(room_matches(&rooms[1], &params(None, None, None), &[]));
+        // Rooms without a known capacity never match a capacity filter
+        assert!(!room_matches(&rooms[1], &params(Some(1), None, None), &[]));
+
+        assert!(room_matches(&rooms[0], &params(None, Some("3"), None), &[]));
+        assert!(!room_matches(
+            &rooms[0],
+            &params(None, Some("2"), None),
+            &[]
+        ));
+        // Rooms without a floor never match a floor filter
+        assert!(!room_matches(
+            &rooms[1],
+            &params(None, Some("3"), None),
+            &[]
+        ));
+        assert!(room_matches(
+            &rooms[1],
+            &params(None, None, Some("SMA")),
+            &[]
+        ));
+        assert!(!room_matches(
+            &rooms[0],
+            &params(None, None, Some("small")),
+            &[]
+        ));
+    }
+
+    #[test]
+    fn test_schedule_entries() {
+        let mut rooms = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "lobby"
+            name = "Lobby"
+            "#,
+        )
+        .unwrap();
+        let at = |hour: u32| {
+            Local
+                .with_ymd_and_hms(2024, 3, 4, hour, 0, 0)
+                .single()
+                .unwrap()
+        };
+        let events = vec![
+            CalendarEvent::new("Breakfast".to_string(), at(7), at(8), None, None),
+            CalendarEvent::new("Planning".to_string(), at(9), at(11), None, None),
+            CalendarEvent::new("Offsite".to_string(), at(12), at(13), None, None)
+                .with_private(true),
+            CalendarEvent::new("Review".to_string(), at(18), at(19), None, None),
+        ];
+
+        // Titles are redacted unless the room publishes them
+        let entries = schedule_entries(&rooms[0], &events, at(10), at(17));
+        assert_eq!(
+            entries,
+            [
+                ScheduleEntry {
+                    start: at(9),
+                    end: at(11),
+                    title: None,
+                },
+                ScheduleEntry {
+                    start: at(12),
+                    end: at(13),
+                    title: None,
+                },
+            ]
+        );
+
+        rooms[0].public_titles = true;
+        let titles: Vec<Option<String>> = schedule_entries(&rooms[0], &events, at(10), at(17))
+            .into_iter()
+            .map(|entry| entry.title)
+            .collect();
+        assert_eq!(titles, [Some("Planning".to_string()), None]);
+    }
+}
@@ -0,0 +1,643 @@
+//! Scheduler of periodic jobs
+//!
+//! The periodic work of the server, e.g. the calendar refresh and the display
+//! watchdog, runs as named jobs of a scheduler. Each job has a built-in
+//! [`Schedule`], which `JOB_SCHEDULES` overrides. Jobs on an interval run
+//! right after startup and then after every interval, jobs on a cron
+//! expression at the matching minutes. Exclusive jobs run on one instance of a
+//! deployment at a time, the one holding the job's lease in the shared
+//! database.
+//!
+//! Every run is recorded in the database with its outcome. The admin API lists
+//! the jobs with their last run, runs a job on demand and pauses it on all
+//! instances. Deployments embedding the server add jobs of their own with
+//! [`super::start_server_with_jobs`].
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Local};
+use futures_util::future::BoxFuture;
+use log::{debug, info, warn};
+use serde::Deserialize;
+
+use super::AppState;
+use super::admin::extract_admin_user;
+use super::alerts::{ALERTS_INTERVAL, check_calendars, check_offline_devices};
+use super::errors::AppError;
+use super::extract::Admin;
+use super::label_reload::labels_job;
+use super::prerender::refresh_calendars;
+use super::watchdog::{WATCHDOG_INTERVAL, check_displays};
+use crate::api::types::{JobInfo, JobRunInfo};
+use crate::database::JobRun;
+use crate::error_report::{panic_message, report_task_failure, spawn_supervised};
+use crate::log_ingest::{RETENTION_INTERVAL, apply_retention};
+use crate::schedule::Schedule;
+use crate::utilization::record_utilization;
+
+/// Runs kept in the database for each job
+const JOB_RUN_HISTORY: usize = 100;
+
+/// Shortest time a lease of an exclusive job is held, in seconds
+const MIN_LEASE_SECONDS: i64 = 60;
+
+/// Longest time a lease of an exclusive job is held, in seconds, so that
+/// another instance takes over soon after the holder is gone, e.g. restarted
+/// under a new instance ID
+const MAX_LEASE_SECONDS: i64 = 60 * 60;
+
+/// Body of a job, returning a summary of what it did
+type JobFn = dyn Fn(AppState) -> BoxFuture<'static, Result<String>> + Send + Sync;
+
+/// Named periodic job
+#[derive(Clone)]
+pub struct Job {
+    name: String,
+    schedule: Schedule,
+    exclusive: bool,
+    run: Arc<JobFn>,
+}
+
+impl Job {
+    /// Job running `run` on the given schedule on every instance
+    ///
+    /// The job returns a summary of what it did, e.g. the number of deleted
+    /// entries, which is recorded with the run.
+    pub fn new<F, Fut>(name: impl Into<String>, schedule: Schedule, run: F) -> Self
+    where
+        F: Fn(AppState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String>> + Send + 'static,
+    {
+        Self {
+            name: name.into(),
+            schedule,
+            exclusive: false,
+            run: Arc::new(move |state| Box::pin(run(state))),
+        }
+    }
+
+    /// Run the job on one instance of a deployment at a time
+    pub fn exclusive(mut self) -> Self {
+        self.exclusive = true;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Built-in jobs of the server
+pub fn builtin_jobs(state: &AppState) -> Vec<Job> {
+    let config = &state.config;
+    let mut jobs = vec![
+        Job::new(
+            "calendar_refresh",
+            Schedule::every_minutes(config.calendar_refresh_minutes.max(1)),
+            |state| async move { refresh_calendars(&state).await },
+        ),
+        Job::new(
+            "watchdog",
+            Schedule::Every(WATCHDOG_INTERVAL),
+            |state| async move {
+                let rooms = state.config.rooms.snapshot();
+                let (frames, notifier) = (state.frames.clone(), state.notifier.clone());
+                let flagged = state
+                    .database
+                    .run_blocking(move |db| {
+                        check_displays(
+                            db,
+                            &rooms,
+                            &frames,
+                            notifier.as_ref(),
+                            chrono::Utc::now().timestamp(),
+                        )
+                    })
+                    .await?;
+                Ok(format!("{} devices flagged", flagged.len()))
+            },
+        )
+        .exclusive(),
+        // Shortly after midnight, also on days whose midnight is skipped by DST
+        Job::new(
+            "utilization",
+            "5 0 * * *".parse().expect("valid schedule"),
+            |state| async move {
+                if state
+                    .database
+                    .run_blocking(|db| db.maintenance())
+                    .await?
+                    .is_some()
+                {
+                    return Ok("skipped during maintenance".to_string());
+                }
+                let rooms = state.config.rooms.snapshot();
+                let recorded = record_utilization(&state.database, &rooms, 1, Local::now()).await?;
+                Ok(format!(
+                    "recorded yesterday's utilization of {} rooms",
+                    recorded
+                ))
+            },
+        )
+        .exclusive(),
+    ];
+    if config.notify_missed_check_ins > 0 || config.notify_calendar_failures > 0 {
+        jobs.push(
+            Job::new(
+                "alerts",
+                Schedule::Every(ALERTS_INTERVAL),
+                |state| async move {
+                    let rooms = state.config.rooms.snapshot();
+                    let offline = if state.config.notify_missed_check_ins > 0 {
+                        let now = chrono::Utc::now().timestamp();
+                        let uptime =
+                            i64::try_from(state.started_at.elapsed().as_secs()).unwrap_or(i64::MAX);
+                        let (rooms, notifier) = (rooms.clone(), state.notifier.clone());
+                        let threshold = state.config.notify_missed_check_ins;
+                        state
+                            .database
+                            .run_blocking(move |db| {
+                                check_offline_devices(
+                                    db,
+                                    &rooms,
+                                    notifier.as_ref(),
+                                    threshold,
+                                    now.saturating_sub(uptime),
+                                    now,
+                                )
+                            })
+                            .await?
+                    } else {
+                        Vec::new()
+                    };
+                    let calendars = if state.config.notify_calendar_failures > 0 {
+                        check_calendars(
+                            &state.calendars,
+                            &rooms,
+                            state.notifier.as_ref(),
+                            state.config.notify_calendar_failures,
+                        )
+                    } else {
+                        Vec::new()
+                    };
+                    Ok(format!(
+                        "{} devices offline, {} calendars failing",
+                        offline.len(),
+                        calendars.len()
+                    ))
+                },
+            )
+            .exclusive(),
+        );
+    }
+    if let Some(dir) = &config.labels_dir {
+        jobs.push(labels_job(dir.clone()));
+    }
+    if config.log_retention_days > 0 || config.log_max_entries > 0 {
+        jobs.push(
+            Job::new(
+                "log_retention",
+                Schedule::Every(RETENTION_INTERVAL),
+                |state| async move {
+                    let (days, max_entries) = (
+                        state.config.log_retention_days,
+                        state.config.log_max_entries,
+                    );
+                    let deleted = state
+                        .database
+                        .run_blocking(move |db| {
+                            apply_retention(db, days, max_entries, chrono::Utc::now().timestamp())
+                        })
+                        .await?;
+                    Ok(format!("deleted {} device log entries", deleted))
+                },
+            )
+            .exclusive(),
+        );
+    }
+    jobs
+}
+
+/// Scheduler of the periodic jobs of this instance
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Mutex<Vec<Job>>,
+    /// Next scheduled run of each job
+    next_runs: Mutex<HashMap<String, DateTime<Local>>>,
+    /// Jobs running on this instance
+    running: Mutex<HashSet<String>>,
+}
+
+/// Marks a job as running until dropped
+struct RunGuard {
+    scheduler: Arc<Scheduler>,
+    job: String,
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        if let Ok(mut running) = self.scheduler.running.lock() {
+            running.remove(&self.job);
+        }
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a job, replacing a job of the same name
+    pub fn add(&self, job: Job) {
+        if let Ok(mut jobs) = self.jobs.lock() {
+            jobs.retain(|existing| existing.name != job.name);
+            jobs.push(job);
+        }
+    }
+
+    /// Change the schedule of a job, returns false for unknown jobs
+    pub fn set_schedule(&self, name: &str, schedule: Schedule) -> bool {
+        let Ok(mut jobs) = self.jobs.lock() else {
+            return false;
+        };
+        match jobs.iter_mut().find(|job| job.name == name) {
+            Some(job) => {
+                job.schedule = schedule;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The job of the given name
+    pub fn job(&self, name: &str) -> Option<Job> {
+        self.jobs
+            .lock()
+            .ok()?
+            .iter()
+            .find(|job| job.name == name)
+            .cloned()
+    }
+
+    /// All jobs, by name
+    pub fn jobs(&self) -> Vec<Job> {
+        let mut jobs = self
+            .jobs
+            .lock()
+            .map(|jobs| jobs.clone())
+            .unwrap_or_default();
+        jobs.sort_by(|a, b| a.name.cmp(&b.name));
+        jobs
+    }
+
+    /// Next scheduled run of a job on this instance
+    pub fn next_run(&self, name: &str) -> Option<DateTime<Local>> {
+        self.next_runs.lock().ok()?.get(name).copied()
+    }
+
+    /// Whether a job is running on this instance
+    pub fn is_running(&self, name: &str) -> bool {
+        self.running
+            .lock()
+            .is_ok_and(|running| running.contains(name))
+    }
+
+    /// Mark a job as running, unless it is running already
+    fn try_start(self: &Arc<Self>, name: &str) -> Option<RunGuard> {
+        let mut running = self.running.lock().ok()?;
+        running.insert(name.to_string()).then(|| RunGuard {
+            scheduler: self.clone(),
+            job: name.to_string(),
+        })
+    }
+
+    /// Start running the jobs on their schedules
+    pub fn start(self: &Arc<Self>, state: AppState) {
+        for job in self.jobs() {
+            info!("Scheduling job {} ({})", job.name, job.schedule);
+            spawn_supervised(
+                job.name.clone(),
+                state.errors.clone(),
+                self.clone().run_scheduled(state.clone(), job),
+            );
+        }
+    }
+
+    /// Run a job whenever it is due
+    async fn run_scheduled(self: Arc<Self>, state: AppState, job: Job) {
+        let mut next = match job.schedule {
+            Schedule::Every(_) => Some(Local::now()),
+            Schedule::Cron(_) => job.schedule.next_after(Local::now()),
+        };
+        while let Some(due) = next {
+            if let Ok(mut next_runs) = self.next_runs.lock() {
+                next_runs.insert(job.name.clone(), due);
+            }
+            let wait = (due - Local::now()).to_std().unwrap_or_default();
+            tokio::time::sleep(wait).await;
+            next = job.schedule.next_after(Local::now());
+
+            let name = job.name.clone();
+            match state
+                .database
+                .run_blocking(move |db| db.job_pause(&name))
+                .await
+            {
+                Ok(None) => {}
+                Ok(Some(_)) => {
+                    debug!("Job {} is paused", job.name);
+                    continue;
+                }
+                Err(e) => {
+                    report_task_failure(state.errors.as_ref(), &job.name, format!("{:#}", e));
+                    continue;
+                }
+            }
+            if job.exclusive {
+                // Held until after the next run, so that it does not lapse in between
+                let lease_seconds = next
+                    .map_or(0, |next| 2 * (next - Local::now()).num_seconds())
+                    .clamp(MIN_LEASE_SECONDS, MAX_LEASE_SECONDS);
+                let (name, holder) = (job.name.clone(), state.config.instance_id.clone());
+                match state
+                    .database
+                    .run_blocking(move |db| db.try_acquire_lease(&name, &holder, lease_seconds))
+                    .await
+                {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        debug!("Lease of job {} held by another instance", job.name);
+                        continue;
+                    }
+                    Err(e) => {
+                        report_task_failure(
+                            state.errors.as_ref(),
+                            &job.name,
+                            format!("Failed to acquire lease of job {}: {:#}", job.name, e),
+                        );
+                        continue;
+                    }
+                }
+            }
+            match self.try_start(&job.name) {
+                Some(guard) => {
+                    run_job(&state, &job, false).await;
+                    drop(guard);
+                }
+                None => debug!("Job {} is still running, skipping its run", job.name),
+            }
+            if job.exclusive {
+                // Renewed at the next run, unless this instance is gone by then
+                let lapses_at = next.map_or(0, |next| next.timestamp());
+                let (name, holder) = (job.name.clone(), state.config.instance_id.clone());
+                if let Err(e) = state
+                    .database
+                    .run_blocking(move |db| db.release_lease(&name, &holder, lapses_at))
+                    .await
+                {
+                    warn!("Failed to release lease of job {}: {:#}", job.name, e);
+                }
+            }
+        }
+        warn!("Job {} is never due again", job.name);
+    }
+
+    /// Run a job now on this instance, in the background
+    ///
+    /// Paused jobs run as well. Returns false if the job is running already.
+    pub fn trigger(self: &Arc<Self>, state: AppState, job: Job) -> bool {
+        let Some(guard) = self.try_start(&job.name) else {
+            return false;
+        };
+        tokio::spawn(async move {
+            run_job(&state, &job, true).await;
+            drop(guard);
+        });
+        true
+    }
+}
+
+/// Run a job and record the run
+///
+/// Panics of the job are caught and recorded as failures.
+async fn run_job(state: &AppState, job: &Job, manual: bool) -> JobRun {
+    let started_at = chrono::Utc::now().timestamp();
+    let result = tokio::spawn((job.run)(state.clone())).await;
+    let (succeeded, message) = match result {
+        Ok(Ok(summary)) => {
+            debug!("Job {} finished: {}", job.name, summary);
+            (true, Some(summary).filter(|summary| !summary.is_empty()))
+        }
+        Ok(Err(e)) => {
+            let message = format!("Job {} failed: {:#}", job.name, e);
+            report_task_failure(state.errors.as_ref(), &job.name, message);
+            (false, Some(format!("{:#}", e)))
+        }
+        Err(e) => {
+            let cause = if e.is_panic() {
+                format!("panicked: {}", panic_message(e.into_panic().as_ref()))
+            } else {
+                "cancelled".to_string()
+            };
+            report_task_failure(
+                state.errors.as_ref(),
+                &job.name,
+                format!("Job {} {}", job.name, cause),
+            );
+            (false, Some(cause))
+        }
+    };
+    let run = JobRun {
+        job: job.name.clone(),
+        instance_id: state.config.instance_id.clone(),
+        manual,
+        started_at,
+        finished_at: chrono::Utc::now().timestamp(),
+        succeeded,
+        message,
+    };
+    let recorded = run.clone();
+    if let Err(e) = state
+        .database
+        .run_blocking(move |db| db.record_job_run(&recorded, JOB_RUN_HISTORY))
+        .await
+    {
+        warn!("Failed to record run of job {}: {:#}", job.name, e);
+    }
+    run
+}
+
+/// Information about a job for the admin API
+fn job_info(state: &AppState, job: &Job) -> Result<JobInfo> {
+    let pause = state.database.job_pause(&job.name)?;
+    let last_run = state
+        .database
+        .list_job_runs(&job.name, 1)
+        .with_context(|| format!("Failed to get last run of job {}", job.name))?
+        .into_iter()
+        .next();
+    Ok(JobInfo {
+        name: job.name.clone(),
+        schedule: job.schedule.to_string(),
+        exclusive: job.exclusive,
+        paused: pause.is_some(),
+        paused_by: pause.as_ref().map(|(by, _)| by.clone()),
+        running: state.scheduler.is_running(&job.name),
+        next_run: state.scheduler.next_run(&job.name).map(|at| at.timestamp()),
+        last_run: last_run.map(JobRunInfo::from),
+    })
+}
+
+/// Job list endpoint handler
+pub async fn list_jobs_handler(
+    _: Admin,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    let jobs = state.scheduler.jobs();
+    let jobs = state
+        .database
+        .run_blocking({
+            let state = state.clone();
+            move |_| {
+                jobs.iter()
+                    .map(|job| job_info(&state, job))
+                    .collect::<Result<Vec<_>>>()
+            }
+        })
+        .await
+        .map_err(AppError::from)?;
+    Ok(Json(jobs))
+}
+
+/// Query parameters of the job run list endpoint
+#[derive(Debug, Deserialize)]
+pub struct JobRunParams {
+    /// Maximum number of runs, most recent first
+    pub limit: Option<usize>,
+}
+
+/// Job run list endpoint handler
+pub async fn list_job_runs_handler(
+    _: Admin,
+    Path(name): Path<String>,
+    Query(params): Query<JobRunParams>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    if state.scheduler.job(&name).is_none() {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    let limit = params.limit.unwrap_or(20).min(JOB_RUN_HISTORY);
+    let runs = state
+        .database
+        .run_blocking(move |db| {
+            db.list_job_runs(&name, limit)
+                .with_context(|| format!("Failed to list runs of job {}", name))
+        })
+        .await
+        .map_err(AppError::from)?;
+    let runs: Vec<JobRunInfo> = runs.into_iter().map(JobRunInfo::from).collect();
+    Ok(Json(runs).into_response())
+}
+
+/// Job trigger endpoint handler
+///
+/// Runs the job now on the instance handling the request, even if it is
+/// paused, and returns before it finishes.
+pub async fn run_job_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let admin_user = extract_admin_user(&headers)?;
+
+    let Some(job) = state.scheduler.job(&name) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    if !state.scheduler.trigger(state.clone(), job) {
+        return Ok(StatusCode::CONFLICT.into_response());
+    }
+    info!("Job {} run by {}", name, admin_user);
+    Ok(StatusCode::ACCEPTED.into_response())
+}
+
+/// Job pause endpoint handler
+///
+/// The job is skipped on all instances until it is resumed.
+pub async fn pause_job_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let admin_user = extract_admin_user(&headers)?;
+
+    let Some(job) = state.scheduler.job(&name) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let info = state
+        .database
+        .run_blocking({
+            let state = state.clone();
+            move |db| {
+                db.pause_job(&name, &admin_user)?;
+                info!("Job {} paused by {}", name, admin_user);
+                job_info(&state, &job)
+            }
+        })
+        .await
+        .map_err(AppError::from)?;
+    Ok(Json(info).into_response())
+}
+
+/// Job resume endpoint handler
+pub async fn resume_job_handler(
+    _: Admin,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Response, AppError> {
+    let admin_user = extract_admin_user(&headers)?;
+
+    let Some(job) = state.scheduler.job(&name) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let info = state
+        .database
+        .run_blocking({
+            let state = state.clone();
+            move |db| {
+                if db.resume_job(&name, &admin_user)? {
+                    info!("Job {} resumed by {}", name, admin_user);
+                }
+                job_info(&state, &job)
+            }
+        })
+        .await
+        .map_err(AppError::from)?;
+    Ok(Json(info).into_response())
+}
+
+impl From<JobRun> for JobRunInfo {
+    fn from(run: JobRun) -> Self {
+        Self {
+            instance_id: run.instance_id,
+            manual: run.manual,
+            started_at: run.started_at,
+            finished_at: run.finished_at,
+            succeeded: run.succeeded,
+            message: run.message,
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Public status page
+//!
+//! Shows the health of the system for the office IT wiki to link to. Only
+//! aggregate numbers are shown, no device identifiers, room names or meeting
+//! details, so the page requires no authentication. As anyone can request it,
+//! the numbers are computed at most every few seconds.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{Html, IntoResponse, Response},
+};
+
+use super::AppState;
+use super::admin::fleet_summary;
+use super::errors::AppError;
+use super::report::page;
+use crate::api::types::FleetSummary;
+
+/// How long the numbers of the status page are reused
+const SUMMARY_TTL: Duration = Duration::from_secs(5);
+
+/// Fleet summary last shown on the status page
+#[derive(Default)]
+pub struct StatusCache {
+    summary: Mutex<Option<(Instant, FleetSummary)>>,
+}
+
+impl StatusCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The cached summary if it is younger than `SUMMARY_TTL`, or a new one
+    fn get_or_compute(
+        &self,
+        compute: impl FnOnce() -> Result<FleetSummary, AppError>,
+    ) -> Result<FleetSummary, AppError> {
+        let mut summary = match self.summary.lock() {
+            Ok(summary) => summary,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some((computed_at, cached)) = summary.as_ref()
+            && computed_at.elapsed() < SUMMARY_TTL
+        {
+            return Ok(cached.clone());
+        }
+        let fresh = compute()?;
+        *summary = Some((Instant::now(), fresh.clone()));
+        Ok(fresh)
+    }
+}
+
+/// Format a duration as days, hours and minutes, e.g. `3 days, 4 hours`
+///
+/// Only the two largest units are shown.
+fn format_uptime(uptime: Duration) -> String {
+    let minutes = uptime.as_secs() / 60;
+    let units = [
+        (minutes / (24 * 60), "day"),
+        (minutes / 60 % 24, "hour"),
+        (minutes % 60, "minute"),
+    ];
+    let parts: Vec<String> = units
+        .iter()
+        .skip_while(|(value, _)| *value == 0)
+        .take(2)
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| {
+            let plural = if *value == 1 { "" } else { "s" };
+            format!("{} {}{}", value, unit, plural)
+        })
+        .collect();
+    if parts.is_empty() {
+        "less than a minute".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
+/// Overall status line of the page
+fn overall_status(summary: &FleetSummary) -> String {
+    match summary.calendars.failing {
+        0 => "All systems operational".to_string(),
+        1 => "Degraded: 1 calendar feed is failing".to_string(),
+        failing => format!("Degraded: {} calendar feeds are failing", failing),
+    }
+}
+
+/// Body of the status page
+fn status_body(summary: &FleetSummary, uptime: Duration) -> String {
+    let devices = &summary.devices;
+    let calendars = &summary.calendars;
+    let rooms = &summary.rooms;
+    format!(
+        "<h1>Meeting room displays</h1>\n\
+         <p><strong>{status}</strong></p>\n\
+         <h2>Server</h2>\n\
+         <p>Up for {uptime} (version {version})</p>\n\
+         <h2>Displays</h2>\n\
+         <p>{online} of {total} online, {offline} offline, {pending} not set up yet</p>\n\
+         <h2>Calendar feeds</h2>\n\
+         <p>{ok} of {feeds} working, {failing} failing, {unknown} not fetched yet</p>\n\
+         <h2>Rooms</h2>\n\
+         <p>{free} free, {busy} busy, {rooms_unknown} unknown</p>",
+        status = overall_status(summary),
+        uptime = format_uptime(uptime),
+        version = env!("CARGO_PKG_VERSION"),
+        online = devices.online,
+        total = devices.total,
+        offline = devices.offline,
+        pending = devices.pending,
+        ok = calendars.ok,
+        feeds = calendars.total,
+        failing = calendars.failing,
+        unknown = calendars.unknown,
+        free = rooms.free,
+        busy = rooms.busy,
+        rooms_unknown = rooms.unknown + rooms.error,
+    )
+}
+
+/// Status page handler
+///
+/// Not authenticated, so that it can be linked from the intranet.
+pub async fn status_page_handler(State(state): State<AppState>) -> Result<Response, AppError> {
+    let (status, calendars, config) = (
+        state.status.clone(),
+        state.calendars.clone(),
+        state.config.clone(),
+    );
+    let summary = state
+        .database
+        .run_blocking(move |db| status.get_or_compute(|| fleet_summary(db, &calendars, &config)))
+        .await?;
+    let body = status_body(&summary, state.started_at.elapsed());
+
+    Ok((
+        [(header::CACHE_CONTROL, "public, max-age=60")],
+        Html(page("System status", &body)),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_uptime() {
+        assert_eq!(format_uptime(Duration::from_secs(30)), "less than a minute");
+        assert_eq!(format_uptime(Duration::from_secs(60)), "1 minute");
+        assert_eq!(
+            format_uptime(Duration::from_secs(2 * 3600 + 5 * 60)),
+            "2 hours, 5 minutes"
+        );
+        assert_eq!(
+            format_uptime(Duration::from_secs(3 * 86400 + 3600 + 60)),
+            "3 days, 1 hour"
+        );
+        assert_eq!(format_uptime(Duration::from_secs(86400 + 60)), "1 day");
+    }
+
+    #[test]
+    fn test_status_cache() {
+        let summary: FleetSummary = serde_json::from_value(serde_json::json!({
+            "devices": {"total": 1, "online": 1, "offline": 0, "pending": 0, "low_battery": 0},
+            "rooms": {"total": 0, "free": 0, "busy": 0, "unknown": 0, "error": 0, "degraded": 0},
+            "calendars": {"total": 0, "ok": 0, "failing": 0, "unknown": 0},
+        }))
+        .unwrap();
+        let cache = StatusCache::new();
+        let mut computed = 0;
+        for _ in 0..3 {
+            let cached = cache
+                .get_or_compute(|| {
+                    computed += 1;
+                    Ok(summary.clone())
+                })
+                .unwrap();
+            assert_eq!(cached, summary);
+        }
+        assert_eq!(computed, 1);
+    }
+}
@@ -0,0 +1,186 @@
+//! HTTPS served by the server itself
+//!
+//! With `TLS_CERT_PATH` and `TLS_KEY_PATH` set, the server terminates TLS
+//! itself (requires the `tls` feature), so that it can be exposed to the
+//! devices on a LAN without a reverse proxy. The certificate and key are
+//! PEM files, e.g. those of Let's Encrypt; they are read again every
+//! [`RELOAD_INTERVAL`], so that a renewed certificate is picked up without a
+//! restart.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::Router;
+use tokio::net::TcpListener;
+
+/// How often the certificate and key are read again
+pub const RELOAD_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Certificate and key of the HTTPS server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain, server certificate first
+    pub cert_path: String,
+    /// PEM file with the private key
+    pub key_path: String,
+}
+
+/// Serve the app over HTTPS on the listener
+#[cfg(feature = "tls")]
+pub async fn serve_tls(listener: TcpListener, app: Router, tls: &TlsConfig) -> Result<()> {
+    use std::net::SocketAddr;
+
+    use anyhow::Context;
+    use axum_server::tls_rustls::RustlsConfig;
+    use log::{info, warn};
+
+    let rustls = RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to load TLS certificate {} and key {}",
+                tls.cert_path, tls.key_path
+            )
+        })?;
+    tokio::spawn({
+        let (rustls, tls) = (rustls.clone(), tls.clone());
+        async move {
+            let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                match rustls
+                    .reload_from_pem_file(&tls.cert_path, &tls.key_path)
+                    .await
+                {
+                    Ok(()) => info!("Reloaded TLS certificate {}", tls.cert_path),
+                    Err(e) => warn!(
+                        "Failed to reload TLS certificate {}, keeping the previous one: {}",
+                        tls.cert_path, e
+                    ),
+                }
+            }
+        }
+    });
+
+    let listener = listener
+        .into_std()
+        .context("Failed to take over listener for TLS")?;
+    axum_server::from_tcp_rustls(listener, rustls)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .context("Server error")
+}
+
+/// Serve the app over HTTPS on the listener
+#[cfg(not(feature = "tls"))]
+pub async fn serve_tls(_listener: TcpListener, _app: Router, _tls: &TlsConfig) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "TLS requested, but the server was built without the tls feature"
+    ))
+}
+
+/// Self-signed certificate and private key for `localhost`, as PEM, for tests
+/// of TLS connections
+#[cfg(all(test, feature = "tls"))]
+pub(crate) fn self_signed_certificate() -> (Vec<u8>, Vec<u8>) {
+    use openssl::{
+        asn1::Asn1Time,
+        hash::MessageDigest,
+        pkey::PKey,
+        rsa::Rsa,
+        x509::{X509, X509NameBuilder, extension::SubjectAlternativeName},
+    };
+
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "localhost").unwrap();
+    let name = name.build();
+    let mut cert = X509::builder().unwrap();
+    cert.set_version(2).unwrap();
+    cert.set_subject_name(&name).unwrap();
+    cert.set_issuer_name(&name).unwrap();
+    cert.set_pubkey(&key).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    let san = SubjectAlternativeName::new()
+        .dns("localhost")
+        .build(&cert.x509v3_context(None, None))
+        .unwrap();
+    cert.append_extension(san).unwrap();
+    cert.sign(&key, MessageDigest::sha256()).unwrap();
+    (
+        cert.build().to_pem().unwrap(),
+        key.private_key_to_pem_pkcs8().unwrap(),
+    )
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_missing_certificate() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tls = TlsConfig {
+            cert_path: "missing-cert.pem".to_string(),
+            key_path: "missing-key.pem".to_string(),
+        };
+        let error = serve_tls(listener, Router::new(), &tls).await.unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("Failed to load TLS certificate missing-cert.pem")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serve_tls() {
+        let (cert, key) = self_signed_certificate();
+        let dir = std::env::temp_dir();
+        let tls = TlsConfig {
+            cert_path: dir
+                .join(format!("trmnl-tls-{}-cert.pem", std::process::id()))
+                .to_string_lossy()
+                .into_owned(),
+            key_path: dir
+                .join(format!("trmnl-tls-{}-key.pem", std::process::id()))
+                .to_string_lossy()
+                .into_owned(),
+        };
+        std::fs::write(&tls.cert_path, &cert).unwrap();
+        std::fs::write(&tls.key_path, &key).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().route("/health", axum::routing::get(|| async { "OK" }));
+        let server = tokio::spawn({
+            let tls = tls.clone();
+            async move { serve_tls(listener, app, &tls).await }
+        });
+
+        let client = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(&cert).unwrap())
+            .build()
+            .unwrap();
+        let url = format!("https://localhost:{}/health", port);
+        let mut response = client.get(&url).send().await;
+        // The certificate is loaded before the server accepts connections
+        for _ in 0..50 {
+            if response.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            response = client.get(&url).send().await;
+        }
+        let response = response.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "OK");
+
+        server.abort();
+        std::fs::remove_file(&tls.cert_path).unwrap();
+        std::fs::remove_file(&tls.key_path).unwrap();
+    }
+}
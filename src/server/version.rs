@@ -0,0 +1,104 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, OriginalUri},
+    http::request::Parts,
+};
+
+use super::errors::AppError;
+
+/// Version of the device API response shapes
+///
+/// Requests to `/api/v1/...` always use [`ApiVersion::V1`]. Requests to the
+/// unversioned `/api/...` routes use the `Api-Version` header if present, and
+/// the original ad-hoc response shapes otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiVersion {
+    /// Original response shapes of this server
+    Legacy,
+    /// Strict TRMNL BYOS-compatible response shapes
+    V1,
+}
+
+impl ApiVersion {
+    /// Parse the value of an `Api-Version` header
+    pub fn from_header(value: &str) -> Option<Self> {
+        match value.trim() {
+            "0" | "legacy" => Some(ApiVersion::Legacy),
+            "1" | "v1" => Some(ApiVersion::V1),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ApiVersion {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // Nested routers strip the prefix from the URI, so look at the original one
+        let path = parts
+            .extensions
+            .get::<OriginalUri>()
+            .map(|uri| uri.path().to_string())
+            .unwrap_or_else(|| parts.uri.path().to_string());
+        if path.starts_with("/api/v1/") {
+            return Ok(ApiVersion::V1);
+        }
+
+        match parts.headers.get("Api-Version") {
+            Some(value) => {
+                let value = value.to_str().map_err(|e| {
+                    AppError::BadRequest(format!("Invalid Api-Version header: {}", e))
+                })?;
+                ApiVersion::from_header(value).ok_or_else(|| {
+                    AppError::BadRequest(format!("Unsupported API version: {}", value))
+                })
+            }
+            None => Ok(ApiVersion::Legacy),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+
+    #[test]
+    fn test_api_version_from_header() {
+        assert_eq!(ApiVersion::from_header("1"), Some(ApiVersion::V1));
+        assert_eq!(ApiVersion::from_header(" v1 "), Some(ApiVersion::V1));
+        assert_eq!(ApiVersion::from_header("0"), Some(ApiVersion::Legacy));
+        assert_eq!(ApiVersion::from_header("2"), None);
+    }
+
+    #[tokio::test]
+    async fn test_api_version_extractor() {
+        let extract = |uri: &str, header: Option<&str>| {
+            let mut request = Request::builder().uri(uri);
+            if let Some(header) = header {
+                request = request.header("Api-Version", header);
+            }
+            let mut parts = request.body(()).unwrap().into_parts().0;
+            async move { ApiVersion::from_request_parts(&mut parts, &()).await }
+        };
+        assert_eq!(
+            extract("/api/display", None).await.unwrap(),
+            ApiVersion::Legacy
+        );
+        assert_eq!(
+            extract("/api/display", Some("1")).await.unwrap(),
+            ApiVersion::V1
+        );
+        // The versioned prefix wins over the header
+        assert_eq!(
+            extract("/api/v1/display", Some("0")).await.unwrap(),
+            ApiVersion::V1
+        );
+        assert!(matches!(
+            extract("/api/display", Some("v99")).await,
+            Err(AppError::BadRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,200 @@
+//! Watchdog for devices that fetch images but fail to draw them
+//!
+//! Some firmware versions download the image, but cannot decode or draw it,
+//! e.g. a PNG on firmware without PNG support, and only say so in their logs
+//! while the display keeps showing an outdated image. The watchdog correlates
+//! the display requests of each device with the render errors it logs
+//! afterwards. Once a device failed to draw [`FAILED_DISPLAYS_THRESHOLD`]
+//! images in a row, it is flagged in the admin API and dashboard with a
+//! suggested remediation, the operators are notified, and the frame of its
+//! room is rendered anew. The flag is cleared once the device draws an image
+//! again.
+
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+
+use super::prerender::FrameCache;
+use crate::database::Database;
+use crate::health::{
+    FAILED_DISPLAYS_THRESHOLD, HEALTH_WINDOW_SECS, LogIssue, Remediation, failed_displays,
+    log_messages,
+};
+use crate::notify::{Notification, Notifier};
+use crate::rooms::{Room, resolve_device_room};
+
+/// How often the display requests are checked, by default
+pub const WATCHDOG_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Flag the devices that failed to draw several images in a row, and clear
+/// the flag of the devices that drew an image again
+///
+/// Returns the IDs of the newly flagged devices.
+pub fn check_displays(
+    database: &Database,
+    rooms: &[Room],
+    frames: &FrameCache,
+    notifier: &dyn Notifier,
+    now: i64,
+) -> Result<Vec<String>> {
+    let since = now - HEALTH_WINDOW_SECS;
+    let mut fetches: HashMap<String, Vec<i64>> = HashMap::new();
+    for (device_id, fetched_at) in database
+        .display_fetches_since(since)
+        .context("Failed to list display fetches")?
+    {
+        fetches
+            .entry(device_id.to_ascii_uppercase())
+            .or_default()
+            .push(fetched_at);
+    }
+    let mut render_errors: HashMap<String, Vec<i64>> = HashMap::new();
+    for entry in database
+        .device_logs_since(since)
+        .context("Failed to get device logs")?
+    {
+        let has_render_error = log_messages(&entry.message)
+            .iter()
+            .any(|message| LogIssue::classify(message) == Some(LogIssue::RenderError));
+        if has_render_error {
+            render_errors
+                .entry(entry.device_id.to_ascii_uppercase())
+                .or_default()
+                .push(entry.received_at);
+        }
+    }
+
+    let mut flagged = Vec::new();
+    for device in database.list_devices().context("Failed to list devices")? {
+        let id = device.id.to_ascii_uppercase();
+        let failed = failed_displays(
+            fetches.get(&id).map_or(&[], Vec::as_slice),
+            render_errors.get(&id).map_or(&[], Vec::as_slice),
+        );
+        let failing = failed >= FAILED_DISPLAYS_THRESHOLD && device.retired_at.is_none();
+        match (failing, device.display_alert_at) {
+            (true, None) => {
+                let remediation = Remediation::for_format(device.last_payload_format.as_deref());
+                warn!(
+                    "Device {} failed to draw the last {} images, suggesting: {}",
+                    device.id,
+                    failed,
+                    remediation.description()
+                );
+                database.set_device_display_alert(&device.id, Some(now))?;
+                let room = resolve_device_room(rooms, &device.id, device.room_id.as_deref());
+                if let Some(room) = room {
+                    frames.remove_room(&room.id);
+                }
+                notifier.notify(Notification {
+                    kind: "device.display_failing".to_string(),
+                    title: format!(
+                        "Display {} does not show its images",
+                        device.name.as_deref().unwrap_or(&device.id)
+                    ),
+                    message: format!(
+                        "The device failed to draw the last {} images it fetched. {}.",
+                        failed,
+                        remediation.description()
+                    ),
+                    room_id: room.map(|room| room.id.clone()),
+                    device_id: Some(device.id.clone()),
+                    details: None,
+                });
+                flagged.push(device.id);
+            }
+            (false, Some(_)) => {
+                info!("Device {} draws its images again", device.id);
+                database.set_device_display_alert(&device.id, None)?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(flagged)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::database::DeviceLogEntry;
+    use crate::rooms::parse_rooms;
+
+    /// Notifier keeping the titles of the notifications
+    #[derive(Default)]
+    struct RecordingNotifier(Mutex<Vec<String>>);
+
+    impl Notifier for RecordingNotifier {
+        fn notify(&self, notification: Notification) {
+            self.0.lock().unwrap().push(notification.title);
+        }
+    }
+
+    #[test]
+    fn test_check_displays() {
+        let db = Database::new(":memory:").unwrap();
+        db.register_device("AA:BB:CC:DD:EE:FF").unwrap();
+        db.record_device_payload("AA:BB:CC:DD:EE:FF", None, "png", 1000)
+            .unwrap();
+        let rooms = parse_rooms(
+            r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+            devices = ["AA:BB:CC:DD:EE:FF"]
+            "#,
+        )
+        .unwrap();
+        let frames = FrameCache::new();
+        frames.insert("room-a", 1, Arc::new(vec![1]));
+        let notifier = RecordingNotifier::default();
+
+        let now = 1_700_000_000;
+        let render_error = |received_at| DeviceLogEntry {
+            device_id: "aa:bb:cc:dd:ee:ff".to_string(),
+            message: r#"{"log":{"logs_array":[{"log_message":"PNG decode error"}]}}"#.to_string(),
+            received_at,
+            authenticated: true,
+            ..Default::default()
+        };
+        for (i, fetched_at) in [now - 900, now - 600, now - 300].into_iter().enumerate() {
+            db.record_display_fetch("AA:BB:CC:DD:EE:FF", fetched_at)
+                .unwrap();
+            if i > 0 {
+                db.insert_device_logs(&[render_error(fetched_at + 10)])
+                    .unwrap();
+            }
+        }
+
+        // Two failed images are not enough yet
+        let check = || check_displays(&db, &rooms, &frames, &notifier, now).unwrap();
+        assert!(check().is_empty());
+
+        db.record_display_fetch("AA:BB:CC:DD:EE:FF", now).unwrap();
+        db.insert_device_logs(&[render_error(now + 10)]).unwrap();
+        assert_eq!(check(), ["AA:BB:CC:DD:EE:FF"]);
+        let device = db.get_device("AA:BB:CC:DD:EE:FF").unwrap().unwrap();
+        assert_eq!(device.display_alert_at, Some(now));
+        assert_eq!(frames.fingerprint("room-a"), None);
+        assert_eq!(
+            *notifier.0.lock().unwrap(),
+            ["Display AA:BB:CC:DD:EE:FF does not show its images"]
+        );
+
+        // Flagged devices are notified once
+        assert!(check().is_empty());
+        assert_eq!(notifier.0.lock().unwrap().len(), 1);
+
+        // An image drawn without error clears the flag
+        db.record_display_fetch("AA:BB:CC:DD:EE:FF", now + 300)
+            .unwrap();
+        db.record_display_fetch("AA:BB:CC:DD:EE:FF", now + 600)
+            .unwrap();
+        check_displays(&db, &rooms, &frames, &notifier, now + 600).unwrap();
+        let device = db.get_device("AA:BB:CC:DD:EE:FF").unwrap().unwrap();
+        assert_eq!(device.display_alert_at, None);
+    }
+}
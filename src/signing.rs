@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+use base64::{Engine as _, engine::general_purpose};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier};
+
+/// Signs served image payloads with an Ed25519 key
+///
+/// Firmware running in security mode verifies the signature against the
+/// public key, so that tampering by an on-path proxy is detectable.
+#[derive(Debug, Clone)]
+pub struct ImageSigner {
+    key: SigningKey,
+}
+
+impl ImageSigner {
+    /// Create a signer from a base64-encoded 32-byte Ed25519 secret key
+    pub fn from_base64(secret_key: &str) -> Result<Self> {
+        let bytes = general_purpose::STANDARD
+            .decode(secret_key.trim())
+            .context("Image signing key is not valid base64")?;
+        let bytes: [u8; 32] = bytes.try_into().map_err(|b: Vec<u8>| {
+            anyhow::anyhow!("Image signing key must be 32 bytes, got {}", b.len())
+        })?;
+        Ok(Self {
+            key: SigningKey::from_bytes(&bytes),
+        })
+    }
+
+    /// Sign the given image data, returning the base64-encoded signature
+    pub fn sign(&self, data: &[u8]) -> String {
+        general_purpose::STANDARD.encode(self.key.sign(data).to_bytes())
+    }
+
+    /// Sign a hosted image name with an expiry timestamp, for use in URLs
+    ///
+    /// All instances sharing the signing key accept the signature, so signed
+    /// URLs work regardless of which instance serves them.
+    pub fn sign_image_url(&self, name: &str, expires: i64) -> String {
+        let message = format!("{}:{}", name, expires);
+        general_purpose::URL_SAFE_NO_PAD.encode(self.key.sign(message.as_bytes()).to_bytes())
+    }
+
+    /// Verify a signature created by [`ImageSigner::sign_image_url`]
+    pub fn verify_image_url(&self, name: &str, expires: i64, signature: &str) -> bool {
+        let Some(signature) = general_purpose::URL_SAFE_NO_PAD
+            .decode(signature)
+            .ok()
+            .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+        else {
+            return false;
+        };
+        let message = format!("{}:{}", name, expires);
+        self.key
+            .verifying_key()
+            .verify(message.as_bytes(), &Signature::from_bytes(&signature))
+            .is_ok()
+    }
+
+    /// The base64-encoded public key for verifying signatures
+    pub fn public_key(&self) -> String {
+        general_purpose::STANDARD.encode(self.key.verifying_key().to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::VerifyingKey;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signer =
+            ImageSigner::from_base64(&general_purpose::STANDARD.encode([7u8; 32])).unwrap();
+        let signature = signer.sign(b"image data");
+
+        let public_key: [u8; 32] = general_purpose::STANDARD
+            .decode(signer.public_key())
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let signature: [u8; 64] = general_purpose::STANDARD
+            .decode(signature)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let verifying_key = VerifyingKey::from_bytes(&public_key).unwrap();
+        assert!(
+            verifying_key
+                .verify(b"image data", &Signature::from_bytes(&signature))
+                .is_ok()
+        );
+        assert!(
+            verifying_key
+                .verify(b"tampered", &Signature::from_bytes(&signature))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_image_url_signature() {
+        let signer =
+            ImageSigner::from_base64(&general_purpose::STANDARD.encode([7u8; 32])).unwrap();
+        let signature = signer.sign_image_url("abc.bmp", 1700000000);
+
+        assert!(signer.verify_image_url("abc.bmp", 1700000000, &signature));
+        assert!(!signer.verify_image_url("abc.bmp", 1700000001, &signature));
+        assert!(!signer.verify_image_url("abd.bmp", 1700000000, &signature));
+        assert!(!signer.verify_image_url("abc.bmp", 1700000000, "garbage"));
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        assert!(ImageSigner::from_base64("not base64!").is_err());
+        assert!(ImageSigner::from_base64(&general_purpose::STANDARD.encode([1u8; 16])).is_err());
+    }
+}
@@ -0,0 +1,280 @@
+use chrono::{DateTime, Duration, Local};
+
+use crate::calendar::CalendarEvent;
+use crate::labels::LabelPack;
+
+/// Minutes before a meeting in which the status indicator announces it
+pub const STARTING_SOON_MINUTES: i64 = 10;
+
+/// Free time below which the status indicator shows the minutes left rather
+/// than the start of the next meeting
+const FREE_FOR_MAX_MINUTES: i64 = 60;
+
+/// Longest refresh rate in seconds while the status indicator counts down the
+/// minutes to the next meeting, so that the count does not go stale
+pub const COUNTDOWN_REFRESH_SECS: u32 = 60;
+
+/// State of a room at a given point in time
+#[derive(Debug, Clone)]
+pub enum RoomState<'a> {
+    /// No meeting in progress
+    Free { next: Option<&'a CalendarEvent> },
+    /// A meeting is in progress
+    Busy {
+        current: &'a CalendarEvent,
+        next: Option<&'a CalendarEvent>,
+    },
+    /// The current meeting ends within the warning period
+    EndingSoon {
+        current: &'a CalendarEvent,
+        next: Option<&'a CalendarEvent>,
+        minutes_left: i64,
+    },
+}
+
+impl RoomState<'_> {
+    /// Resolve the state of a room from its events (sorted by start time)
+    pub fn resolve(
+        events: &[CalendarEvent],
+        now: DateTime<Local>,
+        end_warning_minutes: i64,
+    ) -> RoomState<'_> {
+        let current = events
+            .iter()
+            .find(|e| now >= e.start_time && now < e.end_time);
+        let next = events
+            .iter()
+            .find(|e| e.start_time > now && current.is_none_or(|c| e.start_time >= c.end_time));
+
+        match current {
+            None => RoomState::Free { next },
+            Some(current) => {
+                let left = current.end_time - now;
+                if end_warning_minutes > 0 && left <= Duration::minutes(end_warning_minutes) {
+                    RoomState::EndingSoon {
+                        current,
+                        next,
+                        // Round up, "ends in 0 min" is not helpful
+                        minutes_left: (left.num_seconds() + 59) / 60,
+                    }
+                } else {
+                    RoomState::Busy { current, next }
+                }
+            }
+        }
+    }
+
+    /// Text of the attention banner for this state, if any
+    pub fn banner(&self, labels: &LabelPack) -> Option<String> {
+        match self {
+            RoomState::EndingSoon {
+                next, minutes_left, ..
+            } => Some(match next {
+                Some(next) => labels.format(
+                    "banner-ends-in-next",
+                    &[
+                        ("minutes", (*minutes_left).into()),
+                        ("next", next.name.as_str().into()),
+                        ("time", next.start_time.format("%H:%M").to_string().into()),
+                    ],
+                ),
+                None => labels.format("banner-ends-in", &[("minutes", (*minutes_left).into())]),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Status label for this state, e.g. `FREE`
+    pub fn label(&self, labels: &LabelPack) -> String {
+        match self {
+            RoomState::Free { .. } => labels.text("status-free"),
+            _ => labels.text("status-busy"),
+        }
+    }
+
+    /// Text of the large status indicator, e.g. `BUSY until 14:30`,
+    /// `FREE for 45 min` or `STARTING SOON at 14:30`
+    ///
+    /// Times and minutes are only shown for meetings of the same day.
+    pub fn indicator(&self, labels: &LabelPack, now: DateTime<Local>) -> String {
+        let time = |t: DateTime<Local>| t.format("%H:%M").to_string().into();
+        match self {
+            RoomState::Busy { current, .. } | RoomState::EndingSoon { current, .. } => {
+                labels.format("status-busy-until", &[("time", time(current.end_time))])
+            }
+            RoomState::Free { next: Some(next) }
+                if next.start_time.date_naive() == now.date_naive() =>
+            {
+                let minutes = (next.start_time - now).num_minutes();
+                if minutes < STARTING_SOON_MINUTES {
+                    labels.format("status-starting-soon", &[("time", time(next.start_time))])
+                } else if minutes < FREE_FOR_MAX_MINUTES {
+                    labels.format("status-free-for", &[("minutes", minutes.into())])
+                } else {
+                    labels.format("status-free-until", &[("time", time(next.start_time))])
+                }
+            }
+            RoomState::Free { .. } => labels.text("status-free"),
+        }
+    }
+
+    /// Whether the status indicator counts down the minutes to the next
+    /// meeting, e.g. `FREE for 45 min`
+    pub fn shows_countdown(&self, now: DateTime<Local>) -> bool {
+        match self {
+            RoomState::Free { next: Some(next) }
+                if next.start_time.date_naive() == now.date_naive() =>
+            {
+                let minutes = (next.start_time - now).num_minutes();
+                (STARTING_SOON_MINUTES..FREE_FOR_MAX_MINUTES).contains(&minutes)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Next instant after `now` at which the resolved room state changes
+///
+/// Used to make sure that devices poll in time to show the new state, rather
+/// than only whenever their regular refresh happens to occur. Meetings
+/// starting soon count as a state change if `starting_soon_minutes` is not 0,
+/// for displays with a status indicator, as does the start of the countdown to
+/// them.
+pub fn next_state_change(
+    events: &[CalendarEvent],
+    now: DateTime<Local>,
+    end_warning_minutes: i64,
+    starting_soon_minutes: i64,
+) -> Option<DateTime<Local>> {
+    let warning = Duration::minutes(end_warning_minutes.max(0));
+    let soon = Duration::minutes(starting_soon_minutes.max(0));
+    let countdown = if starting_soon_minutes > 0 {
+        Duration::minutes(FREE_FOR_MAX_MINUTES)
+    } else {
+        Duration::zero()
+    };
+    events
+        .iter()
+        .flat_map(|e| {
+            [
+                e.start_time - countdown,
+                e.start_time - soon,
+                e.start_time,
+                e.end_time - warning,
+                e.end_time,
+            ]
+        })
+        .filter(|t| *t > now)
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::Labels;
+    use chrono::TimeZone;
+
+    fn event(name: &str, start: (u32, u32), end: (u32, u32)) -> CalendarEvent {
+        CalendarEvent::new(
+            name.to_string(),
+            Local
+                .with_ymd_and_hms(2024, 3, 4, start.0, start.1, 0)
+                .unwrap(),
+            Local.with_ymd_and_hms(2024, 3, 4, end.0, end.1, 0).unwrap(),
+            None,
+            None,
+        )
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 3, 4, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_room_state() {
+        let events = vec![
+            event("Standup", (10, 0), (11, 0)),
+            event("Design Review", (11, 0), (12, 0)),
+        ];
+
+        assert!(matches!(
+            RoomState::resolve(&events, at(9, 0), 5),
+            RoomState::Free { next: Some(e) } if e.name == "Standup"
+        ));
+        assert!(matches!(
+            RoomState::resolve(&events, at(10, 30), 5),
+            RoomState::Busy { current, .. } if current.name == "Standup"
+        ));
+
+        let labels = Labels::embedded();
+        let en = labels.pack("en");
+        let state = RoomState::resolve(&events, at(10, 56), 5);
+        assert_eq!(
+            state.banner(&en).as_deref(),
+            Some("Ends in 4 min - next: Design Review 11:00")
+        );
+        assert_eq!(state.label(&en), "BUSY");
+
+        let state = RoomState::resolve(&events, at(11, 55), 5);
+        assert_eq!(state.banner(&en).as_deref(), Some("Ends in 5 min"));
+        assert_eq!(
+            state.banner(&labels.pack("de")).as_deref(),
+            Some("Endet in 5 Min.")
+        );
+        assert_eq!(RoomState::resolve(&events, at(9, 0), 5).label(&en), "FREE");
+    }
+
+    #[test]
+    fn test_status_indicator() {
+        let events = vec![
+            event("Standup", (10, 0), (11, 0)),
+            event("Design Review", (14, 0), (15, 0)),
+        ];
+        let labels = Labels::embedded();
+        let en = labels.pack("en");
+        let indicator = |now| RoomState::resolve(&events, now, 5).indicator(&en, now);
+
+        assert_eq!(indicator(at(8, 0)), "FREE until 10:00");
+        assert_eq!(indicator(at(9, 15)), "FREE for 45 min");
+        assert_eq!(indicator(at(9, 52)), "STARTING SOON at 10:00");
+        assert_eq!(indicator(at(10, 57)), "BUSY until 11:00");
+        assert_eq!(indicator(at(15, 0)), "FREE");
+        assert_eq!(
+            RoomState::resolve(&events, at(9, 15), 5).indicator(&labels.pack("de"), at(9, 15)),
+            "FREI für 45 Min."
+        );
+
+        // Only the minutes count down and need frequent refreshes
+        let countdown = |now| RoomState::resolve(&events, now, 5).shows_countdown(now);
+        assert!(!countdown(at(8, 0)));
+        assert!(countdown(at(9, 15)));
+        assert!(!countdown(at(9, 52)));
+        assert!(!countdown(at(10, 57)));
+        assert!(!countdown(at(15, 0)));
+    }
+
+    #[test]
+    fn test_next_state_change() {
+        let events = vec![event("Standup", (10, 0), (11, 0))];
+
+        assert_eq!(next_state_change(&events, at(9, 0), 5, 0), Some(at(10, 0)));
+        assert_eq!(next_state_change(&events, at(9, 0), 5, 10), Some(at(9, 50)));
+        assert_eq!(
+            next_state_change(&events, at(9, 55), 5, 10),
+            Some(at(10, 0))
+        );
+        assert_eq!(
+            next_state_change(&events, at(10, 30), 5, 0),
+            Some(at(10, 55))
+        );
+        assert_eq!(
+            next_state_change(&events, at(10, 56), 5, 0),
+            Some(at(11, 0))
+        );
+        assert_eq!(next_state_change(&events, at(11, 30), 5, 0), None);
+
+        // The status indicator starts counting down an hour before
+        assert_eq!(next_state_change(&events, at(8, 0), 5, 10), Some(at(9, 0)));
+        assert_eq!(next_state_change(&events, at(8, 0), 5, 0), Some(at(10, 0)));
+    }
+}
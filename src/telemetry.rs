@@ -0,0 +1,209 @@
+//! Opt-in anonymous usage statistics
+//!
+//! Only if `TELEMETRY_URL` is set, the server reports aggregate counts (number
+//! of devices and rooms, server version) to it once a day, to help prioritize
+//! development of the self-hosted server. Nothing identifying is sent: no IDs,
+//! names, addresses or calendar data. `DO_NOT_TRACK` switches reporting off
+//! regardless, and the endpoint can stop all reporting by responding with
+//! `410 Gone`.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context, Result, bail};
+use log::{debug, info};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::database::Database;
+use crate::rooms::SharedRooms;
+
+/// How often usage statistics are reported
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Timeout of requests to the statistics endpoint
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Aggregate usage statistics of an installation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageStats {
+    /// Version of the server
+    pub version: String,
+    /// Number of registered devices
+    pub devices: usize,
+    /// Number of configured rooms
+    pub rooms: usize,
+}
+
+impl UsageStats {
+    /// Collect the statistics of this installation
+    pub fn collect(database: &Database, rooms: &SharedRooms) -> Result<Self> {
+        Ok(Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            devices: database
+                .list_devices()
+                .context("Failed to count devices")?
+                .len(),
+            rooms: rooms.snapshot().len(),
+        })
+    }
+}
+
+/// Sender of usage statistics to the configured endpoint
+pub struct UsageReporter {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl UsageReporter {
+    /// Create a reporter posting to the given URL
+    pub fn new(url: String) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("Failed to create telemetry HTTP client")?;
+        Ok(Self { url, client })
+    }
+
+    /// POST the statistics as JSON
+    ///
+    /// Returns false if the endpoint asked to stop reporting.
+    pub async fn send(&self, stats: &UsageStats) -> Result<bool> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(stats)
+            .send()
+            .await
+            .with_context(|| format!("Failed to send usage statistics to {}", self.url))?;
+        match response.status() {
+            StatusCode::GONE => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => bail!("Usage statistics endpoint {} returned {}", self.url, status),
+        }
+    }
+}
+
+/// Background task reporting usage statistics once per interval
+///
+/// Only the instance holding the telemetry lease reports, so that a fleet of
+/// instances sharing a database counts once. Failures are only logged at debug
+/// level, they are of no concern to the admin.
+pub async fn run_telemetry_task(
+    reporter: UsageReporter,
+    database: Arc<Database>,
+    rooms: SharedRooms,
+    instance_id: String,
+) {
+    let mut interval = tokio::time::interval(REPORT_INTERVAL);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // Held for two intervals, so that it does not lapse between two reports
+    let lease_seconds = 2 * REPORT_INTERVAL.as_secs() as i64;
+    loop {
+        interval.tick().await;
+        let holder = instance_id.clone();
+        match database
+            .run_blocking(move |db| db.try_acquire_lease("telemetry", &holder, lease_seconds))
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => {
+                debug!("Telemetry lease held by another instance");
+                continue;
+            }
+            Err(e) => {
+                debug!("Failed to acquire telemetry lease: {:#}", e);
+                continue;
+            }
+        }
+
+        let collected = {
+            let rooms = rooms.clone();
+            database
+                .run_blocking(move |db| UsageStats::collect(db, &rooms))
+                .await
+        };
+        let result = match collected {
+            Ok(stats) => reporter.send(&stats).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(true) => debug!("Reported usage statistics"),
+            Ok(false) => {
+                info!("Usage statistics endpoint asked to stop reporting");
+                return;
+            }
+            Err(e) => debug!("Failed to report usage statistics: {:#}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::{Json, Router, http::StatusCode, routing::post};
+
+    use super::*;
+    use crate::rooms::parse_rooms;
+
+    /// Serve a stand-in statistics endpoint, returning its URL
+    async fn serve(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/stats", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        url
+    }
+
+    #[test]
+    fn test_collect_usage_stats() {
+        let database = Database::new(":memory:").unwrap();
+        database.register_device("AA:BB:CC:DD:EE:FF").unwrap();
+        let rooms = SharedRooms::new(
+            parse_rooms(
+                r#"
+                [[rooms]]
+                id = "room-a"
+                name = "Room A"
+                calendar_url = "https://example.com/room-a.ics"
+                "#,
+            )
+            .unwrap(),
+        );
+
+        let stats = UsageStats::collect(&database, &rooms).unwrap();
+        assert_eq!(stats.devices, 1);
+        assert_eq!(stats.rooms, 1);
+        // Only the counts and the version are sent
+        let json = serde_json::to_string(&stats).unwrap();
+        assert!(!json.contains("AA:BB") && !json.contains("room-a"));
+    }
+
+    #[tokio::test]
+    async fn test_send_usage_stats() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let url = serve(Router::new().route(
+            "/stats",
+            post({
+                let received = received.clone();
+                |Json(stats): Json<UsageStats>| async move {
+                    received.lock().unwrap().push(stats);
+                    StatusCode::NO_CONTENT
+                }
+            }),
+        ))
+        .await;
+        let stats = UsageStats {
+            version: "1.0.0".to_string(),
+            devices: 12,
+            rooms: 4,
+        };
+
+        let reporter = UsageReporter::new(url).unwrap();
+        assert!(reporter.send(&stats).await.unwrap());
+        assert_eq!(received.lock().unwrap()[0], stats);
+
+        // The endpoint can switch reporting off
+        let url = serve(Router::new().route("/stats", post(|| async { StatusCode::GONE }))).await;
+        assert!(!UsageReporter::new(url).unwrap().send(&stats).await.unwrap());
+    }
+}
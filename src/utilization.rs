@@ -0,0 +1,355 @@
+//! Meeting room utilization statistics
+//!
+//! For every room and day, the number of meetings and the minutes the room
+//! was booked are kept in the `room_utilization` table; overlapping meetings
+//! count once towards the booked minutes. The previous day is recorded after
+//! every midnight. Older days can be imported from the room calendars with the
+//! `backfill-utilization` command, so that the statistics do not start from an
+//! empty history. Weekly reports follow the [`Week`] of the room, i.e. start
+//! on its first day and leave out its weekend.
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Datelike, Days, Local, NaiveDate, NaiveTime};
+use chrono_tz::Tz;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::calendar::{CalendarEvent, EventWindow, deduplicate, parse_calendar_window};
+use crate::database::Database;
+use crate::rooms::Room;
+use crate::week::Week;
+
+/// Utilization of a room on one day
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DailyUtilization {
+    pub room_id: String,
+    /// Local date
+    pub day: NaiveDate,
+    /// Number of meetings taking place on the day, at least partially
+    pub meetings: u32,
+    /// Minutes the room was booked on the day
+    pub busy_minutes: u32,
+}
+
+/// Utilization of a room on the working days of one week
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WeeklyUtilization {
+    pub room_id: String,
+    /// First day of the week
+    pub week: NaiveDate,
+    /// Number of recorded working days
+    pub days: u32,
+    /// Number of meetings on the working days
+    pub meetings: u32,
+    /// Minutes the room was booked on the working days
+    pub busy_minutes: u32,
+}
+
+/// Sum up daily utilization by week, leaving out the weekend
+///
+/// The days must be sorted, as returned by the database.
+pub fn weekly_utilization(days: &[DailyUtilization], week: &Week) -> Vec<WeeklyUtilization> {
+    let mut weeks: Vec<WeeklyUtilization> = Vec::new();
+    for day in days
+        .iter()
+        .filter(|day| !week.is_weekend(day.day.weekday()))
+    {
+        let start = week.start_of_week(day.day);
+        match weeks.last_mut() {
+            Some(current) if current.week == start => {
+                current.days += 1;
+                current.meetings += day.meetings;
+                current.busy_minutes += day.busy_minutes;
+            }
+            _ => weeks.push(WeeklyUtilization {
+                room_id: day.room_id.clone(),
+                week: start,
+                days: 1,
+                meetings: day.meetings,
+                busy_minutes: day.busy_minutes,
+            }),
+        }
+    }
+    weeks
+}
+
+/// Most days before today whose utilization can be read or backfilled
+pub const MAX_UTILIZATION_DAYS: u32 = 366;
+
+/// Date of an instant in the given time zone, or else the server's
+fn date_in(instant: DateTime<Local>, time_zone: Option<Tz>) -> NaiveDate {
+    match time_zone {
+        Some(tz) => instant.with_timezone(&tz).date_naive(),
+        None => instant.date_naive(),
+    }
+}
+
+/// Start of a day in the given time zone, or else the server's
+fn midnight(day: NaiveDate, time_zone: Option<Tz>) -> DateTime<Local> {
+    let midnight = day.and_time(NaiveTime::MIN);
+    match time_zone {
+        Some(tz) => midnight
+            .and_local_timezone(tz)
+            .earliest()
+            .map(|start| start.with_timezone(&Local)),
+        None => midnight.and_local_timezone(Local).earliest(),
+    }
+    .unwrap_or_else(|| midnight.and_utc().with_timezone(&Local))
+}
+
+/// The given number of complete days before today, in the given time zone
+/// or else the server's
+///
+/// At most [`MAX_UTILIZATION_DAYS`] days are allowed.
+pub fn past_days(now: DateTime<Local>, days: u32, time_zone: Option<Tz>) -> Result<EventWindow> {
+    if days > MAX_UTILIZATION_DAYS {
+        bail!(
+            "At most {} days of utilization are kept, not {}",
+            MAX_UTILIZATION_DAYS,
+            days
+        );
+    }
+    let today = date_in(now, time_zone);
+    let first = today
+        .checked_sub_days(Days::new(days.into()))
+        .with_context(|| format!("{} days before {} is out of range", days, today))?;
+    Ok(EventWindow {
+        start: midnight(first, time_zone),
+        end: midnight(today, time_zone),
+    })
+}
+
+/// First day of the given number of complete days before today, see
+/// [`past_days`]
+pub fn first_past_day(now: DateTime<Local>, days: u32, time_zone: Option<Tz>) -> Result<NaiveDate> {
+    let window = past_days(now, days, time_zone)?;
+    Ok(date_in(window.start, time_zone))
+}
+
+/// Utilization of a room on each day of the window, including days without
+/// meetings
+///
+/// Days start at midnight in the given time zone, or else the server's.
+pub fn daily_utilization(
+    room_id: &str,
+    events: &[CalendarEvent],
+    window: EventWindow,
+    time_zone: Option<Tz>,
+) -> Vec<DailyUtilization> {
+    let mut days = Vec::new();
+    let mut day = date_in(window.start, time_zone);
+    while midnight(day, time_zone) < window.end {
+        let start = midnight(day, time_zone);
+        let end = midnight(day + Days::new(1), time_zone);
+        let mut booked: Vec<_> = events
+            .iter()
+            .filter(|event| event.end_time > start && event.start_time < end)
+            .map(|event| (event.start_time.max(start), event.end_time.min(end)))
+            .collect();
+        booked.sort();
+
+        // Merge overlapping meetings, so that double bookings count once
+        let meetings = booked.len() as u32;
+        let mut busy_minutes = 0;
+        let mut current: Option<(DateTime<Local>, DateTime<Local>)> = None;
+        for (from, to) in booked {
+            match &mut current {
+                Some((_, current_end)) if from <= *current_end => {
+                    *current_end = (*current_end).max(to);
+                }
+                _ => {
+                    if let Some((from, to)) = current.replace((from, to)) {
+                        busy_minutes += (to - from).num_minutes();
+                    }
+                }
+            }
+        }
+        if let Some((from, to)) = current {
+            busy_minutes += (to - from).num_minutes();
+        }
+
+        days.push(DailyUtilization {
+            room_id: room_id.to_string(),
+            day,
+            meetings,
+            busy_minutes: busy_minutes as u32,
+        });
+        day = day + Days::new(1);
+    }
+    days
+}
+
+/// Read the utilization of a room within the window from its calendar
+pub async fn room_utilization(room: &Room, window: EventWindow) -> Result<Vec<DailyUtilization>> {
+    let Some(url) = &room.calendar_url else {
+        return Ok(Vec::new());
+    };
+    let data = room
+        .calendar_source
+        .fetch_window(url, window)
+        .await
+        .with_context(|| format!("Failed to fetch calendar of room {}", room.id))?;
+    let parsed = tokio::task::spawn_blocking(move || parse_calendar_window(&data, window))
+        .await
+        .context("Parser task failed")?
+        .with_context(|| format!("Failed to parse calendar of room {}", room.id))?;
+    for warning in &parsed.warnings {
+        debug!("Calendar of room {}: {}", room.id, warning);
+    }
+    let events = deduplicate(parsed.events, room.deduplicate);
+    Ok(daily_utilization(&room.id, &events, window, room.time_zone))
+}
+
+/// Record the utilization of the rooms on the given number of days before
+/// today from their calendars
+///
+/// Days are those of the room's time zone. Days recorded before are
+/// overwritten. Rooms whose calendar cannot be read are skipped with a
+/// warning; returns the number of rooms recorded.
+pub async fn record_utilization(
+    database: &Arc<Database>,
+    rooms: &[Room],
+    days: u32,
+    now: DateTime<Local>,
+) -> Result<usize> {
+    let mut recorded = 0;
+    for room in rooms.iter().filter(|room| room.calendar_url.is_some()) {
+        let window = past_days(now, days, room.time_zone)?;
+        match room_utilization(room, window).await {
+            Ok(utilization) => {
+                let days = utilization.len();
+                database
+                    .run_blocking(move |db| db.record_room_utilization(&utilization))
+                    .await?;
+                debug!("Recorded utilization of room {} on {} days", room.id, days);
+                recorded += 1;
+            }
+            Err(e) => warn!("Skipping room {}: {:#}", room.id, e),
+        }
+    }
+    Ok(recorded)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn event(start: (u32, u32, u32), end: (u32, u32, u32)) -> CalendarEvent {
+        let at = |(day, hour, minute)| {
+            Local
+                .with_ymd_and_hms(2024, 3, day, hour, minute, 0)
+                .unwrap()
+        };
+        CalendarEvent::new("Meeting".to_string(), at(start), at(end), None, None)
+    }
+
+    #[test]
+    fn test_daily_utilization() {
+        let now = Local.with_ymd_and_hms(2024, 3, 7, 9, 0, 0).unwrap();
+        let window = past_days(now, 3, None).unwrap();
+        let events = [
+            event((4, 9, 0), (4, 10, 0)),
+            // Double booking, overlapping the first meeting by 30 minutes
+            event((4, 9, 30), (4, 11, 0)),
+            event((4, 14, 0), (4, 14, 45)),
+            // Across midnight, counted on both days
+            event((5, 23, 0), (6, 1, 0)),
+            // Today and before the window
+            event((7, 8, 0), (7, 9, 0)),
+            event((3, 8, 0), (3, 9, 0)),
+        ];
+
+        let days = daily_utilization("room-a", &events, window, None);
+        let summary: Vec<_> = days
+            .iter()
+            .map(|day| (day.day.to_string(), day.meetings, day.busy_minutes))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("2024-03-04".to_string(), 3, 165),
+                ("2024-03-05".to_string(), 1, 60),
+                ("2024-03-06".to_string(), 1, 60),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_past_days() {
+        let now = Local.with_ymd_and_hms(2024, 3, 7, 9, 0, 0).unwrap();
+        assert!(past_days(now, MAX_UTILIZATION_DAYS, None).is_ok());
+        assert!(past_days(now, MAX_UTILIZATION_DAYS + 1, None).is_err());
+        assert!(past_days(now, u32::MAX, None).is_err());
+
+        // Days start at midnight in the room's time zone
+        let tz = chrono_tz::Pacific::Kiritimati;
+        let window = past_days(now, 2, Some(tz)).unwrap();
+        let today = now.with_timezone(&tz).date_naive();
+        assert_eq!(window.end.with_timezone(&tz).date_naive(), today);
+        assert_eq!(window.end.with_timezone(&tz).time(), NaiveTime::MIN);
+        assert_eq!(window.end - window.start, chrono::Duration::days(2));
+        assert_eq!(
+            first_past_day(now, 2, Some(tz)).unwrap(),
+            today - Days::new(2)
+        );
+    }
+
+    #[test]
+    fn test_record_room_utilization() {
+        let db = Database::new(":memory:").unwrap();
+        let day = |day, busy_minutes| DailyUtilization {
+            room_id: "room-a".to_string(),
+            day: NaiveDate::from_ymd_opt(2024, 3, day).unwrap(),
+            meetings: 1,
+            busy_minutes,
+        };
+        db.record_room_utilization(&[day(4, 60), day(5, 30)])
+            .unwrap();
+        // Recording a day again replaces it
+        db.record_room_utilization(&[day(5, 90)]).unwrap();
+
+        let since = NaiveDate::from_ymd_opt(2024, 3, 5).unwrap();
+        assert_eq!(db.room_utilization("room-a", since).unwrap(), [day(5, 90)]);
+        assert!(db.room_utilization("room-b", since).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_weekly_utilization() {
+        // 2024-03-01 is a Friday, 2024-03-03 a Sunday
+        let days: Vec<_> = (1..=10)
+            .map(|day| DailyUtilization {
+                room_id: "room-a".to_string(),
+                day: NaiveDate::from_ymd_opt(2024, 3, day).unwrap(),
+                meetings: 1,
+                busy_minutes: day * 10,
+            })
+            .collect();
+        let date = |day| NaiveDate::from_ymd_opt(2024, 3, day).unwrap();
+        let summary = |week: &Week| {
+            weekly_utilization(&days, week)
+                .into_iter()
+                .map(|week| (week.week, week.days, week.meetings, week.busy_minutes))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            summary(&Week::default()),
+            [
+                (NaiveDate::from_ymd_opt(2024, 2, 26).unwrap(), 1, 1, 10),
+                (date(4), 5, 5, 300),
+            ]
+        );
+        let week = Week {
+            first_day: chrono::Weekday::Sun,
+            weekend: vec![chrono::Weekday::Fri, chrono::Weekday::Sat],
+        };
+        assert_eq!(
+            summary(&week),
+            [(date(3), 5, 5, 250), (date(10), 1, 1, 100)]
+        );
+    }
+}
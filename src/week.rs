@@ -0,0 +1,73 @@
+//! Definition of the week, which differs between sites
+//!
+//! E.g. the week starts on Monday in Europe and on Sunday in the US, and
+//! some sites work Sunday to Thursday.
+
+use chrono::{NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// First day and weekend days of the week
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Week {
+    /// First day of the week, e.g. `"Sun"`
+    pub first_day: Weekday,
+    /// Days off, e.g. `["Fri", "Sat"]`
+    pub weekend: Vec<Weekday>,
+}
+
+impl Default for Week {
+    fn default() -> Self {
+        Self {
+            first_day: Weekday::Mon,
+            weekend: vec![Weekday::Sat, Weekday::Sun],
+        }
+    }
+}
+
+impl Week {
+    /// Returns true if the day is a weekend day
+    pub fn is_weekend(&self, day: Weekday) -> bool {
+        self.weekend.contains(&day)
+    }
+
+    /// First day of the week containing `date`
+    pub fn start_of_week(&self, date: NaiveDate) -> NaiveDate {
+        date.week(self.first_day).first_day()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_week() {
+        let week = Week::default();
+        assert!(week.is_weekend(Weekday::Sat));
+        assert!(week.is_weekend(Weekday::Sun));
+        assert!(!week.is_weekend(Weekday::Fri));
+        // 2024-03-07 is a Thursday
+        let date = NaiveDate::from_ymd_opt(2024, 3, 7).unwrap();
+        assert_eq!(
+            week.start_of_week(date),
+            NaiveDate::from_ymd_opt(2024, 3, 4).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_custom_week() {
+        let week: Week =
+            toml::from_str("first_day = \"Sun\"\nweekend = [\"Fri\", \"Sat\"]").unwrap();
+        assert!(week.is_weekend(Weekday::Fri));
+        assert!(!week.is_weekend(Weekday::Sun));
+        let date = NaiveDate::from_ymd_opt(2024, 3, 7).unwrap();
+        assert_eq!(
+            week.start_of_week(date),
+            NaiveDate::from_ymd_opt(2024, 3, 3).unwrap()
+        );
+        // A Sunday starts its own week
+        let sunday = NaiveDate::from_ymd_opt(2024, 3, 10).unwrap();
+        assert_eq!(week.start_of_week(sunday), sunday);
+    }
+}
@@ -0,0 +1,193 @@
+//! Support for end-to-end tests against a running server
+
+// Not every test binary uses every helper
+#![allow(dead_code)]
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, OnceLock, mpsc},
+};
+
+use axum::{Router, routing::get};
+use chrono::{Duration, Utc};
+use tokio::net::TcpListener;
+
+use trmnl_meeting_room_display::{
+    bmp::{Dither, ImageFormat},
+    database::Database,
+    health::CRITICAL_BATTERY_VOLTAGE,
+    image_store::{ImageDelivery, ImageStoreConfig},
+    labels::{Labels, SharedLabels},
+    log_ingest::LogAuth,
+    refresh::RefreshRates,
+    render::RendererConfig,
+    rooms::{SharedRooms, parse_rooms},
+    server::{AppState, config::Config, create_app},
+    signing::ImageSigner,
+};
+
+/// Access token of the test server
+pub const ACCESS_TOKEN: &str = "e2e-access-token";
+
+/// Token of the admin API
+pub const ADMIN_TOKEN: &str = "e2e-admin-token";
+
+/// Device assigned to the fixture room
+pub const DEVICE_ID: &str = "E2:E2:00:00:00:01";
+
+/// Server running for the whole test binary
+pub struct TestServer {
+    /// Base URL of the server, e.g. `http://127.0.0.1:12345`
+    pub url: String,
+    /// Database of the server
+    pub database: Arc<Database>,
+}
+
+impl TestServer {
+    /// Absolute URL of the given path
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.url, path)
+    }
+}
+
+/// The shared test server, started on first use
+///
+/// Every test runs on its own runtime, so the server runs on a dedicated
+/// thread that outlives the individual tests. All tests share this server and
+/// must use distinct devices or tolerate each other's data.
+pub fn server() -> &'static TestServer {
+    static SERVER: OnceLock<TestServer> = OnceLock::new();
+    SERVER.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(async move {
+                let calendar_addr = serve(fixture_calendar_app()).await;
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let addr = listener.local_addr().unwrap();
+                let url = format!("http://{}", addr);
+
+                let config = Arc::new(test_config(&url, calendar_addr));
+                let database = Arc::new(Database::new(":memory:").unwrap());
+                let state = AppState::new(database.clone(), config).unwrap();
+                tx.send(TestServer { url, database }).unwrap();
+
+                axum::serve(listener, create_app(state)).await.unwrap();
+            });
+        });
+        rx.recv().expect("Test server failed to start")
+    })
+}
+
+/// Serve an app on an ephemeral port in the background
+async fn serve(app: Router) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+/// Calendar server with a meeting that ends in three minutes
+fn fixture_calendar_app() -> Router {
+    Router::new().route(
+        "/room-a.ics",
+        get(|| async {
+            let format = |t: chrono::DateTime<Utc>| t.format("%Y%m%dT%H%M%SZ").to_string();
+            let now = Utc::now();
+            format!(
+                "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//e2e//EN\r\n\
+                 BEGIN:VEVENT\r\nUID:current@e2e\r\nSUMMARY:Standup\r\n\
+                 DTSTART:{}\r\nDTEND:{}\r\nEND:VEVENT\r\n\
+                 BEGIN:VEVENT\r\nUID:next@e2e\r\nSUMMARY:Retro\r\n\
+                 DTSTART:{}\r\nDTEND:{}\r\nEND:VEVENT\r\n\
+                 END:VCALENDAR\r\n",
+                format(now - Duration::minutes(27)),
+                format(now + Duration::minutes(3)),
+                format(now + Duration::minutes(3)),
+                format(now + Duration::minutes(63)),
+            )
+        }),
+    )
+}
+
+/// Configuration of the test server
+fn test_config(url: &str, calendar_addr: SocketAddr) -> Config {
+    Config {
+        server_host: "127.0.0.1".to_string(),
+        server_port: 0,
+        server_url: url.to_string(),
+        tls: None,
+        database_url: ":memory:".to_string(),
+        database_pool_size: 1,
+        access_token: ACCESS_TOKEN.to_string(),
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        font_path: "assets/fonts/BlockKie.ttf".to_string(),
+        refresh: RefreshRates {
+            default_rate: 200,
+            jitter_percent: 0,
+        },
+        calendar_refresh_minutes: 5,
+        rooms_path: "rooms.toml".to_string(),
+        rooms: SharedRooms::new(
+            parse_rooms(&format!(
+                r#"
+            [[rooms]]
+            id = "room-a"
+            name = "Room A"
+            calendar_url = "http://{}/room-a.ics"
+            devices = ["{}"]
+            show_issue_badge = true
+            capacity = 6
+            floor = "2"
+            zone = "East"
+            equipment = ["vc", "whiteboard"]
+            "#,
+                calendar_addr, DEVICE_ID
+            ))
+            .unwrap(),
+        ),
+        image_signer: Some(
+            ImageSigner::from_base64("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=").unwrap(),
+        ),
+        notify_webhook_urls: Vec::new(),
+        notify_missed_check_ins: 3,
+        notify_calendar_failures: 3,
+        mqtt_url: None,
+        mqtt_topic_prefix: "trmnl".to_string(),
+        image_store: ImageStoreConfig::Memory,
+        image_delivery: ImageDelivery::Hosted,
+        image_format: ImageFormat::Bmp,
+        image_url_ttl_seconds: 3600,
+        instance_id: "e2e".to_string(),
+        require_claim_code: false,
+        labels: SharedLabels::new(Labels::embedded()),
+        labels_dir: None,
+        log_buffer_size: 1000,
+        log_auth: LogAuth::Permissive,
+        log_retention_days: 30,
+        log_max_entries: 100_000,
+        job_schedules: Vec::new(),
+        error_sink: None,
+        proxy: None,
+        renderer: RendererConfig::Local,
+        render_cache_ttl_seconds: 60,
+        dither: Dither::None,
+        telemetry_url: None,
+        battery_warning_voltage: CRITICAL_BATTERY_VOLTAGE,
+        inline_cache_size: 32,
+        rate_limit_device_per_minute: 0,
+        rate_limit_address_per_minute: 0,
+        trust_forwarded_for: false,
+    }
+}
+
+/// HTTP client with the device headers of [`DEVICE_ID`] and the given access token
+pub fn device_client(access_token: &str) -> reqwest::Client {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert("ID", DEVICE_ID.parse().unwrap());
+    headers.insert("Access-Token", access_token.parse().unwrap());
+    reqwest::Client::builder()
+        .default_headers(headers)
+        .build()
+        .unwrap()
+}
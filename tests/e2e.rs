@@ -0,0 +1,339 @@
+//! End-to-end tests driving a running server over HTTP
+
+mod common;
+
+use std::collections::BTreeSet;
+
+use reqwest::StatusCode;
+use serde_json::Value;
+use trmnl_meeting_room_display::database::DeviceLogQuery;
+
+use common::{ACCESS_TOKEN, ADMIN_TOKEN, DEVICE_ID, device_client, server};
+
+/// Keys of a JSON object
+fn keys(value: &Value) -> BTreeSet<&str> {
+    value
+        .as_object()
+        .expect("expected a JSON object")
+        .keys()
+        .map(String::as_str)
+        .collect()
+}
+
+#[tokio::test]
+async fn test_device_lifecycle() {
+    let server = server();
+    let client = device_client(ACCESS_TOKEN);
+
+    // Setup registers the device and issues its API key
+    let resp = client.get(server.url("/api/setup/")).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let setup: Value = resp.json().await.unwrap();
+    assert_eq!(
+        keys(&setup),
+        BTreeSet::from(["status", "api_key", "friendly_id", "image_url"])
+    );
+    assert_eq!(setup["status"], 200);
+    assert!(server.database.device_exists(DEVICE_ID).unwrap());
+    let device = device_client(setup["api_key"].as_str().unwrap());
+
+    // The shared access token no longer works for the device
+    let resp = client.get(server.url("/api/display")).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    // Display serves a hosted, signed BMP of the room screen
+    let resp = device.get(server.url("/api/display")).send().await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let display: Value = resp.json().await.unwrap();
+    assert_eq!(
+        keys(&display),
+        BTreeSet::from([
+            "filename",
+            "image_url",
+            "image_url_timeout",
+            "refresh_rate",
+            "image_signature"
+        ])
+    );
+    // The fixture meeting ends in three minutes, the device must poll by then
+    let refresh_rate = display["refresh_rate"].as_u64().unwrap();
+    assert!(refresh_rate > 0 && refresh_rate <= 180, "{}", refresh_rate);
+
+    let image_url = display["image_url"].as_str().unwrap();
+    assert!(image_url.starts_with(&server.url("/images/")));
+    let resp = reqwest::get(image_url).await.unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers()["content-type"], "image/bmp");
+    let image = resp.bytes().await.unwrap();
+    assert_eq!(&image[..2], b"BM");
+
+    // The v1 API uses the BYOS response format
+    let resp = device
+        .get(server.url("/api/v1/display"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let display: Value = resp.json().await.unwrap();
+    assert_eq!(display["status"], 0);
+    assert_eq!(display["special_function"], "none");
+
+    // Device logs are accepted
+    let resp = device
+        .post(server.url("/api/log"))
+        .header("Content-Type", "text/plain")
+        .body("e2e log line")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    // ... and stored by the background writer
+    let mut stored = Vec::new();
+    for _ in 0..50 {
+        stored = server
+            .database
+            .list_device_logs(
+                &DeviceLogQuery {
+                    device_id: Some(DEVICE_ID),
+                    ..Default::default()
+                },
+                1,
+            )
+            .unwrap();
+        if !stored.is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(stored[0].message, "e2e log line");
+    assert!(stored[0].authenticated);
+
+    // The device shows up in the export, labelled with its room
+    let admin = device_client(ADMIN_TOKEN);
+    let resp = admin
+        .get(server.url("/api/admin/devices/export?format=prometheus_sd"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let groups: Value = resp.json().await.unwrap();
+    let group = groups
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|g| g["targets"][0] == DEVICE_ID)
+        .expect("device missing from export");
+    assert_eq!(group["labels"]["__meta_trmnl_room"], "room-a");
+
+    // A broadcast replaces the room screen until it is cleared
+    let resp = admin
+        .post(server.url("/api/admin/broadcast"))
+        .header("Admin-User", "e2e")
+        .json(&serde_json::json!({"message": "Fire drill", "duration_minutes": 5}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let broadcast: Value = resp.json().await.unwrap();
+
+    let display: Value = device
+        .get(server.url("/api/display"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(
+        display["filename"]
+            .as_str()
+            .unwrap()
+            .starts_with(&format!("broadcast-{}-", broadcast["id"]))
+    );
+
+    let resp = admin
+        .delete(server.url("/api/admin/broadcast"))
+        .header("Admin-User", "e2e")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let display: Value = device
+        .get(server.url("/api/display"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let filename = display["filename"].as_str().unwrap();
+    assert!(filename.ends_with(".bmp") && !filename.starts_with("broadcast-"));
+}
+
+#[tokio::test]
+async fn test_issue_report_flow() {
+    let server = server();
+    let client = reqwest::Client::new();
+
+    let resp = client
+        .get(server.url("/report/room-a"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.text().await.unwrap().contains("Room A"));
+
+    let resp = client
+        .post(server.url("/report/room-a"))
+        .form(&[("category", "audio"), ("description", "e2e: mic broken")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let issues: Value = client
+        .get(server.url("/api/admin/issues?room=room-a"))
+        .header("Access-Token", ADMIN_TOKEN)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(
+        issues
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|i| i["description"] == "e2e: mic broken")
+    );
+}
+
+#[tokio::test]
+async fn test_room_status() {
+    let server = server();
+
+    let rooms: Value = reqwest::get(server.url("/api/rooms?equipment=vc&min_capacity=4"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let rooms = rooms.as_array().unwrap();
+    assert_eq!(rooms.len(), 1);
+    assert_eq!(rooms[0]["id"], "room-a");
+    assert_eq!(rooms[0]["occupancy"], "busy");
+    assert!(rooms[0]["busy_until"].is_string());
+    assert!(rooms[0]["next_meeting_at"].is_string());
+    assert_eq!(
+        rooms[0]["equipment"],
+        serde_json::json!(["vc", "whiteboard"])
+    );
+
+    let rooms: Value = reqwest::get(server.url("/api/rooms?equipment=tv"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(rooms.as_array().unwrap().is_empty());
+
+    let resp = reqwest::get(server.url("/api/rooms?equipment=jacuzzi"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    // Lobby displays of a floor only list its rooms
+    let rooms: Value = reqwest::get(server.url("/api/rooms?floor=2&q=room"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(rooms[0]["id"], "room-a");
+    assert_eq!(rooms[0]["zone"], "East");
+    let rooms: Value = reqwest::get(server.url("/api/rooms?floor=3"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(rooms.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_room_schedule() {
+    let server = server();
+
+    let resp = reqwest::get(server.url("/rooms/room-a/schedule.json?days=3"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let schedule: Value = resp.json().await.unwrap();
+    assert_eq!(
+        keys(&schedule),
+        BTreeSet::from([
+            "id",
+            "name",
+            "floor",
+            "zone",
+            "capacity",
+            "until",
+            "calendar_available",
+            "calendar_degraded",
+            "events"
+        ])
+    );
+    assert_eq!(schedule["calendar_available"], true);
+    assert_eq!(schedule["calendar_degraded"], false);
+    let events = schedule["events"].as_array().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(keys(&events[0]), BTreeSet::from(["start", "end", "title"]));
+    // The fixture room does not publish meeting titles
+    assert!(events.iter().all(|event| event["title"].is_null()));
+
+    for uri in [
+        "/rooms/room-a/schedule.json?days=0",
+        "/rooms/room-a/schedule.json?days=15",
+    ] {
+        let resp = reqwest::get(server.url(uri)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", uri);
+    }
+    let resp = reqwest::get(server.url("/rooms/room-z/schedule.json"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_error_contract() {
+    let server = server();
+
+    let resp = reqwest::Client::new()
+        .get(server.url("/api/display"))
+        .header("ID", DEVICE_ID)
+        .header("Access-Token", "wrong-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let error: Value = resp.json().await.unwrap();
+    assert_eq!(
+        keys(&error),
+        BTreeSet::from(["error", "code", "error_code"])
+    );
+    assert_eq!(error["error_code"], "AUTH-01");
+
+    let resp = reqwest::get(server.url("/images/0000000000000000.bmp"))
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let health: Value = reqwest::get(server.url("/health"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(health["status"], "ok");
+}
@@ -0,0 +1,231 @@
+//! Long-running soak test simulating a fleet of devices
+//!
+//! Ignored by default, run with:
+//!
+//! ```text
+//! SOAK_DURATION_SECS=3600 cargo test --release --test soak -- --ignored --nocapture
+//! ```
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use reqwest::StatusCode;
+
+use common::{ACCESS_TOKEN, TestServer, server};
+
+/// Number of simulated devices
+const DEVICES: usize = 8;
+
+/// Allowed growth of the resident memory after the warm-up phase, in kB
+const MAX_RSS_GROWTH_KB: u64 = 64 * 1024;
+
+/// Simulated device with a slowly draining battery
+struct SimulatedDevice {
+    id: String,
+    /// Access token, the shared one until setup issued the device's API key
+    api_key: String,
+    battery_voltage: f64,
+    rng: StdRng,
+    client: reqwest::Client,
+    /// Client with a timeout so short that requests are aborted mid-flight
+    flaky_client: reqwest::Client,
+}
+
+impl SimulatedDevice {
+    fn new(index: usize) -> Self {
+        Self {
+            id: format!("50:A4:00:00:00:{:02X}", index),
+            api_key: ACCESS_TOKEN.to_string(),
+            battery_voltage: 4.2,
+            rng: StdRng::seed_from_u64(index as u64),
+            client: reqwest::Client::new(),
+            flaky_client: reqwest::Client::builder()
+                .timeout(Duration::from_millis(1))
+                .build()
+                .unwrap(),
+        }
+    }
+
+    /// Request with the headers sent by the firmware
+    fn request(
+        &self,
+        method: reqwest::Method,
+        server: &TestServer,
+        path: &str,
+    ) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, server.url(path))
+            .header("ID", &self.id)
+            .header("Access-Token", &self.api_key)
+            .header("Model", "og")
+            .header("Battery-Voltage", format!("{:.2}", self.battery_voltage))
+    }
+
+    /// One poll cycle: a valid display request, sometimes preceded by a
+    /// network flap or followed by a malformed request
+    async fn poll(&mut self, server: &TestServer) {
+        // Battery decay, with the occasional recharge
+        self.battery_voltage -= self.rng.gen_range(0.0..0.01);
+        if self.battery_voltage < 3.0 || self.rng.gen_bool(0.001) {
+            self.battery_voltage = 4.2;
+        }
+
+        if self.rng.gen_bool(0.05) {
+            // Network flap, the outcome does not matter
+            let _ = self
+                .flaky_client
+                .get(server.url("/api/display"))
+                .header("ID", &self.id)
+                .header("Access-Token", &self.api_key)
+                .send()
+                .await;
+        }
+
+        let resp = self
+            .request(reqwest::Method::GET, server, "/api/display")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK, "device {}", self.id);
+        let display: serde_json::Value = resp.json().await.unwrap();
+        let image_url = display["image_url"].as_str().unwrap();
+        let resp = self.client.get(image_url).send().await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK, "image {}", image_url);
+
+        if self.rng.gen_bool(0.1) {
+            let status = self.malformed_request(server).await;
+            assert!(
+                !status.is_server_error(),
+                "malformed request got {}",
+                status
+            );
+        }
+    }
+
+    /// Send a randomly chosen malformed request, returning its status
+    async fn malformed_request(&mut self, server: &TestServer) -> StatusCode {
+        let request = match self.rng.gen_range(0..5) {
+            0 => self
+                .client
+                .get(server.url("/api/display"))
+                .header("Access-Token", ACCESS_TOKEN),
+            1 => self
+                .client
+                .get(server.url("/api/display"))
+                .header("ID", &self.id)
+                .header("Access-Token", "wrong-token"),
+            2 => self
+                .request(reqwest::Method::POST, server, "/api/log")
+                .body(vec![0xff, 0xfe, 0x00]),
+            3 => self
+                .request(reqwest::Method::GET, server, "/api/display")
+                .header("Api-Version", "v99"),
+            _ => self
+                .client
+                .get(server.url("/images/..%2F..%2Fetc%2Fpasswd"))
+                .header("ID", &self.id),
+        };
+        request.send().await.unwrap().status()
+    }
+}
+
+/// Resident memory of this process (which runs the server), in kB
+fn resident_memory_kb() -> Option<u64> {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Number of lines of the metrics output, which grows with the number of series
+async fn metrics_lines(server: &TestServer) -> usize {
+    reqwest::get(server.url("/metrics"))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+        .lines()
+        .count()
+}
+
+/// Run all devices for one round of polls
+async fn poll_all(
+    server: &'static TestServer,
+    devices: Vec<SimulatedDevice>,
+) -> Vec<SimulatedDevice> {
+    let tasks: Vec<_> = devices
+        .into_iter()
+        .map(|mut device| {
+            tokio::spawn(async move {
+                device.poll(server).await;
+                device
+            })
+        })
+        .collect();
+    let mut devices = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        devices.push(task.await.expect("Simulated device failed"));
+    }
+    devices
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "long-running, set SOAK_DURATION_SECS and run with --ignored"]
+async fn test_soak() {
+    let server = server();
+    let duration = Duration::from_secs(
+        std::env::var("SOAK_DURATION_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(60),
+    );
+
+    let mut devices: Vec<SimulatedDevice> = (0..DEVICES).map(SimulatedDevice::new).collect();
+    for device in &mut devices {
+        let resp = device
+            .request(reqwest::Method::GET, server, "/api/setup/")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let setup: serde_json::Value = resp.json().await.unwrap();
+        device.api_key = setup["api_key"].as_str().unwrap().to_string();
+    }
+
+    // Warm up caches before taking the baseline
+    for _ in 0..10 {
+        devices = poll_all(server, devices).await;
+    }
+    let baseline_rss = resident_memory_kb();
+    let baseline_metrics = metrics_lines(server).await;
+
+    let started = Instant::now();
+    let mut rounds = 0;
+    while started.elapsed() < duration {
+        devices = poll_all(server, devices).await;
+        rounds += 1;
+    }
+
+    let metrics = metrics_lines(server).await;
+    assert_eq!(
+        metrics, baseline_metrics,
+        "metric series must not grow with the number of requests"
+    );
+    if let (Some(baseline), Some(rss)) = (baseline_rss, resident_memory_kb()) {
+        println!("{} rounds, RSS {} kB -> {} kB", rounds, baseline, rss);
+        assert!(
+            rss < baseline + MAX_RSS_GROWTH_KB,
+            "memory grew from {} kB to {} kB",
+            baseline,
+            rss
+        );
+    }
+}